@@ -0,0 +1,55 @@
+-- Indexed subscription accounts, mirrored from the Subscription PDAs
+CREATE TABLE IF NOT EXISTS subscriptions (
+    pda TEXT PRIMARY KEY,
+    owner TEXT NOT NULL,
+    plan_id BIGINT NOT NULL,
+    start_time BIGINT NOT NULL,
+    duration BIGINT NOT NULL,
+    amount BIGINT NOT NULL,
+    active BOOLEAN NOT NULL,
+    closed BOOLEAN NOT NULL DEFAULT FALSE,
+    history BIGINT[] NOT NULL DEFAULT '{}',
+    updated_slot BIGINT NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS subscriptions_owner_idx ON subscriptions (owner);
+CREATE INDEX IF NOT EXISTS subscriptions_plan_id_idx ON subscriptions (plan_id);
+
+-- One row per create/renew instruction that moved lamports to the treasury
+CREATE TABLE IF NOT EXISTS payments (
+    signature TEXT NOT NULL,
+    instruction_index INT NOT NULL,
+    pda TEXT NOT NULL,
+    owner TEXT NOT NULL,
+    plan_id BIGINT NOT NULL,
+    amount BIGINT NOT NULL,
+    kind TEXT NOT NULL,
+    slot BIGINT NOT NULL,
+    block_time BIGINT,
+    PRIMARY KEY (signature, instruction_index)
+);
+
+CREATE INDEX IF NOT EXISTS payments_pda_idx ON payments (pda);
+
+-- Every program instruction observed, in chain order
+CREATE TABLE IF NOT EXISTS events (
+    id BIGSERIAL PRIMARY KEY,
+    signature TEXT NOT NULL,
+    instruction_index INT NOT NULL,
+    pda TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    slot BIGINT NOT NULL,
+    block_time BIGINT,
+    UNIQUE (signature, instruction_index)
+);
+
+CREATE INDEX IF NOT EXISTS events_pda_idx ON events (pda);
+
+-- Single-row cursor of the newest signature the indexer has processed
+CREATE TABLE IF NOT EXISTS indexer_state (
+    id SMALLINT PRIMARY KEY DEFAULT 1 CHECK (id = 1),
+    last_signature TEXT,
+    last_slot BIGINT NOT NULL DEFAULT 0
+);
+
+INSERT INTO indexer_state (id) VALUES (1) ON CONFLICT DO NOTHING;
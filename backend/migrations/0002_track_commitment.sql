@@ -0,0 +1,6 @@
+-- Rows are written at `confirmed` and promoted once their transaction is finalized
+ALTER TABLE subscriptions ADD COLUMN IF NOT EXISTS commitment TEXT NOT NULL DEFAULT 'confirmed';
+ALTER TABLE payments ADD COLUMN IF NOT EXISTS commitment TEXT NOT NULL DEFAULT 'confirmed';
+ALTER TABLE events ADD COLUMN IF NOT EXISTS commitment TEXT NOT NULL DEFAULT 'confirmed';
+
+CREATE INDEX IF NOT EXISTS events_unfinalized_idx ON events (slot) WHERE commitment <> 'finalized';
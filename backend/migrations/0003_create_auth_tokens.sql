@@ -0,0 +1,16 @@
+-- Refresh tokens are stored as SHA-256 hashes and rotated on every use
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    token_hash TEXT PRIMARY KEY,
+    public_key TEXT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    revoked BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_public_key_idx ON refresh_tokens (public_key);
+
+-- Access tokens revoked before their natural expiry, keyed by JWT id
+CREATE TABLE IF NOT EXISTS revoked_tokens (
+    jti TEXT PRIMARY KEY,
+    expires_at BIGINT NOT NULL
+);
@@ -0,0 +1,9 @@
+-- Single-use sign-in nonces issued by GET /auth/challenge
+CREATE TABLE IF NOT EXISTS auth_challenges (
+    nonce TEXT PRIMARY KEY,
+    public_key TEXT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    consumed BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX IF NOT EXISTS auth_challenges_expires_at_idx ON auth_challenges (expires_at);
@@ -0,0 +1,14 @@
+-- Merchant API keys for server-to-server access; only SHA-256 hashes are stored
+CREATE TABLE IF NOT EXISTS api_keys (
+    id TEXT PRIMARY KEY,
+    merchant TEXT NOT NULL,
+    name TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    prefix TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    rotated_at BIGINT,
+    last_used_at BIGINT,
+    revoked BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX IF NOT EXISTS api_keys_merchant_idx ON api_keys (merchant);
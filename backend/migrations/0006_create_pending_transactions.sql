@@ -0,0 +1,17 @@
+-- Submitted transactions, recorded before sending so a restart mid-flight leaves a trace.
+-- status: pending | confirmed | failed | expired
+CREATE TABLE IF NOT EXISTS pending_transactions (
+    signature TEXT PRIMARY KEY,
+    cluster TEXT NOT NULL,
+    instruction TEXT NOT NULL,
+    owner TEXT NOT NULL,
+    plan_id BIGINT NOT NULL,
+    transaction TEXT NOT NULL, -- base64 bincode, for resubmission
+    last_valid_block_height BIGINT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    error TEXT,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS pending_transactions_status_idx ON pending_transactions (status, created_at);
@@ -0,0 +1,13 @@
+-- Results of mutating requests sent with an Idempotency-Key, replayed on retries.
+-- status_code is NULL while the first request is still running.
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    owner TEXT NOT NULL,
+    key TEXT NOT NULL,
+    fingerprint TEXT NOT NULL, -- SHA-256 of cluster, action and parameters
+    status_code INTEGER,
+    response_body TEXT,
+    created_at BIGINT NOT NULL,
+    PRIMARY KEY (owner, key)
+);
+
+CREATE INDEX IF NOT EXISTS idempotency_keys_created_at_idx ON idempotency_keys (created_at);
@@ -0,0 +1,22 @@
+-- Per-subscription settings owned by the backend rather than mirrored from chain
+CREATE TABLE IF NOT EXISTS subscription_settings (
+    pda TEXT PRIMARY KEY,
+    auto_renew BOOLEAN NOT NULL DEFAULT FALSE,
+    updated_at BIGINT NOT NULL
+);
+
+-- End of the billing period the keeper last reported as expired; a renewal moves the period on
+ALTER TABLE subscriptions ADD COLUMN IF NOT EXISTS expired_at BIGINT;
+
+CREATE INDEX IF NOT EXISTS subscriptions_period_end_idx ON subscriptions ((start_time + duration)) WHERE active AND NOT closed;
+
+-- One row per keeper pass
+CREATE TABLE IF NOT EXISTS keeper_runs (
+    id BIGSERIAL PRIMARY KEY,
+    started_at BIGINT NOT NULL,
+    finished_at BIGINT NOT NULL,
+    scanned INTEGER NOT NULL,
+    renewed INTEGER NOT NULL,
+    expired INTEGER NOT NULL,
+    failed INTEGER NOT NULL
+);
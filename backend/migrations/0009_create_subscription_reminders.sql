@@ -0,0 +1,8 @@
+-- Expiry reminders already sent, one per subscription, milestone and billing period
+CREATE TABLE IF NOT EXISTS subscription_reminders (
+    pda TEXT NOT NULL,
+    milestone TEXT NOT NULL, -- expiring_3d | expiring_1d | expired
+    period_end BIGINT NOT NULL,
+    created_at BIGINT NOT NULL,
+    PRIMARY KEY (pda, milestone, period_end)
+);
@@ -0,0 +1,9 @@
+-- Per-wallet email opt-in; every notification type is off until the user enables it
+CREATE TABLE IF NOT EXISTS notification_preferences (
+    owner TEXT PRIMARY KEY,
+    email TEXT,
+    payment_receipts BOOLEAN NOT NULL DEFAULT FALSE,
+    expiry_reminders BOOLEAN NOT NULL DEFAULT FALSE,
+    renewal_failures BOOLEAN NOT NULL DEFAULT FALSE,
+    updated_at BIGINT NOT NULL
+);
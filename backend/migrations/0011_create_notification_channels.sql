@@ -0,0 +1,13 @@
+-- Merchant chat connectors that receive subscription events
+CREATE TABLE IF NOT EXISTS notification_channels (
+    id TEXT PRIMARY KEY,
+    merchant TEXT NOT NULL,
+    kind TEXT NOT NULL, -- discord | telegram
+    target TEXT NOT NULL, -- Discord webhook URL or Telegram chat id
+    bot_token TEXT, -- Telegram only
+    events TEXT[] NOT NULL,
+    plan_ids BIGINT[], -- NULL matches every plan
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS notification_channels_merchant_idx ON notification_channels (merchant);
@@ -0,0 +1,17 @@
+-- Background payment history exports, kept until the retention window passes
+CREATE TABLE IF NOT EXISTS payment_exports (
+    id TEXT PRIMARY KEY,
+    requested_by TEXT NOT NULL,
+    format TEXT NOT NULL, -- csv | json
+    plan_id BIGINT, -- NULL exports every plan
+    from_time BIGINT,
+    to_time BIGINT,
+    status TEXT NOT NULL, -- pending | completed | failed
+    row_count BIGINT NOT NULL DEFAULT 0,
+    content TEXT,
+    error TEXT,
+    created_at BIGINT NOT NULL,
+    completed_at BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS payment_exports_requested_by_idx ON payment_exports (requested_by, created_at DESC);
@@ -0,0 +1,11 @@
+-- Devnet airdrops requested through the API, for the per-wallet daily cap
+CREATE TABLE IF NOT EXISTS airdrops (
+    id BIGSERIAL PRIMARY KEY,
+    wallet TEXT NOT NULL,
+    cluster TEXT NOT NULL,
+    lamports BIGINT NOT NULL,
+    signature TEXT, -- Set once the faucet accepted the request
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS airdrops_wallet_idx ON airdrops (wallet, created_at);
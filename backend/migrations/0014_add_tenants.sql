@@ -0,0 +1,3 @@
+-- Tenant each transaction and session belongs to; existing rows are the default tenant's
+ALTER TABLE pending_transactions ADD COLUMN IF NOT EXISTS tenant TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE refresh_tokens ADD COLUMN IF NOT EXISTS tenant TEXT;
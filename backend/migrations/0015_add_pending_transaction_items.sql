@@ -0,0 +1,2 @@
+-- Actions of batch transactions as `<instruction>:<plan_id>`; empty for single-action rows
+ALTER TABLE pending_transactions ADD COLUMN IF NOT EXISTS items TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,20 @@
+-- Pending transactions become a job queue: every outbound transaction is a job that moves
+-- built -> submitted -> confirmed | failed, retried by the job worker
+ALTER TABLE IF EXISTS pending_transactions RENAME TO transaction_jobs;
+ALTER INDEX IF EXISTS pending_transactions_status_idx RENAME TO transaction_jobs_status_idx;
+
+ALTER TABLE transaction_jobs ADD COLUMN IF NOT EXISTS id TEXT;
+ALTER TABLE transaction_jobs ADD COLUMN IF NOT EXISTS attempts INT NOT NULL DEFAULT 0;
+ALTER TABLE transaction_jobs ADD COLUMN IF NOT EXISTS next_attempt_at BIGINT;
+
+UPDATE transaction_jobs SET id = md5(signature) WHERE id IS NULL;
+UPDATE transaction_jobs SET status = 'submitted', next_attempt_at = updated_at WHERE status = 'pending';
+UPDATE transaction_jobs
+SET status = 'failed', error = 'Blockhash expired before the transaction landed'
+WHERE status = 'expired';
+
+ALTER TABLE transaction_jobs ALTER COLUMN id SET NOT NULL;
+ALTER TABLE transaction_jobs ALTER COLUMN status SET DEFAULT 'built';
+CREATE UNIQUE INDEX IF NOT EXISTS transaction_jobs_id_idx ON transaction_jobs (id);
+CREATE INDEX IF NOT EXISTS transaction_jobs_due_idx ON transaction_jobs (next_attempt_at)
+    WHERE status IN ('built', 'submitted');
@@ -0,0 +1,29 @@
+-- Authenticated mutating API requests. Append-only: rows are never updated or deleted
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    actor TEXT NOT NULL, -- Wallet, or the merchant of an API key
+    credential TEXT NOT NULL, -- `jwt:<jti>` or `api_key:<id>`
+    tenant TEXT,
+    method TEXT NOT NULL,
+    route TEXT NOT NULL, -- Matched route pattern, e.g. /api/subscriptions/{plan_id}/renew
+    path TEXT NOT NULL,
+    status INTEGER NOT NULL,
+    signatures TEXT[] NOT NULL DEFAULT '{}', -- Transactions the request sent
+    ip TEXT,
+    request_id TEXT,
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_created_idx ON audit_log (created_at);
+CREATE INDEX IF NOT EXISTS audit_log_actor_idx ON audit_log (actor, created_at);
+
+CREATE OR REPLACE FUNCTION audit_log_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS audit_log_append_only ON audit_log;
+CREATE TRIGGER audit_log_append_only
+    BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
@@ -0,0 +1,10 @@
+-- Signatures already exchanged for tokens at POST /auth, kept until the signed message could
+-- no longer be accepted so the same signature never mints a second session
+CREATE TABLE IF NOT EXISTS auth_signatures (
+    signature TEXT PRIMARY KEY,
+    public_key TEXT NOT NULL,
+    nonce TEXT NOT NULL,
+    expires_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS auth_signatures_expires_at_idx ON auth_signatures (expires_at);
@@ -0,0 +1,21 @@
+-- Payment intents: an unsigned transaction handed to the wallet, moving
+-- requires_signature -> processing -> succeeded | failed, or expired if never signed in time
+CREATE TABLE IF NOT EXISTS payment_intents (
+    id TEXT PRIMARY KEY,
+    owner TEXT NOT NULL,
+    cluster TEXT NOT NULL,
+    tenant TEXT NOT NULL,
+    action TEXT NOT NULL, -- create or renew
+    plan_id BIGINT NOT NULL,
+    duration BIGINT, -- Create only
+    amount BIGINT, -- Lamports charged, when known
+    transaction TEXT NOT NULL, -- Base64 bincode of the unsigned transaction
+    last_valid_block_height BIGINT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'requires_signature',
+    signature TEXT UNIQUE, -- Set on confirm; links the intent to its transaction job
+    error TEXT,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS payment_intents_owner_idx ON payment_intents (owner, created_at);
@@ -0,0 +1,28 @@
+-- Refunds of indexed payments, paid from the treasury:
+-- requested -> processing -> succeeded | failed, or rejected by an admin
+CREATE TABLE IF NOT EXISTS refunds (
+    id TEXT PRIMARY KEY,
+    payment_signature TEXT NOT NULL,
+    instruction_index INT NOT NULL,
+    pda TEXT NOT NULL,
+    owner TEXT NOT NULL, -- Paid the subscription and receives the refund
+    plan_id BIGINT NOT NULL,
+    lamports BIGINT NOT NULL,
+    reason TEXT,
+    status TEXT NOT NULL DEFAULT 'requested',
+    requested_by TEXT NOT NULL,
+    reviewed_by TEXT, -- Admin who approved or rejected it
+    signature TEXT UNIQUE, -- Refund transaction, once approved
+    error TEXT,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL,
+    FOREIGN KEY (payment_signature, instruction_index) REFERENCES payments (signature, instruction_index)
+);
+
+-- At most one refund in flight or paid per payment
+CREATE UNIQUE INDEX IF NOT EXISTS refunds_payment_idx ON refunds (payment_signature, instruction_index)
+    WHERE status IN ('requested', 'processing', 'succeeded');
+CREATE INDEX IF NOT EXISTS refunds_status_idx ON refunds (status, created_at);
+
+-- Lamports returned on each payment, kept by the indexer alongside it
+ALTER TABLE payments ADD COLUMN IF NOT EXISTS refunded_lamports BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,15 @@
+-- Merchant discount codes. The program has no coupon instructions, so coupons live here and
+-- are applied by the checkout to the amount of a flexible-program create
+CREATE TABLE IF NOT EXISTS coupons (
+    code TEXT PRIMARY KEY, -- Uppercase
+    merchant TEXT NOT NULL,
+    percent_off INTEGER, -- Exactly one of percent_off and amount_off is set
+    amount_off BIGINT, -- Lamports
+    plan_ids BIGINT[], -- NULL matches every plan
+    expires_at BIGINT,
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS coupons_merchant_idx ON coupons (merchant, created_at);
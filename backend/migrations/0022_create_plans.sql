@@ -0,0 +1,19 @@
+-- Merchant plan catalog. The program has no Plan account, so plans are kept here, keyed by the
+-- plan_id subscriptions are created with
+CREATE TABLE IF NOT EXISTS plans (
+    plan_id BIGINT PRIMARY KEY,
+    merchant TEXT NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT,
+    image_url TEXT,
+    metadata TEXT, -- JSON object
+    duration BIGINT NOT NULL, -- Seconds
+    amount BIGINT NOT NULL, -- Lamports
+    scheduled_amount BIGINT, -- Replaces amount once scheduled_at passes
+    scheduled_at BIGINT,
+    archived_at BIGINT,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS plans_merchant_idx ON plans (merchant, plan_id);
@@ -0,0 +1,15 @@
+-- Durable nonce accounts the backend created and advances, one per queued transaction at a time
+CREATE TABLE IF NOT EXISTS nonce_accounts (
+    address TEXT PRIMARY KEY,
+    cluster TEXT NOT NULL,
+    authority TEXT NOT NULL, -- The signer that created it and may advance it
+    leased_by TEXT, -- Owner of the transaction holding it, NULL while free
+    leased_at BIGINT,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS nonce_accounts_pool_idx ON nonce_accounts (cluster, authority, leased_by);
+
+-- Set when the job's transaction was built on a durable nonce rather than a recent blockhash
+ALTER TABLE transaction_jobs ADD COLUMN IF NOT EXISTS nonce_account TEXT;
@@ -0,0 +1,2 @@
+-- Plans a merchant-wide export may include, resolved when it is requested; NULL for every plan
+ALTER TABLE payment_exports ADD COLUMN IF NOT EXISTS plan_ids BIGINT[];
@@ -0,0 +1,2 @@
+-- SOL/USD when the payment was indexed as it landed; NULL for payments found later by a backfill
+ALTER TABLE payments ADD COLUMN IF NOT EXISTS sol_usd DOUBLE PRECISION;
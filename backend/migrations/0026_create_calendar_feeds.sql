@@ -0,0 +1,7 @@
+-- One calendar feed token per wallet; only its SHA-256 is stored
+CREATE TABLE IF NOT EXISTS calendar_feeds (
+    owner TEXT PRIMARY KEY,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at BIGINT NOT NULL,
+    last_used_at BIGINT
+);
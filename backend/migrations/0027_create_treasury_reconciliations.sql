@@ -0,0 +1,14 @@
+-- Daily comparison of treasury inflows on chain with indexed payments, one per UTC day.
+-- The row is inserted before the day is reconciled, so only one replica reconciles it.
+CREATE TABLE IF NOT EXISTS treasury_reconciliations (
+    day_start BIGINT PRIMARY KEY,
+    treasury TEXT NOT NULL,
+    on_chain_lamports BIGINT,
+    on_chain_transactions BIGINT,
+    indexed_lamports BIGINT,
+    indexed_payments BIGINT,
+    difference_lamports BIGINT, -- On chain minus indexed
+    alerted BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at BIGINT NOT NULL,
+    finished_at BIGINT -- Unset while the day is being reconciled
+);
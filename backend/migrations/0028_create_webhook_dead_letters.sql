@@ -0,0 +1,19 @@
+-- Webhook deliveries that failed every attempt, kept with their event for inspection and requeueing
+CREATE TABLE IF NOT EXISTS webhook_dead_letters (
+    id TEXT PRIMARY KEY, -- The failed delivery's ID
+    webhook_id TEXT NOT NULL,
+    owner TEXT NOT NULL,
+    url TEXT NOT NULL,
+    event_id TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    body TEXT NOT NULL,
+    attempts INT NOT NULL,
+    last_status_code INT,
+    last_error TEXT,
+    failed_at BIGINT NOT NULL,
+    requeued_at BIGINT, -- Unset while the delivery is still in the queue
+    requeued_as TEXT -- The delivery that requeued it
+);
+
+CREATE INDEX IF NOT EXISTS webhook_dead_letters_queued_idx
+    ON webhook_dead_letters (failed_at DESC) WHERE requeued_at IS NULL;
@@ -0,0 +1,10 @@
+-- Access token signing keys made active through the admin API; the latest one signs
+CREATE TABLE IF NOT EXISTS jwt_key_rotations (
+    kid TEXT PRIMARY KEY,
+    public_key TEXT NOT NULL, -- Base64url Ed25519 public key
+    sealed_seed TEXT NOT NULL, -- AES-256-GCM nonce and ciphertext under the configured signing key, base64
+    previous_kid TEXT NOT NULL,
+    previous_public_key TEXT NOT NULL, -- The key it replaced, which verifies until previous_expires_at
+    previous_expires_at BIGINT NOT NULL,
+    rotated_at BIGINT NOT NULL
+);
@@ -0,0 +1,8 @@
+-- Wallets proven to belong to the same user as another; the account is its first wallet, which has no row
+CREATE TABLE IF NOT EXISTS wallet_links (
+    wallet TEXT PRIMARY KEY,
+    account TEXT NOT NULL,
+    linked_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS wallet_links_account_idx ON wallet_links (account);
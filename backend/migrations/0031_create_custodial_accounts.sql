@@ -0,0 +1,12 @@
+-- Email and password accounts whose wallet keypair the backend holds, under an envelope key
+CREATE TABLE IF NOT EXISTS custodial_accounts (
+    email TEXT PRIMARY KEY, -- Lowercased
+    password_hash TEXT NOT NULL, -- Argon2id PHC string
+    wallet TEXT NOT NULL UNIQUE,
+    key_backend TEXT NOT NULL, -- What wrapped the data key: local or aws_kms
+    wrapped_key TEXT NOT NULL, -- The wallet's AES-256 data key, wrapped, base64
+    nonce TEXT NOT NULL,
+    sealed_secret TEXT NOT NULL, -- The keypair under the data key, base64
+    created_at BIGINT NOT NULL,
+    exported_at BIGINT -- Last time the keypair was handed out for self-custody
+);
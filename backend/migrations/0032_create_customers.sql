@@ -0,0 +1,15 @@
+-- Merchants' own contact details for their subscribers' wallets
+CREATE TABLE IF NOT EXISTS customers (
+    merchant TEXT NOT NULL,
+    wallet TEXT NOT NULL,
+    email TEXT,
+    display_name TEXT,
+    email_consent BOOLEAN NOT NULL DEFAULT FALSE, -- Whether the customer agreed to be emailed
+    consent_source TEXT, -- Where the merchant collected the consent, e.g. checkout
+    consent_updated_at BIGINT, -- When email_consent last changed
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL,
+    PRIMARY KEY (merchant, wallet)
+);
+
+CREATE INDEX IF NOT EXISTS customers_wallet_idx ON customers (wallet);
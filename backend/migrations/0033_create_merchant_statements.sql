@@ -0,0 +1,14 @@
+-- Merchants' opt-in to having last month's statement emailed on the 1st
+ALTER TABLE notification_preferences ADD COLUMN IF NOT EXISTS monthly_statements BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- One row per merchant and month whose statement was emailed. The row is inserted before the
+-- statement is sent, so only one replica sends it.
+CREATE TABLE IF NOT EXISTS merchant_statements (
+    merchant TEXT NOT NULL,
+    period_start BIGINT NOT NULL,
+    created_at BIGINT NOT NULL,
+    sent_at BIGINT, -- Unset while it is being sent
+    PRIMARY KEY (merchant, period_start)
+);
+
+CREATE INDEX IF NOT EXISTS refunds_paid_idx ON refunds (updated_at) WHERE status = 'succeeded';
@@ -0,0 +1,12 @@
+-- Subscribers' own webhooks and push tokens, notified of events on their subscriptions
+CREATE TABLE IF NOT EXISTS user_notification_channels (
+    id TEXT PRIMARY KEY,
+    owner TEXT NOT NULL,
+    kind TEXT NOT NULL, -- webhook | push
+    target TEXT NOT NULL, -- Webhook URL or Expo push token
+    secret TEXT, -- Signs webhook deliveries
+    events TEXT[] NOT NULL,
+    plan_ids BIGINT[], -- NULL matches every plan
+    created_at BIGINT NOT NULL,
+    UNIQUE (owner, kind, target)
+);
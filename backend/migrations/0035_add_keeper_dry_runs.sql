@@ -0,0 +1,2 @@
+-- Runs that only reported what they would have renewed and expired
+ALTER TABLE keeper_runs ADD COLUMN IF NOT EXISTS dry_run BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+-- The payload version a dead letter's body was rendered in, resent unchanged on requeue
+ALTER TABLE webhook_dead_letters ADD COLUMN IF NOT EXISTS version TEXT NOT NULL DEFAULT 'v1';
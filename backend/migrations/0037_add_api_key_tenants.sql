@@ -0,0 +1,2 @@
+-- The tenant an API key acts for; NULL for the default tenant
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS tenant TEXT;
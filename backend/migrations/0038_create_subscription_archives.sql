@@ -0,0 +1,20 @@
+-- Backend state of closed subscriptions, moved here when the indexer sees the close so it can
+-- be put back if a fork drops the close transaction
+CREATE TABLE IF NOT EXISTS subscription_archives (
+    pda TEXT NOT NULL,
+    signature TEXT NOT NULL, -- The close_subscription transaction
+    owner TEXT NOT NULL,
+    plan_id BIGINT NOT NULL,
+    start_time BIGINT NOT NULL,
+    duration BIGINT NOT NULL,
+    amount BIGINT NOT NULL,
+    history BIGINT[] NOT NULL DEFAULT '{}',
+    auto_renew BOOLEAN, -- From subscription_settings; NULL when it had no settings
+    reminders JSONB NOT NULL DEFAULT '[]', -- Its subscription_reminders rows
+    intents_cancelled INTEGER NOT NULL DEFAULT 0,
+    slot BIGINT NOT NULL,
+    archived_at BIGINT NOT NULL,
+    PRIMARY KEY (pda, signature)
+);
+
+CREATE INDEX IF NOT EXISTS subscription_archives_signature_idx ON subscription_archives (signature);
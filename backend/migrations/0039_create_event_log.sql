@@ -0,0 +1,11 @@
+-- Dispatched events, read by long-polling integrators after a cursor
+CREATE TABLE IF NOT EXISTS event_log (
+    cursor BIGSERIAL PRIMARY KEY,
+    event_id TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    plan_id BIGINT NOT NULL,
+    body TEXT NOT NULL, -- The event as JSON, in the shape of v1 webhook payloads without `version`
+    recorded_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_event_log_recorded_at ON event_log (recorded_at);
@@ -0,0 +1,2 @@
+-- Tenant whose PII key encrypts the record's email and display name
+ALTER TABLE customers ADD COLUMN IF NOT EXISTS tenant TEXT NOT NULL DEFAULT 'default';
@@ -0,0 +1,16 @@
+-- Merchant webhooks, shared by every replica and kept across restarts
+CREATE TABLE IF NOT EXISTS webhooks (
+    id TEXT PRIMARY KEY,
+    owner TEXT NOT NULL,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL, -- Signs deliveries
+    previous_secret TEXT, -- Set by a rotation, also signs until it expires
+    previous_secret_expires_at BIGINT,
+    events TEXT[] NOT NULL,
+    plan_ids BIGINT[], -- NULL matches every plan
+    version TEXT NOT NULL DEFAULT 'v1', -- Payload version the webhook is pinned to
+    filter TEXT, -- Expression events must also match
+    created_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhooks_owner ON webhooks (owner);
@@ -0,0 +1,3 @@
+-- Set for webhooks registered by an admin, which receive events of every plan. Others only
+-- receive events of plans their owner has in the catalog
+ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS all_plans BOOLEAN NOT NULL DEFAULT FALSE;
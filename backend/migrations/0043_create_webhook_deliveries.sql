@@ -0,0 +1,26 @@
+-- Webhook deliveries, shared by every replica and kept across restarts. Pending ones are retried
+-- by the delivery worker of whichever replica claims them first
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id TEXT PRIMARY KEY,
+    webhook_id TEXT NOT NULL,
+    owner TEXT NOT NULL, -- The webhook's owner
+    event_id TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    version TEXT NOT NULL, -- Payload version of `body`
+    body TEXT NOT NULL, -- The event as sent, resent unchanged by retries and replays
+    status TEXT NOT NULL DEFAULT 'pending', -- pending -> succeeded | failed
+    attempts INT NOT NULL DEFAULT 0,
+    last_status_code INT,
+    last_error TEXT,
+    next_attempt_at BIGINT, -- Next send while pending, pushed ahead while a replica sends it
+    replay_of TEXT, -- The delivery or dead letter this one sends again
+    request_id TEXT, -- Of the request that caused the event, sent with every attempt
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_due_idx
+    ON webhook_deliveries (next_attempt_at) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS webhook_deliveries_webhook_idx ON webhook_deliveries (webhook_id, updated_at DESC);
+CREATE INDEX IF NOT EXISTS webhook_deliveries_finished_idx
+    ON webhook_deliveries (updated_at) WHERE status <> 'pending';
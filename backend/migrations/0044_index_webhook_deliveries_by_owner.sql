@@ -0,0 +1,2 @@
+-- Lists a merchant's deliveries across all of its webhooks
+CREATE INDEX IF NOT EXISTS webhook_deliveries_owner_idx ON webhook_deliveries (owner, updated_at DESC);
@@ -0,0 +1,21 @@
+-- Withdrawals from the default tenant's treasury, approved by several admins and sent once
+-- their timelock has passed: requested -> approved -> processing -> succeeded | failed, or
+-- rejected by an admin before it is sent
+CREATE TABLE IF NOT EXISTS treasury_withdrawals (
+    id TEXT PRIMARY KEY,
+    destination TEXT NOT NULL,
+    lamports BIGINT NOT NULL,
+    reason TEXT,
+    status TEXT NOT NULL DEFAULT 'requested',
+    requested_by TEXT NOT NULL,
+    approvals_required INT NOT NULL, -- Admins other than the requester who must approve it
+    approved_by TEXT[] NOT NULL DEFAULT '{}',
+    executable_at BIGINT NOT NULL, -- Not sent before this
+    reviewed_by TEXT, -- Admin who sent or rejected it
+    signature TEXT UNIQUE, -- Withdrawal transaction, once sent
+    error TEXT,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS treasury_withdrawals_status_idx ON treasury_withdrawals (status, created_at);
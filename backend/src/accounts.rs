@@ -0,0 +1,264 @@
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{account::Account, commitment_config::CommitmentConfig, pubkey::Pubkey};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+use crate::cluster::RpcPool;
+use crate::metrics;
+use crate::{AppError, AppResult, Config};
+
+const PROMOTE_INTERVAL: Duration = Duration::from_secs(2);
+// getMultipleAccounts takes at most 100 keys
+const MULTIPLE_ACCOUNTS_LIMIT: usize = 100;
+// Chunks of one `get_many` in flight at once
+const MAX_CONCURRENT_CHUNKS: usize = 4;
+
+/// How settled an account read must be. `processed` is the newest state a node has seen and
+/// suits UI reads; `finalized` cannot be rolled back and is what billing decisions use.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Commitment {
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+impl Commitment {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Commitment::Processed => "processed",
+            Commitment::Confirmed => "confirmed",
+            Commitment::Finalized => "finalized",
+        }
+    }
+
+    pub fn config(&self) -> CommitmentConfig {
+        match self {
+            Commitment::Processed => CommitmentConfig::processed(),
+            Commitment::Confirmed => CommitmentConfig::confirmed(),
+            Commitment::Finalized => CommitmentConfig::finalized(),
+        }
+    }
+
+    fn stronger(&self) -> Option<Commitment> {
+        match self {
+            Commitment::Processed => Some(Commitment::Confirmed),
+            Commitment::Confirmed => Some(Commitment::Finalized),
+            Commitment::Finalized => None,
+        }
+    }
+}
+
+impl FromStr for Commitment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "processed" => Ok(Commitment::Processed),
+            "confirmed" => Ok(Commitment::Confirmed),
+            "finalized" => Ok(Commitment::Finalized),
+            other => Err(format!("Unknown commitment {}", other)),
+        }
+    }
+}
+
+struct Entry {
+    account: Option<Account>, // None when the account does not exist
+    slot: u64, // Context slot of the read
+    fetched_at: Instant,
+}
+
+// Account Cache
+/// Account reads for one cluster and tenant, cached by (pubkey, commitment). A read is served by a
+/// fresh entry at the requested commitment or a stronger one, whichever saw the latest slot,
+/// so a `processed` read never returns an older state than a `finalized` one already cached.
+///
+/// Entries below `finalized` are promoted in the background: once the cluster's confirmed
+/// (then finalized) slot reaches an entry's slot, the account is re-read at that commitment
+/// and cached there, so a billing read soon after a UI read is usually a cache hit.
+#[derive(Clone)]
+pub struct AccountCache {
+    rpc: RpcPool,
+    entries: Arc<Mutex<HashMap<(Pubkey, Commitment), Entry>>>,
+    ttl: Duration, // For processed and confirmed entries
+    finalized_ttl: Duration,
+    capacity: usize,
+}
+
+impl AccountCache {
+    pub fn new(config: &Config, rpc: RpcPool) -> Self {
+        Self {
+            rpc,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            ttl: Duration::from_millis(config.account_cache_ttl_ms),
+            finalized_ttl: Duration::from_millis(config.account_cache_finalized_ttl_ms),
+            capacity: config.account_cache_capacity,
+        }
+    }
+
+    fn ttl(&self, commitment: Commitment) -> Duration {
+        match commitment {
+            Commitment::Finalized => self.finalized_ttl,
+            _ => self.ttl,
+        }
+    }
+
+    fn cached(&self, pubkey: &Pubkey, commitment: Commitment) -> Option<Option<Account>> {
+        let entries = self.entries.lock().unwrap();
+        [Commitment::Processed, Commitment::Confirmed, Commitment::Finalized]
+            .into_iter()
+            .filter(|level| *level >= commitment)
+            .filter_map(|level| {
+                entries
+                    .get(&(*pubkey, level))
+                    .filter(|entry| entry.fetched_at.elapsed() < self.ttl(level))
+            })
+            .max_by_key(|entry| entry.slot)
+            .map(|entry| entry.account.clone())
+    }
+
+    fn store(&self, pubkey: Pubkey, commitment: Commitment, account: Option<Account>, slot: u64) {
+        if self.ttl(commitment).is_zero() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity {
+            entries.retain(|(_, level), entry| entry.fetched_at.elapsed() < self.ttl(*level));
+            if entries.len() >= self.capacity {
+                return;
+            }
+        }
+        // Reads racing each other must not replace a later state with an earlier one
+        if entries.get(&(pubkey, commitment)).is_some_and(|entry| entry.slot > slot) {
+            return;
+        }
+        entries.insert((pubkey, commitment), Entry { account, slot, fetched_at: Instant::now() });
+    }
+
+    /// The account at `commitment`, or `None` if it does not exist.
+    pub async fn get(&self, pubkey: &Pubkey, commitment: Commitment) -> AppResult<Option<Account>> {
+        if let Some(account) = self.cached(pubkey, commitment) {
+            metrics::record_account_cache(commitment.as_str(), true, 1);
+            return Ok(account);
+        }
+        metrics::record_account_cache(commitment.as_str(), false, 1);
+        let response = metrics::observe_rpc(
+            "getAccountInfo",
+            self.rpc.client().get_account_with_commitment(pubkey, commitment.config()),
+        )
+        .await
+        .map_err(|e| AppError::rpc("Failed to fetch account", e))?;
+        self.store(*pubkey, commitment, response.value.clone(), response.context.slot);
+        Ok(response.value)
+    }
+
+    /// The accounts at `commitment`, in the order given, with `None` for those that do not
+    /// exist. Accounts not cached are fetched with `getMultipleAccounts`, up to
+    /// `MAX_CONCURRENT_CHUNKS` chunks of 100 at a time.
+    pub async fn get_many(&self, pubkeys: &[Pubkey], commitment: Commitment) -> AppResult<Vec<Option<Account>>> {
+        let mut accounts: Vec<Option<Option<Account>>> =
+            pubkeys.iter().map(|pubkey| self.cached(pubkey, commitment)).collect();
+        let hits = accounts.iter().filter(|account| account.is_some()).count();
+        metrics::record_account_cache(commitment.as_str(), true, hits as u64);
+
+        let missing: Vec<(usize, Pubkey)> = pubkeys
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| accounts[*index].is_none())
+            .map(|(index, pubkey)| (index, *pubkey))
+            .collect();
+        metrics::record_account_cache(commitment.as_str(), false, missing.len() as u64);
+        // Owned chunks, so the future is `Send` for any caller, including `async_trait` methods
+        let chunks: Vec<Vec<(usize, Pubkey)>> = missing.chunks(MULTIPLE_ACCOUNTS_LIMIT).map(<[_]>::to_vec).collect();
+        let fetched: Vec<Vec<(usize, Option<Account>)>> = stream::iter(chunks)
+            .map(|chunk| async move {
+                let keys: Vec<Pubkey> = chunk.iter().map(|(_, pubkey)| *pubkey).collect();
+                let response = metrics::observe_rpc(
+                    "getMultipleAccounts",
+                    self.rpc.client().get_multiple_accounts_with_commitment(&keys, commitment.config()),
+                )
+                .await
+                .map_err(|e| AppError::rpc("Failed to fetch accounts", e))?;
+                let slot = response.context.slot;
+                Ok::<_, AppError>(
+                    chunk
+                        .iter()
+                        .zip(response.value)
+                        .map(|((index, pubkey), account)| {
+                            self.store(*pubkey, commitment, account.clone(), slot);
+                            (*index, account)
+                        })
+                        .collect(),
+                )
+            })
+            .buffer_unordered(MAX_CONCURRENT_CHUNKS)
+            .try_collect()
+            .await?;
+        for (index, account) in fetched.into_iter().flatten() {
+            accounts[index] = Some(account);
+        }
+        Ok(accounts.into_iter().map(Option::flatten).collect())
+    }
+
+    /// Drops every cached state of `pubkey`, after a transaction that writes it.
+    pub fn invalidate(&self, pubkey: &Pubkey) {
+        self.entries.lock().unwrap().retain(|(key, _), _| key != pubkey);
+    }
+
+    /// Re-reads, one commitment up, the fresh entries the cluster has since confirmed or
+    /// finalized the slot of.
+    async fn promote(&self) -> AppResult<usize> {
+        let client = self.rpc.client();
+        let mut promoted = 0;
+        for commitment in [Commitment::Processed, Commitment::Confirmed] {
+            let target = commitment.stronger().expect("only finalized has no stronger commitment");
+            let settled_slot = metrics::observe_rpc("getSlot", client.get_slot_with_commitment(target.config()))
+                .await
+                .map_err(|e| AppError::rpc("Failed to fetch slot", e))?;
+            let due: Vec<Pubkey> = {
+                let entries = self.entries.lock().unwrap();
+                entries
+                    .iter()
+                    .filter(|((_, level), entry)| {
+                        *level == commitment && entry.slot <= settled_slot && entry.fetched_at.elapsed() < self.ttl
+                    })
+                    .filter(|((pubkey, _), entry)| {
+                        entries.get(&(*pubkey, target)).is_none_or(|settled| settled.slot < entry.slot)
+                    })
+                    .map(|((pubkey, _), _)| *pubkey)
+                    .collect()
+            };
+            for chunk in due.chunks(MULTIPLE_ACCOUNTS_LIMIT) {
+                let response = metrics::observe_rpc(
+                    "getMultipleAccounts",
+                    client.get_multiple_accounts_with_commitment(chunk, target.config()),
+                )
+                .await
+                .map_err(|e| AppError::rpc("Failed to fetch accounts", e))?;
+                for (pubkey, account) in chunk.iter().zip(response.value) {
+                    self.store(*pubkey, target, account, response.context.slot);
+                    promoted += 1;
+                }
+            }
+        }
+        Ok(promoted)
+    }
+
+    pub async fn run_promoter(self) {
+        if self.ttl.is_zero() || self.finalized_ttl.is_zero() {
+            return;
+        }
+        loop {
+            tokio::time::sleep(PROMOTE_INTERVAL).await;
+            if self.entries.lock().unwrap().is_empty() {
+                continue;
+            }
+            if let Err(e) = self.promote().await {
+                tracing::debug!("Account cache promotion skipped: {}", e);
+            }
+        }
+    }
+}
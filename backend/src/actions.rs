@@ -0,0 +1,161 @@
+use actix_web::middleware::DefaultHeaders;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+use crate::cluster::{Cluster, SolanaClusters};
+use crate::plans::PlanService;
+use crate::solana_pay::{self, PayAction, SolanaPay};
+use crate::validation::{validate_pubkey, ValidatedJson};
+use crate::AppResult;
+
+// Version of the Solana Actions spec the responses follow
+const ACTION_VERSION: &str = "2.1.3";
+
+// Models
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct ActionRule {
+    #[serde(rename = "pathPattern")]
+    path_pattern: String,
+    #[serde(rename = "apiPath")]
+    api_path: String,
+}
+
+/// `actions.json`: maps website paths to the Action API, here one-to-one.
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct ActionsManifest {
+    rules: Vec<ActionRule>,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct ActionError {
+    message: String,
+}
+
+/// What a Blink renders: the plan as a card with one button.
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct ActionMetadata {
+    #[serde(rename = "type")]
+    kind: String, // Always "action"
+    icon: String,
+    title: String,
+    description: String,
+    label: String, // Button text
+    disabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ActionError>, // Why the button is disabled
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct ActionRequest {
+    #[validate(custom = "validate_pubkey")]
+    account: String, // The wallet that signs and pays for the transaction
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct ActionTransaction {
+    #[serde(rename = "type")]
+    kind: String, // Always "transaction"
+    transaction: String, // Base64 bincode of the unsigned transaction, the wallet as fee payer
+    message: String,
+}
+
+/// The headers Blink clients expect on every Action response, errors included. Actions are
+/// served on `cluster`, the primary cluster, as Blink clients cannot select another.
+pub fn headers(cluster: Cluster) -> DefaultHeaders {
+    let headers = DefaultHeaders::new().add(("X-Action-Version", ACTION_VERSION));
+    match cluster.chain_id() {
+        Some(chain_id) => headers.add(("X-Blockchain-Ids", chain_id)),
+        None => headers,
+    }
+}
+
+// Controllers
+#[utoipa::path(
+    get,
+    path = "/actions.json",
+    tag = "actions",
+    responses((status = 200, description = "Rules mapping website paths to Actions", body = ActionsManifest))
+)]
+#[get("/actions.json")]
+pub async fn actions_manifest() -> HttpResponse {
+    HttpResponse::Ok().json(ActionsManifest {
+        rules: vec![ActionRule { path_pattern: "/actions/**".to_string(), api_path: "/actions/**".to_string() }],
+    })
+}
+
+/// The plan as a Blink. Archived plans are shown with the button disabled.
+#[utoipa::path(
+    get,
+    path = "/actions/subscribe/{plan_id}",
+    tag = "actions",
+    params(("plan_id" = u64, Path, description = "Plan identifier")),
+    responses(
+        (status = 200, description = "Action metadata", body = ActionMetadata),
+        (status = 404, description = "Plan not in the catalog", body = ErrorResponse),
+    )
+)]
+#[get("/subscribe/{plan_id}")]
+pub async fn subscribe_action(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    clusters: web::Data<SolanaClusters>,
+    solana_pay: web::Data<SolanaPay>,
+    plans: web::Data<PlanService>,
+) -> AppResult<HttpResponse> {
+    let solana_service = clusters.select(&req)?;
+    let plan = plans.published(path.into_inner()).await?;
+    let price = solana_pay::price(solana_service, &plan.terms());
+    let error = plan.archived().then(|| ActionError { message: "This plan is no longer offered".to_string() });
+    Ok(HttpResponse::Ok().json(ActionMetadata {
+        kind: "action".to_string(),
+        icon: plan.image_url().unwrap_or(solana_pay.icon()).to_string(),
+        title: plan.name().to_string(),
+        description: match plan.description() {
+            Some(description) => format!("{}\n\n{}", description, price),
+            None => price,
+        },
+        label: "Subscribe".to_string(),
+        disabled: error.is_some(),
+        error,
+    }))
+}
+
+/// The create transaction for the wallet in `account` to sign and send, at the plan's current
+/// catalog price.
+#[utoipa::path(
+    post,
+    path = "/actions/subscribe/{plan_id}",
+    tag = "actions",
+    params(("plan_id" = u64, Path, description = "Plan identifier")),
+    request_body = ActionRequest,
+    responses(
+        (status = 200, description = "Transaction for the wallet to sign and send", body = ActionTransaction),
+        (status = 400, description = "Subscription already exists or plan archived", body = ErrorResponse),
+        (status = 404, description = "Plan not in the catalog", body = ErrorResponse),
+        (status = 422, description = "Invalid account", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded, retry after `Retry-After` seconds", body = ErrorResponse),
+        (status = 502, description = "RPC node unavailable", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    )
+)]
+#[post("/subscribe/{plan_id}")]
+pub async fn subscribe_transaction(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    clusters: web::Data<SolanaClusters>,
+    plans: web::Data<PlanService>,
+    body: ValidatedJson<ActionRequest>,
+) -> AppResult<HttpResponse> {
+    let solana_service = clusters.select(&req)?;
+    let (transaction, message) = solana_pay::build_transaction(
+        solana_service,
+        &plans,
+        PayAction::Subscribe,
+        path.into_inner(),
+        &body.account,
+        None,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(ActionTransaction { kind: "transaction".to_string(), transaction, message }))
+}
@@ -0,0 +1,175 @@
+use actix_web::{post, web, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use sqlx::postgres::PgPool;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+use validator::Validate;
+use crate::audit;
+use crate::cluster::{Cluster, SolanaClusters};
+use crate::metrics;
+use crate::validation::ValidatedJson;
+use crate::{AppError, AppResult, AuthToken, Config, SolanaService};
+
+const DAY_SECS: i64 = 86400;
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct AirdropRequest {
+    #[validate(range(min = 1, message = "must be a positive lamport amount"))]
+    lamports: Option<u64>, // Defaults to AIRDROP_LAMPORTS
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct AirdropResponse {
+    signature: String,
+    lamports: u64,
+    remaining_today_lamports: u64, // Left under the wallet's daily cap
+}
+
+// Airdrop Service
+/// Requests faucet airdrops for test wallets, capped per wallet over a rolling 24 hours.
+/// Only registered when `DEVNET_AIRDROP_ENABLED` is set and the primary cluster is not
+/// mainnet; requests selecting mainnet are refused as well.
+#[derive(Clone)]
+pub struct AirdropService {
+    pool: PgPool,
+    default_lamports: u64,
+    daily_cap_lamports: u64,
+}
+
+impl AirdropService {
+    pub fn new(config: &Config, pool: PgPool) -> Self {
+        Self {
+            pool,
+            default_lamports: config.airdrop_lamports,
+            daily_cap_lamports: config.airdrop_daily_cap_lamports,
+        }
+    }
+
+    pub async fn airdrop(
+        &self,
+        solana_service: &SolanaService,
+        wallet: &str,
+        lamports: Option<u64>,
+    ) -> AppResult<AirdropResponse> {
+        if solana_service.cluster == Cluster::Mainnet {
+            return Err(AppError::Forbidden("Airdrops are not available on mainnet".to_string()));
+        }
+        let pubkey = Pubkey::from_str(wallet)
+            .map_err(|e| AppError::BadRequest(format!("Invalid public key: {}", e)))?;
+        let lamports = lamports.unwrap_or(self.default_lamports);
+        let (id, used) = self.reserve(wallet, solana_service.cluster, lamports).await?;
+
+        let client = solana_service.rpc.client();
+        let result = metrics::observe_rpc("requestAirdrop", client.request_airdrop(&pubkey, lamports)).await;
+        let signature = match result {
+            Ok(signature) => signature.to_string(),
+            Err(e) => {
+                // A refused airdrop does not count against the cap
+                self.release(id).await;
+                return Err(AppError::rpc("Airdrop failed", e));
+            }
+        };
+        sqlx::query("UPDATE airdrops SET signature = $2 WHERE id = $1")
+            .bind(id)
+            .bind(&signature)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to record airdrop: {}", e)))?;
+
+        Ok(AirdropResponse {
+            signature,
+            lamports,
+            remaining_today_lamports: self.daily_cap_lamports.saturating_sub(used.saturating_add(lamports)),
+        })
+    }
+
+    /// Records the airdrop against the wallet's cap before it is requested. The advisory lock
+    /// serialises concurrent requests for the same wallet so they cannot both pass the check.
+    async fn reserve(&self, wallet: &str, cluster: Cluster, lamports: u64) -> AppResult<(i64, u64)> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to start transaction: {}", e)))?;
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+            .bind(wallet)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to lock wallet: {}", e)))?;
+
+        let used = sqlx::query_scalar::<_, i64>(
+            "SELECT COALESCE(SUM(lamports), 0)::BIGINT FROM airdrops WHERE wallet = $1 AND created_at > $2",
+        )
+        .bind(wallet)
+        .bind(now() - DAY_SECS)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to sum airdrops: {}", e)))? as u64;
+        if used.saturating_add(lamports) > self.daily_cap_lamports {
+            return Err(AppError::RateLimited(format!(
+                "Daily airdrop cap of {} lamports reached, {} lamports left",
+                self.daily_cap_lamports,
+                self.daily_cap_lamports.saturating_sub(used)
+            )));
+        }
+
+        let id = sqlx::query_scalar::<_, i64>(
+            "INSERT INTO airdrops (wallet, cluster, lamports, created_at) VALUES ($1, $2, $3, $4) RETURNING id",
+        )
+        .bind(wallet)
+        .bind(cluster.as_str())
+        .bind(lamports as i64)
+        .bind(now())
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to record airdrop: {}", e)))?;
+        tx.commit()
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to record airdrop: {}", e)))?;
+        Ok((id, used))
+    }
+
+    async fn release(&self, id: i64) {
+        let result = sqlx::query("DELETE FROM airdrops WHERE id = $1").bind(id).execute(&self.pool).await;
+        if let Err(e) = result {
+            tracing::error!("Failed to release airdrop {}: {}", id, e);
+        }
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+// Controllers
+/// Airdrops devnet SOL to the authenticated wallet.
+#[utoipa::path(
+    post,
+    path = "/api/v1/devnet/airdrop",
+    tag = "devnet",
+    request_body = AirdropRequest,
+    responses(
+        (status = 200, description = "Airdrop requested", body = AirdropResponse),
+        (status = 403, description = "Selected cluster is mainnet", body = ErrorResponse),
+        (status = 429, description = "Daily cap reached", body = ErrorResponse),
+        (status = 502, description = "Faucet refused the airdrop", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/devnet/airdrop")]
+pub async fn request_airdrop(
+    req: HttpRequest,
+    clusters: web::Data<SolanaClusters>,
+    airdrops: web::Data<AirdropService>,
+    body: ValidatedJson<AirdropRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let solana_service = clusters.select(&req)?;
+    let airdrop = airdrops.airdrop(solana_service, &auth_token.public_key, body.lamports).await?;
+    audit::attach_signatures(&req, [&airdrop.signature]);
+    Ok(HttpResponse::Ok().json(airdrop))
+}
@@ -0,0 +1,408 @@
+use actix_web::{get, web, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+use crate::cache::{CacheNamespace, ResponseCache};
+use crate::plans::PlanService;
+use crate::validation::{FieldError, ValidatedQuery};
+use crate::{AppError, AppResult, AuthToken};
+
+const MONTH_SECS: i64 = 30 * 86400;
+const DEFAULT_WINDOW_SECS: i64 = 30 * 86400;
+const MAX_BUCKETS: i64 = 1000;
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema, async_graphql::Enum)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    Day,
+    Week,
+    Month,
+}
+
+impl Granularity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Granularity::Day => "day",
+            Granularity::Week => "week",
+            Granularity::Month => "month",
+        }
+    }
+
+    fn approx_secs(&self) -> i64 {
+        match self {
+            Granularity::Day => 86400,
+            Granularity::Week => 7 * 86400,
+            Granularity::Month => MONTH_SECS,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct PlanQuery {
+    #[validate(range(max = 9223372036854775807, message = "must fit in a signed 64-bit integer"))]
+    plan_id: Option<u64>,
+}
+
+/// Time window in unix seconds; defaults to the last 30 days.
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct WindowQuery {
+    #[validate(range(max = 9223372036854775807, message = "must fit in a signed 64-bit integer"))]
+    plan_id: Option<u64>,
+    #[validate(range(min = 0, message = "must not be negative"))]
+    from: Option<i64>,
+    #[validate(range(min = 0, message = "must not be negative"))]
+    to: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct RevenueQuery {
+    #[validate(range(max = 9223372036854775807, message = "must fit in a signed 64-bit integer"))]
+    plan_id: Option<u64>,
+    #[validate(range(min = 0, message = "must not be negative"))]
+    from: Option<i64>,
+    #[validate(range(min = 0, message = "must not be negative"))]
+    to: Option<i64>,
+    granularity: Option<Granularity>, // Defaults to day
+}
+
+pub fn window(from: Option<i64>, to: Option<i64>) -> AppResult<(i64, i64)> {
+    let to = to.unwrap_or_else(now);
+    let from = from.unwrap_or(to - DEFAULT_WINDOW_SECS);
+    if from >= to {
+        return Err(AppError::Validation(vec![FieldError::new("from", "window", "must be before to")]));
+    }
+    Ok((from, to))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, async_graphql::SimpleObject)]
+pub struct MrrResponse {
+    mrr_lamports: u64, // Amount of every active subscription normalised to 30 days
+    active_subscriptions: i64,
+    as_of: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, async_graphql::SimpleObject)]
+pub struct ChurnResponse {
+    from: i64,
+    to: i64,
+    active_at_start: i64,
+    churned: i64, // Active at `from`, no longer paid up at `to`
+    churn_rate: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, async_graphql::SimpleObject)]
+pub struct SubscriberBreakdown {
+    from: i64,
+    to: i64,
+    new_subscribers: i64, // Wallets whose first payment is in the window
+    returning_subscribers: i64, // Wallets that paid in the window and before it
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow, ToSchema, async_graphql::SimpleObject)]
+pub struct RevenuePoint {
+    period_start: i64,
+    revenue_lamports: i64,
+    payments: i64,
+}
+
+// Analytics Service
+/// Merchant metrics computed from the indexed `payments` and `subscriptions` tables, over the
+/// given plans or, with `None`, every plan. Payments without a block time are left out.
+#[derive(Clone)]
+pub struct AnalyticsService {
+    pool: PgPool,
+}
+
+impl AnalyticsService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn mrr(&self, plan_ids: Option<&[i64]>) -> AppResult<MrrResponse> {
+        let as_of = now();
+        let (lamports, active) = sqlx::query_as::<_, (f64, i64)>(
+            "SELECT COALESCE(SUM(amount::FLOAT8 * $1 / GREATEST(duration, 1)), 0), COUNT(*)
+             FROM subscriptions
+             WHERE active AND NOT closed
+               AND start_time + duration > $2
+               AND ($3::BIGINT[] IS NULL OR plan_id = ANY($3))",
+        )
+        .bind(MONTH_SECS as f64)
+        .bind(as_of)
+        .bind(plan_ids)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to compute MRR: {}", e)))?;
+
+        Ok(MrrResponse {
+            mrr_lamports: lamports.round() as u64,
+            active_subscriptions: active,
+            as_of,
+        })
+    }
+
+    /// A subscription counts as active at `t` when one of its payments covers `t`.
+    pub async fn churn(&self, plan_ids: Option<&[i64]>, from: i64, to: i64) -> AppResult<ChurnResponse> {
+        let (active_at_start, churned) = sqlx::query_as::<_, (i64, i64)>(
+            "WITH covered AS (
+                 SELECT p.pda, p.block_time, p.block_time + s.duration AS covered_until
+                 FROM payments p
+                 JOIN subscriptions s ON s.pda = p.pda
+                 WHERE p.block_time IS NOT NULL
+                   AND ($1::BIGINT[] IS NULL OR p.plan_id = ANY($1))
+             ),
+             at_start AS (
+                 SELECT DISTINCT pda FROM covered WHERE block_time <= $2 AND covered_until > $2
+             ),
+             at_end AS (
+                 SELECT DISTINCT pda FROM covered WHERE block_time <= $3 AND covered_until > $3
+             )
+             SELECT
+                 (SELECT COUNT(*) FROM at_start),
+                 (SELECT COUNT(*) FROM at_start WHERE pda NOT IN (SELECT pda FROM at_end))",
+        )
+        .bind(plan_ids)
+        .bind(from)
+        .bind(to)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to compute churn: {}", e)))?;
+
+        Ok(ChurnResponse {
+            from,
+            to,
+            active_at_start,
+            churned,
+            churn_rate: if active_at_start > 0 { churned as f64 / active_at_start as f64 } else { 0.0 },
+        })
+    }
+
+    pub async fn subscribers(&self, plan_ids: Option<&[i64]>, from: i64, to: i64) -> AppResult<SubscriberBreakdown> {
+        let (new_subscribers, returning_subscribers) = sqlx::query_as::<_, (i64, i64)>(
+            "WITH wallets AS (
+                 SELECT owner, MIN(block_time) AS first_paid
+                 FROM payments
+                 WHERE block_time IS NOT NULL AND ($1::BIGINT[] IS NULL OR plan_id = ANY($1))
+                 GROUP BY owner
+                 HAVING BOOL_OR(block_time >= $2 AND block_time < $3)
+             )
+             SELECT
+                 COUNT(*) FILTER (WHERE first_paid >= $2),
+                 COUNT(*) FILTER (WHERE first_paid < $2)
+             FROM wallets",
+        )
+        .bind(plan_ids)
+        .bind(from)
+        .bind(to)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to count subscribers: {}", e)))?;
+
+        Ok(SubscriberBreakdown { from, to, new_subscribers, returning_subscribers })
+    }
+
+    /// Revenue per UTC day, week (starting Monday) or calendar month. Empty periods are omitted.
+    pub async fn revenue(
+        &self,
+        plan_ids: Option<&[i64]>,
+        from: i64,
+        to: i64,
+        granularity: Granularity,
+    ) -> AppResult<Vec<RevenuePoint>> {
+        sqlx::query_as::<_, RevenuePoint>(
+            "SELECT
+                 EXTRACT(EPOCH FROM date_trunc($1, to_timestamp(block_time) AT TIME ZONE 'UTC'))::BIGINT AS period_start,
+                 SUM(amount)::BIGINT AS revenue_lamports,
+                 COUNT(*) AS payments
+             FROM payments
+             WHERE block_time >= $2 AND block_time < $3
+               AND ($4::BIGINT[] IS NULL OR plan_id = ANY($4))
+             GROUP BY 1
+             ORDER BY 1",
+        )
+        .bind(granularity.as_str())
+        .bind(from)
+        .bind(to)
+        .bind(plan_ids)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to compute revenue: {}", e)))
+    }
+}
+
+/// Rejects revenue series that would have more than `MAX_BUCKETS` periods.
+pub fn check_buckets(from: i64, to: i64, granularity: Granularity) -> AppResult<()> {
+    if (to - from) / granularity.approx_secs() > MAX_BUCKETS {
+        return Err(AppError::Validation(vec![FieldError::new(
+            "granularity",
+            "too_many_buckets",
+            format!("window spans more than {} periods, use a coarser granularity", MAX_BUCKETS),
+        )]));
+    }
+    Ok(())
+}
+
+/// The plans a request's analytics cover: the caller's own, or `plan_id` if it is one of them.
+async fn plan_ids(req: &HttpRequest, plans: &PlanService, plan_id: Option<u64>) -> AppResult<Option<Vec<i64>>> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    plans.scope(&auth_token).await?.narrow(plan_id)
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+/// Cache key of an analytics response: the query as sent, so a window defaulting to now
+/// shares one entry and is recomputed as of each refresh.
+fn cache_key(metric: &str, plan_ids: &Option<Vec<i64>>, from: Option<i64>, to: Option<i64>, granularity: &str) -> String {
+    format!("{}:{:?}:{:?}:{:?}:{}", metric, plan_ids, from, to, granularity)
+}
+
+// Controllers
+/// Monthly recurring revenue of subscriptions that are currently paid up.
+#[utoipa::path(
+    get,
+    path = "/api/v1/analytics/mrr",
+    tag = "analytics",
+    params(PlanQuery),
+    responses(
+        (status = 200, description = "Current MRR", body = MrrResponse),
+        (status = 403, description = "Merchant role required, or the plan belongs to another merchant", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/mrr")]
+pub async fn mrr(
+    req: HttpRequest,
+    analytics: web::Data<AnalyticsService>,
+    plans: web::Data<PlanService>,
+    responses: web::Data<ResponseCache>,
+    query: ValidatedQuery<PlanQuery>,
+) -> AppResult<HttpResponse> {
+    let plan_ids = plan_ids(&req, &plans, query.plan_id).await?;
+    let key = cache_key("mrr", &plan_ids, None, None, "");
+    let analytics = analytics.get_ref().clone();
+    let mrr = responses
+        .get(CacheNamespace::Analytics, key, move || {
+            let (analytics, plan_ids) = (analytics.clone(), plan_ids.clone());
+            async move { analytics.mrr(plan_ids.as_deref()).await }
+        })
+        .await?;
+    Ok(HttpResponse::Ok().json(mrr))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/analytics/churn",
+    tag = "analytics",
+    params(WindowQuery),
+    responses(
+        (status = 200, description = "Churn over the window", body = ChurnResponse),
+        (status = 403, description = "Plan belongs to another merchant", body = ErrorResponse),
+        (status = 422, description = "Invalid window", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/churn")]
+pub async fn churn(
+    req: HttpRequest,
+    analytics: web::Data<AnalyticsService>,
+    plans: web::Data<PlanService>,
+    responses: web::Data<ResponseCache>,
+    query: ValidatedQuery<WindowQuery>,
+) -> AppResult<HttpResponse> {
+    window(query.from, query.to)?;
+    let plan_ids = plan_ids(&req, &plans, query.plan_id).await?;
+    let (from, to) = (query.from, query.to);
+    let key = cache_key("churn", &plan_ids, from, to, "");
+    let analytics = analytics.get_ref().clone();
+    let churn = responses
+        .get(CacheNamespace::Analytics, key, move || {
+            let (analytics, plan_ids) = (analytics.clone(), plan_ids.clone());
+            async move {
+                let (from, to) = window(from, to)?;
+                analytics.churn(plan_ids.as_deref(), from, to).await
+            }
+        })
+        .await?;
+    Ok(HttpResponse::Ok().json(churn))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/analytics/subscribers",
+    tag = "analytics",
+    params(WindowQuery),
+    responses(
+        (status = 200, description = "New and returning paying wallets in the window", body = SubscriberBreakdown),
+        (status = 403, description = "Plan belongs to another merchant", body = ErrorResponse),
+        (status = 422, description = "Invalid window", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/subscribers")]
+pub async fn subscribers(
+    req: HttpRequest,
+    analytics: web::Data<AnalyticsService>,
+    plans: web::Data<PlanService>,
+    responses: web::Data<ResponseCache>,
+    query: ValidatedQuery<WindowQuery>,
+) -> AppResult<HttpResponse> {
+    window(query.from, query.to)?;
+    let plan_ids = plan_ids(&req, &plans, query.plan_id).await?;
+    let (from, to) = (query.from, query.to);
+    let key = cache_key("subscribers", &plan_ids, from, to, "");
+    let analytics = analytics.get_ref().clone();
+    let breakdown = responses
+        .get(CacheNamespace::Analytics, key, move || {
+            let (analytics, plan_ids) = (analytics.clone(), plan_ids.clone());
+            async move {
+                let (from, to) = window(from, to)?;
+                analytics.subscribers(plan_ids.as_deref(), from, to).await
+            }
+        })
+        .await?;
+    Ok(HttpResponse::Ok().json(breakdown))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/analytics/revenue",
+    tag = "analytics",
+    params(RevenueQuery),
+    responses(
+        (status = 200, description = "Revenue time series", body = [RevenuePoint]),
+        (status = 403, description = "Plan belongs to another merchant", body = ErrorResponse),
+        (status = 422, description = "Invalid window or granularity", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/revenue")]
+pub async fn revenue(
+    req: HttpRequest,
+    analytics: web::Data<AnalyticsService>,
+    plans: web::Data<PlanService>,
+    responses: web::Data<ResponseCache>,
+    query: ValidatedQuery<RevenueQuery>,
+) -> AppResult<HttpResponse> {
+    let (from, to) = window(query.from, query.to)?;
+    let granularity = query.granularity.unwrap_or(Granularity::Day);
+    check_buckets(from, to, granularity)?;
+    let plan_ids = plan_ids(&req, &plans, query.plan_id).await?;
+    let (from, to) = (query.from, query.to);
+    let key = cache_key("revenue", &plan_ids, from, to, granularity.as_str());
+    let analytics = analytics.get_ref().clone();
+    let series = responses
+        .get(CacheNamespace::Analytics, key, move || {
+            let (analytics, plan_ids) = (analytics.clone(), plan_ids.clone());
+            async move {
+                let (from, to) = window(from, to)?;
+                analytics.revenue(plan_ids.as_deref(), from, to, granularity).await
+            }
+        })
+        .await?;
+    Ok(HttpResponse::Ok().json(series))
+}
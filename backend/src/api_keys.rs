@@ -0,0 +1,301 @@
+use actix_web::{delete, get, post, web, HttpMessage, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use solana_sdk::pubkey::Pubkey;
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+use validator::Validate;
+use crate::tenant::DEFAULT_TENANT;
+use crate::validation::{validate_pubkey, ValidatedJson};
+use crate::{AppError, AppResult, AuthToken, Config, Credential, Role};
+
+const KEY_PREFIX: &str = "sk_";
+const SANDBOX_KEY_PREFIX: &str = "sk_test_";
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow, ToSchema)]
+pub struct ApiKey {
+    id: String,
+    merchant: String,
+    name: String,
+    #[serde(skip_serializing)]
+    #[allow(dead_code)]
+    key_hash: String,
+    prefix: String, // First characters of the key, to tell keys apart
+    tenant: Option<String>, // None for the default tenant
+    created_at: i64,
+    rotated_at: Option<i64>,
+    last_used_at: Option<i64>,
+    revoked: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct ApiKeyRequest {
+    #[validate(length(min = 1, max = 64, message = "must be 1 to 64 characters"))]
+    name: String,
+    #[validate(custom = "validate_pubkey")]
+    merchant: Option<String>, // Defaults to the treasury wallet
+    tenant: Option<String>, // Defaults to the default tenant; sandbox tenants get `sk_test_` keys
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct ApiKeySecretResponse {
+    #[serde(flatten)]
+    api_key: ApiKey,
+    key: String, // Only returned at creation and rotation
+}
+
+// API Key Service
+#[derive(Clone)]
+pub struct ApiKeyService {
+    pool: PgPool,
+    default_merchant: Pubkey,
+    tenants: HashMap<String, bool>, // Configured tenant ids and whether each is a sandbox
+}
+
+impl ApiKeyService {
+    pub fn new(config: &Config, pool: PgPool) -> Self {
+        Self {
+            pool,
+            default_merchant: config.treasury,
+            tenants: config.tenants.iter().map(|tenant| (tenant.id.clone(), tenant.sandbox)).collect(),
+        }
+    }
+
+    pub async fn create(&self, caller: &str, req: ApiKeyRequest) -> AppResult<ApiKeySecretResponse> {
+        let merchant = match req.merchant {
+            Some(merchant) => Pubkey::from_str(&merchant)
+                .map_err(|e| AppError::BadRequest(format!("Invalid merchant public key: {}", e)))?,
+            None => self.default_merchant,
+        };
+        if req.name.trim().is_empty() {
+            return Err(AppError::BadRequest("API key name is required".to_string()));
+        }
+        let tenant = req.tenant.filter(|tenant| tenant != DEFAULT_TENANT);
+        if let Some(tenant) = &tenant {
+            if !self.tenants.contains_key(tenant) {
+                return Err(AppError::BadRequest(format!("Tenant {} is not configured", tenant)));
+            }
+        }
+
+        let id = random_hex(8);
+        let key = generate_key(&id, self.is_sandbox(tenant.as_deref()));
+        let api_key = sqlx::query_as::<_, ApiKey>(
+            "INSERT INTO api_keys (id, merchant, name, key_hash, prefix, tenant, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             RETURNING *",
+        )
+        .bind(&id)
+        .bind(merchant.to_string())
+        .bind(req.name.trim())
+        .bind(hash_key(&key))
+        .bind(key_prefix(&key))
+        .bind(&tenant)
+        .bind(now())
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to create API key: {}", e)))?;
+
+        tracing::info!(
+            "API key {} created for merchant {} on tenant {} by {}",
+            id,
+            merchant,
+            tenant.as_deref().unwrap_or(DEFAULT_TENANT),
+            caller
+        );
+        Ok(ApiKeySecretResponse { api_key, key })
+    }
+
+    pub async fn list(&self) -> AppResult<Vec<ApiKey>> {
+        sqlx::query_as::<_, ApiKey>("SELECT * FROM api_keys ORDER BY created_at DESC")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to list API keys: {}", e)))
+    }
+
+    /// Replaces the secret of a key in place; the old secret stops working immediately.
+    pub async fn rotate(&self, caller: &str, id: &str) -> AppResult<ApiKeySecretResponse> {
+        let tenant: Option<String> = sqlx::query_scalar("SELECT tenant FROM api_keys WHERE id = $1 AND NOT revoked")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to rotate API key: {}", e)))?
+            .ok_or_else(|| AppError::NotFound(format!("API key {} not found", id)))?;
+        let key = generate_key(id, self.is_sandbox(tenant.as_deref()));
+        let api_key = sqlx::query_as::<_, ApiKey>(
+            "UPDATE api_keys SET key_hash = $2, prefix = $3, rotated_at = $4
+             WHERE id = $1 AND NOT revoked
+             RETURNING *",
+        )
+        .bind(id)
+        .bind(hash_key(&key))
+        .bind(key_prefix(&key))
+        .bind(now())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to rotate API key: {}", e)))?
+        .ok_or_else(|| AppError::NotFound(format!("API key {} not found", id)))?;
+
+        tracing::info!("API key {} rotated by {}", id, caller);
+        Ok(ApiKeySecretResponse { api_key, key })
+    }
+
+    pub async fn revoke(&self, caller: &str, id: &str) -> AppResult<()> {
+        let revoked = sqlx::query("UPDATE api_keys SET revoked = TRUE WHERE id = $1 AND NOT revoked")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to revoke API key: {}", e)))?
+            .rows_affected();
+        if revoked == 0 {
+            return Err(AppError::NotFound(format!("API key {} not found", id)));
+        }
+        tracing::info!("API key {} revoked by {}", id, caller);
+        Ok(())
+    }
+
+    fn is_sandbox(&self, tenant: Option<&str>) -> bool {
+        tenant.and_then(|tenant| self.tenants.get(tenant)).copied().unwrap_or(false)
+    }
+
+    /// Resolves a presented key to the merchant and tenant it acts for.
+    pub async fn verify(&self, key: &str) -> AppResult<AuthToken> {
+        let api_key = sqlx::query_as::<_, ApiKey>(
+            "UPDATE api_keys SET last_used_at = $2
+             WHERE key_hash = $1 AND NOT revoked
+             RETURNING *",
+        )
+        .bind(hash_key(key))
+        .bind(now())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to verify API key: {}", e)))?
+        .ok_or_else(|| AppError::Auth("Invalid API key".to_string()))?;
+
+        // Like wallet sessions, keys scoped to another tenant are never merchants: merchant
+        // routes serve indexed data, which only covers the default tenant, so a sandbox key
+        // cannot read or change live data
+        let roles = if api_key.tenant.is_none() { vec![Role::Merchant] } else { Vec::new() };
+        Ok(AuthToken {
+            public_key: api_key.merchant,
+            credential: Credential::ApiKey { id: api_key.id },
+            roles,
+            tenant: api_key.tenant,
+        })
+    }
+}
+
+// Keys look like sk_<id>_<secret>, or sk_test_<id>_<secret> for sandbox tenants; only their
+// SHA-256 is stored
+fn generate_key(id: &str, sandbox: bool) -> String {
+    let secret: Vec<u8> = (0..32).map(|_| rand::random::<u8>()).collect();
+    let prefix = if sandbox { SANDBOX_KEY_PREFIX } else { KEY_PREFIX };
+    format!("{}{}_{}", prefix, id, bs58::encode(secret).into_string())
+}
+
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+fn key_prefix(key: &str) -> String {
+    let prefix = if key.starts_with(SANDBOX_KEY_PREFIX) { SANDBOX_KEY_PREFIX } else { KEY_PREFIX };
+    key.chars().take(prefix.len() + 16 + 5).collect()
+}
+
+fn random_hex(len: usize) -> String {
+    let bytes: Vec<u8> = (0..len).map(|_| rand::random::<u8>()).collect();
+    hex::encode(bytes)
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+// Controllers
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/api-keys",
+    tag = "admin",
+    request_body = ApiKeyRequest,
+    responses(
+        (status = 201, description = "API key created; the key is only returned here", body = ApiKeySecretResponse),
+        (status = 403, description = "Admin role required", body = ErrorResponse),
+        (status = 422, description = "Invalid request", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/api-keys")]
+pub async fn create_api_key(
+    req: actix_web::HttpRequest,
+    api_key_service: web::Data<ApiKeyService>,
+    key_req: ValidatedJson<ApiKeyRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let api_key = api_key_service.create(&auth_token.public_key, key_req.into_inner()).await?;
+    Ok(HttpResponse::Created().json(api_key))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/api-keys",
+    tag = "admin",
+    responses(
+        (status = 200, description = "All API keys, without secrets", body = [ApiKey]),
+        (status = 403, description = "Admin role required", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/api-keys")]
+pub async fn list_api_keys(
+    api_key_service: web::Data<ApiKeyService>,
+) -> AppResult<HttpResponse> {
+    let api_keys = api_key_service.list().await?;
+    Ok(HttpResponse::Ok().json(api_keys))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/api-keys/{id}/rotate",
+    tag = "admin",
+    params(("id" = String, Path, description = "API key id")),
+    responses(
+        (status = 200, description = "New key; the old one stops working immediately", body = ApiKeySecretResponse),
+        (status = 404, description = "API key not found", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/api-keys/{id}/rotate")]
+pub async fn rotate_api_key(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    api_key_service: web::Data<ApiKeyService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let api_key = api_key_service.rotate(&auth_token.public_key, &path.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(api_key))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/admin/api-keys/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "API key id")),
+    responses(
+        (status = 204, description = "API key revoked"),
+        (status = 404, description = "API key not found", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[delete("/api-keys/{id}")]
+pub async fn revoke_api_key(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    api_key_service: web::Data<ApiKeyService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    api_key_service.revoke(&auth_token.public_key, &path.into_inner()).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,102 @@
+use actix_web::{get, web, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use utoipa::IntoParams;
+use validator::Validate;
+use crate::db::{self, NewAuditEntry};
+use crate::validation::ValidatedQuery;
+use crate::{telemetry, AppResult, AuthToken, Credential};
+
+const DEFAULT_AUDIT_LIMIT: i64 = 100;
+
+/// Transactions a request sent, picked up by the `AuditLog` middleware once it responds.
+#[derive(Debug, Clone)]
+pub struct AuditSignatures(pub Vec<String>);
+
+/// Notes transactions sent while handling `req` for its audit entry.
+pub fn attach_signatures<'a>(req: &HttpRequest, signatures: impl IntoIterator<Item = &'a String>) {
+    let mut extensions = req.extensions_mut();
+    if let Some(recorded) = extensions.get_mut::<AuditSignatures>() {
+        recorded.0.extend(signatures.into_iter().cloned());
+        return;
+    }
+    extensions.insert(AuditSignatures(signatures.into_iter().cloned().collect()));
+}
+
+/// The entry of a mutating request `auth_token` made, with `status` and `signatures` filled
+/// in by `record`'s caller once it is answered.
+pub fn new_entry(
+    auth_token: AuthToken,
+    tenant: Option<String>,
+    method: &str,
+    route: String,
+    path: String,
+    ip: Option<String>,
+) -> NewAuditEntry {
+    NewAuditEntry {
+        actor: auth_token.public_key,
+        credential: match auth_token.credential {
+            Credential::Jwt { jti, .. } => format!("jwt:{}", jti),
+            Credential::ApiKey { id } => format!("api_key:{}", id),
+        },
+        tenant: tenant.or(auth_token.tenant),
+        method: method.to_string(),
+        route,
+        path,
+        status: 0,
+        signatures: Vec::new(),
+        ip,
+        request_id: telemetry::request_id(),
+    }
+}
+
+/// Writes `entry`. The action already happened, so a failed write is logged rather than returned.
+pub async fn record(pool: &PgPool, entry: &NewAuditEntry) {
+    if let Err(e) = db::insert_audit_entry(pool, entry).await {
+        tracing::error!("Failed to audit {} {}: {}", entry.method, entry.path, e);
+    }
+}
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct AuditQuery {
+    actor: Option<String>,     // Wallet or merchant public key
+    signature: Option<String>, // Entries whose request sent this transaction
+    since: Option<i64>,        // Unix time, inclusive
+    until: Option<i64>,        // Unix time, exclusive
+    #[validate(range(min = 1, max = 500, message = "must be between 1 and 500"))]
+    limit: Option<i64>,
+    #[validate(range(min = 0, message = "must not be negative"))]
+    offset: Option<i64>,
+}
+
+// Controllers
+/// Recorded mutating requests, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/audit",
+    tag = "admin",
+    params(AuditQuery),
+    responses(
+        (status = 200, description = "Audit entries", body = [AuditEntry]),
+        (status = 422, description = "Invalid query", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/audit")]
+pub async fn list_audit_entries(
+    query: ValidatedQuery<AuditQuery>,
+    pool: web::Data<PgPool>,
+) -> AppResult<HttpResponse> {
+    let entries = db::list_audit_entries(
+        &pool,
+        query.actor.as_deref(),
+        query.signature.as_deref(),
+        query.since,
+        query.until,
+        query.limit.unwrap_or(DEFAULT_AUDIT_LIMIT),
+        query.offset.unwrap_or(0),
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(entries))
+}
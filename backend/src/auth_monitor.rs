@@ -0,0 +1,158 @@
+use actix_web::HttpRequest;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::metrics;
+use crate::webhooks::sign_payload;
+use crate::{unix_now, AppResult, Config};
+
+const MAX_TRACKED_KEYS: usize = 100_000;
+const ALERT_EVENT: &str = "auth.anomaly_detected";
+
+// Models
+#[derive(Debug, Serialize, Clone)]
+struct AuthAnomalyAlert {
+    event: &'static str,
+    key_type: &'static str, // wallet | ip
+    key: String,
+    method: &'static str, // The sign-in method of the failure that crossed the threshold
+    failures: usize,
+    window_secs: i64,
+    detected_at: i64,
+}
+
+#[derive(Debug, Default)]
+struct FailureWindow {
+    failures: VecDeque<i64>, // Unix times of failed attempts within the window
+    alerted_at: Option<i64>,
+}
+
+// Auth Monitor
+/// Counts sign-in attempts on the `auth_attempts_total` metric and flags bursts of failures:
+/// once a wallet or client IP fails `AUTH_ANOMALY_THRESHOLD` times within
+/// `AUTH_ANOMALY_WINDOW_SECS`, it is logged, counted on `auth_anomalies_total` and posted to
+/// the alert webhook, at most once per window. Nothing is blocked; rate limits do that.
+/// Failures are counted per replica.
+#[derive(Clone)]
+pub struct AuthMonitor {
+    windows: Arc<Mutex<HashMap<(&'static str, String), FailureWindow>>>,
+    threshold: usize, // 0 disables detection, attempts are still counted
+    window_secs: i64,
+    trust_forwarded: bool,
+    http_client: reqwest::Client,
+    webhook_url: Option<String>,
+    webhook_secret: Option<String>,
+}
+
+impl AuthMonitor {
+    pub fn new(config: &Config) -> Self {
+        let http_client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("Failed to build auth alert HTTP client");
+
+        Self {
+            windows: Arc::new(Mutex::new(HashMap::new())),
+            threshold: config.auth_anomaly_threshold as usize,
+            window_secs: config.auth_anomaly_window_secs.max(1) as i64,
+            trust_forwarded: config.rate_limit_trust_forwarded,
+            http_client,
+            webhook_url: config.auth_alert_webhook_url.clone(),
+            webhook_secret: config.auth_alert_webhook_secret.clone(),
+        }
+    }
+
+    /// Only trusts `Forwarded`/`X-Forwarded-For` when the rate limiter does.
+    pub fn client_ip(&self, req: &HttpRequest) -> Option<String> {
+        if self.trust_forwarded {
+            req.connection_info().realip_remote_addr().map(str::to_string)
+        } else {
+            req.peer_addr().map(|addr| addr.ip().to_string())
+        }
+    }
+
+    /// Records the outcome of a sign-in by `method`, attributing a failure to the wallet it
+    /// named, if any, and to the client IP.
+    pub fn record<T>(&self, method: &'static str, wallet: Option<&str>, ip: Option<&str>, result: &AppResult<T>) {
+        let Err(e) = result else {
+            metrics::record_auth_attempt(method, "success");
+            return;
+        };
+        metrics::record_auth_attempt(method, "failure");
+        tracing::debug!("Failed {} sign-in for {:?} from {:?}: {}", method, wallet, ip, e);
+        if self.threshold == 0 {
+            return;
+        }
+        let now = unix_now();
+        let keys = wallet.map(|w| ("wallet", w)).into_iter().chain(ip.map(|ip| ("ip", ip)));
+        let mut flagged = Vec::new();
+        {
+            let mut windows = self.windows.lock().unwrap();
+            if windows.len() >= MAX_TRACKED_KEYS {
+                let cutoff = now - self.window_secs;
+                windows.retain(|_, window| window.failures.back().is_some_and(|at| *at > cutoff));
+            }
+            for (key_type, key) in keys {
+                let window = windows.entry((key_type, key.to_string())).or_default();
+                window.failures.push_back(now);
+                while window.failures.front().is_some_and(|at| *at <= now - self.window_secs) {
+                    window.failures.pop_front();
+                }
+                let quiet = window.alerted_at.is_none_or(|at| at <= now - self.window_secs);
+                if window.failures.len() >= self.threshold && quiet {
+                    window.alerted_at = Some(now);
+                    flagged.push(AuthAnomalyAlert {
+                        event: ALERT_EVENT,
+                        key_type,
+                        key: key.to_string(),
+                        method,
+                        failures: window.failures.len(),
+                        window_secs: self.window_secs,
+                        detected_at: now,
+                    });
+                }
+            }
+        }
+        for alert in flagged {
+            tracing::warn!(
+                "Sign-in anomaly: {} failed sign-ins from {} {} within {}s",
+                alert.failures,
+                alert.key_type,
+                alert.key,
+                alert.window_secs
+            );
+            metrics::record_auth_anomaly(alert.key_type);
+            if let Some(url) = self.webhook_url.clone() {
+                let monitor = self.clone();
+                // Sent in the background so the failing request is not held up
+                tokio::spawn(async move {
+                    if let Err(e) = monitor.post_alert(&url, &alert).await {
+                        tracing::warn!("Failed to send auth anomaly webhook: {}", e);
+                    }
+                });
+            }
+        }
+    }
+
+    /// Signed like subscription webhooks when `AUTH_ALERT_WEBHOOK_SECRET` is set.
+    async fn post_alert(&self, url: &str, alert: &AuthAnomalyAlert) -> Result<(), String> {
+        let body = serde_json::to_string(alert).map_err(|e| format!("Failed to serialize alert: {}", e))?;
+        let mut request = self.http_client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .header("X-Webhook-Event", ALERT_EVENT);
+        if let Some(secret) = &self.webhook_secret {
+            let timestamp = unix_now();
+            request = request.header(
+                "X-Webhook-Signature",
+                format!("t={},v1={}", timestamp, sign_payload(secret, timestamp, &body)),
+            );
+        }
+        let response = request.body(body).send().await.map_err(|e| format!("Request failed: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!("Endpoint returned {}", response.status()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+use actix_web::{post, web, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::{Instruction, InstructionError};
+use solana_sdk::message::Message;
+use solana_sdk::packet::PACKET_DATA_SIZE;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::transaction::{Transaction, TransactionError};
+use std::collections::HashSet;
+use std::str::FromStr;
+use utoipa::ToSchema;
+use validator::Validate;
+use crate::audit;
+use crate::cache::CacheService;
+use crate::cluster::SolanaClusters;
+use crate::idempotency::{self, IdempotencyService};
+use crate::indexer::IndexerService;
+use crate::metrics;
+use crate::notifications::NotificationService;
+use crate::simulation::{self, SimulatedAction};
+use crate::validation::{validate, ValidatedJson};
+use crate::webhooks::{WebhookEventType, WebhookService};
+use crate::{
+    index_submission, subscription_event, AppError, AppResult, AuthToken, SolanaService,
+    SubscriptionRequest,
+};
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct BatchAction {
+    action: SimulatedAction,
+    plan_id: u64,
+    duration: Option<u64>, // Required for create
+    amount: Option<u64>,   // Required for create
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct BatchRequest {
+    #[validate(length(min = 1, max = 50, message = "must hold between 1 and 50 actions"))]
+    actions: Vec<BatchAction>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchItemStatus {
+    Confirmed,
+    Rejected, // Never sent: invalid, or would fail on chain
+    Failed,   // Its transaction was sent and failed
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct BatchItemResult {
+    index: usize, // Position in the request's `actions`
+    action: SimulatedAction,
+    plan_id: u64,
+    status: BatchItemStatus,
+    signature: Option<String>, // Shared by every action packed into the same transaction
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct BatchResponse {
+    results: Vec<BatchItemResult>,
+    transactions: Vec<String>, // Signatures of the transactions sent
+}
+
+struct Prepared {
+    index: usize,
+    instruction: Instruction,
+}
+
+impl BatchItemResult {
+    fn new(index: usize, action: &BatchAction) -> Self {
+        Self {
+            index,
+            action: action.action,
+            plan_id: action.plan_id,
+            status: BatchItemStatus::Rejected,
+            signature: None,
+            error: None,
+        }
+    }
+}
+
+fn instruction_name(action: SimulatedAction) -> &'static str {
+    match action {
+        SimulatedAction::Create => "create_subscription",
+        SimulatedAction::Renew => "renew_subscription",
+    }
+}
+
+fn error_message(error: AppError) -> String {
+    match error {
+        AppError::Validation(errors) => errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "),
+        AppError::BadRequest(message) => message,
+        other => other.to_string(),
+    }
+}
+
+/// Whether `instructions` still fit in one signed transaction from `owner`.
+fn fits(owner: &Pubkey, instructions: &[Instruction]) -> bool {
+    let message = Message::new_with_blockhash(instructions, Some(owner), &Hash::default());
+    bincode::serialized_size(&Transaction::new_unsigned(message)).is_ok_and(|size| size as usize <= PACKET_DATA_SIZE)
+}
+
+// Batch
+/// Checks every action, packs the valid ones into as few transactions as the packet size
+/// allows, and sends them in order. Each transaction is simulated first: an action the
+/// program would reject is dropped from it and reported, so it cannot sink the others.
+pub async fn run_batch(
+    solana_service: &SolanaService,
+    owner: &str,
+    actions: &[BatchAction],
+) -> AppResult<(Vec<BatchItemResult>, Vec<String>)> {
+    let owner_pubkey = Pubkey::from_str(owner)
+        .map_err(|e| AppError::BadRequest(format!("Invalid public key: {}", e)))?;
+    // Held across every group, since each is checked against accounts the previous one changed
+    let _guard = solana_service.lock_wallet(owner).await?;
+    let mut results: Vec<BatchItemResult> =
+        actions.iter().enumerate().map(|(index, action)| BatchItemResult::new(index, action)).collect();
+
+    // Per-action checks that need no RPC
+    let mut seen = HashSet::new();
+    let mut candidates = Vec::new();
+    for (index, action) in actions.iter().enumerate() {
+        if !seen.insert(action.plan_id) {
+            results[index].error = Some("plan_id appears more than once in the batch".to_string());
+            continue;
+        }
+        let instruction = match action.action {
+            SimulatedAction::Create => {
+                let (Some(duration), Some(amount)) = (action.duration, action.amount) else {
+                    results[index].error = Some("duration and amount are required for create".to_string());
+                    continue;
+                };
+                let request = SubscriptionRequest { plan_id: action.plan_id, duration, amount };
+                if let Err(e) = validate(&request).and_then(|_| solana_service.check_terms(&request)) {
+                    results[index].error = Some(error_message(e));
+                    continue;
+                }
+                solana_service.create_instruction(&owner_pubkey, &request)
+            }
+            SimulatedAction::Renew => {
+                if action.plan_id > i64::MAX as u64 {
+                    results[index].error = Some("plan_id must fit in a signed 64-bit integer".to_string());
+                    continue;
+                }
+                solana_service.renew_instruction(&owner_pubkey, action.plan_id)
+            }
+        };
+        candidates.push(Prepared { index, instruction });
+    }
+
+    // Creates need a fresh account and renewals an existing one
+    let pdas: Vec<Pubkey> = candidates
+        .iter()
+        .map(|c| solana_service.subscription_pda(&owner_pubkey, actions[c.index].plan_id))
+        .collect();
+    let accounts = metrics::observe_rpc("getMultipleAccounts", solana_service.rpc.client().get_multiple_accounts(&pdas))
+        .await
+        .map_err(|e| AppError::rpc("Failed to fetch subscription accounts", e))?;
+    let mut prepared = Vec::new();
+    for (candidate, account) in candidates.into_iter().zip(accounts) {
+        match (actions[candidate.index].action, account.is_some()) {
+            (SimulatedAction::Create, true) => {
+                results[candidate.index].error = Some("Subscription already exists".to_string());
+            }
+            (SimulatedAction::Renew, false) => {
+                results[candidate.index].error = Some("Subscription does not exist".to_string());
+            }
+            _ => prepared.push(candidate),
+        }
+    }
+
+    // Greedy packing in request order
+    let mut groups: Vec<Vec<Prepared>> = Vec::new();
+    for item in prepared {
+        let extends_last = groups.last().is_some_and(|group| {
+            let instructions: Vec<Instruction> =
+                group.iter().chain(std::iter::once(&item)).map(|p| p.instruction.clone()).collect();
+            fits(&owner_pubkey, &instructions)
+        });
+        match groups.last_mut() {
+            Some(group) if extends_last => group.push(item),
+            _ => groups.push(vec![item]),
+        }
+    }
+
+    let mut signatures = Vec::new();
+    for mut group in groups {
+        // Drop actions the program would reject until the rest simulate cleanly
+        while !group.is_empty() {
+            let instructions: Vec<Instruction> = group.iter().map(|p| p.instruction.clone()).collect();
+            let (_, simulation) = solana_service.simulate(&owner_pubkey, &instructions).await?;
+            match &simulation.err {
+                None => break,
+                Some(err @ TransactionError::InstructionError(position, error)) if (*position as usize) < group.len() => {
+                    let rejected = group.remove(*position as usize);
+                    results[rejected.index].error = Some(match error {
+                        InstructionError::Custom(_) => simulation::describe_error(err),
+                        _ => error.to_string(),
+                    });
+                }
+                Some(error) => {
+                    // Not attributable to one action, such as an unfunded fee payer
+                    for item in group.drain(..) {
+                        results[item.index].error = Some(simulation::describe_error(error));
+                    }
+                }
+            }
+        }
+        if group.is_empty() {
+            continue;
+        }
+
+        let instructions: Vec<Instruction> = group.iter().map(|p| p.instruction.clone()).collect();
+        let items: Vec<String> = group
+            .iter()
+            .map(|p| format!("{}:{}", instruction_name(actions[p.index].action), actions[p.index].plan_id))
+            .collect();
+        let first = &actions[group[0].index];
+        let outcome = solana_service
+            .submit_transaction("batch", &owner_pubkey, first.plan_id, items, &instructions)
+            .await;
+        for item in &group {
+            let result = &mut results[item.index];
+            match &outcome {
+                Ok(signature) => {
+                    result.status = BatchItemStatus::Confirmed;
+                    result.signature = Some(signature.clone());
+                }
+                Err(e) => {
+                    result.status = BatchItemStatus::Failed;
+                    result.error = Some(e.to_string());
+                }
+            }
+        }
+        if let Ok(signature) = outcome {
+            signatures.push(signature);
+        }
+    }
+    Ok((results, signatures))
+}
+
+// Controllers
+/// Creates and renews several subscriptions of the authenticated wallet in as few
+/// transactions as possible, with a result per action.
+#[utoipa::path(
+    post,
+    path = "/api/v1/subscriptions/batch",
+    tag = "subscriptions",
+    request_body = BatchRequest,
+    params(("Idempotency-Key" = Option<String>, Header, description = "Replays the first result for retries with the same key")),
+    responses(
+        (status = 200, description = "Per-action results, including rejected and failed actions", body = BatchResponse),
+        (status = 409, description = "Request with this Idempotency-Key, or another transaction of the wallet, still in progress", body = ErrorResponse),
+        (status = 422, description = "Invalid request", body = ErrorResponse),
+        (status = 502, description = "RPC node unavailable", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/subscriptions/batch")]
+#[allow(clippy::too_many_arguments)]
+pub async fn batch_subscriptions(
+    req: HttpRequest,
+    clusters: web::Data<SolanaClusters>,
+    webhook_service: web::Data<WebhookService>,
+    indexer: web::Data<IndexerService>,
+    cache: web::Data<CacheService>,
+    idempotency: web::Data<IdempotencyService>,
+    notifications: web::Data<NotificationService>,
+    body: ValidatedJson<BatchRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let solana_service = clusters.select(&req)?;
+    let fingerprint_body = serde_json::to_string(&*body)
+        .map_err(|e| AppError::InternalServerError(format!("Failed to serialize request: {}", e)))?;
+    let idempotency_key = IdempotencyService::key(&req)?;
+    if let Some(key) = &idempotency_key {
+        let fingerprint = idempotency::fingerprint(&[
+            solana_service.cluster.as_str(),
+            solana_service.tenant(),
+            "batch",
+            &fingerprint_body,
+        ]);
+        if let Some(replay) = idempotency.begin(&auth_token.public_key, key, &fingerprint).await? {
+            return Ok(replay);
+        }
+    }
+    let result = run_batch(solana_service, &auth_token.public_key, &body.actions)
+        .await
+        .map(|(results, transactions)| BatchResponse { results, transactions });
+    if let Some(key) = &idempotency_key {
+        idempotency.finish(&auth_token.public_key, key, &result).await;
+    }
+    let response = result?;
+    audit::attach_signatures(&req, &response.transactions);
+
+    if solana_service.is_primary() {
+        for signature in &response.transactions {
+            index_submission(&indexer, signature).await;
+        }
+        for item in response.results.iter().filter(|r| r.status == BatchItemStatus::Confirmed) {
+            let Some(signature) = &item.signature else { continue };
+            let pda = solana_service.subscription_address(&auth_token.public_key, item.plan_id)?;
+            cache.invalidate_subscription(&pda).await;
+            let event_type = match item.action {
+                SimulatedAction::Create => WebhookEventType::SubscriptionCreated,
+                SimulatedAction::Renew => WebhookEventType::SubscriptionRenewed,
+            };
+            webhook_service
+                .dispatch(event_type, subscription_event(solana_service, &auth_token.public_key, item.plan_id, signature))
+                .await;
+            notifications.notify_payment(&pda.to_string(), signature);
+        }
+    }
+    Ok(HttpResponse::Ok().json(response))
+}
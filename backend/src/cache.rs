@@ -0,0 +1,246 @@
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use solana_sdk::pubkey::Pubkey;
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::metrics;
+use crate::{AppResult, Config, SubscriptionResponse};
+
+// Cache Service
+/// Read-through cache for decoded subscriptions. Redis is optional: without `REDIS_URL`
+/// every call is a no-op, and Redis errors are logged rather than failing the request.
+#[derive(Clone)]
+pub struct CacheService {
+    conn: Option<ConnectionManager>,
+    subscription_ttl_secs: u64,
+}
+
+impl CacheService {
+    pub async fn new(config: &Config) -> Self {
+        let conn = match &config.redis_url {
+            Some(url) => match connect(url).await {
+                Ok(conn) => Some(conn),
+                Err(e) => {
+                    tracing::warn!("Redis unavailable, subscription cache disabled: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        Self {
+            conn,
+            subscription_ttl_secs: config.cache_ttl_secs,
+        }
+    }
+
+    pub fn connection(&self) -> Option<ConnectionManager> {
+        self.conn.clone()
+    }
+
+    fn subscription_key(pda: &Pubkey) -> String {
+        format!("subscription:{}", pda)
+    }
+
+    pub async fn get_subscription(&self, pda: &Pubkey) -> Option<SubscriptionResponse> {
+        let mut conn = self.conn.clone()?;
+        let cached: Option<String> = match conn.get(Self::subscription_key(pda)).await {
+            Ok(cached) => cached,
+            Err(e) => {
+                tracing::warn!("Cache read failed for {}: {}", pda, e);
+                return None;
+            }
+        };
+        cached.and_then(|json| serde_json::from_str(&json).ok())
+    }
+
+    pub async fn put_subscription(&self, pda: &Pubkey, sub: &SubscriptionResponse) {
+        let Some(mut conn) = self.conn.clone() else { return };
+        let Ok(json) = serde_json::to_string(sub) else { return };
+
+        let result: redis::RedisResult<()> = redis::cmd("SET")
+            .arg(Self::subscription_key(pda))
+            .arg(json)
+            .arg("EX")
+            .arg(self.subscription_ttl_secs)
+            .query_async(&mut conn)
+            .await;
+        if let Err(e) = result {
+            tracing::warn!("Cache write failed for {}: {}", pda, e);
+        }
+    }
+
+    pub async fn invalidate_subscription(&self, pda: &Pubkey) {
+        let Some(mut conn) = self.conn.clone() else { return };
+        let result: redis::RedisResult<()> = conn.del(Self::subscription_key(pda)).await;
+        if let Err(e) = result {
+            tracing::warn!("Cache invalidation failed for {}: {}", pda, e);
+        }
+    }
+}
+
+// Response Cache
+/// Groups of cached responses that writers invalidate together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheNamespace {
+    Plans,     // Merchant plan catalog reads
+    Catalog,   // Public plan catalog, with indexed subscriber counts
+    Analytics, // Merchant metrics over the index
+}
+
+impl CacheNamespace {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CacheNamespace::Plans => "plans",
+            CacheNamespace::Catalog => "catalog",
+            CacheNamespace::Analytics => "analytics",
+        }
+    }
+}
+
+struct CachedResponse {
+    value: Arc<dyn Any + Send + Sync>,
+    fetched_at: Instant,
+    expired: bool,    // Served as stale whatever its age
+    refreshing: bool, // A background fetch is replacing it
+}
+
+#[derive(Default)]
+struct ResponseCacheState {
+    entries: HashMap<(CacheNamespace, String), CachedResponse>,
+    generations: HashMap<CacheNamespace, u64>, // Bumped by every invalidation of the namespace
+}
+
+/// In-memory stale-while-revalidate cache for read-heavy endpoints. An entry younger than
+/// `RESPONSE_CACHE_FRESH_SECS` is served as is. Up to `RESPONSE_CACHE_STALE_SECS` later it is
+/// still served straight away while one background fetch replaces it; older entries are
+/// fetched within the request. Writers either invalidate a whole namespace, so the next read
+/// fetches, or expire it, so the next read still answers at once but refreshes: the indexer
+/// expires analytics whenever the index changes. The cache is per replica, so a write made on
+/// another replica shows up once its entries go stale.
+#[derive(Clone)]
+pub struct ResponseCache {
+    state: Arc<Mutex<ResponseCacheState>>,
+    fresh: Duration,
+    stale: Duration,
+    capacity: usize,
+}
+
+impl ResponseCache {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ResponseCacheState::default())),
+            fresh: Duration::from_secs(config.response_cache_fresh_secs),
+            stale: Duration::from_secs(config.response_cache_stale_secs),
+            capacity: config.response_cache_capacity,
+        }
+    }
+
+    /// The cached response under `key`, or the result of `fetch`.
+    pub async fn get<T, F, Fut>(&self, namespace: CacheNamespace, key: String, fetch: F) -> AppResult<T>
+    where
+        T: Clone + Send + Sync + 'static,
+        F: Fn() -> Fut + 'static,
+        Fut: Future<Output = AppResult<T>> + 'static,
+    {
+        if self.fresh.is_zero() {
+            return fetch().await;
+        }
+        let generation = {
+            let mut state = self.state.lock().unwrap();
+            let generation = state.generations.get(&namespace).copied().unwrap_or(0);
+            let entry = state.entries.get_mut(&(namespace, key.clone()));
+            if let Some(entry) = entry {
+                let age = entry.fetched_at.elapsed();
+                if let Some(value) = entry.value.downcast_ref::<T>().filter(|_| age < self.fresh + self.stale) {
+                    let value = value.clone();
+                    if age < self.fresh && !entry.expired {
+                        metrics::record_response_cache(namespace.as_str(), "fresh");
+                        return Ok(value);
+                    }
+                    if !entry.refreshing {
+                        entry.refreshing = true;
+                        let cache = self.clone();
+                        actix_web::rt::spawn(async move {
+                            let result = fetch().await;
+                            cache.refreshed(namespace, key, generation, result);
+                        });
+                    }
+                    metrics::record_response_cache(namespace.as_str(), "stale");
+                    return Ok(value);
+                }
+            }
+            generation
+        };
+        metrics::record_response_cache(namespace.as_str(), "miss");
+        let value = fetch().await?;
+        self.store(namespace, key, generation, value.clone());
+        Ok(value)
+    }
+
+    /// Drops every entry of `namespace`, including those being fetched right now.
+    pub fn invalidate(&self, namespace: CacheNamespace) {
+        let mut state = self.state.lock().unwrap();
+        *state.generations.entry(namespace).or_insert(0) += 1;
+        state.entries.retain(|(entry_namespace, _), _| *entry_namespace != namespace);
+    }
+
+    /// Marks every entry of `namespace` stale, so each is refreshed on its next read. Refreshes
+    /// already running are discarded, since they may have read from before the change.
+    pub fn expire(&self, namespace: CacheNamespace) {
+        let mut state = self.state.lock().unwrap();
+        *state.generations.entry(namespace).or_insert(0) += 1;
+        for ((entry_namespace, _), entry) in state.entries.iter_mut() {
+            if *entry_namespace == namespace {
+                entry.expired = true;
+                entry.refreshing = false;
+            }
+        }
+    }
+
+    fn refreshed<T: Send + Sync + 'static>(&self, namespace: CacheNamespace, key: String, generation: u64, result: AppResult<T>) {
+        match result {
+            Ok(value) => self.store(namespace, key, generation, value),
+            Err(e) => {
+                tracing::warn!("Background refresh of cached {} response failed: {}", namespace.as_str(), e);
+                // Keep serving the stale entry; the next request past `fresh` tries again
+                if let Some(entry) = self.state.lock().unwrap().entries.get_mut(&(namespace, key)) {
+                    entry.refreshing = false;
+                }
+            }
+        }
+    }
+
+    fn store<T: Send + Sync + 'static>(&self, namespace: CacheNamespace, key: String, generation: u64, value: T) {
+        let mut state = self.state.lock().unwrap();
+        // Fetched before an invalidation, so possibly already outdated
+        if state.generations.get(&namespace).copied().unwrap_or(0) != generation {
+            return;
+        }
+        let key = (namespace, key);
+        if state.entries.len() >= self.capacity && !state.entries.contains_key(&key) {
+            let horizon = self.fresh + self.stale;
+            state.entries.retain(|_, entry| entry.fetched_at.elapsed() < horizon);
+            if state.entries.len() >= self.capacity {
+                let oldest = state.entries.iter().min_by_key(|(_, entry)| entry.fetched_at).map(|(oldest, _)| oldest.clone());
+                if let Some(oldest) = oldest {
+                    state.entries.remove(&oldest);
+                }
+            }
+        }
+        state.entries.insert(key, CachedResponse {
+            value: Arc::new(value),
+            fetched_at: Instant::now(),
+            expired: false,
+            refreshing: false,
+        });
+    }
+}
+
+async fn connect(url: &str) -> redis::RedisResult<ConnectionManager> {
+    let client = redis::Client::open(url)?;
+    ConnectionManager::new(client).await
+}
@@ -0,0 +1,251 @@
+use actix_web::{delete, get, post, web, HttpMessage, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::postgres::PgPool;
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+use crate::db;
+use crate::plans::PlanService;
+use crate::validation::ValidatedQuery;
+use crate::{AppError, AppResult, AuthToken};
+
+const TOKEN_PREFIX: &str = "cal_";
+const FEED_PATH: &str = "/api/calendar.ics";
+// Lines are folded after this many octets, per RFC 5545
+const MAX_LINE_OCTETS: usize = 75;
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct CalendarToken {
+    token: String, // Only returned here
+    url: String,   // Feed path with the token, to subscribe to from a calendar app
+}
+
+#[derive(Debug, Deserialize, Clone, IntoParams, Validate)]
+pub struct CalendarQuery {
+    #[validate(length(min = 1, max = 128, message = "must be 1 to 128 characters"))]
+    token: String,
+}
+
+// Calendar Service
+/// Per-wallet calendar feed tokens. Calendar apps cannot send a bearer token, so the feed is
+/// authenticated by a long-lived token in its URL instead; only its SHA-256 is stored, and
+/// issuing a new one revokes the old.
+#[derive(Clone)]
+pub struct CalendarService {
+    pool: PgPool,
+}
+
+impl CalendarService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn issue(&self, owner: &str) -> AppResult<CalendarToken> {
+        let secret: Vec<u8> = (0..32).map(|_| rand::random::<u8>()).collect();
+        let token = format!("{}{}", TOKEN_PREFIX, bs58::encode(secret).into_string());
+        sqlx::query(
+            "INSERT INTO calendar_feeds (owner, token_hash, created_at)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (owner) DO UPDATE
+             SET token_hash = EXCLUDED.token_hash, created_at = EXCLUDED.created_at, last_used_at = NULL",
+        )
+        .bind(owner)
+        .bind(hash_token(&token))
+        .bind(now())
+        .execute(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to issue calendar token: {}", e)))?;
+
+        Ok(CalendarToken { url: format!("{}?token={}", FEED_PATH, token), token })
+    }
+
+    pub async fn revoke(&self, owner: &str) -> AppResult<()> {
+        let revoked = sqlx::query("DELETE FROM calendar_feeds WHERE owner = $1")
+            .bind(owner)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to revoke calendar token: {}", e)))?
+            .rows_affected();
+        if revoked == 0 {
+            return Err(AppError::NotFound("No calendar token issued".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Resolves a feed token to the wallet it was issued to.
+    pub async fn verify(&self, token: &str) -> AppResult<String> {
+        sqlx::query_scalar(
+            "UPDATE calendar_feeds SET last_used_at = $2
+             WHERE token_hash = $1
+             RETURNING owner",
+        )
+        .bind(hash_token(token))
+        .bind(now())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to verify calendar token: {}", e)))?
+        .ok_or_else(|| AppError::Auth("Invalid calendar token".to_string()))
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+// iCalendar
+fn format_time(timestamp: i64) -> String {
+    DateTime::<Utc>::from_timestamp(timestamp, 0)
+        .map(|t| t.format("%Y%m%dT%H%M%SZ").to_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+        .replace('\r', "")
+}
+
+/// Appends a content line, folded so no line exceeds `MAX_LINE_OCTETS`.
+fn push_line(ics: &mut String, line: &str) {
+    let mut octets = 0;
+    for c in line.chars() {
+        if octets + c.len_utf8() > MAX_LINE_OCTETS {
+            ics.push_str("\r\n ");
+            octets = 1;
+        }
+        ics.push(c);
+        octets += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+/// One event per active subscription, at the end of its paid period, with a reminder a day
+/// before.
+fn render(subscriptions: &[(db::DueSubscriptionRow, String)], stamp: i64) -> String {
+    let mut ics = String::new();
+    for line in [
+        "BEGIN:VCALENDAR",
+        "VERSION:2.0",
+        "PRODID:-//Subscription Manager//Renewals//EN",
+        "CALSCALE:GREGORIAN",
+        "METHOD:PUBLISH",
+        "X-WR-CALNAME:Subscription renewals",
+    ] {
+        push_line(&mut ics, line);
+    }
+    for (due, plan) in subscriptions {
+        let subscription = &due.subscription;
+        let period_end = subscription.start_time + subscription.duration;
+        let summary = if due.auto_renew {
+            format!("{} renews", plan)
+        } else {
+            format!("{} expires", plan)
+        };
+        let description = if due.auto_renew {
+            format!("Your subscription will be renewed automatically.\nSubscription: {}", subscription.pda)
+        } else {
+            format!("Renew before then to keep access.\nSubscription: {}", subscription.pda)
+        };
+        push_line(&mut ics, "BEGIN:VEVENT");
+        push_line(&mut ics, &format!("UID:{}-{}@subscription-manager", subscription.pda, period_end));
+        push_line(&mut ics, &format!("DTSTAMP:{}", format_time(stamp)));
+        push_line(&mut ics, &format!("DTSTART:{}", format_time(period_end)));
+        push_line(&mut ics, &format!("DTEND:{}", format_time(period_end)));
+        push_line(&mut ics, &format!("SUMMARY:{}", escape_text(&summary)));
+        push_line(&mut ics, &format!("DESCRIPTION:{}", escape_text(&description)));
+        push_line(&mut ics, "BEGIN:VALARM");
+        push_line(&mut ics, "ACTION:DISPLAY");
+        push_line(&mut ics, "TRIGGER:-P1D");
+        push_line(&mut ics, &format!("DESCRIPTION:{}", escape_text(&summary)));
+        push_line(&mut ics, "END:VALARM");
+        push_line(&mut ics, "END:VEVENT");
+    }
+    push_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+// Controllers
+/// Issues the calendar feed token of the authenticated wallet, revoking any earlier one.
+#[utoipa::path(
+    post,
+    path = "/api/v1/calendar/token",
+    tag = "calendar",
+    responses((status = 201, description = "Feed token; only returned here", body = CalendarToken)),
+    security(("bearer_auth" = []))
+)]
+#[post("/calendar/token")]
+pub async fn create_calendar_token(
+    req: HttpRequest,
+    calendar: web::Data<CalendarService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let token = calendar.issue(&auth_token.public_key).await?;
+    Ok(HttpResponse::Created().json(token))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/calendar/token",
+    tag = "calendar",
+    responses(
+        (status = 204, description = "Feed token revoked"),
+        (status = 404, description = "No feed token issued", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[delete("/calendar/token")]
+pub async fn revoke_calendar_token(
+    req: HttpRequest,
+    calendar: web::Data<CalendarService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    calendar.revoke(&auth_token.public_key).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Upcoming renewal and expiry dates of the token's wallet, as an iCalendar feed. Served from
+/// the index, so it covers the primary cluster's default tenant.
+#[utoipa::path(
+    get,
+    path = "/api/v1/calendar.ics",
+    tag = "calendar",
+    params(CalendarQuery),
+    responses(
+        (status = 200, description = "iCalendar feed", content_type = "text/calendar"),
+        (status = 401, description = "Invalid or revoked token", body = ErrorResponse),
+    )
+)]
+#[get("/api/v1/calendar.ics")]
+pub async fn calendar_feed(
+    query: ValidatedQuery<CalendarQuery>,
+    calendar: web::Data<CalendarService>,
+    plans: web::Data<PlanService>,
+    pool: web::Data<PgPool>,
+) -> AppResult<HttpResponse> {
+    let owner = calendar.verify(&query.token).await?;
+    let stamp = now();
+    let upcoming = db::list_upcoming_subscriptions(&pool, &owner, stamp).await?;
+    let plan_ids: Vec<i64> = upcoming.iter().map(|due| due.subscription.plan_id).collect();
+    let names = plans.names(&plan_ids).await?;
+    let subscriptions: Vec<(db::DueSubscriptionRow, String)> = upcoming
+        .into_iter()
+        .map(|due| {
+            let plan_id = due.subscription.plan_id;
+            let name = names.get(&plan_id).cloned().unwrap_or_else(|| format!("Plan {}", plan_id));
+            (due, name)
+        })
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .insert_header((actix_web::http::header::CACHE_CONTROL, "private, max-age=300"))
+        .body(render(&subscriptions, stamp)))
+}
@@ -0,0 +1,327 @@
+use actix_web::HttpRequest;
+use async_trait::async_trait;
+use solana_sdk::{instruction::Instruction, pubkey::Pubkey};
+use crate::accounts::Commitment;
+use crate::cluster::{Cluster, SolanaClusters};
+use crate::wallet_locks::WalletGuard;
+use crate::{AppResult, SolanaService, SubscriptionRequest, SubscriptionResponse};
+
+/// One subscription instruction from `owner`, checked and ready for `ChainClient::submit` to
+/// sign and send.
+#[derive(Debug, Clone)]
+pub struct PendingTx {
+    pub name: &'static str, // Program instruction, as recorded on the transaction job
+    pub owner: Pubkey,
+    pub plan_id: u64,
+    pub instruction: Instruction,
+}
+
+/// What the subscription handlers need from one cluster and tenant: building and submitting
+/// the program's transactions and reading subscription accounts. `SolanaService` is the RPC
+/// implementation; in tests, `mock::MockChainClient` runs them on a `MockChain` so handler logic
+/// can be exercised without a cluster or database.
+#[async_trait]
+pub trait ChainClient: Send + Sync {
+    fn cluster(&self) -> Cluster;
+
+    fn tenant(&self) -> &str;
+
+    /// Only the primary cluster's default tenant is indexed, cached and reported to webhooks.
+    fn is_primary(&self) -> bool;
+
+    fn read_commitment(&self, requested: Option<Commitment>) -> Commitment;
+
+    fn subscription_address(&self, owner: &str, plan_id: u64) -> AppResult<Pubkey>;
+
+    /// Held from building one of `owner`'s transactions until it is submitted, so the wallet's
+    /// transactions go out one at a time.
+    async fn lock_wallet(&self, owner: &str) -> AppResult<WalletGuard>;
+
+    /// Checks the terms and that the subscription does not exist yet.
+    async fn build_create_tx(&self, owner: &str, req: &SubscriptionRequest) -> AppResult<PendingTx>;
+
+    fn build_renew_tx(&self, owner: &str, plan_id: u64) -> AppResult<PendingTx>;
+
+    fn build_cancel_tx(&self, owner: &str, plan_id: u64) -> AppResult<PendingTx>;
+
+    fn build_close_tx(&self, owner: &str, plan_id: u64) -> AppResult<PendingTx>;
+
+    /// Signs and sends `tx`, returning its signature once it reached the confirm commitment.
+    async fn submit(&self, tx: PendingTx) -> AppResult<String>;
+
+    async fn fetch_subscription(&self, owner: &str, plan_id: u64, commitment: Commitment) -> AppResult<SubscriptionResponse>;
+
+    /// The subscriptions of `(owner, plan_id)` pairs that exist, in the order given.
+    async fn fetch_subscriptions(&self, keys: &[(String, u64)], commitment: Commitment) -> AppResult<Vec<SubscriptionResponse>>;
+}
+
+/// Picks the `ChainClient` a request acts on. Handlers take it as `web::Data<dyn ChainClients>`.
+pub trait ChainClients: Send + Sync {
+    fn select(&self, req: &HttpRequest) -> AppResult<&dyn ChainClient>;
+}
+
+#[async_trait]
+impl ChainClient for SolanaService {
+    fn cluster(&self) -> Cluster {
+        self.cluster
+    }
+
+    fn tenant(&self) -> &str {
+        SolanaService::tenant(self)
+    }
+
+    fn is_primary(&self) -> bool {
+        SolanaService::is_primary(self)
+    }
+
+    fn read_commitment(&self, requested: Option<Commitment>) -> Commitment {
+        SolanaService::read_commitment(self, requested)
+    }
+
+    fn subscription_address(&self, owner: &str, plan_id: u64) -> AppResult<Pubkey> {
+        SolanaService::subscription_address(self, owner, plan_id)
+    }
+
+    async fn lock_wallet(&self, owner: &str) -> AppResult<WalletGuard> {
+        SolanaService::lock_wallet(self, owner).await
+    }
+
+    async fn build_create_tx(&self, owner: &str, req: &SubscriptionRequest) -> AppResult<PendingTx> {
+        SolanaService::build_create_tx(self, owner, req).await
+    }
+
+    fn build_renew_tx(&self, owner: &str, plan_id: u64) -> AppResult<PendingTx> {
+        SolanaService::build_renew_tx(self, owner, plan_id)
+    }
+
+    fn build_cancel_tx(&self, owner: &str, plan_id: u64) -> AppResult<PendingTx> {
+        SolanaService::build_cancel_tx(self, owner, plan_id)
+    }
+
+    fn build_close_tx(&self, owner: &str, plan_id: u64) -> AppResult<PendingTx> {
+        SolanaService::build_close_tx(self, owner, plan_id)
+    }
+
+    async fn submit(&self, tx: PendingTx) -> AppResult<String> {
+        SolanaService::submit(self, tx).await
+    }
+
+    async fn fetch_subscription(&self, owner: &str, plan_id: u64, commitment: Commitment) -> AppResult<SubscriptionResponse> {
+        self.get_subscription(owner, plan_id, commitment).await
+    }
+
+    async fn fetch_subscriptions(&self, keys: &[(String, u64)], commitment: Commitment) -> AppResult<Vec<SubscriptionResponse>> {
+        self.get_subscriptions(keys, commitment).await
+    }
+}
+
+impl ChainClients for SolanaClusters {
+    fn select(&self, req: &HttpRequest) -> AppResult<&dyn ChainClient> {
+        Ok(SolanaClusters::select(self, req)?)
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use crate::indexer::InstructionKind;
+    use crate::layout::ProgramLayout;
+    use crate::mock_chain::MockChain;
+    use crate::wallet_locks::WalletLocks;
+    use crate::{AppError, Subscription};
+    use solana_sdk::hash::hash;
+    use solana_sdk::instruction::AccountMeta;
+    use solana_sdk::system_program;
+    use std::str::FromStr;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    const PROGRAM_ID: &str = "GVkmkRg63U7QRES1fksSBSQhMFgydMa3oATDby7QyJEp";
+
+    #[derive(Default)]
+    struct State {
+        submitted: Vec<(&'static str, String, u64)>, // (instruction, owner, plan_id)
+        fail_next: Option<AppError>,
+    }
+
+    /// A `ChainClient` over a standalone `MockChain` running the fixed program. Submissions
+    /// run the program's instructions on it at once, with the owner's signature taken as
+    /// given, and get sequential signatures; `fail_next` makes the next submission return an
+    /// error instead, and `submitted` lists what was sent.
+    pub struct MockChainClient {
+        cluster: Cluster,
+        tenant: String,
+        primary: bool,
+        program_id: Pubkey,
+        treasury: Pubkey,
+        chain: MockChain,
+        state: Mutex<State>,
+        locks: WalletLocks,
+    }
+
+    impl MockChainClient {
+        pub fn new(primary: bool) -> Self {
+            let program_id = Pubkey::from_str(PROGRAM_ID).unwrap();
+            Self {
+                cluster: Cluster::Devnet,
+                tenant: crate::tenant::DEFAULT_TENANT.to_string(),
+                primary,
+                program_id,
+                treasury: Pubkey::new_unique(),
+                chain: MockChain::standalone(program_id, ProgramLayout::Fixed),
+                state: Mutex::new(State::default()),
+                locks: WalletLocks::new(Duration::from_secs(30)),
+            }
+        }
+
+        /// Stores `subscription` on the chain as if it had been created.
+        pub fn insert(&self, subscription: SubscriptionResponse) {
+            let user = Pubkey::from_str(&subscription.owner).expect("Mock subscriptions need a valid owner");
+            self.chain.put_subscription(&self.program_id, &Subscription {
+                user,
+                plan_id: subscription.plan_id,
+                start_time: subscription.start_time,
+                duration: subscription.duration,
+                amount: subscription.amount,
+                active: subscription.active,
+                history: subscription.history,
+            });
+        }
+
+        pub fn fail_next(&self, error: AppError) {
+            self.state.lock().unwrap().fail_next = Some(error);
+        }
+
+        pub fn submitted(&self) -> Vec<(&'static str, String, u64)> {
+            self.state.lock().unwrap().submitted.clone()
+        }
+
+        fn pda(&self, owner: &Pubkey, plan_id: u64) -> Pubkey {
+            Pubkey::find_program_address(&[b"subscription", owner.as_ref(), plan_id.to_le_bytes().as_ref()], &self.program_id).0
+        }
+
+        fn owner(owner: &str) -> AppResult<Pubkey> {
+            Pubkey::from_str(owner).map_err(|e| AppError::BadRequest(format!("Invalid public key: {}", e)))
+        }
+
+        /// `kind` with the accounts the program takes, as `SolanaService` builds it.
+        fn pending(&self, kind: InstructionKind, owner: &str, plan_id: u64, args: &[u8]) -> AppResult<PendingTx> {
+            let owner = Self::owner(owner)?;
+            let mut accounts = vec![AccountMeta::new(self.pda(&owner, plan_id), false), AccountMeta::new(owner, true)];
+            if kind.is_payment() {
+                accounts.push(AccountMeta::new(self.treasury, false));
+                accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+            }
+            let mut data = kind.discriminator().to_vec();
+            data.extend_from_slice(args);
+            Ok(PendingTx {
+                name: kind.name(),
+                owner,
+                plan_id,
+                instruction: Instruction { program_id: self.program_id, accounts, data },
+            })
+        }
+
+        fn response(pda: &Pubkey, subscription: Subscription) -> SubscriptionResponse {
+            SubscriptionResponse {
+                id: pda.to_string(),
+                plan_id: subscription.plan_id,
+                duration: subscription.duration,
+                amount: subscription.amount,
+                active: subscription.active,
+                start_time: subscription.start_time,
+                history: subscription.history,
+                owner: subscription.user.to_string(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ChainClient for MockChainClient {
+        fn cluster(&self) -> Cluster {
+            self.cluster
+        }
+
+        fn tenant(&self) -> &str {
+            &self.tenant
+        }
+
+        fn is_primary(&self) -> bool {
+            self.primary
+        }
+
+        fn read_commitment(&self, requested: Option<Commitment>) -> Commitment {
+            requested.unwrap_or(Commitment::Confirmed)
+        }
+
+        fn subscription_address(&self, owner: &str, plan_id: u64) -> AppResult<Pubkey> {
+            Ok(self.pda(&Self::owner(owner)?, plan_id))
+        }
+
+        async fn lock_wallet(&self, owner: &str) -> AppResult<WalletGuard> {
+            self.locks.lock(&Self::owner(owner)?).await
+        }
+
+        async fn build_create_tx(&self, owner: &str, req: &SubscriptionRequest) -> AppResult<PendingTx> {
+            ProgramLayout::Fixed.check_terms(req)?;
+            let pda = self.subscription_address(owner, req.plan_id)?;
+            if self.chain.subscription(&self.program_id, &pda).is_some() {
+                return Err(AppError::BadRequest(format!("Subscription PDA {} already exists", pda)));
+            }
+            self.pending(InstructionKind::Create, owner, req.plan_id, &ProgramLayout::Fixed.create_args(req))
+        }
+
+        fn build_renew_tx(&self, owner: &str, plan_id: u64) -> AppResult<PendingTx> {
+            self.pending(InstructionKind::Renew, owner, plan_id, &[])
+        }
+
+        fn build_cancel_tx(&self, owner: &str, plan_id: u64) -> AppResult<PendingTx> {
+            self.pending(InstructionKind::Cancel, owner, plan_id, &[])
+        }
+
+        fn build_close_tx(&self, owner: &str, plan_id: u64) -> AppResult<PendingTx> {
+            self.pending(InstructionKind::Close, owner, plan_id, &[])
+        }
+
+        async fn submit(&self, tx: PendingTx) -> AppResult<String> {
+            let mut state = self.state.lock().unwrap();
+            if let Some(error) = state.fail_next.take() {
+                return Err(error);
+            }
+            self.chain
+                .process(&tx.owner, tx.instruction)
+                .map_err(|e| AppError::SolanaError(format!("Transaction failed: {}", e)))?;
+            state.submitted.push((tx.name, tx.owner.to_string(), tx.plan_id));
+            let signature = bs58::encode(hash(state.submitted.len().to_string().as_bytes()).to_bytes()).into_string();
+            Ok(signature)
+        }
+
+        async fn fetch_subscription(&self, owner: &str, plan_id: u64, _commitment: Commitment) -> AppResult<SubscriptionResponse> {
+            let pda = self.subscription_address(owner, plan_id)?;
+            let subscription = self.chain.subscription(&self.program_id, &pda).ok_or_else(|| {
+                AppError::SolanaError(format!("Failed to fetch account: AccountNotFound: pubkey={}", pda))
+            })?;
+            Ok(Self::response(&pda, subscription))
+        }
+
+        async fn fetch_subscriptions(&self, keys: &[(String, u64)], _commitment: Commitment) -> AppResult<Vec<SubscriptionResponse>> {
+            let pdas = keys
+                .iter()
+                .map(|(owner, plan_id)| self.subscription_address(owner, *plan_id))
+                .collect::<AppResult<Vec<Pubkey>>>()?;
+            Ok(pdas
+                .iter()
+                .filter_map(|pda| Some(Self::response(pda, self.chain.subscription(&self.program_id, pda)?)))
+                .collect())
+        }
+    }
+
+    /// Serves every request from the same `MockChainClient`.
+    pub struct MockChainClients(pub MockChainClient);
+
+    impl ChainClients for MockChainClients {
+        fn select(&self, _req: &HttpRequest) -> AppResult<&dyn ChainClient> {
+            Ok(&self.0)
+        }
+    }
+}
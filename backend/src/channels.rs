@@ -0,0 +1,396 @@
+use actix_web::{delete, get, post, web, HttpResponse};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use sqlx::postgres::PgPool;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+use crate::validation::{validate_pubkey, FieldError, ValidatedJson, ValidatedQuery};
+use crate::webhooks::{SubscriptionEventData, WebhookEventType};
+use crate::{AppError, AppResult, Config};
+
+const TELEGRAM_API_URL: &str = "https://api.telegram.org";
+const DISCORD_HOSTS: [&str; 2] = ["discord.com", "discordapp.com"];
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelKind {
+    Discord,
+    Telegram,
+}
+
+impl ChannelKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ChannelKind::Discord => "discord",
+            ChannelKind::Telegram => "telegram",
+        }
+    }
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ChannelRow {
+    id: String,
+    merchant: String,
+    kind: String,
+    target: String,
+    bot_token: Option<String>,
+    events: Vec<String>,
+    plan_ids: Option<Vec<i64>>,
+    created_at: i64,
+}
+
+impl ChannelRow {
+    fn matches(&self, event_type: WebhookEventType, plan_id: u64) -> bool {
+        self.events.iter().any(|e| e == event_type.as_str())
+            && self.plan_ids.as_ref().is_none_or(|ids| ids.contains(&(plan_id as i64)))
+    }
+
+    fn notifier(&self) -> Option<Box<dyn Notifier>> {
+        match self.kind.as_str() {
+            "discord" => Some(Box::new(DiscordNotifier { webhook_url: self.target.clone() })),
+            "telegram" => Some(Box::new(TelegramNotifier {
+                bot_token: self.bot_token.clone()?,
+                chat_id: self.target.clone(),
+            })),
+            _ => None,
+        }
+    }
+}
+
+/// A configured connector. Discord webhook URLs and Telegram bot tokens are never returned.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Channel {
+    id: String,
+    merchant: String,
+    kind: ChannelKind,
+    chat_id: Option<String>, // Telegram only
+    events: Vec<WebhookEventType>,
+    plan_ids: Option<Vec<u64>>,
+    created_at: i64,
+}
+
+impl From<ChannelRow> for Channel {
+    fn from(row: ChannelRow) -> Self {
+        let kind = if row.kind == "telegram" { ChannelKind::Telegram } else { ChannelKind::Discord };
+        Channel {
+            id: row.id,
+            merchant: row.merchant,
+            kind,
+            chat_id: (kind == ChannelKind::Telegram).then_some(row.target),
+            events: row.events.iter().filter_map(|e| WebhookEventType::from_name(e)).collect(),
+            plan_ids: row.plan_ids.map(|ids| ids.into_iter().map(|id| id as u64).collect()),
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct ChannelRequest {
+    #[validate(custom = "validate_pubkey")]
+    merchant: Option<String>, // Defaults to the treasury wallet
+    kind: ChannelKind,
+    #[validate(url(message = "must be an absolute https URL"))]
+    webhook_url: Option<String>, // Discord
+    bot_token: Option<String>, // Telegram
+    chat_id: Option<String>,   // Telegram
+    #[validate(length(min = 1, message = "must list at least one event type"))]
+    events: Vec<WebhookEventType>,
+    plan_ids: Option<Vec<u64>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct ChannelsQuery {
+    #[validate(custom = "validate_pubkey")]
+    merchant: Option<String>,
+}
+
+// Notifiers
+/// A chat connector that can post a plain-text message.
+pub trait Notifier: Send + Sync {
+    fn send<'a>(&'a self, http: &'a reqwest::Client, text: &'a str) -> BoxFuture<'a, Result<(), String>>;
+}
+
+struct DiscordNotifier {
+    webhook_url: String,
+}
+
+impl Notifier for DiscordNotifier {
+    fn send<'a>(&'a self, http: &'a reqwest::Client, text: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let response = http
+                .post(&self.webhook_url)
+                .json(&serde_json::json!({ "content": text }))
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            check_status(response.status())
+        })
+    }
+}
+
+struct TelegramNotifier {
+    bot_token: String,
+    chat_id: String,
+}
+
+impl Notifier for TelegramNotifier {
+    fn send<'a>(&'a self, http: &'a reqwest::Client, text: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let response = http
+                .post(format!("{}/bot{}/sendMessage", TELEGRAM_API_URL, self.bot_token))
+                .json(&serde_json::json!({ "chat_id": self.chat_id, "text": text }))
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            check_status(response.status())
+        })
+    }
+}
+
+fn check_status(status: reqwest::StatusCode) -> Result<(), String> {
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(format!("Responded with status {}", status))
+    }
+}
+
+/// A short title for the event, shared with subscribers' push notifications.
+pub fn headline(event_type: WebhookEventType) -> &'static str {
+    match event_type {
+        WebhookEventType::SubscriptionCreated => "New subscription",
+        WebhookEventType::SubscriptionRenewed => "Subscription renewed",
+        WebhookEventType::SubscriptionRenewalFailed => "Automatic renewal failed",
+        WebhookEventType::SubscriptionCancelled => "Subscription cancelled",
+        WebhookEventType::SubscriptionExpiring => "Subscription expiring soon",
+        WebhookEventType::SubscriptionExpired => "Subscription expired",
+        WebhookEventType::SubscriptionClosed => "Subscription closed",
+        WebhookEventType::RefundRequested => "Refund requested",
+        WebhookEventType::RefundSucceeded => "Refund sent",
+        WebhookEventType::RefundFailed => "Refund failed",
+    }
+}
+
+fn message(event_type: WebhookEventType, data: &SubscriptionEventData) -> String {
+    let mut lines = vec![
+        format!("{} (plan {})", headline(event_type), data.plan_id),
+        format!("Subscription: {}", data.subscription),
+        format!("Owner: {}", data.owner),
+    ];
+    if let Some(expires_at) = data.expires_at {
+        lines.push(format!("Expires at: {} (unix)", expires_at));
+    }
+    if let Some(lamports) = data.refund_lamports {
+        lines.push(format!("Refund: {} lamports", lamports));
+    }
+    if let Some(signature) = &data.signature {
+        lines.push(format!("Transaction: {}", signature));
+    }
+    lines.join("\n")
+}
+
+// Channel Service
+/// Merchant Discord and Telegram connectors, configured through the admin API. Events are
+/// posted once, without retries; failures are only logged.
+#[derive(Clone)]
+pub struct ChannelService {
+    pool: PgPool,
+    http_client: reqwest::Client,
+    default_merchant: Pubkey,
+}
+
+impl ChannelService {
+    pub fn new(config: &Config, pool: PgPool) -> Self {
+        let http_client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("Failed to build channel HTTP client");
+        Self {
+            pool,
+            http_client,
+            default_merchant: config.treasury,
+        }
+    }
+
+    pub async fn create(&self, req: ChannelRequest) -> AppResult<Channel> {
+        let merchant = match &req.merchant {
+            Some(merchant) => Pubkey::from_str(merchant)
+                .map_err(|e| AppError::BadRequest(format!("Invalid merchant public key: {}", e)))?,
+            None => self.default_merchant,
+        };
+        let (target, bot_token) = match req.kind {
+            ChannelKind::Discord => {
+                let url = req
+                    .webhook_url
+                    .as_deref()
+                    .and_then(|url| reqwest::Url::parse(url).ok())
+                    .filter(|url| {
+                        url.scheme() == "https" && url.host_str().is_some_and(|host| DISCORD_HOSTS.contains(&host))
+                    })
+                    .ok_or_else(|| invalid("webhook_url", "must be a Discord webhook URL"))?;
+                (url.to_string(), None)
+            }
+            ChannelKind::Telegram => {
+                let bot_token = req
+                    .bot_token
+                    .filter(|t| !t.trim().is_empty())
+                    .ok_or_else(|| invalid("bot_token", "is required for Telegram"))?;
+                let chat_id = req
+                    .chat_id
+                    .filter(|c| !c.trim().is_empty())
+                    .ok_or_else(|| invalid("chat_id", "is required for Telegram"))?;
+                (chat_id.trim().to_string(), Some(bot_token.trim().to_string()))
+            }
+        };
+
+        let events: Vec<String> = req.events.iter().map(|e| e.as_str().to_string()).collect();
+        let plan_ids: Option<Vec<i64>> = req.plan_ids.map(|ids| ids.into_iter().map(|id| id as i64).collect());
+        let row = sqlx::query_as::<_, ChannelRow>(
+            "INSERT INTO notification_channels (id, merchant, kind, target, bot_token, events, plan_ids, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             RETURNING *",
+        )
+        .bind(random_hex(16))
+        .bind(merchant.to_string())
+        .bind(req.kind.as_str())
+        .bind(target)
+        .bind(bot_token)
+        .bind(&events)
+        .bind(&plan_ids)
+        .bind(now())
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to create channel: {}", e)))?;
+
+        tracing::info!("Registered {} channel {} for {}", row.kind, row.id, row.merchant);
+        Ok(row.into())
+    }
+
+    pub async fn list(&self, merchant: Option<&str>) -> AppResult<Vec<Channel>> {
+        let rows = sqlx::query_as::<_, ChannelRow>(
+            "SELECT * FROM notification_channels
+             WHERE ($1::TEXT IS NULL OR merchant = $1)
+             ORDER BY created_at DESC",
+        )
+        .bind(merchant)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to list channels: {}", e)))?;
+        Ok(rows.into_iter().map(Channel::from).collect())
+    }
+
+    pub async fn remove(&self, id: &str) -> AppResult<()> {
+        let result = sqlx::query("DELETE FROM notification_channels WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to delete channel: {}", e)))?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("Channel {} not found", id)));
+        }
+        Ok(())
+    }
+
+    /// Posts the event to every matching channel in the background.
+    pub fn dispatch(&self, event_type: WebhookEventType, data: &SubscriptionEventData) {
+        let service = self.clone();
+        let plan_id = data.plan_id;
+        let text = message(event_type, data);
+        tokio::spawn(async move {
+            let rows = match sqlx::query_as::<_, ChannelRow>("SELECT * FROM notification_channels")
+                .fetch_all(&service.pool)
+                .await
+            {
+                Ok(rows) => rows,
+                Err(e) => {
+                    tracing::error!("Failed to load notification channels: {}", e);
+                    return;
+                }
+            };
+
+            for row in rows.iter().filter(|row| row.matches(event_type, plan_id)) {
+                let Some(notifier) = row.notifier() else { continue };
+                if let Err(e) = notifier.send(&service.http_client, &text).await {
+                    tracing::warn!("Failed to post {} to {} channel {}: {}", event_type.as_str(), row.kind, row.id, e);
+                }
+            }
+        });
+    }
+}
+
+fn invalid(field: &str, message: &str) -> AppError {
+    AppError::Validation(vec![FieldError::new(field, "invalid", message)])
+}
+
+fn random_hex(len: usize) -> String {
+    let bytes: Vec<u8> = (0..len).map(|_| rand::random::<u8>()).collect();
+    hex::encode(bytes)
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+// Controllers
+/// Connects a merchant's Discord webhook or Telegram chat to subscription events.
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/channels",
+    tag = "admin",
+    request_body = ChannelRequest,
+    responses(
+        (status = 201, description = "Channel created", body = Channel),
+        (status = 403, description = "Admin role required", body = ErrorResponse),
+        (status = 422, description = "Invalid request", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/channels")]
+pub async fn create_channel(
+    channel_service: web::Data<ChannelService>,
+    body: ValidatedJson<ChannelRequest>,
+) -> AppResult<HttpResponse> {
+    let channel = channel_service.create(body.into_inner()).await?;
+    Ok(HttpResponse::Created().json(channel))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/channels",
+    tag = "admin",
+    params(ChannelsQuery),
+    responses((status = 200, description = "Configured channels, without secrets", body = [Channel])),
+    security(("bearer_auth" = []))
+)]
+#[get("/channels")]
+pub async fn list_channels(
+    channel_service: web::Data<ChannelService>,
+    query: ValidatedQuery<ChannelsQuery>,
+) -> AppResult<HttpResponse> {
+    let channels = channel_service.list(query.merchant.as_deref()).await?;
+    Ok(HttpResponse::Ok().json(channels))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/admin/channels/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "Channel id")),
+    responses(
+        (status = 204, description = "Channel deleted"),
+        (status = 404, description = "Channel not found", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[delete("/channels/{id}")]
+pub async fn delete_channel(
+    channel_service: web::Data<ChannelService>,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    channel_service.remove(&path.into_inner()).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,175 @@
+use once_cell::sync::OnceCell;
+use solana_client::client_error::{ClientError, ClientErrorKind, Result as ClientResult};
+use solana_client::rpc_request::{RpcError, RpcResponseErrorData};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::{Semaphore, SemaphorePermit};
+use crate::{metrics, Config};
+
+static BREAKER: OnceCell<CircuitBreaker> = OnceCell::new();
+
+/// Why a call was rejected without reaching the RPC node.
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("Solana RPC is unavailable, retry in {retry_after_secs}s")]
+pub struct CircuitOpen {
+    pub retry_after_secs: u64,
+}
+
+impl From<CircuitOpen> for ClientError {
+    fn from(open: CircuitOpen) -> Self {
+        // `Io` is the only kind that keeps a typed source, which `shed` looks for
+        std::io::Error::other(open).into()
+    }
+}
+
+/// The breaker's rejection, when that is why an RPC call failed.
+pub fn shed(error: &ClientError) -> Option<CircuitOpen> {
+    match error.kind() {
+        ClientErrorKind::Io(e) => e.get_ref()?.downcast_ref::<CircuitOpen>().copied(),
+        _ => None,
+    }
+}
+
+/// Failures that say the node is down or overloaded, as opposed to a rejected request.
+fn is_outage(error: &ClientError) -> bool {
+    match error.kind() {
+        ClientErrorKind::Reqwest(e) => e.status().is_none_or(|status| status.as_u16() == 429 || status.is_server_error()),
+        ClientErrorKind::Io(_) => shed(error).is_none(),
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { data: RpcResponseErrorData::NodeUnhealthy { .. }, .. }) => true,
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+struct Inner {
+    state: State,
+    failures: u32, // Consecutive outages while closed
+    opened_at: Instant,
+    probing: bool, // The half-open probe is in flight
+}
+
+/// Guards every Solana RPC call. After `RPC_BREAKER_THRESHOLD` consecutive outages (transport
+/// errors, 429s, 5xxs or an unhealthy node) it opens and rejects calls for
+/// `RPC_BREAKER_COOLDOWN_SECS`, then lets a single probe through: a success closes it again and
+/// an outage restarts the cooldown. At most `RPC_MAX_CONCURRENCY` calls run at once; the rest
+/// wait for a slot.
+pub struct CircuitBreaker {
+    inner: Mutex<Inner>,
+    threshold: u32,
+    cooldown: Duration,
+    permits: Semaphore,
+}
+
+/// A call let through by the breaker, holding one of its concurrency slots.
+pub struct Admission {
+    breaker: &'static CircuitBreaker,
+    _permit: SemaphorePermit<'static>,
+    probe: bool,
+}
+
+impl CircuitBreaker {
+    fn new(config: &Config) -> Self {
+        Self {
+            inner: Mutex::new(Inner { state: State::Closed, failures: 0, opened_at: Instant::now(), probing: false }),
+            threshold: config.rpc_breaker_threshold.max(1),
+            cooldown: Duration::from_secs(config.rpc_breaker_cooldown_secs.max(1)),
+            permits: Semaphore::new(config.rpc_max_concurrency.max(1)),
+        }
+    }
+
+    /// Waits for a concurrency slot, then admits the call unless the breaker is open or its
+    /// probe is already out.
+    pub async fn admit(&'static self) -> Result<Admission, CircuitOpen> {
+        let permit = self.permits.acquire().await.expect("RPC semaphore is never closed");
+        let mut inner = self.inner.lock().unwrap();
+        if inner.state == State::Open {
+            let elapsed = inner.opened_at.elapsed();
+            if elapsed < self.cooldown {
+                let remaining = (self.cooldown - elapsed).as_secs_f64().ceil() as u64;
+                return Err(CircuitOpen { retry_after_secs: remaining.max(1) });
+            }
+            self.set_state(&mut inner, State::HalfOpen);
+        }
+        let probe = inner.state == State::HalfOpen;
+        if probe {
+            if inner.probing {
+                return Err(CircuitOpen { retry_after_secs: 1 });
+            }
+            inner.probing = true;
+        }
+        Ok(Admission { breaker: self, _permit: permit, probe })
+    }
+
+    fn record(&self, probe: bool, outage: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        if probe {
+            inner.probing = false;
+        }
+        match (inner.state, outage) {
+            (State::Closed, true) => {
+                inner.failures += 1;
+                if inner.failures >= self.threshold {
+                    tracing::warn!("Solana RPC circuit opened after {} consecutive failures", inner.failures);
+                    self.open(&mut inner);
+                }
+            }
+            (State::Closed, false) => inner.failures = 0,
+            (State::HalfOpen, true) if probe => {
+                tracing::warn!("Solana RPC recovery probe failed, circuit reopened");
+                self.open(&mut inner);
+            }
+            (State::HalfOpen, false) if probe => {
+                tracing::info!("Solana RPC recovered, circuit closed");
+                inner.failures = 0;
+                self.set_state(&mut inner, State::Closed);
+            }
+            // Calls admitted before the circuit opened
+            _ => {}
+        }
+    }
+
+    fn open(&self, inner: &mut Inner) {
+        inner.failures = 0;
+        inner.opened_at = Instant::now();
+        self.set_state(inner, State::Open);
+    }
+
+    fn set_state(&self, inner: &mut Inner, state: State) {
+        inner.state = state;
+        metrics::set_rpc_circuit_state(state);
+    }
+}
+
+impl Admission {
+    pub fn finish<T>(mut self, result: &ClientResult<T>) {
+        let outage = matches!(result, Err(e) if is_outage(e));
+        self.breaker.record(self.probe, outage);
+        self.probe = false;
+    }
+}
+
+impl Drop for Admission {
+    // A probe dropped before finishing, as when its request is cancelled, frees the way for
+    // the next one
+    fn drop(&mut self) {
+        if self.probe {
+            self.breaker.inner.lock().unwrap().probing = false;
+        }
+    }
+}
+
+pub fn init(config: &Config) {
+    if BREAKER.set(CircuitBreaker::new(config)).is_err() {
+        tracing::warn!("RPC circuit breaker was already initialized");
+    }
+}
+
+pub fn breaker() -> &'static CircuitBreaker {
+    BREAKER.get().expect("circuit::init runs before any RPC call")
+}
@@ -0,0 +1,524 @@
+use actix_web::HttpRequest;
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use solana_client::client_error::Result as ClientResult;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::RpcClientConfig;
+use solana_client::rpc_request::RpcRequest;
+use solana_client::rpc_sender::{RpcSender, RpcTransportStats};
+use solana_rpc_client::http_sender::HttpSender;
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+use sqlx::postgres::PgPool;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use crate::accounts::AccountCache;
+use crate::custody::CustodyService;
+use crate::layout::ProgramLayout;
+use crate::metrics;
+use crate::mock_chain;
+use crate::signer::TransactionSigner;
+use crate::tenant::{self, TenantConfig, DEFAULT_TENANT};
+use crate::wallet_locks::WalletLocks;
+use crate::{AppError, AppResult, Config, SolanaService};
+
+pub const CLUSTER_HEADER: &str = "X-Solana-Cluster";
+const DEFAULT_PROGRAM_ID: &str = "GVkmkRg63U7QRES1fksSBSQhMFgydMa3oATDby7QyJEp";
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+const RPC_TIMEOUT: Duration = Duration::from_secs(30); // The Solana client's own default
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Cluster {
+    Devnet,
+    Mainnet,
+    Localnet,
+}
+
+impl Cluster {
+    pub const ALL: [Cluster; 3] = [Cluster::Devnet, Cluster::Mainnet, Cluster::Localnet];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Cluster::Devnet => "devnet",
+            Cluster::Mainnet => "mainnet",
+            Cluster::Localnet => "localnet",
+        }
+    }
+
+    /// The CAIP-2 chain id wallets know the cluster by, `None` for localnet, whose genesis
+    /// differs per validator.
+    pub fn chain_id(&self) -> Option<&'static str> {
+        match self {
+            Cluster::Devnet => Some("solana:EtWTRABZaYq6iMfeYKouRu166VU2xqa1"),
+            Cluster::Mainnet => Some("solana:5eykt4UsFv8P8NJdTREpY1vzqKqZKvdp"),
+            Cluster::Localnet => None,
+        }
+    }
+
+    fn default_rpc_url(&self) -> &'static str {
+        match self {
+            Cluster::Devnet => "https://api.devnet.solana.com",
+            Cluster::Mainnet => "https://api.mainnet-beta.solana.com",
+            Cluster::Localnet => "http://127.0.0.1:8899",
+        }
+    }
+
+    fn env_suffix(&self) -> String {
+        self.as_str().to_uppercase()
+    }
+}
+
+impl FromStr for Cluster {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "devnet" => Ok(Cluster::Devnet),
+            "mainnet" | "mainnet-beta" => Ok(Cluster::Mainnet),
+            "localnet" | "localhost" => Ok(Cluster::Localnet),
+            other => Err(format!("Unknown cluster {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClusterConfig {
+    pub cluster: Cluster,
+    pub rpc_urls: Vec<String>, // In failover order
+    pub ws_url: String,
+    pub program_id: Pubkey,
+    pub layout: ProgramLayout,
+}
+
+/// Reads `SOLANA_RPC_URLS_<CLUSTER>`, `SOLANA_WS_URL_<CLUSTER>`, `PROGRAM_ID_<CLUSTER>` and
+/// `PROGRAM_LAYOUT_<CLUSTER>` for every cluster. The primary cluster falls back to the unsuffixed
+/// `SOLANA_RPC_URL`, `SOLANA_WS_URL` and `PROGRAM_ID`; other clusters are only enabled when their
+/// RPC URLs are set. Program IDs and layouts fall back to `PROGRAM_ID` and `PROGRAM_LAYOUT`
+/// (default `flexible`) on every cluster.
+pub fn load_clusters(primary: Cluster) -> Vec<ClusterConfig> {
+    let env = |name: String| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+
+    Cluster::ALL
+        .into_iter()
+        .filter_map(|cluster| {
+            let suffix = cluster.env_suffix();
+            let is_primary = cluster == primary;
+            let rpc_urls = match env(format!("SOLANA_RPC_URLS_{}", suffix)) {
+                Some(urls) => urls,
+                None if is_primary => {
+                    env("SOLANA_RPC_URL".to_string()).unwrap_or_else(|| cluster.default_rpc_url().to_string())
+                }
+                None => return None,
+            };
+            let rpc_urls: Vec<String> = rpc_urls
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect();
+
+            // http(s)://host -> ws(s)://host unless a dedicated WebSocket endpoint is configured
+            let ws_url = env(format!("SOLANA_WS_URL_{}", suffix))
+                .or_else(|| if is_primary { env("SOLANA_WS_URL".to_string()) } else { None })
+                .unwrap_or_else(|| rpc_urls[0].replacen("http", "ws", 1));
+            let program_id = env(format!("PROGRAM_ID_{}", suffix))
+                .or_else(|| env("PROGRAM_ID".to_string()))
+                .unwrap_or_else(|| DEFAULT_PROGRAM_ID.to_string());
+            let layout = env(format!("PROGRAM_LAYOUT_{}", suffix))
+                .or_else(|| env("PROGRAM_LAYOUT".to_string()))
+                .map(|layout| layout.parse().unwrap_or_else(|e| panic!("{} for {}", e, cluster.as_str())))
+                .unwrap_or(ProgramLayout::Flexible);
+
+            Some(ClusterConfig {
+                cluster,
+                rpc_urls,
+                ws_url,
+                program_id: Pubkey::from_str(&program_id)
+                    .unwrap_or_else(|_| panic!("Invalid program ID for {}", cluster.as_str())),
+                layout,
+            })
+        })
+        .collect()
+}
+
+struct Endpoint {
+    url: String,
+    client: Arc<RpcClient>,
+    healthy: Arc<AtomicBool>,
+}
+
+/// Whose usage a pool's calls count towards and what extra headers they carry.
+#[derive(Clone)]
+struct ClientOptions {
+    cluster: Cluster,
+    tenant: String,
+    endpoints: &'static str, // shared, or tenant for the tenant's own endpoints
+    headers: HeaderMap,
+}
+
+/// Counts every JSON-RPC call it sends on `tenant_rpc_requests_total`, including retries and
+/// health checks, so a tenant's usage can be billed back to it.
+struct MeteredSender<S> {
+    inner: S,
+    options: ClientOptions,
+}
+
+#[async_trait]
+impl<S: RpcSender + Send + Sync> RpcSender for MeteredSender<S> {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        metrics::record_tenant_rpc(
+            &self.options.tenant,
+            self.options.cluster.as_str(),
+            self.options.endpoints,
+            &request.to_string(),
+        );
+        self.inner.send(request, params).await
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.inner.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.inner.url()
+    }
+}
+
+/// A client for `url`, or for the in-memory chain installed for a `mock://` URL.
+fn rpc_client(url: &str, commitment: CommitmentConfig, options: &ClientOptions) -> RpcClient {
+    let config = RpcClientConfig::with_commitment(commitment);
+    let options = options.clone();
+    match mock_chain::chain(url) {
+        Some(chain) => RpcClient::new_sender(MeteredSender { inner: chain, options }, config),
+        None => {
+            let mut headers = HttpSender::default_headers();
+            headers.extend(options.headers.clone());
+            let http_client = reqwest::Client::builder()
+                .default_headers(headers)
+                .timeout(RPC_TIMEOUT)
+                .pool_idle_timeout(RPC_TIMEOUT)
+                .build()
+                .expect("Failed to build RPC HTTP client");
+            let inner = HttpSender::new_with_client(url, http_client);
+            RpcClient::new_sender(MeteredSender { inner, options }, config)
+        }
+    }
+}
+
+/// Clients for `endpoints` at `commitment`, sharing their health state.
+fn with_clients(endpoints: &[Endpoint], commitment: CommitmentConfig, options: &ClientOptions) -> Vec<Endpoint> {
+    endpoints
+        .iter()
+        .map(|endpoint| Endpoint {
+            url: endpoint.url.clone(),
+            client: Arc::new(rpc_client(&endpoint.url, commitment, options)),
+            healthy: endpoint.healthy.clone(),
+        })
+        .collect()
+}
+
+/// RPC clients for one cluster's endpoints. Calls go to the first endpoint that passed its
+/// last health check, so a failing provider is skipped until it recovers. The endpoints can
+/// be replaced at runtime with `set_urls`.
+#[derive(Clone)]
+pub struct RpcPool {
+    commitment: CommitmentConfig,
+    options: ClientOptions,
+    endpoints: Arc<RwLock<Arc<Vec<Endpoint>>>>,
+    variants: Arc<Mutex<Vec<RpcPool>>>, // Made by `with_commitment` and `for_tenant`, kept on the same endpoints
+}
+
+impl RpcPool {
+    /// `cluster`'s shared endpoints, whose calls count towards the default tenant.
+    pub fn new(cluster: Cluster, urls: &[String], commitment: CommitmentConfig) -> Self {
+        let options = ClientOptions {
+            cluster,
+            tenant: DEFAULT_TENANT.to_string(),
+            endpoints: "shared",
+            headers: HeaderMap::new(),
+        };
+        Self::with_options(urls, commitment, options)
+    }
+
+    /// `tenant`'s own endpoints on `cluster`, sent its API key header if it has one.
+    pub fn dedicated(cluster: Cluster, tenant: &TenantConfig, urls: &[String]) -> Self {
+        let mut headers = HeaderMap::new();
+        if let Some((name, value)) = &tenant.rpc_api_key {
+            let mut value = HeaderValue::from_str(value).expect("Invalid RPC API key");
+            value.set_sensitive(true);
+            headers.insert(HeaderName::from_str(name).expect("Invalid RPC API key header"), value);
+        }
+        let options = ClientOptions { cluster, tenant: tenant.id.clone(), endpoints: "tenant", headers };
+        Self::with_options(urls, CommitmentConfig::default(), options)
+    }
+
+    fn with_options(urls: &[String], commitment: CommitmentConfig, options: ClientOptions) -> Self {
+        let endpoints = urls
+            .iter()
+            .map(|url| Endpoint {
+                url: url.clone(),
+                client: Arc::new(rpc_client(url, commitment, &options)),
+                healthy: Arc::new(AtomicBool::new(true)),
+            })
+            .collect();
+        Self {
+            commitment,
+            options,
+            endpoints: Arc::new(RwLock::new(Arc::new(endpoints))),
+            variants: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Same endpoints and health state, with clients at a different default commitment.
+    pub fn with_commitment(&self, commitment: CommitmentConfig) -> Self {
+        self.variant(commitment, self.options.clone())
+    }
+
+    /// Same endpoints and health state, with calls counted towards `tenant`.
+    pub fn for_tenant(&self, tenant: &str) -> Self {
+        self.variant(self.commitment, ClientOptions { tenant: tenant.to_string(), ..self.options.clone() })
+    }
+
+    fn variant(&self, commitment: CommitmentConfig, options: ClientOptions) -> Self {
+        let variant = Self {
+            commitment,
+            endpoints: Arc::new(RwLock::new(Arc::new(with_clients(&self.endpoints(), commitment, &options)))),
+            options,
+            variants: Arc::new(Mutex::new(Vec::new())),
+        };
+        self.variants.lock().unwrap().push(variant.clone());
+        variant
+    }
+
+    fn endpoints(&self) -> Arc<Vec<Endpoint>> {
+        self.endpoints.read().unwrap().clone()
+    }
+
+    pub fn urls(&self) -> Vec<String> {
+        self.endpoints().iter().map(|endpoint| endpoint.url.clone()).collect()
+    }
+
+    /// Switches to `urls`, in failover order. Endpoints that stay keep their health state;
+    /// new ones start healthy until their first check. Calls already in flight finish on the
+    /// old clients.
+    pub fn set_urls(&self, urls: &[String]) {
+        let current = self.endpoints();
+        let endpoints: Vec<Endpoint> = urls
+            .iter()
+            .map(|url| Endpoint {
+                url: url.clone(),
+                client: Arc::new(rpc_client(url, self.commitment, &self.options)),
+                healthy: current
+                    .iter()
+                    .find(|endpoint| &endpoint.url == url)
+                    .map(|endpoint| endpoint.healthy.clone())
+                    .unwrap_or_else(|| Arc::new(AtomicBool::new(true))),
+            })
+            .collect();
+        self.move_variants(&endpoints);
+        *self.endpoints.write().unwrap() = Arc::new(endpoints);
+    }
+
+    /// Moves the variants made from this pool, and theirs, onto `endpoints`.
+    fn move_variants(&self, endpoints: &[Endpoint]) {
+        for variant in self.variants.lock().unwrap().iter() {
+            let clients = with_clients(endpoints, variant.commitment, &variant.options);
+            variant.move_variants(&clients);
+            *variant.endpoints.write().unwrap() = Arc::new(clients);
+        }
+    }
+
+    /// The preferred healthy client, or the first endpoint when none are healthy.
+    pub fn client(&self) -> Arc<RpcClient> {
+        let endpoints = self.endpoints();
+        endpoints
+            .iter()
+            .find(|endpoint| endpoint.healthy.load(Ordering::Relaxed))
+            .unwrap_or(&endpoints[0])
+            .client
+            .clone()
+    }
+
+    pub async fn check_health(&self) {
+        for endpoint in self.endpoints().iter() {
+            let healthy = matches!(
+                tokio::time::timeout(HEALTH_CHECK_TIMEOUT, endpoint.client.get_health()).await,
+                Ok(Ok(()))
+            );
+            let was_healthy = endpoint.healthy.swap(healthy, Ordering::Relaxed);
+            if was_healthy != healthy {
+                if healthy {
+                    tracing::info!("RPC endpoint {} recovered", endpoint.url);
+                } else {
+                    tracing::warn!("RPC endpoint {} failed its health check", endpoint.url);
+                }
+            }
+        }
+    }
+
+    pub async fn run_health_checks(self, interval: Duration) {
+        loop {
+            self.check_health().await;
+            tokio::time::sleep(interval).await;
+        }
+    }
+}
+
+/// One `SolanaService` per configured cluster and tenant. Requests use the primary cluster
+/// unless overrides are enabled and they send `X-Solana-Cluster`, and the tenant chosen by
+/// `tenant::tenant_of`. Sandbox tenants only exist on devnet and always use it. Tenants use the
+/// cluster's shared RPC endpoints unless they bring their own; either way each has its own
+/// clients and account cache, so its calls are counted towards it.
+#[derive(Clone)]
+pub struct SolanaClusters {
+    services: HashMap<(Cluster, String), SolanaService>,
+    rpcs: HashMap<Cluster, RpcPool>,
+    tenant_rpcs: HashMap<(Cluster, String), RpcPool>, // Only tenants with their own endpoints
+    accounts: Vec<AccountCache>,
+    sandboxes: HashSet<String>,
+    primary: Cluster,
+    allow_override: bool,
+}
+
+impl SolanaClusters {
+    pub fn new(
+        config: &Config,
+        pool: PgPool,
+        signer: Arc<dyn TransactionSigner>,
+        custody: Option<CustodyService>,
+    ) -> Self {
+        let rpcs: HashMap<Cluster, RpcPool> = config.clusters
+            .iter()
+            .map(|cluster| {
+                (cluster.cluster, RpcPool::new(cluster.cluster, &cluster.rpc_urls, CommitmentConfig::default()))
+            })
+            .collect();
+        let sandboxes: HashSet<String> =
+            config.tenants.iter().filter(|tenant| tenant.sandbox).map(|tenant| tenant.id.clone()).collect();
+        assert!(
+            sandboxes.is_empty() || rpcs.contains_key(&Cluster::Devnet),
+            "Sandbox tenants need devnet; set SOLANA_RPC_URLS_DEVNET"
+        );
+        let wallet_locks = WalletLocks::new(Duration::from_secs(config.wallet_lock_timeout_secs));
+        let mut services = HashMap::new();
+        let mut tenant_rpcs = HashMap::new();
+        let mut accounts = Vec::new();
+        for cluster in &config.clusters {
+            for tenant in &config.tenants {
+                if tenant.sandbox && cluster.cluster != Cluster::Devnet {
+                    continue;
+                }
+                let rpc = match tenant.rpc_urls(cluster.cluster) {
+                    Some(urls) => {
+                        let rpc = RpcPool::dedicated(cluster.cluster, tenant, urls);
+                        tenant_rpcs.insert((cluster.cluster, tenant.id.clone()), rpc.clone());
+                        rpc
+                    }
+                    None if tenant.id == DEFAULT_TENANT => rpcs[&cluster.cluster].clone(),
+                    None => rpcs[&cluster.cluster].for_tenant(&tenant.id),
+                };
+                let cache = AccountCache::new(config, rpc.clone());
+                accounts.push(cache.clone());
+                services.insert(
+                    (cluster.cluster, tenant.id.clone()),
+                    SolanaService::new(
+                        config,
+                        cluster,
+                        tenant,
+                        rpc,
+                        cache,
+                        pool.clone(),
+                        signer.clone(),
+                        custody.clone(),
+                        wallet_locks.clone(),
+                    ),
+                );
+            }
+        }
+        Self {
+            services,
+            rpcs,
+            tenant_rpcs,
+            accounts,
+            sandboxes,
+            primary: config.cluster,
+            allow_override: config.allow_cluster_override,
+        }
+    }
+
+    /// The primary cluster's default tenant, the only one that is indexed.
+    pub fn primary(&self) -> &SolanaService {
+        &self.services[&(self.primary, DEFAULT_TENANT.to_string())]
+    }
+
+    /// Every configured cluster and tenant, ordered by cluster then tenant.
+    pub fn all(&self) -> Vec<&SolanaService> {
+        let mut services: Vec<&SolanaService> = self.services.values().collect();
+        services.sort_by(|a, b| (a.cluster.as_str(), a.tenant()).cmp(&(b.cluster.as_str(), b.tenant())));
+        services
+    }
+
+    pub fn get(&self, cluster: Cluster, tenant: &str) -> Option<&SolanaService> {
+        self.services.get(&(cluster, tenant.to_string()))
+    }
+
+    /// The cluster `tenant` runs on unless a request picks another: devnet for sandbox tenants,
+    /// else the primary cluster.
+    pub fn default_cluster(&self, tenant: &str) -> Cluster {
+        if self.sandboxes.contains(tenant) {
+            Cluster::Devnet
+        } else {
+            self.primary
+        }
+    }
+
+    pub fn select(&self, req: &HttpRequest) -> AppResult<&SolanaService> {
+        let tenant = tenant::tenant_of(req)?;
+        let default_cluster = self.default_cluster(&tenant);
+        let cluster = match req.headers().get(CLUSTER_HEADER) {
+            Some(header) => header
+                .to_str()
+                .map_err(|_| AppError::BadRequest(format!("Invalid {} header", CLUSTER_HEADER)))?
+                .parse::<Cluster>()
+                .map_err(AppError::BadRequest)?,
+            None => default_cluster,
+        };
+        if self.sandboxes.contains(&tenant) && cluster != Cluster::Devnet {
+            return Err(AppError::BadRequest(format!("Sandbox tenant {} only runs on devnet", tenant)));
+        }
+        if cluster != default_cluster && !self.allow_override {
+            return Err(AppError::Forbidden("Cluster selection is disabled".to_string()));
+        }
+        if !self.rpcs.contains_key(&cluster) {
+            return Err(AppError::BadRequest(format!("Cluster {} is not configured", cluster.as_str())));
+        }
+        self.get(cluster, &tenant)
+            .ok_or_else(|| AppError::NotFound(format!("Tenant {} is not configured", tenant)))
+    }
+
+    pub fn rpc(&self, cluster: Cluster) -> Option<&RpcPool> {
+        self.rpcs.get(&cluster)
+    }
+
+    /// `tenant`'s own endpoints on `cluster`, `None` when it uses the shared ones.
+    pub fn tenant_rpc(&self, cluster: Cluster, tenant: &str) -> Option<&RpcPool> {
+        self.tenant_rpcs.get(&(cluster, tenant.to_string()))
+    }
+
+    pub fn spawn_health_checks(&self, interval: Duration) {
+        for rpc in self.rpcs.values().chain(self.tenant_rpcs.values()) {
+            tokio::spawn(rpc.clone().run_health_checks(interval));
+        }
+    }
+
+    pub fn spawn_account_promoters(&self) {
+        for accounts in &self.accounts {
+            tokio::spawn(accounts.clone().run_promoter());
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use futures_util::future::LocalBoxFuture;
+use std::future::ready;
+use crate::accounts::Commitment;
+use crate::AppError;
+
+pub const COMMITMENT_HEADER: &str = "X-Solana-Commitment";
+
+tokio::task_local! {
+    static REQUESTED: Option<Commitment>;
+}
+
+/// The commitment used for each kind of operation, from `READ_COMMITMENT`,
+/// `CHECK_COMMITMENT` and `CONFIRM_COMMITMENT`.
+#[derive(Debug, Clone, Copy)]
+pub struct CommitmentPolicy {
+    pub read: Commitment,    // Account reads returned to clients
+    pub check: Commitment,   // Account checks before a transaction is built
+    pub confirm: Commitment, // What a submission waits for before it returns
+}
+
+pub fn load_commitment_policy() -> CommitmentPolicy {
+    let commitment = |name: &str, default: Commitment| {
+        std::env::var(name)
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+            .map(|v| v.parse().unwrap_or_else(|e| panic!("Invalid {}: {}", name, e)))
+            .unwrap_or(default)
+    };
+    CommitmentPolicy {
+        read: commitment("READ_COMMITMENT", Commitment::Confirmed),
+        check: commitment("CHECK_COMMITMENT", Commitment::Confirmed),
+        // The RPC client default, which submissions have always waited for
+        confirm: commitment("CONFIRM_COMMITMENT", Commitment::Finalized),
+    }
+}
+
+/// The commitment the current request asked for in `X-Solana-Commitment`, if any.
+pub fn requested() -> Option<Commitment> {
+    REQUESTED.try_with(|requested| *requested).ok().flatten()
+}
+
+/// Makes `X-Solana-Commitment` available to the handler through `requested`, as the
+/// commitment of its account reads and of the confirmation its submissions wait for.
+/// An unknown commitment gets `400`.
+pub fn scope_commitment<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> LocalBoxFuture<'static, Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    let requested = match req.headers().get(COMMITMENT_HEADER) {
+        Some(header) => match header.to_str().map_err(|_| ()).and_then(|v| v.parse::<Commitment>().map_err(|_| ())) {
+            Ok(commitment) => Some(commitment),
+            Err(()) => {
+                let error = AppError::BadRequest(format!(
+                    "Invalid {} header; expected processed, confirmed or finalized",
+                    COMMITMENT_HEADER
+                ));
+                return Box::pin(ready(Err(error.into())));
+            }
+        },
+        None => None,
+    };
+    let call = REQUESTED.sync_scope(requested, || srv.call(req));
+    Box::pin(REQUESTED.scope(requested, call))
+}
@@ -0,0 +1,118 @@
+use actix_web::http::Method;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use crate::metrics;
+
+/// Listing and scanning routes, which read many accounts or indexed rows per request.
+/// `<method> <route>`, the route matched on the end of its pattern; without a method any
+/// method matches.
+const DEFAULT_HEAVY_ROUTES: [&str; 11] = [
+    "GET /subscriptions",
+    "GET /subscribers",
+    "GET /subscriptions/{plan_id}/payments",
+    "GET /subscriptions/{plan_id}/payments/export",
+    "GET /analytics/mrr",
+    "GET /analytics/churn",
+    "GET /analytics/revenue",
+    "GET /treasury/inflows",
+    "GET /audit",
+    "POST /graphql",
+    "POST /exports",
+];
+
+/// Which routes share the heavy-route pool and how large it is.
+#[derive(Debug, Clone)]
+pub struct HeavyRouteConfig {
+    routes: Vec<(Option<Method>, String)>,
+    max_concurrent: usize, // 0 disables the limit
+    queue_timeout: Duration, // How long a request waits for a slot before it is turned away
+}
+
+/// `HEAVY_ROUTE_CONCURRENCY` (default 16, 0 for no limit), `HEAVY_ROUTE_QUEUE_TIMEOUT_MS`
+/// (default 2000) and `HEAVY_ROUTES`, a comma-separated list of `[<method> ]<route>` replacing
+/// the default listing and scanning routes, e.g. `GET /subscribers,POST /graphql`.
+pub fn load_heavy_routes() -> HeavyRouteConfig {
+    let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+    let routes = match var("HEAVY_ROUTES") {
+        Some(routes) => routes.split(',').map(str::trim).filter(|s| !s.is_empty()).map(parse_route).collect(),
+        None => DEFAULT_HEAVY_ROUTES.into_iter().map(parse_route).collect(),
+    };
+    HeavyRouteConfig {
+        routes,
+        max_concurrent: var("HEAVY_ROUTE_CONCURRENCY")
+            .map(|v| v.trim().parse().expect("Invalid HEAVY_ROUTE_CONCURRENCY"))
+            .unwrap_or(16),
+        queue_timeout: Duration::from_millis(
+            var("HEAVY_ROUTE_QUEUE_TIMEOUT_MS")
+                .map(|v| v.trim().parse().expect("Invalid HEAVY_ROUTE_QUEUE_TIMEOUT_MS"))
+                .unwrap_or(2000),
+        ),
+    }
+}
+
+fn parse_route(entry: &str) -> (Option<Method>, String) {
+    match entry.split_once(' ') {
+        Some((method, route)) => {
+            let method = Method::from_bytes(method.trim().to_uppercase().as_bytes())
+                .unwrap_or_else(|_| panic!("Invalid method in {} in HEAVY_ROUTES", entry));
+            (Some(method), route.trim().to_string())
+        }
+        None => (None, entry.to_string()),
+    }
+}
+
+/// Bounds how many listing and scanning requests run at once, so a burst of them cannot
+/// take every RPC connection and database slot from sign-in, status and submission routes,
+/// which never wait here. The pool is shared by all workers of the process; each replica has
+/// its own.
+#[derive(Clone)]
+pub struct HeavyRouteLimiter {
+    config: Arc<HeavyRouteConfig>,
+    permits: Arc<Semaphore>,
+}
+
+impl HeavyRouteLimiter {
+    pub fn new(config: &HeavyRouteConfig) -> Self {
+        Self {
+            config: Arc::new(config.clone()),
+            permits: Arc::new(Semaphore::new(config.max_concurrent)),
+        }
+    }
+
+    pub fn is_heavy(&self, method: &Method, route: &str) -> bool {
+        self.config.max_concurrent > 0
+            && self.config.routes.iter().any(|(route_method, suffix)| {
+                route_method.as_ref().is_none_or(|m| m == method) && route.ends_with(suffix.as_str())
+            })
+    }
+
+    /// A slot in the pool, held until dropped, or `None` if none freed up within the queue
+    /// timeout.
+    pub async fn acquire(&self) -> Option<HeavyRouteSlot> {
+        let acquired = tokio::time::timeout(self.config.queue_timeout, self.permits.clone().acquire_owned()).await;
+        let Ok(Ok(permit)) = acquired else {
+            metrics::record_heavy_route_rejection();
+            return None;
+        };
+        metrics::set_heavy_routes_in_flight(self.in_flight());
+        Some(HeavyRouteSlot { permit: Some(permit), limiter: self.clone() })
+    }
+
+    fn in_flight(&self) -> i64 {
+        (self.config.max_concurrent - self.permits.available_permits()) as i64
+    }
+}
+
+/// Frees its slot when the request finishes or is dropped.
+pub struct HeavyRouteSlot {
+    permit: Option<OwnedSemaphorePermit>,
+    limiter: HeavyRouteLimiter,
+}
+
+impl Drop for HeavyRouteSlot {
+    fn drop(&mut self) {
+        drop(self.permit.take());
+        metrics::set_heavy_routes_in_flight(self.limiter.in_flight());
+    }
+}
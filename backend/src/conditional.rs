@@ -0,0 +1,70 @@
+use actix_web::http::header::{self, EntityTag, HttpDate};
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use std::time::{Duration, UNIX_EPOCH};
+use crate::db::{OwnerSubscriptionsVersionRow, SubscriptionVersionRow};
+
+/// Validators for an indexed response, derived from a cheap version query so a matching
+/// `If-None-Match` (or, without one, `If-Modified-Since`) is answered with `304 Not Modified`
+/// before the subscription is fetched. ETags are weak since responses may be compressed.
+pub struct Validators {
+    etag: EntityTag,
+    last_modified: Option<HttpDate>,
+}
+
+impl Validators {
+    pub fn for_subscription(version: &SubscriptionVersionRow) -> Self {
+        Self::new(
+            format!("s-{}-{}-{}", version.updated_slot, version.payments, version.active as u8),
+            version.last_event_time,
+        )
+    }
+
+    pub fn for_owner(version: &OwnerSubscriptionsVersionRow) -> Self {
+        Self::new(
+            format!(
+                "l-{}-{}-{}-{}-{}",
+                version.count, version.max_slot, version.slot_sum, version.payments, version.active
+            ),
+            version.last_event_time,
+        )
+    }
+
+    fn new(tag: String, last_event_time: Option<i64>) -> Self {
+        Self {
+            etag: EntityTag::new_weak(tag),
+            last_modified: last_event_time
+                .map(|secs| HttpDate::from(UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64))),
+        }
+    }
+
+    pub fn is_fresh(&self, req: &HttpRequest) -> bool {
+        let headers = req.headers();
+        if let Some(value) = headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+            return value.trim() == "*"
+                || value
+                    .split(',')
+                    .filter_map(|tag| tag.trim().parse::<EntityTag>().ok())
+                    .any(|tag| tag.weak_eq(&self.etag));
+        }
+        match (
+            self.last_modified,
+            headers.get(header::IF_MODIFIED_SINCE).and_then(|v| v.to_str().ok()?.parse::<HttpDate>().ok()),
+        ) {
+            (Some(last_modified), Some(since)) => last_modified <= since,
+            _ => false,
+        }
+    }
+
+    pub fn apply(&self, builder: &mut HttpResponseBuilder) {
+        builder.insert_header(header::ETag(self.etag.clone()));
+        if let Some(last_modified) = self.last_modified {
+            builder.insert_header(header::LastModified(last_modified));
+        }
+    }
+
+    pub fn not_modified(&self) -> HttpResponse {
+        let mut builder = HttpResponse::NotModified();
+        self.apply(&mut builder);
+        builder.finish()
+    }
+}
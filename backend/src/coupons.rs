@@ -0,0 +1,376 @@
+use actix_web::{get, post, web, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::{IntoParams, ToSchema};
+use validator::{Validate, ValidationError};
+use crate::cluster::SolanaClusters;
+use crate::layout::ProgramLayout;
+use crate::validation::{FieldError, ValidatedJson, ValidatedQuery};
+use crate::{AppError, AppResult, AuthToken, SubscriptionRequest};
+
+// Models
+#[derive(Debug, Clone, sqlx::FromRow)]
+struct CouponRow {
+    code: String,
+    merchant: String,
+    percent_off: Option<i32>,
+    amount_off: Option<i64>,
+    plan_ids: Option<Vec<i64>>,
+    expires_at: Option<i64>,
+    active: bool,
+    created_at: i64,
+    updated_at: i64,
+}
+
+impl CouponRow {
+    /// Why the coupon cannot be used on `plan_id` right now, if it cannot.
+    fn unusable(&self, plan_id: u64) -> Option<String> {
+        if !self.active {
+            return Some("Coupon has been disabled".to_string());
+        }
+        if self.expires_at.is_some_and(|expires_at| expires_at <= now()) {
+            return Some("Coupon has expired".to_string());
+        }
+        if self.plan_ids.as_ref().is_some_and(|ids| !ids.contains(&(plan_id as i64))) {
+            return Some(format!("Coupon does not apply to plan {}", plan_id));
+        }
+        None
+    }
+
+    fn discount(&self, amount: u64) -> u64 {
+        match (self.percent_off, self.amount_off) {
+            (Some(percent), _) => (amount as u128 * percent as u128 / 100) as u64,
+            (None, Some(off)) => (off as u64).min(amount),
+            (None, None) => 0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Coupon {
+    code: String,
+    merchant: String,
+    percent_off: Option<u8>,
+    amount_off: Option<u64>,    // Lamports
+    plan_ids: Option<Vec<u64>>, // None applies to every plan
+    expires_at: Option<i64>,
+    active: bool,
+    created_at: i64,
+    updated_at: i64,
+}
+
+impl From<CouponRow> for Coupon {
+    fn from(row: CouponRow) -> Self {
+        Coupon {
+            code: row.code,
+            merchant: row.merchant,
+            percent_off: row.percent_off.map(|percent| percent as u8),
+            amount_off: row.amount_off.map(|off| off as u64),
+            plan_ids: row.plan_ids.map(|ids| ids.into_iter().map(|id| id as u64).collect()),
+            expires_at: row.expires_at,
+            active: row.active,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct CouponRequest {
+    #[validate(custom = "validate_code")]
+    code: String, // Case-insensitive; stored uppercase
+    #[validate(range(min = 1, max = 100, message = "must be between 1 and 100"))]
+    percent_off: Option<u8>,
+    #[validate(range(min = 1, max = 9223372036854775807, message = "must be a positive lamport amount"))]
+    amount_off: Option<u64>,
+    plan_ids: Option<Vec<u64>>,
+    expires_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct ValidateCouponQuery {
+    #[validate(range(max = 9223372036854775807, message = "must fit in a signed 64-bit integer"))]
+    plan_id: u64,
+    duration: u64, // Terms of the create the coupon would apply to
+    amount: u64,   // List price in lamports, before the discount
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct CouponValidation {
+    code: String,
+    valid: bool,
+    reason: Option<String>, // Why the coupon cannot be used, when invalid
+    plan_id: u64,
+    amount: u64,                    // List price
+    discount: Option<u64>,          // Lamports off, when valid
+    discounted_amount: Option<u64>, // Amount to create the subscription with, when valid
+}
+
+fn validate_code(code: &str) -> Result<(), ValidationError> {
+    let valid = (3..=32).contains(&code.len())
+        && code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        return Ok(());
+    }
+    let mut error = ValidationError::new("code");
+    error.message = Some("must be 3 to 32 letters, digits, '-' or '_'".into());
+    Err(error)
+}
+
+fn normalize(code: &str) -> String {
+    code.trim().to_ascii_uppercase()
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+// Coupon Service
+/// Merchant discount codes. The program has no coupon instructions and the fixed-parameter
+/// program always charges the same amount, so coupons are kept off-chain: the checkout
+/// validates a code and creates the subscription with the discounted amount, which only the
+/// flexible program accepts.
+#[derive(Clone)]
+pub struct CouponService {
+    pool: PgPool,
+}
+
+impl CouponService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn create(&self, merchant: &str, req: CouponRequest) -> AppResult<Coupon> {
+        if req.percent_off.is_some() == req.amount_off.is_some() {
+            return Err(AppError::Validation(vec![FieldError::new(
+                "percent_off",
+                "invalid",
+                "set exactly one of percent_off and amount_off",
+            )]));
+        }
+        if req.expires_at.is_some_and(|expires_at| expires_at <= now()) {
+            return Err(AppError::Validation(vec![FieldError::new("expires_at", "invalid", "must be in the future")]));
+        }
+        let code = normalize(&req.code);
+        let plan_ids: Option<Vec<i64>> = req.plan_ids.map(|ids| ids.into_iter().map(|id| id as i64).collect());
+        let created_at = now();
+        let row = sqlx::query_as::<_, CouponRow>(
+            "INSERT INTO coupons (code, merchant, percent_off, amount_off, plan_ids, expires_at, active, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, TRUE, $7, $7)
+             ON CONFLICT (code) DO NOTHING
+             RETURNING code, merchant, percent_off, amount_off, plan_ids, expires_at, active, created_at, updated_at",
+        )
+        .bind(&code)
+        .bind(merchant)
+        .bind(req.percent_off.map(|percent| percent as i32))
+        .bind(req.amount_off.map(|off| off as i64))
+        .bind(&plan_ids)
+        .bind(req.expires_at)
+        .bind(created_at)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to create coupon: {}", e)))?
+        .ok_or_else(|| AppError::Conflict(format!("Coupon {} already exists", code)))?;
+        tracing::info!("Created coupon {} for {}", row.code, row.merchant);
+        Ok(row.into())
+    }
+
+    pub async fn list(&self, merchant: &str) -> AppResult<Vec<Coupon>> {
+        let rows = sqlx::query_as::<_, CouponRow>(
+            "SELECT code, merchant, percent_off, amount_off, plan_ids, expires_at, active, created_at, updated_at
+             FROM coupons
+             WHERE merchant = $1
+             ORDER BY created_at DESC",
+        )
+        .bind(merchant)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to list coupons: {}", e)))?;
+        Ok(rows.into_iter().map(Coupon::from).collect())
+    }
+
+    async fn find(&self, code: &str) -> AppResult<Option<CouponRow>> {
+        sqlx::query_as::<_, CouponRow>(
+            "SELECT code, merchant, percent_off, amount_off, plan_ids, expires_at, active, created_at, updated_at
+             FROM coupons
+             WHERE code = $1",
+        )
+        .bind(normalize(code))
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to fetch coupon: {}", e)))
+    }
+
+    pub async fn get(&self, merchant: &str, code: &str) -> AppResult<Coupon> {
+        self.find(code)
+            .await?
+            .filter(|row| row.merchant == merchant)
+            .map(Coupon::from)
+            .ok_or_else(|| AppError::NotFound(format!("Coupon {} not found", normalize(code))))
+    }
+
+    /// Disabled coupons stay listed but no longer validate. Disabling twice is a no-op.
+    pub async fn disable(&self, merchant: &str, code: &str) -> AppResult<Coupon> {
+        let row = sqlx::query_as::<_, CouponRow>(
+            "UPDATE coupons SET active = FALSE, updated_at = CASE WHEN active THEN $3 ELSE updated_at END
+             WHERE code = $1 AND merchant = $2
+             RETURNING code, merchant, percent_off, amount_off, plan_ids, expires_at, active, created_at, updated_at",
+        )
+        .bind(normalize(code))
+        .bind(merchant)
+        .bind(now())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to disable coupon: {}", e)))?
+        .ok_or_else(|| AppError::NotFound(format!("Coupon {} not found", normalize(code))))?;
+        Ok(row.into())
+    }
+
+    pub async fn validate(&self, code: &str, query: &ValidateCouponQuery, layout: ProgramLayout) -> AppResult<CouponValidation> {
+        let mut validation = CouponValidation {
+            code: normalize(code),
+            valid: false,
+            reason: None,
+            plan_id: query.plan_id,
+            amount: query.amount,
+            discount: None,
+            discounted_amount: None,
+        };
+        let Some(row) = self.find(code).await? else {
+            validation.reason = Some("Unknown coupon".to_string());
+            return Ok(validation);
+        };
+        if layout == ProgramLayout::Fixed {
+            validation.reason = Some("This program charges a fixed amount, so coupons cannot be applied".to_string());
+            return Ok(validation);
+        }
+        if let Some(reason) = row.unusable(query.plan_id) {
+            validation.reason = Some(reason);
+            return Ok(validation);
+        }
+        let discount = row.discount(query.amount);
+        validation.valid = true;
+        validation.discount = Some(discount);
+        validation.discounted_amount = Some(query.amount - discount);
+        Ok(validation)
+    }
+}
+
+// Controllers
+/// Creates a percentage or fixed-lamport discount code for the merchant's plans.
+#[utoipa::path(
+    post,
+    path = "/api/v1/coupons",
+    tag = "coupons",
+    request_body = CouponRequest,
+    responses(
+        (status = 201, description = "Coupon created", body = Coupon),
+        (status = 403, description = "Merchant role required", body = ErrorResponse),
+        (status = 409, description = "Code already taken", body = ErrorResponse),
+        (status = 422, description = "Invalid request", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("")]
+pub async fn create_coupon(
+    req: HttpRequest,
+    coupons: web::Data<CouponService>,
+    body: ValidatedJson<CouponRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let coupon = coupons.create(&auth_token.public_key, body.into_inner()).await?;
+    Ok(HttpResponse::Created().json(coupon))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/coupons",
+    tag = "coupons",
+    responses((status = 200, description = "The merchant's coupons, newest first", body = [Coupon])),
+    security(("bearer_auth" = []))
+)]
+#[get("")]
+pub async fn list_coupons(req: HttpRequest, coupons: web::Data<CouponService>) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    Ok(HttpResponse::Ok().json(coupons.list(&auth_token.public_key).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/coupons/{code}",
+    tag = "coupons",
+    params(("code" = String, Path, description = "Coupon code")),
+    responses(
+        (status = 200, description = "Coupon", body = Coupon),
+        (status = 404, description = "No such coupon for this merchant", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/{code}")]
+pub async fn get_coupon(
+    req: HttpRequest,
+    path: web::Path<String>,
+    coupons: web::Data<CouponService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    Ok(HttpResponse::Ok().json(coupons.get(&auth_token.public_key, &path.into_inner()).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/coupons/{code}/disable",
+    tag = "coupons",
+    params(("code" = String, Path, description = "Coupon code")),
+    responses(
+        (status = 200, description = "Coupon disabled", body = Coupon),
+        (status = 404, description = "No such coupon for this merchant", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/{code}/disable")]
+pub async fn disable_coupon(
+    req: HttpRequest,
+    path: web::Path<String>,
+    coupons: web::Data<CouponService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    Ok(HttpResponse::Ok().json(coupons.disable(&auth_token.public_key, &path.into_inner()).await?))
+}
+
+/// Checks a code against a plan and the terms of the create it would apply to, for the
+/// checkout to call before building the subscribe transaction. Invalid codes answer `200`
+/// with `valid: false` and a `reason`.
+#[utoipa::path(
+    get,
+    path = "/api/v1/coupons/{code}/validate",
+    tag = "coupons",
+    params(("code" = String, Path, description = "Coupon code"), ValidateCouponQuery),
+    responses(
+        (status = 200, description = "Whether the coupon applies, and the discounted amount", body = CouponValidation),
+        (status = 422, description = "Invalid query", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/coupons/{code}/validate")]
+pub async fn validate_coupon(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: ValidatedQuery<ValidateCouponQuery>,
+    clusters: web::Data<SolanaClusters>,
+    coupons: web::Data<CouponService>,
+) -> AppResult<HttpResponse> {
+    let solana_service = clusters.select(&req)?;
+    let mut validation = coupons.validate(&path.into_inner(), &query, solana_service.layout).await?;
+    if let Some(amount) = validation.discounted_amount {
+        // The discounted create must still pass the operator's limits
+        let request = SubscriptionRequest { plan_id: query.plan_id, duration: query.duration, amount };
+        if let Err(AppError::BadRequest(reason)) = solana_service.check_terms(&request) {
+            validation.valid = false;
+            validation.reason = Some(reason);
+            validation.discount = None;
+            validation.discounted_amount = None;
+        }
+    }
+    Ok(HttpResponse::Ok().json(validation))
+}
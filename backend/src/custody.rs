@@ -0,0 +1,641 @@
+use actix_web::{get, post, web, HttpMessage, HttpRequest, HttpResponse};
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::system_instruction;
+use sqlx::postgres::PgPool;
+use std::str::FromStr;
+use std::sync::Arc;
+use utoipa::ToSchema;
+use validator::Validate;
+use zeroize::Zeroizing;
+use crate::audit;
+use crate::auth_monitor::AuthMonitor;
+use crate::cluster::SolanaClusters;
+use crate::db::{self, CustodialAccountRow};
+use crate::metrics;
+use crate::signer::AwsKms;
+use crate::validation::{validate_pubkey, ValidatedJson};
+use crate::{unix_now, AppError, AppResult, AuthService, AuthToken, SolanaService};
+
+pub const WITHDRAWAL_INSTRUCTION: &str = "withdrawal";
+// Network fee of a transfer signed by the wallet alone
+const WITHDRAWAL_FEE_LAMPORTS: u64 = 5000;
+
+// Verified against when an email has no account, so an unknown email takes as long as a wrong password
+static DUMMY_PASSWORD_HASH: Lazy<String> = Lazy::new(|| {
+    Argon2::default()
+        .hash_password(b"not a password", &SaltString::generate(&mut OsRng))
+        .expect("Failed to hash dummy password")
+        .to_string()
+});
+
+/// What wraps custodial wallets' data keys, from `CUSTODY_KEY_BACKEND`. Custody is off unless
+/// `CUSTODIAL_WALLETS_ENABLED` is set.
+#[derive(Clone)]
+pub enum CustodyConfig {
+    /// `CUSTODY_MASTER_KEY`, 32 bytes in base64, held by the backend itself.
+    Local { master_key: String },
+    /// A symmetric key in AWS KMS, with the same credentials as the AWS KMS signer.
+    AwsKms { key_id: String, region: String },
+}
+
+pub fn load_custody_config() -> Option<CustodyConfig> {
+    let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+    let required = |name: &str| var(name).unwrap_or_else(|| panic!("{} must be set", name));
+    if !var("CUSTODIAL_WALLETS_ENABLED").is_some_and(|v| v == "true" || v == "1") {
+        return None;
+    }
+    Some(match var("CUSTODY_KEY_BACKEND").as_deref().unwrap_or("local") {
+        "local" => CustodyConfig::Local { master_key: required("CUSTODY_MASTER_KEY") },
+        "aws_kms" => CustodyConfig::AwsKms {
+            key_id: required("CUSTODY_KMS_KEY_ID"),
+            region: var("AWS_REGION").or_else(|| var("AWS_DEFAULT_REGION")).expect("AWS_REGION must be set"),
+        },
+        other => panic!("Unknown CUSTODY_KEY_BACKEND {}; expected local or aws_kms", other),
+    })
+}
+
+// Models
+#[derive(Deserialize, Clone, ToSchema, Validate)]
+pub struct CustodialSignupRequest {
+    #[validate(email(message = "must be an email address"))]
+    email: String,
+    #[validate(length(min = 10, max = 128, message = "must be 10 to 128 characters"))]
+    password: String,
+}
+
+#[derive(Deserialize, Clone, ToSchema, Validate)]
+pub struct CustodialLoginRequest {
+    #[validate(length(min = 1, max = 320, message = "is required"))]
+    email: String,
+    #[validate(length(min = 1, max = 128, message = "is required"))]
+    password: String,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct CustodialAccount {
+    email: String,
+    wallet: String, // Fund it with SOL to pay for subscriptions
+    created_at: i64,
+    exported_at: Option<i64>,
+}
+
+#[derive(Deserialize, Clone, ToSchema, Validate)]
+pub struct ExportKeyRequest {
+    #[validate(length(min = 1, max = 128, message = "is required"))]
+    password: String,
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+pub struct ExportedKey {
+    wallet: String,
+    secret_key: String, // Base58, as wallet apps import it
+    keypair: Vec<u8>,   // As a `solana-keygen` JSON file holds it
+}
+
+#[derive(Deserialize, Clone, ToSchema, Validate)]
+pub struct WithdrawalRequest {
+    #[validate(length(min = 1, max = 128, message = "is required"))]
+    password: String,
+    #[validate(custom = "validate_pubkey")]
+    destination: String,
+    #[validate(range(min = 1, message = "must be a positive lamport amount"))]
+    lamports: Option<u64>, // Defaults to the whole balance, less the fee
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct WithdrawalResponse {
+    signature: String,
+    lamports: u64,
+}
+
+#[derive(Deserialize)]
+struct AwsDataKey {
+    #[serde(rename = "CiphertextBlob")]
+    ciphertext_blob: String,
+    #[serde(rename = "Plaintext")]
+    plaintext: String,
+}
+
+#[derive(Deserialize)]
+struct AwsPlaintext {
+    #[serde(rename = "Plaintext")]
+    plaintext: String,
+}
+
+enum KeyWrap {
+    Local(Box<Aes256Gcm>),
+    AwsKms { kms: AwsKms, key_id: String },
+}
+
+impl KeyWrap {
+    fn backend(&self) -> &'static str {
+        match self {
+            KeyWrap::Local(_) => "local",
+            KeyWrap::AwsKms { .. } => "aws_kms",
+        }
+    }
+
+    /// A new data key for `wallet`, in the clear and wrapped.
+    async fn generate(&self, wallet: &str) -> AppResult<(Zeroizing<Vec<u8>>, String)> {
+        match self {
+            KeyWrap::Local(master) => {
+                let data_key = Zeroizing::new(rand::random::<[u8; 32]>().to_vec());
+                let nonce: [u8; 12] = rand::random();
+                let wrapped = master
+                    .encrypt(Nonce::from_slice(&nonce), Payload { msg: &data_key, aad: wallet.as_bytes() })
+                    .map_err(|_| AppError::InternalServerError("Failed to wrap data key".to_string()))?;
+                Ok((data_key, BASE64.encode([&nonce[..], &wrapped[..]].concat())))
+            }
+            KeyWrap::AwsKms { kms, key_id } => {
+                let body = serde_json::json!({
+                    "KeyId": key_id,
+                    "KeySpec": "AES_256",
+                    "EncryptionContext": { "wallet": wallet },
+                });
+                let response: AwsDataKey = kms.call("GenerateDataKey", body).await?;
+                let data_key = Zeroizing::new(
+                    BASE64
+                        .decode(&response.plaintext)
+                        .map_err(|e| AppError::InternalServerError(format!("Invalid KMS data key: {}", e)))?,
+                );
+                Ok((data_key, response.ciphertext_blob))
+            }
+        }
+    }
+
+    async fn unwrap(&self, wallet: &str, wrapped: &str) -> AppResult<Zeroizing<Vec<u8>>> {
+        let corrupt = |e: &str| AppError::InternalServerError(format!("Data key of custodial wallet {} is unusable: {}", wallet, e));
+        match self {
+            KeyWrap::Local(master) => {
+                let wrapped = BASE64.decode(wrapped).map_err(|e| corrupt(&e.to_string()))?;
+                if wrapped.len() < 12 {
+                    return Err(corrupt("too short"));
+                }
+                let (nonce, ciphertext) = wrapped.split_at(12);
+                Ok(Zeroizing::new(
+                    master
+                        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: wallet.as_bytes() })
+                        .map_err(|_| corrupt("wrong master key or tampered"))?,
+                ))
+            }
+            KeyWrap::AwsKms { kms, key_id } => {
+                let body = serde_json::json!({
+                    "KeyId": key_id,
+                    "CiphertextBlob": wrapped,
+                    "EncryptionContext": { "wallet": wallet },
+                });
+                let response: AwsPlaintext = kms.call("Decrypt", body).await?;
+                Ok(Zeroizing::new(BASE64.decode(&response.plaintext).map_err(|e| corrupt(&e.to_string()))?))
+            }
+        }
+    }
+}
+
+fn data_cipher(data_key: &[u8]) -> AppResult<Aes256Gcm> {
+    Aes256Gcm::new_from_slice(data_key).map_err(|e| AppError::InternalServerError(format!("Invalid data key: {}", e)))
+}
+
+async fn hash_password(password: Zeroizing<String>) -> AppResult<String> {
+    tokio::task::spawn_blocking(move || {
+        Argon2::default()
+            .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+            .map(|hash| hash.to_string())
+            .map_err(|e| AppError::InternalServerError(format!("Failed to hash password: {}", e)))
+    })
+    .await
+    .map_err(|e| AppError::InternalServerError(format!("Password hashing panicked: {}", e)))?
+}
+
+async fn verify_password(hash: String, password: Zeroizing<String>) -> AppResult<bool> {
+    tokio::task::spawn_blocking(move || {
+        let hash = PasswordHash::new(&hash)
+            .map_err(|e| AppError::InternalServerError(format!("Invalid password hash: {}", e)))?;
+        Ok(Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+    })
+    .await
+    .map_err(|e| AppError::InternalServerError(format!("Password verification panicked: {}", e)))?
+}
+
+// Custody Service
+/// Wallets held by the backend for users who sign up with an email and password instead of
+/// connecting a wallet. Each gets a fresh keypair, encrypted with AES-256-GCM under its own data
+/// key, which is wrapped by `CUSTODY_MASTER_KEY` or an AWS KMS key; only the wrapped key and
+/// ciphertext are stored. The backend signs the wallet's subscription transactions with it, and
+/// the user can export the keypair or withdraw the wallet's SOL to take self-custody.
+#[derive(Clone)]
+pub struct CustodyService {
+    pool: PgPool,
+    wrap: Arc<KeyWrap>,
+}
+
+impl CustodyService {
+    pub fn new(config: &CustodyConfig, pool: PgPool) -> Result<Self, String> {
+        let wrap = match config {
+            CustodyConfig::Local { master_key } => {
+                let key = Zeroizing::new(
+                    BASE64.decode(master_key.trim()).map_err(|e| format!("Invalid CUSTODY_MASTER_KEY: {}", e))?,
+                );
+                if key.len() != 32 {
+                    return Err("CUSTODY_MASTER_KEY must be 32 bytes".to_string());
+                }
+                KeyWrap::Local(Box::new(Aes256Gcm::new_from_slice(&key).map_err(|e| e.to_string())?))
+            }
+            CustodyConfig::AwsKms { key_id, region } => {
+                KeyWrap::AwsKms { kms: AwsKms::from_env(region)?, key_id: key_id.clone() }
+            }
+        };
+        tracing::info!("Custodial wallets enabled, keys wrapped by {}", wrap.backend());
+        Ok(Self { pool, wrap: Arc::new(wrap) })
+    }
+
+    /// Creates an account with a new custodial wallet and returns the wallet.
+    pub async fn sign_up(&self, email: &str, password: &str) -> AppResult<String> {
+        let password_hash = hash_password(Zeroizing::new(password.to_string())).await?;
+        let row = self.seal(&Keypair::new(), email.trim().to_lowercase(), password_hash).await?;
+        if !db::insert_custodial_account(&self.pool, &row).await? {
+            return Err(AppError::Conflict("An account with this email already exists".to_string()));
+        }
+        tracing::info!("Created custodial wallet {}", row.wallet);
+        Ok(row.wallet)
+    }
+
+    /// The wallet of the account, if the password is right.
+    pub async fn log_in(&self, email: &str, password: &str) -> AppResult<String> {
+        let account = db::find_custodial_account_by_email(&self.pool, &email.trim().to_lowercase()).await?;
+        let hash = account.as_ref().map_or_else(|| DUMMY_PASSWORD_HASH.clone(), |a| a.password_hash.clone());
+        let valid = verify_password(hash, Zeroizing::new(password.to_string())).await?;
+        match account {
+            Some(account) if valid => Ok(account.wallet),
+            _ => Err(AppError::Auth("Invalid email or password".to_string())),
+        }
+    }
+
+    pub async fn account(&self, wallet: &str) -> AppResult<CustodialAccount> {
+        let row = self.find(wallet).await?;
+        Ok(CustodialAccount {
+            email: row.email,
+            wallet: row.wallet,
+            created_at: row.created_at,
+            exported_at: row.exported_at,
+        })
+    }
+
+    /// The keypair of `wallet` if it is custodial, for signing its transactions.
+    pub async fn keypair(&self, wallet: &Pubkey) -> AppResult<Option<Keypair>> {
+        match db::find_custodial_account_by_wallet(&self.pool, &wallet.to_string()).await? {
+            Some(row) => Ok(Some(self.open(&row).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Hands the keypair out after checking the password again. The backend keeps its copy, so
+    /// the wallet works both ways.
+    pub async fn export(&self, wallet: &str, password: &str) -> AppResult<ExportedKey> {
+        let row = self.authorize(wallet, password).await?;
+        let keypair = self.open(&row).await?;
+        db::mark_custodial_key_exported(&self.pool, wallet).await?;
+        tracing::warn!("Exported custodial keypair of {}", wallet);
+        Ok(ExportedKey {
+            wallet: row.wallet,
+            secret_key: keypair.to_base58_string(),
+            keypair: keypair.to_bytes().to_vec(),
+        })
+    }
+
+    /// Sends SOL from the wallet to `destination`, signed by the wallet alone.
+    pub async fn withdraw(&self, solana_service: &SolanaService, wallet: &str, req: &WithdrawalRequest) -> AppResult<WithdrawalResponse> {
+        let row = self.authorize(wallet, &req.password).await?;
+        let keypair = self.open(&row).await?;
+        let destination = Pubkey::from_str(&req.destination)
+            .map_err(|e| AppError::BadRequest(format!("Invalid destination: {}", e)))?;
+        let lamports = match req.lamports {
+            Some(lamports) => lamports,
+            None => {
+                let client = solana_service.rpc.client();
+                let balance = metrics::observe_rpc("getBalance", client.get_balance(&keypair.pubkey()))
+                    .await
+                    .map_err(|e| AppError::rpc("Failed to fetch wallet balance", e))?;
+                balance
+                    .checked_sub(WITHDRAWAL_FEE_LAMPORTS)
+                    .filter(|lamports| *lamports > 0)
+                    .ok_or_else(|| AppError::BadRequest("The wallet has nothing to withdraw".to_string()))?
+            }
+        };
+
+        let _guard = solana_service.lock_wallet(wallet).await?;
+        let transfer = system_instruction::transfer(&keypair.pubkey(), &destination, lamports);
+        let (mut tx, last_valid_block_height) = solana_service.unsigned_transaction(&keypair.pubkey(), &[transfer]).await?;
+        let recent_blockhash = tx.message.recent_blockhash;
+        tx.sign(&[&keypair], recent_blockhash);
+        let signature = solana_service
+            .send_signed_transaction(WITHDRAWAL_INSTRUCTION, &keypair.pubkey(), 0, Vec::new(), &tx, last_valid_block_height, None)
+            .await?;
+        tracing::info!("Withdrew {} lamports from custodial wallet {} to {}", lamports, wallet, destination);
+        Ok(WithdrawalResponse { signature, lamports })
+    }
+
+    async fn find(&self, wallet: &str) -> AppResult<CustodialAccountRow> {
+        db::find_custodial_account_by_wallet(&self.pool, wallet)
+            .await?
+            .ok_or_else(|| AppError::NotFound("This wallet is not custodial".to_string()))
+    }
+
+    async fn authorize(&self, wallet: &str, password: &str) -> AppResult<CustodialAccountRow> {
+        let row = self.find(wallet).await?;
+        if !verify_password(row.password_hash.clone(), Zeroizing::new(password.to_string())).await? {
+            return Err(AppError::Forbidden("Wrong password".to_string()));
+        }
+        Ok(row)
+    }
+
+    /// The account row of a new wallet, with its keypair encrypted under a fresh data key.
+    async fn seal(&self, keypair: &Keypair, email: String, password_hash: String) -> AppResult<CustodialAccountRow> {
+        let wallet = keypair.pubkey().to_string();
+        let (data_key, wrapped_key) = self.wrap.generate(&wallet).await?;
+        let nonce: [u8; 12] = rand::random();
+        let secret = Zeroizing::new(keypair.to_bytes());
+        let sealed_secret = data_cipher(&data_key)?
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: &secret[..], aad: wallet.as_bytes() })
+            .map_err(|_| AppError::InternalServerError("Failed to encrypt custodial keypair".to_string()))?;
+        Ok(CustodialAccountRow {
+            email,
+            password_hash,
+            wallet,
+            key_backend: self.wrap.backend().to_string(),
+            wrapped_key,
+            nonce: BASE64.encode(nonce),
+            sealed_secret: BASE64.encode(sealed_secret),
+            created_at: unix_now(),
+            exported_at: None,
+        })
+    }
+
+    async fn open(&self, row: &CustodialAccountRow) -> AppResult<Keypair> {
+        let corrupt = |e: &str| AppError::InternalServerError(format!("Custodial wallet {} is unusable: {}", row.wallet, e));
+        if row.key_backend != self.wrap.backend() {
+            return Err(corrupt(&format!("its key is wrapped by {}", row.key_backend)));
+        }
+        let data_key = self.wrap.unwrap(&row.wallet, &row.wrapped_key).await?;
+        let nonce = BASE64.decode(&row.nonce).map_err(|e| corrupt(&e.to_string()))?;
+        let sealed = BASE64.decode(&row.sealed_secret).map_err(|e| corrupt(&e.to_string()))?;
+        if nonce.len() != 12 {
+            return Err(corrupt("invalid nonce length"));
+        }
+        let secret = Zeroizing::new(
+            data_cipher(&data_key)?
+                .decrypt(Nonce::from_slice(&nonce), Payload { msg: &sealed, aad: row.wallet.as_bytes() })
+                .map_err(|_| corrupt("decryption failed"))?,
+        );
+        let keypair = Keypair::from_bytes(&secret).map_err(|_| corrupt("not a keypair"))?;
+        if keypair.pubkey().to_string() != row.wallet {
+            return Err(corrupt("does not match its wallet"));
+        }
+        Ok(keypair)
+    }
+}
+
+/// Custodial sign-up and sign-in, and the service for the custody routes; nothing is
+/// registered unless custody is enabled.
+pub fn auth_routes(cfg: &mut web::ServiceConfig, custody: Option<&CustodyService>) {
+    if let Some(custody) = custody {
+        cfg.app_data(web::Data::new(custody.clone())).service(custodial_signup).service(custodial_login);
+    }
+}
+
+/// Routes for the authenticated custodial wallet, under `/api/v1`.
+pub fn wallet_routes(cfg: &mut web::ServiceConfig, enabled: bool) {
+    if enabled {
+        cfg.service(get_custodial_account).service(export_custodial_key).service(withdraw_custodial);
+    }
+}
+
+// Controllers
+/// Creates an email and password account with a custodial wallet and signs in to it.
+#[utoipa::path(
+    post,
+    path = "/auth/custodial/signup",
+    tag = "auth",
+    request_body = CustodialSignupRequest,
+    responses(
+        (status = 201, description = "Access and refresh tokens for the new wallet", body = AuthResponse),
+        (status = 409, description = "Email already registered", body = ErrorResponse),
+        (status = 422, description = "Invalid email or password", body = ErrorResponse),
+    )
+)]
+#[post("/auth/custodial/signup")]
+pub async fn custodial_signup(
+    auth_service: web::Data<AuthService>,
+    custody: web::Data<CustodyService>,
+    req: ValidatedJson<CustodialSignupRequest>,
+) -> AppResult<HttpResponse> {
+    let wallet = custody.sign_up(&req.email, &req.password).await?;
+    let auth_response = auth_service.issue_tokens(&wallet, None).await?;
+    Ok(HttpResponse::Created().json(auth_response))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/custodial/login",
+    tag = "auth",
+    request_body = CustodialLoginRequest,
+    responses(
+        (status = 200, description = "Access and refresh tokens for the account's wallet", body = AuthResponse),
+        (status = 401, description = "Invalid email or password", body = ErrorResponse),
+    )
+)]
+#[post("/auth/custodial/login")]
+pub async fn custodial_login(
+    http_req: HttpRequest,
+    auth_service: web::Data<AuthService>,
+    auth_monitor: web::Data<AuthMonitor>,
+    custody: web::Data<CustodyService>,
+    req: ValidatedJson<CustodialLoginRequest>,
+) -> AppResult<HttpResponse> {
+    // Failures name no wallet, so they only count against the client IP
+    let result = custody.log_in(&req.email, &req.password).await;
+    auth_monitor.record("password", None, auth_monitor.client_ip(&http_req).as_deref(), &result);
+    let wallet = result?;
+    let auth_response = auth_service.issue_tokens(&wallet, None).await?;
+    Ok(HttpResponse::Ok().json(auth_response))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/custody",
+    tag = "custody",
+    responses(
+        (status = 200, description = "The authenticated custodial wallet's account", body = CustodialAccount),
+        (status = 404, description = "Wallet is not custodial", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/custody")]
+pub async fn get_custodial_account(req: HttpRequest, custody: web::Data<CustodyService>) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    Ok(HttpResponse::Ok().json(custody.account(&auth_token.public_key).await?))
+}
+
+/// Returns the custodial wallet's keypair for import into a self-custody wallet.
+#[utoipa::path(
+    post,
+    path = "/api/v1/custody/export",
+    tag = "custody",
+    request_body = ExportKeyRequest,
+    responses(
+        (status = 200, description = "The wallet's keypair", body = ExportedKey),
+        (status = 403, description = "Wrong password", body = ErrorResponse),
+        (status = 404, description = "Wallet is not custodial", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/custody/export")]
+pub async fn export_custodial_key(
+    req: HttpRequest,
+    custody: web::Data<CustodyService>,
+    body: ValidatedJson<ExportKeyRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let exported = custody.export(&auth_token.public_key, &body.password).await?;
+    Ok(HttpResponse::Ok().insert_header((actix_web::http::header::CACHE_CONTROL, "no-store")).json(exported))
+}
+
+/// Sends SOL from the custodial wallet to a self-custody wallet.
+#[utoipa::path(
+    post,
+    path = "/api/v1/custody/withdraw",
+    tag = "custody",
+    request_body = WithdrawalRequest,
+    responses(
+        (status = 200, description = "Confirmed withdrawal", body = WithdrawalResponse),
+        (status = 403, description = "Wrong password", body = ErrorResponse),
+        (status = 404, description = "Wallet is not custodial", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/custody/withdraw")]
+pub async fn withdraw_custodial(
+    req: HttpRequest,
+    custody: web::Data<CustodyService>,
+    clusters: web::Data<SolanaClusters>,
+    body: ValidatedJson<WithdrawalRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let solana_service = clusters.select(&req)?;
+    let withdrawal = custody.withdraw(solana_service, &auth_token.public_key, &body).await?;
+    audit::attach_signatures(&req, [&withdrawal.signature]);
+    Ok(HttpResponse::Ok().json(withdrawal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMAIL: &str = "user@example.com";
+
+    fn custody(master_key: [u8; 32]) -> CustodyService {
+        let config = CustodyConfig::Local { master_key: BASE64.encode(master_key) };
+        CustodyService::new(&config, PgPool::connect_lazy("postgres://localhost/unused").unwrap()).unwrap()
+    }
+
+    async fn sealed(custody: &CustodyService, keypair: &Keypair) -> CustodialAccountRow {
+        custody.seal(keypair, EMAIL.to_string(), "hash".to_string()).await.unwrap()
+    }
+
+    fn assert_unusable(result: AppResult<Keypair>) {
+        assert!(matches!(result, Err(AppError::InternalServerError(_))), "expected 500");
+    }
+
+    fn flip_first_byte(value: &str) -> String {
+        let mut bytes = BASE64.decode(value).unwrap();
+        bytes[0] ^= 1;
+        BASE64.encode(bytes)
+    }
+
+    #[actix_web::test]
+    async fn opens_what_it_sealed() {
+        let custody = custody([1; 32]);
+        let keypair = Keypair::new();
+        let row = sealed(&custody, &keypair).await;
+        assert_eq!(row.wallet, keypair.pubkey().to_string());
+        assert_eq!(row.key_backend, "local");
+        assert!(!row.sealed_secret.contains(&BASE64.encode(keypair.to_bytes())));
+        assert_eq!(custody.open(&row).await.unwrap().to_bytes(), keypair.to_bytes());
+    }
+
+    #[actix_web::test]
+    async fn each_wallet_gets_its_own_data_key() {
+        let custody = custody([1; 32]);
+        let first = sealed(&custody, &Keypair::new()).await;
+        let second = sealed(&custody, &Keypair::new()).await;
+        assert_ne!(first.wrapped_key, second.wrapped_key);
+        assert_ne!(first.nonce, second.nonce);
+    }
+
+    #[actix_web::test]
+    async fn rejects_tampered_secret() {
+        let custody = custody([1; 32]);
+        let mut row = sealed(&custody, &Keypair::new()).await;
+        row.sealed_secret = flip_first_byte(&row.sealed_secret);
+        assert_unusable(custody.open(&row).await);
+    }
+
+    #[actix_web::test]
+    async fn rejects_tampered_data_key() {
+        let custody = custody([1; 32]);
+        let mut row = sealed(&custody, &Keypair::new()).await;
+        row.wrapped_key = flip_first_byte(&row.wrapped_key);
+        assert_unusable(custody.open(&row).await);
+        row.wrapped_key = BASE64.encode([0u8; 8]);
+        assert_unusable(custody.open(&row).await);
+    }
+
+    #[actix_web::test]
+    async fn rejects_keys_moved_to_another_wallet() {
+        // The wallet is the associated data of both the data key and the secret
+        let custody = custody([1; 32]);
+        let mut row = sealed(&custody, &Keypair::new()).await;
+        let other = sealed(&custody, &Keypair::new()).await;
+        row.wrapped_key = other.wrapped_key;
+        assert_unusable(custody.open(&row).await);
+
+        let mut row = sealed(&custody, &Keypair::new()).await;
+        row.wallet = other.wallet;
+        assert_unusable(custody.open(&row).await);
+    }
+
+    #[actix_web::test]
+    async fn rejects_another_master_key_or_backend() {
+        let row = sealed(&custody([1; 32]), &Keypair::new()).await;
+        assert_unusable(custody([2; 32]).open(&row).await);
+
+        let custody = custody([1; 32]);
+        let mut row = row;
+        row.key_backend = "aws_kms".to_string();
+        assert_unusable(custody.open(&row).await);
+    }
+
+    #[actix_web::test]
+    async fn rejects_invalid_master_keys() {
+        let pool = PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        let short = CustodyConfig::Local { master_key: BASE64.encode([1u8; 16]) };
+        assert!(CustodyService::new(&short, pool.clone()).is_err());
+        let garbled = CustodyConfig::Local { master_key: "not base64!".to_string() };
+        assert!(CustodyService::new(&garbled, pool).is_err());
+    }
+
+    #[actix_web::test]
+    async fn verifies_passwords() {
+        let hash = hash_password(Zeroizing::new("a long password".to_string())).await.unwrap();
+        assert!(verify_password(hash.clone(), Zeroizing::new("a long password".to_string())).await.unwrap());
+        assert!(!verify_password(hash, Zeroizing::new("another password".to_string())).await.unwrap());
+    }
+}
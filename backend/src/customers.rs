@@ -0,0 +1,394 @@
+use actix_web::{delete, get, put, web, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+use crate::db;
+use crate::pii::{PiiVault, SEALED_PREFIX};
+use crate::tenant::tenant_of;
+use crate::validation::{validate_pubkey, FieldError, ValidatedJson, ValidatedQuery};
+use crate::{AppError, AppResult, AuthToken, Config};
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow, ToSchema)]
+pub struct Customer {
+    #[serde(skip_serializing)]
+    merchant: String,
+    #[serde(skip_serializing)]
+    tenant: String, // Whose PII key encrypts `email` and `display_name` at rest
+    wallet: String,
+    email: Option<String>,
+    display_name: Option<String>,
+    email_consent: bool,
+    consent_source: Option<String>,
+    consent_updated_at: Option<i64>, // When email_consent last changed
+    created_at: i64,
+    updated_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct CustomerRequest {
+    #[validate(email(message = "must be a valid email address"))]
+    email: Option<String>,
+    #[validate(length(min = 1, max = 200, message = "must be 1 to 200 characters"))]
+    display_name: Option<String>,
+    email_consent: bool, // Whether the customer agreed to be emailed
+    #[validate(length(min = 1, max = 100, message = "must be 1 to 100 characters"))]
+    consent_source: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct CustomersQuery {
+    email_consent: Option<bool>,
+    #[validate(range(min = 1, max = 1000, message = "must be between 1 and 1000"))]
+    limit: Option<i64>,
+    #[validate(range(min = 0, message = "must not be negative"))]
+    offset: Option<i64>,
+}
+
+/// A subscriber's directory entry, as listings and exports show it to the plan's merchant.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct CustomerContact {
+    email: Option<String>,
+    display_name: Option<String>,
+}
+
+impl CustomerContact {
+    pub fn email(&self) -> Option<&str> {
+        self.email.as_deref()
+    }
+
+    pub fn display_name(&self) -> Option<&str> {
+        self.display_name.as_deref()
+    }
+}
+
+/// Where a customer field is sealed, binding its ciphertext to the record.
+fn pii_context(merchant: &str, wallet: &str, field: &str) -> String {
+    format!("customers:{}:{}:{}", merchant, wallet, field)
+}
+
+/// The entry in `row`, decrypted.
+pub fn open_contact(vault: &PiiVault, row: db::CustomerContactRow) -> AppResult<CustomerContact> {
+    let context = |field: &str| pii_context(&row.merchant, &row.wallet, field);
+    Ok(CustomerContact {
+        email: vault.open_opt(&row.tenant, &context("email"), row.email.clone())?,
+        display_name: vault.open_opt(&row.tenant, &context("display_name"), row.display_name.clone())?,
+    })
+}
+
+/// Directory entries of `(wallet, plan_id)` pairs, looked up with the plans' merchants.
+pub async fn contacts(
+    pool: &PgPool,
+    vault: &PiiVault,
+    keys: &[(String, i64)],
+) -> AppResult<HashMap<(String, i64), CustomerContact>> {
+    if keys.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let mut wallets: Vec<String> = keys.iter().map(|(wallet, _)| wallet.clone()).collect();
+    let mut plan_ids: Vec<i64> = keys.iter().map(|(_, plan_id)| *plan_id).collect();
+    wallets.sort();
+    wallets.dedup();
+    plan_ids.sort();
+    plan_ids.dedup();
+    db::list_customer_contacts(pool, &wallets, &plan_ids)
+        .await?
+        .into_iter()
+        .map(|row| {
+            let key = (row.wallet.clone(), row.plan_id);
+            Ok((key, open_contact(vault, row)?))
+        })
+        .collect()
+}
+
+// Customer Service
+/// Each merchant's directory of its subscribers: an email and display name per wallet, with
+/// whether the customer agreed to be emailed. Entries enrich the merchant's subscriber
+/// listings and payment exports, and wallets that never set notification preferences get
+/// their emails at a consented address. Emails and display names are encrypted at rest
+/// under the tenant's PII key, when it has one.
+#[derive(Clone)]
+pub struct CustomerService {
+    pool: PgPool,
+    vault: PiiVault,
+}
+
+impl CustomerService {
+    pub fn new(config: &Config, pool: PgPool) -> Self {
+        Self { pool, vault: PiiVault::new(&config.tenants) }
+    }
+
+    pub async fn contacts(&self, keys: &[(String, i64)]) -> AppResult<HashMap<(String, i64), CustomerContact>> {
+        contacts(&self.pool, &self.vault, keys).await
+    }
+
+    fn open(&self, mut customer: Customer) -> AppResult<Customer> {
+        let email_context = pii_context(&customer.merchant, &customer.wallet, "email");
+        let name_context = pii_context(&customer.merchant, &customer.wallet, "display_name");
+        customer.email = self.vault.open_opt(&customer.tenant, &email_context, customer.email.take())?;
+        customer.display_name = self.vault.open_opt(&customer.tenant, &name_context, customer.display_name.take())?;
+        Ok(customer)
+    }
+
+    pub async fn upsert(&self, tenant: &str, merchant: &str, wallet: &str, req: CustomerRequest) -> AppResult<Customer> {
+        if req.email_consent && req.email.is_none() {
+            return Err(AppError::Validation(vec![FieldError::new(
+                "email",
+                "required",
+                "is required to record email consent",
+            )]));
+        }
+        let now = now();
+        let email = self.vault.seal_opt(
+            tenant,
+            &pii_context(merchant, wallet, "email"),
+            req.email.as_deref().map(str::trim),
+        )?;
+        let display_name = self.vault.seal_opt(
+            tenant,
+            &pii_context(merchant, wallet, "display_name"),
+            req.display_name.as_deref().map(str::trim),
+        )?;
+        let customer = sqlx::query_as::<_, Customer>(
+            "INSERT INTO customers
+                (merchant, wallet, email, display_name, email_consent, consent_source, consent_updated_at, created_at,
+                 updated_at, tenant)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $7, $7, $8)
+             ON CONFLICT (merchant, wallet) DO UPDATE SET
+                tenant = EXCLUDED.tenant,
+                email = EXCLUDED.email,
+                display_name = EXCLUDED.display_name,
+                email_consent = EXCLUDED.email_consent,
+                consent_source = EXCLUDED.consent_source,
+                consent_updated_at = CASE
+                    WHEN customers.email_consent = EXCLUDED.email_consent THEN customers.consent_updated_at
+                    ELSE EXCLUDED.consent_updated_at
+                END,
+                updated_at = EXCLUDED.updated_at
+             RETURNING *",
+        )
+        .bind(merchant)
+        .bind(wallet)
+        .bind(email)
+        .bind(display_name)
+        .bind(req.email_consent)
+        .bind(req.consent_source.as_deref())
+        .bind(now)
+        .bind(tenant)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to save customer: {}", e)))?;
+        self.open(customer)
+    }
+
+    pub async fn get(&self, merchant: &str, wallet: &str) -> AppResult<Customer> {
+        let customer = sqlx::query_as::<_, Customer>("SELECT * FROM customers WHERE merchant = $1 AND wallet = $2")
+            .bind(merchant)
+            .bind(wallet)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to fetch customer: {}", e)))?
+            .ok_or_else(|| AppError::NotFound(format!("No customer record for {}", wallet)))?;
+        self.open(customer)
+    }
+
+    pub async fn list(&self, merchant: &str, query: &CustomersQuery) -> AppResult<Vec<Customer>> {
+        let customers = sqlx::query_as::<_, Customer>(
+            "SELECT * FROM customers
+             WHERE merchant = $1 AND ($2::BOOLEAN IS NULL OR email_consent = $2)
+             ORDER BY created_at DESC, wallet
+             LIMIT $3 OFFSET $4",
+        )
+        .bind(merchant)
+        .bind(query.email_consent)
+        .bind(query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE))
+        .bind(query.offset.unwrap_or(0))
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to list customers: {}", e)))?;
+        customers.into_iter().map(|customer| self.open(customer)).collect()
+    }
+
+    /// Erases what the merchant holds on a customer who asked to be forgotten: the directory
+    /// entry, and the merchant's stored exports carrying its email or display name, which can
+    /// be run again without them. Subscriptions and payments keyed by the wallet are kept;
+    /// they mirror the chain, which keeps them regardless.
+    pub async fn erase(&self, merchant: &str, wallet: &str) -> AppResult<()> {
+        let mut tx = self.pool.begin().await.map_err(|e| AppError::DatabaseError(format!("Failed to start transaction: {}", e)))?;
+        let customer = sqlx::query_as::<_, Customer>("DELETE FROM customers WHERE merchant = $1 AND wallet = $2 RETURNING *")
+            .bind(merchant)
+            .bind(wallet)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to delete customer: {}", e)))?
+            .ok_or_else(|| AppError::NotFound(format!("No customer record for {}", wallet)))?;
+        let customer = self.open(customer)?;
+        let details: Vec<&str> = [customer.email.as_deref(), customer.display_name.as_deref()].into_iter().flatten().collect();
+        let exports = sqlx::query(
+            "DELETE FROM payment_exports
+             WHERE requested_by = $1 AND content IS NOT NULL AND strpos(content, $2) > 0
+               AND EXISTS (SELECT 1 FROM unnest($3::TEXT[]) AS detail WHERE strpos(content, detail) > 0)",
+        )
+        .bind(merchant)
+        .bind(wallet)
+        .bind(&details)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to delete exports: {}", e)))?
+        .rows_affected();
+        tx.commit().await.map_err(|e| AppError::DatabaseError(format!("Failed to commit transaction: {}", e)))?;
+        tracing::info!("Erased the customer record of {} held by {}, with {} exports", wallet, merchant, exports);
+        Ok(())
+    }
+
+    /// Encrypts details stored in the clear, such as those saved before the tenant had a PII
+    /// key. Rows changed in the meantime are left for the next run.
+    pub async fn seal_existing(&self) -> AppResult<u64> {
+        let pattern = format!("{}%", SEALED_PREFIX);
+        let rows = sqlx::query_as::<_, Customer>(
+            "SELECT * FROM customers
+             WHERE (email IS NOT NULL AND email NOT LIKE $1) OR (display_name IS NOT NULL AND display_name NOT LIKE $1)",
+        )
+        .bind(&pattern)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to list unencrypted customers: {}", e)))?;
+
+        let mut sealed = 0;
+        for row in rows.into_iter().filter(|row| self.vault.is_enabled(&row.tenant)) {
+            let context = |field: &str| pii_context(&row.merchant, &row.wallet, field);
+            let reseal = |field: &str, value: Option<&str>| match value {
+                Some(value) if !value.starts_with(SEALED_PREFIX) => self.vault.seal(&row.tenant, &context(field), value).map(Some),
+                value => Ok(value.map(str::to_string)),
+            };
+            let email = reseal("email", row.email.as_deref())?;
+            let display_name = reseal("display_name", row.display_name.as_deref())?;
+            let updated = sqlx::query(
+                "UPDATE customers SET email = $3, display_name = $4
+                 WHERE merchant = $1 AND wallet = $2 AND updated_at = $5 AND tenant = $6",
+            )
+            .bind(&row.merchant)
+            .bind(&row.wallet)
+            .bind(email)
+            .bind(display_name)
+            .bind(row.updated_at)
+            .bind(&row.tenant)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to encrypt customer: {}", e)))?;
+            sealed += updated.rows_affected();
+        }
+        Ok(sealed)
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+fn wallet_path(path: web::Path<String>) -> AppResult<String> {
+    let wallet = path.into_inner();
+    validate_pubkey(&wallet).map_err(|_| AppError::BadRequest(format!("Invalid wallet {}", wallet)))?;
+    Ok(wallet)
+}
+
+// Controllers
+/// Creates or replaces the caller's directory entry for a wallet.
+#[utoipa::path(
+    put,
+    path = "/api/v1/customers/{wallet}",
+    tag = "customers",
+    params(("wallet" = String, Path, description = "Subscriber wallet")),
+    request_body = CustomerRequest,
+    responses(
+        (status = 200, description = "Customer saved", body = Customer),
+        (status = 403, description = "Merchant role required", body = ErrorResponse),
+        (status = 422, description = "Invalid email, or consent recorded without one", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []), ("api_key" = []))
+)]
+#[put("/{wallet}")]
+pub async fn upsert_customer(
+    req: HttpRequest,
+    path: web::Path<String>,
+    customers: web::Data<CustomerService>,
+    body: ValidatedJson<CustomerRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let wallet = wallet_path(path)?;
+    let tenant = tenant_of(&req)?;
+    let customer = customers.upsert(&tenant, &auth_token.public_key, &wallet, body.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(customer))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/customers",
+    tag = "customers",
+    params(CustomersQuery),
+    responses(
+        (status = 200, description = "The caller's customers, newest first", body = [Customer]),
+        (status = 403, description = "Merchant role required", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []), ("api_key" = []))
+)]
+#[get("")]
+pub async fn list_customers(
+    req: HttpRequest,
+    query: ValidatedQuery<CustomersQuery>,
+    customers: web::Data<CustomerService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    Ok(HttpResponse::Ok().json(customers.list(&auth_token.public_key, &query).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/customers/{wallet}",
+    tag = "customers",
+    params(("wallet" = String, Path, description = "Subscriber wallet")),
+    responses(
+        (status = 200, description = "Customer", body = Customer),
+        (status = 404, description = "No record for the wallet", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []), ("api_key" = []))
+)]
+#[get("/{wallet}")]
+pub async fn get_customer(
+    req: HttpRequest,
+    path: web::Path<String>,
+    customers: web::Data<CustomerService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let wallet = wallet_path(path)?;
+    Ok(HttpResponse::Ok().json(customers.get(&auth_token.public_key, &wallet).await?))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/customers/{wallet}",
+    tag = "customers",
+    params(("wallet" = String, Path, description = "Subscriber wallet")),
+    responses(
+        (status = 204, description = "Customer record and the caller's exports carrying its details erased"),
+        (status = 404, description = "No record for the wallet", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []), ("api_key" = []))
+)]
+#[delete("/{wallet}")]
+pub async fn delete_customer(
+    req: HttpRequest,
+    path: web::Path<String>,
+    customers: web::Data<CustomerService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let wallet = wallet_path(path)?;
+    customers.erase(&auth_token.public_key, &wallet).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,2509 @@
+use serde::Serialize;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+use crate::schema;
+use crate::{AppError, AppResult, Config, SubscriptionResponse};
+
+/// Connects and brings the schema up to date, or with `DATABASE_AUTO_MIGRATE=false` only
+/// checks that it is.
+pub async fn connect(config: &Config) -> AppResult<PgPool> {
+    let pool = open(config).await?;
+    if config.database_auto_migrate {
+        schema::migrate(&pool).await?;
+    } else {
+        let status = schema::check(&pool).await?;
+        tracing::info!("Database schema at version {}", status.version());
+    }
+    Ok(pool)
+}
+
+/// Connects without touching the schema.
+pub async fn open(config: &Config) -> AppResult<PgPool> {
+    PgPoolOptions::new()
+        .max_connections(config.database_max_connections)
+        .connect(&config.database_url)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to connect: {}", e)))
+}
+
+// Rows
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct SubscriptionRow {
+    pub pda: String,
+    pub owner: String,
+    pub plan_id: i64,
+    pub start_time: i64,
+    pub duration: i64,
+    pub amount: i64,
+    pub active: bool,
+    pub closed: bool,
+    pub history: Vec<i64>,
+    pub updated_slot: i64,
+}
+
+impl From<SubscriptionRow> for SubscriptionResponse {
+    fn from(row: SubscriptionRow) -> Self {
+        SubscriptionResponse {
+            id: row.pda,
+            plan_id: row.plan_id as u64,
+            duration: row.duration as u64,
+            amount: row.amount as u64,
+            active: row.active,
+            start_time: row.start_time,
+            history: row.history,
+            owner: row.owner,
+        }
+    }
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct PaymentRow {
+    pub signature: String,
+    pub instruction_index: i32,
+    pub pda: String,
+    pub owner: String,
+    pub plan_id: i64,
+    pub amount: i64,
+    pub kind: String,
+    pub slot: i64,
+    pub block_time: Option<i64>,
+    pub refunded_lamports: i64, // Returned through refunds; 0 when inserted
+    pub sol_usd: Option<f64>, // When indexed as it landed; None for backfilled payments
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+#[allow(dead_code)]
+pub struct RefreshTokenRow {
+    pub token_hash: String,
+    pub public_key: String,
+    pub expires_at: i64,
+    pub revoked: bool,
+    pub created_at: i64,
+    pub tenant: Option<String>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct EventRow {
+    pub signature: String,
+    pub instruction_index: i32,
+    pub pda: String,
+    pub kind: String,
+    pub slot: i64,
+    pub block_time: Option<i64>,
+}
+
+// Subscriptions
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn upsert_subscription(pool: &PgPool, row: &SubscriptionRow) -> AppResult<()> {
+    sqlx::query(
+        "INSERT INTO subscriptions
+            (pda, owner, plan_id, start_time, duration, amount, active, closed, history, updated_slot)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+         ON CONFLICT (pda) DO UPDATE SET
+            start_time = EXCLUDED.start_time,
+            duration = EXCLUDED.duration,
+            amount = EXCLUDED.amount,
+            active = EXCLUDED.active,
+            closed = EXCLUDED.closed,
+            history = EXCLUDED.history,
+            updated_slot = EXCLUDED.updated_slot,
+            commitment = 'confirmed'
+         WHERE subscriptions.updated_slot <= EXCLUDED.updated_slot",
+    )
+    .bind(&row.pda)
+    .bind(&row.owner)
+    .bind(row.plan_id)
+    .bind(row.start_time)
+    .bind(row.duration)
+    .bind(row.amount)
+    .bind(row.active)
+    .bind(row.closed)
+    .bind(&row.history)
+    .bind(row.updated_slot)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to upsert subscription: {}", e)))?;
+    Ok(())
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn mark_subscription_closed(pool: &PgPool, pda: &str, slot: i64) -> AppResult<()> {
+    sqlx::query(
+        "UPDATE subscriptions SET active = FALSE, closed = TRUE, updated_slot = $2, commitment = 'confirmed'
+         WHERE pda = $1 AND updated_slot <= $2",
+    )
+    .bind(pda)
+    .bind(slot)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to close subscription: {}", e)))?;
+    Ok(())
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn find_subscription(pool: &PgPool, pda: &str) -> AppResult<Option<SubscriptionRow>> {
+    sqlx::query_as::<_, SubscriptionRow>("SELECT * FROM subscriptions WHERE pda = $1")
+        .bind(pda)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to fetch subscription: {}", e)))
+}
+
+/// Whether any subscription or payment has been indexed for a plan id, i.e. someone already
+/// uses it on chain.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn plan_has_activity(pool: &PgPool, plan_id: i64) -> AppResult<bool> {
+    sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM subscriptions WHERE plan_id = $1)
+             OR EXISTS (SELECT 1 FROM payments WHERE plan_id = $1)",
+    )
+    .bind(plan_id)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to check plan activity: {}", e)))
+}
+
+/// What `GET /subscriptions/{plan_id}` validators are derived from, read without the full row.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct SubscriptionVersionRow {
+    pub updated_slot: i64,
+    pub active: bool,
+    pub closed: bool,
+    pub payments: i32, // Length of the history array
+    pub last_event_time: Option<i64>,
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn find_subscription_version(pool: &PgPool, pda: &str) -> AppResult<Option<SubscriptionVersionRow>> {
+    sqlx::query_as::<_, SubscriptionVersionRow>(
+        "SELECT updated_slot, active, closed, COALESCE(cardinality(history), 0) AS payments,
+                (SELECT MAX(block_time) FROM events WHERE events.pda = subscriptions.pda) AS last_event_time
+         FROM subscriptions WHERE pda = $1",
+    )
+    .bind(pda)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to fetch subscription version: {}", e)))
+}
+
+/// Aggregate version of the open subscriptions of an account's wallets, for `GET /subscriptions`.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct OwnerSubscriptionsVersionRow {
+    pub count: i64,
+    pub max_slot: i64,
+    pub slot_sum: i64,
+    pub payments: i64,
+    pub active: i64,
+    pub last_event_time: Option<i64>,
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn owner_subscriptions_version(pool: &PgPool, owners: &[String]) -> AppResult<OwnerSubscriptionsVersionRow> {
+    sqlx::query_as::<_, OwnerSubscriptionsVersionRow>(
+        "SELECT COUNT(*) AS count,
+                COALESCE(MAX(updated_slot), 0)::BIGINT AS max_slot,
+                COALESCE(SUM(updated_slot), 0)::BIGINT AS slot_sum,
+                COALESCE(SUM(cardinality(history)), 0)::BIGINT AS payments,
+                COUNT(*) FILTER (WHERE active) AS active,
+                (SELECT MAX(e.block_time) FROM events e JOIN subscriptions s ON s.pda = e.pda
+                 WHERE s.owner = ANY($1) AND NOT s.closed) AS last_event_time
+         FROM subscriptions WHERE owner = ANY($1) AND NOT closed",
+    )
+    .bind(owners)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to fetch subscriptions version: {}", e)))
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_subscriptions_by_owners(pool: &PgPool, owners: &[String]) -> AppResult<Vec<SubscriptionRow>> {
+    sqlx::query_as::<_, SubscriptionRow>(
+        "SELECT * FROM subscriptions WHERE owner = ANY($1) AND NOT closed ORDER BY plan_id, owner",
+    )
+    .bind(owners)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list subscriptions: {}", e)))
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_subscriptions(
+    pool: &PgPool,
+    plan_ids: Option<&[i64]>, // None for every plan
+    active: Option<bool>,
+    limit: i64,
+    offset: i64,
+) -> AppResult<Vec<SubscriptionRow>> {
+    sqlx::query_as::<_, SubscriptionRow>(
+        "SELECT * FROM subscriptions
+         WHERE NOT closed
+           AND ($1::BIGINT[] IS NULL OR plan_id = ANY($1))
+           AND ($2::BOOLEAN IS NULL OR active = $2)
+         ORDER BY plan_id, owner
+         LIMIT $3 OFFSET $4",
+    )
+    .bind(plan_ids)
+    .bind(active)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list subscriptions: {}", e)))
+}
+
+// Payments and events
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn insert_payment(pool: &PgPool, row: &PaymentRow) -> AppResult<()> {
+    sqlx::query(
+        "INSERT INTO payments
+            (signature, instruction_index, pda, owner, plan_id, amount, kind, slot, block_time, sol_usd)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+         ON CONFLICT (signature, instruction_index) DO NOTHING",
+    )
+    .bind(&row.signature)
+    .bind(row.instruction_index)
+    .bind(&row.pda)
+    .bind(&row.owner)
+    .bind(row.plan_id)
+    .bind(row.amount)
+    .bind(&row.kind)
+    .bind(row.slot)
+    .bind(row.block_time)
+    .bind(row.sol_usd)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to insert payment: {}", e)))?;
+    Ok(())
+}
+
+/// One page of payments in chain order, after the `(slot, signature, instruction_index)` cursor.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_payments_page(
+    pool: &PgPool,
+    pda: Option<&str>,
+    plan_ids: Option<&[i64]>, // None for every plan
+    from: Option<i64>,
+    to: Option<i64>,
+    after: Option<&(i64, String, i32)>,
+    limit: i64,
+) -> AppResult<Vec<PaymentRow>> {
+    sqlx::query_as::<_, PaymentRow>(
+        "SELECT signature, instruction_index, pda, owner, plan_id, amount, kind, slot, block_time, refunded_lamports, sol_usd
+         FROM payments
+         WHERE ($1::TEXT IS NULL OR pda = $1)
+           AND ($2::BIGINT[] IS NULL OR plan_id = ANY($2))
+           AND ($3::BIGINT IS NULL OR block_time >= $3)
+           AND ($4::BIGINT IS NULL OR block_time < $4)
+           AND ($5::BIGINT IS NULL OR (slot, signature, instruction_index) > ($5, $6, $7))
+         ORDER BY slot, signature, instruction_index
+         LIMIT $8",
+    )
+    .bind(pda)
+    .bind(plan_ids)
+    .bind(from)
+    .bind(to)
+    .bind(after.map(|a| a.0))
+    .bind(after.map(|a| a.1.as_str()))
+    .bind(after.map(|a| a.2))
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list payments: {}", e)))
+}
+
+/// The payments made by one transaction, in instruction order.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_payments_by_signature(pool: &PgPool, signature: &str) -> AppResult<Vec<PaymentRow>> {
+    sqlx::query_as::<_, PaymentRow>(
+        "SELECT signature, instruction_index, pda, owner, plan_id, amount, kind, slot, block_time, refunded_lamports, sol_usd
+         FROM payments
+         WHERE signature = $1
+         ORDER BY instruction_index",
+    )
+    .bind(signature)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to fetch payments: {}", e)))
+}
+
+/// Address of every indexed subscription, closed ones included.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_subscription_pdas(pool: &PgPool) -> AppResult<Vec<String>> {
+    sqlx::query_scalar("SELECT pda FROM subscriptions ORDER BY pda")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to list subscriptions: {}", e)))
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn find_subscriptions(pool: &PgPool, pdas: &[String]) -> AppResult<Vec<SubscriptionRow>> {
+    sqlx::query_as::<_, SubscriptionRow>("SELECT * FROM subscriptions WHERE pda = ANY($1)")
+        .bind(pdas)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to fetch subscriptions: {}", e)))
+}
+
+/// Every payment of the given subscriptions, newest first.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_payments_for(pool: &PgPool, pdas: &[String]) -> AppResult<Vec<PaymentRow>> {
+    sqlx::query_as::<_, PaymentRow>(
+        "SELECT signature, instruction_index, pda, owner, plan_id, amount, kind, slot, block_time, refunded_lamports, sol_usd
+         FROM payments
+         WHERE pda = ANY($1)
+         ORDER BY slot DESC, signature DESC, instruction_index DESC",
+    )
+    .bind(pdas)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list payments: {}", e)))
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct PlanStatsRow {
+    pub plan_id: i64,
+    pub subscriptions: i64, // Not closed
+    pub active_subscriptions: i64, // Active and paid up
+    pub revenue_lamports: i64,
+}
+
+/// Per-plan totals for `plan_ids`, or every plan with an indexed subscription.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_plan_stats(pool: &PgPool, plan_ids: Option<&[i64]>) -> AppResult<Vec<PlanStatsRow>> {
+    sqlx::query_as::<_, PlanStatsRow>(
+        "SELECT s.plan_id,
+                COUNT(*) FILTER (WHERE NOT s.closed) AS subscriptions,
+                COUNT(*) FILTER (WHERE s.active AND NOT s.closed AND s.start_time + s.duration > $2) AS active_subscriptions,
+                COALESCE((SELECT SUM(p.amount) FROM payments p WHERE p.plan_id = s.plan_id), 0)::BIGINT AS revenue_lamports
+         FROM subscriptions s
+         WHERE ($1::BIGINT[] IS NULL OR s.plan_id = ANY($1))
+         GROUP BY s.plan_id
+         ORDER BY s.plan_id",
+    )
+    .bind(plan_ids)
+    .bind(now())
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to compute plan totals: {}", e)))
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn insert_event(pool: &PgPool, row: &EventRow) -> AppResult<()> {
+    sqlx::query(
+        "INSERT INTO events (signature, instruction_index, pda, kind, slot, block_time)
+         VALUES ($1, $2, $3, $4, $5, $6)
+         ON CONFLICT (signature, instruction_index) DO NOTHING",
+    )
+    .bind(&row.signature)
+    .bind(row.instruction_index)
+    .bind(&row.pda)
+    .bind(&row.kind)
+    .bind(row.slot)
+    .bind(row.block_time)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to insert event: {}", e)))?;
+    Ok(())
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn is_transaction_indexed(pool: &PgPool, signature: &str) -> AppResult<bool> {
+    sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM events WHERE signature = $1)")
+        .bind(signature)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to check transaction: {}", e)))
+}
+
+// Commitment tracking
+/// Signatures with rows not yet finalized, oldest first, with the slot they were indexed at.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn unfinalized_signatures(pool: &PgPool, limit: i64) -> AppResult<Vec<(String, i64)>> {
+    sqlx::query_as::<_, (String, i64)>(
+        "SELECT signature, MIN(slot) AS slot FROM events
+         WHERE commitment <> 'finalized'
+         GROUP BY signature
+         ORDER BY slot
+         LIMIT $1",
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list unfinalized signatures: {}", e)))
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn mark_finalized(pool: &PgPool, signatures: &[String]) -> AppResult<()> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to begin transaction: {}", e)))?;
+
+    sqlx::query("UPDATE payments SET commitment = 'finalized' WHERE signature = ANY($1)")
+        .bind(signatures)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to finalize payments: {}", e)))?;
+    sqlx::query("UPDATE events SET commitment = 'finalized' WHERE signature = ANY($1)")
+        .bind(signatures)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to finalize events: {}", e)))?;
+    // A subscription is final once nothing that touched it is still pending
+    sqlx::query(
+        "UPDATE subscriptions SET commitment = 'finalized'
+         WHERE pda IN (SELECT pda FROM events WHERE signature = ANY($1))
+           AND NOT EXISTS (
+               SELECT 1 FROM events e WHERE e.pda = subscriptions.pda AND e.commitment <> 'finalized'
+           )",
+    )
+    .bind(signatures)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to finalize subscriptions: {}", e)))?;
+
+    tx.commit()
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to commit transaction: {}", e)))
+}
+
+/// Removes every row written for a transaction that was dropped by a fork, restores what a
+/// close in it archived, and returns the subscription PDAs it touched so they can be re-read
+/// from chain.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn rollback_transaction(pool: &PgPool, signature: &str) -> AppResult<Vec<String>> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to begin transaction: {}", e)))?;
+
+    sqlx::query("DELETE FROM payments WHERE signature = $1")
+        .bind(signature)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to roll back payments: {}", e)))?;
+    let pdas = sqlx::query_scalar::<_, String>("DELETE FROM events WHERE signature = $1 RETURNING pda")
+        .bind(signature)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to roll back events: {}", e)))?;
+    // A dropped close puts back what its cleanup archived; cancelled intents stay cancelled
+    sqlx::query(
+        "INSERT INTO subscription_reminders (pda, milestone, period_end, created_at)
+         SELECT a.pda, r.milestone, r.period_end, r.created_at
+         FROM subscription_archives a,
+              jsonb_to_recordset(a.reminders) AS r(milestone TEXT, period_end BIGINT, created_at BIGINT)
+         WHERE a.signature = $1
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(signature)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to restore subscription reminders: {}", e)))?;
+    sqlx::query(
+        "WITH restored AS (
+             DELETE FROM subscription_archives WHERE signature = $1 RETURNING pda, auto_renew, archived_at
+         )
+         INSERT INTO subscription_settings (pda, auto_renew, updated_at)
+         SELECT pda, auto_renew, archived_at FROM restored WHERE auto_renew IS NOT NULL
+         ON CONFLICT (pda) DO NOTHING",
+    )
+    .bind(signature)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to restore subscription settings: {}", e)))?;
+
+    tx.commit()
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to commit transaction: {}", e)))?;
+    Ok(pdas)
+}
+
+/// Runs the cleanup of a subscription whose close the indexer observed, in one transaction:
+/// payment intents for it still awaiting a signature are cancelled, and its settings (and so
+/// any keeper auto-renewal) and reminder history are moved to `subscription_archives` along
+/// with a snapshot of the row. Returns `None` if this close was already archived.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn archive_closed_subscription(
+    pool: &PgPool,
+    row: &SubscriptionRow,
+    signature: &str,
+    slot: i64,
+    cluster: &str,
+    tenant: &str,
+) -> AppResult<Option<u64>> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to begin transaction: {}", e)))?;
+
+    let archived = sqlx::query(
+        "INSERT INTO subscription_archives
+            (pda, signature, owner, plan_id, start_time, duration, amount, history, auto_renew, reminders, slot,
+             archived_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8,
+                 (SELECT auto_renew FROM subscription_settings WHERE pda = $1),
+                 (SELECT COALESCE(jsonb_agg(jsonb_build_object(
+                      'milestone', milestone, 'period_end', period_end, 'created_at', created_at
+                  )), '[]') FROM subscription_reminders WHERE pda = $1),
+                 $9, $10)
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(&row.pda)
+    .bind(signature)
+    .bind(&row.owner)
+    .bind(row.plan_id)
+    .bind(row.start_time)
+    .bind(row.duration)
+    .bind(row.amount)
+    .bind(&row.history)
+    .bind(slot)
+    .bind(now())
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to archive subscription: {}", e)))?
+    .rows_affected();
+    if archived == 0 {
+        return Ok(None);
+    }
+
+    let intents = sqlx::query(
+        "UPDATE payment_intents SET status = 'cancelled', error = $5, updated_at = $6
+         WHERE owner = $1 AND plan_id = $2 AND cluster = $3 AND tenant = $4 AND status = 'requires_signature'",
+    )
+    .bind(&row.owner)
+    .bind(row.plan_id)
+    .bind(cluster)
+    .bind(tenant)
+    .bind("Subscription was closed")
+    .bind(now())
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to cancel payment intents: {}", e)))?
+    .rows_affected();
+    sqlx::query("UPDATE subscription_archives SET intents_cancelled = $3 WHERE pda = $1 AND signature = $2")
+        .bind(&row.pda)
+        .bind(signature)
+        .bind(intents as i32)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to archive subscription: {}", e)))?;
+    sqlx::query("DELETE FROM subscription_settings WHERE pda = $1")
+        .bind(&row.pda)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to archive subscription settings: {}", e)))?;
+    sqlx::query("DELETE FROM subscription_reminders WHERE pda = $1")
+        .bind(&row.pda)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to archive subscription reminders: {}", e)))?;
+
+    tx.commit()
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to commit transaction: {}", e)))?;
+    Ok(Some(intents))
+}
+
+// Indexer cursor
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn last_indexed_signature(pool: &PgPool) -> AppResult<Option<String>> {
+    sqlx::query_scalar::<_, Option<String>>("SELECT last_signature FROM indexer_state WHERE id = 1")
+        .fetch_optional(pool)
+        .await
+        .map(Option::flatten)
+        .map_err(|e| AppError::DatabaseError(format!("Failed to read indexer state: {}", e)))
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn set_last_indexed_signature(pool: &PgPool, signature: &str, slot: i64) -> AppResult<()> {
+    sqlx::query(
+        "UPDATE indexer_state SET last_signature = $1, last_slot = $2
+         WHERE id = 1 AND last_slot <= $2",
+    )
+    .bind(signature)
+    .bind(slot)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to update indexer state: {}", e)))?;
+    Ok(())
+}
+
+// Auth tokens
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn insert_refresh_token(
+    pool: &PgPool,
+    token_hash: &str,
+    public_key: &str,
+    tenant: Option<&str>,
+    expires_at: i64,
+) -> AppResult<()> {
+    sqlx::query(
+        "INSERT INTO refresh_tokens (token_hash, public_key, tenant, expires_at, created_at)
+         VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(token_hash)
+    .bind(public_key)
+    .bind(tenant)
+    .bind(expires_at)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to store refresh token: {}", e)))?;
+    Ok(())
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn find_refresh_token(pool: &PgPool, token_hash: &str) -> AppResult<Option<RefreshTokenRow>> {
+    sqlx::query_as::<_, RefreshTokenRow>("SELECT * FROM refresh_tokens WHERE token_hash = $1")
+        .bind(token_hash)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to fetch refresh token: {}", e)))
+}
+
+/// Revokes a refresh token. Returns false if it was already revoked, or does not exist.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn revoke_refresh_token(pool: &PgPool, token_hash: &str) -> AppResult<bool> {
+    let result = sqlx::query("UPDATE refresh_tokens SET revoked = TRUE WHERE token_hash = $1 AND NOT revoked")
+        .bind(token_hash)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to revoke refresh token: {}", e)))?;
+    Ok(result.rows_affected() == 1)
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn revoke_refresh_tokens_for(pool: &PgPool, public_key: &str) -> AppResult<()> {
+    sqlx::query("UPDATE refresh_tokens SET revoked = TRUE WHERE public_key = $1")
+        .bind(public_key)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to revoke refresh tokens: {}", e)))?;
+    Ok(())
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn revoke_access_token(pool: &PgPool, jti: &str, expires_at: i64) -> AppResult<()> {
+    // Entries are only needed until the token would have expired anyway
+    sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < $1")
+        .bind(now())
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to prune revoked tokens: {}", e)))?;
+    sqlx::query("INSERT INTO revoked_tokens (jti, expires_at) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+        .bind(jti)
+        .bind(expires_at)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to revoke token: {}", e)))?;
+    Ok(())
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn is_access_token_revoked(pool: &PgPool, jti: &str) -> AppResult<bool> {
+    sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = $1)")
+        .bind(jti)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to check token revocation: {}", e)))
+}
+
+// Auth challenges
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn insert_auth_challenge(pool: &PgPool, nonce: &str, public_key: &str, expires_at: i64) -> AppResult<()> {
+    sqlx::query("DELETE FROM auth_challenges WHERE expires_at < $1")
+        .bind(now())
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to prune auth challenges: {}", e)))?;
+    sqlx::query("INSERT INTO auth_challenges (nonce, public_key, expires_at) VALUES ($1, $2, $3)")
+        .bind(nonce)
+        .bind(public_key)
+        .bind(expires_at)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to store auth challenge: {}", e)))?;
+    Ok(())
+}
+
+/// Atomically marks a live, unused challenge for `public_key` as consumed.
+/// Returns false if it doesn't exist, belongs to another wallet, expired or was already used.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn consume_auth_challenge(pool: &PgPool, nonce: &str, public_key: &str) -> AppResult<bool> {
+    let consumed = sqlx::query(
+        "UPDATE auth_challenges SET consumed = TRUE
+         WHERE nonce = $1 AND public_key = $2 AND NOT consumed AND expires_at >= $3",
+    )
+    .bind(nonce)
+    .bind(public_key)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to consume auth challenge: {}", e)))?
+    .rows_affected();
+    Ok(consumed == 1)
+}
+
+/// Records a signature presented to POST /auth. Returns false if it was already used.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn consume_auth_signature(
+    pool: &PgPool,
+    signature: &str,
+    public_key: &str,
+    nonce: &str,
+    expires_at: i64,
+) -> AppResult<bool> {
+    sqlx::query("DELETE FROM auth_signatures WHERE expires_at < $1")
+        .bind(now())
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to prune auth signatures: {}", e)))?;
+    let inserted = sqlx::query(
+        "INSERT INTO auth_signatures (signature, public_key, nonce, expires_at)
+         VALUES ($1, $2, $3, $4)
+         ON CONFLICT (signature) DO NOTHING",
+    )
+    .bind(signature)
+    .bind(public_key)
+    .bind(nonce)
+    .bind(expires_at)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to record auth signature: {}", e)))?
+    .rows_affected();
+    Ok(inserted == 1)
+}
+
+// Transaction jobs
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct TransactionJobRow {
+    pub id: String,
+    pub signature: String,
+    pub cluster: String,
+    pub tenant: String,
+    pub instruction: String,
+    pub owner: String,
+    pub plan_id: i64,
+    pub items: Vec<String>, // `<instruction>:<plan_id>` per action of a batch, else empty
+    pub transaction: String,
+    pub last_valid_block_height: i64,
+    pub attempts: i32,
+    pub nonce_account: Option<String>, // Durable nonce the transaction was built on, if any
+}
+
+impl TransactionJobRow {
+    /// The (instruction, plan_id) pairs the transaction carries.
+    pub fn actions(&self) -> Vec<(String, u64)> {
+        if self.items.is_empty() {
+            return vec![(self.instruction.clone(), self.plan_id as u64)];
+        }
+        self.items
+            .iter()
+            .filter_map(|item| {
+                let (instruction, plan_id) = item.rsplit_once(':')?;
+                Some((instruction.to_string(), plan_id.parse().ok()?))
+            })
+            .collect()
+    }
+}
+
+/// Records a signed transaction as a `built` job, first picked up by the worker at
+/// `next_attempt_at` if the submitting request has not resolved it by then.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn insert_transaction_job(pool: &PgPool, row: &TransactionJobRow, next_attempt_at: i64) -> AppResult<()> {
+    sqlx::query(
+        "INSERT INTO transaction_jobs
+            (id, signature, cluster, tenant, instruction, owner, plan_id, items, transaction, last_valid_block_height,
+             nonce_account, status, next_attempt_at, created_at, updated_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, 'built', $12, $13, $13)
+         ON CONFLICT (signature) DO NOTHING",
+    )
+    .bind(&row.id)
+    .bind(&row.signature)
+    .bind(&row.cluster)
+    .bind(&row.tenant)
+    .bind(&row.instruction)
+    .bind(&row.owner)
+    .bind(row.plan_id)
+    .bind(&row.items)
+    .bind(&row.transaction)
+    .bind(row.last_valid_block_height)
+    .bind(&row.nonce_account)
+    .bind(next_attempt_at)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to record transaction job: {}", e)))?;
+    Ok(())
+}
+
+/// Unresolved jobs whose next attempt is due, oldest first.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_due_transaction_jobs(pool: &PgPool, limit: i64) -> AppResult<Vec<TransactionJobRow>> {
+    sqlx::query_as::<_, TransactionJobRow>(
+        "SELECT id, signature, cluster, tenant, instruction, owner, plan_id, items, transaction,
+                last_valid_block_height, attempts, nonce_account
+         FROM transaction_jobs
+         WHERE status IN ('built', 'submitted') AND next_attempt_at <= $1
+         ORDER BY next_attempt_at
+         LIMIT $2",
+    )
+    .bind(now())
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list transaction jobs: {}", e)))
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn set_transaction_job_status(
+    pool: &PgPool,
+    id: &str,
+    status: &str,
+    error: Option<&str>,
+) -> AppResult<()> {
+    sqlx::query("UPDATE transaction_jobs SET status = $2, error = $3, updated_at = $4 WHERE id = $1")
+        .bind(id)
+        .bind(status)
+        .bind(error)
+        .bind(now())
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to update transaction job: {}", e)))?;
+    // A payment intent, refund or treasury withdrawal sent as this job settles with it
+    let settled_status = match status {
+        "confirmed" => "succeeded",
+        "failed" => "failed",
+        _ => return Ok(()),
+    };
+    sqlx::query(
+        "UPDATE payment_intents SET status = $2, error = $3, updated_at = $4
+         WHERE status = 'processing' AND signature = (SELECT signature FROM transaction_jobs WHERE id = $1)",
+    )
+    .bind(id)
+    .bind(settled_status)
+    .bind(error)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to settle payment intent: {}", e)))?;
+    sqlx::query(
+        "WITH settled AS (
+             UPDATE refunds SET status = $2, error = $3, updated_at = $4
+             WHERE status = 'processing' AND signature = (SELECT signature FROM transaction_jobs WHERE id = $1)
+             RETURNING payment_signature, instruction_index, lamports, status
+         )
+         UPDATE payments p SET refunded_lamports = p.refunded_lamports + s.lamports
+         FROM settled s
+         WHERE s.status = 'succeeded' AND p.signature = s.payment_signature AND p.instruction_index = s.instruction_index",
+    )
+    .bind(id)
+    .bind(settled_status)
+    .bind(error)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to settle refund: {}", e)))?;
+    sqlx::query(
+        "UPDATE treasury_withdrawals SET status = $2, error = $3, updated_at = $4
+         WHERE status = 'processing' AND signature = (SELECT signature FROM transaction_jobs WHERE id = $1)",
+    )
+    .bind(id)
+    .bind(settled_status)
+    .bind(error)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to settle treasury withdrawal: {}", e)))?;
+    // Landed or never will, so its nonce can carry the next transaction
+    sqlx::query(
+        "UPDATE nonce_accounts SET leased_by = NULL, leased_at = NULL, updated_at = $2
+         WHERE address = (SELECT nonce_account FROM transaction_jobs WHERE id = $1)",
+    )
+    .bind(id)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to release nonce account: {}", e)))?;
+    Ok(())
+}
+
+/// Moves a job to `submitted` and schedules its next check; `attempts` counts sends.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn reschedule_transaction_job(
+    pool: &PgPool,
+    id: &str,
+    attempts: i32,
+    next_attempt_at: i64,
+    error: Option<&str>,
+) -> AppResult<()> {
+    sqlx::query(
+        "UPDATE transaction_jobs
+         SET status = 'submitted', attempts = $2, next_attempt_at = $3, error = $4, updated_at = $5
+         WHERE id = $1",
+    )
+    .bind(id)
+    .bind(attempts)
+    .bind(next_attempt_at)
+    .bind(error)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to reschedule transaction job: {}", e)))?;
+    Ok(())
+}
+
+// Nonce accounts
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn count_nonce_accounts(pool: &PgPool, cluster: &str, authority: &str) -> AppResult<i64> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM nonce_accounts WHERE cluster = $1 AND authority = $2")
+        .bind(cluster)
+        .bind(authority)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to count nonce accounts: {}", e)))
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn insert_nonce_account(pool: &PgPool, address: &str, cluster: &str, authority: &str) -> AppResult<()> {
+    sqlx::query(
+        "INSERT INTO nonce_accounts (address, cluster, authority, created_at, updated_at)
+         VALUES ($1, $2, $3, $4, $4)
+         ON CONFLICT (address) DO NOTHING",
+    )
+    .bind(address)
+    .bind(cluster)
+    .bind(authority)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to record nonce account: {}", e)))?;
+    Ok(())
+}
+
+/// Leases the least recently used free nonce account to `owner`, unless it already holds
+/// `max_per_owner`. A lease older than `stale_after` secs with no unresolved job behind it
+/// was lost before its job was recorded and counts as free.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn lease_nonce_account(
+    pool: &PgPool,
+    cluster: &str,
+    authority: &str,
+    owner: &str,
+    max_per_owner: i64,
+    stale_after: i64,
+) -> AppResult<Option<String>> {
+    let now = now();
+    sqlx::query_scalar(
+        "WITH held AS (
+             SELECT n.address, n.leased_by,
+                    n.leased_by IS NOT NULL AND (n.leased_at > $5 OR EXISTS (
+                        SELECT 1 FROM transaction_jobs j
+                        WHERE j.nonce_account = n.address AND j.status IN ('built', 'submitted')
+                    )) AS leased
+             FROM nonce_accounts n
+             WHERE n.cluster = $1 AND n.authority = $2
+         )
+         UPDATE nonce_accounts SET leased_by = $3, leased_at = $6, updated_at = $6
+         WHERE address = (
+             SELECT n.address FROM nonce_accounts n JOIN held h ON h.address = n.address
+             WHERE NOT h.leased
+               AND (SELECT COUNT(*) FROM held WHERE leased AND leased_by = $3) < $4
+             ORDER BY n.updated_at
+             LIMIT 1
+             FOR UPDATE OF n SKIP LOCKED
+         )
+         RETURNING address",
+    )
+    .bind(cluster)
+    .bind(authority)
+    .bind(owner)
+    .bind(max_per_owner)
+    .bind(now - stale_after)
+    .bind(now)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to lease nonce account: {}", e)))
+}
+
+/// Frees a nonce account whose transaction was never recorded as a job.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn release_nonce_account(pool: &PgPool, address: &str) -> AppResult<()> {
+    sqlx::query("UPDATE nonce_accounts SET leased_by = NULL, leased_at = NULL, updated_at = $2 WHERE address = $1")
+        .bind(address)
+        .bind(now())
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to release nonce account: {}", e)))?;
+    Ok(())
+}
+
+// Payment intents
+/// A create or renew the wallet signs itself: `requires_signature` → `processing` (signature
+/// accepted and sent) → `succeeded` or `failed`, or `expired` if never confirmed in time and
+/// `cancelled` if the subscription was closed first.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, ToSchema)]
+pub struct PaymentIntent {
+    pub id: String,
+    #[serde(skip_serializing)]
+    pub owner: String,
+    pub cluster: String,
+    pub tenant: String,
+    pub action: String, // create or renew
+    pub plan_id: i64,
+    pub duration: Option<i64>, // Create only
+    pub amount: Option<i64>,   // Lamports charged, when known
+    pub transaction: String,   // Base64 bincode of the unsigned transaction, for the wallet to sign
+    pub last_valid_block_height: i64, // Must be confirmed before the cluster passes this height
+    pub status: String,
+    pub signature: Option<String>,
+    pub error: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn insert_payment_intent(pool: &PgPool, intent: &PaymentIntent) -> AppResult<()> {
+    sqlx::query(
+        "INSERT INTO payment_intents
+            (id, owner, cluster, tenant, action, plan_id, duration, amount, transaction, last_valid_block_height,
+             status, created_at, updated_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $12)",
+    )
+    .bind(&intent.id)
+    .bind(&intent.owner)
+    .bind(&intent.cluster)
+    .bind(&intent.tenant)
+    .bind(&intent.action)
+    .bind(intent.plan_id)
+    .bind(intent.duration)
+    .bind(intent.amount)
+    .bind(&intent.transaction)
+    .bind(intent.last_valid_block_height)
+    .bind(&intent.status)
+    .bind(intent.created_at)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to record payment intent: {}", e)))?;
+    Ok(())
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn get_payment_intent(pool: &PgPool, id: &str) -> AppResult<Option<PaymentIntent>> {
+    sqlx::query_as::<_, PaymentIntent>(
+        "SELECT id, owner, cluster, tenant, action, plan_id, duration, amount, transaction, last_valid_block_height,
+                status, signature, error, created_at, updated_at
+         FROM payment_intents
+         WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to fetch payment intent: {}", e)))
+}
+
+/// Moves an intent awaiting its signature to `processing` with `signature`. Returns false if
+/// it had already left `requires_signature`.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn start_payment_intent(pool: &PgPool, id: &str, signature: &str) -> AppResult<bool> {
+    let updated = sqlx::query(
+        "UPDATE payment_intents SET status = 'processing', signature = $2, updated_at = $3
+         WHERE id = $1 AND status = 'requires_signature'",
+    )
+    .bind(id)
+    .bind(signature)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to confirm payment intent: {}", e)))?
+    .rows_affected();
+    Ok(updated == 1)
+}
+
+/// Expires an intent whose transaction can no longer land without it having been signed.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn expire_payment_intent(pool: &PgPool, id: &str) -> AppResult<()> {
+    sqlx::query(
+        "UPDATE payment_intents SET status = 'expired', error = $2, updated_at = $3
+         WHERE id = $1 AND status = 'requires_signature'",
+    )
+    .bind(id)
+    .bind("Blockhash expired before the intent was confirmed")
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to expire payment intent: {}", e)))?;
+    Ok(())
+}
+
+// Refunds
+/// A refund of one indexed payment: `requested` by a merchant, then `rejected`, or approved
+/// by an admin and sent from the treasury as `processing` → `succeeded` or `failed`.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, ToSchema)]
+pub struct Refund {
+    pub id: String,
+    pub payment_signature: String,
+    pub instruction_index: i32,
+    pub pda: String,
+    pub owner: String, // Receives the refund
+    pub plan_id: i64,
+    pub lamports: i64,
+    pub reason: Option<String>,
+    pub status: String,
+    pub requested_by: String,
+    pub reviewed_by: Option<String>,
+    pub signature: Option<String>, // Refund transaction, once approved
+    pub error: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+/// Records a requested refund. Returns false if the payment already has one requested,
+/// in flight or paid.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn insert_refund(pool: &PgPool, refund: &Refund) -> AppResult<bool> {
+    let inserted = sqlx::query(
+        "INSERT INTO refunds
+            (id, payment_signature, instruction_index, pda, owner, plan_id, lamports, reason, status, requested_by,
+             created_at, updated_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $11)
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(&refund.id)
+    .bind(&refund.payment_signature)
+    .bind(refund.instruction_index)
+    .bind(&refund.pda)
+    .bind(&refund.owner)
+    .bind(refund.plan_id)
+    .bind(refund.lamports)
+    .bind(&refund.reason)
+    .bind(&refund.status)
+    .bind(&refund.requested_by)
+    .bind(refund.created_at)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to record refund: {}", e)))?
+    .rows_affected();
+    Ok(inserted == 1)
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn get_refund(pool: &PgPool, id: &str) -> AppResult<Option<Refund>> {
+    sqlx::query_as::<_, Refund>(
+        "SELECT id, payment_signature, instruction_index, pda, owner, plan_id, lamports, reason, status, requested_by,
+                reviewed_by, signature, error, created_at, updated_at
+         FROM refunds
+         WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to fetch refund: {}", e)))
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn get_refund_by_signature(pool: &PgPool, signature: &str) -> AppResult<Option<Refund>> {
+    sqlx::query_as::<_, Refund>(
+        "SELECT id, payment_signature, instruction_index, pda, owner, plan_id, lamports, reason, status, requested_by,
+                reviewed_by, signature, error, created_at, updated_at
+         FROM refunds
+         WHERE signature = $1",
+    )
+    .bind(signature)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to fetch refund: {}", e)))
+}
+
+/// Refunds, newest first, optionally in one status.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_refunds(
+    pool: &PgPool,
+    plan_ids: Option<&[i64]>, // None for every plan
+    status: Option<&str>,
+    limit: i64,
+    offset: i64,
+) -> AppResult<Vec<Refund>> {
+    sqlx::query_as::<_, Refund>(
+        "SELECT id, payment_signature, instruction_index, pda, owner, plan_id, lamports, reason, status, requested_by,
+                reviewed_by, signature, error, created_at, updated_at
+         FROM refunds
+         WHERE ($1::TEXT IS NULL OR status = $1)
+           AND ($4::BIGINT[] IS NULL OR plan_id = ANY($4))
+         ORDER BY created_at DESC, id
+         LIMIT $2 OFFSET $3",
+    )
+    .bind(status)
+    .bind(limit)
+    .bind(offset)
+    .bind(plan_ids)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list refunds: {}", e)))
+}
+
+/// Moves a requested refund to `processing` with the transaction about to pay it. Returns
+/// false if it was no longer `requested`.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn start_refund(pool: &PgPool, id: &str, reviewed_by: &str, signature: &str) -> AppResult<bool> {
+    let updated = sqlx::query(
+        "UPDATE refunds SET status = 'processing', reviewed_by = $2, signature = $3, updated_at = $4
+         WHERE id = $1 AND status = 'requested'",
+    )
+    .bind(id)
+    .bind(reviewed_by)
+    .bind(signature)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to approve refund: {}", e)))?
+    .rows_affected();
+    Ok(updated == 1)
+}
+
+/// Returns false if the refund was no longer `requested`.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn reject_refund(pool: &PgPool, id: &str, reviewed_by: &str, reason: Option<&str>) -> AppResult<bool> {
+    let updated = sqlx::query(
+        "UPDATE refunds SET status = 'rejected', reviewed_by = $2, error = $3, updated_at = $4
+         WHERE id = $1 AND status = 'requested'",
+    )
+    .bind(id)
+    .bind(reviewed_by)
+    .bind(reason)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to reject refund: {}", e)))?
+    .rows_affected();
+    Ok(updated == 1)
+}
+
+// Treasury withdrawals
+/// A withdrawal from the treasury: `requested` by an admin, `approved` once enough other
+/// admins approved it, then sent after `executable_at` as `processing` → `succeeded` or
+/// `failed`. It can be `rejected` until it is sent.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, ToSchema)]
+pub struct TreasuryWithdrawal {
+    pub id: String,
+    pub destination: String,
+    pub lamports: i64,
+    pub reason: Option<String>,
+    pub status: String,
+    pub requested_by: String,
+    pub approvals_required: i32,
+    pub approved_by: Vec<String>,
+    pub executable_at: i64, // Timelock: not sent before this
+    pub reviewed_by: Option<String>, // Sent or rejected it
+    pub signature: Option<String>, // Withdrawal transaction, once sent
+    pub error: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn insert_treasury_withdrawal(pool: &PgPool, withdrawal: &TreasuryWithdrawal) -> AppResult<()> {
+    sqlx::query(
+        "INSERT INTO treasury_withdrawals
+            (id, destination, lamports, reason, status, requested_by, approvals_required, executable_at,
+             created_at, updated_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $9)",
+    )
+    .bind(&withdrawal.id)
+    .bind(&withdrawal.destination)
+    .bind(withdrawal.lamports)
+    .bind(&withdrawal.reason)
+    .bind(&withdrawal.status)
+    .bind(&withdrawal.requested_by)
+    .bind(withdrawal.approvals_required)
+    .bind(withdrawal.executable_at)
+    .bind(withdrawal.created_at)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to record treasury withdrawal: {}", e)))?;
+    Ok(())
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn get_treasury_withdrawal(pool: &PgPool, id: &str) -> AppResult<Option<TreasuryWithdrawal>> {
+    sqlx::query_as::<_, TreasuryWithdrawal>(
+        "SELECT id, destination, lamports, reason, status, requested_by, approvals_required, approved_by, executable_at,
+                reviewed_by, signature, error, created_at, updated_at
+         FROM treasury_withdrawals
+         WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to fetch treasury withdrawal: {}", e)))
+}
+
+/// Treasury withdrawals, newest first, optionally in one status.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_treasury_withdrawals(
+    pool: &PgPool,
+    status: Option<&str>,
+    limit: i64,
+    offset: i64,
+) -> AppResult<Vec<TreasuryWithdrawal>> {
+    sqlx::query_as::<_, TreasuryWithdrawal>(
+        "SELECT id, destination, lamports, reason, status, requested_by, approvals_required, approved_by, executable_at,
+                reviewed_by, signature, error, created_at, updated_at
+         FROM treasury_withdrawals
+         WHERE ($1::TEXT IS NULL OR status = $1)
+         ORDER BY created_at DESC, id
+         LIMIT $2 OFFSET $3",
+    )
+    .bind(status)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list treasury withdrawals: {}", e)))
+}
+
+/// Adds `admin`'s approval to a requested withdrawal, which becomes `approved` with the last
+/// one it needs. Returns None if it was no longer `requested`, or `admin` requested or already
+/// approved it.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn approve_treasury_withdrawal(pool: &PgPool, id: &str, admin: &str) -> AppResult<Option<TreasuryWithdrawal>> {
+    sqlx::query_as::<_, TreasuryWithdrawal>(
+        "UPDATE treasury_withdrawals
+         SET approved_by = array_append(approved_by, $2),
+             status = CASE WHEN cardinality(approved_by) + 1 >= approvals_required THEN 'approved' ELSE status END,
+             updated_at = $3
+         WHERE id = $1 AND status = 'requested' AND requested_by <> $2 AND NOT ($2 = ANY(approved_by))
+         RETURNING id, destination, lamports, reason, status, requested_by, approvals_required, approved_by, executable_at,
+                reviewed_by, signature, error, created_at, updated_at",
+    )
+    .bind(id)
+    .bind(admin)
+    .bind(now())
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to approve treasury withdrawal: {}", e)))
+}
+
+/// Moves an approved withdrawal to `processing` with the transaction about to send it.
+/// Returns false if it was no longer `approved`.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn start_treasury_withdrawal(pool: &PgPool, id: &str, reviewed_by: &str, signature: &str) -> AppResult<bool> {
+    let updated = sqlx::query(
+        "UPDATE treasury_withdrawals SET status = 'processing', reviewed_by = $2, signature = $3, updated_at = $4
+         WHERE id = $1 AND status = 'approved'",
+    )
+    .bind(id)
+    .bind(reviewed_by)
+    .bind(signature)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to send treasury withdrawal: {}", e)))?
+    .rows_affected();
+    Ok(updated == 1)
+}
+
+/// Returns false if the withdrawal was already sent or rejected.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn reject_treasury_withdrawal(pool: &PgPool, id: &str, reviewed_by: &str, reason: Option<&str>) -> AppResult<bool> {
+    let updated = sqlx::query(
+        "UPDATE treasury_withdrawals SET status = 'rejected', reviewed_by = $2, error = $3, updated_at = $4
+         WHERE id = $1 AND status IN ('requested', 'approved')",
+    )
+    .bind(id)
+    .bind(reviewed_by)
+    .bind(reason)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to reject treasury withdrawal: {}", e)))?
+    .rows_affected();
+    Ok(updated == 1)
+}
+
+// Keeper
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct DueSubscriptionRow {
+    #[sqlx(flatten)]
+    pub subscription: SubscriptionRow,
+    pub auto_renew: bool,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, ToSchema)]
+pub struct KeeperRunRow {
+    pub id: i64,
+    pub started_at: i64,
+    pub finished_at: i64,
+    pub scanned: i32,
+    pub renewed: i32,
+    pub expired: i32,
+    pub failed: i32,
+    pub dry_run: bool, // Nothing was sent; the counts are what would have happened
+}
+
+/// Active subscriptions whose billing period ended at or before `now` and has not been
+/// reported as expired yet, soonest first.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_due_subscriptions(pool: &PgPool, now: i64, limit: i64) -> AppResult<Vec<DueSubscriptionRow>> {
+    sqlx::query_as::<_, DueSubscriptionRow>(
+        "SELECT s.*, COALESCE(st.auto_renew, FALSE) AS auto_renew
+         FROM subscriptions s
+         LEFT JOIN subscription_settings st ON st.pda = s.pda
+         WHERE s.active AND NOT s.closed
+           AND s.start_time + s.duration <= $1
+           AND (s.expired_at IS NULL OR s.expired_at < s.start_time + s.duration)
+         ORDER BY s.start_time + s.duration
+         LIMIT $2",
+    )
+    .bind(now)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list due subscriptions: {}", e)))
+}
+
+/// The owner's active subscriptions whose billing period ends after `now`, soonest first.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_upcoming_subscriptions(pool: &PgPool, owner: &str, now: i64) -> AppResult<Vec<DueSubscriptionRow>> {
+    sqlx::query_as::<_, DueSubscriptionRow>(
+        "SELECT s.*, COALESCE(st.auto_renew, FALSE) AS auto_renew
+         FROM subscriptions s
+         LEFT JOIN subscription_settings st ON st.pda = s.pda
+         WHERE s.owner = $1 AND s.active AND NOT s.closed
+           AND s.start_time + s.duration > $2
+         ORDER BY s.start_time + s.duration",
+    )
+    .bind(owner)
+    .bind(now)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list upcoming subscriptions: {}", e)))
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn mark_subscription_expired(pool: &PgPool, pda: &str, period_end: i64) -> AppResult<()> {
+    sqlx::query("UPDATE subscriptions SET expired_at = $2 WHERE pda = $1")
+        .bind(pda)
+        .bind(period_end)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to mark subscription expired: {}", e)))?;
+    Ok(())
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn set_auto_renew(pool: &PgPool, pda: &str, enabled: bool) -> AppResult<()> {
+    sqlx::query(
+        "INSERT INTO subscription_settings (pda, auto_renew, updated_at)
+         VALUES ($1, $2, $3)
+         ON CONFLICT (pda) DO UPDATE SET auto_renew = EXCLUDED.auto_renew, updated_at = EXCLUDED.updated_at",
+    )
+    .bind(pda)
+    .bind(enabled)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to update auto-renew: {}", e)))?;
+    Ok(())
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn insert_keeper_run(pool: &PgPool, run: &KeeperRunRow) -> AppResult<()> {
+    sqlx::query(
+        "INSERT INTO keeper_runs (started_at, finished_at, scanned, renewed, expired, failed, dry_run)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(run.started_at)
+    .bind(run.finished_at)
+    .bind(run.scanned)
+    .bind(run.renewed)
+    .bind(run.expired)
+    .bind(run.failed)
+    .bind(run.dry_run)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to record keeper run: {}", e)))?;
+    Ok(())
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_keeper_runs(pool: &PgPool, limit: i64) -> AppResult<Vec<KeeperRunRow>> {
+    sqlx::query_as::<_, KeeperRunRow>("SELECT * FROM keeper_runs ORDER BY id DESC LIMIT $1")
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to list keeper runs: {}", e)))
+}
+
+// Webhook dead letters
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, ToSchema)]
+pub struct DeadLetterRow {
+    pub id: String,
+    pub webhook_id: String,
+    pub owner: String,
+    pub url: String,
+    pub event_id: String,
+    pub event_type: String,
+    pub version: String, // Payload version of `body`
+    #[serde(skip)]
+    pub body: String,
+    pub attempts: i32,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub failed_at: i64,
+    pub requeued_at: Option<i64>,
+    pub requeued_as: Option<String>,
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn insert_dead_letter(pool: &PgPool, row: &DeadLetterRow) -> AppResult<()> {
+    sqlx::query(
+        "INSERT INTO webhook_dead_letters
+            (id, webhook_id, owner, url, event_id, event_type, version, body, attempts, last_status_code, last_error,
+             failed_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(&row.id)
+    .bind(&row.webhook_id)
+    .bind(&row.owner)
+    .bind(&row.url)
+    .bind(&row.event_id)
+    .bind(&row.event_type)
+    .bind(&row.version)
+    .bind(&row.body)
+    .bind(row.attempts)
+    .bind(row.last_status_code)
+    .bind(&row.last_error)
+    .bind(row.failed_at)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to record dead letter: {}", e)))?;
+    Ok(())
+}
+
+/// Dead letters, newest first; only those still queued unless `include_requeued`.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_dead_letters(
+    pool: &PgPool,
+    webhook_id: Option<&str>,
+    include_requeued: bool,
+    limit: i64,
+    offset: i64,
+) -> AppResult<Vec<DeadLetterRow>> {
+    sqlx::query_as::<_, DeadLetterRow>(
+        "SELECT * FROM webhook_dead_letters
+         WHERE ($1::TEXT IS NULL OR webhook_id = $1)
+           AND ($2 OR requeued_at IS NULL)
+         ORDER BY failed_at DESC, id
+         LIMIT $3 OFFSET $4",
+    )
+    .bind(webhook_id)
+    .bind(include_requeued)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list dead letters: {}", e)))
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn find_dead_letter(pool: &PgPool, id: &str) -> AppResult<Option<DeadLetterRow>> {
+    sqlx::query_as::<_, DeadLetterRow>("SELECT * FROM webhook_dead_letters WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to fetch dead letter: {}", e)))
+}
+
+/// Takes a dead letter off the queue; returns false if it was already requeued.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn mark_dead_letter_requeued(pool: &PgPool, id: &str, delivery_id: &str) -> AppResult<bool> {
+    let result = sqlx::query(
+        "UPDATE webhook_dead_letters SET requeued_at = $2, requeued_as = $3
+         WHERE id = $1 AND requeued_at IS NULL",
+    )
+    .bind(id)
+    .bind(now())
+    .bind(delivery_id)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to requeue dead letter: {}", e)))?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Dead letters still in the queue.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn count_dead_letters(pool: &PgPool) -> AppResult<i64> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM webhook_dead_letters WHERE requeued_at IS NULL")
+        .fetch_one(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to count dead letters: {}", e)))
+}
+
+// Webhook deliveries
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct WebhookDeliveryRow {
+    pub id: String,
+    pub webhook_id: String,
+    pub owner: String,
+    pub event_id: String,
+    pub event_type: String,
+    pub version: String, // Payload version of `body`
+    pub body: String,
+    pub status: String, // pending | succeeded | failed
+    pub attempts: i32,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub next_attempt_at: Option<i64>,
+    pub replay_of: Option<String>,
+    pub request_id: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn insert_webhook_delivery(pool: &PgPool, row: &WebhookDeliveryRow) -> AppResult<()> {
+    sqlx::query(
+        "INSERT INTO webhook_deliveries
+            (id, webhook_id, owner, event_id, event_type, version, body, status, attempts, next_attempt_at,
+             replay_of, request_id, created_at, updated_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)",
+    )
+    .bind(&row.id)
+    .bind(&row.webhook_id)
+    .bind(&row.owner)
+    .bind(&row.event_id)
+    .bind(&row.event_type)
+    .bind(&row.version)
+    .bind(&row.body)
+    .bind(&row.status)
+    .bind(row.attempts)
+    .bind(row.next_attempt_at)
+    .bind(&row.replay_of)
+    .bind(&row.request_id)
+    .bind(row.created_at)
+    .bind(row.updated_at)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to record webhook delivery: {}", e)))?;
+    Ok(())
+}
+
+/// Claims up to `limit` pending deliveries that are due by moving their next attempt to
+/// `claimed_until`, so no other replica sends them meanwhile. One whose sender dies is due
+/// again once the claim runs out.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn claim_due_webhook_deliveries(pool: &PgPool, claimed_until: i64, limit: i64) -> AppResult<Vec<WebhookDeliveryRow>> {
+    sqlx::query_as::<_, WebhookDeliveryRow>(
+        "UPDATE webhook_deliveries SET next_attempt_at = $2
+         WHERE id IN (
+             SELECT id FROM webhook_deliveries
+             WHERE status = 'pending' AND next_attempt_at <= $1
+             ORDER BY next_attempt_at
+             LIMIT $3
+             FOR UPDATE SKIP LOCKED
+         )
+         RETURNING *",
+    )
+    .bind(now())
+    .bind(claimed_until)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to claim webhook deliveries: {}", e)))
+}
+
+/// Records an attempt at a pending delivery; `next_attempt_at` is unset once it succeeded or
+/// failed for good.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn record_webhook_attempt(
+    pool: &PgPool,
+    id: &str,
+    status: &str,
+    attempts: i32,
+    last_status_code: Option<i32>,
+    last_error: Option<&str>,
+    next_attempt_at: Option<i64>,
+) -> AppResult<()> {
+    sqlx::query(
+        "UPDATE webhook_deliveries
+         SET status = $2, attempts = $3, last_status_code = $4, last_error = $5, next_attempt_at = $6, updated_at = $7
+         WHERE id = $1 AND status = 'pending'",
+    )
+    .bind(id)
+    .bind(status)
+    .bind(attempts)
+    .bind(last_status_code)
+    .bind(last_error)
+    .bind(next_attempt_at)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to update webhook delivery: {}", e)))?;
+    Ok(())
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn find_webhook_delivery(pool: &PgPool, id: &str) -> AppResult<Option<WebhookDeliveryRow>> {
+    sqlx::query_as::<_, WebhookDeliveryRow>("SELECT * FROM webhook_deliveries WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to fetch webhook delivery: {}", e)))
+}
+
+/// Deliveries to one webhook, most recently updated first.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_webhook_deliveries(pool: &PgPool, webhook_id: &str, limit: i64) -> AppResult<Vec<WebhookDeliveryRow>> {
+    sqlx::query_as::<_, WebhookDeliveryRow>(
+        "SELECT * FROM webhook_deliveries WHERE webhook_id = $1 ORDER BY updated_at DESC, id LIMIT $2",
+    )
+    .bind(webhook_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list webhook deliveries: {}", e)))
+}
+
+/// Deliveries to any of `owner`'s webhooks, most recently updated first, optionally narrowed
+/// to one webhook, status and event type.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_owner_webhook_deliveries(
+    pool: &PgPool,
+    owner: &str,
+    webhook_id: Option<&str>,
+    status: Option<&str>,
+    event_type: Option<&str>,
+    limit: i64,
+) -> AppResult<Vec<WebhookDeliveryRow>> {
+    sqlx::query_as::<_, WebhookDeliveryRow>(
+        "SELECT * FROM webhook_deliveries
+         WHERE owner = $1
+           AND ($2::TEXT IS NULL OR webhook_id = $2)
+           AND ($3::TEXT IS NULL OR status = $3)
+           AND ($4::TEXT IS NULL OR event_type = $4)
+         ORDER BY updated_at DESC, id
+         LIMIT $5",
+    )
+    .bind(owner)
+    .bind(webhook_id)
+    .bind(status)
+    .bind(event_type)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list webhook deliveries: {}", e)))
+}
+
+/// Drops deliveries that succeeded or failed before `before`; pending ones are kept.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn prune_webhook_deliveries(pool: &PgPool, before: i64) -> AppResult<u64> {
+    let result = sqlx::query("DELETE FROM webhook_deliveries WHERE status <> 'pending' AND updated_at < $1")
+        .bind(before)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to remove expired webhook deliveries: {}", e)))?;
+    Ok(result.rows_affected())
+}
+
+// Event log
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct EventLogRow {
+    pub cursor: i64,
+    #[allow(dead_code)]
+    pub event_id: String,
+    pub event_type: String,
+    pub plan_id: i64,
+    pub body: String,
+    pub recorded_at: i64,
+}
+
+/// Appends an event and returns its cursor.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn insert_event_log(
+    pool: &PgPool,
+    event_id: &str,
+    event_type: &str,
+    plan_id: i64,
+    body: &str,
+) -> AppResult<i64> {
+    sqlx::query_scalar(
+        "INSERT INTO event_log (event_id, event_type, plan_id, body, recorded_at)
+         VALUES ($1, $2, $3, $4, $5)
+         RETURNING cursor",
+    )
+    .bind(event_id)
+    .bind(event_type)
+    .bind(plan_id)
+    .bind(body)
+    .bind(now())
+    .fetch_one(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to record event: {}", e)))
+}
+
+/// Events after `cursor`, oldest first.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_event_log(pool: &PgPool, cursor: i64, limit: i64) -> AppResult<Vec<EventLogRow>> {
+    sqlx::query_as::<_, EventLogRow>("SELECT * FROM event_log WHERE cursor > $1 ORDER BY cursor LIMIT $2")
+        .bind(cursor)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to list events: {}", e)))
+}
+
+/// The newest event's cursor, 0 when there is none.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn latest_event_cursor(pool: &PgPool) -> AppResult<i64> {
+    sqlx::query_scalar("SELECT COALESCE(MAX(cursor), 0) FROM event_log")
+        .fetch_one(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to fetch the latest event: {}", e)))
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn prune_event_log(pool: &PgPool, before: i64) -> AppResult<u64> {
+    let result = sqlx::query("DELETE FROM event_log WHERE recorded_at < $1")
+        .bind(before)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to prune the event log: {}", e)))?;
+    Ok(result.rows_affected())
+}
+
+// JWT key rotations
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct JwtRotationRow {
+    pub kid: String,
+    pub public_key: String,
+    pub sealed_seed: String,
+    pub previous_kid: String,
+    pub previous_public_key: String,
+    pub previous_expires_at: i64,
+    pub rotated_at: i64,
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn insert_jwt_rotation(pool: &PgPool, row: &JwtRotationRow) -> AppResult<()> {
+    sqlx::query(
+        "INSERT INTO jwt_key_rotations
+            (kid, public_key, sealed_seed, previous_kid, previous_public_key, previous_expires_at, rotated_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(&row.kid)
+    .bind(&row.public_key)
+    .bind(&row.sealed_seed)
+    .bind(&row.previous_kid)
+    .bind(&row.previous_public_key)
+    .bind(row.previous_expires_at)
+    .bind(row.rotated_at)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to record JWT key rotation: {}", e)))?;
+    Ok(())
+}
+
+/// Every rotation, oldest first.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_jwt_rotations(pool: &PgPool) -> AppResult<Vec<JwtRotationRow>> {
+    sqlx::query_as::<_, JwtRotationRow>(
+        "SELECT kid, public_key, sealed_seed, previous_kid, previous_public_key, previous_expires_at, rotated_at
+         FROM jwt_key_rotations
+         ORDER BY rotated_at, kid",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list JWT key rotations: {}", e)))
+}
+
+// Wallet links
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct WalletLinkRow {
+    pub wallet: String,
+    #[allow(dead_code)]
+    pub account: String,
+    pub linked_at: i64,
+}
+
+/// The account `wallet` is linked to, if it is linked to one.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn find_wallet_account(pool: &PgPool, wallet: &str) -> AppResult<Option<String>> {
+    sqlx::query_scalar("SELECT account FROM wallet_links WHERE wallet = $1")
+        .bind(wallet)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to fetch wallet account: {}", e)))
+}
+
+/// The wallets linked to `account`, oldest first.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_wallet_links(pool: &PgPool, account: &str) -> AppResult<Vec<WalletLinkRow>> {
+    sqlx::query_as::<_, WalletLinkRow>(
+        "SELECT wallet, account, linked_at FROM wallet_links WHERE account = $1 ORDER BY linked_at, wallet",
+    )
+    .bind(account)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list linked wallets: {}", e)))
+}
+
+/// Links `wallet` to `account`; returns false if it is already linked or is itself an account
+/// with linked wallets, so accounts never nest.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn insert_wallet_link(pool: &PgPool, wallet: &str, account: &str, linked_at: i64) -> AppResult<bool> {
+    let result = sqlx::query(
+        "INSERT INTO wallet_links (wallet, account, linked_at)
+         SELECT $1, $2, $3
+         WHERE NOT EXISTS (SELECT 1 FROM wallet_links WHERE account = $1)
+           AND NOT EXISTS (SELECT 1 FROM wallet_links WHERE wallet = $2)
+         ON CONFLICT (wallet) DO NOTHING",
+    )
+    .bind(wallet)
+    .bind(account)
+    .bind(linked_at)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to link wallet: {}", e)))?;
+    Ok(result.rows_affected() == 1)
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn delete_wallet_link(pool: &PgPool, wallet: &str, account: &str) -> AppResult<bool> {
+    let result = sqlx::query("DELETE FROM wallet_links WHERE wallet = $1 AND account = $2")
+        .bind(wallet)
+        .bind(account)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to unlink wallet: {}", e)))?;
+    Ok(result.rows_affected() == 1)
+}
+
+// Customers
+/// The directory entry a plan's merchant keeps for a subscriber.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct CustomerContactRow {
+    pub merchant: String,
+    pub tenant: String,
+    pub plan_id: i64,
+    pub wallet: String,
+    pub email: Option<String>,
+    pub display_name: Option<String>,
+}
+
+/// Directory entries of `wallets` kept by the merchants of `plan_ids`, one per wallet and plan.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_customer_contacts(pool: &PgPool, wallets: &[String], plan_ids: &[i64]) -> AppResult<Vec<CustomerContactRow>> {
+    sqlx::query_as::<_, CustomerContactRow>(
+        "SELECT c.merchant, c.tenant, p.plan_id, c.wallet, c.email, c.display_name
+         FROM customers c JOIN plans p ON p.merchant = c.merchant
+         WHERE c.wallet = ANY($1) AND p.plan_id = ANY($2)",
+    )
+    .bind(wallets)
+    .bind(plan_ids)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list customer contacts: {}", e)))
+}
+
+/// The entry the merchant of `plan_id` holds for `wallet`, if it has an email the customer
+/// agreed to be emailed at.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn find_consented_customer_email(pool: &PgPool, wallet: &str, plan_id: i64) -> AppResult<Option<CustomerContactRow>> {
+    sqlx::query_as::<_, CustomerContactRow>(
+        "SELECT c.merchant, c.tenant, p.plan_id, c.wallet, c.email, c.display_name
+         FROM customers c JOIN plans p ON p.merchant = c.merchant
+         WHERE c.wallet = $1 AND p.plan_id = $2 AND c.email_consent AND c.email IS NOT NULL",
+    )
+    .bind(wallet)
+    .bind(plan_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to fetch customer email: {}", e)))
+}
+
+// Custodial accounts
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct CustodialAccountRow {
+    pub email: String,
+    pub password_hash: String,
+    pub wallet: String,
+    pub key_backend: String,
+    pub wrapped_key: String,
+    pub nonce: String,
+    pub sealed_secret: String,
+    pub created_at: i64,
+    pub exported_at: Option<i64>,
+}
+
+/// Returns false if the email already has an account.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn insert_custodial_account(pool: &PgPool, row: &CustodialAccountRow) -> AppResult<bool> {
+    let result = sqlx::query(
+        "INSERT INTO custodial_accounts
+            (email, password_hash, wallet, key_backend, wrapped_key, nonce, sealed_secret, created_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+         ON CONFLICT (email) DO NOTHING",
+    )
+    .bind(&row.email)
+    .bind(&row.password_hash)
+    .bind(&row.wallet)
+    .bind(&row.key_backend)
+    .bind(&row.wrapped_key)
+    .bind(&row.nonce)
+    .bind(&row.sealed_secret)
+    .bind(row.created_at)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to create custodial account: {}", e)))?;
+    Ok(result.rows_affected() == 1)
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn find_custodial_account_by_email(pool: &PgPool, email: &str) -> AppResult<Option<CustodialAccountRow>> {
+    sqlx::query_as::<_, CustodialAccountRow>("SELECT * FROM custodial_accounts WHERE email = $1")
+        .bind(email)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to fetch custodial account: {}", e)))
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn find_custodial_account_by_wallet(pool: &PgPool, wallet: &str) -> AppResult<Option<CustodialAccountRow>> {
+    sqlx::query_as::<_, CustodialAccountRow>("SELECT * FROM custodial_accounts WHERE wallet = $1")
+        .bind(wallet)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to fetch custodial account: {}", e)))
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn mark_custodial_key_exported(pool: &PgPool, wallet: &str) -> AppResult<()> {
+    sqlx::query("UPDATE custodial_accounts SET exported_at = $2 WHERE wallet = $1")
+        .bind(wallet)
+        .bind(now())
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to record key export: {}", e)))?;
+    Ok(())
+}
+
+// Treasury
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, ToSchema)]
+pub struct TreasuryInflowRow {
+    pub signature: String,
+    pub instruction_index: i32,
+    pub kind: String,
+    pub amount: i64,
+    pub slot: i64,
+    pub block_time: Option<i64>,
+    pub pda: String,
+    pub owner: String,
+    pub plan_id: i64,
+    pub subscription_active: Option<bool>, // None when the subscription account is not indexed
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, ToSchema)]
+pub struct TreasuryInflowTotals {
+    pub last_24h_lamports: i64,
+    pub last_24h_payments: i64,
+    pub last_30d_lamports: i64,
+    pub last_30d_payments: i64,
+}
+
+/// Indexed payments to the treasury, newest first, with the paying subscription's current state.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_treasury_inflows(
+    pool: &PgPool,
+    plan_id: Option<i64>,
+    limit: i64,
+    offset: i64,
+) -> AppResult<Vec<TreasuryInflowRow>> {
+    sqlx::query_as::<_, TreasuryInflowRow>(
+        "SELECT p.signature, p.instruction_index, p.kind, p.amount, p.slot, p.block_time,
+                p.pda, p.owner, p.plan_id, s.active AND NOT s.closed AS subscription_active
+         FROM payments p
+         LEFT JOIN subscriptions s ON s.pda = p.pda
+         WHERE ($1::BIGINT IS NULL OR p.plan_id = $1)
+         ORDER BY p.slot DESC, p.signature DESC, p.instruction_index DESC
+         LIMIT $2 OFFSET $3",
+    )
+    .bind(plan_id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list treasury inflows: {}", e)))
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn treasury_inflow_totals(pool: &PgPool) -> AppResult<TreasuryInflowTotals> {
+    let now = now();
+    sqlx::query_as::<_, TreasuryInflowTotals>(
+        "SELECT COALESCE(SUM(amount) FILTER (WHERE block_time >= $1), 0)::BIGINT AS last_24h_lamports,
+                COUNT(*) FILTER (WHERE block_time >= $1) AS last_24h_payments,
+                COALESCE(SUM(amount), 0)::BIGINT AS last_30d_lamports,
+                COUNT(*) AS last_30d_payments
+         FROM payments
+         WHERE block_time >= $2",
+    )
+    .bind(now - 86400)
+    .bind(now - 30 * 86400)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to total treasury inflows: {}", e)))
+}
+
+/// Count and sum of the payments indexed with a block time in `[from, until)`.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn indexed_inflows(pool: &PgPool, from: i64, until: i64) -> AppResult<(i64, i64)> {
+    sqlx::query_as::<_, (i64, i64)>(
+        "SELECT COUNT(*), COALESCE(SUM(amount), 0)::BIGINT
+         FROM payments
+         WHERE block_time >= $1 AND block_time < $2",
+    )
+    .bind(from)
+    .bind(until)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to total indexed payments: {}", e)))
+}
+
+// Treasury reconciliations
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ReconciliationRow {
+    pub day_start: i64,
+    pub treasury: String,
+    pub on_chain_lamports: i64,
+    pub on_chain_transactions: i64,
+    pub indexed_lamports: i64,
+    pub indexed_payments: i64,
+    pub difference_lamports: i64,
+    pub alerted: bool,
+}
+
+/// Claims the day starting at `day_start`; returns false if it is already reconciled or
+/// being reconciled, so each day is only reconciled once.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn claim_reconciliation(pool: &PgPool, day_start: i64, treasury: &str) -> AppResult<bool> {
+    let result = sqlx::query(
+        "INSERT INTO treasury_reconciliations (day_start, treasury, created_at)
+         VALUES ($1, $2, $3)
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(day_start)
+    .bind(treasury)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to claim reconciliation: {}", e)))?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Gives up a claimed day so the next run retries it.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn release_reconciliation(pool: &PgPool, day_start: i64) -> AppResult<()> {
+    sqlx::query("DELETE FROM treasury_reconciliations WHERE day_start = $1 AND finished_at IS NULL")
+        .bind(day_start)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to release reconciliation: {}", e)))?;
+    Ok(())
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn finish_reconciliation(pool: &PgPool, row: &ReconciliationRow) -> AppResult<()> {
+    sqlx::query(
+        "UPDATE treasury_reconciliations
+         SET on_chain_lamports = $2, on_chain_transactions = $3, indexed_lamports = $4,
+             indexed_payments = $5, difference_lamports = $6, alerted = $7, finished_at = $8
+         WHERE day_start = $1",
+    )
+    .bind(row.day_start)
+    .bind(row.on_chain_lamports)
+    .bind(row.on_chain_transactions)
+    .bind(row.indexed_lamports)
+    .bind(row.indexed_payments)
+    .bind(row.difference_lamports)
+    .bind(row.alerted)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to record reconciliation: {}", e)))?;
+    Ok(())
+}
+
+// Merchant statements
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct PlanPeriodTotalsRow {
+    pub plan_id: i64,
+    pub name: Option<String>, // Unset for plans not registered with this backend
+    pub payments: i64,
+    pub payment_lamports: i64,
+    pub refunds: i64,
+    pub refunded_lamports: i64,
+}
+
+/// Per plan of `plan_ids` (every plan when `None`), the payments with a block time in
+/// `[from, to)` and the refunds paid out in it.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_plan_period_totals(
+    pool: &PgPool,
+    plan_ids: Option<&[i64]>,
+    from: i64,
+    to: i64,
+) -> AppResult<Vec<PlanPeriodTotalsRow>> {
+    sqlx::query_as::<_, PlanPeriodTotalsRow>(
+        "WITH paid AS (
+             SELECT plan_id, COUNT(*) AS payments, SUM(amount) AS lamports
+             FROM payments
+             WHERE block_time >= $2 AND block_time < $3 AND ($1::BIGINT[] IS NULL OR plan_id = ANY($1))
+             GROUP BY plan_id
+         ), refunded AS (
+             SELECT plan_id, COUNT(*) AS refunds, SUM(lamports) AS lamports
+             FROM refunds
+             WHERE status = 'succeeded' AND updated_at >= $2 AND updated_at < $3
+               AND ($1::BIGINT[] IS NULL OR plan_id = ANY($1))
+             GROUP BY plan_id
+         )
+         SELECT COALESCE(paid.plan_id, refunded.plan_id) AS plan_id,
+                plans.name,
+                COALESCE(paid.payments, 0) AS payments,
+                COALESCE(paid.lamports, 0)::BIGINT AS payment_lamports,
+                COALESCE(refunded.refunds, 0) AS refunds,
+                COALESCE(refunded.lamports, 0)::BIGINT AS refunded_lamports
+         FROM paid
+         FULL OUTER JOIN refunded ON refunded.plan_id = paid.plan_id
+         LEFT JOIN plans ON plans.plan_id = COALESCE(paid.plan_id, refunded.plan_id)
+         ORDER BY 1",
+    )
+    .bind(plan_ids)
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to compute statement totals: {}", e)))
+}
+
+/// Merchants owning a plan who opted in to monthly statements and have an email address.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_statement_recipients(pool: &PgPool) -> AppResult<Vec<String>> {
+    sqlx::query_scalar(
+        "SELECT DISTINCT plans.merchant
+         FROM plans
+         JOIN notification_preferences n ON n.owner = plans.merchant
+         WHERE n.monthly_statements AND n.email IS NOT NULL
+         ORDER BY plans.merchant",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list statement recipients: {}", e)))
+}
+
+/// Claims sending `merchant`'s statement for the month starting at `period_start`; returns
+/// false if it was already sent or is being sent.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn claim_statement(pool: &PgPool, merchant: &str, period_start: i64) -> AppResult<bool> {
+    let result = sqlx::query(
+        "INSERT INTO merchant_statements (merchant, period_start, created_at)
+         VALUES ($1, $2, $3)
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(merchant)
+    .bind(period_start)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to claim statement: {}", e)))?;
+    Ok(result.rows_affected() == 1)
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn finish_statement(pool: &PgPool, merchant: &str, period_start: i64) -> AppResult<()> {
+    sqlx::query("UPDATE merchant_statements SET sent_at = $3 WHERE merchant = $1 AND period_start = $2")
+        .bind(merchant)
+        .bind(period_start)
+        .bind(now())
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to record statement: {}", e)))?;
+    Ok(())
+}
+
+/// Gives up a claimed statement so the next run retries it.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn release_statement(pool: &PgPool, merchant: &str, period_start: i64) -> AppResult<()> {
+    sqlx::query("DELETE FROM merchant_statements WHERE merchant = $1 AND period_start = $2 AND sent_at IS NULL")
+        .bind(merchant)
+        .bind(period_start)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to release statement: {}", e)))?;
+    Ok(())
+}
+
+// Audit log
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, ToSchema)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub actor: String,
+    pub credential: String,
+    pub tenant: Option<String>,
+    pub method: String,
+    pub route: String,
+    pub path: String,
+    pub status: i32,
+    pub signatures: Vec<String>,
+    pub ip: Option<String>,
+    pub request_id: Option<String>,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewAuditEntry {
+    pub actor: String,
+    pub credential: String,
+    pub tenant: Option<String>,
+    pub method: String,
+    pub route: String,
+    pub path: String,
+    pub status: i32,
+    pub signatures: Vec<String>,
+    pub ip: Option<String>,
+    pub request_id: Option<String>,
+}
+
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn insert_audit_entry(pool: &PgPool, entry: &NewAuditEntry) -> AppResult<()> {
+    sqlx::query(
+        "INSERT INTO audit_log
+            (actor, credential, tenant, method, route, path, status, signatures, ip, request_id, created_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+    )
+    .bind(&entry.actor)
+    .bind(&entry.credential)
+    .bind(&entry.tenant)
+    .bind(&entry.method)
+    .bind(&entry.route)
+    .bind(&entry.path)
+    .bind(entry.status)
+    .bind(&entry.signatures)
+    .bind(&entry.ip)
+    .bind(&entry.request_id)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to record audit entry: {}", e)))?;
+    Ok(())
+}
+
+/// Audit entries, newest first. `signature` matches any transaction the request sent.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_audit_entries(
+    pool: &PgPool,
+    actor: Option<&str>,
+    signature: Option<&str>,
+    since: Option<i64>,
+    until: Option<i64>,
+    limit: i64,
+    offset: i64,
+) -> AppResult<Vec<AuditEntry>> {
+    sqlx::query_as::<_, AuditEntry>(
+        "SELECT * FROM audit_log
+         WHERE ($1::TEXT IS NULL OR actor = $1)
+           AND ($2::TEXT IS NULL OR $2 = ANY(signatures))
+           AND ($3::BIGINT IS NULL OR created_at >= $3)
+           AND ($4::BIGINT IS NULL OR created_at < $4)
+         ORDER BY id DESC
+         LIMIT $5 OFFSET $6",
+    )
+    .bind(actor)
+    .bind(signature)
+    .bind(since)
+    .bind(until)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list audit entries: {}", e)))
+}
+
+// Reminders
+/// Active subscriptions whose period ends within `(now + until_secs, now + from_secs]` and
+/// have no `milestone` reminder for that period yet.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_expiring_subscriptions(
+    pool: &PgPool,
+    milestone: &str,
+    now: i64,
+    from_secs: i64,
+    until_secs: i64,
+    limit: i64,
+) -> AppResult<Vec<SubscriptionRow>> {
+    sqlx::query_as::<_, SubscriptionRow>(
+        "SELECT s.* FROM subscriptions s
+         WHERE s.active AND NOT s.closed
+           AND s.start_time + s.duration <= $2 + $3
+           AND s.start_time + s.duration > $2 + $4
+           AND NOT EXISTS (
+               SELECT 1 FROM subscription_reminders r
+               WHERE r.pda = s.pda AND r.milestone = $1 AND r.period_end = s.start_time + s.duration
+           )
+         ORDER BY s.start_time + s.duration
+         LIMIT $5",
+    )
+    .bind(milestone)
+    .bind(now)
+    .bind(from_secs)
+    .bind(until_secs)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list expiring subscriptions: {}", e)))
+}
+
+/// Subscriptions the keeper expired for their current period without a `milestone` reminder.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn list_expired_subscriptions(pool: &PgPool, milestone: &str, limit: i64) -> AppResult<Vec<SubscriptionRow>> {
+    sqlx::query_as::<_, SubscriptionRow>(
+        "SELECT s.* FROM subscriptions s
+         WHERE s.active AND NOT s.closed
+           AND s.expired_at = s.start_time + s.duration
+           AND NOT EXISTS (
+               SELECT 1 FROM subscription_reminders r
+               WHERE r.pda = s.pda AND r.milestone = $1 AND r.period_end = s.expired_at
+           )
+         ORDER BY s.expired_at
+         LIMIT $2",
+    )
+    .bind(milestone)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to list expired subscriptions: {}", e)))
+}
+
+/// Records a reminder; returns false if it was already sent, so each is only sent once.
+#[tracing::instrument(target = "db", skip_all, fields(db.system = "postgresql"))]
+pub async fn claim_reminder(pool: &PgPool, pda: &str, milestone: &str, period_end: i64) -> AppResult<bool> {
+    let result = sqlx::query(
+        "INSERT INTO subscription_reminders (pda, milestone, period_end, created_at)
+         VALUES ($1, $2, $3, $4)
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(pda)
+    .bind(milestone)
+    .bind(period_end)
+    .bind(now())
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to record reminder: {}", e)))?;
+    Ok(result.rows_affected() == 1)
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
@@ -0,0 +1,281 @@
+use flate2::read::ZlibDecoder;
+use serde_json::Value;
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+use std::collections::HashMap;
+use std::io::Read;
+use std::str::FromStr;
+use crate::cluster::{Cluster, SolanaClusters};
+use crate::indexer::InstructionKind;
+use crate::layout::{subscription_discriminator, ProgramLayout};
+use crate::metrics;
+use crate::SolanaService;
+
+/// Retry-After sent while transactions are disabled by a failed deployment check.
+pub const READ_ONLY_RETRY_AFTER_SECS: u64 = 3600;
+
+// Fields of the `Subscription` account, in order, with their normalized IDL types
+const SUBSCRIPTION_FIELDS: [(&str, &str); 7] = [
+    ("user", "pubkey"),
+    ("plan_id", "u64"),
+    ("start_time", "i64"),
+    ("duration", "u64"),
+    ("amount", "u64"),
+    ("active", "bool"),
+    ("history", "vec<i64>"),
+];
+
+/// What to do when a program does not match what this backend was built against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeploymentCheck {
+    /// Refuse to start.
+    Enforce,
+    /// Start, but refuse to build transactions for the mismatched program.
+    ReadOnly,
+    /// Start and log the mismatch.
+    Warn,
+    /// Skip the check.
+    Off,
+}
+
+impl FromStr for DeploymentCheck {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "enforce" => Ok(DeploymentCheck::Enforce),
+            "read_only" | "readonly" => Ok(DeploymentCheck::ReadOnly),
+            "warn" => Ok(DeploymentCheck::Warn),
+            "off" => Ok(DeploymentCheck::Off),
+            other => Err(format!("Unknown deployment check mode {}", other)),
+        }
+    }
+}
+
+/// Address of the IDL account `anchor idl init` publishes for a program.
+pub fn idl_address(program_id: &Pubkey) -> Pubkey {
+    let (base, _bump) = Pubkey::find_program_address(&[], program_id);
+    Pubkey::create_with_seed(&base, "anchor:idl", program_id).expect("IDL seed is valid")
+}
+
+/// IDL account data: discriminator (8), authority (32), then a length-prefixed zlib stream.
+fn decode_idl(data: &[u8]) -> Result<Value, String> {
+    let len = data
+        .get(40..44)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+        .ok_or("IDL account is too short")?;
+    let compressed = data.get(44..44 + len).ok_or("IDL account is truncated")?;
+    let mut json = String::new();
+    ZlibDecoder::new(compressed)
+        .read_to_string(&mut json)
+        .map_err(|e| format!("IDL is not valid zlib: {}", e))?;
+    serde_json::from_str(&json).map_err(|e| format!("IDL is not valid JSON: {}", e))
+}
+
+/// Older IDLs name things in camelCase, newer ones in snake_case.
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            if !snake.is_empty() {
+                snake.push('_');
+            }
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+fn type_name(ty: &Value) -> String {
+    match ty {
+        Value::String(name) if name == "publicKey" => "pubkey".to_string(),
+        Value::String(name) => name.clone(),
+        Value::Object(map) if map.contains_key("vec") => format!("vec<{}>", type_name(&map["vec"])),
+        other => other.to_string(),
+    }
+}
+
+fn fields(value: Option<&Value>) -> Vec<(String, String)> {
+    value
+        .and_then(Value::as_array)
+        .map(|fields| {
+            fields
+                .iter()
+                .map(|field| {
+                    let name = field["name"].as_str().map(snake_case).unwrap_or_default();
+                    (name, type_name(&field["type"]))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn discriminator(value: &Value) -> Option<Vec<u8>> {
+    value
+        .as_array()
+        .map(|bytes| bytes.iter().filter_map(|b| b.as_u64()).map(|b| b as u8).collect())
+}
+
+/// Differences between a published IDL and the program this backend builds instructions for
+/// and decodes accounts of. Both the Anchor 0.30+ format and the older one are understood.
+fn idl_mismatches(idl: &Value, program_id: &Pubkey, layout: ProgramLayout) -> Vec<String> {
+    let mut mismatches = Vec::new();
+
+    let address = idl["address"].as_str().or_else(|| idl["metadata"]["address"].as_str());
+    if let Some(address) = address.filter(|address| *address != program_id.to_string()) {
+        mismatches.push(format!("IDL is for program {}", address));
+    }
+
+    let instructions = idl["instructions"].as_array().cloned().unwrap_or_default();
+    for kind in [InstructionKind::Create, InstructionKind::Renew, InstructionKind::Cancel, InstructionKind::Close] {
+        let named = |ix: &&Value| ix["name"].as_str().map(snake_case).as_deref() == Some(kind.name());
+        let Some(instruction) = instructions.iter().find(named) else {
+            mismatches.push(format!("IDL has no {} instruction", kind.name()));
+            continue;
+        };
+        if let Some(published) = discriminator(&instruction["discriminator"]) {
+            if published != kind.discriminator() {
+                mismatches.push(format!(
+                    "{} has discriminator {:?}, expected {:?}",
+                    kind.name(),
+                    published,
+                    kind.discriminator()
+                ));
+            }
+        }
+        let args: Vec<String> = fields(instruction.get("args"))
+            .into_iter()
+            .map(|(name, ty)| format!("{}: {}", name, ty))
+            .collect();
+        let expected: Vec<&str> = match (kind, layout) {
+            (InstructionKind::Create, ProgramLayout::Fixed) => vec!["plan_id: u64"],
+            (InstructionKind::Create, ProgramLayout::Flexible) => vec!["plan_id: u64", "duration: u64", "amount: u64"],
+            _ => vec![],
+        };
+        if args != expected {
+            mismatches.push(format!("{} takes ({}), expected ({})", kind.name(), args.join(", "), expected.join(", ")));
+        }
+    }
+
+    let is_subscription = |value: &&Value| value["name"].as_str() == Some("Subscription");
+    let account = idl["accounts"].as_array().and_then(|accounts| accounts.iter().find(is_subscription));
+    let Some(account) = account else {
+        mismatches.push("IDL has no Subscription account".to_string());
+        return mismatches;
+    };
+    if let Some(published) = discriminator(&account["discriminator"]) {
+        if published != subscription_discriminator() {
+            mismatches.push("Subscription account has a different discriminator".to_string());
+        }
+    }
+    // Newer IDLs keep the account's fields with the type definitions
+    let definition = idl["types"]
+        .as_array()
+        .and_then(|types| types.iter().find(is_subscription))
+        .unwrap_or(account);
+    let published = fields(definition["type"].get("fields"));
+    let expected: Vec<(String, String)> = SUBSCRIPTION_FIELDS
+        .iter()
+        .map(|(name, ty)| (name.to_string(), ty.to_string()))
+        .collect();
+    if published != expected {
+        let describe = |fields: &[(String, String)]| {
+            fields.iter().map(|(name, ty)| format!("{}: {}", name, ty)).collect::<Vec<_>>().join(", ")
+        };
+        mismatches.push(format!(
+            "Subscription account is {{{}}}, expected {{{}}}",
+            describe(&published),
+            describe(&expected)
+        ));
+    }
+    mismatches
+}
+
+/// Checks one deployed program. `Ok` holds the mismatches found; `Err` means the program
+/// could not be checked at all.
+async fn check_program(solana_service: &SolanaService) -> Result<Vec<String>, String> {
+    let client = solana_service.rpc.client();
+    let program_id = solana_service.program_id;
+    let program = metrics::observe_rpc(
+        "getAccountInfo",
+        client.get_account_with_commitment(&program_id, CommitmentConfig::confirmed()),
+    )
+    .await
+    .map_err(|e| format!("Failed to fetch program account: {}", e))?
+    .value;
+    let Some(program) = program else {
+        return Ok(vec![format!("Program {} is not deployed", program_id)]);
+    };
+    if !program.executable {
+        return Ok(vec![format!("Account {} is not an executable program", program_id)]);
+    }
+
+    let idl = metrics::observe_rpc(
+        "getAccountInfo",
+        client.get_account_with_commitment(&idl_address(&program_id), CommitmentConfig::confirmed()),
+    )
+    .await
+    .map_err(|e| format!("Failed to fetch IDL account: {}", e))?
+    .value;
+    let Some(idl) = idl else {
+        tracing::info!(
+            "Program {} on {} has no published IDL; only its deployment was checked",
+            program_id,
+            solana_service.cluster.as_str()
+        );
+        return Ok(Vec::new());
+    };
+    match decode_idl(&idl.data) {
+        Ok(idl) => Ok(idl_mismatches(&idl, &program_id, solana_service.layout)),
+        Err(e) => Ok(vec![e]),
+    }
+}
+
+/// Verifies every configured program against its published IDL at startup. On a mismatch
+/// it returns an error under `Enforce` and disables transactions for the affected clusters
+/// and tenants under `ReadOnly`. Programs that cannot be reached are logged and skipped, so
+/// an RPC outage does not keep the server from starting.
+pub async fn verify(clusters: &SolanaClusters, mode: DeploymentCheck) -> Result<(), String> {
+    if mode == DeploymentCheck::Off {
+        return Ok(());
+    }
+    // Tenants on one cluster may share a program; check each deployment once
+    let mut deployments: HashMap<(Cluster, Pubkey, ProgramLayout), Vec<&SolanaService>> = HashMap::new();
+    for service in clusters.all() {
+        deployments
+            .entry((service.cluster, service.program_id, service.layout))
+            .or_default()
+            .push(service);
+    }
+
+    let mut failures = Vec::new();
+    for ((cluster, program_id, _), services) in deployments {
+        let mismatches = match check_program(services[0]).await {
+            Ok(mismatches) => mismatches,
+            Err(e) => {
+                tracing::warn!("Could not verify program {} on {}: {}", program_id, cluster.as_str(), e);
+                continue;
+            }
+        };
+        if mismatches.is_empty() {
+            tracing::info!("Program {} on {} matches this backend", program_id, cluster.as_str());
+            continue;
+        }
+        for mismatch in &mismatches {
+            tracing::error!("Program {} on {}: {}", program_id, cluster.as_str(), mismatch);
+        }
+        if mode == DeploymentCheck::ReadOnly {
+            for service in services {
+                service.set_read_only();
+            }
+            tracing::warn!("Transactions for program {} on {} are disabled", program_id, cluster.as_str());
+        }
+        failures.push(format!("{} on {}", program_id, cluster.as_str()));
+    }
+
+    if mode == DeploymentCheck::Enforce && !failures.is_empty() {
+        return Err(format!("Deployed programs do not match this backend: {}", failures.join(", ")));
+    }
+    Ok(())
+}
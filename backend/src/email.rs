@@ -0,0 +1,217 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use lettre::message::{header::ContentType, Attachment, Mailbox, MultiPart, SinglePart};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::sync::{Arc, RwLock};
+use crate::{AppError, AppResult, Config};
+
+const LAMPORTS_PER_SOL: f64 = 1_000_000_000.0;
+
+#[derive(Debug, Clone)]
+pub enum EmailTemplate {
+    PaymentReceipt {
+        subscription: String,
+        plan_id: u64,
+        amount: u64, // Lamports
+        signature: String,
+    },
+    ExpiryReminder {
+        subscription: String,
+        plan_id: u64,
+        expires_at: i64,
+        expired: bool,
+    },
+    RenewalFailed {
+        subscription: String,
+        plan_id: u64,
+    },
+    ReconciliationAlert {
+        treasury: String,
+        day_start: i64,
+        on_chain: u64, // Lamports
+        indexed: u64,  // Lamports
+    },
+    MonthlyStatement {
+        merchant: String,
+        period_start: i64,
+        payments: u64,
+        gross: u64,    // Lamports
+        refunded: u64, // Lamports
+        fees: u64,     // Lamports
+        net: i64,      // Lamports
+        csv: String,   // Attached as the full statement
+    },
+}
+
+impl EmailTemplate {
+    /// The plan a subscriber email is about; operator alerts have none.
+    pub fn plan_id(&self) -> Option<u64> {
+        match self {
+            EmailTemplate::PaymentReceipt { plan_id, .. }
+            | EmailTemplate::ExpiryReminder { plan_id, .. }
+            | EmailTemplate::RenewalFailed { plan_id, .. } => Some(*plan_id),
+            EmailTemplate::ReconciliationAlert { .. } | EmailTemplate::MonthlyStatement { .. } => None,
+        }
+    }
+
+    /// File name and CSV content sent along with the body.
+    fn attachment(&self) -> Option<(String, String)> {
+        match self {
+            EmailTemplate::MonthlyStatement { period_start, csv, .. } => {
+                Some((format!("statement-{}.csv", format_month(*period_start, "%Y-%m")), csv.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Subject and plain-text body.
+    fn render(&self) -> (String, String) {
+        match self {
+            EmailTemplate::PaymentReceipt { subscription, plan_id, amount, signature } => (
+                format!("Payment receipt for plan {}", plan_id),
+                format!(
+                    "We received your payment of {} SOL for plan {}.\n\n\
+                     Subscription: {}\n\
+                     Transaction: {}\n",
+                    *amount as f64 / LAMPORTS_PER_SOL, plan_id, subscription, signature
+                ),
+            ),
+            EmailTemplate::ExpiryReminder { subscription, plan_id, expires_at, expired: false } => (
+                format!("Your plan {} subscription expires soon", plan_id),
+                format!(
+                    "Your subscription to plan {} expires at {}. Renew it to keep access.\n\n\
+                     Subscription: {}\n",
+                    plan_id, format_time(*expires_at), subscription
+                ),
+            ),
+            EmailTemplate::ExpiryReminder { subscription, plan_id, expires_at, expired: true } => (
+                format!("Your plan {} subscription has expired", plan_id),
+                format!(
+                    "Your subscription to plan {} expired at {}. Renew it to restore access.\n\n\
+                     Subscription: {}\n",
+                    plan_id, format_time(*expires_at), subscription
+                ),
+            ),
+            EmailTemplate::RenewalFailed { subscription, plan_id } => (
+                format!("Automatic renewal of plan {} failed", plan_id),
+                format!(
+                    "We could not renew your subscription to plan {} automatically, so it has expired. \
+                     Check the wallet balance and renew it manually.\n\n\
+                     Subscription: {}\n",
+                    plan_id, subscription
+                ),
+            ),
+            EmailTemplate::ReconciliationAlert { treasury, day_start, on_chain, indexed } => (
+                format!("Treasury inflows diverged from the index on {}", format_time(*day_start)),
+                format!(
+                    "The treasury received {} SOL on chain in the day from {}, but {} SOL of payments \
+                     were indexed. Check for indexing gaps or unexpected transfers.\n\n\
+                     Treasury: {}\n",
+                    *on_chain as f64 / LAMPORTS_PER_SOL, format_time(*day_start), *indexed as f64 / LAMPORTS_PER_SOL, treasury
+                ),
+            ),
+            EmailTemplate::MonthlyStatement { merchant, period_start, payments, gross, refunded, fees, net, .. } => (
+                format!("Your statement for {}", format_month(*period_start, "%B %Y")),
+                format!(
+                    "Your plans received {} payments totalling {} SOL in {}. {} SOL was refunded and \
+                     platform fees came to {} SOL, for net revenue of {} SOL. The attached CSV breaks \
+                     it down by plan.\n\n\
+                     Merchant: {}\n",
+                    payments,
+                    *gross as f64 / LAMPORTS_PER_SOL,
+                    format_month(*period_start, "%B %Y"),
+                    *refunded as f64 / LAMPORTS_PER_SOL,
+                    *fees as f64 / LAMPORTS_PER_SOL,
+                    *net as f64 / LAMPORTS_PER_SOL,
+                    merchant
+                ),
+            ),
+        }
+    }
+}
+
+fn format_month(timestamp: i64, format: &str) -> String {
+    DateTime::<Utc>::from_timestamp(timestamp, 0)
+        .map(|t| t.format(format).to_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+fn format_time(timestamp: i64) -> String {
+    DateTime::<Utc>::from_timestamp(timestamp, 0)
+        .map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+// Email Sender
+struct Smtp {
+    transport: Option<AsyncSmtpTransport<Tokio1Executor>>,
+    from: Mailbox,
+}
+
+/// SMTP sender, configured from `SMTP_URL` (e.g. `smtps://apikey:<key>@smtp.sendgrid.net`).
+/// Without it, emails are logged and dropped. The settings can be replaced at runtime with
+/// `reload`.
+#[derive(Clone)]
+pub struct EmailSender {
+    smtp: Arc<RwLock<Arc<Smtp>>>,
+}
+
+impl EmailSender {
+    pub fn new(config: &Config) -> Self {
+        let transport = config.smtp_url.as_ref().and_then(|url| {
+            match AsyncSmtpTransport::<Tokio1Executor>::from_url(url) {
+                Ok(builder) => Some(builder.build()),
+                Err(e) => {
+                    tracing::error!("Invalid SMTP_URL, emails are disabled: {}", e);
+                    None
+                }
+            }
+        });
+        let smtp = Smtp {
+            transport,
+            from: config.email_from.parse().expect("Invalid EMAIL_FROM"),
+        };
+        Self { smtp: Arc::new(RwLock::new(Arc::new(smtp))) }
+    }
+
+    /// Switches to the SMTP settings in `config`. Unlike at startup an invalid `SMTP_URL` is
+    /// an error, and the current settings are kept.
+    pub fn reload(&self, config: &Config) -> Result<(), String> {
+        let transport = config
+            .smtp_url
+            .as_ref()
+            .map(|url| AsyncSmtpTransport::<Tokio1Executor>::from_url(url).map(|builder| builder.build()))
+            .transpose()
+            .map_err(|e| format!("Invalid SMTP_URL: {}", e))?;
+        let from = config.email_from.parse().map_err(|e| format!("Invalid EMAIL_FROM: {}", e))?;
+        *self.smtp.write().unwrap() = Arc::new(Smtp { transport, from });
+        Ok(())
+    }
+
+    pub async fn send(&self, to: &str, template: &EmailTemplate) -> AppResult<()> {
+        let (subject, body) = template.render();
+        let smtp = self.smtp.read().unwrap().clone();
+        let Some(transport) = &smtp.transport else {
+            tracing::debug!("SMTP not configured, skipping email \"{}\" to {}", subject, to);
+            return Ok(());
+        };
+
+        let to: Mailbox = to
+            .parse()
+            .map_err(|e| AppError::BadRequest(format!("Invalid email address: {}", e)))?;
+        let builder = Message::builder().from(smtp.from.clone()).to(to).subject(subject);
+        let message = match template.attachment() {
+            Some((filename, content)) => builder.multipart(
+                MultiPart::mixed()
+                    .singlepart(SinglePart::plain(body))
+                    .singlepart(Attachment::new(filename).body(content, ContentType::parse("text/csv").unwrap())),
+            ),
+            None => builder.header(ContentType::TEXT_PLAIN).body(body),
+        }
+        .map_err(|e| AppError::InternalServerError(format!("Failed to build email: {}", e)))?;
+        transport
+            .send(message)
+            .await
+            .map_err(|e| AppError::InternalServerError(format!("Failed to send email: {}", e)))?;
+        Ok(())
+    }
+}
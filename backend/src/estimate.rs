@@ -0,0 +1,121 @@
+use actix_web::{get, web, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::str::FromStr;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+use crate::cluster::SolanaClusters;
+use crate::metrics;
+use crate::price::{lamports_to_usd, PriceFeed};
+use crate::simulation::SimulatedAction;
+use crate::validation::{FieldError, ValidatedQuery};
+use crate::{AppError, AppResult, AuthToken, SubscriptionRequest};
+
+// Default compute unit limit for a single instruction, which bounds the priority fee
+const COMPUTE_UNIT_LIMIT: u64 = 200_000;
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct EstimateQuery {
+    action: SimulatedAction,
+    #[validate(range(max = 9223372036854775807, message = "must fit in a signed 64-bit integer"))]
+    plan_id: u64,
+    #[validate(range(min = 1, max = 9223372036854775807, message = "must be a positive lamport amount"))]
+    amount: Option<u64>, // Required for create; renewals pay the subscription's amount
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct CostEstimate {
+    action: SimulatedAction,
+    plan_id: u64,
+    rent_lamports: u64, // Rent-exempt deposit for a new subscription account, 0 for renewals
+    base_fee_lamports: u64,
+    priority_fee_micro_lamports: u64, // Median compute unit price over recent slots
+    priority_fee_lamports: u64, // At that price for the default compute unit limit
+    amount_lamports: u64,
+    total_lamports: u64,
+    sol_usd: Option<f64>, // Unset when no price feed is configured or reachable
+    amount_usd: Option<f64>,
+    total_usd: Option<f64>,
+}
+
+fn median(mut values: Vec<u64>) -> u64 {
+    if values.is_empty() {
+        return 0;
+    }
+    values.sort_unstable();
+    values[values.len() / 2]
+}
+
+// Controllers
+/// Breaks down what a create or renew would cost the authenticated wallet right now.
+#[utoipa::path(
+    get,
+    path = "/api/v1/estimate",
+    tag = "subscriptions",
+    params(EstimateQuery),
+    responses(
+        (status = 200, description = "Cost breakdown", body = CostEstimate),
+        (status = 422, description = "Invalid query", body = ErrorResponse),
+        (status = 502, description = "RPC node unavailable, or renewal of an unknown subscription", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/estimate")]
+pub async fn estimate_cost(
+    req: HttpRequest,
+    query: ValidatedQuery<EstimateQuery>,
+    clusters: web::Data<SolanaClusters>,
+    prices: web::Data<PriceFeed>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let solana_service = clusters.select(&req)?;
+    let owner = Pubkey::from_str(&auth_token.public_key)
+        .map_err(|e| AppError::BadRequest(format!("Invalid public key: {}", e)))?;
+    let plan_id = query.plan_id;
+
+    let (instruction, rent_lamports, amount_lamports) = match query.action {
+        SimulatedAction::Create => {
+            let amount = query.amount.ok_or_else(|| {
+                AppError::Validation(vec![FieldError::new("amount", "required", "is required to estimate create")])
+            })?;
+            let request = SubscriptionRequest { plan_id, duration: 0, amount };
+            let rent = solana_service.subscription_rent().await?;
+            (solana_service.create_instruction(&owner, &request), rent, solana_service.layout.charged_amount(amount))
+        }
+        SimulatedAction::Renew => {
+            let subscription = solana_service
+                .get_subscription(&auth_token.public_key, plan_id, solana_service.read_commitment(None))
+                .await?;
+            (solana_service.renew_instruction(&owner, plan_id), 0, subscription.amount)
+        }
+    };
+
+    let writable: Vec<Pubkey> = instruction.accounts.iter().filter(|a| a.is_writable).map(|a| a.pubkey).collect();
+    let base_fee_lamports = solana_service.base_fee(&owner, instruction).await?;
+    let recent_fees = metrics::observe_rpc(
+        "getRecentPrioritizationFees",
+        solana_service.rpc.client().get_recent_prioritization_fees(&writable),
+    )
+    .await
+    .map_err(|e| AppError::rpc("Failed to get prioritization fees", e))?;
+    let priority_fee_micro_lamports = median(recent_fees.iter().map(|f| f.prioritization_fee).collect());
+    let priority_fee_lamports = (priority_fee_micro_lamports * COMPUTE_UNIT_LIMIT).div_ceil(1_000_000);
+
+    let total_lamports = rent_lamports + base_fee_lamports + priority_fee_lamports + amount_lamports;
+    let sol_usd = prices.sol_usd().await;
+    Ok(HttpResponse::Ok().json(CostEstimate {
+        action: query.action,
+        plan_id,
+        rent_lamports,
+        base_fee_lamports,
+        priority_fee_micro_lamports,
+        priority_fee_lamports,
+        amount_lamports,
+        total_lamports,
+        sol_usd,
+        amount_usd: lamports_to_usd(amount_lamports, sol_usd),
+        total_usd: lamports_to_usd(total_lamports, sol_usd),
+    }))
+}
@@ -0,0 +1,285 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use redis::aio::ConnectionManager;
+use rskafka::client::partition::{Compression, PartitionClient, UnknownTopicHandling};
+use rskafka::client::ClientBuilder;
+use rskafka::record::Record;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use crate::metrics;
+use crate::webhooks::{SubscriptionEventData, WebhookEvent, WebhookEventType, WebhookService};
+
+const PUBLISH_ATTEMPTS: u32 = 3;
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+const DEFAULT_STREAM: &str = "subscription-events";
+const DEFAULT_STREAM_MAX_LEN: u64 = 100_000;
+const DEFAULT_NATS_SUBJECT: &str = "subscriptions.events";
+const DEFAULT_KAFKA_TOPIC: &str = "subscription-events";
+
+/// A message broker events are published to.
+#[derive(Debug, Clone)]
+pub enum EventSinkConfig {
+    /// `XADD`s to the stream `EVENT_SINK_REDIS_STREAM` at `EVENT_SINK_REDIS_URL`, trimmed to
+    /// about `EVENT_SINK_REDIS_MAX_LEN` entries.
+    Redis { url: String, stream: String, max_len: u64 },
+    /// Publishes to `<EVENT_SINK_NATS_SUBJECT>.<event type>` on `EVENT_SINK_NATS_URL`.
+    Nats { url: String, subject: String },
+    /// Produces to `EVENT_SINK_KAFKA_TOPIC` on the comma-separated `EVENT_SINK_KAFKA_BROKERS`,
+    /// keyed and partitioned by subscription.
+    Kafka { brokers: Vec<String>, topic: String },
+}
+
+/// Every sink whose URL or brokers are set.
+pub fn load_event_sinks() -> Vec<EventSinkConfig> {
+    let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+
+    let mut sinks = Vec::new();
+    if let Some(url) = var("EVENT_SINK_REDIS_URL") {
+        sinks.push(EventSinkConfig::Redis {
+            url,
+            stream: var("EVENT_SINK_REDIS_STREAM").unwrap_or_else(|| DEFAULT_STREAM.to_string()),
+            max_len: var("EVENT_SINK_REDIS_MAX_LEN")
+                .map(|v| v.parse().expect("Invalid EVENT_SINK_REDIS_MAX_LEN"))
+                .unwrap_or(DEFAULT_STREAM_MAX_LEN),
+        });
+    }
+    if let Some(url) = var("EVENT_SINK_NATS_URL") {
+        sinks.push(EventSinkConfig::Nats {
+            url,
+            subject: var("EVENT_SINK_NATS_SUBJECT").unwrap_or_else(|| DEFAULT_NATS_SUBJECT.to_string()),
+        });
+    }
+    if let Some(brokers) = var("EVENT_SINK_KAFKA_BROKERS") {
+        sinks.push(EventSinkConfig::Kafka {
+            brokers: brokers.split(',').map(str::trim).filter(|s| !s.is_empty()).map(str::to_string).collect(),
+            topic: var("EVENT_SINK_KAFKA_TOPIC").unwrap_or_else(|| DEFAULT_KAFKA_TOPIC.to_string()),
+        });
+    }
+    sinks
+}
+
+/// The event types sinks receive, from `EVENT_SINK_EVENTS` (comma-separated, default all).
+pub fn load_event_types() -> Vec<WebhookEventType> {
+    match std::env::var("EVENT_SINK_EVENTS").ok().filter(|v| !v.trim().is_empty()) {
+        Some(names) => names
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|name| {
+                WebhookEventType::from_name(name)
+                    .unwrap_or_else(|| panic!("Unknown event type {} in EVENT_SINK_EVENTS", name))
+            })
+            .collect(),
+        None => WebhookEventType::ALL.to_vec(),
+    }
+}
+
+/// One broker connection. A failed publish drops the connection, so the next one reconnects.
+#[async_trait]
+trait Sink: Send {
+    fn name(&self) -> &'static str;
+
+    async fn publish(&mut self, event: &WebhookEvent<SubscriptionEventData>, body: &[u8]) -> Result<(), String>;
+}
+
+struct RedisSink {
+    url: String,
+    stream: String,
+    max_len: u64,
+    conn: Option<ConnectionManager>,
+}
+
+#[async_trait]
+impl Sink for RedisSink {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    async fn publish(&mut self, event: &WebhookEvent<SubscriptionEventData>, body: &[u8]) -> Result<(), String> {
+        let mut conn = match self.conn.clone() {
+            Some(conn) => conn,
+            None => {
+                let client = redis::Client::open(self.url.as_str()).map_err(|e| format!("Invalid Redis URL: {}", e))?;
+                let conn = ConnectionManager::new(client).await.map_err(|e| format!("Failed to connect: {}", e))?;
+                self.conn = Some(conn.clone());
+                conn
+            }
+        };
+        let result: redis::RedisResult<String> = redis::cmd("XADD")
+            .arg(&self.stream)
+            .arg("MAXLEN")
+            .arg("~")
+            .arg(self.max_len)
+            .arg("*")
+            .arg("type")
+            .arg(event.event_type.as_str())
+            .arg("event")
+            .arg(body)
+            .query_async(&mut conn)
+            .await;
+        result.map(|_| ()).map_err(|e| {
+            self.conn = None;
+            format!("XADD failed: {}", e)
+        })
+    }
+}
+
+struct NatsSink {
+    url: String,
+    subject: String,
+    client: Option<async_nats::Client>,
+}
+
+#[async_trait]
+impl Sink for NatsSink {
+    fn name(&self) -> &'static str {
+        "nats"
+    }
+
+    async fn publish(&mut self, event: &WebhookEvent<SubscriptionEventData>, body: &[u8]) -> Result<(), String> {
+        let client = match self.client.clone() {
+            Some(client) => client,
+            None => {
+                let client = async_nats::connect(self.url.as_str())
+                    .await
+                    .map_err(|e| format!("Failed to connect: {}", e))?;
+                self.client = Some(client.clone());
+                client
+            }
+        };
+        let subject = format!("{}.{}", self.subject, event.event_type.as_str());
+        let result = match client.publish(subject, body.to_vec().into()).await {
+            Ok(()) => client.flush().await.map_err(|e| format!("Flush failed: {}", e)),
+            Err(e) => Err(format!("Publish failed: {}", e)),
+        };
+        if result.is_err() {
+            self.client = None;
+        }
+        result
+    }
+}
+
+struct KafkaSink {
+    brokers: Vec<String>,
+    topic: String,
+    partitions: Vec<PartitionClient>,
+}
+
+impl KafkaSink {
+    async fn connect(&self) -> Result<Vec<PartitionClient>, String> {
+        let client = ClientBuilder::new(self.brokers.clone())
+            .build()
+            .await
+            .map_err(|e| format!("Failed to connect: {}", e))?;
+        let topics = client.list_topics().await.map_err(|e| format!("Failed to list topics: {}", e))?;
+        let topic = topics
+            .into_iter()
+            .find(|topic| topic.name == self.topic)
+            .ok_or_else(|| format!("Topic {} does not exist", self.topic))?;
+        let mut partitions = Vec::new();
+        for partition in topic.partitions {
+            let partition = client
+                .partition_client(self.topic.clone(), partition, UnknownTopicHandling::Error)
+                .await
+                .map_err(|e| format!("Failed to open partition {}: {}", partition, e))?;
+            partitions.push(partition);
+        }
+        Ok(partitions)
+    }
+}
+
+#[async_trait]
+impl Sink for KafkaSink {
+    fn name(&self) -> &'static str {
+        "kafka"
+    }
+
+    async fn publish(&mut self, event: &WebhookEvent<SubscriptionEventData>, body: &[u8]) -> Result<(), String> {
+        if self.partitions.is_empty() {
+            self.partitions = self.connect().await?;
+        }
+        // A subscription's events all go to one partition, so consumers see them in order
+        let mut hasher = DefaultHasher::new();
+        event.data.subscription.hash(&mut hasher);
+        let partition = &self.partitions[(hasher.finish() % self.partitions.len() as u64) as usize];
+        let record = Record {
+            key: Some(event.data.subscription.as_bytes().to_vec()),
+            value: Some(body.to_vec()),
+            headers: BTreeMap::from([("type".to_string(), event.event_type.as_str().as_bytes().to_vec())]),
+            timestamp: Utc::now(),
+        };
+        let result = partition.produce(vec![record], Compression::NoCompression).await;
+        result.map(|_| ()).map_err(|e| {
+            self.partitions.clear();
+            format!("Produce failed: {}", e)
+        })
+    }
+}
+
+/// Publishes every dispatched event, as the JSON webhooks receive, to each configured
+/// broker so existing data pipelines can consume them without polling. Each sink reads the
+/// live event stream on its own and retries a failed publish a few times before skipping the
+/// event; one that falls too far behind skips events too. Both are counted on
+/// `event_sink_events_total`. Replicas each publish the events they dispatch; consumers can
+/// deduplicate on the event `id`.
+pub fn spawn(sinks: &[EventSinkConfig], events: &[WebhookEventType], webhooks: &WebhookService) {
+    for config in sinks {
+        let sink: Box<dyn Sink> = match config.clone() {
+            EventSinkConfig::Redis { url, stream, max_len } => Box::new(RedisSink { url, stream, max_len, conn: None }),
+            EventSinkConfig::Nats { url, subject } => Box::new(NatsSink { url, subject, client: None }),
+            EventSinkConfig::Kafka { brokers, topic } => Box::new(KafkaSink { brokers, topic, partitions: Vec::new() }),
+        };
+        tracing::info!("Publishing events to {}", sink.name());
+        tokio::spawn(run(sink, events.to_vec(), webhooks.subscribe_events()));
+    }
+}
+
+async fn run(
+    mut sink: Box<dyn Sink>,
+    events: Vec<WebhookEventType>,
+    mut receiver: broadcast::Receiver<WebhookEvent<SubscriptionEventData>>,
+) {
+    loop {
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                tracing::warn!("Event sink {} fell behind and skipped {} events", sink.name(), missed);
+                metrics::record_event_sink(sink.name(), "skipped", missed);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        if !events.contains(&event.event_type) {
+            continue;
+        }
+        let body = match serde_json::to_vec(&event) {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::error!("Failed to serialize event {}: {}", event.id, e);
+                continue;
+            }
+        };
+        let mut attempt = 1;
+        loop {
+            match sink.publish(&event, &body).await {
+                Ok(()) => {
+                    metrics::record_event_sink(sink.name(), "published", 1);
+                    break;
+                }
+                Err(e) if attempt < PUBLISH_ATTEMPTS => {
+                    tracing::debug!("Publishing event {} to {} failed, retrying: {}", event.id, sink.name(), e);
+                    tokio::time::sleep(RETRY_BACKOFF * attempt).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to publish event {} to {}: {}", event.id, sink.name(), e);
+                    metrics::record_event_sink(sink.name(), "failed", 1);
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use actix_web::{get, web, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::postgres::PgPool;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+use crate::db::{self, EventLogRow};
+use crate::plans::PlanService;
+use crate::validation::{FieldError, ValidatedQuery};
+use crate::webhooks::{SubscriptionEventData, WebhookEvent, WebhookEventType};
+use crate::{AppError, AppResult, AuthToken, Config};
+
+const DEFAULT_LIMIT: usize = 100;
+const SCAN_SIZE: i64 = 500;
+// Events recorded by other replicas are only noticed by reading the log again
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// How long a gap in the cursors is waited on before it is taken for a rolled-back insert
+const GAP_SETTLE_SECS: i64 = 2;
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct EventsQuery {
+    #[validate(range(min = 0, message = "must not be negative"))]
+    since_cursor: Option<i64>, // Default the latest event, so only events after the call are returned
+    timeout: Option<u64>, // Seconds to wait for an event; default and at most EVENT_POLL_MAX_WAIT_SECS
+    event_types: Option<String>, // Comma-separated, default every type
+    plan_id: Option<u64>,
+    #[validate(range(min = 1, max = 500, message = "must be between 1 and 500"))]
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct PolledEvent {
+    cursor: i64,
+    #[schema(value_type = Object)]
+    event: Value, // As dispatched to webhooks, in the v1 shape without `version`
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct EventsResponse {
+    events: Vec<PolledEvent>, // Empty when the wait timed out
+    next_cursor: i64, // Pass as `since_cursor` on the next call
+}
+
+struct EventFilter {
+    event_types: Vec<String>, // Empty for every type
+    plan_ids: Option<Vec<i64>>, // None for every plan
+}
+
+impl EventFilter {
+    fn matches(&self, row: &EventLogRow) -> bool {
+        (self.event_types.is_empty() || self.event_types.contains(&row.event_type))
+            && self.plan_ids.as_ref().is_none_or(|ids| ids.contains(&row.plan_id))
+    }
+}
+
+// Event Log Service
+/// Keeps dispatched events in the `event_log` table for `retention_secs`, so integrators that
+/// cannot hold a WebSocket or gRPC stream open can long-poll for them by cursor instead.
+/// Each replica records the events it dispatches; cursors are shared by all of them.
+#[derive(Clone)]
+pub struct EventLogService {
+    pool: PgPool,
+    retention_secs: i64,
+    max_wait: Duration,
+    recorded: Arc<watch::Sender<i64>>, // Cursor of the last event recorded by this replica
+}
+
+impl EventLogService {
+    pub fn new(config: &Config, pool: PgPool) -> Self {
+        Self {
+            pool,
+            retention_secs: config.event_log_retention_secs as i64,
+            max_wait: Duration::from_secs(config.event_poll_max_wait_secs),
+            recorded: Arc::new(watch::channel(0).0),
+        }
+    }
+
+    /// Appends every event from the live stream to the log. An event missed because the
+    /// recorder fell behind is logged and not recorded.
+    pub async fn run_recorder(self, mut events: broadcast::Receiver<WebhookEvent<SubscriptionEventData>>) {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!("Event log fell behind and did not record {} events", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+            let body = match serde_json::to_string(&event) {
+                Ok(body) => body,
+                Err(e) => {
+                    tracing::error!("Failed to serialize event {}: {}", event.id, e);
+                    continue;
+                }
+            };
+            let recorded = db::insert_event_log(
+                &self.pool,
+                &event.id,
+                event.event_type.as_str(),
+                event.data.plan_id as i64,
+                &body,
+            )
+            .await;
+            match recorded {
+                Ok(cursor) => {
+                    self.recorded.send_replace(cursor);
+                }
+                Err(e) => tracing::error!("Failed to record event {} in the event log: {}", event.id, e),
+            }
+        }
+    }
+
+    /// Drops events past retention.
+    pub async fn run_cleanup(self) {
+        loop {
+            if let Err(e) = db::prune_event_log(&self.pool, now() - self.retention_secs).await {
+                tracing::error!("Failed to remove expired events: {}", e);
+            }
+            tokio::time::sleep(CLEANUP_INTERVAL).await;
+        }
+    }
+
+    /// Events after `since` that pass `filter`, waiting up to `wait` for the first one. Events
+    /// skipped by the filter still move the returned cursor on.
+    async fn poll(
+        &self,
+        since: Option<i64>,
+        filter: &EventFilter,
+        limit: usize,
+        wait: Duration,
+    ) -> AppResult<EventsResponse> {
+        let deadline = Instant::now() + wait.min(self.max_wait);
+        // Subscribed before reading, so an event recorded in between still wakes the wait
+        let mut recorded = self.recorded.subscribe();
+        let mut cursor = match since {
+            Some(cursor) => cursor,
+            None => db::latest_event_cursor(&self.pool).await?,
+        };
+        loop {
+            let rows = db::list_event_log(&self.pool, cursor, SCAN_SIZE).await?;
+            let full_page = rows.len() as i64 == SCAN_SIZE;
+            let scanned_from = cursor;
+            let mut events = Vec::new();
+            for row in settled(rows, cursor) {
+                cursor = row.cursor;
+                if filter.matches(&row) {
+                    events.push(PolledEvent {
+                        cursor: row.cursor,
+                        event: serde_json::from_str(&row.body).unwrap_or(Value::Null),
+                    });
+                    if events.len() == limit {
+                        break;
+                    }
+                }
+            }
+            if !events.is_empty() {
+                return Ok(EventsResponse { events, next_cursor: cursor });
+            }
+            if full_page && cursor > scanned_from {
+                continue;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(EventsResponse { events, next_cursor: cursor });
+            }
+            let _ = tokio::time::timeout(remaining.min(POLL_INTERVAL), recorded.changed()).await;
+        }
+    }
+}
+
+/// `rows` up to the first gap in the cursors that is younger than `GAP_SETTLE_SECS`: the
+/// event there may still be committing, and reading past it would skip it for good.
+fn settled(rows: Vec<EventLogRow>, after: i64) -> Vec<EventLogRow> {
+    let now = now();
+    let mut expected = after + 1;
+    let mut settled = Vec::with_capacity(rows.len());
+    for row in rows {
+        if row.cursor != expected && row.recorded_at > now - GAP_SETTLE_SECS {
+            break;
+        }
+        expected = row.cursor + 1;
+        settled.push(row);
+    }
+    settled
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+// Controllers
+/// Long-polls for events after `since_cursor`, for integrators whose proxies break
+/// WebSockets and SSE. Returns as soon as there are events, or with none once `timeout`
+/// seconds pass.
+#[utoipa::path(
+    get,
+    path = "/api/v1/events",
+    tag = "webhooks",
+    params(EventsQuery),
+    responses(
+        (status = 200, description = "Events after the cursor, oldest first, or none if the wait timed out", body = EventsResponse),
+        (status = 403, description = "Merchant role required, or plan belongs to another merchant", body = ErrorResponse),
+        (status = 422, description = "Invalid query", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []), ("api_key" = []))
+)]
+#[get("")]
+pub async fn poll_events(
+    req: HttpRequest,
+    query: ValidatedQuery<EventsQuery>,
+    event_log: web::Data<EventLogService>,
+    plans: web::Data<PlanService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let event_types = query
+        .event_types
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|name| {
+            WebhookEventType::from_name(name).map(|event_type| event_type.as_str().to_string()).ok_or_else(|| {
+                AppError::Validation(vec![FieldError::new(
+                    "event_types",
+                    "invalid",
+                    format!("unknown event type {}", name),
+                )])
+            })
+        })
+        .collect::<AppResult<Vec<_>>>()?;
+    let filter = EventFilter {
+        event_types,
+        plan_ids: plans.scope(&auth_token).await?.narrow(query.plan_id)?,
+    };
+    let wait = query.timeout.map_or(event_log.max_wait, Duration::from_secs);
+    let response = event_log
+        .poll(query.since_cursor, &filter, query.limit.unwrap_or(DEFAULT_LIMIT), wait)
+        .await?;
+    Ok(HttpResponse::Ok().json(response))
+}
@@ -0,0 +1,602 @@
+use actix_web::http::header::CONTENT_DISPOSITION;
+use actix_web::{get, post, web, web::Bytes, HttpMessage, HttpRequest, HttpResponse};
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures_util::stream::{self, Stream};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+use crate::cluster::SolanaClusters;
+use crate::customers;
+use crate::db::{self, PaymentRow};
+use crate::pii::PiiVault;
+use crate::plans::PlanService;
+use crate::validation::{FieldError, ValidatedJson, ValidatedQuery};
+use crate::{AppError, AppResult, AuthToken, Config};
+
+const PAGE_SIZE: i64 = 500;
+const LIST_LIMIT: i64 = 50;
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+const STALE_JOB_SECS: i64 = 3600; // Pending longer than this means the worker was interrupted
+const LAMPORTS_PER_SOL: f64 = 1_000_000_000.0;
+const CSV_HEADER: &str =
+    "invoice_number,timestamp,block_time,slot,signature,instruction_index,subscription,owner,plan_id,kind,amount_lamports,amount_sol,refunded_lamports,customer_email,customer_name\n";
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "csv" => Some(ExportFormat::Csv),
+            "json" => Some(ExportFormat::Json),
+            _ => None,
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Json => "application/json",
+        }
+    }
+
+    fn begin(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => CSV_HEADER,
+            ExportFormat::Json => "[",
+        }
+    }
+
+    fn end(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "",
+            ExportFormat::Json => "]",
+        }
+    }
+
+    fn write(&self, out: &mut String, record: &PaymentRecord, first: bool) {
+        match self {
+            // Every field but the customer's is numeric, base58 or a fixed word, so only those
+            // need quoting
+            ExportFormat::Csv => {
+                let _ = writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                    record.invoice_number,
+                    record.timestamp.as_deref().unwrap_or_default(),
+                    record.block_time.map(|t| t.to_string()).unwrap_or_default(),
+                    record.slot,
+                    record.signature,
+                    record.instruction_index,
+                    record.subscription,
+                    record.owner,
+                    record.plan_id,
+                    record.kind,
+                    record.amount_lamports,
+                    record.amount_sol,
+                    record.refunded_lamports,
+                    csv_field(record.customer_email.as_deref().unwrap_or_default()),
+                    csv_field(record.customer_name.as_deref().unwrap_or_default()),
+                );
+            }
+            ExportFormat::Json => {
+                if !first {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::to_string(record).unwrap_or_default());
+            }
+        }
+    }
+}
+
+/// One indexed payment as it appears in an export.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct PaymentRecord {
+    invoice_number: String, // Derived from the transaction, stable across exports
+    timestamp: Option<String>, // RFC 3339, unset when the block time is unknown
+    block_time: Option<i64>,
+    slot: i64,
+    signature: String,
+    instruction_index: i32,
+    subscription: String,
+    owner: String,
+    plan_id: u64,
+    kind: String, // create | renew
+    amount_lamports: u64,
+    amount_sol: f64,
+    refunded_lamports: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    customer_email: Option<String>, // From the plan merchant's customer directory, in merchant exports
+    #[serde(skip_serializing_if = "Option::is_none")]
+    customer_name: Option<String>,
+}
+
+impl From<PaymentRow> for PaymentRecord {
+    fn from(row: PaymentRow) -> Self {
+        PaymentRecord {
+            invoice_number: invoice_number(&row.signature, row.instruction_index),
+            timestamp: row.block_time.and_then(format_time),
+            block_time: row.block_time,
+            slot: row.slot,
+            signature: row.signature,
+            instruction_index: row.instruction_index,
+            subscription: row.pda,
+            owner: row.owner,
+            plan_id: row.plan_id as u64,
+            kind: row.kind,
+            amount_lamports: row.amount as u64,
+            amount_sol: row.amount as f64 / LAMPORTS_PER_SOL,
+            refunded_lamports: row.refunded_lamports as u64,
+            customer_email: None,
+            customer_name: None,
+        }
+    }
+}
+
+/// Quotes a CSV field that holds a separator, quote or line break.
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn invoice_number(signature: &str, instruction_index: i32) -> String {
+    format!("INV-{}-{}", &signature[..signature.len().min(16)], instruction_index)
+}
+
+fn format_time(timestamp: i64) -> Option<String> {
+    DateTime::<Utc>::from_timestamp(timestamp, 0).map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct ExportQuery {
+    format: Option<ExportFormat>, // Defaults to csv
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct ExportJobRequest {
+    format: Option<ExportFormat>, // Defaults to csv
+    #[validate(range(max = 9223372036854775807, message = "must fit in a signed 64-bit integer"))]
+    plan_id: Option<u64>, // Every plan when unset
+    #[validate(range(min = 0, message = "must not be negative"))]
+    from: Option<i64>, // Unix seconds, inclusive
+    #[validate(range(min = 0, message = "must not be negative"))]
+    to: Option<i64>, // Unix seconds, exclusive
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow, ToSchema)]
+pub struct ExportJob {
+    id: String,
+    format: String,
+    plan_id: Option<i64>,
+    from_time: Option<i64>,
+    to_time: Option<i64>,
+    status: String, // pending | completed | failed
+    row_count: i64,
+    error: Option<String>,
+    created_at: i64,
+    completed_at: Option<i64>,
+}
+
+const JOB_COLUMNS: &str =
+    "id, format, plan_id, from_time, to_time, status, row_count, error, created_at, completed_at";
+
+/// Which payments an export covers.
+#[derive(Debug, Clone, Default)]
+struct PaymentFilter {
+    pda: Option<String>,
+    plan_ids: Option<Vec<i64>>, // None for every plan
+    from: Option<i64>,
+    to: Option<i64>,
+    customers: bool, // Add the merchants' customer directory entries
+}
+
+/// Keyset position of an export in progress.
+#[derive(Default)]
+struct Cursor {
+    after: Option<(i64, String, i32)>,
+    rows: usize,
+    done: bool,
+}
+
+// Export Service
+/// Exports the indexed payment history. Single subscriptions are streamed straight to the
+/// client page by page; merchant-wide exports run as background jobs whose output is kept
+/// for `retention_secs`.
+#[derive(Clone)]
+pub struct ExportService {
+    pool: PgPool,
+    vault: PiiVault, // Opens the customer details added to exports
+    retention_secs: i64,
+}
+
+impl ExportService {
+    pub fn new(config: &Config, pool: PgPool) -> Self {
+        Self {
+            pool,
+            vault: PiiVault::new(&config.tenants),
+            retention_secs: config.export_retention_secs as i64,
+        }
+    }
+
+    fn stream(&self, filter: PaymentFilter, format: ExportFormat) -> impl Stream<Item = AppResult<Bytes>> {
+        stream::unfold(
+            (self.pool.clone(), self.vault.clone(), filter, Cursor::default()),
+            move |(pool, vault, filter, mut cursor)| async move {
+                match next_chunk(&pool, &vault, &filter, format, &mut cursor).await {
+                    Ok(Some(chunk)) => Some((Ok(Bytes::from(chunk)), (pool, vault, filter, cursor))),
+                    Ok(None) => None,
+                    Err(e) => {
+                        cursor.done = true;
+                        Some((Err(e), (pool, vault, filter, cursor)))
+                    }
+                }
+            },
+        )
+    }
+
+    /// Queues an export of the payments of `plan_ids` (`None` for every plan), kept with the job.
+    pub async fn create_job(
+        &self,
+        requested_by: &str,
+        plan_ids: Option<Vec<i64>>,
+        req: ExportJobRequest,
+    ) -> AppResult<ExportJob> {
+        if let (Some(from), Some(to)) = (req.from, req.to) {
+            if from >= to {
+                return Err(AppError::Validation(vec![FieldError::new("from", "window", "must be before to")]));
+            }
+        }
+        let format = req.format.unwrap_or(ExportFormat::Csv);
+        let job = sqlx::query_as::<_, ExportJob>(&format!(
+            "INSERT INTO payment_exports (id, requested_by, format, plan_id, plan_ids, from_time, to_time, status, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, 'pending', $8)
+             RETURNING {}",
+            JOB_COLUMNS
+        ))
+        .bind(random_hex(16))
+        .bind(requested_by)
+        .bind(format.as_str())
+        .bind(req.plan_id.map(|id| id as i64))
+        .bind(&plan_ids)
+        .bind(req.from)
+        .bind(req.to)
+        .bind(now())
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to create export: {}", e)))?;
+
+        let filter = PaymentFilter {
+            pda: None,
+            plan_ids,
+            from: job.from_time,
+            to: job.to_time,
+            customers: true,
+        };
+        let service = self.clone();
+        let id = job.id.clone();
+        tokio::spawn(async move { service.run_job(&id, filter, format).await });
+        Ok(job)
+    }
+
+    async fn run_job(&self, id: &str, filter: PaymentFilter, format: ExportFormat) {
+        let mut cursor = Cursor::default();
+        let mut content = String::new();
+        let outcome = loop {
+            match next_chunk(&self.pool, &self.vault, &filter, format, &mut cursor).await {
+                Ok(Some(chunk)) => content.push_str(&chunk),
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+
+        let result = match outcome {
+            Ok(()) => {
+                sqlx::query(
+                    "UPDATE payment_exports SET status = 'completed', content = $2, row_count = $3, completed_at = $4
+                     WHERE id = $1",
+                )
+                .bind(id)
+                .bind(&content)
+                .bind(cursor.rows as i64)
+                .bind(now())
+                .execute(&self.pool)
+                .await
+            }
+            Err(e) => {
+                tracing::error!("Payment export {} failed: {}", id, e);
+                sqlx::query("UPDATE payment_exports SET status = 'failed', error = $2, completed_at = $3 WHERE id = $1")
+                    .bind(id)
+                    .bind(e.to_string())
+                    .bind(now())
+                    .execute(&self.pool)
+                    .await
+            }
+        };
+        if let Err(e) = result {
+            tracing::error!("Failed to store payment export {}: {}", id, e);
+        }
+    }
+
+    pub async fn list_jobs(&self, requested_by: &str) -> AppResult<Vec<ExportJob>> {
+        sqlx::query_as::<_, ExportJob>(&format!(
+            "SELECT {} FROM payment_exports WHERE requested_by = $1 ORDER BY created_at DESC LIMIT $2",
+            JOB_COLUMNS
+        ))
+        .bind(requested_by)
+        .bind(LIST_LIMIT)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to list exports: {}", e)))
+    }
+
+    pub async fn find_job(&self, requested_by: &str, id: &str) -> AppResult<ExportJob> {
+        sqlx::query_as::<_, ExportJob>(&format!(
+            "SELECT {} FROM payment_exports WHERE id = $1 AND requested_by = $2",
+            JOB_COLUMNS
+        ))
+        .bind(id)
+        .bind(requested_by)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to fetch export: {}", e)))?
+        .ok_or_else(|| AppError::NotFound(format!("Export {} not found", id)))
+    }
+
+    pub async fn job_content(&self, requested_by: &str, id: &str) -> AppResult<(ExportJob, String)> {
+        let job = self.find_job(requested_by, id).await?;
+        match job.status.as_str() {
+            "completed" => {}
+            "failed" => return Err(AppError::BadRequest(format!("Export {} failed: {}", id, job.error.unwrap_or_default()))),
+            _ => return Err(AppError::Conflict(format!("Export {} is still running", id))),
+        }
+        let content = sqlx::query_scalar::<_, Option<String>>("SELECT content FROM payment_exports WHERE id = $1")
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to fetch export: {}", e)))?;
+        Ok((job, content.unwrap_or_default()))
+    }
+
+    /// Drops exports past retention and fails jobs whose worker died with the process.
+    pub async fn run_cleanup(self) {
+        loop {
+            let expired = sqlx::query("DELETE FROM payment_exports WHERE created_at < $1")
+                .bind(now() - self.retention_secs)
+                .execute(&self.pool)
+                .await;
+            if let Err(e) = expired {
+                tracing::error!("Failed to remove expired payment exports: {}", e);
+            }
+            let stale = sqlx::query(
+                "UPDATE payment_exports SET status = 'failed', error = 'interrupted', completed_at = $2
+                 WHERE status = 'pending' AND created_at < $1",
+            )
+            .bind(now() - STALE_JOB_SECS)
+            .bind(now())
+            .execute(&self.pool)
+            .await;
+            if let Err(e) = stale {
+                tracing::error!("Failed to fail interrupted payment exports: {}", e);
+            }
+            tokio::time::sleep(CLEANUP_INTERVAL).await;
+        }
+    }
+}
+
+/// Encodes the next page of payments, or `None` once the closing chunk has been returned.
+async fn next_chunk(
+    pool: &PgPool,
+    vault: &PiiVault,
+    filter: &PaymentFilter,
+    format: ExportFormat,
+    cursor: &mut Cursor,
+) -> AppResult<Option<String>> {
+    if cursor.done {
+        return Ok(None);
+    }
+    let rows = db::list_payments_page(
+        pool,
+        filter.pda.as_deref(),
+        filter.plan_ids.as_deref(),
+        filter.from,
+        filter.to,
+        cursor.after.as_ref(),
+        PAGE_SIZE,
+    )
+    .await?;
+
+    let mut chunk = String::new();
+    if cursor.after.is_none() {
+        chunk.push_str(format.begin());
+    }
+    let full_page = rows.len() as i64 == PAGE_SIZE;
+    if let Some(last) = rows.last() {
+        cursor.after = Some((last.slot, last.signature.clone(), last.instruction_index));
+    }
+    let contacts = if filter.customers {
+        let keys: Vec<(String, i64)> = rows.iter().map(|row| (row.owner.clone(), row.plan_id)).collect();
+        customers::contacts(pool, vault, &keys).await?
+    } else {
+        Default::default()
+    };
+    for row in rows {
+        let contact = contacts.get(&(row.owner.clone(), row.plan_id));
+        let mut record = PaymentRecord::from(row);
+        if let Some(contact) = contact {
+            record.customer_email = contact.email().map(str::to_string);
+            record.customer_name = contact.display_name().map(str::to_string);
+        }
+        format.write(&mut chunk, &record, cursor.rows == 0);
+        cursor.rows += 1;
+    }
+    if !full_page {
+        chunk.push_str(format.end());
+        cursor.done = true;
+    }
+    Ok(Some(chunk))
+}
+
+fn attachment(name: &str, format: ExportFormat) -> (actix_web::http::header::HeaderName, String) {
+    (CONTENT_DISPOSITION, format!("attachment; filename=\"{}.{}\"", name, format.as_str()))
+}
+
+fn random_hex(len: usize) -> String {
+    let bytes: Vec<u8> = (0..len).map(|_| rand::random::<u8>()).collect();
+    hex::encode(bytes)
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+// Controllers
+/// Streams the caller's payment history for one subscription, oldest first.
+#[utoipa::path(
+    get,
+    path = "/api/v1/subscriptions/{plan_id}/payments/export",
+    tag = "subscriptions",
+    params(("plan_id" = u64, Path, description = "Plan identifier"), ExportQuery),
+    responses(
+        (status = 200, description = "CSV rows, or a JSON array of payments", body = [PaymentRecord]),
+        (status = 400, description = "Cluster is not indexed", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/subscriptions/{plan_id}/payments/export")]
+pub async fn export_subscription_payments(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    query: ValidatedQuery<ExportQuery>,
+    clusters: web::Data<SolanaClusters>,
+    exports: web::Data<ExportService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let solana_service = clusters.select(&req)?;
+    if !solana_service.is_primary() {
+        return Err(AppError::BadRequest("Payment history is only indexed for the default tenant on the primary cluster".to_string()));
+    }
+    let plan_id = path.into_inner();
+    let pda = solana_service.subscription_address(&auth_token.public_key, plan_id)?;
+    let format = query.format.unwrap_or(ExportFormat::Csv);
+    let filter = PaymentFilter {
+        pda: Some(pda.to_string()),
+        ..PaymentFilter::default()
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(attachment(&format!("payments-plan-{}", plan_id), format))
+        .streaming(exports.stream(filter, format)))
+}
+
+/// Starts a background export of every payment of the caller's plans (every plan for admins),
+/// optionally narrowed to one plan and a time window.
+#[utoipa::path(
+    post,
+    path = "/api/v1/exports",
+    tag = "exports",
+    request_body = ExportJobRequest,
+    responses(
+        (status = 202, description = "Export queued", body = ExportJob),
+        (status = 403, description = "Merchant role required, or the plan belongs to another merchant", body = ErrorResponse),
+        (status = 422, description = "Invalid filter", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("")]
+pub async fn create_export(
+    req: HttpRequest,
+    exports: web::Data<ExportService>,
+    plans: web::Data<PlanService>,
+    body: ValidatedJson<ExportJobRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let body = body.into_inner();
+    let plan_ids = plans.scope(&auth_token).await?.narrow(body.plan_id)?;
+    let job = exports.create_job(&auth_token.public_key, plan_ids, body).await?;
+    Ok(HttpResponse::Accepted().json(job))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/exports",
+    tag = "exports",
+    responses((status = 200, description = "The caller's recent exports, newest first", body = [ExportJob])),
+    security(("bearer_auth" = []))
+)]
+#[get("")]
+pub async fn list_exports(
+    req: HttpRequest,
+    exports: web::Data<ExportService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let jobs = exports.list_jobs(&auth_token.public_key).await?;
+    Ok(HttpResponse::Ok().json(jobs))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/exports/{id}",
+    tag = "exports",
+    params(("id" = String, Path, description = "Export identifier")),
+    responses(
+        (status = 200, description = "Export status", body = ExportJob),
+        (status = 404, description = "Export not found", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/{id}")]
+pub async fn get_export(
+    req: HttpRequest,
+    path: web::Path<String>,
+    exports: web::Data<ExportService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let job = exports.find_job(&auth_token.public_key, &path.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(job))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/exports/{id}/download",
+    tag = "exports",
+    params(("id" = String, Path, description = "Export identifier")),
+    responses(
+        (status = 200, description = "Export file in the requested format"),
+        (status = 400, description = "Export failed", body = ErrorResponse),
+        (status = 404, description = "Export not found", body = ErrorResponse),
+        (status = 409, description = "Export still running", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/{id}/download")]
+pub async fn download_export(
+    req: HttpRequest,
+    path: web::Path<String>,
+    exports: web::Data<ExportService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let (job, content) = exports.job_content(&auth_token.public_key, &path.into_inner()).await?;
+    let format = ExportFormat::from_name(&job.format).unwrap_or(ExportFormat::Csv);
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(attachment(&format!("payments-{}", job.id), format))
+        .body(content))
+}
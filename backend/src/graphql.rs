@@ -0,0 +1,467 @@
+use actix_web::{get, post, web, HttpMessage, HttpRequest, HttpResponse};
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::futures_util::Stream;
+use async_graphql::{Context, Data, EmptyMutation, Object, Result, Schema, Subscription};
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse, GraphQLSubscription};
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::StreamExt;
+use crate::analytics::{self, AnalyticsService, ChurnResponse, Granularity, MrrResponse, RevenuePoint, SubscriberBreakdown};
+use crate::db::{self, PaymentRow, PlanStatsRow, SubscriptionRow};
+use crate::plans::{PlanScope, PlanService};
+use crate::price::lamports_to_usd;
+use crate::webhooks::{SubscriptionEventData, WebhookEvent, WebhookEventType, WebhookService};
+use crate::{AppError, AppResult, AuthToken};
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 500;
+const MAX_DEPTH: usize = 8;
+const MAX_COMPLEXITY: usize = 2000;
+
+pub type DashboardSchema = Schema<QueryRoot, EmptyMutation, SubscriptionRoot>;
+
+/// Read-only schema over the indexer tables and analytics. Nested lookups go through
+/// batching data loaders, so a page of subscriptions costs one query per level rather than
+/// one per row. Each request carries the caller's `PlanScope`, and root fields only reach
+/// the plans in it.
+pub fn build_schema(pool: PgPool, analytics: AnalyticsService, webhook_service: WebhookService) -> DashboardSchema {
+    Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot)
+        .data(DataLoader::new(SubscriptionLoader { pool: pool.clone() }, tokio::spawn))
+        .data(DataLoader::new(PaymentsLoader { pool: pool.clone() }, tokio::spawn))
+        .data(DataLoader::new(PlanLoader { pool: pool.clone() }, tokio::spawn))
+        .data(pool)
+        .data(analytics)
+        .data(webhook_service)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+/// The caller's plans, narrowed to `plan_id` when given; `None` for every plan.
+fn plan_ids(ctx: &Context<'_>, plan_id: Option<i64>) -> Result<Option<Vec<i64>>> {
+    Ok(ctx.data_unchecked::<PlanScope>().narrow(plan_id.map(|id| id as u64))?)
+}
+
+fn page(limit: Option<i64>, offset: Option<i64>) -> (i64, i64) {
+    (limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE), offset.unwrap_or(0).max(0))
+}
+
+// Data Loaders
+pub struct SubscriptionLoader {
+    pool: PgPool,
+}
+
+impl Loader<String> for SubscriptionLoader {
+    type Value = SubscriptionRow;
+    type Error = Arc<AppError>;
+
+    async fn load(&self, pdas: &[String]) -> Result<HashMap<String, SubscriptionRow>, Self::Error> {
+        let rows = db::find_subscriptions(&self.pool, pdas).await.map_err(Arc::new)?;
+        Ok(rows.into_iter().map(|row| (row.pda.clone(), row)).collect())
+    }
+}
+
+pub struct PaymentsLoader {
+    pool: PgPool,
+}
+
+impl Loader<String> for PaymentsLoader {
+    type Value = Vec<PaymentRow>;
+    type Error = Arc<AppError>;
+
+    async fn load(&self, pdas: &[String]) -> Result<HashMap<String, Vec<PaymentRow>>, Self::Error> {
+        let mut payments: HashMap<String, Vec<PaymentRow>> = HashMap::new();
+        for row in db::list_payments_for(&self.pool, pdas).await.map_err(Arc::new)? {
+            payments.entry(row.pda.clone()).or_default().push(row);
+        }
+        Ok(payments)
+    }
+}
+
+pub struct PlanLoader {
+    pool: PgPool,
+}
+
+impl Loader<i64> for PlanLoader {
+    type Value = PlanStatsRow;
+    type Error = Arc<AppError>;
+
+    async fn load(&self, plan_ids: &[i64]) -> Result<HashMap<i64, PlanStatsRow>, Self::Error> {
+        let rows = db::list_plan_stats(&self.pool, Some(plan_ids)).await.map_err(Arc::new)?;
+        Ok(rows.into_iter().map(|row| (row.plan_id, row)).collect())
+    }
+}
+
+// Types
+pub struct SubscriptionNode(SubscriptionRow);
+
+#[Object(name = "SubscriptionAccount")]
+impl SubscriptionNode {
+    /// Subscription PDA address.
+    async fn pda(&self) -> &str {
+        &self.0.pda
+    }
+
+    async fn owner(&self) -> &str {
+        &self.0.owner
+    }
+
+    async fn plan_id(&self) -> i64 {
+        self.0.plan_id
+    }
+
+    async fn start_time(&self) -> i64 {
+        self.0.start_time
+    }
+
+    async fn duration(&self) -> i64 {
+        self.0.duration
+    }
+
+    async fn amount(&self) -> i64 {
+        self.0.amount
+    }
+
+    async fn active(&self) -> bool {
+        self.0.active
+    }
+
+    async fn closed(&self) -> bool {
+        self.0.closed
+    }
+
+    /// End of the current billing period.
+    async fn expires_at(&self) -> i64 {
+        self.0.start_time + self.0.duration
+    }
+
+    async fn history(&self) -> &[i64] {
+        &self.0.history
+    }
+
+    /// Indexed payments, newest first.
+    async fn payments(&self, ctx: &Context<'_>) -> Result<Vec<PaymentNode>> {
+        let payments = ctx.data_unchecked::<DataLoader<PaymentsLoader>>().load_one(self.0.pda.clone()).await?;
+        Ok(payments.unwrap_or_default().into_iter().map(PaymentNode).collect())
+    }
+
+    async fn plan(&self, ctx: &Context<'_>) -> Result<Option<PlanNode>> {
+        let plan = ctx.data_unchecked::<DataLoader<PlanLoader>>().load_one(self.0.plan_id).await?;
+        Ok(plan.map(PlanNode))
+    }
+}
+
+pub struct PaymentNode(PaymentRow);
+
+#[Object(name = "Payment")]
+impl PaymentNode {
+    async fn signature(&self) -> &str {
+        &self.0.signature
+    }
+
+    async fn instruction_index(&self) -> i32 {
+        self.0.instruction_index
+    }
+
+    /// `create_subscription` or `renew_subscription`.
+    async fn kind(&self) -> &str {
+        &self.0.kind
+    }
+
+    async fn amount(&self) -> i64 {
+        self.0.amount
+    }
+
+    /// USD value at the SOL/USD rate when the payment was indexed, if it was indexed as it
+    /// landed.
+    async fn paid_usd(&self) -> Option<f64> {
+        lamports_to_usd(self.0.amount as u64, self.0.sol_usd)
+    }
+
+    async fn slot(&self) -> i64 {
+        self.0.slot
+    }
+
+    async fn block_time(&self) -> Option<i64> {
+        self.0.block_time
+    }
+
+    async fn owner(&self) -> &str {
+        &self.0.owner
+    }
+
+    async fn plan_id(&self) -> i64 {
+        self.0.plan_id
+    }
+
+    async fn subscription(&self, ctx: &Context<'_>) -> Result<Option<SubscriptionNode>> {
+        let sub = ctx.data_unchecked::<DataLoader<SubscriptionLoader>>().load_one(self.0.pda.clone()).await?;
+        Ok(sub.map(SubscriptionNode))
+    }
+}
+
+/// A plan id with at least one indexed subscription; plans have no on-chain account.
+pub struct PlanNode(PlanStatsRow);
+
+#[Object(name = "Plan")]
+impl PlanNode {
+    async fn plan_id(&self) -> i64 {
+        self.0.plan_id
+    }
+
+    /// Subscriptions that are not closed.
+    async fn subscription_count(&self) -> i64 {
+        self.0.subscriptions
+    }
+
+    /// Subscriptions that are active and paid up.
+    async fn active_subscription_count(&self) -> i64 {
+        self.0.active_subscriptions
+    }
+
+    /// Every indexed payment for the plan.
+    async fn revenue_lamports(&self) -> i64 {
+        self.0.revenue_lamports
+    }
+
+    async fn subscriptions(
+        &self,
+        ctx: &Context<'_>,
+        active: Option<bool>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Vec<SubscriptionNode>> {
+        let (limit, offset) = page(limit, offset);
+        let plan_ids = std::slice::from_ref(&self.0.plan_id);
+        let rows = db::list_subscriptions(ctx.data_unchecked::<PgPool>(), Some(plan_ids), active, limit, offset).await?;
+        Ok(rows.into_iter().map(SubscriptionNode).collect())
+    }
+
+    async fn mrr(&self, ctx: &Context<'_>) -> Result<MrrResponse> {
+        Ok(ctx.data_unchecked::<AnalyticsService>().mrr(Some(std::slice::from_ref(&self.0.plan_id))).await?)
+    }
+
+    async fn revenue(
+        &self,
+        ctx: &Context<'_>,
+        from: Option<i64>,
+        to: Option<i64>,
+        granularity: Option<Granularity>,
+    ) -> Result<Vec<RevenuePoint>> {
+        revenue(ctx, Some(std::slice::from_ref(&self.0.plan_id)), from, to, granularity).await
+    }
+}
+
+pub struct SubscriptionEventNode(WebhookEvent<SubscriptionEventData>);
+
+#[Object(name = "SubscriptionEvent")]
+impl SubscriptionEventNode {
+    async fn id(&self) -> &str {
+        &self.0.id
+    }
+
+    /// Webhook event type, e.g. `subscription.renewed`.
+    async fn event_type(&self) -> &str {
+        self.0.event_type.as_str()
+    }
+
+    async fn created_at(&self) -> i64 {
+        self.0.created_at
+    }
+
+    async fn signature(&self) -> Option<&str> {
+        self.0.data.signature.as_deref()
+    }
+
+    async fn milestone(&self) -> Option<&str> {
+        self.0.data.milestone.as_deref()
+    }
+
+    async fn refund_id(&self) -> Option<&str> {
+        self.0.data.refund_id.as_deref()
+    }
+
+    async fn subscription(&self, ctx: &Context<'_>) -> Result<Option<SubscriptionNode>> {
+        let sub = ctx
+            .data_unchecked::<DataLoader<SubscriptionLoader>>()
+            .load_one(self.0.data.subscription.clone())
+            .await?;
+        Ok(sub.map(SubscriptionNode))
+    }
+}
+
+async fn revenue(
+    ctx: &Context<'_>,
+    plan_ids: Option<&[i64]>,
+    from: Option<i64>,
+    to: Option<i64>,
+    granularity: Option<Granularity>,
+) -> Result<Vec<RevenuePoint>> {
+    let (from, to) = analytics::window(from, to)?;
+    let granularity = granularity.unwrap_or(Granularity::Day);
+    analytics::check_buckets(from, to, granularity)?;
+    Ok(ctx.data_unchecked::<AnalyticsService>().revenue(plan_ids, from, to, granularity).await?)
+}
+
+// Roots
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn subscription(&self, ctx: &Context<'_>, pda: String) -> Result<Option<SubscriptionNode>> {
+        let sub = ctx.data_unchecked::<DataLoader<SubscriptionLoader>>().load_one(pda).await?;
+        let scope = ctx.data_unchecked::<PlanScope>();
+        Ok(sub.filter(|sub| scope.allows(sub.plan_id)).map(SubscriptionNode))
+    }
+
+    /// Open subscriptions ordered by plan and owner.
+    async fn subscriptions(
+        &self,
+        ctx: &Context<'_>,
+        plan_id: Option<i64>,
+        active: Option<bool>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Vec<SubscriptionNode>> {
+        let (limit, offset) = page(limit, offset);
+        let plan_ids = plan_ids(ctx, plan_id)?;
+        let rows = db::list_subscriptions(ctx.data_unchecked::<PgPool>(), plan_ids.as_deref(), active, limit, offset).await?;
+        Ok(rows.into_iter().map(SubscriptionNode).collect())
+    }
+
+    /// Payments in chain order, optionally within `[from, to)` by block time.
+    async fn payments(
+        &self,
+        ctx: &Context<'_>,
+        plan_id: Option<i64>,
+        pda: Option<String>,
+        from: Option<i64>,
+        to: Option<i64>,
+        limit: Option<i64>,
+    ) -> Result<Vec<PaymentNode>> {
+        let (limit, _) = page(limit, None);
+        let plan_ids = plan_ids(ctx, plan_id)?;
+        let rows =
+            db::list_payments_page(ctx.data_unchecked::<PgPool>(), pda.as_deref(), plan_ids.as_deref(), from, to, None, limit)
+                .await?;
+        Ok(rows.into_iter().map(PaymentNode).collect())
+    }
+
+    async fn plan(&self, ctx: &Context<'_>, plan_id: i64) -> Result<Option<PlanNode>> {
+        if !ctx.data_unchecked::<PlanScope>().allows(plan_id) {
+            return Ok(None);
+        }
+        let plan = ctx.data_unchecked::<DataLoader<PlanLoader>>().load_one(plan_id).await?;
+        Ok(plan.map(PlanNode))
+    }
+
+    async fn plans(&self, ctx: &Context<'_>) -> Result<Vec<PlanNode>> {
+        let plan_ids = ctx.data_unchecked::<PlanScope>().plan_ids();
+        let rows = db::list_plan_stats(ctx.data_unchecked::<PgPool>(), plan_ids).await?;
+        Ok(rows.into_iter().map(PlanNode).collect())
+    }
+
+    async fn mrr(&self, ctx: &Context<'_>, plan_id: Option<i64>) -> Result<MrrResponse> {
+        let plan_ids = plan_ids(ctx, plan_id)?;
+        Ok(ctx.data_unchecked::<AnalyticsService>().mrr(plan_ids.as_deref()).await?)
+    }
+
+    async fn churn(
+        &self,
+        ctx: &Context<'_>,
+        plan_id: Option<i64>,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Result<ChurnResponse> {
+        let (from, to) = analytics::window(from, to)?;
+        let plan_ids = plan_ids(ctx, plan_id)?;
+        Ok(ctx.data_unchecked::<AnalyticsService>().churn(plan_ids.as_deref(), from, to).await?)
+    }
+
+    async fn subscriber_breakdown(
+        &self,
+        ctx: &Context<'_>,
+        plan_id: Option<i64>,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Result<SubscriberBreakdown> {
+        let (from, to) = analytics::window(from, to)?;
+        let plan_ids = plan_ids(ctx, plan_id)?;
+        Ok(ctx.data_unchecked::<AnalyticsService>().subscribers(plan_ids.as_deref(), from, to).await?)
+    }
+
+    async fn revenue(
+        &self,
+        ctx: &Context<'_>,
+        plan_id: Option<i64>,
+        from: Option<i64>,
+        to: Option<i64>,
+        granularity: Option<Granularity>,
+    ) -> Result<Vec<RevenuePoint>> {
+        let plan_ids = plan_ids(ctx, plan_id)?;
+        revenue(ctx, plan_ids.as_deref(), from, to, granularity).await
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Subscription events of the caller's plans as they are dispatched to webhooks, optionally
+    /// filtered. A client that falls too far behind skips the events it missed.
+    async fn subscription_events(
+        &self,
+        ctx: &Context<'_>,
+        event_types: Option<Vec<String>>,
+        plan_ids: Option<Vec<i64>>,
+    ) -> Result<impl Stream<Item = SubscriptionEventNode>> {
+        let event_types = event_types
+            .unwrap_or_default()
+            .iter()
+            .map(|name| WebhookEventType::from_name(name).ok_or_else(|| format!("Unknown event type {}", name)))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let plan_ids = plan_ids.unwrap_or_default();
+        let scope = ctx.data_unchecked::<PlanScope>().clone();
+        let events = BroadcastStream::new(ctx.data_unchecked::<WebhookService>().subscribe_events());
+        Ok(events.filter_map(move |event| {
+            let event = event.ok()?;
+            let plan_id = event.data.plan_id as i64;
+            let wanted = (event_types.is_empty() || event_types.contains(&event.event_type))
+                && (plan_ids.is_empty() || plan_ids.contains(&plan_id))
+                && scope.allows(plan_id);
+            wanted.then_some(SubscriptionEventNode(event))
+        }))
+    }
+}
+
+async fn plan_scope(req: &HttpRequest, plans: &PlanService) -> AppResult<PlanScope> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    plans.scope(&auth_token).await
+}
+
+// Controllers
+#[post("")]
+pub async fn graphql(
+    req: HttpRequest,
+    schema: web::Data<DashboardSchema>,
+    plans: web::Data<PlanService>,
+    request: GraphQLRequest,
+) -> AppResult<GraphQLResponse> {
+    let scope = plan_scope(&req, &plans).await?;
+    Ok(schema.execute(request.into_inner().data(scope)).await.into())
+}
+
+/// GraphQL subscriptions over WebSocket (`graphql-transport-ws` or `graphql-ws`).
+#[get("/ws")]
+pub async fn graphql_ws(
+    schema: web::Data<DashboardSchema>,
+    plans: web::Data<PlanService>,
+    req: HttpRequest,
+    payload: web::Payload,
+) -> actix_web::Result<HttpResponse> {
+    let mut data = Data::default();
+    data.insert(plan_scope(&req, &plans).await?);
+    GraphQLSubscription::new(Schema::clone(&*schema)).with_data(data).start(&req, payload)
+}
@@ -0,0 +1,337 @@
+// tonic::Status is the error type of every RPC, however large
+#![allow(clippy::result_large_err)]
+
+use sqlx::postgres::PgPool;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::metadata::MetadataValue;
+use tonic::{Request, Response, Status};
+use crate::api_keys::ApiKeyService;
+use crate::audit;
+use crate::cache::CacheService;
+use crate::cluster::SolanaClusters;
+use crate::idempotency::IdempotencyService;
+use crate::indexer::IndexerService;
+use crate::plans::PlanService;
+use crate::quota::QuotaService;
+use crate::rate_limit::RateLimiter;
+use crate::submissions::{SubmissionService, Submitted, WalletAction};
+use crate::tenant::DEFAULT_TENANT;
+use crate::validation::validate;
+use crate::webhooks::{SubscriptionEventData, WebhookEvent, WebhookEventType, WebhookService};
+use crate::{AppError, AppResult, AuthService, AuthToken, Role, SolanaService, SubscriptionRequest, SubscriptionResponse};
+
+pub mod proto {
+    tonic::include_proto!("subscriptions.v1");
+}
+
+use proto::subscription_service_server::{SubscriptionService, SubscriptionServiceServer};
+
+impl From<AppError> for Status {
+    fn from(error: AppError) -> Self {
+        let message = match &error {
+            AppError::Validation(errors) => errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "),
+            other => other.to_string(),
+        };
+        match error {
+            AppError::Auth(_) => Status::unauthenticated(message),
+            AppError::BadRequest(_) | AppError::Validation(_) => Status::invalid_argument(message),
+            AppError::Forbidden(_) => Status::permission_denied(message),
+            AppError::NotFound(_) => Status::not_found(message),
+            AppError::Conflict(_) => Status::already_exists(message),
+            AppError::NotAcceptable(_) => Status::failed_precondition(message),
+            AppError::RateLimited(_) => Status::resource_exhausted(message),
+            AppError::PayloadTooLarge(_) => Status::out_of_range(message),
+            AppError::SolanaError(_) | AppError::TransactionFailed(..) | AppError::ServiceUnavailable(..) => Status::unavailable(message),
+            AppError::Timeout(_) => Status::deadline_exceeded(message),
+            AppError::DatabaseError(_) | AppError::InternalServerError(_) => Status::internal(message),
+        }
+    }
+}
+
+impl From<SubscriptionResponse> for proto::Subscription {
+    fn from(sub: SubscriptionResponse) -> Self {
+        Self {
+            id: sub.id,
+            plan_id: sub.plan_id,
+            duration: sub.duration,
+            amount: sub.amount,
+            active: sub.active,
+            start_time: sub.start_time,
+            history: sub.history,
+            owner: sub.owner,
+        }
+    }
+}
+
+impl From<WebhookEvent<SubscriptionEventData>> for proto::Event {
+    fn from(event: WebhookEvent<SubscriptionEventData>) -> Self {
+        Self {
+            id: event.id,
+            r#type: event.event_type.as_str().to_string(),
+            created_at: event.created_at,
+            subscription: event.data.subscription,
+            owner: event.data.owner,
+            plan_id: event.data.plan_id,
+            signature: event.data.signature,
+            milestone: event.data.milestone,
+            expires_at: event.data.expires_at,
+            refund_id: event.data.refund_id,
+            refund_lamports: event.data.refund_lamports,
+        }
+    }
+}
+
+const SERVICE_PATH: &str = "/subscriptions.v1.SubscriptionService";
+const IDEMPOTENCY_METADATA: &str = "idempotency-key";
+
+// gRPC Service
+/// The subscription API over gRPC, backed by the same services as the REST handlers, with
+/// the same per-wallet rate limit, daily quotas, audit log and idempotency keys. Wallet calls
+/// act on the primary cluster, for the tenant of the caller's token.
+#[derive(Clone)]
+pub struct GrpcApi {
+    clusters: SolanaClusters,
+    auth_service: AuthService,
+    api_keys: ApiKeyService,
+    webhook_service: WebhookService,
+    indexer: IndexerService,
+    cache: CacheService,
+    submissions: SubmissionService,
+    plans: PlanService,
+    limiter: RateLimiter,
+    quotas: QuotaService,
+    pool: PgPool,
+}
+
+impl GrpcApi {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        clusters: SolanaClusters,
+        auth_service: AuthService,
+        api_keys: ApiKeyService,
+        webhook_service: WebhookService,
+        indexer: IndexerService,
+        cache: CacheService,
+        submissions: SubmissionService,
+        plans: PlanService,
+        limiter: RateLimiter,
+        quotas: QuotaService,
+        pool: PgPool,
+    ) -> Self {
+        Self { clusters, auth_service, api_keys, webhook_service, indexer, cache, submissions, plans, limiter, quotas, pool }
+    }
+
+    /// Resolves `authorization: Bearer <jwt>`, or `x-api-key` when `allow_api_key` is set.
+    async fn authenticate<T>(&self, req: &Request<T>, allow_api_key: bool) -> Result<AuthToken, Status> {
+        let metadata = req.metadata();
+        if let Some(value) = metadata.get("authorization") {
+            let token = value
+                .to_str()
+                .ok()
+                .and_then(|v| v.strip_prefix("Bearer "))
+                .ok_or_else(|| Status::unauthenticated("Invalid authorization metadata"))?;
+            return Ok(self.auth_service.verify_token(token).await?);
+        }
+        match metadata.get("x-api-key").and_then(|v| v.to_str().ok()) {
+            Some(key) if allow_api_key => Ok(self.api_keys.verify(key).await?),
+            _ => Err(Status::unauthenticated("No auth token found")),
+        }
+    }
+
+    fn service_for(&self, auth_token: &AuthToken) -> AppResult<&SolanaService> {
+        let tenant = auth_token.tenant.as_deref().unwrap_or(DEFAULT_TENANT);
+        self.clusters
+            .get(self.clusters.default_cluster(tenant), tenant)
+            .ok_or_else(|| AppError::NotFound(format!("Tenant {} is not configured", tenant)))
+    }
+
+    /// Applies the per-wallet rate limit and daily quotas, as the REST API does to every
+    /// authenticated route.
+    async fn limit(&self, auth_token: &AuthToken, builds_transaction: bool) -> Result<(), Status> {
+        let decision = self.limiter.check(&format!("pubkey:{}", auth_token.public_key)).await;
+        if !decision.allowed {
+            return Err(rate_limited("Too many requests".to_string(), decision.retry_after_secs));
+        }
+        self.quotas
+            .consume(auth_token, builds_transaction)
+            .await
+            .map_err(|exceeded| rate_limited(exceeded.message, exceeded.retry_after_secs))
+    }
+
+    /// Sends `action` through the same path as the REST handlers and writes its audit entry.
+    async fn submit<T>(&self, req: &Request<T>, auth_token: &AuthToken, method: &str, action: WalletAction) -> Result<String, Status> {
+        self.limit(auth_token, true).await?;
+        let route = format!("{}/{}", SERVICE_PATH, method);
+        let mut entry = audit::new_entry(
+            auth_token.clone(),
+            None,
+            "POST",
+            route.clone(),
+            route,
+            req.remote_addr().map(|addr| addr.ip().to_string()),
+        );
+        let result = self.submit_action(req, auth_token, &action).await;
+        match &result {
+            Ok(signature) => {
+                entry.status = 200;
+                entry.signatures = vec![signature.clone()];
+            }
+            Err(e) => entry.status = actix_web::ResponseError::status_code(e).as_u16() as i32,
+        }
+        audit::record(&self.pool, &entry).await;
+        Ok(result?)
+    }
+
+    async fn submit_action<T>(&self, req: &Request<T>, auth_token: &AuthToken, action: &WalletAction) -> AppResult<String> {
+        let solana_service = self.service_for(auth_token)?;
+        let idempotency_key = match req.metadata().get(IDEMPOTENCY_METADATA) {
+            Some(value) => Some(IdempotencyService::check_key(value.to_str().unwrap_or_default())?),
+            None => None,
+        };
+        match self
+            .submissions
+            .submit(solana_service, &auth_token.public_key, action, idempotency_key.as_deref())
+            .await?
+        {
+            Submitted::Sent(signature) => Ok(signature),
+            Submitted::Replayed(replay) => replay.into_signature(),
+        }
+    }
+}
+
+fn rate_limited(message: String, retry_after_secs: u64) -> Status {
+    let mut status = Status::from(AppError::RateLimited(message));
+    status.metadata_mut().insert("retry-after", MetadataValue::from(retry_after_secs));
+    status
+}
+
+#[tonic::async_trait]
+impl SubscriptionService for GrpcApi {
+    type StreamEventsStream = Pin<Box<dyn Stream<Item = Result<proto::Event, Status>> + Send>>;
+
+    async fn create_subscription(
+        &self,
+        req: Request<proto::CreateSubscriptionRequest>,
+    ) -> Result<Response<proto::SignatureReply>, Status> {
+        let auth_token = self.authenticate(&req, false).await?;
+        let body = req.get_ref();
+        let request = SubscriptionRequest { plan_id: body.plan_id, duration: body.duration, amount: body.amount };
+        validate(&request)?;
+        let signature = self
+            .submit(&req, &auth_token, "CreateSubscription", WalletAction::Create(request))
+            .await?;
+        Ok(Response::new(proto::SignatureReply { signature }))
+    }
+
+    async fn get_subscription(
+        &self,
+        req: Request<proto::GetSubscriptionRequest>,
+    ) -> Result<Response<proto::Subscription>, Status> {
+        let auth_token = self.authenticate(&req, false).await?;
+        self.limit(&auth_token, false).await?;
+        let solana_service = self.service_for(&auth_token)?;
+        let plan_id = req.into_inner().plan_id;
+        if !solana_service.is_primary() {
+            let sub = solana_service.get_subscription(&auth_token.public_key, plan_id, solana_service.read_commitment(None)).await?;
+            return Ok(Response::new(sub.into()));
+        }
+
+        let pda = solana_service.subscription_address(&auth_token.public_key, plan_id)?;
+        if let Some(sub) = self.cache.get_subscription(&pda).await {
+            return Ok(Response::new(sub.into()));
+        }
+        let sub = match self.indexer.find_subscription(&auth_token.public_key, plan_id).await? {
+            Some(sub) => sub,
+            None => solana_service.get_subscription(&auth_token.public_key, plan_id, solana_service.read_commitment(None)).await?,
+        };
+        self.cache.put_subscription(&pda, &sub).await;
+        Ok(Response::new(sub.into()))
+    }
+
+    async fn renew_subscription(
+        &self,
+        req: Request<proto::RenewSubscriptionRequest>,
+    ) -> Result<Response<proto::SignatureReply>, Status> {
+        let auth_token = self.authenticate(&req, false).await?;
+        let plan_id = req.get_ref().plan_id;
+        let signature = self
+            .submit(&req, &auth_token, "RenewSubscription", WalletAction::Renew(plan_id))
+            .await?;
+        Ok(Response::new(proto::SignatureReply { signature }))
+    }
+
+    async fn list_subscriptions(
+        &self,
+        req: Request<proto::ListSubscriptionsRequest>,
+    ) -> Result<Response<proto::ListSubscriptionsReply>, Status> {
+        let auth_token = self.authenticate(&req, false).await?;
+        self.limit(&auth_token, false).await?;
+        if !self.service_for(&auth_token)?.is_primary() {
+            return Err(Status::invalid_argument(
+                "Subscriptions are only listed for the default tenant on the primary cluster",
+            ));
+        }
+        let subscriptions = self.indexer.list_subscriptions(std::slice::from_ref(&auth_token.public_key)).await?;
+        Ok(Response::new(proto::ListSubscriptionsReply {
+            subscriptions: subscriptions.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    /// Events of the caller's plans dispatched after the call starts, every plan for admins;
+    /// plans created later need a new call. A subscriber that falls too far behind skips the
+    /// events it missed rather than stalling dispatch.
+    async fn stream_events(
+        &self,
+        req: Request<proto::StreamEventsRequest>,
+    ) -> Result<Response<Self::StreamEventsStream>, Status> {
+        let auth_token = self.authenticate(&req, true).await?;
+        if !auth_token.has_role(Role::Merchant) {
+            return Err(Status::permission_denied("Requires the merchant role"));
+        }
+        self.limit(&auth_token, false).await?;
+        let scope = self.plans.scope(&auth_token).await?;
+        let filter = req.into_inner();
+        let event_types = filter
+            .event_types
+            .iter()
+            .map(|name| {
+                WebhookEventType::from_name(name)
+                    .ok_or_else(|| Status::invalid_argument(format!("Unknown event type {}", name)))
+            })
+            .collect::<Result<Vec<_>, Status>>()?;
+        for plan_id in &filter.plan_ids {
+            scope.narrow(Some(*plan_id))?;
+        }
+        let plan_ids = filter.plan_ids;
+
+        let stream = BroadcastStream::new(self.webhook_service.subscribe_events()).filter_map(move |event| match event {
+            Ok(event) => {
+                let wanted = scope.allows(event.data.plan_id as i64)
+                    && (event_types.is_empty() || event_types.contains(&event.event_type))
+                    && (plan_ids.is_empty() || plan_ids.contains(&event.data.plan_id));
+                wanted.then(|| Ok(event.into()))
+            }
+            Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                tracing::warn!("gRPC event stream fell behind and skipped {} events", missed);
+                None
+            }
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// Serves the gRPC API on `addr` until `shutdown` resolves.
+pub async fn serve(addr: SocketAddr, api: GrpcApi, shutdown: impl std::future::Future<Output = ()>) {
+    tracing::info!("Serving gRPC at {}", addr);
+    if let Err(e) = tonic::transport::Server::builder()
+        .add_service(SubscriptionServiceServer::new(api))
+        .serve_with_shutdown(addr, shutdown)
+        .await
+    {
+        tracing::error!("gRPC server failed: {}", e);
+    }
+}
@@ -0,0 +1,130 @@
+use actix_web::{get, web, HttpResponse};
+use serde::Serialize;
+use sqlx::postgres::PgPool;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+use crate::{metrics, SolanaService};
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Models
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Ok,
+    Unavailable,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct ComponentStatus {
+    status: HealthStatus,
+    latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct HealthResponse {
+    status: HealthStatus,
+    components: BTreeMap<String, ComponentStatus>,
+}
+
+async fn check<F>(check: F) -> ComponentStatus
+where
+    F: Future<Output = Result<(), String>>,
+{
+    let started = Instant::now();
+    let result = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("timed out after {}s", CHECK_TIMEOUT.as_secs())),
+    };
+    ComponentStatus {
+        status: if result.is_ok() { HealthStatus::Ok } else { HealthStatus::Unavailable },
+        latency_ms: started.elapsed().as_millis() as u64,
+        error: result.err(),
+    }
+}
+
+async fn check_rpc(solana_service: &SolanaService) -> Result<(), String> {
+    metrics::observe_rpc("getHealth", solana_service.rpc.client().get_health())
+        .await
+        .map_err(|e| format!("RPC node unhealthy: {}", e))
+}
+
+async fn check_database(pool: &PgPool) -> Result<(), String> {
+    sqlx::query("SELECT 1")
+        .execute(pool)
+        .await
+        .map(|_| ())
+        .map_err(|e| format!("Database unreachable: {}", e))
+}
+
+async fn check_program(solana_service: &SolanaService) -> Result<(), String> {
+    let account = metrics::observe_rpc("getAccountInfo", solana_service.rpc.client().get_account(&solana_service.program_id))
+        .await
+        .map_err(|e| format!("Program account {} not found: {}", solana_service.program_id, e))?;
+    if !account.executable {
+        return Err(format!("Account {} is not an executable program", solana_service.program_id));
+    }
+    if solana_service.is_read_only() {
+        return Err(format!("Program {} does not match this backend; transactions are disabled", solana_service.program_id));
+    }
+    Ok(())
+}
+
+// Controllers
+/// Liveness: the process is up and serving requests. Does not touch dependencies.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    responses((status = 200, description = "Process is alive", body = HealthResponse))
+)]
+#[get("/healthz")]
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(HealthResponse {
+        status: HealthStatus::Ok,
+        components: BTreeMap::new(),
+    })
+}
+
+/// Readiness: every dependency needed to serve traffic is reachable.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = 200, description = "All components ready", body = HealthResponse),
+        (status = 503, description = "At least one component unavailable", body = HealthResponse),
+    )
+)]
+#[get("/readyz")]
+pub async fn readyz(
+    solana_service: web::Data<SolanaService>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let (rpc, database, program) = tokio::join!(
+        check(check_rpc(&solana_service)),
+        check(check_database(&pool)),
+        check(check_program(&solana_service)),
+    );
+
+    let components = BTreeMap::from([
+        ("rpc".to_string(), rpc),
+        ("database".to_string(), database),
+        ("program".to_string(), program),
+    ]);
+    let ready = components.values().all(|c| c.status == HealthStatus::Ok);
+    let response = HealthResponse {
+        status: if ready { HealthStatus::Ok } else { HealthStatus::Unavailable },
+        components,
+    };
+
+    if ready {
+        HttpResponse::Ok().json(response)
+    } else {
+        HttpResponse::ServiceUnavailable().json(response)
+    }
+}
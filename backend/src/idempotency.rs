@@ -0,0 +1,220 @@
+use actix_web::{http::StatusCode, HttpRequest, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::postgres::PgPool;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::validation::FieldError;
+use crate::{AppError, AppResult, Config};
+
+pub const IDEMPOTENCY_HEADER: &str = "Idempotency-Key";
+pub const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+const MAX_KEY_LENGTH: usize = 255;
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Remembers the outcome of create/renew/cancel requests sent with an `Idempotency-Key`,
+/// so a double-submitted request returns the first result instead of sending a second
+/// transaction. Keys are scoped to the caller's wallet and kept for `ttl_secs`.
+#[derive(Clone)]
+pub struct IdempotencyService {
+    pool: PgPool,
+    ttl_secs: i64,
+}
+
+impl IdempotencyService {
+    pub fn new(config: &Config, pool: PgPool) -> Self {
+        Self {
+            pool,
+            ttl_secs: config.idempotency_ttl_secs as i64,
+        }
+    }
+
+    /// Reads and checks the `Idempotency-Key` header; requests without one are not deduplicated.
+    pub fn key(req: &HttpRequest) -> AppResult<Option<String>> {
+        let Some(header) = req.headers().get(IDEMPOTENCY_HEADER) else {
+            return Ok(None);
+        };
+        Self::check_key(header.to_str().unwrap_or_default()).map(Some)
+    }
+
+    /// Checks a key sent other than as a header, such as gRPC `idempotency-key` metadata.
+    pub fn check_key(key: &str) -> AppResult<String> {
+        let key = key.trim();
+        if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+            return Err(AppError::Validation(vec![FieldError::new(
+                IDEMPOTENCY_HEADER,
+                "length",
+                format!("must be 1 to {} visible ASCII characters", MAX_KEY_LENGTH),
+            )]));
+        }
+        Ok(key.to_string())
+    }
+
+    /// Claims `key` for this request. Returns the stored response when an earlier request
+    /// with the same key already finished, or `None` if the caller should go ahead.
+    pub async fn begin(&self, owner: &str, key: &str, fingerprint: &str) -> AppResult<Option<HttpResponse>> {
+        Ok(self.begin_replay(owner, key, fingerprint).await?.map(Replay::into_response))
+    }
+
+    /// `begin`, returning the stored response as is for callers that do not answer over HTTP.
+    pub async fn begin_replay(&self, owner: &str, key: &str, fingerprint: &str) -> AppResult<Option<Replay>> {
+        sqlx::query("DELETE FROM idempotency_keys WHERE owner = $1 AND key = $2 AND created_at < $3")
+            .bind(owner)
+            .bind(key)
+            .bind(now() - self.ttl_secs)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to expire idempotency key: {}", e)))?;
+
+        let claimed = sqlx::query(
+            "INSERT INTO idempotency_keys (owner, key, fingerprint, created_at)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (owner, key) DO NOTHING",
+        )
+        .bind(owner)
+        .bind(key)
+        .bind(fingerprint)
+        .bind(now())
+        .execute(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to store idempotency key: {}", e)))?
+        .rows_affected()
+            == 1;
+        if claimed {
+            return Ok(None);
+        }
+
+        let stored = sqlx::query_as::<_, (String, Option<i32>, Option<String>)>(
+            "SELECT fingerprint, status_code, response_body FROM idempotency_keys WHERE owner = $1 AND key = $2",
+        )
+        .bind(owner)
+        .bind(key)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to fetch idempotency key: {}", e)))?;
+
+        match stored {
+            Some((stored_fingerprint, _, _)) if stored_fingerprint != fingerprint => {
+                Err(AppError::Validation(vec![FieldError::new(
+                    IDEMPOTENCY_HEADER,
+                    "reused",
+                    "was already used for a different request",
+                )]))
+            }
+            Some((_, Some(status_code), Some(body))) => Ok(Some(Replay {
+                status: StatusCode::from_u16(status_code as u16).unwrap_or(StatusCode::OK),
+                body,
+            })),
+            _ => Err(AppError::Conflict(
+                "A request with this Idempotency-Key is still in progress".to_string(),
+            )),
+        }
+    }
+
+    /// Stores the outcome for replay. Errors raised before a transaction could have been
+    /// sent release the key instead, so the corrected request can reuse it.
+    pub async fn finish<T: Serialize>(&self, owner: &str, key: &str, result: &AppResult<T>) {
+        let stored = match result {
+            Ok(response) => serde_json::to_string(response).map(|body| (StatusCode::OK, body)),
+            Err(e @ (AppError::SolanaError(_) | AppError::TransactionFailed(..))) => {
+                serde_json::to_string(&e.body()).map(|body| (e.status_code(), body))
+            }
+            Err(_) => {
+                self.release(owner, key).await;
+                return;
+            }
+        };
+        let (status, body) = match stored {
+            Ok(stored) => stored,
+            Err(e) => {
+                tracing::error!("Failed to serialize idempotent response: {}", e);
+                self.release(owner, key).await;
+                return;
+            }
+        };
+
+        let result = sqlx::query(
+            "UPDATE idempotency_keys SET status_code = $3, response_body = $4 WHERE owner = $1 AND key = $2",
+        )
+        .bind(owner)
+        .bind(key)
+        .bind(status.as_u16() as i32)
+        .bind(body)
+        .execute(&self.pool)
+        .await;
+        if let Err(e) = result {
+            tracing::error!("Failed to store idempotent response for key {}: {}", key, e);
+        }
+    }
+
+    async fn release(&self, owner: &str, key: &str) {
+        let result = sqlx::query("DELETE FROM idempotency_keys WHERE owner = $1 AND key = $2")
+            .bind(owner)
+            .bind(key)
+            .execute(&self.pool)
+            .await;
+        if let Err(e) = result {
+            tracing::error!("Failed to release idempotency key {}: {}", key, e);
+        }
+    }
+
+    pub async fn run_cleanup(self) {
+        loop {
+            let result = sqlx::query("DELETE FROM idempotency_keys WHERE created_at < $1")
+                .bind(now() - self.ttl_secs)
+                .execute(&self.pool)
+                .await;
+            match result {
+                Ok(done) if done.rows_affected() > 0 => {
+                    tracing::debug!("Removed {} expired idempotency keys", done.rows_affected())
+                }
+                Ok(_) => {}
+                Err(e) => tracing::error!("Failed to remove expired idempotency keys: {}", e),
+            }
+            tokio::time::sleep(CLEANUP_INTERVAL).await;
+        }
+    }
+}
+
+/// The stored response of a finished request: the signature it sent, or the error it failed with.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+impl Replay {
+    pub fn into_response(self) -> HttpResponse {
+        HttpResponse::build(self.status)
+            .insert_header((REPLAYED_HEADER, "true"))
+            .content_type("application/json")
+            .body(self.body)
+    }
+
+    /// The signature of a successful request, or the transaction error of a failed one.
+    pub fn into_signature(self) -> AppResult<String> {
+        #[derive(Deserialize)]
+        struct Stored {
+            signature: Option<String>,
+            message: Option<String>,
+        }
+        let stored: Stored = serde_json::from_str(&self.body)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to parse idempotent response: {}", e)))?;
+        match stored {
+            Stored { signature: Some(signature), .. } if self.status.is_success() => Ok(signature),
+            // Only transaction errors are stored, and their message already reads "Solana error: ..."
+            Stored { message, .. } => {
+                let message = message.unwrap_or(self.body);
+                Err(AppError::SolanaError(message.trim_start_matches("Solana error: ").to_string()))
+            }
+        }
+    }
+}
+
+/// Identifies what a request asks for, so a key reused for a different request is rejected.
+pub fn fingerprint(parts: &[&str]) -> String {
+    hex::encode(Sha256::digest(parts.join("\n").as_bytes()))
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
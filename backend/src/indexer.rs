@@ -0,0 +1,603 @@
+use anchor_lang::solana_program::hash::hash;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_client::rpc_response::RpcConfirmedTransactionStatusWithSignature;
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::UiTransactionEncoding;
+use sqlx::postgres::PgPool;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use crate::cache::{CacheNamespace, ResponseCache};
+use crate::cluster::{Cluster, RpcPool};
+use crate::db::{self, EventRow, PaymentRow, SubscriptionRow};
+use crate::layout::AccountDecoder;
+use crate::metrics;
+use crate::price::PriceFeed;
+use crate::reporting;
+use crate::tenant::DEFAULT_TENANT;
+use crate::webhooks::{SubscriptionEventData, WebhookEventType, WebhookService};
+use crate::{unix_now, AppError, AppResult, Config, SubscriptionResponse};
+
+const SIGNATURE_PAGE_SIZE: usize = 1000;
+const FINALIZE_BATCH_SIZE: i64 = 256; // getSignatureStatuses accepts at most 256 signatures
+const FINALIZE_INTERVAL: Duration = Duration::from_secs(15);
+const RECONCILE_BATCH_SIZE: usize = 100; // getMultipleAccounts accepts at most 100 accounts
+// Oldest payment the current SOL/USD rate is recorded for
+const MAX_RATE_AGE_SECS: i64 = 600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstructionKind {
+    Create,
+    Renew,
+    Cancel,
+    Close,
+}
+
+impl InstructionKind {
+    const ALL: [InstructionKind; 4] = [
+        InstructionKind::Create,
+        InstructionKind::Renew,
+        InstructionKind::Cancel,
+        InstructionKind::Close,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            InstructionKind::Create => "create_subscription",
+            InstructionKind::Renew => "renew_subscription",
+            InstructionKind::Cancel => "cancel_subscription",
+            InstructionKind::Close => "close_subscription",
+        }
+    }
+
+    pub fn discriminator(&self) -> [u8; 8] {
+        let mut discriminator = [0u8; 8];
+        discriminator.copy_from_slice(&hash(format!("global:{}", self.name()).as_bytes()).to_bytes()[..8]);
+        discriminator
+    }
+
+    pub fn from_data(data: &[u8]) -> Option<Self> {
+        let prefix = data.get(..8)?;
+        Self::ALL.into_iter().find(|kind| kind.discriminator() == prefix)
+    }
+
+    pub fn is_payment(&self) -> bool {
+        matches!(self, InstructionKind::Create | InstructionKind::Renew)
+    }
+}
+
+/// A field of an indexed subscription that disagreed with its account, found by `reconcile`.
+#[derive(Debug, Clone)]
+pub struct Discrepancy {
+    pub pda: String,
+    pub field: &'static str,
+    pub indexed: String,
+    pub on_chain: String,
+}
+
+impl Discrepancy {
+    fn new(pda: &str, field: &'static str, indexed: impl ToString, on_chain: impl ToString) -> Self {
+        Self { pda: pda.to_string(), field, indexed: indexed.to_string(), on_chain: on_chain.to_string() }
+    }
+
+    fn between(indexed: &SubscriptionRow, on_chain: &SubscriptionRow) -> Vec<Self> {
+        let pda = &indexed.pda;
+        let mut found = Vec::new();
+        if indexed.owner != on_chain.owner {
+            found.push(Self::new(pda, "owner", &indexed.owner, &on_chain.owner));
+        }
+        if indexed.plan_id != on_chain.plan_id {
+            found.push(Self::new(pda, "plan_id", indexed.plan_id, on_chain.plan_id));
+        }
+        if indexed.start_time != on_chain.start_time {
+            found.push(Self::new(pda, "start_time", indexed.start_time, on_chain.start_time));
+        }
+        if indexed.duration != on_chain.duration {
+            found.push(Self::new(pda, "duration", indexed.duration, on_chain.duration));
+        }
+        if indexed.amount != on_chain.amount {
+            found.push(Self::new(pda, "amount", indexed.amount, on_chain.amount));
+        }
+        if indexed.active != on_chain.active {
+            found.push(Self::new(pda, "active", indexed.active, on_chain.active));
+        }
+        if indexed.closed != on_chain.closed {
+            found.push(Self::new(pda, "closed", indexed.closed, on_chain.closed));
+        }
+        if indexed.history != on_chain.history {
+            found.push(Self::new(pda, "history", format!("{:?}", indexed.history), format!("{:?}", on_chain.history)));
+        }
+        found
+    }
+}
+
+impl std::fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}: indexed {}, on chain {}", self.pda, self.field, self.indexed, self.on_chain)
+    }
+}
+
+// Indexer Service
+#[derive(Clone)]
+pub struct IndexerService {
+    rpc: RpcPool,
+    cluster: Cluster,
+    program_id: Pubkey,
+    decoder: AccountDecoder,
+    pool: PgPool,
+    prices: PriceFeed,
+    responses: ResponseCache, // Analytics and catalog responses are expired whenever the index changes
+    webhooks: WebhookService,
+    poll_interval: Duration,
+}
+
+impl IndexerService {
+    /// `rpc` should share health state with the primary cluster's `SolanaService`.
+    pub fn new(
+        config: &Config,
+        pool: PgPool,
+        rpc: RpcPool,
+        prices: PriceFeed,
+        responses: ResponseCache,
+        webhooks: WebhookService,
+    ) -> Self {
+        Self {
+            rpc: rpc.with_commitment(CommitmentConfig::confirmed()),
+            cluster: config.cluster,
+            program_id: config.primary_cluster().program_id,
+            decoder: AccountDecoder::new(config),
+            pool,
+            prices,
+            responses,
+            webhooks,
+            poll_interval: Duration::from_secs(config.indexer_poll_interval_secs),
+        }
+    }
+
+    /// Marks the responses computed from the index stale.
+    fn expire_responses(&self) {
+        self.responses.expire(CacheNamespace::Analytics);
+        self.responses.expire(CacheNamespace::Catalog);
+    }
+
+    fn subscription_pda(&self, owner: &Pubkey, plan_id: u64) -> Pubkey {
+        let (subscription_pda, _bump) = Pubkey::find_program_address(
+            &[b"subscription", owner.as_ref(), plan_id.to_le_bytes().as_ref()],
+            &self.program_id,
+        );
+        subscription_pda
+    }
+
+    /// Periodically sweeps for transactions the live listener missed.
+    pub async fn run(self) {
+        loop {
+            let started = Instant::now();
+            match self.backfill(false).await {
+                Ok(count) => {
+                    metrics::record_job_success("indexer_backfill", started, count);
+                    if count > 0 {
+                        tracing::info!("Indexed {} new program transactions", count);
+                    }
+                }
+                Err(e) => {
+                    metrics::record_job_failure("indexer_backfill", started);
+                    tracing::error!("Indexer poll failed: {}", e);
+                    reporting::capture_job_failure("indexer_backfill", &e);
+                }
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+
+    /// Promotes indexed rows to `finalized` and rolls back rows whose transaction was
+    /// dropped by a fork.
+    pub async fn run_finalizer(self) {
+        loop {
+            let started = Instant::now();
+            match self.finalize().await {
+                Ok((finalized, rolled_back)) => {
+                    metrics::record_job_success("finalizer", started, finalized + rolled_back);
+                    if finalized + rolled_back > 0 {
+                        tracing::info!("Finalized {} transactions, rolled back {}", finalized, rolled_back);
+                    }
+                }
+                Err(e) => {
+                    metrics::record_job_failure("finalizer", started);
+                    tracing::error!("Finalizer failed: {}", e);
+                    reporting::capture_job_failure("finalizer", &e);
+                }
+            }
+            tokio::time::sleep(FINALIZE_INTERVAL).await;
+        }
+    }
+
+    #[tracing::instrument(name = "indexer_finalize", skip_all)]
+    pub async fn finalize(&self) -> AppResult<(usize, usize)> {
+        let pending = db::unfinalized_signatures(&self.pool, FINALIZE_BATCH_SIZE).await?;
+        if pending.is_empty() {
+            return Ok((0, 0));
+        }
+
+        let finalized_slot = metrics::observe_rpc(
+            "getSlot",
+            self.rpc.client().get_slot_with_commitment(CommitmentConfig::finalized()),
+        )
+        .await
+            .map_err(|e| AppError::rpc("Failed to fetch finalized slot", e))?;
+        let signatures = pending
+            .iter()
+            .map(|(signature, _)| Signature::from_str(signature))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| AppError::InternalServerError(format!("Invalid indexed signature: {}", e)))?;
+        let statuses = metrics::observe_rpc(
+            "getSignatureStatuses",
+            self.rpc.client().get_signature_statuses_with_history(&signatures),
+        )
+        .await
+            .map_err(|e| AppError::rpc("Failed to fetch signature statuses", e))?
+            .value;
+
+        let mut finalized = Vec::new();
+        let mut dropped = Vec::new();
+        for ((signature, slot), status) in pending.into_iter().zip(statuses) {
+            match status {
+                Some(status) if status.err.is_some() => dropped.push(signature),
+                Some(status) if status.satisfies_commitment(CommitmentConfig::finalized()) => {
+                    finalized.push(signature)
+                }
+                Some(_) => {}
+                // Unknown to the cluster even though its slot is already rooted: the fork is gone
+                None if (slot as u64) < finalized_slot => dropped.push(signature),
+                None => {}
+            }
+        }
+
+        if !finalized.is_empty() {
+            db::mark_finalized(&self.pool, &finalized).await?;
+        }
+        for signature in &dropped {
+            tracing::warn!("Transaction {} was dropped by a fork, rolling back", signature);
+            for pda in db::rollback_transaction(&self.pool, signature).await? {
+                if let Ok(pda) = Pubkey::from_str(&pda) {
+                    self.refresh_subscription(&pda, 0).await?;
+                }
+            }
+        }
+        Ok((finalized.len(), dropped.len()))
+    }
+
+    /// Walks `getSignaturesForAddress` back to the last indexed signature (or to the
+    /// program's first transaction when `full` is set) and indexes everything oldest-first.
+    #[tracing::instrument(name = "indexer_backfill", skip(self))]
+    pub async fn backfill(&self, full: bool) -> AppResult<usize> {
+        let until = if full {
+            None
+        } else {
+            db::last_indexed_signature(&self.pool)
+                .await?
+                .and_then(|s| Signature::from_str(&s).ok())
+        };
+        let pending = self.signatures(&self.program_id, until, 0).await?;
+        self.index_missing(pending, true).await
+    }
+
+    /// Like a full backfill, but only walks back to `slot`.
+    #[tracing::instrument(name = "indexer_backfill", skip(self))]
+    pub async fn backfill_from_slot(&self, slot: u64) -> AppResult<usize> {
+        let pending = self.signatures(&self.program_id, None, slot).await?;
+        self.index_missing(pending, true).await
+    }
+
+    /// Indexes the transactions of one subscription missing from the index, then reconciles
+    /// its row with the account.
+    #[tracing::instrument(name = "indexer_repair", skip(self))]
+    pub async fn repair(&self, pda: &Pubkey) -> AppResult<(usize, Vec<Discrepancy>)> {
+        let pending = self.signatures(pda, None, 0).await?;
+        let count = self.index_missing(pending, false).await?;
+        let discrepancies = self.reconcile(&[pda.to_string()]).await?;
+        Ok((count, discrepancies))
+    }
+
+    /// Successful transactions of `address` newer than `until` and at or after `from_slot`,
+    /// newest first.
+    async fn signatures(
+        &self,
+        address: &Pubkey,
+        until: Option<Signature>,
+        from_slot: u64,
+    ) -> AppResult<Vec<RpcConfirmedTransactionStatusWithSignature>> {
+        let mut pending = Vec::new();
+        let mut before = None;
+        loop {
+            let page = metrics::observe_rpc(
+                "getSignaturesForAddress",
+                self.rpc.client().get_signatures_for_address_with_config(
+                    address,
+                    GetConfirmedSignaturesForAddress2Config {
+                        before,
+                        until,
+                        limit: Some(SIGNATURE_PAGE_SIZE),
+                        commitment: Some(CommitmentConfig::confirmed()),
+                    },
+                ),
+            )
+            .await
+                .map_err(|e| AppError::rpc("Failed to fetch signatures", e))?;
+
+            let page_len = page.len();
+            before = page.last().and_then(|s| Signature::from_str(&s.signature).ok());
+            let reached_slot = page.last().is_some_and(|s| s.slot < from_slot);
+            pending.extend(page.into_iter().filter(|s| s.err.is_none() && s.slot >= from_slot));
+
+            if page_len < SIGNATURE_PAGE_SIZE || before.is_none() || reached_slot {
+                break;
+            }
+        }
+        Ok(pending)
+    }
+
+    /// Indexes, oldest first, those of `pending` the index does not hold yet. Returns how many.
+    async fn index_missing(
+        &self,
+        pending: Vec<RpcConfirmedTransactionStatusWithSignature>,
+        advance_cursor: bool,
+    ) -> AppResult<usize> {
+        let mut count = 0;
+        for status in pending.into_iter().rev() {
+            // Live notifications may already have indexed it
+            if !db::is_transaction_indexed(&self.pool, &status.signature).await? {
+                self.index_signature(&status.signature).await?;
+                count += 1;
+            }
+            if advance_cursor {
+                db::set_last_indexed_signature(&self.pool, &status.signature, status.slot as i64).await?;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Compares the indexed rows of `pdas` with their accounts, read in one batch per 100,
+    /// and overwrites the rows that disagree. Returns every disagreement found.
+    pub async fn reconcile(&self, pdas: &[String]) -> AppResult<Vec<Discrepancy>> {
+        let mut discrepancies = Vec::new();
+        for chunk in pdas.chunks(RECONCILE_BATCH_SIZE) {
+            let keys = chunk
+                .iter()
+                .map(|pda| Pubkey::from_str(pda))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| AppError::BadRequest(format!("Invalid subscription address: {}", e)))?;
+            let response = metrics::observe_rpc(
+                "getMultipleAccounts",
+                self.rpc.client().get_multiple_accounts_with_commitment(&keys, CommitmentConfig::confirmed()),
+            )
+            .await
+                .map_err(|e| AppError::rpc("Failed to fetch accounts", e))?;
+            let slot = response.context.slot as i64;
+            let indexed = db::find_subscriptions(&self.pool, chunk).await?;
+
+            for (pda, account) in chunk.iter().zip(response.value) {
+                let row = indexed.iter().find(|row| &row.pda == pda);
+                let Some(account) = account else {
+                    if let Some(row) = row.filter(|row| !row.closed) {
+                        discrepancies.push(Discrepancy::new(pda, "closed", row.closed, true));
+                        db::mark_subscription_closed(&self.pool, pda, slot).await?;
+                    }
+                    continue;
+                };
+                let subscription = self.decoder.decode(&account)?;
+                let on_chain = SubscriptionRow {
+                    pda: pda.clone(),
+                    owner: subscription.user.to_string(),
+                    plan_id: subscription.plan_id as i64,
+                    start_time: subscription.start_time,
+                    duration: subscription.duration as i64,
+                    amount: subscription.amount as i64,
+                    active: subscription.active,
+                    closed: false,
+                    history: subscription.history,
+                    updated_slot: slot,
+                };
+                let found = match row {
+                    Some(row) => Discrepancy::between(row, &on_chain),
+                    None => vec![Discrepancy::new(pda, "indexed", false, true)],
+                };
+                if !found.is_empty() {
+                    db::upsert_subscription(&self.pool, &on_chain).await?;
+                    discrepancies.extend(found);
+                }
+            }
+        }
+        if !discrepancies.is_empty() {
+            self.expire_responses();
+        }
+        Ok(discrepancies)
+    }
+
+    /// Indexes every program instruction in a transaction. Safe to call repeatedly.
+    #[tracing::instrument(name = "index_transaction", skip(self))]
+    pub async fn index_signature(&self, signature: &str) -> AppResult<()> {
+        let sig = Signature::from_str(signature)
+            .map_err(|e| AppError::BadRequest(format!("Invalid signature: {}", e)))?;
+
+        let tx = metrics::observe_rpc(
+            "getTransaction",
+            self.rpc.client().get_transaction_with_config(
+                &sig,
+                RpcTransactionConfig {
+                    encoding: Some(UiTransactionEncoding::Base64),
+                    commitment: Some(CommitmentConfig::confirmed()),
+                    max_supported_transaction_version: Some(0),
+                },
+            ),
+        )
+        .await
+            .map_err(|e| AppError::rpc(&format!("Failed to fetch transaction {}", signature), e))?;
+
+        if tx.transaction.meta.as_ref().is_some_and(|meta| meta.err.is_some()) {
+            return Ok(());
+        }
+        let versioned = tx.transaction.transaction
+            .decode()
+            .ok_or_else(|| AppError::SolanaError(format!("Failed to decode transaction {}", signature)))?;
+
+        let slot = tx.slot as i64;
+        let keys = versioned.message.static_account_keys();
+        for (index, ix) in versioned.message.instructions().iter().enumerate() {
+            if keys.get(ix.program_id_index as usize) != Some(&self.program_id) {
+                continue;
+            }
+            let Some(kind) = InstructionKind::from_data(&ix.data) else { continue };
+            let Some(pda) = ix.accounts.first().and_then(|i| keys.get(*i as usize)) else { continue };
+
+            let row = self.refresh_subscription(pda, slot).await?;
+
+            db::insert_event(&self.pool, &EventRow {
+                signature: signature.to_string(),
+                instruction_index: index as i32,
+                pda: pda.to_string(),
+                kind: kind.name().to_string(),
+                slot,
+                block_time: tx.block_time,
+            })
+            .await?;
+
+            if kind == InstructionKind::Close {
+                if let Some(row) = row.filter(|row| row.closed) {
+                    self.clean_up_closed(&row, signature, slot).await?;
+                }
+                continue;
+            }
+            if !kind.is_payment() {
+                continue;
+            }
+            let Some(row) = row else {
+                tracing::warn!("Skipping payment {}#{}: subscription {} is not indexed", signature, index, pda);
+                continue;
+            };
+            db::insert_payment(&self.pool, &PaymentRow {
+                signature: signature.to_string(),
+                instruction_index: index as i32,
+                pda: row.pda,
+                owner: row.owner,
+                plan_id: row.plan_id,
+                amount: row.amount,
+                kind: kind.name().to_string(),
+                slot,
+                block_time: tx.block_time,
+                refunded_lamports: 0,
+                sol_usd: self.payment_rate(tx.block_time).await,
+            })
+            .await?;
+            self.expire_responses();
+        }
+        Ok(())
+    }
+
+    /// Ends what was still pending for a subscription that was just closed (see
+    /// `db::archive_closed_subscription`), then sends its final `subscription.closed` webhook.
+    /// Only the first sighting of a close does anything, so replicas and re-indexing do not
+    /// repeat the webhook.
+    async fn clean_up_closed(&self, row: &SubscriptionRow, signature: &str, slot: i64) -> AppResult<()> {
+        let Some(intents) =
+            db::archive_closed_subscription(&self.pool, row, signature, slot, self.cluster.as_str(), DEFAULT_TENANT)
+                .await?
+        else {
+            return Ok(());
+        };
+        tracing::info!("Subscription {} closed in {}; cancelled {} payment intents", row.pda, signature, intents);
+        self.webhooks
+            .dispatch(
+                WebhookEventType::SubscriptionClosed,
+                SubscriptionEventData {
+                    subscription: row.pda.clone(),
+                    owner: row.owner.clone(),
+                    plan_id: row.plan_id as u64,
+                    signature: Some(signature.to_string()),
+                    milestone: None,
+                    expires_at: None,
+                    refund_id: None,
+                    refund_lamports: None,
+                },
+            )
+            .await;
+        Ok(())
+    }
+
+    /// SOL/USD at a payment's block time: the current rate for payments indexed as they
+    /// land, none for older ones found by a backfill.
+    async fn payment_rate(&self, block_time: Option<i64>) -> Option<f64> {
+        if unix_now() - block_time? > MAX_RATE_AGE_SECS {
+            return None;
+        }
+        self.prices.sol_usd().await
+    }
+
+    /// Re-reads a subscription PDA and mirrors it into the database. Returns the last
+    /// known row, which for a closed account is the state it had before closing.
+    pub async fn refresh_subscription(&self, pda: &Pubkey, slot: i64) -> AppResult<Option<SubscriptionRow>> {
+        let response = metrics::observe_rpc(
+            "getAccountInfo",
+            self.rpc.client().get_account_with_commitment(pda, CommitmentConfig::confirmed()),
+        )
+        .await
+            .map_err(|e| AppError::rpc("Failed to fetch account", e))?;
+
+        let Some(account) = response.value else {
+            db::mark_subscription_closed(&self.pool, &pda.to_string(), slot).await?;
+            self.expire_responses();
+            return db::find_subscription(&self.pool, &pda.to_string()).await;
+        };
+
+        let subscription = self.decoder.decode(&account)?;
+
+        let row = SubscriptionRow {
+            pda: pda.to_string(),
+            owner: subscription.user.to_string(),
+            plan_id: subscription.plan_id as i64,
+            start_time: subscription.start_time,
+            duration: subscription.duration as i64,
+            amount: subscription.amount as i64,
+            active: subscription.active,
+            closed: false,
+            history: subscription.history,
+            updated_slot: response.context.slot.max(slot as u64) as i64,
+        };
+        db::upsert_subscription(&self.pool, &row).await?;
+        self.expire_responses();
+        Ok(Some(row))
+    }
+
+    pub async fn find_subscription(&self, owner: &str, plan_id: u64) -> AppResult<Option<SubscriptionResponse>> {
+        let owner_pubkey = Pubkey::from_str(owner)
+            .map_err(|e| AppError::BadRequest(format!("Invalid public key: {}", e)))?;
+        let pda = self.subscription_pda(&owner_pubkey, plan_id);
+
+        Ok(db::find_subscription(&self.pool, &pda.to_string())
+            .await?
+            .filter(|row| !row.closed)
+            .map(SubscriptionResponse::from))
+    }
+
+    /// Open subscriptions of any of `owners`, by plan.
+    pub async fn list_subscriptions(&self, owners: &[String]) -> AppResult<Vec<SubscriptionResponse>> {
+        Ok(db::list_subscriptions_by_owners(&self.pool, owners)
+            .await?
+            .into_iter()
+            .map(SubscriptionResponse::from)
+            .collect())
+    }
+
+    pub async fn list_subscribers(
+        &self,
+        plan_ids: Option<&[i64]>,
+        active: Option<bool>,
+        limit: i64,
+        offset: i64,
+    ) -> AppResult<Vec<SubscriptionResponse>> {
+        Ok(db::list_subscriptions(&self.pool, plan_ids, active, limit, offset)
+            .await?
+            .into_iter()
+            .map(SubscriptionResponse::from)
+            .collect())
+    }
+}
@@ -0,0 +1,287 @@
+use actix_web::{get, post, web, HttpMessage, HttpRequest, HttpResponse};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{pubkey::Pubkey, signature::Signature, transaction::Transaction};
+use sqlx::postgres::PgPool;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+use validator::Validate;
+use crate::accounts::Commitment;
+use crate::audit;
+use crate::cache::CacheService;
+use crate::cluster::{Cluster, SolanaClusters};
+use crate::db::{self, PaymentIntent};
+use crate::indexer::IndexerService;
+use crate::metrics;
+use crate::notifications::NotificationService;
+use crate::simulation::SimulatedAction;
+use crate::validation::{validate, FieldError, ValidatedJson};
+use crate::webhooks::{WebhookEventType, WebhookService};
+use crate::{
+    index_submission, subscription_event, AppError, AppResult, AuthToken, SubscriptionRequest,
+};
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct IntentRequest {
+    action: SimulatedAction,
+    #[validate(range(max = 9223372036854775807, message = "must fit in a signed 64-bit integer"))]
+    plan_id: u64,
+    duration: Option<u64>, // Required for create
+    amount: Option<u64>,   // Required for create
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct ConfirmIntentRequest {
+    #[validate(length(min = 1, message = "is required"))]
+    signature: String, // The wallet's base58 signature of the intent's transaction
+}
+
+fn action_name(action: SimulatedAction) -> &'static str {
+    match action {
+        SimulatedAction::Create => "create",
+        SimulatedAction::Renew => "renew",
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+async fn owned_intent(pool: &PgPool, id: &str, owner: &str) -> AppResult<PaymentIntent> {
+    db::get_payment_intent(pool, id)
+        .await?
+        .filter(|intent| intent.owner == owner)
+        .ok_or_else(|| AppError::NotFound(format!("Payment intent {} not found", id)))
+}
+
+// Controllers
+/// Builds the create or renew transaction for the wallet to sign itself, and records it as
+/// an intent awaiting that signature.
+#[utoipa::path(
+    post,
+    path = "/api/v1/intents",
+    tag = "intents",
+    request_body = IntentRequest,
+    responses(
+        (status = 201, description = "Intent created; sign `transaction` and confirm it", body = PaymentIntent),
+        (status = 400, description = "Subscription already exists", body = ErrorResponse),
+        (status = 422, description = "Invalid request", body = ErrorResponse),
+        (status = 502, description = "RPC node unavailable", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/intents")]
+pub async fn create_intent(
+    req: HttpRequest,
+    clusters: web::Data<SolanaClusters>,
+    pool: web::Data<PgPool>,
+    body: ValidatedJson<IntentRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let solana_service = clusters.select(&req)?;
+    let owner = Pubkey::from_str(&auth_token.public_key)
+        .map_err(|e| AppError::BadRequest(format!("Invalid public key: {}", e)))?;
+    let body = body.into_inner();
+
+    let (instruction, duration, amount) = match body.action {
+        SimulatedAction::Create => {
+            let mut missing = Vec::new();
+            if body.duration.is_none() {
+                missing.push(FieldError::new("duration", "required", "is required for create"));
+            }
+            if body.amount.is_none() {
+                missing.push(FieldError::new("amount", "required", "is required for create"));
+            }
+            if !missing.is_empty() {
+                return Err(AppError::Validation(missing));
+            }
+            let request = SubscriptionRequest {
+                plan_id: body.plan_id,
+                duration: body.duration.unwrap_or_default(),
+                amount: body.amount.unwrap_or_default(),
+            };
+            validate(&request)?;
+            solana_service.check_terms(&request)?;
+
+            let pda = solana_service.subscription_pda(&owner, body.plan_id);
+            if solana_service.accounts().get(&pda, solana_service.check_commitment()).await?.is_some() {
+                return Err(AppError::BadRequest(format!("Subscription PDA {} already exists", pda)));
+            }
+            let charged = solana_service.layout.charged_amount(request.amount);
+            (solana_service.create_instruction(&owner, &request), Some(request.duration as i64), Some(charged as i64))
+        }
+        SimulatedAction::Renew => {
+            let amount = solana_service
+                .get_subscription(&auth_token.public_key, body.plan_id, Commitment::Finalized)
+                .await
+                .ok()
+                .map(|sub| sub.amount as i64);
+            (solana_service.renew_instruction(&owner, body.plan_id), None, amount)
+        }
+    };
+
+    let (tx, last_valid_block_height) = solana_service.unsigned_transaction(&owner, &[instruction]).await?;
+    let serialized = bincode::serialize(&tx)
+        .map_err(|e| AppError::InternalServerError(format!("Failed to serialize transaction: {}", e)))?;
+    let created_at = now();
+    let intent = PaymentIntent {
+        id: hex::encode(rand::random::<[u8; 16]>()),
+        owner: auth_token.public_key.clone(),
+        cluster: solana_service.cluster.as_str().to_string(),
+        tenant: solana_service.tenant().to_string(),
+        action: action_name(body.action).to_string(),
+        plan_id: body.plan_id as i64,
+        duration,
+        amount,
+        transaction: BASE64.encode(serialized),
+        last_valid_block_height: last_valid_block_height as i64,
+        status: "requires_signature".to_string(),
+        signature: None,
+        error: None,
+        created_at,
+        updated_at: created_at,
+    };
+    db::insert_payment_intent(&pool, &intent).await?;
+    Ok(HttpResponse::Created().json(intent))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/intents/{id}",
+    tag = "intents",
+    params(("id" = String, Path, description = "Intent id")),
+    responses(
+        (status = 200, description = "Intent state", body = PaymentIntent),
+        (status = 404, description = "No such intent for this wallet", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/intents/{id}")]
+pub async fn get_intent(
+    req: HttpRequest,
+    path: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let intent = owned_intent(&pool, &path.into_inner(), &auth_token.public_key).await?;
+    Ok(HttpResponse::Ok().json(intent))
+}
+
+/// Accepts the wallet's signature of the intent's transaction, sends it and waits for
+/// confirmation. The intent comes back `succeeded`, `failed`, or still `processing` if
+/// confirmation timed out, in which case the job worker settles it later. Confirming again
+/// with the same signature returns the intent as it stands.
+#[utoipa::path(
+    post,
+    path = "/api/v1/intents/{id}/confirm",
+    tag = "intents",
+    params(("id" = String, Path, description = "Intent id")),
+    request_body = ConfirmIntentRequest,
+    responses(
+        (status = 200, description = "Intent after sending", body = PaymentIntent),
+        (status = 400, description = "Signature does not sign the intent's transaction", body = ErrorResponse),
+        (status = 404, description = "No such intent for this wallet", body = ErrorResponse),
+        (status = 409, description = "Intent already confirmed with another signature, or expired", body = ErrorResponse),
+        (status = 422, description = "Invalid request", body = ErrorResponse),
+        (status = 502, description = "RPC node unavailable", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/intents/{id}/confirm")]
+#[allow(clippy::too_many_arguments)]
+pub async fn confirm_intent(
+    req: HttpRequest,
+    path: web::Path<String>,
+    clusters: web::Data<SolanaClusters>,
+    webhook_service: web::Data<WebhookService>,
+    indexer: web::Data<IndexerService>,
+    cache: web::Data<CacheService>,
+    notifications: web::Data<NotificationService>,
+    pool: web::Data<PgPool>,
+    body: ValidatedJson<ConfirmIntentRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let id = path.into_inner();
+    let intent = owned_intent(&pool, &id, &auth_token.public_key).await?;
+    if intent.status != "requires_signature" {
+        if intent.signature.as_deref() == Some(body.signature.as_str()) {
+            return Ok(HttpResponse::Ok().json(intent));
+        }
+        return Err(AppError::Conflict(format!("Payment intent {} is {}", id, intent.status)));
+    }
+
+    let signature = Signature::from_str(&body.signature)
+        .map_err(|e| AppError::BadRequest(format!("Invalid signature: {}", e)))?;
+    let mut tx: Transaction = BASE64
+        .decode(&intent.transaction)
+        .ok()
+        .and_then(|bytes| bincode::deserialize(&bytes).ok())
+        .ok_or_else(|| AppError::InternalServerError("Stored intent transaction is corrupt".to_string()))?;
+    // The wallet is the fee payer and the only signer
+    tx.signatures = vec![signature];
+    tx.verify()
+        .map_err(|_| AppError::BadRequest("Signature does not sign this intent's transaction".to_string()))?;
+
+    let solana_service = intent
+        .cluster
+        .parse::<Cluster>()
+        .ok()
+        .and_then(|cluster| clusters.get(cluster, &intent.tenant))
+        .ok_or_else(|| {
+            AppError::InternalServerError(format!("Cluster {} tenant {} is not configured", intent.cluster, intent.tenant))
+        })?;
+    let guard = solana_service.lock_wallet(&intent.owner).await?;
+    let block_height = metrics::observe_rpc("getBlockHeight", solana_service.rpc.client().get_block_height())
+        .await
+        .map_err(|e| AppError::rpc("Failed to fetch block height", e))?;
+    if block_height > intent.last_valid_block_height as u64 {
+        db::expire_payment_intent(&pool, &id).await?;
+        return Err(AppError::Conflict(format!("Payment intent {} expired; create a new one", id)));
+    }
+    if !db::start_payment_intent(&pool, &id, &body.signature).await? {
+        return Err(AppError::Conflict(format!("Payment intent {} was confirmed concurrently", id)));
+    }
+    audit::attach_signatures(&req, [&body.signature]);
+
+    let owner = Pubkey::from_str(&intent.owner)
+        .map_err(|e| AppError::InternalServerError(format!("Invalid intent owner: {}", e)))?;
+    let name = format!("{}_subscription", intent.action);
+    let plan_id = intent.plan_id as u64;
+    // The job records the outcome on the intent, whether it settles now or in the job worker
+    let result = solana_service
+        .send_signed_transaction(
+            &name,
+            &owner,
+            plan_id,
+            Vec::new(),
+            &tx,
+            intent.last_valid_block_height as u64,
+            None,
+        )
+        .await;
+    drop(guard);
+    match result {
+        Ok(signature) if solana_service.is_primary() => {
+            let pda = solana_service.subscription_pda(&owner, plan_id);
+            index_submission(&indexer, &signature).await;
+            cache.invalidate_subscription(&pda).await;
+            let event_type = match intent.action.as_str() {
+                "create" => WebhookEventType::SubscriptionCreated,
+                _ => WebhookEventType::SubscriptionRenewed,
+            };
+            webhook_service
+                .dispatch(event_type, subscription_event(solana_service, &intent.owner, plan_id, &signature))
+                .await;
+            notifications.notify_payment(&pda.to_string(), &signature);
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!("Payment intent {} did not confirm: {}", id, e),
+    }
+
+    let intent = owned_intent(&pool, &id, &auth_token.public_key).await?;
+    Ok(HttpResponse::Ok().json(intent))
+}
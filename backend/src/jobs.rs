@@ -0,0 +1,327 @@
+use actix_web::{get, web, HttpMessage, HttpRequest, HttpResponse};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{pubkey::Pubkey, signature::Signature, transaction::Transaction};
+use solana_transaction_status::TransactionStatus;
+use sqlx::postgres::PgPool;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+use crate::cache::CacheService;
+use crate::circuit;
+use crate::custody;
+use crate::cluster::{Cluster, SolanaClusters};
+use crate::db::{self, TransactionJobRow};
+use crate::indexer::IndexerService;
+use crate::metrics;
+use crate::nonces;
+use crate::notifications::NotificationService;
+use crate::refunds;
+use crate::reporting;
+use crate::treasury_withdrawals;
+use crate::webhooks::{WebhookEventType, WebhookService};
+use crate::{subscription_event, AppError, AppResult, AuthToken, Config, Role, SolanaService};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+// Leaves jobs whose request is still waiting on confirmation alone
+pub const FIRST_CHECK_SECS: i64 = 90;
+const BATCH_SIZE: i64 = 100;
+const MAX_BACKOFF_SECS: u64 = 600;
+
+// Models
+/// An outbound transaction. `status` moves `built` (signed and recorded) → `submitted`
+/// (sent at least once) → `confirmed` or `failed`.
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow, ToSchema)]
+pub struct TransactionJob {
+    id: String,
+    signature: String,
+    cluster: String,
+    tenant: String,
+    instruction: String, // Program instruction, or `batch`
+    #[serde(skip_serializing)]
+    owner: String,
+    plan_id: i64,
+    items: Vec<String>, // `<instruction>:<plan_id>` per action of a batch, else empty
+    status: String,
+    attempts: i32, // Sends so far
+    error: Option<String>,
+    next_attempt_at: Option<i64>, // Next check by the worker while unresolved
+    created_at: i64,
+    updated_at: i64,
+}
+
+// Job Worker
+/// Resolves jobs the submitting request left unresolved, after a restart or a confirmation
+/// timeout: landed transactions are confirmed, indexed and reported, unconfirmed ones are
+/// re-sent with exponential backoff up to `JOB_MAX_ATTEMPTS` sends while their blockhash or
+/// durable nonce is valid, and the rest fail.
+#[derive(Clone)]
+pub struct JobWorker {
+    clusters: SolanaClusters,
+    indexer: IndexerService,
+    webhooks: WebhookService,
+    notifications: NotificationService,
+    cache: CacheService,
+    pool: PgPool,
+    max_attempts: i32,
+    backoff_base_secs: u64,
+}
+
+impl JobWorker {
+    pub fn new(
+        config: &Config,
+        clusters: SolanaClusters,
+        indexer: IndexerService,
+        webhooks: WebhookService,
+        notifications: NotificationService,
+        cache: CacheService,
+        pool: PgPool,
+    ) -> Self {
+        Self {
+            clusters,
+            indexer,
+            webhooks,
+            notifications,
+            cache,
+            pool,
+            max_attempts: config.job_max_attempts.max(1),
+            backoff_base_secs: config.job_retry_backoff_secs.max(1),
+        }
+    }
+
+    pub async fn run(self) {
+        loop {
+            let started = Instant::now();
+            match self.sweep().await {
+                Ok(resolved) => {
+                    metrics::record_job_success("transaction_jobs", started, resolved);
+                    if resolved > 0 {
+                        tracing::info!("Resolved {} transaction jobs", resolved);
+                    }
+                }
+                Err(e) => {
+                    metrics::record_job_failure("transaction_jobs", started);
+                    tracing::error!("Transaction job sweep failed: {}", e);
+                    reporting::capture_job_failure("transaction_jobs", &e);
+                }
+            }
+            tokio::time::sleep(SWEEP_INTERVAL).await;
+        }
+    }
+
+    #[tracing::instrument(name = "transaction_job_sweep", skip_all)]
+    pub async fn sweep(&self) -> AppResult<usize> {
+        let due = db::list_due_transaction_jobs(&self.pool, BATCH_SIZE).await?;
+        let mut resolved = 0;
+        for row in due {
+            match self.resolve(&row).await {
+                Ok(true) => {
+                    resolved += 1;
+                    if row.instruction == refunds::REFUND_INSTRUCTION {
+                        self.report_refund(&row).await;
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    tracing::warn!("Could not resolve transaction job {}: {}", row.id, e);
+                    // Checked again after a backoff, without counting as a send
+                    let next_attempt_at = self.next_attempt_at(row.attempts);
+                    db::reschedule_transaction_job(&self.pool, &row.id, row.attempts, next_attempt_at, Some(&e.to_string()))
+                        .await?;
+                }
+            }
+        }
+        Ok(resolved)
+    }
+
+    fn next_attempt_at(&self, attempts: i32) -> i64 {
+        let backoff = self.backoff_base_secs.saturating_mul(1 << attempts.clamp(0, 16)).min(MAX_BACKOFF_SECS);
+        now() + backoff as i64
+    }
+
+    async fn resolve(&self, row: &TransactionJobRow) -> AppResult<bool> {
+        let solana_service = row.cluster
+            .parse::<Cluster>()
+            .ok()
+            .and_then(|cluster| self.clusters.get(cluster, &row.tenant))
+            .ok_or_else(|| {
+                AppError::InternalServerError(format!("Cluster {} tenant {} is not configured", row.cluster, row.tenant))
+            })?;
+        let signature = Signature::from_str(&row.signature)
+            .map_err(|e| AppError::InternalServerError(format!("Invalid stored signature: {}", e)))?;
+        let client = solana_service.rpc.client();
+
+        match signature_status(solana_service, signature).await? {
+            Some(status) if status.err.is_some() => {
+                let error = format!("{:?}", status.err);
+                db::set_transaction_job_status(&self.pool, &row.id, "failed", Some(&error)).await?;
+                Ok(true)
+            }
+            Some(_) => {
+                db::set_transaction_job_status(&self.pool, &row.id, "confirmed", None).await?;
+                self.complete(solana_service, row).await;
+                Ok(true)
+            }
+            None => {
+                let tx: Transaction = match BASE64
+                    .decode(&row.transaction)
+                    .ok()
+                    .and_then(|bytes| bincode::deserialize(&bytes).ok())
+                {
+                    Some(tx) => tx,
+                    None => {
+                        db::set_transaction_job_status(&self.pool, &row.id, "failed", Some("Stored transaction is corrupt"))
+                            .await?;
+                        return Ok(true);
+                    }
+                };
+                let nonce_account = row.nonce_account.as_deref().and_then(|address| Pubkey::from_str(address).ok());
+                let expired = match nonce_account {
+                    // Valid for as long as the nonce still holds the value it was built on
+                    Some(address) => {
+                        nonces::current_blockhash(solana_service, &address).await? != tx.message.recent_blockhash
+                    }
+                    None => {
+                        let block_height = metrics::observe_rpc("getBlockHeight", client.get_block_height())
+                            .await
+                            .map_err(|e| AppError::rpc("Failed to fetch block height", e))?;
+                        block_height > row.last_valid_block_height as u64
+                    }
+                };
+                if expired {
+                    // Landing advances the nonce too, possibly since the status was read
+                    if nonce_account.is_some() && signature_status(solana_service, signature).await?.is_some() {
+                        return Ok(false);
+                    }
+                    // It can no longer land, so nothing was charged
+                    let error = match nonce_account {
+                        Some(_) => "Nonce advanced before the transaction landed",
+                        None => "Blockhash expired before the transaction landed",
+                    };
+                    db::set_transaction_job_status(&self.pool, &row.id, "failed", Some(error)).await?;
+                    return Ok(true);
+                }
+                // Out of sends: keep polling until it lands or the blockhash expires
+                if row.attempts >= self.max_attempts {
+                    // A nonce never expires on its own, so advance it; the next sweep then
+                    // finds the transaction either landed or no longer able to
+                    if let Some(address) = nonce_account {
+                        nonces::invalidate(solana_service, &address).await?;
+                        return Ok(false);
+                    }
+                    db::reschedule_transaction_job(&self.pool, &row.id, row.attempts, self.next_attempt_at(row.attempts), None)
+                        .await?;
+                    return Ok(false);
+                }
+
+                let error = match metrics::observe_rpc("sendTransaction", client.send_transaction(&tx)).await {
+                    Ok(_) => None,
+                    // Never sent, so it does not use up an attempt
+                    Err(e) if circuit::shed(&e).is_some() => return Err(AppError::rpc("Failed to resend transaction", e)),
+                    Err(e) => Some(format!("Failed to resend transaction: {}", e)),
+                };
+                let attempts = row.attempts + 1;
+                db::reschedule_transaction_job(&self.pool, &row.id, attempts, self.next_attempt_at(attempts), error.as_deref())
+                    .await?;
+                Ok(false)
+            }
+        }
+    }
+
+    // Settling the job settled the refund too
+    async fn report_refund(&self, row: &TransactionJobRow) {
+        match db::get_refund_by_signature(&self.pool, &row.signature).await {
+            Ok(Some(refund)) => refunds::dispatch_event(&self.webhooks, &refund).await,
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Failed to load refund of transaction {}: {}", row.signature, e),
+        }
+    }
+
+    // The request that submitted it never got to these steps
+    async fn complete(&self, solana_service: &SolanaService, row: &TransactionJobRow) {
+        if !solana_service.is_primary()
+            || row.instruction == refunds::REFUND_INSTRUCTION
+            || row.instruction == custody::WITHDRAWAL_INSTRUCTION
+            || row.instruction == treasury_withdrawals::TREASURY_WITHDRAWAL_INSTRUCTION
+        {
+            return;
+        }
+        if let Err(e) = self.indexer.index_signature(&row.signature).await {
+            tracing::warn!("Failed to index recovered transaction {}: {}", row.signature, e);
+        }
+        for (instruction, plan_id) in row.actions() {
+            let pda = match solana_service.subscription_address(&row.owner, plan_id) {
+                Ok(pda) => pda,
+                Err(_) => return,
+            };
+            self.cache.invalidate_subscription(&pda).await;
+            if instruction == "create_subscription" || instruction == "renew_subscription" {
+                self.notifications.notify_payment(&pda.to_string(), &row.signature);
+            }
+
+            let event_type = match instruction.as_str() {
+                "create_subscription" => WebhookEventType::SubscriptionCreated,
+                "renew_subscription" => WebhookEventType::SubscriptionRenewed,
+                "cancel_subscription" => WebhookEventType::SubscriptionCancelled,
+                _ => continue,
+            };
+            self.webhooks
+                .dispatch(event_type, subscription_event(solana_service, &row.owner, plan_id, &row.signature))
+                .await;
+        }
+    }
+}
+
+async fn signature_status(
+    solana_service: &SolanaService,
+    signature: Signature,
+) -> AppResult<Option<TransactionStatus>> {
+    let client = solana_service.rpc.client();
+    Ok(metrics::observe_rpc("getSignatureStatuses", client.get_signature_statuses_with_history(&[signature]))
+        .await
+        .map_err(|e| AppError::rpc("Failed to fetch signature status", e))?
+        .value
+        .pop()
+        .flatten())
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+// Controllers
+/// A transaction job by its id or transaction signature. Wallets see their own jobs; admins
+/// see every job.
+#[utoipa::path(
+    get,
+    path = "/api/v1/jobs/{id}",
+    tag = "jobs",
+    params(("id" = String, Path, description = "Job id, or the transaction signature")),
+    responses(
+        (status = 200, description = "Job state", body = TransactionJob),
+        (status = 404, description = "No such job for this wallet", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/jobs/{id}")]
+pub async fn get_job(
+    req: HttpRequest,
+    path: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let id = path.into_inner();
+    let job = sqlx::query_as::<_, TransactionJob>(
+        "SELECT id, signature, cluster, tenant, instruction, owner, plan_id, items, status, attempts, error,
+                next_attempt_at, created_at, updated_at
+         FROM transaction_jobs
+         WHERE id = $1 OR signature = $1",
+    )
+    .bind(&id)
+    .fetch_optional(pool.get_ref())
+    .await
+    .map_err(|e| AppError::DatabaseError(format!("Failed to fetch transaction job: {}", e)))?
+    .filter(|job| job.owner == auth_token.public_key || auth_token.has_role(Role::Admin))
+    .ok_or_else(|| AppError::NotFound(format!("Job {} not found", id)))?;
+    Ok(HttpResponse::Ok().json(job))
+}
@@ -0,0 +1,298 @@
+use actix_web::{get, post, web, HttpResponse};
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use base64::{engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD}, Engine};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use solana_sdk::signer::keypair::keypair_from_seed;
+use solana_sdk::signer::Signer;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use utoipa::ToSchema;
+use zeroize::Zeroizing;
+use crate::db::JwtRotationRow;
+use crate::{AppError, AppResult, AuthService, Claims};
+
+// PKCS#8 v1 wrapping of a raw Ed25519 seed, as expected by `EncodingKey::from_ed_der`
+const ED25519_PKCS8_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+// Key id of the key derived from JWT_SECRET when no JWT_SIGNING_KEYS are configured
+const LEGACY_KID: &str = "default";
+// Seconds of clock skew `Validation` allows past `exp`, its default
+pub const EXPIRY_LEEWAY_SECS: u64 = 60;
+
+// Models
+/// An Ed25519 public key in JWK form (RFC 8037).
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Jwk {
+    kty: String, // Always "OKP"
+    crv: String, // Always "Ed25519"
+    x: String,   // Base64url public key
+    kid: String,
+    alg: String,
+    #[serde(rename = "use")]
+    key_use: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct JwtRotation {
+    pub kid: String, // Signs every token issued from now on
+    pub previous_kid: String,
+    pub previous_expires_at: i64, // Tokens of the previous key are rejected after this
+}
+
+/// Reads `JWT_SIGNING_KEYS` as comma-separated `<kid>:<base58 32-byte seed>` pairs. Without
+/// it, a single key is derived from `JWT_SECRET`.
+pub fn load_signing_keys() -> Vec<(String, [u8; 32])> {
+    let Some(keys) = std::env::var("JWT_SIGNING_KEYS").ok().filter(|v| !v.trim().is_empty()) else {
+        let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET or JWT_SIGNING_KEYS must be set");
+        return vec![(LEGACY_KID.to_string(), Sha256::digest(secret.as_bytes()).into())];
+    };
+    let mut parsed: Vec<(String, [u8; 32])> = Vec::new();
+    for entry in keys.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (kid, seed) = entry
+            .split_once(':')
+            .unwrap_or_else(|| panic!("JWT_SIGNING_KEYS entry {} is not <kid>:<seed>", entry));
+        let seed: [u8; 32] = bs58::decode(seed)
+            .into_vec()
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .unwrap_or_else(|| panic!("Signing key {} must be a base58 32-byte seed", kid));
+        assert!(parsed.iter().all(|(k, _)| k != kid), "Signing key {} is listed twice", kid);
+        parsed.push((kid.to_string(), seed));
+    }
+    assert!(!parsed.is_empty(), "JWT_SIGNING_KEYS lists no keys");
+    parsed
+}
+
+// Signing Keys
+#[derive(Clone)]
+struct SigningKey {
+    kid: String,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    x: String,
+}
+
+impl SigningKey {
+    fn from_seed(kid: &str, seed: &[u8; 32]) -> Self {
+        let public_key = keypair_from_seed(seed).expect("Invalid Ed25519 seed").pubkey().to_bytes();
+        let x = URL_SAFE_NO_PAD.encode(public_key);
+        SigningKey {
+            kid: kid.to_string(),
+            encoding: EncodingKey::from_ed_der(&[&ED25519_PKCS8_PREFIX[..], &seed[..]].concat()),
+            decoding: DecodingKey::from_ed_components(&x).expect("Invalid Ed25519 public key"),
+            x,
+        }
+    }
+}
+
+/// Keys made active through `rotate`, as recorded in `jwt_key_rotations`.
+#[derive(Default)]
+struct Rotations {
+    keys: Vec<SigningKey>,          // Oldest first; the last one signs
+    expiries: HashMap<String, i64>, // Public key of each replaced key, to when it stops verifying
+}
+
+/// Ed25519 keys for access tokens. Tokens are signed with the active key and carry its
+/// `kid`; every configured key verifies, so a retired key can stay listed until the tokens it
+/// signed have expired. A rotation replaces the active key with a generated one and keeps the
+/// key it replaced verifying until the tokens it signed have expired. Generated keys are
+/// stored sealed under a key derived from the configured active key, so changing that key
+/// drops them.
+#[derive(Clone)]
+pub struct JwtKeys {
+    keys: Vec<SigningKey>,
+    active: usize,
+    rotations: Arc<RwLock<Rotations>>,
+    sealing_key: Zeroizing<[u8; 32]>,
+    validation: Validation,
+}
+
+impl JwtKeys {
+    pub fn new(seeds: &[(String, [u8; 32])], active_kid: Option<&str>, issuer: &str, audience: &str) -> Self {
+        let keys: Vec<SigningKey> = seeds.iter().map(|(kid, seed)| SigningKey::from_seed(kid, seed)).collect();
+        let active = match active_kid {
+            Some(kid) => keys
+                .iter()
+                .position(|k| k.kid == kid)
+                .unwrap_or_else(|| panic!("JWT_ACTIVE_KID {} is not in JWT_SIGNING_KEYS", kid)),
+            None => 0,
+        };
+        let mut sealing_key = Zeroizing::new([0u8; 32]);
+        sealing_key.copy_from_slice(&Sha256::digest([&b"jwt-key-rotation:"[..], &seeds[active].1[..]].concat()));
+
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_issuer(&[issuer]);
+        validation.set_audience(&[audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        validation.leeway = EXPIRY_LEEWAY_SECS;
+        Self { keys, active, rotations: Arc::new(RwLock::new(Rotations::default())), sealing_key, validation }
+    }
+
+    pub fn sign(&self, claims: &Claims) -> AppResult<String> {
+        let rotations = self.rotations.read().unwrap();
+        let key = rotations.keys.last().unwrap_or(&self.keys[self.active]);
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(key.kid.clone());
+        jsonwebtoken::encode(&header, claims, &key.encoding)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to create JWT: {}", e)))
+    }
+
+    /// Rotated keys first, newest first, then the configured keys; replaced keys past their
+    /// expiry are left out. The signing key is always included.
+    fn live_keys(&self, rotations: &Rotations, now: i64) -> Vec<SigningKey> {
+        let signing = rotations.keys.last().unwrap_or(&self.keys[self.active]);
+        rotations
+            .keys
+            .iter()
+            .rev()
+            .chain(self.keys.iter())
+            .filter(|key| key.x == signing.x || rotations.expiries.get(&key.x).is_none_or(|expiry| now < *expiry))
+            .cloned()
+            .collect()
+    }
+
+    pub fn verify(&self, token: &str) -> AppResult<Claims> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|e| AppError::Auth(format!("Invalid token: {}", e)))?;
+        let kid = header.kid.ok_or_else(|| AppError::Auth("Invalid token: missing kid".to_string()))?;
+        let live = self.live_keys(&self.rotations.read().unwrap(), crate::unix_now());
+        let key = live
+            .iter()
+            .find(|k| k.kid == kid)
+            .ok_or_else(|| AppError::Auth(format!("Invalid token: unknown kid {}", kid)))?;
+        jsonwebtoken::decode::<Claims>(token, &key.decoding, &self.validation)
+            .map(|data| data.claims)
+            .map_err(|e| AppError::Auth(format!("Invalid token: {}", e)))
+    }
+
+    /// Whether `token` names a `kid` this replica has never loaded, as after a rotation made
+    /// on another replica.
+    pub fn is_unknown_kid(&self, token: &str) -> bool {
+        let Some(kid) = jsonwebtoken::decode_header(token).ok().and_then(|header| header.kid) else {
+            return false;
+        };
+        let rotations = self.rotations.read().unwrap();
+        !rotations.keys.iter().chain(self.keys.iter()).any(|key| key.kid == kid)
+    }
+
+    /// Generates a key to replace the signing key. The replaced key keeps verifying for
+    /// `verify_for_secs`. Takes effect once the row is stored and passed to `apply`.
+    pub fn rotate(&self, now: i64, verify_for_secs: u64) -> AppResult<JwtRotationRow> {
+        let kid = format!("r{}-{}", now, hex::encode(rand::random::<[u8; 4]>()));
+        let seed = Zeroizing::new(rand::random::<[u8; 32]>());
+        let key = SigningKey::from_seed(&kid, &seed);
+        let nonce: [u8; 12] = rand::random();
+        let ciphertext = self
+            .cipher()
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: &seed[..], aad: kid.as_bytes() })
+            .map_err(|_| AppError::InternalServerError("Failed to seal JWT signing key".to_string()))?;
+
+        let rotations = self.rotations.read().unwrap();
+        let previous = rotations.keys.last().unwrap_or(&self.keys[self.active]);
+        Ok(JwtRotationRow {
+            kid,
+            public_key: key.x,
+            sealed_seed: BASE64.encode([&nonce[..], &ciphertext[..]].concat()),
+            previous_kid: previous.kid.clone(),
+            previous_public_key: previous.x.clone(),
+            previous_expires_at: now + verify_for_secs as i64,
+            rotated_at: now,
+        })
+    }
+
+    /// Replaces the rotated keys with those of `rows`, oldest first. Rows sealed under another
+    /// configured key are skipped.
+    pub fn apply(&self, rows: &[JwtRotationRow]) {
+        let mut rotations = Rotations::default();
+        for row in rows {
+            rotations.expiries.insert(row.previous_public_key.clone(), row.previous_expires_at);
+            match self.unseal(row) {
+                Ok(key) => rotations.keys.push(key),
+                Err(e) => tracing::warn!("Skipping JWT signing key {}: {}", row.kid, e),
+            }
+        }
+        *self.rotations.write().unwrap() = rotations;
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new_from_slice(&self.sealing_key[..]).expect("Sealing key is 32 bytes")
+    }
+
+    fn unseal(&self, row: &JwtRotationRow) -> Result<SigningKey, String> {
+        let sealed = BASE64.decode(&row.sealed_seed).map_err(|e| format!("invalid sealed seed: {}", e))?;
+        if sealed.len() < 12 {
+            return Err("sealed seed is too short".to_string());
+        }
+        let (nonce, ciphertext) = sealed.split_at(12);
+        let seed = Zeroizing::new(
+            self.cipher()
+                .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: row.kid.as_bytes() })
+                .map_err(|_| "sealed under a different signing key".to_string())?,
+        );
+        let seed: &[u8; 32] = seed[..].try_into().map_err(|_| "seed is not 32 bytes".to_string())?;
+        let key = SigningKey::from_seed(&row.kid, seed);
+        if key.x != row.public_key {
+            return Err("does not match its public key".to_string());
+        }
+        Ok(key)
+    }
+
+    pub fn jwk_set(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .live_keys(&self.rotations.read().unwrap(), crate::unix_now())
+                .into_iter()
+                .map(|key| Jwk {
+                    kty: "OKP".to_string(),
+                    crv: "Ed25519".to_string(),
+                    x: key.x,
+                    kid: key.kid,
+                    alg: "EdDSA".to_string(),
+                    key_use: "sig".to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+// Controllers
+/// Public keys that verify access tokens, matched by the token's `kid` header.
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    tag = "auth",
+    responses((status = 200, description = "JSON Web Key Set", body = JwkSet))
+)]
+#[get("/.well-known/jwks.json")]
+pub async fn jwks(auth_service: web::Data<AuthService>) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(("Cache-Control", "public, max-age=300"))
+        .json(auth_service.jwt_keys().jwk_set())
+}
+
+/// Replaces the access token signing key with a generated one on every replica. Tokens
+/// signed with the replaced key stay valid until they expire, then that key stops verifying.
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/jwt/rotate",
+    tag = "admin",
+    responses(
+        (status = 200, description = "New signing key", body = JwtRotation),
+        (status = 500, description = "Rotation could not be stored", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/jwt/rotate")]
+pub async fn rotate_jwt_key(auth_service: web::Data<AuthService>) -> AppResult<HttpResponse> {
+    let rotation = auth_service.rotate_jwt_key().await?;
+    Ok(HttpResponse::Ok().json(rotation))
+}
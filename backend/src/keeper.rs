@@ -0,0 +1,336 @@
+use actix_web::{get, put, web, HttpMessage, HttpRequest, HttpResponse};
+use futures_util::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+use crate::cache::CacheService;
+use crate::db::{self, DueSubscriptionRow, KeeperRunRow};
+use crate::indexer::IndexerService;
+use crate::email::EmailTemplate;
+use crate::metrics;
+use crate::notifications::NotificationService;
+use crate::reporting;
+use crate::simulation;
+use crate::validation::{ValidatedJson, ValidatedQuery};
+use crate::webhooks::{SubscriptionEventData, WebhookEventType, WebhookService};
+use crate::{subscription_event, AppError, AppResult, AuthToken, Config, SolanaService};
+
+const DEFAULT_RUNS_LIMIT: i64 = 20;
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct AutoRenewRequest {
+    enabled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct AutoRenewResponse {
+    subscription: String,
+    auto_renew: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct KeeperRunsQuery {
+    #[validate(range(min = 1, max = 500, message = "must be between 1 and 500"))]
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Renewed,
+    Expired,
+    Failed, // Auto-renewal failed; reported as expired
+}
+
+// Keeper Service
+/// Cranks subscriptions whose billing period has ended. Subscriptions with auto-renew on are
+/// renewed; the rest are marked expired, which the reminder job then reports.
+///
+/// Renewals are signed by the backend wallet and the program has no delegate or escrow, so
+/// only subscriptions owned by that wallet can be auto-renewed.
+///
+/// In dry-run mode nothing is sent or marked: each renewal is simulated instead, and what
+/// would have been renewed or expired is logged and recorded as a dry run.
+#[derive(Clone)]
+pub struct KeeperService {
+    solana_service: SolanaService,
+    indexer: IndexerService,
+    webhooks: WebhookService,
+    notifications: NotificationService,
+    cache: CacheService,
+    pool: PgPool,
+    interval: Duration,
+    concurrency: usize,
+    batch_size: i64,
+    dry_run: bool,
+}
+
+impl KeeperService {
+    /// `solana_service` must be the primary cluster's, which is the one that is indexed.
+    pub fn new(
+        config: &Config,
+        solana_service: SolanaService,
+        indexer: IndexerService,
+        webhooks: WebhookService,
+        notifications: NotificationService,
+        cache: CacheService,
+        pool: PgPool,
+    ) -> Self {
+        Self {
+            solana_service,
+            indexer,
+            webhooks,
+            notifications,
+            cache,
+            pool,
+            interval: Duration::from_secs(config.keeper_interval_secs),
+            concurrency: config.keeper_concurrency.max(1),
+            batch_size: config.keeper_batch_size,
+            dry_run: config.keeper_dry_run,
+        }
+    }
+
+    /// Overrides `KEEPER_DRY_RUN`, for the `keeper --dry-run` command.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub async fn run(self) {
+        // Kept apart so a dry run does not pass for keeper activity on dashboards
+        let job = if self.dry_run { "keeper_dry_run" } else { "keeper" };
+        if self.dry_run {
+            tracing::warn!("Keeper is in dry-run mode: subscriptions will not be renewed or expired");
+        }
+        loop {
+            let started = Instant::now();
+            match self.run_once().await {
+                Ok(report) => {
+                    metrics::record_job_success(job, started, report.scanned as usize);
+                    if report.scanned > 0 {
+                        tracing::info!(
+                            "Keeper {}: {} due, {} renewed, {} expired, {} failed renewals",
+                            if report.dry_run { "dry run" } else { "run" },
+                            report.scanned, report.renewed, report.expired, report.failed
+                        );
+                    }
+                }
+                Err(e) => {
+                    metrics::record_job_failure(job, started);
+                    tracing::error!("Keeper run failed: {}", e);
+                    reporting::capture_job_failure(job, &e);
+                }
+            }
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+
+    /// Processes one batch of due subscriptions and records the run.
+    #[tracing::instrument(name = "keeper_run", skip_all)]
+    pub async fn run_once(&self) -> AppResult<KeeperRunRow> {
+        let started_at = now();
+        let due = db::list_due_subscriptions(&self.pool, started_at, self.batch_size).await?;
+        let outcomes: Vec<Outcome> = stream::iter(due)
+            .map(|row| self.process(row))
+            .buffer_unordered(self.concurrency)
+            .collect()
+            .await;
+
+        let count = |outcome: Outcome| outcomes.iter().filter(|o| **o == outcome).count() as i32;
+        let report = KeeperRunRow {
+            id: 0,
+            started_at,
+            finished_at: now(),
+            scanned: outcomes.len() as i32,
+            renewed: count(Outcome::Renewed),
+            expired: count(Outcome::Expired),
+            failed: count(Outcome::Failed),
+            dry_run: self.dry_run,
+        };
+        if report.scanned > 0 {
+            db::insert_keeper_run(&self.pool, &report).await?;
+        }
+        Ok(report)
+    }
+
+    #[tracing::instrument(name = "keeper_subscription", skip_all, fields(subscription = %row.subscription.pda))]
+    async fn process(&self, row: DueSubscriptionRow) -> Outcome {
+        if self.dry_run {
+            return self.preview(row).await;
+        }
+        let subscription = row.subscription;
+        let plan_id = subscription.plan_id as u64;
+
+        let mut outcome = Outcome::Expired;
+        if row.auto_renew && subscription.owner == self.solana_service.signer().to_string() {
+            match self.solana_service.renew_subscription(&subscription.owner, plan_id).await {
+                Ok(signature) => {
+                    if let Err(e) = self.indexer.index_signature(&signature).await {
+                        tracing::warn!("Failed to index keeper renewal {}: {}", signature, e);
+                    }
+                    self.invalidate(&subscription.owner, plan_id).await;
+                    self.webhooks
+                        .dispatch(
+                            WebhookEventType::SubscriptionRenewed,
+                            subscription_event(&self.solana_service, &subscription.owner, plan_id, &signature),
+                        )
+                        .await;
+                    self.notifications.notify_payment(&subscription.pda, &signature);
+                    return Outcome::Renewed;
+                }
+                Err(e) => {
+                    tracing::warn!("Auto-renewal of {} failed: {}", subscription.pda, e);
+                    reporting::capture(
+                        "job",
+                        format!("Auto-renewal failed: {}", e),
+                        vec![("job", "keeper".to_string()), ("subscription", subscription.pda.clone())],
+                    );
+                    outcome = Outcome::Failed;
+                }
+            }
+        }
+
+        let period_end = subscription.start_time + subscription.duration;
+        if let Err(e) = db::mark_subscription_expired(&self.pool, &subscription.pda, period_end).await {
+            // Left unmarked, so the next run retries it
+            tracing::error!("Failed to expire subscription {}: {}", subscription.pda, e);
+            return Outcome::Failed;
+        }
+        self.invalidate(&subscription.owner, plan_id).await;
+        if outcome == Outcome::Failed {
+            self.notifications.notify(
+                &subscription.owner,
+                EmailTemplate::RenewalFailed { subscription: subscription.pda.clone(), plan_id },
+            );
+            self.webhooks
+                .dispatch(
+                    WebhookEventType::SubscriptionRenewalFailed,
+                    SubscriptionEventData {
+                        subscription: subscription.pda.clone(),
+                        owner: subscription.owner.clone(),
+                        plan_id,
+                        signature: None,
+                        milestone: None,
+                        expires_at: Some(period_end),
+                        refund_id: None,
+                        refund_lamports: None,
+                    },
+                )
+                .await;
+        }
+        outcome
+    }
+
+    /// What `process` would do, without sending or marking anything.
+    async fn preview(&self, row: DueSubscriptionRow) -> Outcome {
+        let subscription = row.subscription;
+        let plan_id = subscription.plan_id as u64;
+        let period_end = subscription.start_time + subscription.duration;
+        if row.auto_renew && subscription.owner == self.solana_service.signer().to_string() {
+            match self.simulate_renewal(&subscription.owner, plan_id).await {
+                Ok(fee) => {
+                    tracing::info!(
+                        "Dry run: would renew {} (plan {}, owner {}) for a {} lamport fee",
+                        subscription.pda, plan_id, subscription.owner, fee
+                    );
+                    return Outcome::Renewed;
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Dry run: renewal of {} (plan {}) would fail and it would be expired: {}",
+                        subscription.pda, plan_id, e
+                    );
+                    return Outcome::Failed;
+                }
+            }
+        }
+        tracing::info!(
+            "Dry run: would expire {} (plan {}, owner {}), its period ended at {}",
+            subscription.pda, plan_id, subscription.owner, period_end
+        );
+        Outcome::Expired
+    }
+
+    /// The renewal transaction, simulated; returns its network fee.
+    async fn simulate_renewal(&self, owner: &str, plan_id: u64) -> AppResult<u64> {
+        let tx = self.solana_service.build_renew_tx(owner, plan_id)?;
+        let (fee, result) = self.solana_service.simulate(&tx.owner, &[tx.instruction]).await?;
+        match result.err {
+            Some(err) => Err(AppError::SolanaError(simulation::describe_error(&err))),
+            None => Ok(fee),
+        }
+    }
+
+    async fn invalidate(&self, owner: &str, plan_id: u64) {
+        if let Ok(pda) = self.solana_service.subscription_address(owner, plan_id) {
+            self.cache.invalidate_subscription(&pda).await;
+        }
+    }
+
+    pub async fn set_auto_renew(&self, owner: &str, plan_id: u64, enabled: bool) -> AppResult<AutoRenewResponse> {
+        let pda = self.solana_service.subscription_address(owner, plan_id)?.to_string();
+        if enabled && owner != self.solana_service.signer().to_string() {
+            return Err(AppError::BadRequest(
+                "Auto-renew is only available for subscriptions held by the service wallet".to_string(),
+            ));
+        }
+        if db::find_subscription(&self.pool, &pda).await?.is_none_or(|row| row.closed) {
+            return Err(AppError::NotFound(format!("Subscription {} not found", pda)));
+        }
+        db::set_auto_renew(&self.pool, &pda, enabled).await?;
+        Ok(AutoRenewResponse { subscription: pda, auto_renew: enabled })
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+// Controllers
+/// Turns keeper auto-renewal on or off for a subscription.
+#[utoipa::path(
+    put,
+    path = "/api/v1/subscriptions/{plan_id}/auto-renew",
+    tag = "subscriptions",
+    params(("plan_id" = u64, Path, description = "Plan identifier")),
+    request_body = AutoRenewRequest,
+    responses(
+        (status = 200, description = "Auto-renew updated", body = AutoRenewResponse),
+        (status = 400, description = "Subscription cannot be renewed by the backend", body = ErrorResponse),
+        (status = 404, description = "Subscription not indexed", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[put("/subscriptions/{plan_id}/auto-renew")]
+pub async fn set_auto_renew(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    keeper: web::Data<KeeperService>,
+    body: ValidatedJson<AutoRenewRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let response = keeper
+        .set_auto_renew(&auth_token.public_key, path.into_inner(), body.enabled)
+        .await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Recent keeper runs, newest first. Runs with nothing due are not recorded.
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/keeper/runs",
+    tag = "admin",
+    params(KeeperRunsQuery),
+    responses((status = 200, description = "Keeper run reports", body = [KeeperRunRow])),
+    security(("bearer_auth" = []))
+)]
+#[get("/keeper/runs")]
+pub async fn list_keeper_runs(
+    query: ValidatedQuery<KeeperRunsQuery>,
+    pool: web::Data<PgPool>,
+) -> AppResult<HttpResponse> {
+    let runs = db::list_keeper_runs(&pool, query.limit.unwrap_or(DEFAULT_RUNS_LIMIT)).await?;
+    Ok(HttpResponse::Ok().json(runs))
+}
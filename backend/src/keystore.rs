@@ -0,0 +1,370 @@
+use actix_web::{get, post, web, HttpMessage, HttpRequest, HttpResponse};
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{read_keypair_file, Keypair, Signature, Signer};
+use std::str::FromStr;
+use std::sync::RwLock;
+use utoipa::ToSchema;
+use validator::Validate;
+use zeroize::Zeroizing;
+use crate::signer::TransactionSigner;
+use crate::validation::ValidatedJson;
+use crate::{AppError, AppResult, AuthToken};
+
+const FORMAT_VERSION: u32 = 1;
+// Argon2id cost of a newly sealed key: 64 MiB, three passes
+const MEMORY_KIB: u32 = 64 * 1024;
+const ITERATIONS: u32 = 3;
+const PARALLELISM: u32 = 1;
+const SEALED_RETRY_AFTER_SECS: u64 = 30;
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Kdf {
+    algorithm: String, // Always "argon2id"
+    salt: String,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+/// A keypair file encrypted with AES-256-GCM under an Argon2id key derived from a passphrase.
+/// The public key is kept in the clear, and authenticated with the ciphertext, so the signer
+/// is known before it is unsealed.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SealedKeypair {
+    version: u32,
+    pubkey: String,
+    kdf: Kdf,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct SignerStatus {
+    pubkey: String,
+    sealed: bool, // Transactions the backend signs fail with 503 until it is unsealed
+}
+
+#[derive(Deserialize, Clone, ToSchema, Validate)]
+pub struct UnsealRequest {
+    #[validate(length(min = 1, message = "is required"))]
+    passphrase: String,
+}
+
+/// Whether `contents` is a sealed keypair rather than a `solana-keygen` byte array.
+pub fn is_sealed(contents: &str) -> bool {
+    contents.trim_start().starts_with('{')
+}
+
+fn cipher(passphrase: &str, kdf: &Kdf) -> Result<Aes256Gcm, String> {
+    if kdf.algorithm != "argon2id" {
+        return Err(format!("Unsupported key derivation {}", kdf.algorithm));
+    }
+    let salt = BASE64.decode(&kdf.salt).map_err(|e| format!("Invalid salt: {}", e))?;
+    let params = Params::new(kdf.memory_kib, kdf.iterations, kdf.parallelism, Some(32))
+        .map_err(|e| format!("Invalid key derivation parameters: {}", e))?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), &salt, &mut key[..])
+        .map_err(|e| format!("Key derivation failed: {}", e))?;
+    Aes256Gcm::new_from_slice(&key[..]).map_err(|e| e.to_string())
+}
+
+impl SealedKeypair {
+    pub fn seal(keypair: &Keypair, passphrase: &str) -> Result<Self, String> {
+        let kdf = Kdf {
+            algorithm: "argon2id".to_string(),
+            salt: BASE64.encode(rand::random::<[u8; 16]>()),
+            memory_kib: MEMORY_KIB,
+            iterations: ITERATIONS,
+            parallelism: PARALLELISM,
+        };
+        let pubkey = keypair.pubkey().to_string();
+        let nonce: [u8; 12] = rand::random();
+        let secret = Zeroizing::new(keypair.to_bytes());
+        let ciphertext = cipher(passphrase, &kdf)?
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: &secret[..], aad: pubkey.as_bytes() })
+            .map_err(|_| "Encryption failed".to_string())?;
+        Ok(Self {
+            version: FORMAT_VERSION,
+            pubkey,
+            kdf,
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(ciphertext),
+        })
+    }
+
+    pub fn pubkey(&self) -> Result<Pubkey, String> {
+        Pubkey::from_str(&self.pubkey).map_err(|e| format!("Invalid public key in sealed keypair: {}", e))
+    }
+
+    /// The keypair. `403` if `passphrase` is wrong or the file was tampered with.
+    pub fn open(&self, passphrase: &str) -> AppResult<Keypair> {
+        let corrupt = |e: String| AppError::BadRequest(format!("Sealed keypair is unusable: {}", e));
+        if self.version != FORMAT_VERSION {
+            return Err(corrupt(format!("unsupported version {}", self.version)));
+        }
+        let nonce = BASE64.decode(&self.nonce).map_err(|e| corrupt(format!("invalid nonce: {}", e)))?;
+        let ciphertext = BASE64.decode(&self.ciphertext).map_err(|e| corrupt(format!("invalid ciphertext: {}", e)))?;
+        if nonce.len() != 12 {
+            return Err(corrupt("invalid nonce length".to_string()));
+        }
+        let secret = Zeroizing::new(
+            cipher(passphrase, &self.kdf)
+                .map_err(corrupt)?
+                .decrypt(Nonce::from_slice(&nonce), Payload { msg: &ciphertext, aad: self.pubkey.as_bytes() })
+                .map_err(|_| AppError::Forbidden("Wrong passphrase".to_string()))?,
+        );
+        let keypair = Keypair::from_bytes(&secret).map_err(|_| corrupt("not a keypair".to_string()))?;
+        if keypair.pubkey().to_string() != self.pubkey {
+            return Err(corrupt("does not match its public key".to_string()));
+        }
+        Ok(keypair)
+    }
+}
+
+/// `SIGNER_KEYPAIR_PASSPHRASE`, or the contents of `SIGNER_KEYPAIR_PASSPHRASE_FILE`. Removed
+/// from the environment once read.
+pub fn take_passphrase() -> Result<Option<Zeroizing<String>>, String> {
+    let passphrase = match std::env::var("SIGNER_KEYPAIR_PASSPHRASE").ok().filter(|v| !v.is_empty()) {
+        Some(passphrase) => Some(passphrase),
+        None => match std::env::var("SIGNER_KEYPAIR_PASSPHRASE_FILE").ok().filter(|v| !v.is_empty()) {
+            Some(path) => Some(
+                std::fs::read_to_string(&path)
+                    .map_err(|e| format!("Failed to read {}: {}", path, e))?
+                    .trim_end_matches(['\r', '\n'])
+                    .to_string(),
+            ),
+            None => None,
+        },
+    };
+    std::env::remove_var("SIGNER_KEYPAIR_PASSPHRASE");
+    Ok(passphrase.map(Zeroizing::new))
+}
+
+/// `backend seal-keypair <keypair.json> <sealed.json>`: encrypts a `solana-keygen` keypair
+/// file with the passphrase from `SIGNER_KEYPAIR_PASSPHRASE` (or its file), or else the first
+/// line of stdin.
+pub fn seal_keypair_file(input: &str, output: &str) -> Result<Pubkey, String> {
+    let keypair = read_keypair_file(input).map_err(|e| format!("Failed to read {}: {}", input, e))?;
+    let passphrase = match take_passphrase()? {
+        Some(passphrase) => passphrase,
+        None => {
+            let mut line = Zeroizing::new(String::new());
+            std::io::stdin().read_line(&mut line).map_err(|e| format!("Failed to read passphrase: {}", e))?;
+            Zeroizing::new(line.trim_end_matches(['\r', '\n']).to_string())
+        }
+    };
+    if passphrase.is_empty() {
+        return Err("The passphrase must not be empty".to_string());
+    }
+    let sealed = SealedKeypair::seal(&keypair, &passphrase)?;
+    let contents = serde_json::to_string_pretty(&sealed).map_err(|e| e.to_string())?;
+    std::fs::write(output, contents).map_err(|e| format!("Failed to write {}: {}", output, e))?;
+    Ok(keypair.pubkey())
+}
+
+// Sealed Signer
+/// A local keypair kept encrypted at rest. It signs once unsealed, at startup with the
+/// configured passphrase or later through `POST /api/v1/admin/signer/unseal`; the keypair is
+/// zeroed when dropped.
+pub struct SealedSigner {
+    sealed: SealedKeypair,
+    pubkey: Pubkey,
+    keypair: RwLock<Option<Keypair>>,
+}
+
+impl SealedSigner {
+    pub fn load(path: &str, contents: &str, passphrase: Option<&str>) -> Result<Self, String> {
+        let sealed: SealedKeypair =
+            serde_json::from_str(contents).map_err(|e| format!("Invalid sealed keypair {}: {}", path, e))?;
+        let signer = Self { pubkey: sealed.pubkey()?, sealed, keypair: RwLock::new(None) };
+        match passphrase {
+            Some(passphrase) => signer.unseal(passphrase).map_err(|e| format!("Failed to unseal {}: {}", path, e))?,
+            None => tracing::warn!("Transaction signer {} is sealed; unseal it to send transactions", signer.pubkey),
+        }
+        Ok(signer)
+    }
+}
+
+impl TransactionSigner for SealedSigner {
+    fn pubkey(&self) -> Pubkey {
+        self.pubkey
+    }
+
+    fn sign_message<'a>(&'a self, message: &'a [u8]) -> BoxFuture<'a, AppResult<Signature>> {
+        Box::pin(async move {
+            match self.keypair.read().unwrap().as_ref() {
+                Some(keypair) => Ok(keypair.sign_message(message)),
+                None => Err(AppError::ServiceUnavailable(
+                    "The transaction signer is sealed".to_string(),
+                    SEALED_RETRY_AFTER_SECS,
+                )),
+            }
+        })
+    }
+
+    fn is_sealed(&self) -> bool {
+        self.keypair.read().unwrap().is_none()
+    }
+
+    fn unseal(&self, passphrase: &str) -> AppResult<()> {
+        let keypair = self.sealed.open(passphrase)?;
+        *self.keypair.write().unwrap() = Some(keypair);
+        Ok(())
+    }
+}
+
+// Controllers
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/signer",
+    tag = "admin",
+    responses((status = 200, description = "Signer public key and whether it is sealed", body = SignerStatus)),
+    security(("bearer_auth" = []))
+)]
+#[get("/signer")]
+pub async fn signer_status(signer: web::Data<dyn TransactionSigner>) -> HttpResponse {
+    HttpResponse::Ok().json(SignerStatus { pubkey: signer.pubkey().to_string(), sealed: signer.is_sealed() })
+}
+
+/// Decrypts a sealed local keypair so the backend can sign again. Unsealing an unsealed
+/// signer with the right passphrase is a no-op.
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/signer/unseal",
+    tag = "admin",
+    request_body = UnsealRequest,
+    responses(
+        (status = 200, description = "Signer unsealed", body = SignerStatus),
+        (status = 400, description = "The signer is not a sealed keypair", body = ErrorResponse),
+        (status = 403, description = "Wrong passphrase", body = ErrorResponse),
+        (status = 422, description = "Invalid request", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/signer/unseal")]
+pub async fn unseal_signer(
+    req: HttpRequest,
+    signer: web::Data<dyn TransactionSigner>,
+    body: ValidatedJson<UnsealRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let passphrase = Zeroizing::new(body.into_inner().passphrase);
+    let signer = signer.into_inner();
+    // Argon2 is deliberately slow, so it stays off the async workers
+    let result = web::block({
+        let signer = signer.clone();
+        move || signer.unseal(&passphrase)
+    })
+    .await
+    .map_err(|e| AppError::InternalServerError(format!("Unseal task failed: {}", e)))?;
+    if let Err(e) = result {
+        tracing::warn!("Failed unseal of transaction signer by {}: {}", auth_token.public_key, e);
+        return Err(e);
+    }
+    tracing::info!("Transaction signer {} unsealed by {}", signer.pubkey(), auth_token.public_key);
+    Ok(HttpResponse::Ok().json(SignerStatus { pubkey: signer.pubkey().to_string(), sealed: signer.is_sealed() }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use once_cell::sync::Lazy;
+
+    const PASSPHRASE: &str = "correct horse battery staple";
+
+    // Argon2 is slow in debug builds, so the tests share one sealed keypair
+    static SEALED: Lazy<(Keypair, SealedKeypair)> = Lazy::new(|| {
+        let keypair = Keypair::new();
+        let sealed = SealedKeypair::seal(&keypair, PASSPHRASE).unwrap();
+        (keypair, sealed)
+    });
+
+    fn assert_forbidden(result: AppResult<Keypair>) {
+        assert!(matches!(result, Err(AppError::Forbidden(_))), "expected 403");
+    }
+
+    #[test]
+    fn opens_what_it_sealed() {
+        let (keypair, sealed) = &*SEALED;
+        assert_eq!(sealed.pubkey().unwrap(), keypair.pubkey());
+        assert_eq!(sealed.open(PASSPHRASE).unwrap().to_bytes(), keypair.to_bytes());
+    }
+
+    #[test]
+    fn sealed_file_round_trips_through_json() {
+        let (keypair, sealed) = &*SEALED;
+        let contents = serde_json::to_string_pretty(sealed).unwrap();
+        assert!(is_sealed(&contents));
+        assert!(!contents.contains(&BASE64.encode(keypair.to_bytes())));
+        let parsed: SealedKeypair = serde_json::from_str(&contents).unwrap();
+        assert_eq!(parsed.open(PASSPHRASE).unwrap().pubkey(), keypair.pubkey());
+    }
+
+    #[test]
+    fn rejects_wrong_passphrase() {
+        assert_forbidden(SEALED.1.open("wrong"));
+    }
+
+    #[test]
+    fn rejects_tampered_ciphertext() {
+        let mut sealed = SEALED.1.clone();
+        let mut ciphertext = BASE64.decode(&sealed.ciphertext).unwrap();
+        ciphertext[0] ^= 1;
+        sealed.ciphertext = BASE64.encode(ciphertext);
+        assert_forbidden(sealed.open(PASSPHRASE));
+    }
+
+    #[test]
+    fn rejects_swapped_public_key() {
+        // The public key is the associated data, so claiming another signer fails to decrypt
+        let mut sealed = SEALED.1.clone();
+        sealed.pubkey = Keypair::new().pubkey().to_string();
+        assert_forbidden(sealed.open(PASSPHRASE));
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let mut sealed = SEALED.1.clone();
+        sealed.version = FORMAT_VERSION + 1;
+        assert!(matches!(sealed.open(PASSPHRASE), Err(AppError::BadRequest(_))));
+
+        let mut sealed = SEALED.1.clone();
+        sealed.nonce = BASE64.encode([0u8; 8]);
+        assert!(matches!(sealed.open(PASSPHRASE), Err(AppError::BadRequest(_))));
+
+        let mut sealed = SEALED.1.clone();
+        sealed.kdf.algorithm = "scrypt".to_string();
+        assert!(matches!(sealed.open(PASSPHRASE), Err(AppError::BadRequest(_))));
+    }
+
+    #[test]
+    fn keygen_files_are_not_sealed() {
+        let contents = serde_json::to_string(&Keypair::new().to_bytes().to_vec()).unwrap();
+        assert!(!is_sealed(&contents));
+        assert!(is_sealed("  {\"version\": 1}"));
+    }
+
+    #[actix_web::test]
+    async fn signer_refuses_to_sign_until_unsealed() {
+        let (keypair, sealed) = &*SEALED;
+        let contents = serde_json::to_string(sealed).unwrap();
+        let signer = SealedSigner::load("sealed.json", &contents, None).unwrap();
+        assert_eq!(TransactionSigner::pubkey(&signer), keypair.pubkey());
+        assert!(signer.is_sealed());
+        assert!(matches!(signer.sign_message(b"hello").await, Err(AppError::ServiceUnavailable(_, _))));
+
+        assert!(matches!(signer.unseal("wrong"), Err(AppError::Forbidden(_))));
+        assert!(signer.is_sealed());
+        signer.unseal(PASSPHRASE).unwrap();
+        assert!(!signer.is_sealed());
+        assert_eq!(signer.sign_message(b"hello").await.unwrap(), keypair.sign_message(b"hello"));
+    }
+}
@@ -0,0 +1,132 @@
+use anchor_lang::solana_program::hash::hash;
+use borsh::BorshDeserialize;
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use crate::{AppError, AppResult, Config, Subscription, SubscriptionRequest};
+
+/// Terms every subscription of the fixed-parameter program is created with.
+pub const FIXED_DURATION_SECS: u64 = 60;
+pub const FIXED_AMOUNT_LAMPORTS: u64 = 10_000_000;
+
+/// Which deployment of the subscription program a program ID runs. Both keep the same
+/// `Subscription` account; they differ in what `create_subscription` takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProgramLayout {
+    /// The program in this repo: `create_subscription(plan_id)`, always
+    /// `FIXED_DURATION_SECS` for `FIXED_AMOUNT_LAMPORTS`.
+    Fixed,
+    /// `create_subscription(plan_id, duration, amount)` with caller-chosen terms.
+    Flexible,
+}
+
+impl ProgramLayout {
+    /// Arguments of `create_subscription`, after its discriminator.
+    pub fn create_args(&self, req: &SubscriptionRequest) -> Vec<u8> {
+        let mut data = req.plan_id.to_le_bytes().to_vec();
+        if *self == ProgramLayout::Flexible {
+            data.extend_from_slice(&req.duration.to_le_bytes());
+            data.extend_from_slice(&req.amount.to_le_bytes());
+        }
+        data
+    }
+
+    /// The fixed program ignores requested terms, so anything but its own is refused rather
+    /// than silently replaced.
+    pub fn check_terms(&self, req: &SubscriptionRequest) -> AppResult<()> {
+        if *self == ProgramLayout::Fixed
+            && (req.duration != FIXED_DURATION_SECS || req.amount != FIXED_AMOUNT_LAMPORTS)
+        {
+            return Err(AppError::BadRequest(format!(
+                "This program only creates subscriptions of {} seconds for {} lamports",
+                FIXED_DURATION_SECS, FIXED_AMOUNT_LAMPORTS
+            )));
+        }
+        Ok(())
+    }
+
+    /// Lamports a create with `amount` would actually charge.
+    pub fn charged_amount(&self, amount: u64) -> u64 {
+        match self {
+            ProgramLayout::Fixed => FIXED_AMOUNT_LAMPORTS,
+            ProgramLayout::Flexible => amount,
+        }
+    }
+
+    fn decode(&self, data: &[u8]) -> AppResult<Subscription> {
+        // Anchor accounts are allocated at full size, so trailing bytes are unused history room
+        let mut data_slice = data;
+        let subscription = Subscription::deserialize(&mut data_slice)
+            .map_err(|e| AppError::SolanaError(format!("Deserialization error: {}", e)))?;
+        if *self == ProgramLayout::Fixed
+            && (subscription.duration != FIXED_DURATION_SECS || subscription.amount != FIXED_AMOUNT_LAMPORTS)
+        {
+            tracing::warn!(
+                "Fixed-parameter subscription {} stores terms of {}s for {} lamports",
+                subscription.plan_id,
+                subscription.duration,
+                subscription.amount
+            );
+        }
+        Ok(subscription)
+    }
+}
+
+impl FromStr for ProgramLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "fixed" => Ok(ProgramLayout::Fixed),
+            "flexible" => Ok(ProgramLayout::Flexible),
+            other => Err(format!("Unknown program layout {}", other)),
+        }
+    }
+}
+
+/// Decodes subscription accounts of every configured program, picking the layout from the
+/// program that owns the account.
+#[derive(Clone)]
+pub struct AccountDecoder {
+    layouts: Arc<HashMap<Pubkey, ProgramLayout>>,
+}
+
+impl AccountDecoder {
+    pub fn new(config: &Config) -> Self {
+        let mut layouts = HashMap::new();
+        for cluster in &config.clusters {
+            layouts.insert(cluster.program_id, cluster.layout);
+            for tenant in &config.tenants {
+                if let Some(program_id) = tenant.program_id(cluster.cluster) {
+                    layouts.insert(program_id, tenant.program_layout(cluster.cluster).unwrap_or(cluster.layout));
+                }
+            }
+        }
+        Self { layouts: Arc::new(layouts) }
+    }
+
+    pub fn layout_of(&self, program_id: &Pubkey) -> Option<ProgramLayout> {
+        self.layouts.get(program_id).copied()
+    }
+
+    pub fn decode(&self, account: &Account) -> AppResult<Subscription> {
+        let layout = self.layout_of(&account.owner).ok_or_else(|| {
+            AppError::SolanaError(format!("Account is owned by {}, not a configured program", account.owner))
+        })?;
+        if account.data.len() < 8 {
+            return Err(AppError::SolanaError(format!("Account data is too short ({} bytes)", account.data.len())));
+        }
+        let (discriminator, data) = account.data.split_at(8);
+        if discriminator != subscription_discriminator() {
+            return Err(AppError::SolanaError("Account is not a Subscription".to_string()));
+        }
+        layout.decode(data)
+    }
+}
+
+pub fn subscription_discriminator() -> [u8; 8] {
+    let mut discriminator = [0u8; 8];
+    discriminator.copy_from_slice(&hash(b"account:Subscription").to_bytes()[..8]);
+    discriminator
+}
@@ -0,0 +1,69 @@
+use crate::tenant::TenantConfig;
+use crate::{AppError, AppResult, Config, SubscriptionRequest};
+
+const LAMPORTS_PER_SOL: f64 = 1_000_000_000.0;
+
+/// Operator bounds on the subscriptions the backend will create, checked before a
+/// transaction is built. The schema rules on `SubscriptionRequest` only keep values
+/// representable; these catch plausible-looking typos such as an amount with extra zeros.
+///
+/// The program keeps no Plan or Config account, so there are no on-chain limits to
+/// compare against; these bounds are the only guard.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubscriptionLimits {
+    min_duration: u64,
+    max_duration: u64,
+    min_amount: u64,
+    max_amount: u64,
+}
+
+impl SubscriptionLimits {
+    /// The global limits, with any of the tenant's overrides applied.
+    pub fn new(config: &Config, tenant: &TenantConfig) -> Self {
+        Self::try_new(config, tenant).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_new(config: &Config, tenant: &TenantConfig) -> Result<Self, String> {
+        let limits = Self {
+            min_duration: tenant.min_duration_secs.unwrap_or(config.min_duration_secs),
+            max_duration: tenant.max_duration_secs.unwrap_or(config.max_duration_secs),
+            min_amount: tenant.min_amount_lamports.unwrap_or(config.min_amount_lamports),
+            max_amount: tenant.max_amount_lamports.unwrap_or(config.max_amount_lamports),
+        };
+        if limits.min_duration == 0 || limits.min_duration > limits.max_duration {
+            return Err("MIN_DURATION_SECS must be positive and no greater than MAX_DURATION_SECS".to_string());
+        }
+        if limits.min_amount == 0 || limits.min_amount > limits.max_amount {
+            return Err("MIN_AMOUNT_LAMPORTS must be positive and no greater than MAX_AMOUNT_LAMPORTS".to_string());
+        }
+        Ok(limits)
+    }
+
+    pub fn check(&self, req: &SubscriptionRequest) -> AppResult<()> {
+        if req.duration == 0 || req.amount == 0 {
+            return Err(AppError::BadRequest("duration and amount must be greater than zero".to_string()));
+        }
+        if req.duration < self.min_duration || req.duration > self.max_duration {
+            return Err(AppError::BadRequest(format!(
+                "duration of {} seconds is outside the allowed range of {} to {} seconds",
+                req.duration, self.min_duration, self.max_duration
+            )));
+        }
+        if req.amount < self.min_amount || req.amount > self.max_amount {
+            return Err(AppError::BadRequest(format!(
+                "amount of {} lamports ({} SOL) is outside the allowed range of {} to {} lamports ({} to {} SOL)",
+                req.amount,
+                sol(req.amount),
+                self.min_amount,
+                self.max_amount,
+                sol(self.min_amount),
+                sol(self.max_amount)
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn sol(lamports: u64) -> f64 {
+    lamports as f64 / LAMPORTS_PER_SOL
+}
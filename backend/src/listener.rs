@@ -0,0 +1,65 @@
+use futures::StreamExt;
+use solana_client::nonblocking::pubsub_client::PubsubClient;
+use solana_client::rpc_config::{RpcTransactionLogsConfig, RpcTransactionLogsFilter};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+use std::time::Duration;
+use crate::indexer::IndexerService;
+use crate::{AppError, AppResult};
+
+const MAX_RECONNECT_BACKOFF_SECS: u64 = 60;
+
+/// Streams program logs over the RPC WebSocket and feeds each signature to the indexer.
+/// Every (re)connect first backfills from the indexer cursor, so anything that landed
+/// while the socket was down is picked up before live notifications resume.
+pub async fn run(indexer: IndexerService, ws_url: String, program_id: Pubkey) {
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        match subscribe(&indexer, &ws_url, &program_id).await {
+            Ok(()) => {
+                tracing::warn!("Program log stream closed, reconnecting");
+                backoff = Duration::from_secs(1);
+            }
+            Err(e) => tracing::error!("Program log stream failed: {}", e),
+        }
+
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(Duration::from_secs(MAX_RECONNECT_BACKOFF_SECS));
+    }
+}
+
+async fn subscribe(indexer: &IndexerService, ws_url: &str, program_id: &Pubkey) -> AppResult<()> {
+    let client = PubsubClient::new(ws_url)
+        .await
+        .map_err(|e| AppError::SolanaError(format!("Failed to connect to {}: {}", ws_url, e)))?;
+
+    let (mut stream, unsubscribe) = client
+        .logs_subscribe(
+            RpcTransactionLogsFilter::Mentions(vec![program_id.to_string()]),
+            RpcTransactionLogsConfig {
+                commitment: Some(CommitmentConfig::confirmed()),
+            },
+        )
+        .await
+        .map_err(|e| AppError::SolanaError(format!("Failed to subscribe to program logs: {}", e)))?;
+    tracing::info!("Subscribed to program logs for {}", program_id);
+
+    // Close the gap between the last indexed signature and the start of the subscription
+    let backfilled = indexer.backfill(false).await?;
+    if backfilled > 0 {
+        tracing::info!("Backfilled {} transactions after connecting", backfilled);
+    }
+
+    while let Some(response) = stream.next().await {
+        let logs = response.value;
+        if logs.err.is_some() {
+            continue;
+        }
+        tracing::debug!("Program notification {} at slot {}", logs.signature, response.context.slot);
+        if let Err(e) = indexer.index_signature(&logs.signature).await {
+            tracing::error!("Failed to index {}: {}", logs.signature, e);
+        }
+    }
+
+    unsubscribe().await;
+    Ok(())
+}
@@ -1,15 +1,96 @@
+mod airdrop;
+mod analytics;
+mod accounts;
+mod actions;
+mod api_keys;
+mod audit;
+mod auth_monitor;
+mod batch;
+mod cache;
+mod chain;
+mod channels;
+mod circuit;
+mod calendar;
+mod cluster;
+mod commitment;
+mod concurrency;
+mod conditional;
+mod coupons;
+mod custody;
+mod customers;
+mod db;
+mod deployment;
+mod email;
+mod estimate;
+mod event_sinks;
+mod events;
+mod exports;
+mod graphql;
+mod grpc;
+mod health;
+mod idempotency;
+mod indexer;
+mod intents;
+mod jobs;
+mod jwks;
+mod keeper;
+mod keystore;
+mod layout;
+mod limits;
+mod listener;
+mod merchant;
+mod metrics;
 mod middlewares;
+mod mock_chain;
+mod nonces;
+mod notifications;
+mod openapi;
+mod payments;
+mod pii;
+mod plans;
+mod price;
+mod quota;
+mod rate_limit;
+mod receipts;
+mod refunds;
+mod reconciliation;
+mod reload;
+mod reminders;
+mod reporting;
+mod schema;
+mod simulation;
+mod signer;
+mod siws;
+mod solana_pay;
+mod statements;
+mod status;
+mod submissions;
+mod telemetry;
+mod tenant;
+mod timeouts;
+mod tls;
+mod treasury;
+mod treasury_withdrawals;
+mod user_channels;
+mod validation;
+mod versioning;
+mod wallet_locks;
+mod wallets;
+mod webhook_filters;
+mod webhook_versions;
+mod webhooks;
 
 use actix_cors::Cors;
+use actix_web_prom::PrometheusMetricsBuilder;
 use actix_web::{
-    middleware::Logger,
+    http::KeepAlive,
+    middleware::Compress,
     web::{self, Data},
-    App, HttpResponse, HttpServer, HttpMessage, get, post,
+    App, HttpResponse, HttpServer, HttpMessage, ResponseError, get, post,
 };
-use dotenv::dotenv;
-use log::info;
+use tracing::info;
+use tracing_actix_web::TracingLogger;
 use serde::{Deserialize, Serialize};
-use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::{
     pubkey::Pubkey,
     signature::Signature,
@@ -17,85 +98,704 @@ use solana_sdk::{
     instruction::Instruction,
     system_program,
     message::Message,
-    signer::keypair::Keypair,
 };
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_client::rpc_config::RpcSimulateTransactionConfig;
 use solana_client::rpc_request::{RpcError, RpcResponseErrorData};
+use solana_client::rpc_response::RpcSimulateTransactionResult;
 use anchor_lang::solana_program::hash::hash; // For Anchor discriminator
 use borsh::{BorshDeserialize, BorshSerialize}; // Use borsh crate directly
-use jsonwebtoken::{encode, Header, EncodingKey, Validation};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::str::FromStr;
-use middlewares::Authentication;
-use std::sync::Arc;
+use airdrop::AirdropService;
+use analytics::AnalyticsService;
+use accounts::{AccountCache, Commitment};
+use commitment::CommitmentPolicy;
+use api_keys::ApiKeyService;
+use auth_monitor::AuthMonitor;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use cache::{CacheService, ResponseCache};
+use calendar::CalendarService;
+use chain::{ChainClient, ChainClients, PendingTx};
+use channels::ChannelService;
+use db::TransactionJobRow;
+use deployment::DeploymentCheck;
+use cluster::{Cluster, ClusterConfig, RpcPool, SolanaClusters};
+use conditional::Validators;
+use coupons::CouponService;
+use custody::{CustodyConfig, CustodyService};
+use customers::CustomerService;
+use idempotency::IdempotencyService;
+use grpc::GrpcApi;
+use indexer::IndexerService;
+use jobs::JobWorker;
+use tls::ReloadingCertResolver;
+use jwks::{JwtKeys, JwtRotation};
+use concurrency::{HeavyRouteConfig, HeavyRouteLimiter};
+use event_sinks::EventSinkConfig;
+use events::EventLogService;
+use exports::ExportService;
+use keeper::KeeperService;
+use layout::{AccountDecoder, ProgramLayout};
+use limits::SubscriptionLimits;
+use notifications::NotificationService;
+use plans::PlanService;
+use price::PriceFeed;
+use simulation::{SimulatedError, TransactionFailure};
+use quota::{QuotaConfig, QuotaService};
+use middlewares::{
+    ApiKeyAuthentication, AuditLog, Authentication, ConcurrencyLimit, Quota, RateLimit, RequireRole, SecurityHeaders, Timeout,
+};
+use rate_limit::RateLimiter;
+use refunds::RefundService;
+use treasury_withdrawals::TreasuryWithdrawalService;
+use receipts::ReceiptService;
+use reconciliation::ReconciliationService;
+use reload::ConfigReloader;
+use reminders::ReminderService;
+use signer::{SignerConfig, TransactionSigner};
+use siws::{SiwsInput, SiwsMessage};
+use solana_pay::SolanaPay;
+use statements::StatementService;
+use submissions::{SubmissionService, Submitted, WalletAction};
+use tenant::{TenantConfig, DEFAULT_TENANT};
+use timeouts::RequestTimeouts;
+use user_channels::UserChannelService;
+use utoipa::{IntoParams, ToSchema};
+use validation::{validate_pubkey, FieldError, ValidatedJson, ValidatedQuery};
+use validator::Validate;
+use wallet_locks::{WalletGuard, WalletLocks};
+use wallets::WalletService;
+use webhooks::{SubscriptionEventData, WebhookEventType, WebhookService};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::{Arc, RwLock};
+use sha2::{Digest, Sha256};
+use sqlx::postgres::PgPool;
 
 // Configuration
 #[derive(Clone)]
 pub struct Config {
     server_host: String,
     server_port: u16,
-    solana_rpc_url: String,
-    program_id: Pubkey,
-    jwt_secret: String,
+    tls_cert_path: Option<String>, // Serve HTTPS directly when set together with the key
+    tls_key_path: Option<String>,
+    tls_reload_interval_secs: u64,
+    grpc_port: Option<u16>, // gRPC API, disabled unless set
+    cluster: Cluster, // Primary cluster, backing the indexer, cache and webhooks
+    clusters: Vec<ClusterConfig>,
+    mock_chain: bool, // Clusters are in-memory `mock_chain`s instead of real RPC endpoints
+    mock_chain_balance_lamports: u64, // Starting balance of every mock chain wallet
+    allow_cluster_override: bool,
+    deployment_check: DeploymentCheck, // On a program that does not match this build
+    rpc_health_check_interval_secs: u64,
+    shutdown_timeout_secs: u64,
+    idempotency_ttl_secs: u64,
+    job_max_attempts: i32, // Sends per transaction job, the first included
+    job_retry_backoff_secs: u64,
+    nonce_pool_size: usize, // Durable nonce accounts per cluster, 0 to use recent blockhashes only
+    nonce_max_per_user: i64, // Nonce accounts one owner's queued transactions may hold at once
+    rpc_breaker_threshold: u32, // Consecutive RPC outages that open the circuit
+    rpc_breaker_cooldown_secs: u64,
+    rpc_max_concurrency: usize,
+    wallet_lock_timeout_secs: u64, // How long a wallet's transaction waits behind another of the same wallet
+    jwt_signing_keys: Vec<(String, [u8; 32])>, // (kid, Ed25519 seed)
+    jwt_active_kid: Option<String>, // Defaults to the first signing key
+    jwt_issuer: String,
+    jwt_audience: String,
+    receipt_signing_key: Option<[u8; 32]>, // Ed25519 seed; receipts are not issued without it
     treasury: Pubkey,
-    phantom_private_key: String,
+    tenants: Vec<TenantConfig>, // The default tenant first
+    signer: SignerConfig, // Fee payer key, see `signer`
+    custody: Option<CustodyConfig>, // Set when custodial wallets are enabled
+    commitments: CommitmentPolicy,
+    refund_private_key: Option<String>, // Treasury keypair; refunds cannot be approved without it
+    treasury_withdrawal_approvals: u32, // Admins besides the requester who approve a treasury withdrawal
+    treasury_withdrawal_timelock_secs: u64, // Least time between requesting a treasury withdrawal and sending it
+    min_duration_secs: u64,
+    max_duration_secs: u64,
+    min_amount_lamports: u64,
+    max_amount_lamports: u64,
+    webhook_max_attempts: u32,
+    webhook_backoff_base_secs: u64,
+    webhook_dead_letter_alert_threshold: i64, // 0 disables the dead-letter alert
+    webhook_delivery_retention_secs: u64, // How long finished deliveries stay listed and replayable
+    event_sinks: Vec<EventSinkConfig>, // Message brokers events are published to, see `event_sinks`
+    event_sink_events: Vec<WebhookEventType>,
+    event_log_retention_secs: u64, // How long events stay available to long-polling
+    event_poll_max_wait_secs: u64, // Longest a `GET /events` call waits; keep below REQUEST_TIMEOUT_SECS
+    database_url: String,
+    database_max_connections: u32,
+    database_auto_migrate: bool, // Otherwise startup only checks the schema, see `backend migrate`
+    indexer_poll_interval_secs: u64,
+    keeper_enabled: bool,
+    keeper_interval_secs: u64,
+    keeper_dry_run: bool, // Log and record what the keeper would do without sending or marking anything
+    keeper_concurrency: usize,
+    keeper_batch_size: i64,
+    reminder_interval_secs: u64,
+    reconciliation_enabled: bool,
+    reconciliation_threshold_lamports: u64, // Largest daily difference that is not alerted on
+    reconciliation_alert_email: Option<String>,
+    reconciliation_webhook_url: Option<String>,
+    reconciliation_webhook_secret: Option<String>,
+    auth_anomaly_threshold: u32, // Failed sign-ins per wallet or IP within the window that raise an alert, 0 disables
+    auth_anomaly_window_secs: u64,
+    auth_alert_webhook_url: Option<String>,
+    auth_alert_webhook_secret: Option<String>,
+    push_api_url: String, // Expo push service, for subscribers' push notification channels
+    push_access_token: Option<String>,
+    smtp_url: Option<String>,
+    email_from: String,
+    export_retention_secs: u64,
+    statement_emails_enabled: bool,
+    platform_fee_bps: u32, // Deducted from gross revenue on merchant statements
+    price_feed_url: Option<String>,
+    price_cache_secs: u64,
+    token_price_feed_url: Option<String>,
+    devnet_airdrop_enabled: bool, // Never honoured when the primary cluster is mainnet
+    airdrop_lamports: u64,
+    airdrop_daily_cap_lamports: u64,
+    cors_allowed_origins: Vec<String>, // Empty means no cross-origin access
+    hsts_max_age_secs: u64, // 0 disables HSTS
+    content_security_policy: String,
+    error_reporting_enabled: bool,
+    sentry_dsn: Option<String>, // Takes precedence over the generic endpoint
+    error_report_url: Option<String>,
+    error_report_environment: String,
+    redis_url: Option<String>,
+    cache_ttl_secs: u64,
+    account_cache_ttl_ms: u64, // Processed and confirmed account reads, 0 to disable the account cache
+    account_cache_finalized_ttl_ms: u64,
+    account_cache_capacity: usize,
+    response_cache_fresh_secs: u64, // Plan and analytics responses, 0 to disable the response cache
+    response_cache_stale_secs: u64, // Served past `fresh` while refreshing in the background
+    response_cache_capacity: usize,
+    rate_limit_ip_per_minute: u32,
+    rate_limit_pubkey_per_minute: u32,
+    rate_limit_trust_forwarded: bool,
+    status_rate_limit_per_minute: u32, // Per client IP, for the unauthenticated status check
+    solana_pay_label: String,
+    solana_pay_icon_url: Option<String>, // Solana Pay endpoints are off until set
+    solana_pay_rate_limit_per_minute: u32, // Per client IP
+    request_timeouts: RequestTimeouts,
+    heavy_routes: HeavyRouteConfig, // Concurrency pool of listing and scanning routes
+    json_body_limit_bytes: usize,
+    keep_alive_secs: u64, // 0 closes connections after each response
+    client_request_timeout_ms: u64, // To receive a request's headers
+    client_disconnect_timeout_ms: u64,
+    max_connections: usize, // Per worker
+    quotas: QuotaConfig,
+    access_token_ttl_secs: u64,
+    refresh_token_ttl_secs: u64,
+    auth_challenge_ttl_secs: u64,
+    auth_replay_ttl_secs: u64, // How long used sign-in signatures are remembered
+    siws_domain: String,
+    siws_statement: String,
+    admin_pubkeys: Vec<Pubkey>,
+    merchant_pubkeys: Vec<Pubkey>,
+}
+
+impl Config {
+    fn primary_cluster(&self) -> &ClusterConfig {
+        self.clusters
+            .iter()
+            .find(|c| c.cluster == self.cluster)
+            .expect("Primary cluster is always configured")
+    }
 }
 
 pub fn get_config() -> Config {
-    dotenv().ok();
+    reload::load_env();
+    let cluster: Cluster = std::env::var("SOLANA_CLUSTER")
+        .unwrap_or_else(|_| "devnet".to_string())
+        .parse()
+        .expect("Invalid SOLANA_CLUSTER");
+    let treasury = Pubkey::from_str(
+        &std::env::var("TREASURY_PUBKEY").unwrap_or_else(|_| "4wa7saJG78PMAzfCaXEBMR4jtPV5SGhYwewkqHMLTEqo".to_string()),
+    )
+    .expect("Invalid treasury pubkey");
+    let mock_chain = std::env::var("MOCK_CHAIN").map(|v| v == "true" || v == "1").unwrap_or(false);
     Config {
         server_host: std::env::var("SERVER_HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
         server_port: std::env::var("SERVER_PORT")
             .unwrap_or_else(|_| "8080".to_string())
             .parse()
             .unwrap_or(8080),
-        solana_rpc_url: std::env::var("SOLANA_RPC_URL")
-            .unwrap_or_else(|_| "https://api.devnet.solana.com".to_string()),
-        program_id: Pubkey::from_str("GVkmkRg63U7QRES1fksSBSQhMFgydMa3oATDby7QyJEp")
-            .expect("Invalid program ID"),
-        jwt_secret: std::env::var("JWT_SECRET").expect("JWT_SECRET must be set"),
-        treasury: Pubkey::from_str(
-            &std::env::var("TREASURY_PUBKEY").unwrap_or_else(|_| "4wa7saJG78PMAzfCaXEBMR4jtPV5SGhYwewkqHMLTEqo".to_string()),
-        )
-        .expect("Invalid treasury pubkey"),
-        phantom_private_key: std::env::var("PHANTOM_PRIVATE_KEY").expect("PHANTOM_PRIVATE_KEY must be set"),
+        tls_cert_path: std::env::var("TLS_CERT_PATH").ok().filter(|v| !v.is_empty()),
+        tls_key_path: std::env::var("TLS_KEY_PATH").ok().filter(|v| !v.is_empty()),
+        tls_reload_interval_secs: std::env::var("TLS_RELOAD_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60),
+        grpc_port: std::env::var("GRPC_PORT").ok().and_then(|v| v.parse().ok()),
+        cluster,
+        clusters: if mock_chain {
+            mock_chain::clusters(cluster)
+        } else {
+            cluster::load_clusters(cluster)
+        },
+        mock_chain,
+        mock_chain_balance_lamports: std::env::var("MOCK_CHAIN_BALANCE_LAMPORTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10_000_000_000),
+        allow_cluster_override: std::env::var("ALLOW_CLUSTER_OVERRIDE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false),
+        deployment_check: std::env::var("DEPLOYMENT_CHECK")
+            .map(|v| v.parse().expect("Invalid DEPLOYMENT_CHECK"))
+            .unwrap_or(DeploymentCheck::Enforce),
+        rpc_health_check_interval_secs: std::env::var("RPC_HEALTH_CHECK_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(15),
+        shutdown_timeout_secs: std::env::var("SHUTDOWN_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60),
+        idempotency_ttl_secs: std::env::var("IDEMPOTENCY_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(86400),
+        job_max_attempts: std::env::var("JOB_MAX_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3),
+        job_retry_backoff_secs: std::env::var("JOB_RETRY_BACKOFF_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10),
+        nonce_pool_size: std::env::var("NONCE_POOL_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0),
+        nonce_max_per_user: std::env::var("NONCE_MAX_PER_USER")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2),
+        rpc_breaker_threshold: std::env::var("RPC_BREAKER_THRESHOLD")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5),
+        rpc_breaker_cooldown_secs: std::env::var("RPC_BREAKER_COOLDOWN_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30),
+        rpc_max_concurrency: std::env::var("RPC_MAX_CONCURRENCY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(32),
+        wallet_lock_timeout_secs: std::env::var("WALLET_LOCK_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30),
+        jwt_signing_keys: jwks::load_signing_keys(),
+        jwt_active_kid: std::env::var("JWT_ACTIVE_KID").ok().filter(|v| !v.is_empty()),
+        jwt_issuer: std::env::var("JWT_ISSUER").unwrap_or_else(|_| "subscription-manager".to_string()),
+        jwt_audience: std::env::var("JWT_AUDIENCE").unwrap_or_else(|_| "subscription-manager-api".to_string()),
+        receipt_signing_key: receipts::load_receipt_key(),
+        treasury,
+        tenants: tenant::load_tenants(treasury, cluster),
+        signer: signer::load_signer_config(),
+        custody: custody::load_custody_config(),
+        commitments: commitment::load_commitment_policy(),
+        refund_private_key: std::env::var("REFUND_PRIVATE_KEY").ok().filter(|v| !v.trim().is_empty()),
+        treasury_withdrawal_approvals: std::env::var("TREASURY_WITHDRAWAL_APPROVALS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2),
+        treasury_withdrawal_timelock_secs: std::env::var("TREASURY_WITHDRAWAL_TIMELOCK_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(86400),
+        min_duration_secs: std::env::var("MIN_DURATION_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60),
+        max_duration_secs: std::env::var("MAX_DURATION_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(31536000),
+        min_amount_lamports: std::env::var("MIN_AMOUNT_LAMPORTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1000),
+        max_amount_lamports: std::env::var("MAX_AMOUNT_LAMPORTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(100_000_000_000),
+        webhook_max_attempts: std::env::var("WEBHOOK_MAX_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(6),
+        webhook_backoff_base_secs: std::env::var("WEBHOOK_BACKOFF_BASE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2),
+        webhook_dead_letter_alert_threshold: std::env::var("WEBHOOK_DEAD_LETTER_ALERT_THRESHOLD")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10),
+        webhook_delivery_retention_secs: std::env::var("WEBHOOK_DELIVERY_RETENTION_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30 * 86400),
+        event_sinks: event_sinks::load_event_sinks(),
+        event_sink_events: event_sinks::load_event_types(),
+        event_log_retention_secs: std::env::var("EVENT_LOG_RETENTION_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(7 * 86400),
+        event_poll_max_wait_secs: std::env::var("EVENT_POLL_MAX_WAIT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(25),
+        // The mock chain is for local development and CI, which use the local database by default
+        database_url: std::env::var("DATABASE_URL")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .or_else(|| mock_chain.then(|| mock_chain::DATABASE_URL.to_string()))
+            .expect("DATABASE_URL must be set"),
+        database_max_connections: std::env::var("DATABASE_MAX_CONNECTIONS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10),
+        database_auto_migrate: std::env::var("DATABASE_AUTO_MIGRATE")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(true),
+        indexer_poll_interval_secs: std::env::var("INDEXER_POLL_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60),
+        keeper_enabled: std::env::var("KEEPER_ENABLED")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(true),
+        keeper_interval_secs: std::env::var("KEEPER_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60),
+        keeper_dry_run: std::env::var("KEEPER_DRY_RUN")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false),
+        keeper_concurrency: std::env::var("KEEPER_CONCURRENCY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(4),
+        keeper_batch_size: std::env::var("KEEPER_BATCH_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(100),
+        reminder_interval_secs: std::env::var("REMINDER_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60),
+        reconciliation_enabled: std::env::var("RECONCILIATION_ENABLED")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(true),
+        reconciliation_threshold_lamports: std::env::var("RECONCILIATION_THRESHOLD_LAMPORTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0),
+        reconciliation_alert_email: std::env::var("RECONCILIATION_ALERT_EMAIL").ok().filter(|v| !v.is_empty()),
+        reconciliation_webhook_url: std::env::var("RECONCILIATION_WEBHOOK_URL").ok().filter(|v| !v.is_empty()),
+        reconciliation_webhook_secret: std::env::var("RECONCILIATION_WEBHOOK_SECRET").ok().filter(|v| !v.is_empty()),
+        auth_anomaly_threshold: std::env::var("AUTH_ANOMALY_THRESHOLD")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10),
+        auth_anomaly_window_secs: std::env::var("AUTH_ANOMALY_WINDOW_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(300),
+        auth_alert_webhook_url: std::env::var("AUTH_ALERT_WEBHOOK_URL").ok().filter(|v| !v.is_empty()),
+        auth_alert_webhook_secret: std::env::var("AUTH_ALERT_WEBHOOK_SECRET").ok().filter(|v| !v.is_empty()),
+        push_api_url: std::env::var("PUSH_API_URL").unwrap_or_else(|_| "https://exp.host/--/api/v2/push/send".to_string()),
+        push_access_token: std::env::var("PUSH_ACCESS_TOKEN").ok().filter(|v| !v.is_empty()),
+        smtp_url: std::env::var("SMTP_URL").ok(),
+        email_from: std::env::var("EMAIL_FROM").unwrap_or_else(|_| "Subscriptions <no-reply@localhost>".to_string()),
+        export_retention_secs: std::env::var("EXPORT_RETENTION_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(7 * 86400),
+        statement_emails_enabled: std::env::var("STATEMENT_EMAILS_ENABLED")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(true),
+        platform_fee_bps: std::env::var("PLATFORM_FEE_BPS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0),
+        price_feed_url: std::env::var("PRICE_FEED_URL").ok().filter(|v| !v.is_empty()),
+        price_cache_secs: std::env::var("PRICE_CACHE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60),
+        token_price_feed_url: std::env::var("TOKEN_PRICE_FEED_URL").ok().filter(|v| !v.is_empty()),
+        devnet_airdrop_enabled: cluster != Cluster::Mainnet
+            && std::env::var("DEVNET_AIRDROP_ENABLED")
+                .map(|v| v == "true" || v == "1")
+                .unwrap_or(false),
+        airdrop_lamports: std::env::var("AIRDROP_LAMPORTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1_000_000_000),
+        airdrop_daily_cap_lamports: std::env::var("AIRDROP_DAILY_CAP_LAMPORTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2_000_000_000),
+        cors_allowed_origins: std::env::var("CORS_ALLOWED_ORIGINS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect(),
+        hsts_max_age_secs: std::env::var("HSTS_MAX_AGE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(31536000),
+        content_security_policy: std::env::var("CONTENT_SECURITY_POLICY")
+            .unwrap_or_else(|_| "default-src 'none'; frame-ancestors 'none'".to_string()),
+        error_reporting_enabled: std::env::var("ERROR_REPORTING_ENABLED")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(true),
+        sentry_dsn: std::env::var("SENTRY_DSN").ok().filter(|v| !v.is_empty()),
+        error_report_url: std::env::var("ERROR_REPORT_URL").ok().filter(|v| !v.is_empty()),
+        error_report_environment: std::env::var("ERROR_REPORT_ENVIRONMENT").unwrap_or_else(|_| "production".to_string()),
+        redis_url: std::env::var("REDIS_URL").ok(),
+        cache_ttl_secs: std::env::var("CACHE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(15),
+        account_cache_ttl_ms: std::env::var("ACCOUNT_CACHE_TTL_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2000),
+        account_cache_finalized_ttl_ms: std::env::var("ACCOUNT_CACHE_FINALIZED_TTL_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30000),
+        account_cache_capacity: std::env::var("ACCOUNT_CACHE_CAPACITY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10000),
+        response_cache_fresh_secs: std::env::var("RESPONSE_CACHE_FRESH_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30),
+        response_cache_stale_secs: std::env::var("RESPONSE_CACHE_STALE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(300),
+        response_cache_capacity: std::env::var("RESPONSE_CACHE_CAPACITY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10000),
+        rate_limit_ip_per_minute: std::env::var("RATE_LIMIT_IP_PER_MINUTE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(120),
+        rate_limit_pubkey_per_minute: std::env::var("RATE_LIMIT_PUBKEY_PER_MINUTE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60),
+        status_rate_limit_per_minute: std::env::var("STATUS_RATE_LIMIT_PER_MINUTE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30),
+        solana_pay_label: std::env::var("SOLANA_PAY_LABEL").unwrap_or_else(|_| "Subscription Manager".to_string()),
+        solana_pay_icon_url: std::env::var("SOLANA_PAY_ICON_URL").ok().filter(|v| !v.is_empty()),
+        solana_pay_rate_limit_per_minute: std::env::var("SOLANA_PAY_RATE_LIMIT_PER_MINUTE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60),
+        request_timeouts: timeouts::load_request_timeouts(),
+        heavy_routes: concurrency::load_heavy_routes(),
+        json_body_limit_bytes: std::env::var("JSON_BODY_LIMIT_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(262_144),
+        keep_alive_secs: std::env::var("KEEP_ALIVE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5),
+        client_request_timeout_ms: std::env::var("CLIENT_REQUEST_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5000),
+        client_disconnect_timeout_ms: std::env::var("CLIENT_DISCONNECT_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1000),
+        max_connections: std::env::var("MAX_CONNECTIONS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(25_000),
+        rate_limit_trust_forwarded: std::env::var("RATE_LIMIT_TRUST_FORWARDED")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false),
+        quotas: quota::load_quotas(),
+        access_token_ttl_secs: std::env::var("ACCESS_TOKEN_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(900),
+        refresh_token_ttl_secs: std::env::var("REFRESH_TOKEN_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30 * 86400),
+        auth_challenge_ttl_secs: std::env::var("AUTH_CHALLENGE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(300),
+        auth_replay_ttl_secs: std::env::var("AUTH_REPLAY_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(86400),
+        siws_domain: std::env::var("SIWS_DOMAIN").unwrap_or_else(|_| "localhost:8080".to_string()),
+        siws_statement: std::env::var("SIWS_STATEMENT")
+            .unwrap_or_else(|_| "Sign in to Subscription Manager".to_string()),
+        admin_pubkeys: std::env::var("ADMIN_PUBKEYS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| Pubkey::from_str(s).expect("Invalid pubkey in ADMIN_PUBKEYS"))
+            .collect(),
+        merchant_pubkeys: std::env::var("MERCHANT_PUBKEYS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| Pubkey::from_str(s).expect("Invalid pubkey in MERCHANT_PUBKEYS"))
+            .collect(),
     }
 }
 
 // Models
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
 pub struct AuthRequest {
+    #[validate(custom = "validate_pubkey")]
     public_key: String,
+    #[validate(length(min = 1, message = "is required"))]
     signature: String,
-    timestamp: i64,
+    nonce: Option<String>,   // Plain challenge flow
+    message: Option<String>, // Full SIWS message text, as signed by the wallet
+    tenant: Option<String>,  // Scopes the session to one tenant
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct ChallengeQuery {
+    #[validate(custom = "validate_pubkey")]
+    public_key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct SubscriptionQuery {
+    commitment: Option<Commitment>, // Read the account from chain at this commitment, skipping the index
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct ChallengeResponse {
+    nonce: String,
+    message: String, // Exact bytes the wallet must sign
+    expires_at: i64,
+    siws: SiwsInput, // Ready-made input for wallet adapters' signIn()
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct AuthResponse {
     token: String,
     expires_in: u64,
+    refresh_token: String,
+    refresh_expires_in: u64,
     public_key: String,
+    roles: Vec<Role>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct RefreshRequest {
+    #[validate(length(min = 1, message = "is required"))]
+    refresh_token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, ToSchema)]
+pub struct LogoutRequest {
+    refresh_token: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Merchant,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Merchant => "merchant",
+            Role::Admin => "admin",
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     sub: String,
+    iss: String,
+    aud: String,
     exp: u64,
     iat: u64,
+    jti: String,
+    #[serde(default)]
+    roles: Vec<Role>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct AuthToken {
     public_key: String,
+    credential: Credential,
+    roles: Vec<Role>,
+    tenant: Option<String>, // Set for sessions scoped to a tenant
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl AuthToken {
+    /// Admins pass every role check.
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role) || self.roles.contains(&Role::Admin)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Credential {
+    Jwt { jti: String, exp: u64 },
+    ApiKey { id: String },
+}
+
+// Upper bounds keep values representable in the index's BIGINT columns
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
 pub struct SubscriptionRequest {
+    #[validate(range(max = 9223372036854775807, message = "must fit in a signed 64-bit integer"))]
     plan_id: u64,
+    #[validate(range(min = 60, max = 31536000, message = "must be between 60 seconds and 365 days"))]
     duration: u64, // in seconds
+    #[validate(range(min = 1, max = 9223372036854775807, message = "must be a positive lamport amount"))]
     amount: u64,   // in lamports
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct SignatureResponse {
+    signature: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct SubscriptionResponse {
     id: String,       // PDA-derived address
     plan_id: u64,
@@ -108,16 +808,48 @@ pub struct SubscriptionResponse {
 }
 
 // Error Handling
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct ErrorResponse {
+    status: String,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<Vec<FieldError>>, // Field-level details of a 422
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transaction_error: Option<SimulatedError>, // Decoded error of a transaction rejected in preflight
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logs: Option<Vec<String>>, // Its program logs
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum AppError {
     #[error("Authentication error: {0}")]
     Auth(String),
     #[error("Bad request: {0}")]
     BadRequest(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
     #[error("Not found: {0}")]
     NotFound(String),
+    #[error("Conflict: {0}")]
+    Conflict(String),
+    #[error("Not acceptable: {0}")]
+    NotAcceptable(String),
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+    #[error("Validation failed")]
+    Validation(Vec<FieldError>),
     #[error("Solana error: {0}")]
     SolanaError(String),
+    #[error("Solana error: {0}")]
+    TransactionFailed(String, Box<TransactionFailure>), // Rejected in preflight, with its logs
+    #[error("Payload too large: {0}")]
+    PayloadTooLarge(String),
+    #[error("Service unavailable: {0}")]
+    ServiceUnavailable(String, u64), // Retry-After in seconds
+    #[error("Timed out: {0}")]
+    Timeout(String),
+    #[error("Database error: {0}")]
+    DatabaseError(String),
     #[error("Internal server error: {0}")]
     InternalServerError(String),
 }
@@ -127,17 +859,71 @@ impl actix_web::ResponseError for AppError {
         match self {
             AppError::Auth(_) => actix_web::http::StatusCode::UNAUTHORIZED,
             AppError::BadRequest(_) => actix_web::http::StatusCode::BAD_REQUEST,
+            AppError::Forbidden(_) => actix_web::http::StatusCode::FORBIDDEN,
             AppError::NotFound(_) => actix_web::http::StatusCode::NOT_FOUND,
-            AppError::SolanaError(_) => actix_web::http::StatusCode::BAD_GATEWAY,
+            AppError::Conflict(_) => actix_web::http::StatusCode::CONFLICT,
+            AppError::NotAcceptable(_) => actix_web::http::StatusCode::NOT_ACCEPTABLE,
+            AppError::RateLimited(_) => actix_web::http::StatusCode::TOO_MANY_REQUESTS,
+            AppError::Validation(_) => actix_web::http::StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::SolanaError(_) | AppError::TransactionFailed(..) => actix_web::http::StatusCode::BAD_GATEWAY,
+            AppError::PayloadTooLarge(_) => actix_web::http::StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::ServiceUnavailable(..) => actix_web::http::StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Timeout(_) => actix_web::http::StatusCode::GATEWAY_TIMEOUT,
+            AppError::DatabaseError(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
             AppError::InternalServerError(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code()).json(serde_json::json!({
-            "status": self.status_code().to_string(),
-            "message": self.to_string()
-        }))
+        let mut builder = HttpResponse::build(self.status_code());
+        if let AppError::ServiceUnavailable(_, retry_after_secs) = self {
+            builder.insert_header((actix_web::http::header::RETRY_AFTER, retry_after_secs.to_string()));
+        }
+        builder.json(self.body())
+    }
+}
+
+impl AppError {
+    /// The JSON body of the error response.
+    pub fn body(&self) -> ErrorResponse {
+        let failure = match self {
+            AppError::TransactionFailed(_, failure) => Some(failure.as_ref().clone()),
+            _ => None,
+        };
+        ErrorResponse {
+            status: self.status_code().to_string(),
+            message: self.to_string(),
+            errors: match self {
+                AppError::Validation(errors) => Some(errors.clone()),
+                _ => None,
+            },
+            transaction_error: failure.as_ref().and_then(|failure| failure.error.clone()),
+            logs: failure.map(|failure| failure.logs),
+        }
+    }
+
+    /// A failed RPC call: `503` when the circuit breaker shed it, `502` otherwise, with the
+    /// simulation's error and logs when a send failed preflight.
+    pub fn rpc(context: &str, error: ClientError) -> Self {
+        if let Some(open) = circuit::shed(&error) {
+            return AppError::ServiceUnavailable(open.to_string(), open.retry_after_secs);
+        }
+        let message = format!("{}: {}", context, error);
+        match preflight_failure(&error) {
+            Some(failure) => AppError::TransactionFailed(message, Box::new(failure)),
+            None => AppError::SolanaError(message),
+        }
+    }
+}
+
+/// The decoded error and sanitized logs of a send that failed preflight simulation.
+fn preflight_failure(error: &ClientError) -> Option<TransactionFailure> {
+    match error.kind() {
+        ClientErrorKind::RpcError(RpcError::RpcResponseError {
+            data: RpcResponseErrorData::SendTransactionPreflightFailure(sim),
+            ..
+        }) => Some(TransactionFailure::new(sim.err.as_ref(), sim.logs.as_deref().unwrap_or_default())),
+        _ => None,
     }
 }
 
@@ -146,116 +932,259 @@ pub type AppResult<T> = Result<T, AppError>;
 // Solana Service
 #[derive(Clone)]
 pub struct SolanaService {
-    rpc_client: Arc<RpcClient>,
+    rpc: RpcPool,
+    confirm_rpcs: Arc<HashMap<Commitment, RpcPool>>, // Clients that send and wait for each commitment
+    accounts: AccountCache, // Shared by the cluster's tenants
+    cluster: Cluster,
+    tenant: String,
+    primary: bool,
     program_id: Pubkey,
-    treasury: Pubkey,
-    phantom_keypair: Arc<Keypair>,
+    layout: ProgramLayout,
+    decoder: AccountDecoder,
+    treasury: Arc<RwLock<Pubkey>>, // Reloadable, see `reload`
+    signer: Arc<dyn TransactionSigner>, // Shared by every cluster and tenant
+    custody: Option<CustodyService>, // Signs for custodial wallets
+    wallet_locks: WalletLocks, // Shared by every cluster and tenant
+    commitments: CommitmentPolicy,
+    limits: Arc<RwLock<SubscriptionLimits>>,
+    pool: PgPool,
+    read_only: Arc<AtomicBool>, // Set when the deployed program does not match this build
+    nonce_pool_size: usize,
+    nonce_max_per_user: i64,
 }
 
 impl SolanaService {
-    pub fn new(config: &Config) -> Self {
-        let private_key_bytes = bs58::decode(&config.phantom_private_key)
-            .into_vec()
-            .expect("Invalid PHANTOM_PRIVATE_KEY format");
-        let keypair = Keypair::from_bytes(&private_key_bytes)
-            .expect("Failed to parse Phantom private key");
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        config: &Config,
+        cluster: &ClusterConfig,
+        tenant: &TenantConfig,
+        rpc: RpcPool,
+        accounts: AccountCache,
+        pool: PgPool,
+        signer: Arc<dyn TransactionSigner>,
+        custody: Option<CustodyService>,
+        wallet_locks: WalletLocks,
+    ) -> Self {
+        let program_id = tenant.program_id(cluster.cluster).unwrap_or(cluster.program_id);
+        let decoder = AccountDecoder::new(config);
+
+        let confirm_rpcs = [Commitment::Processed, Commitment::Confirmed, Commitment::Finalized]
+            .into_iter()
+            .map(|commitment| (commitment, rpc.with_commitment(commitment.config())))
+            .collect();
 
         Self {
-            rpc_client: Arc::new(RpcClient::new(config.solana_rpc_url.clone())),
-            program_id: config.program_id,
-            treasury: config.treasury,
-            phantom_keypair: Arc::new(keypair),
+            rpc,
+            confirm_rpcs: Arc::new(confirm_rpcs),
+            accounts,
+            cluster: cluster.cluster,
+            tenant: tenant.id.clone(),
+            primary: cluster.cluster == config.cluster && tenant.id == DEFAULT_TENANT,
+            program_id,
+            layout: decoder.layout_of(&program_id).unwrap_or(cluster.layout),
+            decoder,
+            treasury: Arc::new(RwLock::new(tenant.treasury)),
+            signer,
+            custody,
+            wallet_locks,
+            commitments: config.commitments,
+            limits: Arc::new(RwLock::new(SubscriptionLimits::new(config, tenant))),
+            pool,
+            read_only: Arc::new(AtomicBool::new(false)),
+            nonce_pool_size: config.nonce_pool_size,
+            nonce_max_per_user: config.nonce_max_per_user.max(1),
         }
     }
 
+    pub fn subscription_pda(&self, owner: &Pubkey, plan_id: u64) -> Pubkey {
+        let (subscription_pda, _bump) = Pubkey::find_program_address(
+            &[b"subscription", owner.as_ref(), plan_id.to_le_bytes().as_ref()],
+            &self.program_id,
+        );
+        subscription_pda
+    }
+
+    /// Cached account reads at a chosen commitment.
+    pub fn accounts(&self) -> &AccountCache {
+        &self.accounts
+    }
+
+    /// The commitment of an account read returned to a client: `requested`, else the request's
+    /// `X-Solana-Commitment`, else `READ_COMMITMENT`.
+    pub fn read_commitment(&self, requested: Option<Commitment>) -> Commitment {
+        requested.or_else(commitment::requested).unwrap_or(self.commitments.read)
+    }
+
+    /// The commitment of account checks made before a transaction is built.
+    pub fn check_commitment(&self) -> Commitment {
+        self.commitments.check
+    }
+
+    /// What a submission waits for: the request's `X-Solana-Commitment`, else
+    /// `CONFIRM_COMMITMENT`.
+    pub fn confirm_commitment(&self) -> Commitment {
+        commitment::requested().unwrap_or(self.commitments.confirm)
+    }
+
+    /// Only the primary cluster's default tenant is indexed, cached and reported to webhooks.
+    pub fn is_primary(&self) -> bool {
+        self.primary
+    }
+
+    pub fn tenant(&self) -> &str {
+        &self.tenant
+    }
+
+    /// Stops this cluster and tenant from building transactions; see `deployment::verify`.
+    pub fn set_read_only(&self) {
+        self.read_only.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    /// The wallet payments go to.
+    pub fn treasury(&self) -> Pubkey {
+        *self.treasury.read().unwrap()
+    }
+
+    pub fn set_treasury(&self, treasury: Pubkey) {
+        *self.treasury.write().unwrap() = treasury;
+    }
+
+    pub fn limits(&self) -> SubscriptionLimits {
+        self.limits.read().unwrap().clone()
+    }
+
+    pub fn set_limits(&self, limits: SubscriptionLimits) {
+        *self.limits.write().unwrap() = limits;
+    }
+
+    /// The wallet that signs submitted transactions, and so the only owner the backend
+    /// can renew on its own.
+    pub fn signer(&self) -> Pubkey {
+        self.signer.pubkey()
+    }
+
+    /// Adds the signer's signature to `tx`.
+    pub async fn sign(&self, tx: &mut Transaction) -> AppResult<()> {
+        signer::sign_transaction(self.signer.as_ref(), tx).await
+    }
+
+    /// Adds the signatures `owner`'s transaction needs from the backend: the signer's, and the
+    /// owner's own when its wallet is custodial. A custodial owner pays its own fees, so the
+    /// signer only signs when it advances a durable nonce.
+    async fn sign_for(&self, tx: &mut Transaction, owner: &Pubkey) -> AppResult<()> {
+        let keypair = match &self.custody {
+            Some(custody) => custody.keypair(owner).await?,
+            None => None,
+        };
+        let Some(keypair) = keypair else {
+            return self.sign(tx).await;
+        };
+        let required = tx.message.header.num_required_signatures as usize;
+        if tx.message.account_keys[..required].contains(&self.signer()) {
+            self.sign(tx).await?;
+        }
+        let recent_blockhash = tx.message.recent_blockhash;
+        tx.try_partial_sign(&[&keypair], recent_blockhash)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to sign for custodial wallet {}: {}", owner, e)))
+    }
+
+    /// Serializes `owner`'s transactions: hold the guard from building one until it is
+    /// submitted.
+    pub async fn lock_wallet(&self, owner: &str) -> AppResult<WalletGuard> {
+        let owner_pubkey = Pubkey::from_str(owner)
+            .map_err(|e| AppError::BadRequest(format!("Invalid public key: {}", e)))?;
+        self.wallet_locks.lock(&owner_pubkey).await
+    }
+
+    pub fn subscription_address(&self, owner: &str, plan_id: u64) -> AppResult<Pubkey> {
+        let owner_pubkey = Pubkey::from_str(owner)
+            .map_err(|e| AppError::BadRequest(format!("Invalid public key: {}", e)))?;
+        Ok(self.subscription_pda(&owner_pubkey, plan_id))
+    }
+
     pub async fn create_subscription(
         &self,
         owner: &str,
         req: SubscriptionRequest,
     ) -> AppResult<String> {
+        let _guard = self.lock_wallet(owner).await?;
+        let tx = self.build_create_tx(owner, &req).await?;
+        self.submit(tx).await
+    }
+
+    /// The `create_subscription` instruction, once the terms are allowed and the account
+    /// does not exist yet.
+    pub async fn build_create_tx(&self, owner: &str, req: &SubscriptionRequest) -> AppResult<PendingTx> {
         let owner_pubkey = Pubkey::from_str(owner)
             .map_err(|e| AppError::BadRequest(format!("Invalid public key: {}", e)))?;
+        self.check_terms(req)?;
 
-        let (subscription_pda, _bump) = Pubkey::find_program_address(
-            &[b"subscription", owner_pubkey.as_ref(), req.plan_id.to_le_bytes().as_ref()],
-            &self.program_id,
-        );
+        let subscription_pda = self.subscription_pda(&owner_pubkey, req.plan_id);
 
         // Check if the account already exists
-        if let Ok(account) = self.rpc_client.get_account(&subscription_pda).await {
+        if self.accounts.get(&subscription_pda, self.check_commitment()).await?.is_some() {
             return Err(AppError::BadRequest(format!(
                 "Subscription PDA {} already exists",
                 subscription_pda
             )));
         }
 
+        Ok(PendingTx {
+            name: "create_subscription",
+            owner: owner_pubkey,
+            plan_id: req.plan_id,
+            instruction: self.create_instruction(&owner_pubkey, req),
+        })
+    }
+
+    /// Operator limits, then whatever terms the program itself accepts.
+    pub fn check_terms(&self, req: &SubscriptionRequest) -> AppResult<()> {
+        self.limits.read().unwrap().check(req)?;
+        self.layout.check_terms(req)
+    }
+
+    fn create_instruction(&self, owner: &Pubkey, req: &SubscriptionRequest) -> Instruction {
+        let subscription_pda = self.subscription_pda(owner, req.plan_id);
+
         let mut data = hash("global:create_subscription".as_bytes()).to_bytes()[..8].to_vec();
-        data.extend_from_slice(&req.plan_id.to_le_bytes());
-        data.extend_from_slice(&req.duration.to_le_bytes());
-        data.extend_from_slice(&req.amount.to_le_bytes());
+        data.extend_from_slice(&self.layout.create_args(req));
 
-        let instruction = Instruction {
+        Instruction {
             program_id: self.program_id,
             accounts: vec![
                 solana_sdk::instruction::AccountMeta::new(subscription_pda, false),
-                solana_sdk::instruction::AccountMeta::new(owner_pubkey, true),
-                solana_sdk::instruction::AccountMeta::new(self.treasury, false),
+                solana_sdk::instruction::AccountMeta::new(*owner, true),
+                solana_sdk::instruction::AccountMeta::new(self.treasury(), false),
                 solana_sdk::instruction::AccountMeta::new_readonly(system_program::id(), false),
             ],
             data,
-        };
-
-        let recent_blockhash = self.rpc_client
-            .get_latest_blockhash()
-            .await
-            .map_err(|e| AppError::SolanaError(format!("Failed to get blockhash: {}", e)))?;
-        let message = Message::new_with_blockhash(&[instruction], Some(&owner_pubkey), &recent_blockhash);
-        let mut tx = Transaction::new_unsigned(message);
-
-        tx.sign(&[&self.phantom_keypair], recent_blockhash);
-
-        let signature = self.rpc_client
-            .send_and_confirm_transaction(&tx)
-            .await
-            .map_err(|e| {
-                if let solana_client::client_error::ClientErrorKind::RpcError(RpcError::RpcResponseError { data, .. }) = &e.kind() {
-                    if let RpcResponseErrorData::SendTransactionPreflightFailure(sim) = data {
-                        log::error!("Transaction simulation failed: {:?}", sim.logs);
-                    }
-                }
-                AppError::SolanaError(format!("Transaction failed: {}", e))
-            })?;
-
-        Ok(signature.to_string())
+        }
     }
 
-    pub async fn get_subscription(&self, owner: &str, plan_id: u64) -> AppResult<SubscriptionResponse> {
+    /// The subscription as read from chain at `commitment`; see `AccountCache`.
+    pub async fn get_subscription(
+        &self,
+        owner: &str,
+        plan_id: u64,
+        commitment: Commitment,
+    ) -> AppResult<SubscriptionResponse> {
         let owner_pubkey = Pubkey::from_str(owner)
             .map_err(|e| AppError::BadRequest(format!("Invalid public key: {}", e)))?;
 
-        let (subscription_pda, _bump) = Pubkey::find_program_address(
-            &[b"subscription", owner_pubkey.as_ref(), plan_id.to_le_bytes().as_ref()],
-            &self.program_id,
-        );
-
-        log::info!("Fetching subscription PDA: {}", subscription_pda);
-
-        let account = self.rpc_client
-            .get_account(&subscription_pda)
-            .await
-            .map_err(|e| AppError::SolanaError(format!("Failed to fetch account: {}", e)))?;
+        let subscription_pda = self.subscription_pda(&owner_pubkey, plan_id);
 
-        log::info!("Raw account data (len={}): {:?}", account.data.len(), account.data);
+        tracing::info!("Fetching subscription PDA: {} at {}", subscription_pda, commitment.as_str());
 
-        // Skip the 8-byte discriminator and deserialize
-        let mut data_slice = &account.data[8..];
-        let subscription = Subscription::deserialize(&mut data_slice)
-            .map_err(|e| AppError::SolanaError(format!("Deserialization error: {}", e)))?;
+        let account = self.accounts.get(&subscription_pda, commitment).await?.ok_or_else(|| {
+            AppError::SolanaError(format!("Failed to fetch account: AccountNotFound: pubkey={}", subscription_pda))
+        })?;
 
-        // Log any extra bytes (for debugging)
-        if !data_slice.is_empty() {
-            log::warn!("Extra bytes remaining after deserialization: {} bytes", data_slice.len());
-        }
+        let subscription = self.decoder.decode(&account)?;
 
         Ok(SubscriptionResponse {
             id: subscription_pda.to_string(),
@@ -269,54 +1198,113 @@ impl SolanaService {
         })
     }
 
+    /// The subscriptions of `(owner, plan_id)` pairs that exist at `commitment`, in the order
+    /// given. Accounts are fetched in batches of 100 and decoded in parallel, chunk by chunk.
+    pub async fn get_subscriptions(
+        &self,
+        keys: &[(String, u64)],
+        commitment: Commitment,
+    ) -> AppResult<Vec<SubscriptionResponse>> {
+        let pdas = keys
+            .iter()
+            .map(|(owner, plan_id)| self.subscription_address(owner, *plan_id))
+            .collect::<AppResult<Vec<Pubkey>>>()?;
+        let accounts = self.accounts.get_many(&pdas, commitment).await?;
+
+        let found: Vec<(Pubkey, solana_sdk::account::Account)> = pdas
+            .into_iter()
+            .zip(accounts)
+            .filter_map(|(pda, account)| Some((pda, account?)))
+            .collect();
+        let decoded = futures::future::try_join_all(found.chunks(DECODE_CHUNK_SIZE).map(|chunk| {
+            let decoder = self.decoder.clone();
+            let chunk = chunk.to_vec();
+            tokio::task::spawn_blocking(move || {
+                chunk
+                    .iter()
+                    .map(|(pda, account)| {
+                        let subscription = decoder.decode(account)?;
+                        Ok(SubscriptionResponse {
+                            id: pda.to_string(),
+                            plan_id: subscription.plan_id,
+                            duration: subscription.duration,
+                            amount: subscription.amount,
+                            active: subscription.active,
+                            start_time: subscription.start_time,
+                            history: subscription.history,
+                            owner: subscription.user.to_string(),
+                        })
+                    })
+                    .collect::<AppResult<Vec<SubscriptionResponse>>>()
+            })
+        }))
+        .await
+        .map_err(|e| AppError::InternalServerError(format!("Failed to decode subscriptions: {}", e)))?;
+        Ok(decoded.into_iter().collect::<AppResult<Vec<_>>>()?.into_iter().flatten().collect())
+    }
+
     pub async fn renew_subscription(&self, owner: &str, plan_id: u64) -> AppResult<String> {
+        let _guard = self.lock_wallet(owner).await?;
+        let tx = self.build_renew_tx(owner, plan_id)?;
+        self.submit(tx).await
+    }
+
+    pub fn build_renew_tx(&self, owner: &str, plan_id: u64) -> AppResult<PendingTx> {
         let owner_pubkey = Pubkey::from_str(owner)
             .map_err(|e| AppError::BadRequest(format!("Invalid public key: {}", e)))?;
 
-        let (subscription_pda, _bump) = Pubkey::find_program_address(
-            &[b"subscription", owner_pubkey.as_ref(), plan_id.to_le_bytes().as_ref()],
-            &self.program_id,
-        );
+        Ok(PendingTx {
+            name: "renew_subscription",
+            owner: owner_pubkey,
+            plan_id,
+            instruction: self.renew_instruction(&owner_pubkey, plan_id),
+        })
+    }
+
+    fn renew_instruction(&self, owner: &Pubkey, plan_id: u64) -> Instruction {
+        let subscription_pda = self.subscription_pda(owner, plan_id);
 
         let data = hash("global:renew_subscription".as_bytes()).to_bytes()[..8].to_vec();
-        let instruction = Instruction {
+        Instruction {
             program_id: self.program_id,
             accounts: vec![
                 solana_sdk::instruction::AccountMeta::new(subscription_pda, false),
-                solana_sdk::instruction::AccountMeta::new(owner_pubkey, true),
-                solana_sdk::instruction::AccountMeta::new(self.treasury, false),
+                solana_sdk::instruction::AccountMeta::new(*owner, true),
+                solana_sdk::instruction::AccountMeta::new(self.treasury(), false),
                 solana_sdk::instruction::AccountMeta::new_readonly(system_program::id(), false),
             ],
             data,
-        };
+        }
+    }
 
-        let recent_blockhash = self.rpc_client
-            .get_latest_blockhash()
-            .await
-            .map_err(|e| AppError::SolanaError(format!("Failed to get blockhash: {}", e)))?;
-        let message = Message::new_with_blockhash(&[instruction], Some(&owner_pubkey), &recent_blockhash);
-        let mut tx = Transaction::new_unsigned(message);
+    pub async fn cancel_subscription(&self, owner: &str, plan_id: u64) -> AppResult<String> {
+        let _guard = self.lock_wallet(owner).await?;
+        let tx = self.build_cancel_tx(owner, plan_id)?;
+        self.submit(tx).await
+    }
 
-        tx.sign(&[&self.phantom_keypair], recent_blockhash);
+    pub fn build_cancel_tx(&self, owner: &str, plan_id: u64) -> AppResult<PendingTx> {
+        self.owner_only_tx("cancel_subscription", owner, plan_id)
+    }
 
-        let signature = self.rpc_client
-            .send_and_confirm_transaction(&tx)
-            .await
-            .map_err(|e| AppError::SolanaError(format!("Transaction failed: {}", e)))?;
+    pub async fn close_subscription(&self, owner: &str, plan_id: u64) -> AppResult<String> {
+        let _guard = self.lock_wallet(owner).await?;
+        let tx = self.build_close_tx(owner, plan_id)?;
+        self.submit(tx).await
+    }
 
-        Ok(signature.to_string())
+    pub fn build_close_tx(&self, owner: &str, plan_id: u64) -> AppResult<PendingTx> {
+        self.owner_only_tx("close_subscription", owner, plan_id)
     }
 
-    pub async fn cancel_subscription(&self, owner: &str, plan_id: u64) -> AppResult<String> {
+    /// Cancel and close: the subscription account and its owner, no arguments.
+    fn owner_only_tx(&self, name: &'static str, owner: &str, plan_id: u64) -> AppResult<PendingTx> {
         let owner_pubkey = Pubkey::from_str(owner)
             .map_err(|e| AppError::BadRequest(format!("Invalid public key: {}", e)))?;
 
-        let (subscription_pda, _bump) = Pubkey::find_program_address(
-            &[b"subscription", owner_pubkey.as_ref(), plan_id.to_le_bytes().as_ref()],
-            &self.program_id,
-        );
+        let subscription_pda = self.subscription_pda(&owner_pubkey, plan_id);
 
-        let data = hash("global:cancel_subscription".as_bytes()).to_bytes()[..8].to_vec();
+        let data = hash(format!("global:{}", name).as_bytes()).to_bytes()[..8].to_vec();
         let instruction = Instruction {
             program_id: self.program_id,
             accounts: vec![
@@ -326,61 +1314,188 @@ impl SolanaService {
             data,
         };
 
-        let recent_blockhash = self.rpc_client
-            .get_latest_blockhash()
-            .await
-            .map_err(|e| AppError::SolanaError(format!("Failed to get blockhash: {}", e)))?;
-        let message = Message::new_with_blockhash(&[instruction], Some(&owner_pubkey), &recent_blockhash);
-        let mut tx = Transaction::new_unsigned(message);
-
-        tx.sign(&[&self.phantom_keypair], recent_blockhash);
+        Ok(PendingTx { name, owner: owner_pubkey, plan_id, instruction })
+    }
 
-        let signature = self.rpc_client
-            .send_and_confirm_transaction(&tx)
+    /// Runs `instructions` through `simulateTransaction` as `owner` would send them, without
+    /// signing, and returns the network fee for the message along with the result.
+    pub async fn simulate(&self, owner: &Pubkey, instructions: &[Instruction]) -> AppResult<(u64, RpcSimulateTransactionResult)> {
+        let client = self.rpc.client();
+        let message = self.unsigned_message(owner, instructions).await?;
+        let fee = metrics::observe_rpc("getFeeForMessage", client.get_fee_for_message(&message))
             .await
-            .map_err(|e| AppError::SolanaError(format!("Transaction failed: {}", e)))?;
+            .map_err(|e| AppError::rpc("Failed to get fee", e))?;
 
-        Ok(signature.to_string())
+        let config = RpcSimulateTransactionConfig {
+            sig_verify: false,
+            commitment: Some(client.commitment()),
+            ..RpcSimulateTransactionConfig::default()
+        };
+        let result = metrics::observe_rpc(
+            "simulateTransaction",
+            client.simulate_transaction_with_config(&Transaction::new_unsigned(message), config),
+        )
+        .await
+        .map_err(|e| AppError::rpc("Failed to simulate transaction", e))?;
+        Ok((fee, result.value))
     }
 
-    pub async fn close_subscription(&self, owner: &str, plan_id: u64) -> AppResult<String> {
-        let owner_pubkey = Pubkey::from_str(owner)
-            .map_err(|e| AppError::BadRequest(format!("Invalid public key: {}", e)))?;
-
-        let (subscription_pda, _bump) = Pubkey::find_program_address(
-            &[b"subscription", owner_pubkey.as_ref(), plan_id.to_le_bytes().as_ref()],
-            &self.program_id,
-        );
+    /// Rent-exempt deposit for a new subscription account.
+    pub async fn subscription_rent(&self) -> AppResult<u64> {
+        metrics::observe_rpc(
+            "getMinimumBalanceForRentExemption",
+            self.rpc.client().get_minimum_balance_for_rent_exemption(SUBSCRIPTION_ACCOUNT_SPACE),
+        )
+        .await
+        .map_err(|e| AppError::rpc("Failed to get rent", e))
+    }
 
-        let data = hash("global:close_subscription".as_bytes()).to_bytes()[..8].to_vec();
-        let instruction = Instruction {
-            program_id: self.program_id,
-            accounts: vec![
-                solana_sdk::instruction::AccountMeta::new(subscription_pda, false),
-                solana_sdk::instruction::AccountMeta::new(owner_pubkey, true),
-            ],
-            data,
-        };
+    /// Base network fee for `owner` sending `instruction` now, before any priority fee.
+    pub async fn base_fee(&self, owner: &Pubkey, instruction: Instruction) -> AppResult<u64> {
+        let message = self.unsigned_message(owner, &[instruction]).await?;
+        metrics::observe_rpc("getFeeForMessage", self.rpc.client().get_fee_for_message(&message))
+            .await
+            .map_err(|e| AppError::rpc("Failed to get fee", e))
+    }
 
-        let recent_blockhash = self.rpc_client
-            .get_latest_blockhash()
+    async fn unsigned_message(&self, owner: &Pubkey, instructions: &[Instruction]) -> AppResult<Message> {
+        let recent_blockhash = metrics::observe_rpc("getLatestBlockhash", self.rpc.client().get_latest_blockhash())
             .await
-            .map_err(|e| AppError::SolanaError(format!("Failed to get blockhash: {}", e)))?;
-        let message = Message::new_with_blockhash(&[instruction], Some(&owner_pubkey), &recent_blockhash);
-        let mut tx = Transaction::new_unsigned(message);
+            .map_err(|e| AppError::rpc("Failed to get blockhash", e))?;
+        Ok(Message::new_with_blockhash(instructions, Some(owner), &recent_blockhash))
+    }
 
-        tx.sign(&[&self.phantom_keypair], recent_blockhash);
+    /// Signs and sends one instruction built by a `build_*_tx`.
+    pub async fn submit(&self, tx: PendingTx) -> AppResult<String> {
+        self.submit_transaction(tx.name, &tx.owner, tx.plan_id, Vec::new(), &[tx.instruction]).await
+    }
 
-        let signature = self.rpc_client
-            .send_and_confirm_transaction(&tx)
-            .await
-            .map_err(|e| AppError::SolanaError(format!("Transaction failed: {}", e)))?;
+    /// Signs and sends `instructions` as one transaction. `items` lists
+    /// `<instruction>:<plan_id>` for transactions carrying more than one action. Built on a
+    /// durable nonce from the pool when one is free, so the job can wait out an RPC outage
+    /// without its blockhash expiring.
+    async fn submit_transaction(
+        &self,
+        name: &str,
+        owner: &Pubkey,
+        plan_id: u64,
+        items: Vec<String>,
+        instructions: &[Instruction],
+    ) -> AppResult<String> {
+        let (mut tx, last_valid_block_height) = self.unsigned_transaction(owner, instructions).await?;
+        let lease = nonces::lease(self, owner).await?;
+        if let Some(lease) = lease {
+            let mut with_nonce = vec![lease.advance_instruction(&self.signer())];
+            with_nonce.extend_from_slice(instructions);
+            tx = Transaction::new_unsigned(Message::new_with_blockhash(&with_nonce, Some(owner), &lease.blockhash));
+        }
+        self.sign_for(&mut tx, owner).await?;
+        let nonce_account = lease.map(|lease| lease.address);
+        let mut plan_ids = vec![plan_id];
+        plan_ids.extend(items.iter().filter_map(|item| item.rsplit_once(':')?.1.parse::<u64>().ok()));
+        let result = self
+            .send_signed_transaction(name, owner, plan_id, items, &tx, last_valid_block_height, nonce_account)
+            .await;
+        for plan_id in plan_ids {
+            self.accounts.invalidate(&self.subscription_pda(owner, plan_id));
+        }
+        result
+    }
 
-        Ok(signature.to_string())
+    /// `instructions` as a transaction from `owner` on a fresh blockhash, with the last block
+    /// height it can land at.
+    async fn unsigned_transaction(&self, owner: &Pubkey, instructions: &[Instruction]) -> AppResult<(Transaction, u64)> {
+        if self.is_read_only() {
+            return Err(AppError::ServiceUnavailable(
+                format!("Program {} does not match this backend; transactions are disabled", self.program_id),
+                deployment::READ_ONLY_RETRY_AFTER_SECS,
+            ));
+        }
+        let client = self.rpc.client();
+        let (recent_blockhash, last_valid_block_height) = metrics::observe_rpc(
+            "getLatestBlockhash",
+            client.get_latest_blockhash_with_commitment(client.commitment()),
+        )
+        .await
+        .map_err(|e| AppError::rpc("Failed to get blockhash", e))?;
+        let message = Message::new_with_blockhash(instructions, Some(owner), &recent_blockhash);
+        Ok((Transaction::new_unsigned(message), last_valid_block_height))
+    }
+
+    /// Sends a fully signed transaction and waits for confirmation. It is recorded in
+    /// `transaction_jobs` before it is sent, so if the process stops before confirmation
+    /// the job worker can still resolve it. `nonce_account` is the durable nonce it was
+    /// built on, held until the job resolves.
+    #[allow(clippy::too_many_arguments)]
+    async fn send_signed_transaction(
+        &self,
+        name: &str,
+        owner: &Pubkey,
+        plan_id: u64,
+        items: Vec<String>,
+        tx: &Transaction,
+        last_valid_block_height: u64,
+        nonce_account: Option<Pubkey>,
+    ) -> AppResult<String> {
+        let client = self.confirm_rpcs[&self.confirm_commitment()].client();
+        let signature = tx.signatures[0].to_string();
+        let serialized = bincode::serialize(tx)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to serialize transaction: {}", e)))?;
+        let job_id = hex::encode(rand::random::<[u8; 16]>());
+        let recorded = db::insert_transaction_job(&self.pool, &TransactionJobRow {
+            id: job_id.clone(),
+            signature: signature.clone(),
+            cluster: self.cluster.as_str().to_string(),
+            tenant: self.tenant.clone(),
+            instruction: name.to_string(),
+            owner: owner.to_string(),
+            plan_id: plan_id as i64,
+            items,
+            transaction: BASE64.encode(serialized),
+            last_valid_block_height: last_valid_block_height as i64,
+            attempts: 0,
+            nonce_account: nonce_account.map(|address| address.to_string()),
+        }, unix_now() + jobs::FIRST_CHECK_SECS)
+        .await;
+        if let Err(e) = recorded {
+            if let Some(address) = nonce_account {
+                db::release_nonce_account(&self.pool, &address.to_string()).await?;
+            }
+            return Err(e);
+        }
+        db::reschedule_transaction_job(&self.pool, &job_id, 1, unix_now() + jobs::FIRST_CHECK_SECS, None).await?;
+
+        let result = metrics::observe_rpc("sendTransaction", client.send_and_confirm_transaction(tx)).await;
+        metrics::record_transaction(name, result.is_ok());
+        if let Err(e) = result {
+            // A failed preflight or a shed send never reached the cluster; other errors may
+            // still land and are left submitted for the job worker
+            if let Some(open) = circuit::shed(&e) {
+                db::set_transaction_job_status(&self.pool, &job_id, "failed", Some(&open.to_string())).await?;
+                return Err(AppError::ServiceUnavailable(open.to_string(), open.retry_after_secs));
+            }
+            let message = format!("Transaction failed (job {}): {}", job_id, e);
+            if let Some(failure) = preflight_failure(&e) {
+                tracing::error!("Transaction simulation failed: {:?}", failure.logs);
+                db::set_transaction_job_status(&self.pool, &job_id, "failed", Some(&e.to_string())).await?;
+                return Err(AppError::TransactionFailed(message, Box::new(failure)));
+            }
+            return Err(AppError::SolanaError(message));
+        }
+
+        db::set_transaction_job_status(&self.pool, &job_id, "confirmed", None).await?;
+        Ok(signature)
     }
 }
 
-// Subscription struct to deserialize on-chain data
+// Accounts decoded per blocking task when listing subscriptions
+const DECODE_CHUNK_SIZE: usize = 100;
+
+/// Size of a Subscription account as allocated by `create_subscription`, with room for
+/// ten history entries.
+pub const SUBSCRIPTION_ACCOUNT_SPACE: usize = 8 + 32 + 8 + 8 + 8 + 8 + 1 + 4 + (10 * 8);
+
+// Subscription account, decoded through `layout::AccountDecoder`
 #[derive(BorshDeserialize, BorshSerialize, Debug)]
 pub struct Subscription {
     pub user: Pubkey,      // 32 bytes
@@ -392,24 +1507,113 @@ pub struct Subscription {
     pub history: Vec<i64>, // 4 bytes (len) + 8 bytes per i64
 }
 
+// How often each replica reads signing keys rotated elsewhere
+const JWT_KEY_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+// Least time between reloads prompted by a token with an unknown kid
+const JWT_KEY_RELOAD_MIN_SECS: i64 = 5;
+
 // Simplified AuthService
 #[derive(Clone)]
 pub struct AuthService {
     config: Config,
+    pool: PgPool,
+    jwt_keys: JwtKeys,
+    jwt_keys_loaded_at: Arc<AtomicI64>, // When rotations were last read from the database
 }
 
 impl AuthService {
-    pub fn new(config: Config) -> Self {
-        Self { config }
+    pub fn new(config: Config, pool: PgPool) -> Self {
+        let jwt_keys = JwtKeys::new(
+            &config.jwt_signing_keys,
+            config.jwt_active_kid.as_deref(),
+            &config.jwt_issuer,
+            &config.jwt_audience,
+        );
+        Self { config, pool, jwt_keys, jwt_keys_loaded_at: Arc::new(AtomicI64::new(0)) }
     }
 
-    pub async fn authenticate(&self, req: AuthRequest) -> AppResult<AuthResponse> {
-        let current_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
-        if (current_time - req.timestamp).abs() > 86400 {
-            return Err(AppError::Auth("Authentication request expired".to_string()));
+    pub fn jwt_keys(&self) -> &JwtKeys {
+        &self.jwt_keys
+    }
+
+    /// Picks up signing keys rotated on any replica.
+    pub async fn load_jwt_rotations(&self) -> AppResult<()> {
+        self.jwt_keys_loaded_at.store(unix_now(), Ordering::Relaxed);
+        let rows = db::list_jwt_rotations(&self.pool).await?;
+        self.jwt_keys.apply(&rows);
+        Ok(())
+    }
+
+    /// Makes a generated key the signing key. The one it replaces verifies for one more access
+    /// token lifetime, so no one is signed out.
+    pub async fn rotate_jwt_key(&self) -> AppResult<JwtRotation> {
+        // Rotate from the latest key, not whatever this replica last loaded
+        self.load_jwt_rotations().await?;
+        let row = self.jwt_keys.rotate(unix_now(), self.config.access_token_ttl_secs + jwks::EXPIRY_LEEWAY_SECS)?;
+        db::insert_jwt_rotation(&self.pool, &row).await?;
+        self.load_jwt_rotations().await?;
+        tracing::info!("Rotated JWT signing key {} to {}", row.previous_kid, row.kid);
+        Ok(JwtRotation {
+            kid: row.kid,
+            previous_kid: row.previous_kid,
+            previous_expires_at: row.previous_expires_at,
+        })
+    }
+
+    pub async fn run_jwt_key_refresh(self) {
+        loop {
+            tokio::time::sleep(JWT_KEY_REFRESH_INTERVAL).await;
+            if let Err(e) = self.load_jwt_rotations().await {
+                tracing::warn!("Failed to refresh JWT signing keys: {}", e);
+            }
         }
+    }
+
+    /// Issues a single-use nonce bound to `public_key` for the next sign-in.
+    pub async fn challenge(&self, public_key: &str) -> AppResult<ChallengeResponse> {
+        Pubkey::from_str(public_key)
+            .map_err(|e| AppError::BadRequest(format!("Invalid public key: {}", e)))?;
+
+        let nonce = random_token(32);
+        let expires_at = unix_now() + self.config.auth_challenge_ttl_secs as i64;
+        db::insert_auth_challenge(&self.pool, &nonce, public_key, expires_at).await?;
+
+        Ok(ChallengeResponse {
+            message: challenge_message(&nonce),
+            siws: SiwsInput::new(
+                &self.config.siws_domain,
+                public_key,
+                &self.config.siws_statement,
+                &nonce,
+                expires_at,
+            ),
+            nonce,
+            expires_at,
+        })
+    }
 
-        let message = format!("Sign in to Subscription Manager: {}", req.timestamp);
+    pub async fn authenticate(&self, req: AuthRequest) -> AppResult<AuthResponse> {
+        // Sessions for the default tenant carry no tenant claim, like those that name none
+        let tenant = match req.tenant.as_deref() {
+            None | Some(DEFAULT_TENANT) => None,
+            Some(id) if self.config.tenants.iter().any(|t| t.id == id) => Some(id.to_string()),
+            Some(id) => return Err(AppError::NotFound(format!("Unknown tenant {}", id))),
+        };
+        let (message, nonce, message_expires_at) = match &req.message {
+            Some(message) => {
+                let siws = SiwsMessage::parse(message)
+                    .map_err(|e| AppError::BadRequest(format!("Invalid SIWS message: {}", e)))?;
+                siws.validate(&self.config.siws_domain, &req.public_key, unix_now())
+                    .map_err(|e| AppError::Auth(format!("Invalid SIWS message: {}", e)))?;
+                (message.clone(), siws.nonce.unwrap_or_default(), siws.expiration_time)
+            }
+            None => {
+                let nonce = req.nonce
+                    .clone()
+                    .ok_or(AppError::BadRequest("Either nonce or message is required".to_string()))?;
+                (challenge_message(&nonce), nonce, None)
+            }
+        };
         let signature_bytes = bs58::decode(&req.signature)
             .into_vec()
             .map_err(|e| AppError::BadRequest(format!("Invalid signature format: {}", e)))?;
@@ -422,145 +1626,1576 @@ impl AuthService {
             return Err(AppError::Auth("Invalid signature".to_string()));
         }
 
+        // Remembered at least as long as its challenge or SIWS message stays acceptable, so a
+        // replay is refused even if the challenge store is lost
+        let ttl = self.config.auth_replay_ttl_secs.max(self.config.auth_challenge_ttl_secs) as i64;
+        let remember_until = message_expires_at.map_or(unix_now() + ttl, |exp| exp.max(unix_now() + ttl));
+        if !db::consume_auth_signature(&self.pool, &signature.to_string(), &req.public_key, &nonce, remember_until).await? {
+            tracing::warn!("Replayed sign-in signature for {}", req.public_key);
+            return Err(AppError::Auth("Signature has already been used".to_string()));
+        }
+
+        // Consumed only after the signature checks out, so nobody else can burn a wallet's nonce
+        if !db::consume_auth_challenge(&self.pool, &nonce, &req.public_key).await? {
+            return Err(AppError::Auth("Unknown, expired or already used challenge".to_string()));
+        }
+
+        self.issue_tokens(&req.public_key, tenant.as_deref()).await
+    }
+
+    /// Exchanges a refresh token for a new access/refresh pair. Refresh tokens are
+    /// single-use: presenting one that was already rotated revokes the whole family.
+    pub async fn refresh(&self, req: RefreshRequest) -> AppResult<AuthResponse> {
+        let token_hash = hash_refresh_token(&req.refresh_token);
+        let stored = db::find_refresh_token(&self.pool, &token_hash)
+            .await?
+            .ok_or_else(|| AppError::Auth("Invalid refresh token".to_string()))?;
+
+        // Revoking is the claim on the token, so of two concurrent uses only one gets a session
+        if !db::revoke_refresh_token(&self.pool, &token_hash).await? {
+            tracing::warn!("Refresh token reuse detected for {}, revoking all sessions", stored.public_key);
+            db::revoke_refresh_tokens_for(&self.pool, &stored.public_key).await?;
+            return Err(AppError::Auth("Refresh token has been revoked".to_string()));
+        }
+        if stored.expires_at < unix_now() {
+            return Err(AppError::Auth("Refresh token expired".to_string()));
+        }
+        self.issue_tokens(&stored.public_key, stored.tenant.as_deref()).await
+    }
+
+    pub async fn logout(&self, auth_token: &AuthToken, req: LogoutRequest) -> AppResult<()> {
+        if let Credential::Jwt { jti, exp } = &auth_token.credential {
+            db::revoke_access_token(&self.pool, jti, *exp as i64).await?;
+        }
+        if let Some(refresh_token) = req.refresh_token {
+            db::revoke_refresh_token(&self.pool, &hash_refresh_token(&refresh_token)).await?;
+        }
+        Ok(())
+    }
+
+    // Roles are resolved at issuance, so config changes apply from the next refresh. Merchant
+    // routes serve indexed data, which only covers the default tenant, so sessions scoped to
+    // another tenant are never merchants.
+    fn roles_for(&self, public_key: &str, tenant: Option<&str>) -> Vec<Role> {
+        let mut roles = vec![Role::User];
+        let Ok(pubkey) = Pubkey::from_str(public_key) else {
+            return roles;
+        };
+        if tenant.is_none() && (pubkey == self.config.treasury || self.config.merchant_pubkeys.contains(&pubkey)) {
+            roles.push(Role::Merchant);
+        }
+        if self.config.admin_pubkeys.contains(&pubkey) {
+            roles.push(Role::Admin);
+        }
+        roles
+    }
+
+    async fn issue_tokens(&self, public_key: &str, tenant: Option<&str>) -> AppResult<AuthResponse> {
+        let current_time = unix_now();
+        let roles = self.roles_for(public_key, tenant);
         let claims = Claims {
-            sub: req.public_key.clone(),
-            exp: (current_time + 86400) as u64,
+            sub: public_key.to_string(),
+            iss: self.config.jwt_issuer.clone(),
+            aud: self.config.jwt_audience.clone(),
+            exp: (current_time + self.config.access_token_ttl_secs as i64) as u64,
             iat: current_time as u64,
+            jti: random_token(16),
+            roles: roles.clone(),
+            tenant: tenant.map(str::to_string),
         };
-        let token = encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(self.config.jwt_secret.as_bytes()),
+        let token = self.jwt_keys.sign(&claims)?;
+
+        let refresh_token = random_token(32);
+        db::insert_refresh_token(
+            &self.pool,
+            &hash_refresh_token(&refresh_token),
+            public_key,
+            tenant,
+            current_time + self.config.refresh_token_ttl_secs as i64,
         )
-        .map_err(|e| AppError::InternalServerError(format!("Failed to create JWT: {}", e)))?;
+        .await?;
 
         Ok(AuthResponse {
             token,
-            expires_in: 86400,
-            public_key: req.public_key,
+            expires_in: self.config.access_token_ttl_secs,
+            refresh_token,
+            refresh_expires_in: self.config.refresh_token_ttl_secs,
+            public_key: public_key.to_string(),
+            roles,
+            tenant: tenant.map(str::to_string),
         })
     }
 
-    pub fn verify_token(&self, token: &str) -> AppResult<AuthToken> {
-        let token_data = jsonwebtoken::decode::<Claims>(
-            token,
-            &jsonwebtoken::DecodingKey::from_secret(self.config.jwt_secret.as_bytes()),
-            &Validation::default(),
-        )
-        .map_err(|e| AppError::Auth(format!("Invalid token: {}", e)))?;
+    pub async fn verify_token(&self, token: &str) -> AppResult<AuthToken> {
+        // A key rotated on another replica may sign tokens before the next refresh here
+        let reloadable = unix_now() - self.jwt_keys_loaded_at.load(Ordering::Relaxed) >= JWT_KEY_RELOAD_MIN_SECS;
+        if reloadable && self.jwt_keys.is_unknown_kid(token) {
+            if let Err(e) = self.load_jwt_rotations().await {
+                tracing::warn!("Failed to refresh JWT signing keys: {}", e);
+            }
+        }
+        let claims = self.jwt_keys.verify(token)?;
+
+        if db::is_access_token_revoked(&self.pool, &claims.jti).await? {
+            return Err(AppError::Auth("Token has been revoked".to_string()));
+        }
+
         Ok(AuthToken {
-            public_key: token_data.claims.sub,
+            public_key: claims.sub,
+            credential: Credential::Jwt {
+                jti: claims.jti,
+                exp: claims.exp,
+            },
+            roles: claims.roles,
+            tenant: claims.tenant,
         })
     }
 }
 
+fn challenge_message(nonce: &str) -> String {
+    format!("Sign in to Subscription Manager: {}", nonce)
+}
+
+// Refresh tokens are opaque; only their SHA-256 is stored
+fn hash_refresh_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn random_token(len: usize) -> String {
+    let bytes: Vec<u8> = (0..len).map(|_| rand::random::<u8>()).collect();
+    bs58::encode(bytes).into_string()
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+fn subscription_event(
+    chain: &dyn ChainClient,
+    owner: &str,
+    plan_id: u64,
+    signature: &str,
+) -> SubscriptionEventData {
+    let subscription = chain
+        .subscription_address(owner, plan_id)
+        .map(|pda| pda.to_string())
+        .unwrap_or_default();
+    SubscriptionEventData {
+        subscription,
+        owner: owner.to_string(),
+        plan_id,
+        signature: Some(signature.to_string()),
+        milestone: None,
+        expires_at: None,
+        refund_id: None,
+        refund_lamports: None,
+    }
+}
+
+// Mirror our own submissions right away so reads served from the index don't lag the poller
+async fn index_submission(indexer: &IndexerService, signature: &str) {
+    if let Err(e) = indexer.index_signature(signature).await {
+        tracing::warn!("Failed to index submitted transaction {}: {}", signature, e);
+    }
+}
+
 // Controllers
+#[utoipa::path(
+    get,
+    path = "/auth/challenge",
+    tag = "auth",
+    params(ChallengeQuery),
+    responses(
+        (status = 200, description = "Single-use sign-in challenge", body = ChallengeResponse),
+        (status = 422, description = "Invalid public key", body = ErrorResponse),
+    )
+)]
+#[get("/auth/challenge")]
+pub async fn auth_challenge(
+    auth_service: web::Data<AuthService>,
+    query: ValidatedQuery<ChallengeQuery>,
+) -> AppResult<HttpResponse> {
+    let challenge = auth_service.challenge(&query.public_key).await?;
+    Ok(HttpResponse::Ok().json(challenge))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth",
+    tag = "auth",
+    request_body = AuthRequest,
+    responses(
+        (status = 200, description = "Access and refresh tokens", body = AuthResponse),
+        (status = 401, description = "Invalid signature or challenge", body = ErrorResponse),
+        (status = 422, description = "Invalid request", body = ErrorResponse),
+    )
+)]
 #[post("/auth")]
 pub async fn authenticate(
+    http_req: actix_web::HttpRequest,
+    auth_service: web::Data<AuthService>,
+    auth_monitor: web::Data<AuthMonitor>,
+    req: ValidatedJson<AuthRequest>,
+) -> AppResult<HttpResponse> {
+    let req = req.into_inner();
+    let method = if req.message.is_some() { "siws" } else { "signature" };
+    let public_key = req.public_key.clone();
+    let result = auth_service.authenticate(req).await;
+    auth_monitor.record(method, Some(&public_key), auth_monitor.client_ip(&http_req).as_deref(), &result);
+    Ok(HttpResponse::Ok().json(result?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/refresh",
+    tag = "auth",
+    request_body = RefreshRequest,
+    responses(
+        (status = 200, description = "Rotated access and refresh tokens", body = AuthResponse),
+        (status = 401, description = "Refresh token invalid, expired or reused", body = ErrorResponse),
+    )
+)]
+#[post("/auth/refresh")]
+pub async fn refresh(
     auth_service: web::Data<AuthService>,
-    req: web::Json<AuthRequest>,
+    req: ValidatedJson<RefreshRequest>,
 ) -> AppResult<HttpResponse> {
-    let auth_response = auth_service.authenticate(req.into_inner()).await?;
+    let auth_response = auth_service.refresh(req.into_inner()).await?;
     Ok(HttpResponse::Ok().json(auth_response))
 }
 
+/// Revokes the presented access token and, if a body is sent, its refresh token.
+#[utoipa::path(
+    post,
+    path = "/auth/logout",
+    tag = "auth",
+    request_body = LogoutRequest,
+    responses(
+        (status = 204, description = "Tokens revoked"),
+        (status = 401, description = "Missing or invalid access token", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/auth/logout")]
+pub async fn logout(
+    req: actix_web::HttpRequest,
+    auth_service: web::Data<AuthService>,
+    body: Option<web::Json<LogoutRequest>>,
+) -> AppResult<HttpResponse> {
+    let token = req
+        .headers()
+        .get("Authorization")
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .ok_or(AppError::Auth("No token provided".to_string()))?;
+    let auth_token = auth_service.verify_token(token).await?;
+    auth_service
+        .logout(&auth_token, body.map(web::Json::into_inner).unwrap_or_default())
+        .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/subscriptions",
+    tag = "subscriptions",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Replays the first result for retries with the same key")),
+    request_body = SubscriptionRequest,
+    responses(
+        (status = 200, description = "Transaction submitted", body = SignatureResponse),
+        (status = 400, description = "Subscription already exists", body = ErrorResponse),
+        (status = 409, description = "Request with this Idempotency-Key, or another transaction of the wallet, still in progress", body = ErrorResponse),
+        (status = 422, description = "Invalid request", body = ErrorResponse),
+        (status = 502, description = "Transaction failed", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
 #[post("/subscriptions")]
 pub async fn create_subscription(
     req: actix_web::HttpRequest,
-    solana_service: web::Data<SolanaService>,
-    sub_req: web::Json<SubscriptionRequest>,
+    chains: web::Data<dyn ChainClients>,
+    submissions: web::Data<SubmissionService>,
+    sub_req: ValidatedJson<SubscriptionRequest>,
+) -> AppResult<HttpResponse> {
+    submit_wallet_action(&req, chains.get_ref(), &submissions, WalletAction::Create(sub_req.into_inner())).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/subscriptions",
+    tag = "subscriptions",
+    params(SubscriptionQuery),
+    responses(
+        (status = 200, description = "Subscriptions of every wallet of the authenticated wallet's account", body = [SubscriptionResponse]),
+        (status = 304, description = "Unchanged since the ETag in If-None-Match"),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/subscriptions")]
+pub async fn list_subscriptions(
+    req: actix_web::HttpRequest,
+    query: ValidatedQuery<SubscriptionQuery>,
+    chains: web::Data<dyn ChainClients>,
+    indexer: web::Data<IndexerService>,
+    plans: web::Data<PlanService>,
+    wallets: web::Data<WalletService>,
+    pool: web::Data<PgPool>,
 ) -> AppResult<HttpResponse> {
     let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
-    let signature = solana_service
-        .create_subscription(&auth_token.public_key, sub_req.into_inner())
-        .await?;
-    Ok(HttpResponse::Ok().json(serde_json::json!({ "signature": signature })))
+    let chain = chains.select(&req)?;
+    let owners = wallets.wallets(&auth_token.public_key).await?;
+    // Read from chain in batches: the indexed subscriptions, or every catalog plan of every
+    // wallet where there is no index
+    if !chain.is_primary() || query.commitment.is_some() || commitment::requested().is_some() {
+        let keys: Vec<(String, u64)> = if chain.is_primary() {
+            indexer
+                .list_subscriptions(&owners)
+                .await?
+                .into_iter()
+                .map(|sub| (sub.owner, sub.plan_id))
+                .collect()
+        } else {
+            let plan_ids = plans.plan_ids().await?;
+            plan_ids
+                .into_iter()
+                .flat_map(|plan_id| owners.iter().map(move |owner| (owner.clone(), plan_id)))
+                .collect()
+        };
+        let subs = chain
+            .fetch_subscriptions(&keys, chain.read_commitment(query.commitment))
+            .await?;
+        return Ok(HttpResponse::Ok().json(subs));
+    }
+    let validators = Validators::for_owner(&db::owner_subscriptions_version(&pool, &owners).await?);
+    if validators.is_fresh(&req) {
+        return Ok(validators.not_modified());
+    }
+    let subs = indexer.list_subscriptions(&owners).await?;
+    let mut response = HttpResponse::Ok();
+    validators.apply(&mut response);
+    Ok(response.json(subs))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/subscriptions/{plan_id}",
+    tag = "subscriptions",
+    params(("plan_id" = u64, Path, description = "Plan identifier"), SubscriptionQuery),
+    responses(
+        (status = 200, description = "Subscription state", body = SubscriptionResponse),
+        (status = 304, description = "Unchanged since the ETag in If-None-Match"),
+        (status = 502, description = "Subscription account not found on chain", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
 #[get("/subscriptions/{plan_id}")]
 pub async fn get_subscription(
     req: actix_web::HttpRequest,
     path: web::Path<u64>,
-    solana_service: web::Data<SolanaService>,
+    query: ValidatedQuery<SubscriptionQuery>,
+    chains: web::Data<dyn ChainClients>,
+    indexer: web::Data<IndexerService>,
+    cache: web::Data<CacheService>,
+    pool: web::Data<PgPool>,
 ) -> AppResult<HttpResponse> {
     let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let chain = chains.select(&req)?;
     let plan_id = path.into_inner();
-    let sub = solana_service.get_subscription(&auth_token.public_key, plan_id).await?;
-    Ok(HttpResponse::Ok().json(sub))
+    // Other clusters and tenants are not indexed, so read them straight from the chain
+    if !chain.is_primary() || query.commitment.is_some() || commitment::requested().is_some() {
+        let commitment = chain.read_commitment(query.commitment);
+        let sub = chain.fetch_subscription(&auth_token.public_key, plan_id, commitment).await?;
+        return Ok(HttpResponse::Ok().json(sub));
+    }
+
+    let pda = chain.subscription_address(&auth_token.public_key, plan_id)?;
+    // Only indexed, open accounts have validators; the rest are always fetched
+    let validators = db::find_subscription_version(&pool, &pda.to_string())
+        .await?
+        .filter(|version| !version.closed)
+        .map(|version| Validators::for_subscription(&version));
+    if let Some(validators) = validators.as_ref().filter(|v| v.is_fresh(&req)) {
+        return Ok(validators.not_modified());
+    }
+    let mut response = HttpResponse::Ok();
+    if let Some(validators) = &validators {
+        validators.apply(&mut response);
+    }
+
+    if let Some(sub) = cache.get_subscription(&pda).await {
+        return Ok(response.json(sub));
+    }
+
+    // Served from the index; fall back to RPC for accounts the indexer hasn't seen yet
+    let sub = match indexer.find_subscription(&auth_token.public_key, plan_id).await? {
+        Some(sub) => sub,
+        None => {
+            let commitment = chain.read_commitment(None);
+            chain.fetch_subscription(&auth_token.public_key, plan_id, commitment).await?
+        }
+    };
+    cache.put_subscription(&pda, &sub).await;
+    Ok(response.json(sub))
 }
 
+/// Renews an expired subscription.
+#[utoipa::path(
+    post,
+    path = "/api/v1/subscriptions/{plan_id}/renew",
+    tag = "subscriptions",
+    params(
+        ("plan_id" = u64, Path, description = "Plan identifier"),
+        ("Idempotency-Key" = Option<String>, Header, description = "Replays the first result for retries with the same key"),
+    ),
+    responses(
+        (status = 200, description = "Transaction submitted", body = SignatureResponse),
+        (status = 409, description = "Request with this Idempotency-Key, or another transaction of the wallet, still in progress", body = ErrorResponse),
+        (status = 502, description = "Transaction failed", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
 #[post("/subscriptions/{plan_id}/renew")]
 pub async fn renew_subscription(
     req: actix_web::HttpRequest,
     path: web::Path<u64>,
-    solana_service: web::Data<SolanaService>,
+    chains: web::Data<dyn ChainClients>,
+    submissions: web::Data<SubmissionService>,
 ) -> AppResult<HttpResponse> {
-    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
-    let plan_id = path.into_inner();
-    let signature = solana_service.renew_subscription(&auth_token.public_key, plan_id).await?;
-    Ok(HttpResponse::Ok().json(serde_json::json!({ "signature": signature })))
+    submit_wallet_action(&req, chains.get_ref(), &submissions, WalletAction::Renew(path.into_inner())).await
 }
 
+/// Cancels an active subscription.
+#[utoipa::path(
+    post,
+    path = "/api/v1/subscriptions/{plan_id}/cancel",
+    tag = "subscriptions",
+    params(
+        ("plan_id" = u64, Path, description = "Plan identifier"),
+        ("Idempotency-Key" = Option<String>, Header, description = "Replays the first result for retries with the same key"),
+    ),
+    responses(
+        (status = 200, description = "Transaction submitted", body = SignatureResponse),
+        (status = 409, description = "Request with this Idempotency-Key, or another transaction of the wallet, still in progress", body = ErrorResponse),
+        (status = 502, description = "Transaction failed", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
 #[post("/subscriptions/{plan_id}/cancel")]
 pub async fn cancel_subscription(
     req: actix_web::HttpRequest,
     path: web::Path<u64>,
-    solana_service: web::Data<SolanaService>,
+    chains: web::Data<dyn ChainClients>,
+    submissions: web::Data<SubmissionService>,
+) -> AppResult<HttpResponse> {
+    submit_wallet_action(&req, chains.get_ref(), &submissions, WalletAction::Cancel(path.into_inner())).await
+}
+
+async fn submit_wallet_action(
+    req: &actix_web::HttpRequest,
+    chains: &dyn ChainClients,
+    submissions: &SubmissionService,
+    action: WalletAction,
 ) -> AppResult<HttpResponse> {
     let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
-    let plan_id = path.into_inner();
-    let signature = solana_service.cancel_subscription(&auth_token.public_key, plan_id).await?;
-    Ok(HttpResponse::Ok().json(serde_json::json!({ "signature": signature })))
+    let chain = chains.select(req)?;
+    let idempotency_key = IdempotencyService::key(req)?;
+    let signature = match submissions
+        .submit(chain, &auth_token.public_key, &action, idempotency_key.as_deref())
+        .await?
+    {
+        Submitted::Sent(signature) => signature,
+        Submitted::Replayed(replay) => return Ok(replay.into_response()),
+    };
+    audit::attach_signatures(req, [&signature]);
+    Ok(HttpResponse::Ok().json(SignatureResponse { signature }))
 }
 
+/// Closes a cancelled subscription and reclaims rent.
+#[utoipa::path(
+    post,
+    path = "/api/v1/subscriptions/{plan_id}/close",
+    tag = "subscriptions",
+    params(("plan_id" = u64, Path, description = "Plan identifier")),
+    responses(
+        (status = 200, description = "Transaction submitted", body = SignatureResponse),
+        (status = 409, description = "Another transaction of the wallet still in progress", body = ErrorResponse),
+        (status = 502, description = "Transaction failed", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
 #[post("/subscriptions/{plan_id}/close")]
 pub async fn close_subscription(
     req: actix_web::HttpRequest,
     path: web::Path<u64>,
-    solana_service: web::Data<SolanaService>,
+    chains: web::Data<dyn ChainClients>,
+    indexer: web::Data<IndexerService>,
+    cache: web::Data<CacheService>,
 ) -> AppResult<HttpResponse> {
     let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let chain = chains.select(&req)?;
     let plan_id = path.into_inner();
-    let signature = solana_service.close_subscription(&auth_token.public_key, plan_id).await?;
-    Ok(HttpResponse::Ok().json(serde_json::json!({ "signature": signature })))
+    let pda = chain.subscription_address(&auth_token.public_key, plan_id)?;
+    let guard = chain.lock_wallet(&auth_token.public_key).await?;
+    let signature = chain.submit(chain.build_close_tx(&auth_token.public_key, plan_id)?).await?;
+    drop(guard);
+    audit::attach_signatures(&req, [&signature]);
+    if chain.is_primary() {
+        index_submission(&indexer, &signature).await;
+        cache.invalidate_subscription(&pda).await;
+    }
+    Ok(HttpResponse::Ok().json(SignatureResponse { signature }))
+}
+
+/// Subscription routes for the authenticated wallet, served under `/api/v1` and
+/// `/api/v1/tenants/{tenant}`.
+fn wallet_routes(cfg: &mut web::ServiceConfig, airdrop_enabled: bool) {
+    cfg.service(create_subscription)
+        .service(batch::batch_subscriptions)
+        .service(simulation::simulate_subscription)
+        .service(list_subscriptions)
+        .service(get_subscription)
+        .service(renew_subscription)
+        .service(cancel_subscription)
+        .service(close_subscription)
+        .service(intents::create_intent)
+        .service(intents::get_intent)
+        .service(intents::confirm_intent)
+        .service(coupons::validate_coupon)
+        .service(payments::list_onchain_payments)
+        .service(exports::export_subscription_payments)
+        .service(estimate::estimate_cost);
+    // Test-only faucet, left unregistered unless explicitly enabled off mainnet
+    if airdrop_enabled {
+        cfg.service(airdrop::request_airdrop);
+    }
 }
 
 // Main
 #[tokio::main(worker_threads = 4)]
 async fn main() -> std::io::Result<()> {
-    dotenv().ok();
-    env_logger::init();
+    reload::load_env();
+    telemetry::init();
+
+    // `backend seal-keypair <keypair.json> <sealed.json>` encrypts a keypair for
+    // SIGNER_KEYPAIR_PATH and exits
+    if std::env::args().nth(1).as_deref() == Some("seal-keypair") {
+        let (Some(input), Some(output)) = (std::env::args().nth(2), std::env::args().nth(3)) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Usage: backend seal-keypair <keypair.json> <sealed.json>",
+            ));
+        };
+        let pubkey = keystore::seal_keypair_file(&input, &output)
+            .map_err(std::io::Error::other)?;
+        info!("Sealed keypair {} to {}", pubkey, output);
+        return Ok(());
+    }
 
     let config = get_config();
+
+    // `backend migrate` applies the pending migrations and exits; `backend migrate --status`
+    // only reports them, failing while any are pending
+    let command: Vec<String> = std::env::args().skip(1).collect();
+    if command.first().map(String::as_str) == Some("migrate") {
+        let to_io = |e: AppError| std::io::Error::other(e.to_string());
+        let pool = db::open(&config).await.map_err(to_io)?;
+        match &command[1..] {
+            [] => {
+                let applied = schema::migrate(&pool).await.map_err(to_io)?;
+                let status = schema::status(&pool).await.map_err(to_io)?;
+                info!("Applied {} migrations, schema at version {}", applied.len(), status.version());
+            }
+            [flag] if flag == "--status" => {
+                let status = schema::status(&pool).await.map_err(to_io)?;
+                info!(
+                    "Schema at version {}, build expects {}: {} applied, pending {:?}, unknown {:?}, modified {:?}, failed {:?}",
+                    status.version(),
+                    status.expected,
+                    status.applied.len(),
+                    status.pending,
+                    status.unknown,
+                    status.modified,
+                    status.failed
+                );
+                schema::check(&pool).await.map_err(to_io)?;
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "Usage: backend migrate [--status]",
+                ))
+            }
+        }
+        pool.close().await;
+        telemetry::shutdown();
+        return Ok(());
+    }
+
     info!("Starting server at {}:{}", config.server_host, config.server_port);
 
-    let solana_service = SolanaService::new(&config);
-    let auth_service = AuthService::new(config.clone());
+    metrics::init();
+    circuit::init(&config);
+    reporting::init(&config);
+    // HTTP request counts and latency histograms, labelled by route pattern
+    let prometheus = PrometheusMetricsBuilder::new(metrics::NAMESPACE)
+        .registry(metrics::REGISTRY.clone())
+        .endpoint("/metrics")
+        .build()
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+    let pool = db::connect(&config)
+        .await
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+    let transaction_signer = signer::connect(&config.signer)
+        .await
+        .map_err(std::io::Error::other)?;
+    let custody = config.custody
+        .as_ref()
+        .map(|custody| CustodyService::new(custody, pool.clone()))
+        .transpose()
+        .map_err(std::io::Error::other)?;
+    let mock_chain = mock_chain::install(&config);
+    let clusters = SolanaClusters::new(&config, pool.clone(), transaction_signer.clone(), custody.clone());
+    deployment::verify(&clusters, config.deployment_check)
+        .await
+        .map_err(std::io::Error::other)?;
+    let solana_service = clusters.primary().clone();
+    let auth_service = AuthService::new(config.clone(), pool.clone());
+    let auth_monitor = AuthMonitor::new(&config);
+    let channel_service = ChannelService::new(&config, pool.clone());
+    let user_channels = UserChannelService::new(&config, pool.clone());
+    let webhook_service = WebhookService::new(&config, channel_service.clone(), user_channels.clone(), pool.clone());
+    let api_key_service = ApiKeyService::new(&config, pool.clone());
+    let cache = CacheService::new(&config).await;
+    let ip_limiter = RateLimiter::new(config.rate_limit_ip_per_minute, cache.connection());
+    let pubkey_limiter = RateLimiter::new(config.rate_limit_pubkey_per_minute, cache.connection());
+    let status_limiter = RateLimiter::new(config.status_rate_limit_per_minute, cache.connection()).namespaced("status");
+    let pay_limiter = RateLimiter::new(config.solana_pay_rate_limit_per_minute, cache.connection()).namespaced("pay");
+    let trust_forwarded = config.rate_limit_trust_forwarded;
+    let quotas = QuotaService::new(&config, cache.connection());
+    let airdrop_enabled = config.devnet_airdrop_enabled;
+    let custody_enabled = custody.is_some();
+    let prices = PriceFeed::new(&config);
+    let responses = ResponseCache::new(&config);
+    let indexer = IndexerService::new(
+        &config,
+        pool.clone(),
+        solana_service.rpc.clone(),
+        prices.clone(),
+        responses.clone(),
+        webhook_service.clone(),
+    );
+    let idempotency = IdempotencyService::new(&config, pool.clone());
+    let notifications = NotificationService::new(&config, pool.clone());
+    let submissions = SubmissionService::new(
+        idempotency.clone(),
+        webhook_service.clone(),
+        indexer.clone(),
+        cache.clone(),
+        notifications.clone(),
+    );
+    let analytics = AnalyticsService::new(pool.clone());
+    let exports = ExportService::new(&config, pool.clone());
+    let event_log = EventLogService::new(&config, pool.clone());
+    let airdrops = AirdropService::new(&config, pool.clone());
+    let refunds = RefundService::new(&config, pool.clone(), webhook_service.clone());
+    let treasury_withdrawals = TreasuryWithdrawalService::new(&config, pool.clone(), refunds.clone());
+    let coupons = CouponService::new(pool.clone());
+    let plans = PlanService::new(pool.clone());
+    let calendar = CalendarService::new(pool.clone());
+    let customers = CustomerService::new(&config, pool.clone());
+    let receipts = ReceiptService::new(&config, pool.clone(), plans.clone());
+    let statements = StatementService::new(&config, pool.clone(), plans.clone(), notifications.clone());
+    let receipts_enabled = receipts.is_some();
+    let wallets = WalletService::new(&config, pool.clone());
+    let solana_pay = SolanaPay::new(&config);
+    let action_cluster = clusters.primary().cluster;
+    let reloader = ConfigReloader::new(&config, clusters.clone(), refunds.clone(), notifications.clone());
+    let graphql_schema = graphql::build_schema(pool.clone(), analytics.clone(), webhook_service.clone());
+    let keeper = KeeperService::new(
+        &config,
+        solana_service.clone(),
+        indexer.clone(),
+        webhook_service.clone(),
+        notifications.clone(),
+        cache.clone(),
+        pool.clone(),
+    );
+
+    // `backend keeper --dry-run` runs one keeper pass that only reports what it would renew and
+    // expire, and exits
+    if command.first().map(String::as_str) == Some("keeper") {
+        if command[1..] != ["--dry-run"] {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Usage: backend keeper --dry-run"));
+        }
+        let report = keeper
+            .clone()
+            .with_dry_run(true)
+            .run_once()
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        info!(
+            "Keeper dry run complete: {} due, {} would be renewed, {} expired, {} renewals would fail",
+            report.scanned, report.renewed, report.expired, report.failed
+        );
+        telemetry::shutdown();
+        return Ok(());
+    }
 
-    HttpServer::new(move || {
+    // `backend backfill [--from-slot <slot>]` indexes the program's transactions missing from
+    // the index, back to its first or to `slot`, reconciles every indexed subscription with its
+    // account and exits. `backend repair <pda>` does the same for one subscription.
+    if let Some(name @ ("backfill" | "repair")) = command.first().map(String::as_str) {
+        let to_io = |e: AppError| std::io::Error::other(e.to_string());
+        let usage = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Usage: backend backfill [--from-slot <slot>] | backend repair <pda>",
+            )
+        };
+        let (count, discrepancies) = match (name, &command[1..]) {
+            ("repair", [pda]) => {
+                let pda = Pubkey::from_str(pda).map_err(|_| usage())?;
+                indexer.repair(&pda).await.map_err(to_io)?
+            }
+            ("backfill", args) => {
+                let count = match args {
+                    [] => indexer.backfill(true).await.map_err(to_io)?,
+                    [flag, slot] if flag == "--from-slot" => {
+                        let slot = slot.parse().map_err(|_| usage())?;
+                        indexer.backfill_from_slot(slot).await.map_err(to_io)?
+                    }
+                    _ => return Err(usage()),
+                };
+                let pdas = db::list_subscription_pdas(&pool).await.map_err(to_io)?;
+                (count, indexer.reconcile(&pdas).await.map_err(to_io)?)
+            }
+            _ => return Err(usage()),
+        };
+        for discrepancy in &discrepancies {
+            tracing::warn!("Repaired {}", discrepancy);
+        }
+        info!(
+            "{} complete: {} transactions indexed, {} discrepancies repaired",
+            name,
+            count,
+            discrepancies.len()
+        );
+        telemetry::shutdown();
+        return Ok(());
+    }
+    tokio::spawn(indexer.clone().run());
+    tokio::spawn(indexer.clone().run_finalizer());
+    match &mock_chain {
+        Some(chain) => {
+            tokio::spawn(mock_chain::run_listener(chain.clone(), indexer.clone(), config.primary_cluster().program_id));
+        }
+        None => {
+            tokio::spawn(listener::run(
+                indexer.clone(),
+                config.primary_cluster().ws_url.clone(),
+                config.primary_cluster().program_id,
+            ));
+        }
+    }
+    if let Err(e) = auth_service.load_jwt_rotations().await {
+        tracing::warn!("Failed to load rotated JWT signing keys: {}", e);
+    }
+    tokio::spawn(auth_service.clone().run_jwt_key_refresh());
+    if let Err(e) = webhook_service.refresh_dead_letters().await {
+        tracing::warn!("Failed to count webhook dead letters: {}", e);
+    }
+    tokio::spawn(webhook_service.clone().run_deliveries());
+    tokio::spawn(webhook_service.clone().run_cleanup());
+    tokio::spawn(idempotency.clone().run_cleanup());
+    tokio::spawn(exports.clone().run_cleanup());
+    event_sinks::spawn(&config.event_sinks, &config.event_sink_events, &webhook_service);
+    tokio::spawn(event_log.clone().run_recorder(webhook_service.subscribe_events()));
+    tokio::spawn(event_log.clone().run_cleanup());
+    tokio::spawn({
+        let customers = customers.clone();
+        async move {
+            match customers.seal_existing().await {
+                Ok(0) => {}
+                Ok(sealed) => tracing::info!("Encrypted the details of {} customers stored in the clear", sealed),
+                Err(e) => tracing::error!("Failed to encrypt stored customer details: {}", e),
+            }
+        }
+    });
+    // Disable on all but one replica so subscriptions are not cranked twice
+    if config.keeper_enabled {
+        tokio::spawn(keeper.clone().run());
+    }
+    tokio::spawn(ReminderService::new(&config, pool.clone(), webhook_service.clone(), notifications.clone()).run());
+    if config.reconciliation_enabled {
+        tokio::spawn(
+            ReconciliationService::new(&config, solana_service.clone(), pool.clone(), notifications.clone()).run(),
+        );
+    }
+    if config.statement_emails_enabled {
+        tokio::spawn(statements.clone().run());
+    }
+    tokio::spawn(reloader.clone().run());
+    tokio::spawn(nonces::fill_pools(clusters.clone()));
+    clusters.spawn_health_checks(Duration::from_secs(config.rpc_health_check_interval_secs));
+    clusters.spawn_account_promoters();
+    tokio::spawn(
+        JobWorker::new(
+            &config,
+            clusters.clone(),
+            indexer.clone(),
+            webhook_service.clone(),
+            notifications.clone(),
+            cache.clone(),
+            pool.clone(),
+        )
+        .run(),
+    );
+
+    let shutdown_timeout = Duration::from_secs(config.shutdown_timeout_secs);
+    let drain_webhooks = webhook_service.clone();
+    let drain_pool = pool.clone();
+    let cors_allowed_origins = reloader.cors_allowed_origins();
+    let hsts_max_age_secs = config.hsts_max_age_secs;
+    let content_security_policy = config.content_security_policy.clone();
+    let request_timeouts = config.request_timeouts.clone();
+    let heavy_routes = HeavyRouteLimiter::new(&config.heavy_routes);
+    let json_body_limit_bytes = config.json_body_limit_bytes;
+    let tls = match (&config.tls_cert_path, &config.tls_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let resolver = ReloadingCertResolver::new(cert_path, key_path)?;
+            tokio::spawn(resolver.clone().run(Duration::from_secs(config.tls_reload_interval_secs)));
+            Some(resolver)
+        }
+        (None, None) => None,
+        _ => panic!("TLS_CERT_PATH and TLS_KEY_PATH must be set together"),
+    };
+
+    // Stopped together with the HTTP server
+    let (grpc_shutdown, grpc_stopped) = tokio::sync::oneshot::channel::<()>();
+    let grpc_server = match config.grpc_port {
+        Some(port) => {
+            let addr = format!("{}:{}", config.server_host, port)
+                .parse()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid gRPC address: {}", e)))?;
+            let api = GrpcApi::new(
+                clusters.clone(),
+                auth_service.clone(),
+                api_key_service.clone(),
+                webhook_service.clone(),
+                indexer.clone(),
+                cache.clone(),
+                submissions.clone(),
+                plans.clone(),
+                pubkey_limiter.clone(),
+                quotas.clone(),
+                pool.clone(),
+            );
+            Some(tokio::spawn(grpc::serve(addr, api, async {
+                let _ = grpc_stopped.await;
+            })))
+        }
+        None => None,
+    };
+
+    // On SIGTERM/SIGINT actix stops accepting connections and lets in-flight requests, including
+    // transaction submissions awaiting confirmation, finish within the shutdown timeout
+    let server = HttpServer::new(move || {
+        // Browsers only get CORS access from the configured origins, as last reloaded, except to
+        // Solana Pay and Actions, which wallets and Blink clients call from their own origin
+        let origins = cors_allowed_origins.clone();
         let cors = Cors::default()
-            .allow_any_origin()
-            .allow_any_method()
-            .allow_any_header()
+            .allowed_origin_fn(move |origin, head| {
+                let path = head.uri.path();
+                path.starts_with("/pay/")
+                    || path.starts_with("/actions")
+                    || origins.read().unwrap().iter().any(|allowed| allowed.as_bytes() == origin.as_bytes())
+            })
+            .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
+            .allowed_headers(vec![
+                actix_web::http::header::AUTHORIZATION,
+                actix_web::http::header::CONTENT_TYPE,
+                actix_web::http::header::ACCEPT,
+                actix_web::http::header::ACCEPT_ENCODING,
+                actix_web::http::header::CONTENT_ENCODING,
+            ])
+            .allowed_header(idempotency::IDEMPOTENCY_HEADER)
+            .allowed_header(cluster::CLUSTER_HEADER)
+            .allowed_header(commitment::COMMITMENT_HEADER)
+            .expose_headers(vec![
+                telemetry::REQUEST_ID_HEADER,
+                idempotency::REPLAYED_HEADER,
+                versioning::API_VERSION_HEADER,
+                "x-ratelimit-limit",
+                "x-ratelimit-remaining",
+                "x-ratelimit-reset",
+                "retry-after",
+                "x-action-version",
+                "x-blockchain-ids",
+            ])
             .max_age(3600);
 
         App::new()
-            .wrap(Logger::default())
+            .wrap_fn(commitment::scope_commitment)
+            .wrap(Timeout::new(request_timeouts.clone()))
+            .wrap(ConcurrencyLimit::new(heavy_routes.clone()))
+            .wrap_fn(versioning::route_version)
+            .wrap_fn(reporting::capture_server_errors)
+            // gzip/brotli, negotiated from Accept-Encoding
+            .wrap(Compress::default())
+            .wrap(RateLimit::per_ip(ip_limiter.clone(), trust_forwarded))
+            .wrap(prometheus.clone())
+            .wrap_fn(telemetry::scope_request_id)
+            .wrap(TracingLogger::default())
             .wrap(cors)
+            .wrap(SecurityHeaders::new(hsts_max_age_secs, &content_security_policy))
             .app_data(Data::new(auth_service.clone()))
+            .app_data(Data::new(auth_monitor.clone()))
             .app_data(Data::new(solana_service.clone()))
+            .app_data(Data::new(clusters.clone()))
+            .app_data(Data::from(Arc::new(clusters.clone()) as Arc<dyn ChainClients>))
+            .app_data(Data::new(webhook_service.clone()))
+            .app_data(Data::new(indexer.clone()))
+            .app_data(Data::new(cache.clone()))
+            .app_data(Data::new(responses.clone()))
+            .app_data(Data::new(api_key_service.clone()))
+            .app_data(Data::new(idempotency.clone()))
+            .app_data(Data::new(submissions.clone()))
+            .app_data(Data::new(keeper.clone()))
+            .app_data(Data::new(notifications.clone()))
+            .app_data(Data::new(channel_service.clone()))
+            .app_data(Data::new(user_channels.clone()))
+            .app_data(Data::new(analytics.clone()))
+            .app_data(Data::new(graphql_schema.clone()))
+            .app_data(Data::new(exports.clone()))
+            .app_data(Data::new(event_log.clone()))
+            .app_data(Data::new(statements.clone()))
+            .app_data(Data::new(prices.clone()))
+            .app_data(Data::new(airdrops.clone()))
+            .app_data(Data::new(quotas.clone()))
+            .app_data(Data::new(refunds.clone()))
+            .app_data(Data::new(treasury_withdrawals.clone()))
+            .app_data(Data::new(coupons.clone()))
+            .app_data(Data::new(plans.clone()))
+            .app_data(Data::new(calendar.clone()))
+            .app_data(Data::new(customers.clone()))
+            .app_data(Data::new(wallets.clone()))
+            .app_data(Data::new(reloader.clone()))
+            .app_data(Data::from(transaction_signer.clone()))
+            .app_data(Data::new(pool.clone()))
+            .app_data(
+                web::JsonConfig::default()
+                    .limit(json_body_limit_bytes)
+                    .error_handler(validation::json_error_handler),
+            )
+            .app_data(web::PayloadConfig::new(json_body_limit_bytes))
+            .app_data(web::QueryConfig::default().error_handler(validation::query_error_handler))
+            .app_data(web::PathConfig::default().error_handler(validation::path_error_handler))
+            .service(health::healthz)
+            .service(health::readyz)
+            .service(openapi::openapi_json)
+            .service(jwks::jwks)
+            .service(auth_challenge)
             .service(authenticate)
+            .service(refresh)
+            .service(logout)
+            .configure(|cfg| custody::auth_routes(cfg, custody.as_ref()))
+            .service(
+                web::scope("/status")
+                    .wrap(RateLimit::per_ip(status_limiter.clone(), trust_forwarded))
+                    .service(status::membership_status),
+            )
+            .configure(|cfg| {
+                if let Some(solana_pay) = &solana_pay {
+                    cfg.service(
+                        web::scope("/pay")
+                            .app_data(Data::new(solana_pay.clone()))
+                            .wrap(RateLimit::per_ip(pay_limiter.clone(), trust_forwarded))
+                            .service(solana_pay::pay_label)
+                            .service(solana_pay::pay_transaction),
+                    );
+                    cfg.service(actions::actions_manifest).service(
+                        web::scope("/actions")
+                            .app_data(Data::new(solana_pay.clone()))
+                            .wrap(actions::headers(action_cluster))
+                            .wrap(RateLimit::per_ip(pay_limiter.clone(), trust_forwarded))
+                            .service(actions::subscribe_action)
+                            .service(actions::subscribe_transaction),
+                    );
+                }
+            })
+            .configure(|cfg| {
+                if let Some(chain) = &mock_chain {
+                    cfg.service(
+                        web::scope("/mock")
+                            .app_data(Data::new(chain.clone()))
+                            .service(mock_chain::mock_rpc),
+                    );
+                }
+            })
+            // Calendar apps cannot send a bearer token; the feed checks its own
+            .service(calendar::calendar_feed)
+            // Checkout pages list plans before the buyer has signed in
+            .service(plans::plan_catalog)
+            .configure(|cfg| receipts::public_routes(cfg, receipts.as_ref()))
+            // The wallet routes again, acting for the tenant named in the path
+            .service(
+                web::scope("/api/v1/tenants/{tenant}")
+                    .wrap(AuditLog::new(pool.clone(), trust_forwarded))
+                    .wrap(Quota::new(quotas.clone()))
+                    .wrap(RateLimit::per_public_key(pubkey_limiter.clone()))
+                    .wrap(Authentication::new(auth_service.clone()))
+                    .configure(|cfg| wallet_routes(cfg, airdrop_enabled)),
+            )
             .service(
-                web::scope("/api")
+                web::scope("/api/v1")
+                    .wrap(AuditLog::new(pool.clone(), trust_forwarded))
+                    .wrap(Quota::new(quotas.clone()))
+                    .wrap(RateLimit::per_public_key(pubkey_limiter.clone()))
                     .wrap(Authentication::new(auth_service.clone()))
-                    .service(create_subscription)
-                    .service(get_subscription)
-                    .service(renew_subscription)
-                    .service(cancel_subscription)
-                    .service(close_subscription)
+                    .configure(|cfg| wallet_routes(cfg, airdrop_enabled))
+                    .service(jobs::get_job)
+                    .service(keeper::set_auto_renew)
+                    .service(notifications::get_preferences)
+                    .service(notifications::update_preferences)
+                    .service(user_channels::create_user_channel)
+                    .service(user_channels::list_user_channels)
+                    .service(user_channels::delete_user_channel)
+                    .service(calendar::create_calendar_token)
+                    .service(calendar::revoke_calendar_token)
+                    .service(wallets::link_challenge)
+                    .service(wallets::link_wallet)
+                    .service(wallets::list_wallets)
+                    .service(wallets::unlink_wallet)
+                    .configure(|cfg| receipts::wallet_routes(cfg, receipts_enabled))
+                    .configure(|cfg| custody::wallet_routes(cfg, custody_enabled))
+                    .service(quota::get_usage)
+                    .service(
+                        web::scope("/webhooks")
+                            .wrap(RequireRole::new(Role::Merchant))
+                            .service(webhook_versions::list_webhook_schemas)
+                            .service(webhook_versions::get_webhook_schema)
+                            .service(webhooks::register_webhook)
+                            .service(webhooks::list_webhooks)
+                            .service(webhooks::list_deliveries)
+                            .service(webhooks::replay_delivery)
+                            .service(webhooks::delete_webhook)
+                            .service(webhooks::rotate_webhook_secret)
+                            .service(webhooks::pin_webhook_version)
+                            .service(webhooks::set_webhook_filter)
+                            .service(webhooks::list_webhook_deliveries),
+                    )
+                    .service(
+                        web::scope("/customers")
+                            .wrap(RequireRole::new(Role::Merchant))
+                            .service(customers::list_customers)
+                            .service(customers::get_customer)
+                            .service(customers::upsert_customer)
+                            .service(customers::delete_customer),
+                    )
+                    .service(
+                        web::scope("/analytics")
+                            .wrap(RequireRole::new(Role::Merchant))
+                            .service(analytics::mrr)
+                            .service(analytics::churn)
+                            .service(analytics::subscribers)
+                            .service(analytics::revenue),
+                    )
+                    .service(
+                        web::scope("/graphql")
+                            .wrap(RequireRole::new(Role::Merchant))
+                            .service(graphql::graphql)
+                            .service(graphql::graphql_ws),
+                    )
+                    .service(
+                        web::scope("/exports")
+                            .wrap(RequireRole::new(Role::Merchant))
+                            .service(exports::create_export)
+                            .service(exports::list_exports)
+                            .service(exports::get_export)
+                            .service(exports::download_export),
+                    )
+                    .service(
+                        web::scope("/events")
+                            .wrap(RequireRole::new(Role::Merchant))
+                            .service(events::poll_events),
+                    )
+                    .service(
+                        web::scope("/statements")
+                            .wrap(RequireRole::new(Role::Merchant))
+                            .service(statements::get_statement),
+                    )
+                    .service(
+                        web::scope("/plans")
+                            .wrap(RequireRole::new(Role::Merchant))
+                            .service(plans::create_plan)
+                            .service(plans::list_plans)
+                            .service(plans::get_plan)
+                            .service(plans::update_plan),
+                    )
+                    .service(
+                        web::scope("/coupons")
+                            .wrap(RequireRole::new(Role::Merchant))
+                            .service(coupons::create_coupon)
+                            .service(coupons::list_coupons)
+                            .service(coupons::get_coupon)
+                            .service(coupons::disable_coupon),
+                    )
+                    .service(
+                        web::scope("/payments")
+                            .wrap(RequireRole::new(Role::Merchant))
+                            .service(payments::get_payment)
+                            .service(refunds::request_refund),
+                    )
+                    .service(
+                        web::scope("/refunds")
+                            .wrap(RequireRole::new(Role::Merchant))
+                            .service(refunds::list_refunds)
+                            .service(refunds::get_refund),
+                    )
+                    .service(
+                        web::scope("/admin")
+                            .wrap(RequireRole::new(Role::Admin))
+                            .service(api_keys::create_api_key)
+                            .service(api_keys::list_api_keys)
+                            .service(api_keys::rotate_api_key)
+                            .service(api_keys::revoke_api_key)
+                            .service(audit::list_audit_entries)
+                            .service(keeper::list_keeper_runs)
+                            .service(webhooks::list_dead_letters)
+                            .service(webhooks::requeue_dead_letter)
+                            .service(treasury::treasury_summary)
+                            .service(treasury::list_treasury_inflows)
+                            .service(treasury_withdrawals::request_treasury_withdrawal)
+                            .service(treasury_withdrawals::list_treasury_withdrawals)
+                            .service(treasury_withdrawals::get_treasury_withdrawal)
+                            .service(treasury_withdrawals::approve_treasury_withdrawal)
+                            .service(treasury_withdrawals::execute_treasury_withdrawal)
+                            .service(treasury_withdrawals::reject_treasury_withdrawal)
+                            .service(refunds::approve_refund)
+                            .service(refunds::reject_refund)
+                            .service(reload::reload_config)
+                            .service(jwks::rotate_jwt_key)
+                            .service(keystore::signer_status)
+                            .service(keystore::unseal_signer)
+                            .service(channels::create_channel)
+                            .service(channels::list_channels)
+                            .service(channels::delete_channel),
+                    )
             )
+            // Server-to-server routes for merchants, authenticated with API keys
+            .service(
+                web::scope("/merchant")
+                    .wrap(AuditLog::new(pool.clone(), trust_forwarded))
+                    .wrap(Quota::new(quotas.clone()))
+                    .wrap(RateLimit::per_public_key(pubkey_limiter.clone()))
+                    .wrap(ApiKeyAuthentication::new(api_key_service.clone()))
+                    .service(
+                        web::scope("")
+                            .wrap(RequireRole::new(Role::Merchant))
+                            .service(merchant::list_subscribers)
+                            .service(web::scope("/events").service(events::poll_events))
+                            .service(
+                                web::scope("/customers")
+                                    .service(customers::list_customers)
+                                    .service(customers::get_customer)
+                                    .service(customers::upsert_customer)
+                                    .service(customers::delete_customer),
+                            )
+                            .service(
+                                web::scope("/webhooks")
+                                    .service(webhook_versions::list_webhook_schemas)
+                                    .service(webhook_versions::get_webhook_schema)
+                                    .service(webhooks::register_webhook)
+                                    .service(webhooks::list_webhooks)
+                                    .service(webhooks::list_deliveries)
+                                    .service(webhooks::replay_delivery)
+                                    .service(webhooks::delete_webhook)
+                                    .service(webhooks::rotate_webhook_secret)
+                                    .service(webhooks::pin_webhook_version)
+                                    .service(webhooks::set_webhook_filter)
+                                    .service(webhooks::list_webhook_deliveries),
+                            ),
+                    )
+            )
+    })
+    .shutdown_timeout(config.shutdown_timeout_secs)
+    // Slow or idle clients are dropped instead of holding a connection open
+    .keep_alive(match config.keep_alive_secs {
+        0 => KeepAlive::Disabled,
+        secs => KeepAlive::Timeout(Duration::from_secs(secs)),
     })
-    .bind((config.server_host, config.server_port))?
-    .run()
-    .await
-}
\ No newline at end of file
+    .client_request_timeout(Duration::from_millis(config.client_request_timeout_ms))
+    .client_disconnect_timeout(Duration::from_millis(config.client_disconnect_timeout_ms))
+    .max_connections(config.max_connections);
+    let server = match tls {
+        Some(resolver) => {
+            info!("Serving HTTPS");
+            server.bind_rustls_021((config.server_host, config.server_port), resolver.server_config())?
+        }
+        None => server.bind((config.server_host, config.server_port))?,
+    };
+    server.run().await?;
+    let _ = grpc_shutdown.send(());
+    if let Some(grpc_server) = grpc_server {
+        let _ = grpc_server.await;
+    }
+
+    info!("HTTP server stopped, draining background work");
+    // Submissions cut off by the timeout stay in transaction_jobs for the worker on next start
+    drain_webhooks.drain(shutdown_timeout).await;
+    drain_pool.close().await;
+    info!("Shutdown complete");
+    telemetry::shutdown();
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{dev::ServiceResponse, http::StatusCode, test};
+    use chain::mock::{MockChainClient, MockChainClients};
+    use channels::ChannelService;
+    use once_cell::sync::Lazy;
+    use serde_json::json;
+    use solana_sdk::commitment_config::CommitmentConfig;
+    use solana_sdk::signature::{Keypair, Signer};
+    use sqlx::postgres::PgPoolOptions;
+
+    // The defaults of `get_config` on the mock chain, without reading `.env` or setting
+    // anything in the environment; the handlers under test never reach the database
+    static CONFIG: Lazy<Config> = Lazy::new(|| {
+        let treasury = Pubkey::from_str("4wa7saJG78PMAzfCaXEBMR4jtPV5SGhYwewkqHMLTEqo").unwrap();
+        Config {
+            server_host: "127.0.0.1".to_string(),
+            server_port: 8080,
+            tls_cert_path: None,
+            tls_key_path: None,
+            tls_reload_interval_secs: 60,
+            grpc_port: None,
+            cluster: Cluster::Devnet,
+            clusters: mock_chain::clusters(Cluster::Devnet),
+            mock_chain: true,
+            mock_chain_balance_lamports: 10_000_000_000,
+            allow_cluster_override: false,
+            deployment_check: DeploymentCheck::Enforce,
+            rpc_health_check_interval_secs: 15,
+            shutdown_timeout_secs: 60,
+            idempotency_ttl_secs: 86400,
+            job_max_attempts: 3,
+            job_retry_backoff_secs: 10,
+            nonce_pool_size: 0,
+            nonce_max_per_user: 2,
+            rpc_breaker_threshold: 5,
+            rpc_breaker_cooldown_secs: 30,
+            rpc_max_concurrency: 32,
+            wallet_lock_timeout_secs: 30,
+            jwt_signing_keys: vec![("test".to_string(), [7; 32])],
+            jwt_active_kid: None,
+            jwt_issuer: "subscription-manager".to_string(),
+            jwt_audience: "subscription-manager-api".to_string(),
+            receipt_signing_key: None,
+            treasury,
+            tenants: tenant::load_tenants(treasury, Cluster::Devnet),
+            signer: SignerConfig::Local { keypair_path: None, private_key: None },
+            custody: None,
+            commitments: CommitmentPolicy {
+                read: Commitment::Confirmed,
+                check: Commitment::Confirmed,
+                confirm: Commitment::Finalized,
+            },
+            refund_private_key: None,
+            treasury_withdrawal_approvals: 2,
+            treasury_withdrawal_timelock_secs: 86400,
+            min_duration_secs: 60,
+            max_duration_secs: 31536000,
+            min_amount_lamports: 1000,
+            max_amount_lamports: 100_000_000_000,
+            webhook_max_attempts: 6,
+            webhook_backoff_base_secs: 2,
+            webhook_dead_letter_alert_threshold: 10,
+            webhook_delivery_retention_secs: 30 * 86400,
+            event_sinks: Vec::new(),
+            event_sink_events: WebhookEventType::ALL.to_vec(),
+            event_log_retention_secs: 7 * 86400,
+            event_poll_max_wait_secs: 25,
+            database_url: mock_chain::DATABASE_URL.to_string(),
+            database_max_connections: 10,
+            database_auto_migrate: true,
+            indexer_poll_interval_secs: 60,
+            keeper_enabled: true,
+            keeper_interval_secs: 60,
+            keeper_dry_run: false,
+            keeper_concurrency: 4,
+            keeper_batch_size: 100,
+            reminder_interval_secs: 60,
+            reconciliation_enabled: true,
+            reconciliation_threshold_lamports: 0,
+            reconciliation_alert_email: None,
+            reconciliation_webhook_url: None,
+            reconciliation_webhook_secret: None,
+            auth_anomaly_threshold: 10,
+            auth_anomaly_window_secs: 300,
+            auth_alert_webhook_url: None,
+            auth_alert_webhook_secret: None,
+            push_api_url: "https://exp.host/--/api/v2/push/send".to_string(),
+            push_access_token: None,
+            smtp_url: None,
+            email_from: "Subscriptions <no-reply@localhost>".to_string(),
+            export_retention_secs: 7 * 86400,
+            statement_emails_enabled: true,
+            platform_fee_bps: 0,
+            price_feed_url: None,
+            price_cache_secs: 60,
+            token_price_feed_url: None,
+            devnet_airdrop_enabled: false,
+            airdrop_lamports: 1_000_000_000,
+            airdrop_daily_cap_lamports: 2_000_000_000,
+            cors_allowed_origins: Vec::new(),
+            hsts_max_age_secs: 31536000,
+            content_security_policy: "default-src 'none'; frame-ancestors 'none'".to_string(),
+            error_reporting_enabled: true,
+            sentry_dsn: None,
+            error_report_url: None,
+            error_report_environment: "production".to_string(),
+            redis_url: None,
+            cache_ttl_secs: 15,
+            account_cache_ttl_ms: 2000,
+            account_cache_finalized_ttl_ms: 30000,
+            account_cache_capacity: 10000,
+            response_cache_fresh_secs: 30,
+            response_cache_stale_secs: 300,
+            response_cache_capacity: 10000,
+            rate_limit_ip_per_minute: 120,
+            rate_limit_pubkey_per_minute: 60,
+            status_rate_limit_per_minute: 30,
+            solana_pay_label: "Subscription Manager".to_string(),
+            solana_pay_icon_url: None,
+            solana_pay_rate_limit_per_minute: 60,
+            request_timeouts: timeouts::load_request_timeouts(),
+            heavy_routes: concurrency::load_heavy_routes(),
+            json_body_limit_bytes: 262_144,
+            keep_alive_secs: 5,
+            client_request_timeout_ms: 5000,
+            client_disconnect_timeout_ms: 1000,
+            max_connections: 25_000,
+            rate_limit_trust_forwarded: false,
+            quotas: quota::load_quotas(),
+            access_token_ttl_secs: 900,
+            refresh_token_ttl_secs: 30 * 86400,
+            auth_challenge_ttl_secs: 300,
+            auth_replay_ttl_secs: 86400,
+            siws_domain: "localhost:8080".to_string(),
+            siws_statement: "Sign in to Subscription Manager".to_string(),
+            admin_pubkeys: Vec::new(),
+            merchant_pubkeys: Vec::new(),
+        }
+    });
+
+    // A non-primary client, so handlers skip the index, cache and webhooks
+    fn chains() -> Arc<MockChainClients> {
+        Arc::new(MockChainClients(MockChainClient::new(false)))
+    }
+
+    fn token(owner: &Keypair) -> AuthToken {
+        AuthToken {
+            public_key: owner.pubkey().to_string(),
+            credential: Credential::Jwt { jti: "test".to_string(), exp: u64::MAX },
+            roles: vec![Role::User],
+            tenant: None,
+        }
+    }
+
+    fn subscription(owner: &Keypair, plan_id: u64) -> SubscriptionResponse {
+        SubscriptionResponse {
+            id: String::new(),
+            plan_id,
+            duration: 60,
+            amount: 10_000_000,
+            active: true,
+            start_time: 0,
+            history: vec![0],
+            owner: owner.pubkey().to_string(),
+        }
+    }
+
+    /// Sends `req` to the wallet routes, authenticated as `owner` when given.
+    async fn send(chains: &Arc<MockChainClients>, owner: Option<&Keypair>, req: test::TestRequest) -> ServiceResponse {
+        let config = &*CONFIG;
+        let pool = PgPoolOptions::new().connect_lazy(&config.database_url).unwrap();
+        let webhook_service = WebhookService::new(
+            config,
+            ChannelService::new(config, pool.clone()),
+            UserChannelService::new(config, pool.clone()),
+            pool.clone(),
+        );
+        let rpc = RpcPool::new(config.cluster, &config.primary_cluster().rpc_urls, CommitmentConfig::default());
+        let indexer = IndexerService::new(
+            config,
+            pool.clone(),
+            rpc,
+            PriceFeed::new(config),
+            ResponseCache::new(config),
+            webhook_service.clone(),
+        );
+        let cache = CacheService::new(config).await;
+        let submissions = SubmissionService::new(
+            IdempotencyService::new(config, pool.clone()),
+            webhook_service,
+            indexer.clone(),
+            cache.clone(),
+            NotificationService::new(config, pool.clone()),
+        );
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(chains.clone() as Arc<dyn ChainClients>))
+                .app_data(Data::new(submissions))
+                .app_data(Data::new(indexer))
+                .app_data(Data::new(cache))
+                .app_data(Data::new(pool))
+                .configure(|cfg| wallet_routes(cfg, false)),
+        )
+        .await;
+        let req = req.to_request();
+        if let Some(owner) = owner {
+            req.extensions_mut().insert(token(owner));
+        }
+        test::call_service(&app, req).await
+    }
+
+    fn create(plan_id: u64) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/subscriptions")
+            .set_json(json!({"plan_id": plan_id, "duration": 60, "amount": 10_000_000}))
+    }
+
+    fn post(path: &str) -> test::TestRequest {
+        test::TestRequest::post().uri(path)
+    }
+
+    #[actix_web::test]
+    async fn create_submits_and_the_subscription_reads_back() {
+        let chains = chains();
+        let owner = Keypair::new();
+
+        let res = send(&chains, Some(&owner), create(7)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert!(body["signature"].is_string());
+        assert_eq!(chains.0.submitted(), vec![("create_subscription", owner.pubkey().to_string(), 7)]);
+
+        let res = send(&chains, Some(&owner), test::TestRequest::get().uri("/subscriptions/7")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let sub: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(sub["owner"], owner.pubkey().to_string());
+        assert_eq!(sub["active"], true);
+    }
+
+    #[actix_web::test]
+    async fn create_of_an_existing_subscription_is_refused_before_submitting() {
+        let chains = chains();
+        let owner = Keypair::new();
+        chains.0.insert(subscription(&owner, 7));
+
+        let res = send(&chains, Some(&owner), create(7)).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(chains.0.submitted().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn invalid_terms_are_rejected_with_field_errors() {
+        let chains = chains();
+        let owner = Keypair::new();
+        let req = test::TestRequest::post()
+            .uri("/subscriptions")
+            .set_json(json!({"plan_id": 7, "duration": 1, "amount": 10_000_000}));
+
+        let res = send(&chains, Some(&owner), req).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["errors"][0]["field"], "duration");
+        assert!(chains.0.submitted().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn renew_starts_a_new_period() {
+        let chains = chains();
+        let owner = Keypair::new();
+        chains.0.insert(subscription(&owner, 7));
+        let before = unix_now();
+
+        let res = send(&chains, Some(&owner), post("/subscriptions/7/renew")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let sub = chains.0.fetch_subscription(&owner.pubkey().to_string(), 7, Commitment::Confirmed).await.unwrap();
+        assert!(sub.start_time >= before);
+        assert_eq!(sub.history, vec![0, sub.start_time]);
+    }
+
+    #[actix_web::test]
+    async fn cancel_then_close_removes_the_subscription() {
+        let chains = chains();
+        let owner = Keypair::new();
+        chains.0.insert(subscription(&owner, 7));
+
+        let res = send(&chains, Some(&owner), post("/subscriptions/7/cancel")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let sub = chains.0.fetch_subscription(&owner.pubkey().to_string(), 7, Commitment::Confirmed).await.unwrap();
+        assert!(!sub.active);
+
+        let res = send(&chains, Some(&owner), post("/subscriptions/7/close")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = send(&chains, Some(&owner), test::TestRequest::get().uri("/subscriptions/7")).await;
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[actix_web::test]
+    async fn failed_submission_is_reported_as_a_gateway_error() {
+        let chains = chains();
+        let owner = Keypair::new();
+        chains.0.insert(subscription(&owner, 7));
+        chains.0.fail_next(AppError::SolanaError("NotYetExpired".to_string()));
+
+        let res = send(&chains, Some(&owner), post("/subscriptions/7/renew")).await;
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert!(body["message"].as_str().unwrap().contains("NotYetExpired"));
+        assert!(chains.0.submitted().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn renew_before_the_period_ends_is_refused_by_the_program() {
+        let chains = chains();
+        let owner = Keypair::new();
+        chains.0.insert(SubscriptionResponse { start_time: unix_now(), ..subscription(&owner, 7) });
+
+        let res = send(&chains, Some(&owner), post("/subscriptions/7/renew")).await;
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert!(body["message"].as_str().unwrap().contains("NotYetExpired"));
+        assert!(chains.0.submitted().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn renew_of_a_missing_subscription_fails() {
+        let chains = chains();
+        let owner = Keypair::new();
+
+        let res = send(&chains, Some(&owner), post("/subscriptions/7/renew")).await;
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[actix_web::test]
+    async fn requests_without_a_token_are_unauthorized() {
+        let chains = chains();
+
+        let res = send(&chains, None, create(7)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert!(chains.0.submitted().is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+use actix_web::{get, web, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use crate::accounts::Commitment;
+use crate::cluster::SolanaClusters;
+use crate::customers::{CustomerContact, CustomerService};
+use crate::indexer::IndexerService;
+use crate::plans::PlanService;
+use crate::{AppError, AppResult, AuthToken, SubscriptionResponse};
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams)]
+pub struct SubscribersQuery {
+    plan_id: Option<u64>,
+    active: Option<bool>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    commitment: Option<Commitment>, // Re-read the page from chain at this commitment
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct SubscriberResponse {
+    #[serde(flatten)]
+    subscription: SubscriptionResponse,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    customer: Option<CustomerContact>, // The plan merchant's directory entry for the wallet
+}
+
+async fn with_customers(customers: &CustomerService, subscriptions: Vec<SubscriptionResponse>) -> AppResult<Vec<SubscriberResponse>> {
+    let keys: Vec<(String, i64)> = subscriptions.iter().map(|sub| (sub.owner.clone(), sub.plan_id as i64)).collect();
+    let mut contacts = customers.contacts(&keys).await?;
+    Ok(subscriptions
+        .into_iter()
+        .map(|subscription| {
+            let customer = contacts.remove(&(subscription.owner.clone(), subscription.plan_id as i64));
+            SubscriberResponse { subscription, customer }
+        })
+        .collect())
+}
+
+// Controllers
+/// Subscribers of the merchant's plans, or of every plan for admin keys, with the plan
+/// merchant's customer directory entry where there is one.
+#[utoipa::path(
+    get,
+    path = "/merchant/subscribers",
+    tag = "merchant",
+    params(SubscribersQuery),
+    responses(
+        (status = 200, description = "Indexed subscriptions, newest first", body = [SubscriberResponse]),
+        (status = 403, description = "Plan belongs to another merchant", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid API key", body = ErrorResponse),
+    ),
+    security(("api_key" = []))
+)]
+#[get("/subscribers")]
+pub async fn list_subscribers(
+    req: HttpRequest,
+    query: web::Query<SubscribersQuery>,
+    indexer: web::Data<IndexerService>,
+    clusters: web::Data<SolanaClusters>,
+    plans: web::Data<PlanService>,
+    customers: web::Data<CustomerService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let plan_ids = plans.scope(&auth_token).await?.narrow(query.plan_id)?;
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let subscribers = indexer
+        .list_subscribers(plan_ids.as_deref(), query.active, limit, offset)
+        .await?;
+    let subscribers = match query.commitment {
+        Some(commitment) => {
+            // Accounts closed since they were indexed drop out of the page
+            let keys: Vec<(String, u64)> = subscribers.into_iter().map(|sub| (sub.owner, sub.plan_id)).collect();
+            clusters.primary().get_subscriptions(&keys, commitment).await?
+        }
+        None => subscribers,
+    };
+    Ok(HttpResponse::Ok().json(with_customers(&customers, subscribers).await?))
+}
@@ -0,0 +1,352 @@
+use once_cell::sync::Lazy;
+use prometheus::{
+    core::Collector, Gauge, GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry,
+};
+use solana_client::client_error::Result as ClientResult;
+use std::future::Future;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::Instrument;
+use crate::circuit::{self, State};
+use crate::telemetry;
+
+pub const NAMESPACE: &str = "subscription_manager";
+
+/// Shared by the custom collectors below and the HTTP middleware, served at `/metrics`.
+pub static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+static RPC_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("solana_rpc_requests_total", "Solana RPC calls by method and outcome").namespace(NAMESPACE),
+        &["method", "outcome"],
+    ))
+});
+
+static RPC_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new("solana_rpc_duration_seconds", "Solana RPC call latency by method")
+            .namespace(NAMESPACE)
+            .buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]),
+        &["method"],
+    ))
+});
+
+static TENANT_RPC_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("tenant_rpc_requests_total", "JSON-RPC requests sent by tenant, cluster, endpoints and method")
+            .namespace(NAMESPACE),
+        &["tenant", "cluster", "endpoints", "method"],
+    ))
+});
+
+static RPC_CIRCUIT_STATE: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::with_opts(
+        Opts::new("solana_rpc_circuit_state", "RPC circuit breaker state: 0 closed, 1 half-open, 2 open")
+            .namespace(NAMESPACE),
+    ))
+});
+
+static TRANSACTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("transactions_total", "Program transactions submitted by instruction and outcome").namespace(NAMESPACE),
+        &["instruction", "outcome"],
+    ))
+});
+
+static ACCOUNT_CACHE: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("account_cache_requests_total", "Account reads by commitment and cache result").namespace(NAMESPACE),
+        &["commitment", "result"],
+    ))
+});
+
+static RESPONSE_CACHE: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("response_cache_requests_total", "Cached endpoint reads by namespace and result").namespace(NAMESPACE),
+        &["namespace", "result"],
+    ))
+});
+
+static WEBHOOK_ATTEMPTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("webhook_attempts_total", "Webhook HTTP attempts by result").namespace(NAMESPACE),
+        &["result"],
+    ))
+});
+
+static WEBHOOK_DELIVERIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("webhook_deliveries_total", "Webhook deliveries by final status").namespace(NAMESPACE),
+        &["status"],
+    ))
+});
+
+static WEBHOOK_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new("webhook_attempt_duration_seconds", "Webhook HTTP attempt latency")
+            .namespace(NAMESPACE)
+            .buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]),
+        &["result"],
+    ))
+});
+
+static WEBHOOK_DEAD_LETTERS: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::with_opts(
+        Opts::new("webhook_dead_letters", "Failed webhook deliveries waiting in the dead-letter queue")
+            .namespace(NAMESPACE),
+    ))
+});
+
+static WEBHOOK_FILTERED: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::with_opts(
+        Opts::new("webhook_events_filtered_total", "Webhook deliveries skipped because the event failed the webhook's filter")
+            .namespace(NAMESPACE),
+    ))
+});
+
+static EVENT_SINK_EVENTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("event_sink_events_total", "Events sent to message brokers by sink and outcome").namespace(NAMESPACE),
+        &["sink", "outcome"],
+    ))
+});
+
+static JOB_LAST_SUCCESS: Lazy<GaugeVec> = Lazy::new(|| {
+    register(GaugeVec::new(
+        Opts::new("job_last_success_timestamp_seconds", "Unix time of the last successful background job run")
+            .namespace(NAMESPACE),
+        &["job"],
+    ))
+});
+
+static JOB_DURATION: Lazy<GaugeVec> = Lazy::new(|| {
+    register(GaugeVec::new(
+        Opts::new("job_last_duration_seconds", "Duration of the last background job run").namespace(NAMESPACE),
+        &["job"],
+    ))
+});
+
+static JOB_ITEMS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new("job_last_items", "Items processed by the last background job run").namespace(NAMESPACE),
+        &["job"],
+    ))
+});
+
+static JOB_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("job_failures_total", "Failed background job runs").namespace(NAMESPACE),
+        &["job"],
+    ))
+});
+
+static RECONCILIATION_DIFFERENCE: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::with_opts(
+        Opts::new(
+            "treasury_reconciliation_difference_lamports",
+            "Treasury inflows on chain minus indexed payments, for the last reconciled day",
+        )
+        .namespace(NAMESPACE),
+    ))
+});
+
+static RECONCILIATION_ALERTS: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::with_opts(
+        Opts::new("treasury_reconciliation_alerts_total", "Days whose treasury inflows diverged from the index")
+            .namespace(NAMESPACE),
+    ))
+});
+
+static AUTH_ATTEMPTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("auth_attempts_total", "Sign-in attempts by method and outcome").namespace(NAMESPACE),
+        &["method", "result"],
+    ))
+});
+
+static AUTH_ANOMALIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("auth_anomalies_total", "Wallets and client IPs flagged for bursts of failed sign-ins")
+            .namespace(NAMESPACE),
+        &["key"],
+    ))
+});
+
+static HEAVY_ROUTES_IN_FLIGHT: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::with_opts(
+        Opts::new("heavy_route_requests_in_flight", "Listing and scanning requests holding a heavy-route slot")
+            .namespace(NAMESPACE),
+    ))
+});
+
+static HEAVY_ROUTE_REJECTIONS: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::with_opts(
+        Opts::new("heavy_route_rejections_total", "Listing and scanning requests turned away for want of a slot")
+            .namespace(NAMESPACE),
+    ))
+});
+
+static START_TIME: Lazy<Gauge> = Lazy::new(|| {
+    register(Gauge::with_opts(
+        Opts::new("process_start_time_seconds", "Unix time the server started").namespace(NAMESPACE),
+    ))
+});
+
+fn register<M: Collector + Clone + 'static>(metric: prometheus::Result<M>) -> M {
+    let metric = metric.expect("Invalid metric definition");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("Failed to register metric");
+    metric
+}
+
+/// Registers every collector up front so `/metrics` lists them before their first sample.
+pub fn init() {
+    Lazy::force(&RPC_REQUESTS);
+    Lazy::force(&RPC_DURATION);
+    Lazy::force(&TENANT_RPC_REQUESTS);
+    Lazy::force(&RPC_CIRCUIT_STATE);
+    Lazy::force(&TRANSACTIONS);
+    Lazy::force(&ACCOUNT_CACHE);
+    Lazy::force(&RESPONSE_CACHE);
+    Lazy::force(&WEBHOOK_ATTEMPTS);
+    Lazy::force(&WEBHOOK_DELIVERIES);
+    Lazy::force(&WEBHOOK_DURATION);
+    Lazy::force(&WEBHOOK_DEAD_LETTERS);
+    Lazy::force(&WEBHOOK_FILTERED);
+    Lazy::force(&EVENT_SINK_EVENTS);
+    Lazy::force(&JOB_LAST_SUCCESS);
+    Lazy::force(&JOB_DURATION);
+    Lazy::force(&JOB_ITEMS);
+    Lazy::force(&JOB_FAILURES);
+    Lazy::force(&RECONCILIATION_DIFFERENCE);
+    Lazy::force(&RECONCILIATION_ALERTS);
+    Lazy::force(&AUTH_ATTEMPTS);
+    Lazy::force(&AUTH_ANOMALIES);
+    Lazy::force(&HEAVY_ROUTES_IN_FLIGHT);
+    Lazy::force(&HEAVY_ROUTE_REJECTIONS);
+    START_TIME.set(unix_now());
+}
+
+/// Times an RPC call, counts it by outcome and runs it in a `solana_rpc` span carrying the
+/// current request ID. Calls go through the circuit breaker: while it is open they fail
+/// straight away with an error `circuit::shed` recognises, counted as `shed`.
+pub async fn observe_rpc<T, F>(method: &'static str, call: F) -> ClientResult<T>
+where
+    F: Future<Output = ClientResult<T>>,
+{
+    let admission = match circuit::breaker().admit().await {
+        Ok(admission) => admission,
+        Err(open) => {
+            RPC_REQUESTS.with_label_values(&[method, "shed"]).inc();
+            return Err(open.into());
+        }
+    };
+    let span = tracing::info_span!("solana_rpc", method, request_id = tracing::field::Empty);
+    if let Some(request_id) = telemetry::request_id() {
+        span.record("request_id", request_id.as_str());
+    }
+    let started = Instant::now();
+    let result = call.instrument(span).await;
+    admission.finish(&result);
+    RPC_DURATION.with_label_values(&[method]).observe(started.elapsed().as_secs_f64());
+    RPC_REQUESTS
+        .with_label_values(&[method, if result.is_ok() { "success" } else { "error" }])
+        .inc();
+    result
+}
+
+/// One JSON-RPC request as sent, retries and health checks included. `endpoints` is `shared`
+/// or `tenant` for the tenant's own.
+pub fn record_tenant_rpc(tenant: &str, cluster: &str, endpoints: &str, method: &str) {
+    TENANT_RPC_REQUESTS.with_label_values(&[tenant, cluster, endpoints, method]).inc();
+}
+
+pub fn set_rpc_circuit_state(state: State) {
+    RPC_CIRCUIT_STATE.set(match state {
+        State::Closed => 0,
+        State::HalfOpen => 1,
+        State::Open => 2,
+    });
+}
+
+pub fn record_transaction(instruction: &str, success: bool) {
+    TRANSACTIONS
+        .with_label_values(&[instruction, if success { "success" } else { "failure" }])
+        .inc();
+}
+
+pub fn record_account_cache(commitment: &str, hit: bool, accounts: u64) {
+    ACCOUNT_CACHE
+        .with_label_values(&[commitment, if hit { "hit" } else { "miss" }])
+        .inc_by(accounts);
+}
+
+/// `result` is one of `fresh`, `stale` (served while refreshing) or `miss`.
+pub fn record_response_cache(namespace: &str, result: &str) {
+    RESPONSE_CACHE.with_label_values(&[namespace, result]).inc();
+}
+
+/// `result` is one of `success`, `http_error` or `network_error`.
+pub fn record_webhook_attempt(result: &str, elapsed_secs: f64) {
+    WEBHOOK_ATTEMPTS.with_label_values(&[result]).inc();
+    WEBHOOK_DURATION.with_label_values(&[result]).observe(elapsed_secs);
+}
+
+pub fn record_webhook_delivery(succeeded: bool) {
+    WEBHOOK_DELIVERIES
+        .with_label_values(&[if succeeded { "succeeded" } else { "failed" }])
+        .inc();
+}
+
+pub fn set_webhook_dead_letters(queued: i64) {
+    WEBHOOK_DEAD_LETTERS.set(queued);
+}
+
+pub fn record_webhooks_filtered(webhooks: u64) {
+    WEBHOOK_FILTERED.inc_by(webhooks);
+}
+
+/// `outcome` is one of `published`, `failed` (after every retry) or `skipped` (the sink fell
+/// behind).
+pub fn record_event_sink(sink: &str, outcome: &str, events: u64) {
+    EVENT_SINK_EVENTS.with_label_values(&[sink, outcome]).inc_by(events);
+}
+
+pub fn record_job_success(job: &str, started: Instant, items: usize) {
+    JOB_LAST_SUCCESS.with_label_values(&[job]).set(unix_now());
+    JOB_DURATION.with_label_values(&[job]).set(started.elapsed().as_secs_f64());
+    JOB_ITEMS.with_label_values(&[job]).set(items as i64);
+}
+
+pub fn record_job_failure(job: &str, started: Instant) {
+    JOB_DURATION.with_label_values(&[job]).set(started.elapsed().as_secs_f64());
+    JOB_FAILURES.with_label_values(&[job]).inc();
+}
+
+pub fn record_reconciliation(difference_lamports: i64, alerted: bool) {
+    RECONCILIATION_DIFFERENCE.set(difference_lamports);
+    if alerted {
+        RECONCILIATION_ALERTS.inc();
+    }
+}
+
+pub fn record_auth_attempt(method: &str, result: &str) {
+    AUTH_ATTEMPTS.with_label_values(&[method, result]).inc();
+}
+
+pub fn record_auth_anomaly(key: &str) {
+    AUTH_ANOMALIES.with_label_values(&[key]).inc();
+}
+
+pub fn set_heavy_routes_in_flight(requests: i64) {
+    HEAVY_ROUTES_IN_FLIGHT.set(requests);
+}
+
+pub fn record_heavy_route_rejection() {
+    HEAVY_ROUTE_REJECTIONS.inc();
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64()
+}
@@ -1,10 +1,25 @@
 use actix_web::{
+    body::EitherBody,
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    Error, HttpMessage,
+    http::header::{
+        HeaderMap, HeaderName, HeaderValue, CONTENT_SECURITY_POLICY, RETRY_AFTER, STRICT_TRANSPORT_SECURITY,
+        X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+    },
+    http::{Method, StatusCode},
+    Error, HttpMessage, HttpResponse, ResponseError,
 };
 use futures_util::future::LocalBoxFuture;
 use std::future::{ready, Ready};
-use crate::{AppError, AuthService};
+use std::rc::Rc;
+use sqlx::postgres::PgPool;
+use crate::api_keys::ApiKeyService;
+use crate::audit::{self, AuditSignatures};
+use crate::concurrency::HeavyRouteLimiter;
+use crate::db::NewAuditEntry;
+use crate::quota::{self, QuotaService};
+use crate::rate_limit::{RateLimitDecision, RateLimiter};
+use crate::timeouts::RequestTimeouts;
+use crate::{AppError, AuthService, AuthToken, Role};
 
 pub struct Authentication {
     auth_service: AuthService,
@@ -18,7 +33,7 @@ impl Authentication {
 
 impl<S, B> Transform<S, ServiceRequest> for Authentication
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -30,20 +45,20 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(AuthenticationMiddleware {
-            service,
+            service: Rc::new(service),
             auth_service: self.auth_service.clone(),
         }))
     }
 }
 
 pub struct AuthenticationMiddleware<S> {
-    service: S,
+    service: Rc<S>,
     auth_service: AuthService,
 }
 
 impl<S, B> Service<ServiceRequest> for AuthenticationMiddleware<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -64,16 +79,704 @@ where
             None => return Box::pin(async { Err(AppError::Auth("No token provided".to_string()).into()) }),
         };
 
+        let service = Rc::clone(&self.service);
         let auth_service = self.auth_service.clone();
 
-        match auth_service.verify_token(&token) {
-            Ok(auth_token) => {
-                let req = req; 
-                req.extensions_mut().insert(auth_token);
-                let fut = self.service.call(req);
-                Box::pin(async move { fut.await })
+        Box::pin(async move {
+            // Verification checks the revocation list, so it has to run asynchronously
+            let auth_token = auth_service.verify_token(&token).await?;
+            req.extensions_mut().insert(auth_token);
+            service.call(req).await
+        })
+    }
+}
+
+/// Server-to-server authentication with merchant API keys (`X-Api-Key` header).
+pub struct ApiKeyAuthentication {
+    api_key_service: ApiKeyService,
+}
+
+impl ApiKeyAuthentication {
+    pub fn new(api_key_service: ApiKeyService) -> Self {
+        ApiKeyAuthentication { api_key_service }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ApiKeyAuthentication
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ApiKeyAuthenticationMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ApiKeyAuthenticationMiddleware {
+            service: Rc::new(service),
+            api_key_service: self.api_key_service.clone(),
+        }))
+    }
+}
+
+pub struct ApiKeyAuthenticationMiddleware<S> {
+    service: Rc<S>,
+    api_key_service: ApiKeyService,
+}
+
+impl<S, B> Service<ServiceRequest> for ApiKeyAuthenticationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let key = req
+            .headers()
+            .get("X-Api-Key")
+            .and_then(|header| header.to_str().ok())
+            .map(|s| s.to_string());
+
+        let key = match key {
+            Some(k) => k,
+            None => return Box::pin(async { Err(AppError::Auth("No API key provided".to_string()).into()) }),
+        };
+
+        let service = Rc::clone(&self.service);
+        let api_key_service = self.api_key_service.clone();
+
+        Box::pin(async move {
+            let auth_token = api_key_service.verify(&key).await?;
+            req.extensions_mut().insert(auth_token);
+            service.call(req).await
+        })
+    }
+}
+
+/// Rejects requests whose `AuthToken` lacks `role`. Must run inside an authentication middleware.
+pub struct RequireRole {
+    role: Role,
+}
+
+impl RequireRole {
+    pub fn new(role: Role) -> Self {
+        RequireRole { role }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequireRole
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequireRoleMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireRoleMiddleware {
+            service: Rc::new(service),
+            role: self.role,
+        }))
+    }
+}
+
+pub struct RequireRoleMiddleware<S> {
+    service: Rc<S>,
+    role: Role,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireRoleMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let allowed = match req.extensions().get::<AuthToken>() {
+            Some(auth_token) => auth_token.has_role(self.role),
+            None => return Box::pin(async { Err(AppError::Auth("No auth token found".to_string()).into()) }),
+        };
+        if !allowed {
+            let role = self.role;
+            return Box::pin(async move {
+                Err(AppError::Forbidden(format!("{} role required", role.as_str())).into())
+            });
+        }
+
+        let service = Rc::clone(&self.service);
+        Box::pin(async move { service.call(req).await })
+    }
+}
+
+#[derive(Clone, Copy)]
+enum RateLimitKey {
+    Ip { trust_forwarded: bool },
+    PublicKey,
+}
+
+pub struct RateLimit {
+    limiter: RateLimiter,
+    key: RateLimitKey,
+}
+
+impl RateLimit {
+    /// Limits by client IP. Only trust `Forwarded`/`X-Forwarded-For` behind a proxy that sets them.
+    pub fn per_ip(limiter: RateLimiter, trust_forwarded: bool) -> Self {
+        RateLimit { limiter, key: RateLimitKey::Ip { trust_forwarded } }
+    }
+
+    /// Limits by authenticated wallet; must run inside `Authentication`.
+    pub fn per_public_key(limiter: RateLimiter) -> Self {
+        RateLimit { limiter, key: RateLimitKey::PublicKey }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            limiter: self.limiter.clone(),
+            key: self.key,
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    limiter: RateLimiter,
+    key: RateLimitKey,
+}
+
+impl<S> RateLimitMiddleware<S> {
+    fn key_for(&self, req: &ServiceRequest) -> Option<String> {
+        match self.key {
+            RateLimitKey::Ip { trust_forwarded } => client_ip(req, trust_forwarded).map(|ip| format!("ip:{}", ip)),
+            RateLimitKey::PublicKey => req
+                .extensions()
+                .get::<AuthToken>()
+                .map(|token| format!("pubkey:{}", token.public_key)),
+        }
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let limiter = self.limiter.clone();
+        let key = self.key_for(&req);
+
+        Box::pin(async move {
+            let Some(key) = key else {
+                return service.call(req).await.map(ServiceResponse::map_into_left_body);
+            };
+
+            let decision = limiter.check(&key).await;
+            if !decision.allowed {
+                let mut response = AppError::RateLimited("Too many requests".to_string()).error_response();
+                set_rate_limit_headers(response.headers_mut(), &decision);
+                response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, HeaderValue::from(decision.retry_after_secs));
+                return Ok(req.into_response(response).map_into_right_body());
             }
-            Err(e) => Box::pin(async move { Err(e.into()) }),
+
+            let mut res = service.call(req).await?;
+            set_rate_limit_headers(res.headers_mut(), &decision);
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+/// Counts authenticated requests, and the transactions they build, against the wallet's daily
+/// quotas and refuses them with `429` once one is used up; must run inside an authentication
+/// middleware.
+pub struct Quota {
+    quotas: QuotaService,
+}
+
+impl Quota {
+    pub fn new(quotas: QuotaService) -> Self {
+        Quota { quotas }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Quota
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = QuotaMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(QuotaMiddleware {
+            service: Rc::new(service),
+            quotas: self.quotas.clone(),
+        }))
+    }
+}
+
+pub struct QuotaMiddleware<S> {
+    service: Rc<S>,
+    quotas: QuotaService,
+}
+
+impl<S, B> Service<ServiceRequest> for QuotaMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let quotas = self.quotas.clone();
+        let auth_token = req.extensions().get::<AuthToken>().cloned();
+        let route = req.match_pattern().unwrap_or_else(|| req.path().to_string());
+        let builds_transaction = quota::builds_transaction(req.method(), &route);
+
+        Box::pin(async move {
+            let Some(auth_token) = auth_token else {
+                return service.call(req).await.map(ServiceResponse::map_into_left_body);
+            };
+
+            if let Err(exceeded) = quotas.consume(&auth_token, builds_transaction).await {
+                let mut response = AppError::RateLimited(exceeded.message).error_response();
+                response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, HeaderValue::from(exceeded.retry_after_secs));
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+
+            service.call(req).await.map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
+
+/// Answers requests whose handler, body extraction included, outlives the route's timeout
+/// with `504 Gateway Timeout`, dropping the handler. Routes are matched on their pattern, so
+/// this must run after `/api` paths are versioned. A streamed response body, such as an export
+/// download, is not bounded once its headers are sent.
+pub struct Timeout {
+    timeouts: Rc<RequestTimeouts>,
+}
+
+impl Timeout {
+    pub fn new(timeouts: RequestTimeouts) -> Self {
+        Timeout { timeouts: Rc::new(timeouts) }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Timeout
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = TimeoutMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TimeoutMiddleware {
+            service: Rc::new(service),
+            timeouts: Rc::clone(&self.timeouts),
+        }))
+    }
+}
+
+pub struct TimeoutMiddleware<S> {
+    service: Rc<S>,
+    timeouts: Rc<RequestTimeouts>,
+}
+
+impl<S, B> Service<ServiceRequest> for TimeoutMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let route = req.match_pattern().unwrap_or_else(|| req.path().to_string());
+        let timeout = self.timeouts.for_route(req.method(), &route);
+        let method = req.method().clone();
+
+        Box::pin(async move {
+            if timeout.is_zero() {
+                return service.call(req).await;
+            }
+            match tokio::time::timeout(timeout, service.call(req)).await {
+                Ok(result) => result,
+                Err(_) => {
+                    tracing::warn!("{} {} timed out after {}s", method, route, timeout.as_secs());
+                    // Answered as an error: a response would need a copy of the request, and
+                    // holding one while the app routes it panics
+                    Err(AppError::Timeout(format!("No response within {}s", timeout.as_secs())).into())
+                }
+            }
+        })
+    }
+}
+
+/// Runs listing and scanning routes within the heavy-route pool of `HeavyRouteLimiter`,
+/// answering `503 Service Unavailable` with `Retry-After` when no slot frees up in time. Other
+/// routes pass straight through. Like `Timeout`, it matches route patterns, so it must run
+/// after `/api` paths are versioned, and a streamed response body is not held to its slot.
+pub struct ConcurrencyLimit {
+    limiter: HeavyRouteLimiter,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(limiter: HeavyRouteLimiter) -> Self {
+        ConcurrencyLimit { limiter }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ConcurrencyLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ConcurrencyLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ConcurrencyLimitMiddleware {
+            service: Rc::new(service),
+            limiter: self.limiter.clone(),
+        }))
+    }
+}
+
+pub struct ConcurrencyLimitMiddleware<S> {
+    service: Rc<S>,
+    limiter: HeavyRouteLimiter,
+}
+
+impl<S, B> Service<ServiceRequest> for ConcurrencyLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let route = req.match_pattern().unwrap_or_else(|| req.path().to_string());
+        if !self.limiter.is_heavy(req.method(), &route) {
+            return Box::pin(async move { service.call(req).await.map(ServiceResponse::map_into_left_body) });
         }
+        let limiter = self.limiter.clone();
+
+        Box::pin(async move {
+            let Some(slot) = limiter.acquire().await else {
+                tracing::warn!("{} {} turned away: every heavy-route slot is busy", req.method(), route);
+                let (http_req, _) = req.into_parts();
+                let response = AppError::ServiceUnavailable(
+                    "Too many listing requests in progress, retry shortly".to_string(),
+                    1,
+                )
+                .error_response();
+                return Ok(ServiceResponse::new(http_req, response).map_into_right_body());
+            };
+            let result = service.call(req).await;
+            drop(slot);
+            result.map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
+
+/// Only trust `Forwarded`/`X-Forwarded-For` behind a proxy that sets them.
+fn client_ip(req: &ServiceRequest, trust_forwarded: bool) -> Option<String> {
+    if trust_forwarded {
+        req.connection_info().realip_remote_addr().map(str::to_string)
+    } else {
+        req.peer_addr().map(|addr| addr.ip().to_string())
+    }
+}
+
+fn set_rate_limit_headers(headers: &mut HeaderMap, decision: &RateLimitDecision) {
+    headers.insert(HeaderName::from_static("x-ratelimit-limit"), HeaderValue::from(decision.limit));
+    headers.insert(HeaderName::from_static("x-ratelimit-remaining"), HeaderValue::from(decision.remaining));
+    headers.insert(HeaderName::from_static("x-ratelimit-reset"), HeaderValue::from(decision.reset_secs));
+}
+
+/// Adds `Strict-Transport-Security`, `X-Content-Type-Options`, `X-Frame-Options` and
+/// `Content-Security-Policy` to every response, including error responses, unless a handler
+/// already set them. HSTS is skipped when `hsts_max_age_secs` is 0.
+pub struct SecurityHeaders {
+    headers: Rc<Vec<(HeaderName, HeaderValue)>>,
+}
+
+impl SecurityHeaders {
+    pub fn new(hsts_max_age_secs: u64, content_security_policy: &str) -> Self {
+        let mut headers = vec![
+            (X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+            (X_FRAME_OPTIONS, HeaderValue::from_static("DENY")),
+            (
+                CONTENT_SECURITY_POLICY,
+                HeaderValue::from_str(content_security_policy).expect("Invalid CONTENT_SECURITY_POLICY"),
+            ),
+        ];
+        if hsts_max_age_secs > 0 {
+            let hsts = format!("max-age={}; includeSubDomains", hsts_max_age_secs);
+            headers.push((STRICT_TRANSPORT_SECURITY, HeaderValue::from_str(&hsts).expect("Invalid HSTS header")));
+        }
+        SecurityHeaders { headers: Rc::new(headers) }
     }
-}
\ No newline at end of file
+}
+
+impl<S, B> Transform<S, ServiceRequest> for SecurityHeaders
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SecurityHeadersMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SecurityHeadersMiddleware {
+            service: Rc::new(service),
+            headers: Rc::clone(&self.headers),
+        }))
+    }
+}
+
+pub struct SecurityHeadersMiddleware<S> {
+    service: Rc<S>,
+    headers: Rc<Vec<(HeaderName, HeaderValue)>>,
+}
+
+impl<S, B> Service<ServiceRequest> for SecurityHeadersMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let headers = Rc::clone(&self.headers);
+
+        Box::pin(async move {
+            // Errors are rendered outside, so they carry the headers along; rendering them here
+            // would need a copy of the request, and holding one while the app routes it panics
+            let mut res = service
+                .call(req)
+                .await
+                .map_err(|error| SecuredError { error, headers: Rc::clone(&headers) })?;
+            add_missing_headers(res.headers_mut(), &headers);
+            Ok(res)
+        })
+    }
+}
+
+/// An error from within `SecurityHeaders`, rendered with its headers.
+#[derive(Debug)]
+struct SecuredError {
+    error: Error,
+    headers: Rc<Vec<(HeaderName, HeaderValue)>>,
+}
+
+impl std::fmt::Display for SecuredError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl ResponseError for SecuredError {
+    fn status_code(&self) -> StatusCode {
+        self.error.as_response_error().status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = self.error.error_response();
+        add_missing_headers(response.headers_mut(), &self.headers);
+        response
+    }
+}
+
+fn add_missing_headers(target: &mut HeaderMap, headers: &[(HeaderName, HeaderValue)]) {
+    for (name, value) in headers {
+        if !target.contains_key(name) {
+            target.insert(name.clone(), value.clone());
+        }
+    }
+}
+
+/// Writes an `audit_log` entry for every authenticated `POST`, `PUT`, `PATCH` or `DELETE`
+/// once it has been answered, whatever the outcome; must run inside an authentication
+/// middleware. Requests that only read, such as GraphQL queries and simulations, are skipped.
+pub struct AuditLog {
+    pool: PgPool,
+    trust_forwarded: bool,
+}
+
+impl AuditLog {
+    pub fn new(pool: PgPool, trust_forwarded: bool) -> Self {
+        AuditLog { pool, trust_forwarded }
+    }
+}
+
+const READ_ONLY_ROUTES: [&str; 2] = ["/graphql", "/subscriptions/simulate"];
+
+impl<S, B> Transform<S, ServiceRequest> for AuditLog
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuditLogMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuditLogMiddleware {
+            service: Rc::new(service),
+            pool: self.pool.clone(),
+            trust_forwarded: self.trust_forwarded,
+        }))
+    }
+}
+
+pub struct AuditLogMiddleware<S> {
+    service: Rc<S>,
+    pool: PgPool,
+    trust_forwarded: bool,
+}
+
+impl<S> AuditLogMiddleware<S> {
+    fn entry_for(&self, req: &ServiceRequest) -> Option<NewAuditEntry> {
+        if !matches!(*req.method(), Method::POST | Method::PUT | Method::PATCH | Method::DELETE) {
+            return None;
+        }
+        let route = req.match_pattern().unwrap_or_else(|| req.path().to_string());
+        if READ_ONLY_ROUTES.iter().any(|suffix| route.ends_with(suffix)) {
+            return None;
+        }
+        let auth_token = req.extensions().get::<AuthToken>()?.clone();
+        Some(audit::new_entry(
+            auth_token,
+            req.match_info().get("tenant").map(str::to_string),
+            req.method().as_str(),
+            route,
+            req.path().to_string(),
+            client_ip(req, self.trust_forwarded),
+        ))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for AuditLogMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let Some(mut entry) = self.entry_for(&req) else {
+            return Box::pin(async move { service.call(req).await });
+        };
+        let pool = self.pool.clone();
+
+        Box::pin(async move {
+            let result = service.call(req).await;
+            match &result {
+                Ok(res) => {
+                    entry.status = res.status().as_u16() as i32;
+                    if let Some(signatures) = res.request().extensions().get::<AuditSignatures>() {
+                        entry.signatures = signatures.0.clone();
+                    }
+                }
+                Err(e) => entry.status = e.as_response_error().status_code().as_u16() as i32,
+            }
+            audit::record(&pool, &entry).await;
+            result
+        })
+    }
+}
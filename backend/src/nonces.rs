@@ -0,0 +1,156 @@
+use solana_client::nonce_utils;
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    hash::Hash,
+    instruction::Instruction,
+    message::Message,
+    nonce,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction,
+    transaction::Transaction,
+};
+use std::collections::HashSet;
+use std::str::FromStr;
+use crate::cluster::{Cluster, SolanaClusters};
+use crate::db;
+use crate::metrics;
+use crate::{AppError, AppResult, SolanaService};
+
+// A lease this old with no job recorded behind it was lost to a crash between lease and send
+const STALE_LEASE_SECS: i64 = 300;
+
+/// A durable nonce account held by one transaction until its job is confirmed or failed.
+#[derive(Debug, Clone, Copy)]
+pub struct NonceLease {
+    pub address: Pubkey,
+    pub blockhash: Hash, // The nonce's current value, used in place of a recent blockhash
+}
+
+impl NonceLease {
+    /// Must be the transaction's first instruction.
+    pub fn advance_instruction(&self, authority: &Pubkey) -> Instruction {
+        system_instruction::advance_nonce_account(&self.address, authority)
+    }
+}
+
+/// The value currently stored in a nonce account. A transaction built on it stays valid
+/// until the account is advanced, by that transaction landing or by `invalidate`.
+pub async fn current_blockhash(solana_service: &SolanaService, address: &Pubkey) -> AppResult<Hash> {
+    let account = metrics::observe_rpc(
+        "getAccountInfo",
+        solana_service.rpc.client().get_account_with_commitment(address, CommitmentConfig::confirmed()),
+    )
+    .await
+    .map_err(|e| AppError::rpc("Failed to fetch nonce account", e))?
+    .value
+    .ok_or_else(|| AppError::InternalServerError(format!("Nonce account {} does not exist", address)))?;
+    let data = nonce_utils::data_from_account(&account)
+        .map_err(|e| AppError::InternalServerError(format!("Invalid nonce account {}: {}", address, e)))?;
+    Ok(data.blockhash())
+}
+
+/// Leases a free nonce account of the service's cluster to `owner`. `None` when the pool is
+/// off, exhausted, or `owner` already holds `NONCE_MAX_PER_USER`; the caller then falls back
+/// to a recent blockhash.
+pub async fn lease(solana_service: &SolanaService, owner: &Pubkey) -> AppResult<Option<NonceLease>> {
+    if solana_service.nonce_pool_size == 0 {
+        return Ok(None);
+    }
+    let Some(address) = db::lease_nonce_account(
+        &solana_service.pool,
+        solana_service.cluster.as_str(),
+        &solana_service.signer().to_string(),
+        &owner.to_string(),
+        solana_service.nonce_max_per_user,
+        STALE_LEASE_SECS,
+    )
+    .await?
+    else {
+        return Ok(None);
+    };
+    let lease = match Pubkey::from_str(&address) {
+        Ok(address) => current_blockhash(solana_service, &address)
+            .await
+            .map(|blockhash| NonceLease { address, blockhash }),
+        Err(e) => Err(AppError::InternalServerError(format!("Invalid nonce account {}: {}", address, e))),
+    };
+    if lease.is_err() {
+        db::release_nonce_account(&solana_service.pool, &address).await?;
+    }
+    lease.map(Some)
+}
+
+/// Advances a nonce account on its own, so a transaction built on it can no longer land.
+pub async fn invalidate(solana_service: &SolanaService, address: &Pubkey) -> AppResult<()> {
+    let authority = solana_service.signer();
+    let client = solana_service.rpc.client();
+    let recent_blockhash = metrics::observe_rpc("getLatestBlockhash", client.get_latest_blockhash())
+        .await
+        .map_err(|e| AppError::rpc("Failed to get blockhash", e))?;
+    let mut tx = Transaction::new_unsigned(Message::new_with_blockhash(
+        &[system_instruction::advance_nonce_account(address, &authority)],
+        Some(&authority),
+        &recent_blockhash,
+    ));
+    solana_service.sign(&mut tx).await?;
+    metrics::observe_rpc("sendTransaction", client.send_and_confirm_transaction(&tx))
+        .await
+        .map_err(|e| AppError::rpc("Failed to advance nonce account", e))?;
+    Ok(())
+}
+
+/// Creates nonce accounts, paid for and advanced by the signer, until the cluster has
+/// `NONCE_POOL_SIZE`. Returns how many were created.
+async fn fill(solana_service: &SolanaService) -> AppResult<usize> {
+    let authority = solana_service.signer();
+    let cluster = solana_service.cluster.as_str();
+    let existing = db::count_nonce_accounts(&solana_service.pool, cluster, &authority.to_string()).await?;
+    let missing = (solana_service.nonce_pool_size as i64 - existing).max(0) as usize;
+    if missing == 0 {
+        return Ok(0);
+    }
+
+    let client = solana_service.rpc.client();
+    let rent = metrics::observe_rpc(
+        "getMinimumBalanceForRentExemption",
+        client.get_minimum_balance_for_rent_exemption(nonce::State::size()),
+    )
+    .await
+    .map_err(|e| AppError::rpc("Failed to get rent", e))?;
+    for _ in 0..missing {
+        let account = Keypair::new();
+        let recent_blockhash = metrics::observe_rpc("getLatestBlockhash", client.get_latest_blockhash())
+            .await
+            .map_err(|e| AppError::rpc("Failed to get blockhash", e))?;
+        let mut tx = Transaction::new_unsigned(Message::new_with_blockhash(
+            &system_instruction::create_nonce_account(&authority, &account.pubkey(), &authority, rent),
+            Some(&authority),
+            &recent_blockhash,
+        ));
+        tx.partial_sign(&[&account], recent_blockhash);
+        solana_service.sign(&mut tx).await?;
+        metrics::observe_rpc("sendTransaction", client.send_and_confirm_transaction(&tx))
+            .await
+            .map_err(|e| AppError::rpc("Failed to create nonce account", e))?;
+        db::insert_nonce_account(&solana_service.pool, &account.pubkey().to_string(), cluster, &authority.to_string())
+            .await?;
+    }
+    Ok(missing)
+}
+
+/// Tops up every cluster's pool in the background. Tenants on a cluster share one pool, as
+/// they share the signer. Until a pool is filled, transactions use recent blockhashes.
+pub async fn fill_pools(clusters: SolanaClusters) {
+    let mut seen: HashSet<Cluster> = HashSet::new();
+    for solana_service in clusters.all() {
+        if solana_service.nonce_pool_size == 0 || !seen.insert(solana_service.cluster) {
+            continue;
+        }
+        match fill(solana_service).await {
+            Ok(0) => {}
+            Ok(created) => tracing::info!("Created {} nonce accounts on {}", created, solana_service.cluster.as_str()),
+            Err(e) => tracing::error!("Failed to fill the nonce pool on {}: {}", solana_service.cluster.as_str(), e),
+        }
+    }
+}
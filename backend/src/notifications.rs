@@ -0,0 +1,247 @@
+use actix_web::{get, put, web, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+use validator::Validate;
+use crate::customers;
+use crate::db;
+use crate::email::{EmailSender, EmailTemplate};
+use crate::pii::PiiVault;
+use crate::validation::{FieldError, ValidatedJson};
+use crate::{AppError, AppResult, AuthToken, Config};
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow, ToSchema)]
+pub struct NotificationPreferences {
+    #[serde(skip_serializing)]
+    #[allow(dead_code)]
+    owner: String,
+    email: Option<String>,
+    payment_receipts: bool,
+    expiry_reminders: bool,
+    renewal_failures: bool,
+    monthly_statements: bool, // Merchants only: last month's statement, emailed on the 1st
+    updated_at: Option<i64>, // Unset until the preferences are first saved
+}
+
+impl NotificationPreferences {
+    fn defaults(owner: &str) -> Self {
+        Self {
+            owner: owner.to_string(),
+            email: None,
+            payment_receipts: false,
+            expiry_reminders: false,
+            renewal_failures: false,
+            monthly_statements: false,
+            updated_at: None,
+        }
+    }
+
+    fn allows(&self, template: &EmailTemplate) -> bool {
+        match template {
+            EmailTemplate::PaymentReceipt { .. } => self.payment_receipts,
+            EmailTemplate::ExpiryReminder { .. } => self.expiry_reminders,
+            EmailTemplate::RenewalFailed { .. } => self.renewal_failures,
+            EmailTemplate::MonthlyStatement { .. } => self.monthly_statements,
+            EmailTemplate::ReconciliationAlert { .. } => false, // Operators only, see `alert`
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct NotificationPreferencesRequest {
+    #[validate(email(message = "must be a valid email address"))]
+    email: Option<String>,
+    payment_receipts: bool,
+    expiry_reminders: bool,
+    renewal_failures: bool,
+    #[serde(default)]
+    monthly_statements: bool,
+}
+
+// Notification Service
+/// Sends user-facing notifications by email, honouring each wallet's opt-in, or else the
+/// consent recorded in its merchant's customer directory. Sends run in the background so
+/// callers never wait on SMTP.
+#[derive(Clone)]
+pub struct NotificationService {
+    pool: PgPool,
+    email: EmailSender,
+    vault: PiiVault, // Opens the addresses merchants hold for their customers
+}
+
+impl NotificationService {
+    pub fn new(config: &Config, pool: PgPool) -> Self {
+        Self {
+            pool,
+            email: EmailSender::new(config),
+            vault: PiiVault::new(&config.tenants),
+        }
+    }
+
+    /// Switches to the SMTP settings in `config`, keeping the current ones if they are invalid.
+    pub fn reload(&self, config: &Config) -> Result<(), String> {
+        self.email.reload(config)
+    }
+
+    pub async fn preferences(&self, owner: &str) -> AppResult<NotificationPreferences> {
+        let preferences = sqlx::query_as::<_, NotificationPreferences>(
+            "SELECT * FROM notification_preferences WHERE owner = $1",
+        )
+        .bind(owner)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to fetch notification preferences: {}", e)))?;
+        Ok(preferences.unwrap_or_else(|| NotificationPreferences::defaults(owner)))
+    }
+
+    pub async fn update_preferences(
+        &self,
+        owner: &str,
+        req: NotificationPreferencesRequest,
+    ) -> AppResult<NotificationPreferences> {
+        let any_enabled = req.payment_receipts || req.expiry_reminders || req.renewal_failures || req.monthly_statements;
+        if any_enabled && req.email.is_none() {
+            return Err(AppError::Validation(vec![FieldError::new(
+                "email",
+                "required",
+                "is required to enable notifications",
+            )]));
+        }
+
+        sqlx::query_as::<_, NotificationPreferences>(
+            "INSERT INTO notification_preferences
+                (owner, email, payment_receipts, expiry_reminders, renewal_failures, monthly_statements, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             ON CONFLICT (owner) DO UPDATE SET
+                email = EXCLUDED.email,
+                payment_receipts = EXCLUDED.payment_receipts,
+                expiry_reminders = EXCLUDED.expiry_reminders,
+                renewal_failures = EXCLUDED.renewal_failures,
+                monthly_statements = EXCLUDED.monthly_statements,
+                updated_at = EXCLUDED.updated_at
+             RETURNING *",
+        )
+        .bind(owner)
+        .bind(req.email.as_deref().map(str::trim))
+        .bind(req.payment_receipts)
+        .bind(req.expiry_reminders)
+        .bind(req.renewal_failures)
+        .bind(req.monthly_statements)
+        .bind(now())
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to save notification preferences: {}", e)))
+    }
+
+    /// Queues `template` for `owner` if they opted in to that kind of email.
+    pub fn notify(&self, owner: &str, template: EmailTemplate) {
+        let service = self.clone();
+        let owner = owner.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = service.deliver(&owner, &template).await {
+                tracing::warn!("Failed to notify {}: {}", owner, e);
+            }
+        });
+    }
+
+    /// Receipt for a create or renew payment, priced from the indexed subscription.
+    pub fn notify_payment(&self, pda: &str, signature: &str) {
+        let service = self.clone();
+        let pda = pda.to_string();
+        let signature = signature.to_string();
+        tokio::spawn(async move {
+            let subscription = match db::find_subscription(&service.pool, &pda).await {
+                Ok(Some(subscription)) => subscription,
+                Ok(None) => return,
+                Err(e) => {
+                    tracing::warn!("Failed to load subscription {} for receipt: {}", pda, e);
+                    return;
+                }
+            };
+            let template = EmailTemplate::PaymentReceipt {
+                subscription: pda,
+                plan_id: subscription.plan_id as u64,
+                amount: subscription.amount as u64,
+                signature,
+            };
+            if let Err(e) = service.deliver(&subscription.owner, &template).await {
+                tracing::warn!("Failed to send receipt to {}: {}", subscription.owner, e);
+            }
+        });
+    }
+
+    /// Sends `template` to an operator address, which has no preferences to honour.
+    pub async fn alert(&self, email: &str, template: &EmailTemplate) -> AppResult<()> {
+        self.email.send(email, template).await
+    }
+
+    /// Sends to the wallet's own address per its preferences. A wallet that never saved any
+    /// gets its email at the address the plan's merchant holds for it, if the customer
+    /// consented to be emailed.
+    pub async fn deliver(&self, owner: &str, template: &EmailTemplate) -> AppResult<()> {
+        let preferences = self.preferences(owner).await?;
+        if preferences.updated_at.is_none() {
+            let Some(plan_id) = template.plan_id() else { return Ok(()) };
+            let Some(row) = db::find_consented_customer_email(&self.pool, owner, plan_id as i64).await? else {
+                return Ok(());
+            };
+            return match customers::open_contact(&self.vault, row)?.email() {
+                Some(email) => self.email.send(email, template).await,
+                None => Ok(()),
+            };
+        }
+        match &preferences.email {
+            Some(email) if preferences.allows(template) => self.email.send(email, template).await,
+            _ => Ok(()),
+        }
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+// Controllers
+#[utoipa::path(
+    get,
+    path = "/api/v1/notifications/preferences",
+    tag = "notifications",
+    responses((status = 200, description = "Email preferences of the authenticated wallet", body = NotificationPreferences)),
+    security(("bearer_auth" = []))
+)]
+#[get("/notifications/preferences")]
+pub async fn get_preferences(
+    req: HttpRequest,
+    notifications: web::Data<NotificationService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let preferences = notifications.preferences(&auth_token.public_key).await?;
+    Ok(HttpResponse::Ok().json(preferences))
+}
+
+/// Replaces the email preferences. Every notification type is opt-in.
+#[utoipa::path(
+    put,
+    path = "/api/v1/notifications/preferences",
+    tag = "notifications",
+    request_body = NotificationPreferencesRequest,
+    responses(
+        (status = 200, description = "Preferences saved", body = NotificationPreferences),
+        (status = 422, description = "Invalid email, or notifications enabled without one", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[put("/notifications/preferences")]
+pub async fn update_preferences(
+    req: HttpRequest,
+    notifications: web::Data<NotificationService>,
+    body: ValidatedJson<NotificationPreferencesRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let preferences = notifications
+        .update_preferences(&auth_token.public_key, body.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(preferences))
+}
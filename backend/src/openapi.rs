@@ -0,0 +1,291 @@
+use actix_web::{get, HttpResponse};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use crate::{actions, airdrop, analytics, api_keys, audit, batch, calendar, channels, coupons, custody, customers, db, estimate, events, exports, health, intents, jobs, jwks, keeper, keystore, merchant, notifications, payments, plans, quota, receipts, refunds, reload, simulation, siws, solana_pay, statements, status, treasury, treasury_withdrawals, user_channels, validation, wallets, webhook_versions, webhooks};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Subscription Manager API",
+        description = "Backend for the on-chain subscription manager program. Routes are versioned under `/api/v1`; an unversioned `/api` path is served as the version asked for in `Accept` (`application/vnd.subscription-manager.v1+json`), version 1 by default."
+    ),
+    paths(
+        health::healthz,
+        health::readyz,
+        crate::auth_challenge,
+        crate::authenticate,
+        crate::refresh,
+        crate::logout,
+        custody::custodial_signup,
+        custody::custodial_login,
+        jwks::jwks,
+        status::membership_status,
+        solana_pay::pay_label,
+        solana_pay::pay_transaction,
+        actions::actions_manifest,
+        actions::subscribe_action,
+        actions::subscribe_transaction,
+        crate::create_subscription,
+        simulation::simulate_subscription,
+        batch::batch_subscriptions,
+        estimate::estimate_cost,
+        airdrop::request_airdrop,
+        crate::list_subscriptions,
+        crate::get_subscription,
+        crate::renew_subscription,
+        crate::cancel_subscription,
+        crate::close_subscription,
+        payments::list_onchain_payments,
+        receipts::payment_receipt,
+        receipts::verify_receipt,
+        receipts::receipt_key,
+        exports::export_subscription_payments,
+        intents::create_intent,
+        intents::get_intent,
+        intents::confirm_intent,
+        coupons::validate_coupon,
+        jobs::get_job,
+        quota::get_usage,
+        keeper::set_auto_renew,
+        notifications::get_preferences,
+        notifications::update_preferences,
+        user_channels::create_user_channel,
+        user_channels::list_user_channels,
+        user_channels::delete_user_channel,
+        calendar::create_calendar_token,
+        calendar::revoke_calendar_token,
+        calendar::calendar_feed,
+        wallets::link_challenge,
+        wallets::link_wallet,
+        wallets::list_wallets,
+        wallets::unlink_wallet,
+        custody::get_custodial_account,
+        custody::export_custodial_key,
+        custody::withdraw_custodial,
+        webhooks::register_webhook,
+        webhooks::list_webhooks,
+        webhooks::delete_webhook,
+        webhooks::list_webhook_deliveries,
+        webhooks::list_deliveries,
+        webhooks::replay_delivery,
+        webhooks::rotate_webhook_secret,
+        webhooks::pin_webhook_version,
+        webhooks::set_webhook_filter,
+        webhook_versions::list_webhook_schemas,
+        webhook_versions::get_webhook_schema,
+        webhooks::list_dead_letters,
+        webhooks::requeue_dead_letter,
+        api_keys::create_api_key,
+        api_keys::list_api_keys,
+        api_keys::rotate_api_key,
+        api_keys::revoke_api_key,
+        audit::list_audit_entries,
+        keeper::list_keeper_runs,
+        treasury::treasury_summary,
+        treasury::list_treasury_inflows,
+        refunds::approve_refund,
+        refunds::reject_refund,
+        treasury_withdrawals::request_treasury_withdrawal,
+        treasury_withdrawals::list_treasury_withdrawals,
+        treasury_withdrawals::get_treasury_withdrawal,
+        treasury_withdrawals::approve_treasury_withdrawal,
+        treasury_withdrawals::execute_treasury_withdrawal,
+        treasury_withdrawals::reject_treasury_withdrawal,
+        reload::reload_config,
+        jwks::rotate_jwt_key,
+        keystore::signer_status,
+        keystore::unseal_signer,
+        channels::create_channel,
+        channels::list_channels,
+        channels::delete_channel,
+        merchant::list_subscribers,
+        customers::list_customers,
+        customers::get_customer,
+        customers::upsert_customer,
+        customers::delete_customer,
+        plans::plan_catalog,
+        plans::create_plan,
+        plans::list_plans,
+        plans::get_plan,
+        plans::update_plan,
+        coupons::create_coupon,
+        coupons::list_coupons,
+        coupons::get_coupon,
+        coupons::disable_coupon,
+        payments::get_payment,
+        refunds::request_refund,
+        refunds::list_refunds,
+        refunds::get_refund,
+        analytics::mrr,
+        analytics::churn,
+        analytics::subscribers,
+        analytics::revenue,
+        exports::create_export,
+        events::poll_events,
+        exports::list_exports,
+        exports::get_export,
+        exports::download_export,
+        statements::get_statement,
+    ),
+    components(schemas(
+        crate::AuthRequest,
+        crate::AuthResponse,
+        crate::ChallengeResponse,
+        crate::RefreshRequest,
+        crate::LogoutRequest,
+        jwks::Jwk,
+        jwks::JwkSet,
+        jwks::JwtRotation,
+        crate::Role,
+        crate::SubscriptionRequest,
+        crate::SubscriptionResponse,
+        crate::accounts::Commitment,
+        status::MembershipStatus,
+        solana_pay::PayAction,
+        solana_pay::PayLabel,
+        solana_pay::PayRequest,
+        solana_pay::PayTransaction,
+        actions::ActionRule,
+        actions::ActionsManifest,
+        actions::ActionError,
+        actions::ActionMetadata,
+        actions::ActionRequest,
+        actions::ActionTransaction,
+        crate::SignatureResponse,
+        crate::ErrorResponse,
+        validation::FieldError,
+        siws::SiwsInput,
+        webhooks::WebhookEventType,
+        webhooks::Webhook,
+        webhooks::WebhookRequest,
+        webhooks::WebhookCreatedResponse,
+        webhooks::RotateSecretRequest,
+        webhooks::PinVersionRequest,
+        webhooks::SetFilterRequest,
+        webhook_versions::WebhookVersion,
+        webhook_versions::WebhookSchema,
+        webhooks::DeliveryStatus,
+        webhooks::WebhookDelivery,
+        api_keys::ApiKey,
+        api_keys::ApiKeyRequest,
+        api_keys::ApiKeySecretResponse,
+        intents::IntentRequest,
+        intents::ConfirmIntentRequest,
+        db::PaymentIntent,
+        plans::Plan,
+        plans::PlanRequest,
+        plans::PlanUpdate,
+        plans::CatalogPlan,
+        coupons::Coupon,
+        coupons::CouponRequest,
+        coupons::CouponValidation,
+        jobs::TransactionJob,
+        quota::QuotaUsage,
+        quota::UsageResponse,
+        keeper::AutoRenewRequest,
+        keeper::AutoRenewResponse,
+        db::KeeperRunRow,
+        db::DeadLetterRow,
+        treasury::TokenBalance,
+        treasury::TreasuryBalance,
+        treasury::TreasurySummary,
+        db::TreasuryInflowRow,
+        db::TreasuryInflowTotals,
+        db::AuditEntry,
+        payments::ProgramInstruction,
+        payments::OnChainPayment,
+        payments::PaymentHistory,
+        payments::PaymentLookup,
+        receipts::ReceiptPayment,
+        receipts::Receipt,
+        receipts::SignedReceipt,
+        receipts::VerifyReceiptRequest,
+        receipts::ReceiptVerification,
+        receipts::ReceiptKey,
+        refunds::RefundRequest,
+        refunds::RejectRefundRequest,
+        treasury_withdrawals::TreasuryWithdrawalRequest,
+        treasury_withdrawals::RejectTreasuryWithdrawalRequest,
+        reload::ReloadReport,
+        keystore::SignerStatus,
+        keystore::UnsealRequest,
+        db::Refund,
+        db::TreasuryWithdrawal,
+        channels::ChannelKind,
+        channels::Channel,
+        channels::ChannelRequest,
+        notifications::NotificationPreferences,
+        notifications::NotificationPreferencesRequest,
+        user_channels::UserChannelKind,
+        user_channels::UserChannel,
+        user_channels::UserChannelRequest,
+        user_channels::UserChannelCreated,
+        calendar::CalendarToken,
+        wallets::LinkChallengeRequest,
+        wallets::LinkChallenge,
+        wallets::LinkWalletRequest,
+        wallets::LinkedWallet,
+        wallets::LinkedWallets,
+        custody::CustodialSignupRequest,
+        custody::CustodialLoginRequest,
+        custody::CustodialAccount,
+        custody::ExportKeyRequest,
+        custody::ExportedKey,
+        custody::WithdrawalRequest,
+        custody::WithdrawalResponse,
+        customers::Customer,
+        customers::CustomerRequest,
+        customers::CustomerContact,
+        merchant::SubscriberResponse,
+        analytics::Granularity,
+        analytics::MrrResponse,
+        analytics::ChurnResponse,
+        analytics::SubscriberBreakdown,
+        analytics::RevenuePoint,
+        simulation::SimulatedAction,
+        simulation::SimulationRequest,
+        simulation::SimulatedError,
+        simulation::SimulationResponse,
+        batch::BatchAction,
+        batch::BatchRequest,
+        batch::BatchItemStatus,
+        batch::BatchItemResult,
+        batch::BatchResponse,
+        estimate::CostEstimate,
+        airdrop::AirdropRequest,
+        airdrop::AirdropResponse,
+        exports::ExportFormat,
+        exports::PaymentRecord,
+        exports::ExportJobRequest,
+        exports::ExportJob,
+        events::EventsResponse,
+        events::PolledEvent,
+        statements::StatementFormat,
+        statements::StatementLine,
+        statements::Statement,
+        health::HealthStatus,
+        health::ComponentStatus,
+        health::HealthResponse,
+    )),
+    modifiers(&SecurityAddon)
+)]
+pub struct ApiDoc;
+
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer_auth",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).bearer_format("JWT").build()),
+        );
+        components.add_security_scheme("api_key", SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Api-Key"))));
+    }
+}
+
+// Controllers
+#[get("/api/openapi.json")]
+pub async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
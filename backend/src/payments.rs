@@ -0,0 +1,393 @@
+use actix_web::{get, web, HttpMessage, HttpRequest, HttpResponse};
+use futures_util::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use solana_client::client_error::ClientErrorKind;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, UiTransactionEncoding};
+use sqlx::postgres::PgPool;
+use std::collections::HashSet;
+use std::str::FromStr;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+use crate::cluster::SolanaClusters;
+use crate::db;
+use crate::indexer::InstructionKind;
+use crate::layout::{ProgramLayout, FIXED_AMOUNT_LAMPORTS};
+use crate::metrics;
+use crate::plans::PlanService;
+use crate::price::{lamports_to_usd, PriceFeed};
+use crate::validation::ValidatedQuery;
+use crate::{AppError, AppResult, AuthToken, Role, SolanaService, SubscriptionResponse};
+
+const DEFAULT_HISTORY_LIMIT: usize = 100;
+// Transactions fetched at once while reconstructing a history
+const HISTORY_CONCURRENCY: usize = 8;
+
+// Models
+/// One instruction of the subscription program within the transaction.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct ProgramInstruction {
+    instruction_index: u32,
+    instruction: String,            // create_subscription, renew_subscription, ...
+    subscription: String,           // PDA
+    owner: Option<String>,
+    treasury: Option<String>,       // Payments only
+    plan_id: Option<u64>,           // None if the subscription is no longer readable
+    amount: Option<u64>,            // Lamports charged; payments only
+    amount_usd: Option<f64>,        // At the current SOL/USD rate
+    paid_usd: Option<f64>,          // At the rate when it was indexed, if indexed as it landed
+    indexed: bool,                  // Whether the index holds this payment
+    refunded_lamports: Option<u64>, // Indexed payments only
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct PaymentLookup {
+    signature: String,
+    cluster: String,
+    slot: u64,
+    block_time: Option<i64>,
+    succeeded: bool,
+    error: Option<String>, // Why the transaction failed
+    fee: Option<u64>,      // Lamports
+    instructions: Vec<ProgramInstruction>,
+    subscriptions: Vec<SubscriptionResponse>, // Current state of each affected subscription
+}
+
+/// A create or renew of the subscription, read from its transaction.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct OnChainPayment {
+    signature: String,
+    instruction_index: u32,
+    instruction: String, // create_subscription | renew_subscription
+    slot: u64,
+    block_time: Option<i64>,
+    amount: Option<u64>, // Lamports; None if the subscription is no longer readable
+    amount_usd: Option<f64>, // At the current SOL/USD rate
+    paid_usd: Option<f64>,   // At the rate when it was indexed, if indexed as it landed
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct PaymentHistory {
+    subscription: String,          // PDA
+    payments: Vec<OnChainPayment>, // Newest first
+    next_before: Option<String>,   // Pass as `before` for older payments; None once exhausted
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct HistoryQuery {
+    before: Option<String>, // Signature to continue from, exclusive
+    #[validate(range(min = 1, max = 1000, message = "must be between 1 and 1000"))]
+    limit: Option<usize>, // Transactions scanned, not payments returned
+}
+
+/// Lamports a payment instruction charged. Creates carry their amount in the instruction data
+/// on the flexible program; renewals charge the subscription's stored amount.
+fn charged(
+    layout: ProgramLayout,
+    kind: InstructionKind,
+    data: &[u8],
+    subscription: Option<&SubscriptionResponse>,
+) -> Option<u64> {
+    match (kind, layout) {
+        (InstructionKind::Create, ProgramLayout::Fixed) => Some(FIXED_AMOUNT_LAMPORTS),
+        (InstructionKind::Create, ProgramLayout::Flexible) => {
+            data.get(24..32).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        }
+        (InstructionKind::Renew, _) => subscription.map(|sub| sub.amount),
+        _ => None,
+    }
+}
+
+async fn fetch_transaction(
+    solana_service: &SolanaService,
+    signature: &str,
+) -> AppResult<EncodedConfirmedTransactionWithStatusMeta> {
+    let sig = Signature::from_str(signature).map_err(|e| AppError::BadRequest(format!("Invalid signature: {}", e)))?;
+    metrics::observe_rpc(
+        "getTransaction",
+        solana_service.rpc.client().get_transaction_with_config(
+            &sig,
+            RpcTransactionConfig {
+                encoding: Some(UiTransactionEncoding::Base64),
+                commitment: Some(CommitmentConfig::confirmed()),
+                max_supported_transaction_version: Some(0),
+            },
+        ),
+    )
+    .await
+    .map_err(|e| match e.kind() {
+        // The node answers null for signatures it has no record of
+        ClientErrorKind::SerdeJson(_) => AppError::NotFound(format!("Transaction {} not found", signature)),
+        _ => AppError::rpc(&format!("Failed to fetch transaction {}", signature), e),
+    })
+}
+
+/// Creates and renewals of `pda` within one transaction.
+async fn payments_in(
+    solana_service: &SolanaService,
+    pda: &Pubkey,
+    subscription: Option<&SubscriptionResponse>,
+    signature: String,
+) -> AppResult<Vec<OnChainPayment>> {
+    let tx = fetch_transaction(solana_service, &signature).await?;
+    let versioned = tx
+        .transaction
+        .transaction
+        .decode()
+        .ok_or_else(|| AppError::SolanaError(format!("Failed to decode transaction {}", signature)))?;
+    let keys = versioned.message.static_account_keys();
+    let mut payments = Vec::new();
+    for (index, ix) in versioned.message.instructions().iter().enumerate() {
+        if keys.get(ix.program_id_index as usize) != Some(&solana_service.program_id) {
+            continue;
+        }
+        let Some(kind) = InstructionKind::from_data(&ix.data).filter(|kind| kind.is_payment()) else { continue };
+        if ix.accounts.first().and_then(|i| keys.get(*i as usize)) != Some(pda) {
+            continue;
+        }
+        payments.push(OnChainPayment {
+            signature: signature.clone(),
+            instruction_index: index as u32,
+            instruction: kind.name().to_string(),
+            slot: tx.slot,
+            block_time: tx.block_time,
+            amount: charged(solana_service.layout, kind, &ix.data, subscription),
+            amount_usd: None,
+            paid_usd: None,
+        });
+    }
+    // Instructions run in order, so the last one of the transaction is the newest
+    payments.reverse();
+    Ok(payments)
+}
+
+/// The subscription as it is now, or as the index last saw it if the account was closed.
+async fn subscription_state(
+    solana_service: &SolanaService,
+    pool: &PgPool,
+    pda: &Pubkey,
+) -> AppResult<Option<SubscriptionResponse>> {
+    let response = metrics::observe_rpc(
+        "getAccountInfo",
+        solana_service.rpc.client().get_account_with_commitment(pda, CommitmentConfig::confirmed()),
+    )
+    .await
+    .map_err(|e| AppError::rpc("Failed to fetch account", e))?;
+    if let Some(account) = response.value {
+        let subscription = solana_service.decoder.decode(&account)?;
+        return Ok(Some(SubscriptionResponse {
+            id: pda.to_string(),
+            plan_id: subscription.plan_id,
+            duration: subscription.duration,
+            amount: subscription.amount,
+            active: subscription.active,
+            start_time: subscription.start_time,
+            history: subscription.history,
+            owner: subscription.user.to_string(),
+        }));
+    }
+    if !solana_service.is_primary() {
+        return Ok(None);
+    }
+    Ok(db::find_subscription(pool, &pda.to_string()).await?.map(SubscriptionResponse::from))
+}
+
+// Controllers
+/// Fetches a transaction, picks out its subscription program instructions and reports the
+/// subscriptions and payments they affected, for support and reconciliation. Merchants only
+/// see transactions whose every instruction is for one of their plans.
+#[utoipa::path(
+    get,
+    path = "/api/v1/payments/{signature}",
+    tag = "payments",
+    params(("signature" = String, Path, description = "Transaction signature")),
+    responses(
+        (status = 200, description = "Program instructions of the transaction", body = PaymentLookup),
+        (status = 400, description = "Invalid signature", body = ErrorResponse),
+        (status = 403, description = "Merchant role required, or the transaction is for another merchant's plan", body = ErrorResponse),
+        (status = 404, description = "Transaction not found, or it has no subscription program instruction", body = ErrorResponse),
+        (status = 502, description = "RPC node unavailable", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/{signature}")]
+pub async fn get_payment(
+    req: HttpRequest,
+    path: web::Path<String>,
+    clusters: web::Data<SolanaClusters>,
+    pool: web::Data<PgPool>,
+    plans: web::Data<PlanService>,
+    prices: web::Data<PriceFeed>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let solana_service = clusters.select(&req)?;
+    let signature = path.into_inner();
+    let tx = fetch_transaction(solana_service, &signature).await?;
+    let versioned = tx
+        .transaction
+        .transaction
+        .decode()
+        .ok_or_else(|| AppError::SolanaError(format!("Failed to decode transaction {}", signature)))?;
+    let meta = tx.transaction.meta.as_ref();
+
+    let keys = versioned.message.static_account_keys();
+    let mut instructions = Vec::new();
+    let mut subscriptions: Vec<SubscriptionResponse> = Vec::new();
+    let mut seen = HashSet::new();
+    let payments = if solana_service.is_primary() {
+        db::list_payments_by_signature(&pool, &signature).await?
+    } else {
+        Vec::new()
+    };
+    let sol_usd = prices.sol_usd().await;
+    for (index, ix) in versioned.message.instructions().iter().enumerate() {
+        if keys.get(ix.program_id_index as usize) != Some(&solana_service.program_id) {
+            continue;
+        }
+        let Some(kind) = InstructionKind::from_data(&ix.data) else { continue };
+        let account = |position: usize| ix.accounts.get(position).and_then(|i| keys.get(*i as usize)).copied();
+        let Some(pda) = account(0) else { continue };
+
+        if seen.insert(pda) {
+            if let Some(subscription) = subscription_state(solana_service, &pool, &pda).await? {
+                subscriptions.push(subscription);
+            }
+        }
+        let subscription = subscriptions.iter().find(|sub| sub.id == pda.to_string());
+        let plan_id = match kind {
+            InstructionKind::Create => ix.data.get(8..16).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap())),
+            _ => subscription.map(|sub| sub.plan_id),
+        };
+        let payment = payments.iter().find(|p| p.instruction_index == index as i32);
+        let amount = kind
+            .is_payment()
+            .then(|| payment.map(|p| p.amount as u64).or_else(|| charged(solana_service.layout, kind, &ix.data, subscription)))
+            .flatten();
+        instructions.push(ProgramInstruction {
+            instruction_index: index as u32,
+            instruction: kind.name().to_string(),
+            subscription: pda.to_string(),
+            owner: account(1).map(|owner| owner.to_string()),
+            treasury: kind.is_payment().then(|| account(2)).flatten().map(|treasury| treasury.to_string()),
+            plan_id,
+            amount,
+            amount_usd: amount.and_then(|amount| lamports_to_usd(amount, sol_usd)),
+            paid_usd: payment.and_then(|p| lamports_to_usd(p.amount as u64, p.sol_usd)),
+            indexed: payment.is_some(),
+            refunded_lamports: payment.map(|p| p.refunded_lamports as u64),
+        });
+    }
+    if instructions.is_empty() {
+        return Err(AppError::NotFound(format!("Transaction {} has no subscription program instruction", signature)));
+    }
+    for instruction in &instructions {
+        match instruction.plan_id {
+            Some(plan_id) => plans.authorize(&auth_token, plan_id).await?,
+            None if !auth_token.has_role(Role::Admin) => {
+                return Err(AppError::Forbidden(format!("The plan of {} is unknown", instruction.subscription)))
+            }
+            None => {}
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(PaymentLookup {
+        signature,
+        cluster: solana_service.cluster.as_str().to_string(),
+        slot: tx.slot,
+        block_time: tx.block_time,
+        succeeded: meta.is_none_or(|meta| meta.err.is_none()),
+        error: meta.and_then(|meta| meta.err.as_ref()).map(|err| format!("{:?}", err)),
+        fee: meta.map(|meta| meta.fee),
+        instructions,
+        subscriptions,
+    }))
+}
+
+/// Reconstructs the subscription's payment history from the transactions that touched its
+/// PDA. The account's own `history` only keeps the last 10 payments; this walks every
+/// transaction the RPC node still has, a page at a time.
+#[utoipa::path(
+    get,
+    path = "/api/v1/subscriptions/{plan_id}/payments",
+    tag = "subscriptions",
+    params(("plan_id" = u64, Path, description = "Plan identifier"), HistoryQuery),
+    responses(
+        (status = 200, description = "A page of payments, newest first", body = PaymentHistory),
+        (status = 400, description = "Invalid `before` signature", body = ErrorResponse),
+        (status = 422, description = "Invalid query", body = ErrorResponse),
+        (status = 502, description = "RPC node unavailable", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/subscriptions/{plan_id}/payments")]
+pub async fn list_onchain_payments(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    query: ValidatedQuery<HistoryQuery>,
+    clusters: web::Data<SolanaClusters>,
+    pool: web::Data<PgPool>,
+    prices: web::Data<PriceFeed>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let solana_service = clusters.select(&req)?;
+    let pda = solana_service.subscription_address(&auth_token.public_key, path.into_inner())?;
+    let before = query
+        .before
+        .as_deref()
+        .map(Signature::from_str)
+        .transpose()
+        .map_err(|e| AppError::BadRequest(format!("Invalid before signature: {}", e)))?;
+    let limit = query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT);
+
+    let page = metrics::observe_rpc(
+        "getSignaturesForAddress",
+        solana_service.rpc.client().get_signatures_for_address_with_config(
+            &pda,
+            GetConfirmedSignaturesForAddress2Config {
+                before,
+                until: None,
+                limit: Some(limit),
+                commitment: Some(CommitmentConfig::confirmed()),
+            },
+        ),
+    )
+    .await
+    .map_err(|e| AppError::rpc("Failed to fetch signatures", e))?;
+    let next_before = (page.len() == limit).then(|| page.last().map(|status| status.signature.clone())).flatten();
+
+    let subscription = subscription_state(solana_service, &pool, &pda).await?;
+    let signatures = page.into_iter().filter(|status| status.err.is_none()).map(|status| status.signature);
+    let pages: Vec<AppResult<Vec<OnChainPayment>>> = stream::iter(signatures)
+        .map(|signature| payments_in(solana_service, &pda, subscription.as_ref(), signature))
+        .buffered(HISTORY_CONCURRENCY)
+        .collect()
+        .await;
+    let mut payments = Vec::new();
+    for page in pages {
+        payments.extend(page?);
+    }
+
+    let indexed = if solana_service.is_primary() {
+        db::list_payments_for(&pool, &[pda.to_string()]).await?
+    } else {
+        Vec::new()
+    };
+    let sol_usd = prices.sol_usd().await;
+    for payment in &mut payments {
+        let row = indexed
+            .iter()
+            .find(|row| row.signature == payment.signature && row.instruction_index == payment.instruction_index as i32);
+        payment.amount_usd = payment.amount.and_then(|amount| lamports_to_usd(amount, sol_usd));
+        payment.paid_usd = row.and_then(|row| lamports_to_usd(row.amount as u64, row.sol_usd));
+    }
+
+    Ok(HttpResponse::Ok().json(PaymentHistory {
+        subscription: pda.to_string(),
+        payments,
+        next_before,
+    }))
+}
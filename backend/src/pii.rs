@@ -0,0 +1,176 @@
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use std::collections::HashMap;
+use std::sync::Arc;
+use zeroize::Zeroizing;
+use crate::tenant::TenantConfig;
+use crate::{AppError, AppResult};
+
+// Marks a sealed value; anything else was stored before the tenant had a key
+pub const SEALED_PREFIX: &str = "enc:v1:";
+
+/// A tenant's key for personal data at rest, 32 bytes in base64. Never printed.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PiiKey(Zeroizing<[u8; 32]>);
+
+impl PiiKey {
+    pub fn parse(value: &str, name: &str) -> Self {
+        let bytes = Zeroizing::new(BASE64.decode(value.trim()).unwrap_or_else(|_| panic!("{} is not base64", name)));
+        let key: [u8; 32] = bytes[..].try_into().unwrap_or_else(|_| panic!("{} must be 32 bytes", name));
+        Self(Zeroizing::new(key))
+    }
+}
+
+impl std::fmt::Debug for PiiKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PiiKey(..)")
+    }
+}
+
+/// Seals customer contact details with AES-256-GCM under the key of the tenant they belong
+/// to, bound to the record and field they are stored in so a value cannot be moved to
+/// another row. Tenants without a key store them in the clear; values stored before a key
+/// was set are read as they are and sealed by `CustomerService::seal_existing`.
+#[derive(Clone)]
+pub struct PiiVault {
+    ciphers: Arc<HashMap<String, Aes256Gcm>>,
+}
+
+impl PiiVault {
+    pub fn new(tenants: &[TenantConfig]) -> Self {
+        let ciphers = tenants
+            .iter()
+            .filter_map(|tenant| {
+                let key = tenant.pii_key.as_ref()?;
+                Some((tenant.id.clone(), Aes256Gcm::new_from_slice(&key.0[..]).expect("PII key is 32 bytes")))
+            })
+            .collect();
+        Self { ciphers: Arc::new(ciphers) }
+    }
+
+    pub fn is_enabled(&self, tenant: &str) -> bool {
+        self.ciphers.contains_key(tenant)
+    }
+
+    /// `value` sealed for `context`, e.g. `customers:<merchant>:<wallet>:email`, or as it is
+    /// when the tenant has no key.
+    pub fn seal(&self, tenant: &str, context: &str, value: &str) -> AppResult<String> {
+        let Some(cipher) = self.ciphers.get(tenant) else {
+            return Ok(value.to_string());
+        };
+        let nonce: [u8; 12] = rand::random();
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: value.as_bytes(), aad: context.as_bytes() })
+            .map_err(|_| AppError::InternalServerError("Failed to encrypt personal data".to_string()))?;
+        Ok(format!("{}{}", SEALED_PREFIX, BASE64.encode([&nonce[..], &ciphertext[..]].concat())))
+    }
+
+    /// The value `seal` was given. Values stored in the clear are returned as they are.
+    pub fn open(&self, tenant: &str, context: &str, value: &str) -> AppResult<String> {
+        let Some(sealed) = value.strip_prefix(SEALED_PREFIX) else {
+            return Ok(value.to_string());
+        };
+        let unusable = |reason: &str| {
+            AppError::InternalServerError(format!("Personal data of {} cannot be decrypted: {}", context, reason))
+        };
+        let cipher = self.ciphers.get(tenant).ok_or_else(|| unusable("the tenant has no PII key"))?;
+        let sealed = BASE64.decode(sealed).map_err(|_| unusable("not base64"))?;
+        if sealed.len() < 12 {
+            return Err(unusable("too short"));
+        }
+        let (nonce, ciphertext) = sealed.split_at(12);
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: context.as_bytes() })
+            .map_err(|_| unusable("wrong key or tampered"))?;
+        String::from_utf8(plaintext).map_err(|_| unusable("not UTF-8"))
+    }
+
+    pub fn seal_opt(&self, tenant: &str, context: &str, value: Option<&str>) -> AppResult<Option<String>> {
+        value.map(|value| self.seal(tenant, context, value)).transpose()
+    }
+
+    pub fn open_opt(&self, tenant: &str, context: &str, value: Option<String>) -> AppResult<Option<String>> {
+        value.map(|value| self.open(tenant, context, &value)).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTEXT: &str = "customers:merchant:wallet:email";
+
+    fn vault() -> PiiVault {
+        let key = PiiKey::parse(&BASE64.encode([7u8; 32]), "PII_KEY");
+        let cipher = Aes256Gcm::new_from_slice(&key.0[..]).unwrap();
+        PiiVault { ciphers: Arc::new(HashMap::from([("acme".to_string(), cipher)])) }
+    }
+
+    #[test]
+    fn seal_and_open_round_trip() {
+        let vault = vault();
+        let sealed = vault.seal("acme", CONTEXT, "ada@example.com").unwrap();
+        assert!(sealed.starts_with(SEALED_PREFIX));
+        assert!(!sealed.contains("ada@example.com"));
+        assert_eq!(vault.open("acme", CONTEXT, &sealed).unwrap(), "ada@example.com");
+    }
+
+    #[test]
+    fn sealing_twice_uses_fresh_nonces() {
+        let vault = vault();
+        assert_ne!(vault.seal("acme", CONTEXT, "x").unwrap(), vault.seal("acme", CONTEXT, "x").unwrap());
+    }
+
+    #[test]
+    fn tampered_value_is_rejected() {
+        let vault = vault();
+        let sealed = vault.seal("acme", CONTEXT, "ada@example.com").unwrap();
+        let mut bytes = BASE64.decode(sealed.strip_prefix(SEALED_PREFIX).unwrap()).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        let tampered = format!("{}{}", SEALED_PREFIX, BASE64.encode(bytes));
+        assert!(vault.open("acme", CONTEXT, &tampered).is_err());
+    }
+
+    #[test]
+    fn value_moved_to_another_field_is_rejected() {
+        let vault = vault();
+        let sealed = vault.seal("acme", CONTEXT, "ada@example.com").unwrap();
+        assert!(vault.open("acme", "customers:merchant:wallet:name", &sealed).is_err());
+    }
+
+    #[test]
+    fn malformed_values_are_rejected() {
+        let vault = vault();
+        assert!(vault.open("acme", CONTEXT, &format!("{}not base64!", SEALED_PREFIX)).is_err());
+        assert!(vault.open("acme", CONTEXT, &format!("{}{}", SEALED_PREFIX, BASE64.encode([1u8; 4]))).is_err());
+    }
+
+    #[test]
+    fn sealed_value_needs_the_tenants_key() {
+        let vault = vault();
+        let sealed = vault.seal("acme", CONTEXT, "ada@example.com").unwrap();
+        assert!(vault.open("globex", CONTEXT, &sealed).is_err());
+    }
+
+    #[test]
+    fn plaintext_passes_through() {
+        let vault = vault();
+        assert_eq!(vault.open("acme", CONTEXT, "stored before the key").unwrap(), "stored before the key");
+        assert_eq!(vault.seal("globex", CONTEXT, "no key").unwrap(), "no key");
+        assert!(!vault.is_enabled("globex"));
+    }
+
+    #[test]
+    fn optional_values_stay_absent() {
+        let vault = vault();
+        assert_eq!(vault.seal_opt("acme", CONTEXT, None).unwrap(), None);
+        assert_eq!(vault.open_opt("acme", CONTEXT, None).unwrap(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be 32 bytes")]
+    fn short_key_is_refused() {
+        PiiKey::parse(&BASE64.encode([7u8; 16]), "PII_KEY");
+    }
+}
@@ -0,0 +1,706 @@
+use actix_web::{get, patch, post, web, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+use crate::cache::{CacheNamespace, ResponseCache};
+use crate::cluster::SolanaClusters;
+use crate::db;
+use crate::validation::{validate_pubkey, FieldError, ValidatedJson, ValidatedQuery};
+use crate::{AppError, AppResult, AuthToken, Role, SolanaService, SubscriptionRequest};
+
+const MAX_METADATA_BYTES: usize = 8 * 1024;
+
+// Models
+#[derive(Debug, Clone, sqlx::FromRow)]
+struct PlanRow {
+    plan_id: i64,
+    merchant: String,
+    name: String,
+    description: Option<String>,
+    image_url: Option<String>,
+    metadata: Option<String>,
+    duration: i64,
+    amount: i64,
+    scheduled_amount: Option<i64>,
+    scheduled_at: Option<i64>,
+    archived_at: Option<i64>,
+    created_at: i64,
+    updated_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Plan {
+    plan_id: u64,
+    merchant: String, // Authority allowed to edit the plan
+    name: String,
+    description: Option<String>,
+    image_url: Option<String>,
+    #[schema(value_type = Option<Object>)]
+    metadata: Option<serde_json::Value>,
+    duration: u64, // Seconds
+    amount: u64,   // Lamports, as of now
+    scheduled_amount: Option<u64>,
+    scheduled_at: Option<i64>, // When scheduled_amount replaces amount
+    archived: bool,
+    archived_at: Option<i64>,
+    created_at: i64,
+    updated_at: i64,
+}
+
+impl From<PlanRow> for Plan {
+    fn from(row: PlanRow) -> Self {
+        Plan {
+            plan_id: row.plan_id as u64,
+            merchant: row.merchant,
+            name: row.name,
+            description: row.description,
+            image_url: row.image_url,
+            metadata: row.metadata.and_then(|metadata| serde_json::from_str(&metadata).ok()),
+            duration: row.duration as u64,
+            amount: row.amount as u64,
+            scheduled_amount: row.scheduled_amount.map(|amount| amount as u64),
+            scheduled_at: row.scheduled_at,
+            archived: row.archived_at.is_some(),
+            archived_at: row.archived_at,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+impl Plan {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn image_url(&self) -> Option<&str> {
+        self.image_url.as_deref()
+    }
+
+    pub fn archived(&self) -> bool {
+        self.archived
+    }
+
+    /// The plan's current price, as the terms of a create.
+    pub fn terms(&self) -> SubscriptionRequest {
+        SubscriptionRequest { plan_id: self.plan_id, duration: self.duration, amount: self.amount }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct PlanRequest {
+    #[validate(range(max = 9223372036854775807, message = "must fit in a signed 64-bit integer"))]
+    plan_id: u64,
+    #[validate(length(min = 1, max = 100, message = "must be 1 to 100 characters"))]
+    name: String,
+    #[validate(length(max = 2000, message = "must be at most 2000 characters"))]
+    description: Option<String>,
+    #[validate(url(message = "must be an absolute URL"))]
+    image_url: Option<String>,
+    #[schema(value_type = Option<Object>)]
+    metadata: Option<serde_json::Value>, // JSON object of up to 8 KiB
+    duration: u64,
+    amount: u64,
+    #[validate(custom = "validate_pubkey")]
+    merchant: Option<String>, // Admins only: the merchant the plan is assigned to; default the caller
+}
+
+/// Fields left out are unchanged.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct PlanUpdate {
+    #[validate(length(min = 1, max = 100, message = "must be 1 to 100 characters"))]
+    name: Option<String>,
+    #[validate(length(max = 2000, message = "must be at most 2000 characters"))]
+    description: Option<String>,
+    #[validate(url(message = "must be an absolute URL"))]
+    image_url: Option<String>,
+    #[schema(value_type = Option<Object>)]
+    metadata: Option<serde_json::Value>,
+    duration: Option<u64>,
+    amount: Option<u64>,           // Takes effect immediately
+    scheduled_amount: Option<u64>, // Set with scheduled_at
+    scheduled_at: Option<i64>,
+    cancel_scheduled_price: Option<bool>,
+    archived: Option<bool>,
+}
+
+/// A plan as listed to checkout UIs, with its indexed subscriber counts.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct CatalogPlan {
+    plan_id: u64,
+    merchant: String,
+    name: String,
+    description: Option<String>,
+    image_url: Option<String>,
+    #[schema(value_type = Option<Object>)]
+    metadata: Option<serde_json::Value>,
+    duration: u64, // Seconds
+    amount: u64,   // Lamports, as of now
+    mint: Option<String>, // SPL mint the price is in; None for native SOL, the only one the program takes
+    scheduled_amount: Option<u64>,
+    scheduled_at: Option<i64>,
+    subscribers: i64,        // Open subscriptions
+    active_subscribers: i64, // Active and paid up
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct PlansQuery {
+    include_archived: Option<bool>,
+}
+
+/// The plans a merchant request may read: every plan for admins, otherwise the caller's own.
+#[derive(Debug, Clone)]
+pub struct PlanScope(Option<Vec<i64>>); // None for every plan
+
+impl PlanScope {
+    pub fn plan_ids(&self) -> Option<&[i64]> {
+        self.0.as_deref()
+    }
+
+    pub fn allows(&self, plan_id: i64) -> bool {
+        self.0.as_ref().is_none_or(|plan_ids| plan_ids.contains(&plan_id))
+    }
+
+    /// The plan ids to filter on, `None` meaning every plan: `plan_id` alone when given, which
+    /// must be in scope, otherwise the whole scope.
+    pub fn narrow(&self, plan_id: Option<u64>) -> AppResult<Option<Vec<i64>>> {
+        match plan_id.map(|id| id as i64) {
+            Some(id) if !self.allows(id) => Err(not_yours(id as u64)),
+            Some(id) => Ok(Some(vec![id])),
+            None => Ok(self.0.clone()),
+        }
+    }
+}
+
+fn not_yours(plan_id: u64) -> AppError {
+    AppError::Forbidden(format!("Plan {} belongs to another merchant", plan_id))
+}
+
+/// Whether a scheduled price among `plans` has come due. Reading plans applies it, so cached
+/// plans showing one are read again.
+fn price_due(scheduled_at: impl IntoIterator<Item = Option<i64>>) -> bool {
+    let now = now();
+    scheduled_at.into_iter().any(|at| at.is_some_and(|at| at <= now))
+}
+
+fn metadata_text(metadata: Option<&serde_json::Value>) -> AppResult<Option<String>> {
+    let Some(metadata) = metadata else {
+        return Ok(None);
+    };
+    if !metadata.is_object() {
+        return Err(invalid("metadata", "must be a JSON object"));
+    }
+    let text = metadata.to_string();
+    if text.len() > MAX_METADATA_BYTES {
+        return Err(invalid("metadata", "must be at most 8 KiB"));
+    }
+    Ok(Some(text))
+}
+
+/// Plans are priced in the terms of a create, so the same limits and program layout apply.
+fn check_price(solana_service: &SolanaService, field: &str, plan_id: u64, duration: u64, amount: u64) -> AppResult<()> {
+    let request = SubscriptionRequest { plan_id, duration, amount };
+    solana_service.check_terms(&request).map_err(|e| match e {
+        AppError::BadRequest(message) => invalid(field, &message),
+        e => e,
+    })
+}
+
+fn invalid(field: &str, message: &str) -> AppError {
+    AppError::Validation(vec![FieldError::new(field, "invalid", message)])
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+// Plan Service
+/// The merchant plan catalog. The program has no Plan account: a plan id is just a seed of the
+/// subscription PDA, so the name, price and lifecycle of a plan are kept here and editing
+/// them sends no transaction. Scheduled prices are applied lazily, whenever plans are read.
+///
+/// For the same reason a plan's merchant cannot be checked on chain: the catalog is what ties
+/// a plan id, and so its subscribers and payments, to a merchant. Plans on chain that are not
+/// in the catalog belong to no merchant and are only visible to admins, who alone can assign
+/// them; a merchant can only claim a plan id nobody has subscribed to yet.
+#[derive(Clone)]
+pub struct PlanService {
+    pool: PgPool,
+    merchants: Arc<RwLock<HashMap<u64, String>>>, // plan id -> merchant; a plan never changes hands
+}
+
+impl PlanService {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            merchants: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// The merchant that owns `plan_id`, or `None` when it is not in the catalog.
+    pub async fn merchant_of(&self, plan_id: u64) -> AppResult<Option<String>> {
+        if let Some(merchant) = self.merchants.read().unwrap().get(&plan_id) {
+            return Ok(Some(merchant.clone()));
+        }
+        let merchant: Option<String> = sqlx::query_scalar("SELECT merchant FROM plans WHERE plan_id = $1")
+            .bind(plan_id as i64)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to fetch plan merchant: {}", e)))?;
+        if let Some(merchant) = &merchant {
+            self.merchants.write().unwrap().insert(plan_id, merchant.clone());
+        }
+        Ok(merchant)
+    }
+
+    /// Fails with `403` unless the caller is an admin or the merchant of `plan_id`.
+    pub async fn authorize(&self, auth_token: &AuthToken, plan_id: u64) -> AppResult<()> {
+        if auth_token.has_role(Role::Admin) {
+            return Ok(());
+        }
+        match self.merchant_of(plan_id).await? {
+            Some(merchant) if merchant == auth_token.public_key => Ok(()),
+            _ => Err(not_yours(plan_id)),
+        }
+    }
+
+    /// The plans `merchant` owns, whatever its roles.
+    pub async fn merchant_plan_ids(&self, merchant: &str) -> AppResult<Vec<i64>> {
+        sqlx::query_scalar("SELECT plan_id FROM plans WHERE merchant = $1 ORDER BY plan_id")
+            .bind(merchant)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to list merchant plans: {}", e)))
+    }
+
+    /// The plans the caller may read: every plan for admins, otherwise the caller's own.
+    pub async fn scope(&self, auth_token: &AuthToken) -> AppResult<PlanScope> {
+        if auth_token.has_role(Role::Admin) {
+            return Ok(PlanScope(None));
+        }
+        let plan_ids = self.merchant_plan_ids(&auth_token.public_key).await?;
+        let mut merchants = self.merchants.write().unwrap();
+        for plan_id in &plan_ids {
+            merchants.insert(*plan_id as u64, auth_token.public_key.clone());
+        }
+        Ok(PlanScope(Some(plan_ids)))
+    }
+
+    async fn apply_scheduled_prices(&self) -> AppResult<()> {
+        sqlx::query(
+            "UPDATE plans
+             SET amount = scheduled_amount, updated_at = scheduled_at, scheduled_amount = NULL, scheduled_at = NULL
+             WHERE scheduled_at <= $1",
+        )
+        .bind(now())
+        .execute(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to apply scheduled prices: {}", e)))?;
+        Ok(())
+    }
+
+    pub async fn create(&self, auth_token: &AuthToken, solana_service: &SolanaService, req: PlanRequest) -> AppResult<Plan> {
+        let is_admin = auth_token.has_role(Role::Admin);
+        if req.merchant.is_some() && !is_admin {
+            return Err(AppError::Forbidden("Only admins can assign a plan to another merchant".to_string()));
+        }
+        if !is_admin && db::plan_has_activity(&self.pool, req.plan_id as i64).await? {
+            return Err(AppError::Forbidden(format!(
+                "Plan {} already has subscriptions on chain; ask an admin to assign it",
+                req.plan_id
+            )));
+        }
+        let merchant = req.merchant.as_deref().unwrap_or(&auth_token.public_key);
+        check_price(solana_service, "amount", req.plan_id, req.duration, req.amount)?;
+        let metadata = metadata_text(req.metadata.as_ref())?;
+        let row = sqlx::query_as::<_, PlanRow>(
+            "INSERT INTO plans (plan_id, merchant, name, description, image_url, metadata, duration, amount, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $9)
+             ON CONFLICT (plan_id) DO NOTHING
+             RETURNING plan_id, merchant, name, description, image_url, metadata, duration, amount, scheduled_amount,
+                       scheduled_at, archived_at, created_at, updated_at",
+        )
+        .bind(req.plan_id as i64)
+        .bind(merchant)
+        .bind(req.name.trim())
+        .bind(&req.description)
+        .bind(&req.image_url)
+        .bind(metadata)
+        .bind(req.duration as i64)
+        .bind(req.amount as i64)
+        .bind(now())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to create plan: {}", e)))?
+        .ok_or_else(|| AppError::Conflict(format!("Plan {} already exists", req.plan_id)))?;
+        tracing::info!("Created plan {} for {}", row.plan_id, row.merchant);
+        self.merchants.write().unwrap().insert(row.plan_id as u64, row.merchant.clone());
+        Ok(row.into())
+    }
+
+    /// Catalog names of those of `plan_ids` that are in the catalog.
+    pub async fn names(&self, plan_ids: &[i64]) -> AppResult<HashMap<i64, String>> {
+        let names: Vec<(i64, String)> = sqlx::query_as("SELECT plan_id, name FROM plans WHERE plan_id = ANY($1)")
+            .bind(plan_ids)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to fetch plan names: {}", e)))?;
+        Ok(names.into_iter().collect())
+    }
+
+    /// Every plan id in the catalog, archived ones included since they can still have
+    /// subscribers. Used to find a wallet's subscriptions where there is no index.
+    pub async fn plan_ids(&self) -> AppResult<Vec<u64>> {
+        let ids: Vec<i64> = sqlx::query_scalar("SELECT plan_id FROM plans ORDER BY plan_id")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to list plan ids: {}", e)))?;
+        Ok(ids.into_iter().map(|id| id as u64).collect())
+    }
+
+    /// The caller's plans, or every plan for admins.
+    pub async fn list(&self, auth_token: &AuthToken, include_archived: bool) -> AppResult<Vec<Plan>> {
+        self.apply_scheduled_prices().await?;
+        let merchant = (!auth_token.has_role(Role::Admin)).then_some(auth_token.public_key.as_str());
+        let rows = sqlx::query_as::<_, PlanRow>(
+            "SELECT plan_id, merchant, name, description, image_url, metadata, duration, amount, scheduled_amount,
+                    scheduled_at, archived_at, created_at, updated_at
+             FROM plans
+             WHERE ($1::TEXT IS NULL OR merchant = $1) AND ($2 OR archived_at IS NULL)
+             ORDER BY plan_id",
+        )
+        .bind(merchant)
+        .bind(include_archived)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to list plans: {}", e)))?;
+        Ok(rows.into_iter().map(Plan::from).collect())
+    }
+
+    /// Every plan that is not archived, with its subscriber counts on the primary cluster.
+    pub async fn catalog(&self) -> AppResult<Vec<CatalogPlan>> {
+        self.apply_scheduled_prices().await?;
+        let rows = sqlx::query_as::<_, PlanRow>(
+            "SELECT plan_id, merchant, name, description, image_url, metadata, duration, amount, scheduled_amount,
+                    scheduled_at, archived_at, created_at, updated_at
+             FROM plans
+             WHERE archived_at IS NULL
+             ORDER BY plan_id",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to list plans: {}", e)))?;
+        let plan_ids: Vec<i64> = rows.iter().map(|row| row.plan_id).collect();
+        let stats: HashMap<i64, db::PlanStatsRow> = db::list_plan_stats(&self.pool, Some(&plan_ids))
+            .await?
+            .into_iter()
+            .map(|row| (row.plan_id, row))
+            .collect();
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let stats = stats.get(&row.plan_id);
+                CatalogPlan {
+                    plan_id: row.plan_id as u64,
+                    merchant: row.merchant,
+                    name: row.name,
+                    description: row.description,
+                    image_url: row.image_url,
+                    metadata: row.metadata.and_then(|metadata| serde_json::from_str(&metadata).ok()),
+                    duration: row.duration as u64,
+                    amount: row.amount as u64,
+                    mint: None,
+                    scheduled_amount: row.scheduled_amount.map(|amount| amount as u64),
+                    scheduled_at: row.scheduled_at,
+                    subscribers: stats.map_or(0, |stats| stats.subscriptions),
+                    active_subscribers: stats.map_or(0, |stats| stats.active_subscriptions),
+                }
+            })
+            .collect())
+    }
+
+    /// Plans belong to the merchant that created them; admins may read and edit any.
+    async fn owned(&self, auth_token: &AuthToken, plan_id: u64) -> AppResult<PlanRow> {
+        self.apply_scheduled_prices().await?;
+        let row = sqlx::query_as::<_, PlanRow>(
+            "SELECT plan_id, merchant, name, description, image_url, metadata, duration, amount, scheduled_amount,
+                    scheduled_at, archived_at, created_at, updated_at
+             FROM plans
+             WHERE plan_id = $1",
+        )
+        .bind(plan_id as i64)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to fetch plan: {}", e)))?
+        .ok_or_else(|| AppError::NotFound(format!("Plan {} not found", plan_id)))?;
+        if row.merchant != auth_token.public_key && !auth_token.has_role(Role::Admin) {
+            return Err(not_yours(plan_id));
+        }
+        Ok(row)
+    }
+
+    /// A plan as shown to paying wallets, which are not authenticated.
+    pub async fn published(&self, plan_id: u64) -> AppResult<Plan> {
+        self.apply_scheduled_prices().await?;
+        let row = sqlx::query_as::<_, PlanRow>(
+            "SELECT plan_id, merchant, name, description, image_url, metadata, duration, amount, scheduled_amount,
+                    scheduled_at, archived_at, created_at, updated_at
+             FROM plans
+             WHERE plan_id = $1",
+        )
+        .bind(plan_id as i64)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to fetch plan: {}", e)))?
+        .ok_or_else(|| AppError::NotFound(format!("Plan {} not found", plan_id)))?;
+        Ok(row.into())
+    }
+
+    pub async fn update(
+        &self,
+        auth_token: &AuthToken,
+        solana_service: &SolanaService,
+        plan_id: u64,
+        update: PlanUpdate,
+    ) -> AppResult<Plan> {
+        let mut row = self.owned(auth_token, plan_id).await?;
+        if let Some(name) = update.name {
+            row.name = name.trim().to_string();
+        }
+        if update.description.is_some() {
+            row.description = update.description;
+        }
+        if update.image_url.is_some() {
+            row.image_url = update.image_url;
+        }
+        if update.metadata.is_some() {
+            row.metadata = metadata_text(update.metadata.as_ref())?;
+        }
+        if let Some(duration) = update.duration {
+            row.duration = duration as i64;
+        }
+        if let Some(amount) = update.amount {
+            row.amount = amount as i64;
+        }
+        check_price(solana_service, "amount", plan_id, row.duration as u64, row.amount as u64)?;
+
+        if update.cancel_scheduled_price == Some(true) {
+            row.scheduled_amount = None;
+            row.scheduled_at = None;
+        }
+        match (update.scheduled_amount, update.scheduled_at) {
+            (Some(amount), Some(at)) => {
+                if at <= now() {
+                    return Err(invalid("scheduled_at", "must be in the future"));
+                }
+                check_price(solana_service, "scheduled_amount", plan_id, row.duration as u64, amount)?;
+                row.scheduled_amount = Some(amount as i64);
+                row.scheduled_at = Some(at);
+            }
+            (None, None) => {}
+            _ => return Err(invalid("scheduled_at", "scheduled_amount and scheduled_at must be set together")),
+        }
+        match update.archived {
+            Some(true) if row.archived_at.is_none() => row.archived_at = Some(now()),
+            Some(false) => row.archived_at = None,
+            _ => {}
+        }
+
+        let row = sqlx::query_as::<_, PlanRow>(
+            "UPDATE plans
+             SET name = $2, description = $3, image_url = $4, metadata = $5, duration = $6, amount = $7,
+                 scheduled_amount = $8, scheduled_at = $9, archived_at = $10, updated_at = $11
+             WHERE plan_id = $1
+             RETURNING plan_id, merchant, name, description, image_url, metadata, duration, amount, scheduled_amount,
+                       scheduled_at, archived_at, created_at, updated_at",
+        )
+        .bind(row.plan_id)
+        .bind(&row.name)
+        .bind(&row.description)
+        .bind(&row.image_url)
+        .bind(&row.metadata)
+        .bind(row.duration)
+        .bind(row.amount)
+        .bind(row.scheduled_amount)
+        .bind(row.scheduled_at)
+        .bind(row.archived_at)
+        .bind(now())
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to update plan: {}", e)))?;
+        Ok(row.into())
+    }
+}
+
+// Controllers
+/// Registers a plan id with its name, metadata and price. No transaction is sent. Plan ids
+/// that already have indexed subscriptions or payments can only be assigned by an admin.
+#[utoipa::path(
+    post,
+    path = "/api/v1/plans",
+    tag = "plans",
+    request_body = PlanRequest,
+    responses(
+        (status = 201, description = "Plan created", body = Plan),
+        (status = 403, description = "Merchant role required, or the plan id is in use on chain and needs an admin", body = ErrorResponse),
+        (status = 409, description = "Plan id already taken", body = ErrorResponse),
+        (status = 422, description = "Invalid request, or a price outside the subscription limits", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("")]
+pub async fn create_plan(
+    req: HttpRequest,
+    clusters: web::Data<SolanaClusters>,
+    plans: web::Data<PlanService>,
+    responses: web::Data<ResponseCache>,
+    body: ValidatedJson<PlanRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let solana_service = clusters.select(&req)?;
+    let plan = plans.create(&auth_token, solana_service, body.into_inner()).await?;
+    responses.invalidate(CacheNamespace::Plans);
+    responses.invalidate(CacheNamespace::Catalog);
+    Ok(HttpResponse::Created().json(plan))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/plans",
+    tag = "plans",
+    params(PlansQuery),
+    responses((status = 200, description = "The merchant's plans, by plan id", body = [Plan])),
+    security(("bearer_auth" = []))
+)]
+#[get("")]
+pub async fn list_plans(
+    req: HttpRequest,
+    query: ValidatedQuery<PlansQuery>,
+    plans: web::Data<PlanService>,
+    responses: web::Data<ResponseCache>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let include_archived = query.include_archived.unwrap_or(false);
+    let scope = if auth_token.has_role(Role::Admin) { "*" } else { auth_token.public_key.as_str() };
+    let key = format!("list:{}:{}", scope, include_archived);
+    let fetch = {
+        let (plans, auth_token) = (plans.get_ref().clone(), auth_token.clone());
+        move || {
+            let (plans, auth_token) = (plans.clone(), auth_token.clone());
+            async move { plans.list(&auth_token, include_archived).await }
+        }
+    };
+    let mut listed = responses.get(CacheNamespace::Plans, key.clone(), fetch.clone()).await?;
+    if price_due(listed.iter().map(|plan| plan.scheduled_at)) {
+        responses.invalidate(CacheNamespace::Plans);
+        listed = responses.get(CacheNamespace::Plans, key, fetch).await?;
+    }
+    Ok(HttpResponse::Ok().json(listed))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/plans/{plan_id}",
+    tag = "plans",
+    params(("plan_id" = u64, Path, description = "Plan id")),
+    responses(
+        (status = 200, description = "Plan", body = Plan),
+        (status = 403, description = "Plan belongs to another merchant", body = ErrorResponse),
+        (status = 404, description = "No such plan", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/{plan_id}")]
+pub async fn get_plan(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    plans: web::Data<PlanService>,
+    responses: web::Data<ResponseCache>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let plan_id = path.into_inner();
+    let fetch = {
+        let plans = plans.get_ref().clone();
+        move || {
+            let plans = plans.clone();
+            async move { plans.published(plan_id).await }
+        }
+    };
+    let mut plan = responses.get(CacheNamespace::Plans, format!("plan:{}", plan_id), fetch.clone()).await?;
+    if price_due([plan.scheduled_at]) {
+        responses.invalidate(CacheNamespace::Plans);
+        plan = responses.get(CacheNamespace::Plans, format!("plan:{}", plan_id), fetch).await?;
+    }
+    if plan.merchant != auth_token.public_key && !auth_token.has_role(Role::Admin) {
+        return Err(not_yours(plan_id));
+    }
+    Ok(HttpResponse::Ok().json(plan))
+}
+
+/// Edits a plan's details and price, schedules a price change, or archives it.
+#[utoipa::path(
+    patch,
+    path = "/api/v1/plans/{plan_id}",
+    tag = "plans",
+    params(("plan_id" = u64, Path, description = "Plan id")),
+    request_body = PlanUpdate,
+    responses(
+        (status = 200, description = "Updated plan", body = Plan),
+        (status = 403, description = "Plan belongs to another merchant", body = ErrorResponse),
+        (status = 404, description = "No such plan", body = ErrorResponse),
+        (status = 422, description = "Invalid request, or a price outside the subscription limits", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[patch("/{plan_id}")]
+pub async fn update_plan(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    clusters: web::Data<SolanaClusters>,
+    plans: web::Data<PlanService>,
+    responses: web::Data<ResponseCache>,
+    body: ValidatedJson<PlanUpdate>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let solana_service = clusters.select(&req)?;
+    let plan = plans.update(&auth_token, solana_service, path.into_inner(), body.into_inner()).await?;
+    responses.invalidate(CacheNamespace::Plans);
+    responses.invalidate(CacheNamespace::Catalog);
+    Ok(HttpResponse::Ok().json(plan))
+}
+
+/// The public plan catalog, for checkout UIs to list plans from. The program has no Plan
+/// account to read, so this is the catalog merchants register, with subscriber counts from
+/// the index. No authentication.
+#[utoipa::path(
+    get,
+    path = "/api/v1/plans/catalog",
+    tag = "plans",
+    responses((status = 200, description = "Plans that are not archived, by plan id", body = [CatalogPlan]))
+)]
+#[get("/api/v1/plans/catalog")]
+pub async fn plan_catalog(plans: web::Data<PlanService>, responses: web::Data<ResponseCache>) -> AppResult<HttpResponse> {
+    let fetch = {
+        let plans = plans.get_ref().clone();
+        move || {
+            let plans = plans.clone();
+            async move { plans.catalog().await }
+        }
+    };
+    let mut catalog = responses.get(CacheNamespace::Catalog, String::new(), fetch.clone()).await?;
+    if price_due(catalog.iter().map(|plan| plan.scheduled_at)) {
+        responses.invalidate(CacheNamespace::Catalog);
+        catalog = responses.get(CacheNamespace::Catalog, String::new(), fetch).await?;
+    }
+    Ok(HttpResponse::Ok()
+        .insert_header((actix_web::http::header::CACHE_CONTROL, "public, max-age=30"))
+        .json(catalog))
+}
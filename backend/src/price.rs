@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use crate::Config;
+
+const LAMPORTS_PER_SOL: f64 = 1_000_000_000.0;
+
+/// USD value of `lamports` at `sol_usd`, rounded to the cent.
+pub fn lamports_to_usd(lamports: u64, sol_usd: Option<f64>) -> Option<f64> {
+    sol_usd.map(|price| (lamports as f64 / LAMPORTS_PER_SOL * price * 100.0).round() / 100.0)
+}
+
+// Price Feed
+/// SOL/USD price from `PRICE_FEED_URL`, which must answer in CoinGecko's `simple/price`
+/// shape (`{"solana":{"usd":<price>}}`), and SPL token prices by mint from
+/// `TOKEN_PRICE_FEED_URL`, in CoinGecko's `simple/token_price` shape
+/// (`{"<mint>":{"usd":<price>}}`). Prices are cached for `ttl`; without a feed, or when it
+/// fails and nothing is cached, no price is available.
+#[derive(Clone)]
+pub struct PriceFeed {
+    http_client: reqwest::Client,
+    url: Option<String>,
+    token_url: Option<String>, // Mints are appended as `contract_addresses`
+    ttl: Duration,
+    cached: Arc<RwLock<Option<(f64, Instant)>>>,
+    cached_tokens: Arc<RwLock<HashMap<String, (f64, Instant)>>>,
+}
+
+impl PriceFeed {
+    pub fn new(config: &Config) -> Self {
+        let http_client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .expect("Failed to build price feed HTTP client");
+        Self {
+            http_client,
+            url: config.price_feed_url.clone(),
+            token_url: config.token_price_feed_url.clone(),
+            ttl: Duration::from_secs(config.price_cache_secs),
+            cached: Arc::new(RwLock::new(None)),
+            cached_tokens: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub async fn sol_usd(&self) -> Option<f64> {
+        let url = self.url.as_ref()?;
+        let cached = *self.cached.read().await;
+        if let Some((price, fetched_at)) = cached {
+            if fetched_at.elapsed() < self.ttl {
+                return Some(price);
+            }
+        }
+
+        match self.fetch(url).await {
+            Ok(price) => {
+                *self.cached.write().await = Some((price, Instant::now()));
+                Some(price)
+            }
+            Err(e) => {
+                tracing::warn!("Failed to fetch SOL/USD price: {}", e);
+                // A stale price beats none for an estimate
+                cached.map(|(price, _)| price)
+            }
+        }
+    }
+
+    /// USD prices of the given mints, keyed by mint. Mints the feed does not price are left
+    /// out.
+    pub async fn token_usd(&self, mints: &[String]) -> HashMap<String, f64> {
+        let Some(url) = self.token_url.as_ref() else {
+            return HashMap::new();
+        };
+        let cached = self.cached_tokens.read().await.clone();
+        let stale: Vec<&String> = mints
+            .iter()
+            .filter(|mint| cached.get(*mint).is_none_or(|(_, fetched_at)| fetched_at.elapsed() >= self.ttl))
+            .collect();
+        if !stale.is_empty() {
+            match self.fetch_tokens(url, &stale).await {
+                Ok(prices) => {
+                    let mut cached = self.cached_tokens.write().await;
+                    for (mint, price) in prices {
+                        cached.insert(mint, (price, Instant::now()));
+                    }
+                }
+                Err(e) => tracing::warn!("Failed to fetch token prices: {}", e),
+            }
+        }
+
+        // A stale price beats none for a valuation
+        let cached = self.cached_tokens.read().await;
+        mints
+            .iter()
+            .filter_map(|mint| cached.get(mint).map(|(price, _)| (mint.clone(), *price)))
+            .collect()
+    }
+
+    async fn fetch_tokens(&self, url: &str, mints: &[&String]) -> Result<HashMap<String, f64>, String> {
+        let separator = if url.contains('?') { '&' } else { '?' };
+        let addresses = mints.iter().map(|mint| mint.as_str()).collect::<Vec<_>>().join(",");
+        let body = self.get(&format!("{}{}contract_addresses={}", url, separator, addresses)).await?;
+        let prices = body.as_object().ok_or_else(|| "response is not an object".to_string())?;
+        // CoinGecko may change the case of the addresses it echoes back
+        Ok(mints
+            .iter()
+            .filter_map(|mint| {
+                prices
+                    .iter()
+                    .find(|(address, _)| address.eq_ignore_ascii_case(mint))
+                    .and_then(|(_, price)| price["usd"].as_f64())
+                    .map(|price| (mint.to_string(), price))
+            })
+            .collect())
+    }
+
+    async fn get(&self, url: &str) -> Result<serde_json::Value, String> {
+        self.http_client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| e.to_string())?
+            .json()
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn fetch(&self, url: &str) -> Result<f64, String> {
+        let body = self.get(url).await?;
+        body["solana"]["usd"]
+            .as_f64()
+            .ok_or_else(|| "response has no solana.usd price".to_string())
+    }
+}
@@ -0,0 +1,308 @@
+use actix_web::{get, http::Method, web, HttpMessage, HttpRequest, HttpResponse};
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+use crate::{AppError, AppResult, AuthToken, Config, Role};
+
+const DAY_SECS: i64 = 86_400;
+
+/// Routes that build and send a transaction when posted to, matched on the end of the route
+/// so they hold under `/api` and `/api/tenants/{tenant}` alike.
+const TRANSACTION_ROUTES: [&str; 7] = [
+    "/subscriptions",
+    "/subscriptions/batch",
+    "/subscriptions/{plan_id}/renew",
+    "/subscriptions/{plan_id}/cancel",
+    "/subscriptions/{plan_id}/close",
+    "/intents",
+    "/devnet/airdrop",
+];
+
+pub fn builds_transaction(method: &Method, route: &str) -> bool {
+    *method == Method::POST && TRANSACTION_ROUTES.iter().any(|suffix| route.ends_with(suffix))
+}
+
+/// Daily allowance of one tier; 0 means unlimited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaTier {
+    pub name: String,
+    pub requests_per_day: u64,
+    pub transactions_per_day: u64,
+}
+
+impl FromStr for QuotaTier {
+    type Err = String;
+
+    /// `<name>:<requests per day>:<transactions per day>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.trim().split(':').map(str::trim).collect();
+        let [name, requests, transactions] = parts.as_slice() else {
+            return Err(format!("Expected <name>:<requests>:<transactions>, got {}", s));
+        };
+        if name.is_empty() {
+            return Err(format!("Tier name is missing in {}", s));
+        }
+        Ok(QuotaTier {
+            name: name.to_string(),
+            requests_per_day: requests.parse().map_err(|_| format!("Invalid request quota in {}", s))?,
+            transactions_per_day: transactions.parse().map_err(|_| format!("Invalid transaction quota in {}", s))?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QuotaConfig {
+    tiers: HashMap<String, QuotaTier>,
+    default_tier: String,
+    wallet_tiers: HashMap<Pubkey, String>, // Wallets on another tier than the default
+}
+
+/// Tiers from `QUOTA_TIERS` (comma-separated `<name>:<requests>:<transactions>`), the tier of
+/// wallets not listed from `QUOTA_DEFAULT_TIER`, and per-wallet tiers from `QUOTA_WALLET_TIERS`
+/// (comma-separated `<pubkey>:<tier>`).
+pub fn load_quotas() -> QuotaConfig {
+    let tiers: HashMap<String, QuotaTier> = std::env::var("QUOTA_TIERS")
+        .unwrap_or_else(|_| "free:10000:500".to_string())
+        .split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| {
+            let tier: QuotaTier = s.parse().unwrap_or_else(|e| panic!("Invalid QUOTA_TIERS: {}", e));
+            (tier.name.clone(), tier)
+        })
+        .collect();
+    let default_tier = std::env::var("QUOTA_DEFAULT_TIER").unwrap_or_else(|_| "free".to_string());
+    assert!(tiers.contains_key(&default_tier), "QUOTA_DEFAULT_TIER {} is not in QUOTA_TIERS", default_tier);
+    let wallet_tiers = std::env::var("QUOTA_WALLET_TIERS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            let (pubkey, tier) = s.split_once(':').unwrap_or_else(|| panic!("Invalid entry {} in QUOTA_WALLET_TIERS", s));
+            let pubkey = Pubkey::from_str(pubkey.trim()).expect("Invalid pubkey in QUOTA_WALLET_TIERS");
+            let tier = tier.trim().to_string();
+            assert!(tiers.contains_key(&tier), "Unknown tier {} in QUOTA_WALLET_TIERS", tier);
+            (pubkey, tier)
+        })
+        .collect();
+    QuotaConfig { tiers, default_tier, wallet_tiers }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Counter {
+    Requests,
+    Transactions,
+}
+
+impl Counter {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Counter::Requests => "requests",
+            Counter::Transactions => "transactions",
+        }
+    }
+}
+
+/// Why a request was refused, and how long until the quotas reset.
+#[derive(Debug, Clone)]
+pub struct QuotaExceeded {
+    pub message: String,
+    pub retry_after_secs: u64,
+}
+
+#[derive(Clone)]
+enum Store {
+    Memory(Arc<Mutex<(i64, HashMap<String, u64>)>>), // Counts of the current day only
+    Redis(ConnectionManager),
+}
+
+// Quota Service
+/// Per-wallet daily quotas on API requests and on built transactions, counted per UTC day.
+/// Shared through Redis when available so quotas hold across replicas, otherwise kept in
+/// process memory. Admins are exempt.
+#[derive(Clone)]
+pub struct QuotaService {
+    store: Store,
+    config: Arc<QuotaConfig>,
+}
+
+impl QuotaService {
+    pub fn new(config: &Config, redis: Option<ConnectionManager>) -> Self {
+        let store = match redis {
+            Some(conn) => Store::Redis(conn),
+            None => Store::Memory(Arc::new(Mutex::new((day(now()), HashMap::new())))),
+        };
+        Self { store, config: Arc::new(config.quotas.clone()) }
+    }
+
+    fn tier_of(&self, public_key: &str) -> &QuotaTier {
+        let name = Pubkey::from_str(public_key)
+            .ok()
+            .and_then(|pubkey| self.config.wallet_tiers.get(&pubkey))
+            .unwrap_or(&self.config.default_tier);
+        &self.config.tiers[name]
+    }
+
+    /// Counts a request, and a transaction when it builds one, against the caller's quotas.
+    pub async fn consume(&self, auth_token: &AuthToken, builds_transaction: bool) -> Result<(), QuotaExceeded> {
+        if auth_token.has_role(Role::Admin) {
+            return Ok(());
+        }
+        let tier = self.tier_of(&auth_token.public_key);
+        let requests = self.increment(Counter::Requests, &auth_token.public_key).await;
+        if exceeded(requests, tier.requests_per_day) {
+            return Err(quota_exceeded(Counter::Requests, tier.requests_per_day));
+        }
+        if builds_transaction {
+            let transactions = self.increment(Counter::Transactions, &auth_token.public_key).await;
+            if exceeded(transactions, tier.transactions_per_day) {
+                return Err(quota_exceeded(Counter::Transactions, tier.transactions_per_day));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn usage(&self, auth_token: &AuthToken) -> UsageResponse {
+        let tier = self.tier_of(&auth_token.public_key);
+        let exempt = auth_token.has_role(Role::Admin);
+        let requests = self.count(Counter::Requests, &auth_token.public_key).await;
+        let transactions = self.count(Counter::Transactions, &auth_token.public_key).await;
+        UsageResponse {
+            tier: tier.name.clone(),
+            exempt,
+            requests: QuotaUsage::new(requests, if exempt { 0 } else { tier.requests_per_day }),
+            transactions: QuotaUsage::new(transactions, if exempt { 0 } else { tier.transactions_per_day }),
+            resets_at: (day(now()) + 1) * DAY_SECS,
+        }
+    }
+
+    async fn increment(&self, counter: Counter, public_key: &str) -> u64 {
+        let today = day(now());
+        let key = quota_key(counter, public_key, today);
+        match &self.store {
+            Store::Memory(counts) => {
+                let mut counts = counts.lock().unwrap();
+                if counts.0 != today {
+                    *counts = (today, HashMap::new());
+                }
+                let count = counts.1.entry(key).or_insert(0);
+                *count += 1;
+                *count
+            }
+            Store::Redis(conn) => {
+                let mut conn = conn.clone();
+                let result: redis::RedisResult<(u64,)> = redis::pipe()
+                    .atomic()
+                    .cmd("INCR")
+                    .arg(&key)
+                    .cmd("EXPIREAT")
+                    .arg(&key)
+                    .arg((today + 1) * DAY_SECS)
+                    .ignore()
+                    .query_async(&mut conn)
+                    .await;
+                match result {
+                    Ok((count,)) => count,
+                    Err(e) => {
+                        // Fail open: a Redis outage must not take the API down with it
+                        tracing::warn!("Quota store unavailable for {}: {}", public_key, e);
+                        0
+                    }
+                }
+            }
+        }
+    }
+
+    async fn count(&self, counter: Counter, public_key: &str) -> u64 {
+        let today = day(now());
+        let key = quota_key(counter, public_key, today);
+        match &self.store {
+            Store::Memory(counts) => {
+                let counts = counts.lock().unwrap();
+                if counts.0 != today {
+                    return 0;
+                }
+                counts.1.get(&key).copied().unwrap_or(0)
+            }
+            Store::Redis(conn) => {
+                let mut conn = conn.clone();
+                let result: redis::RedisResult<Option<u64>> = redis::cmd("GET").arg(&key).query_async(&mut conn).await;
+                result.unwrap_or_else(|e| {
+                    tracing::warn!("Quota store unavailable for {}: {}", public_key, e);
+                    None
+                })
+                .unwrap_or(0)
+            }
+        }
+    }
+}
+
+fn exceeded(count: u64, limit: u64) -> bool {
+    limit > 0 && count > limit
+}
+
+fn quota_exceeded(counter: Counter, limit: u64) -> QuotaExceeded {
+    QuotaExceeded {
+        message: format!("Daily {} quota of {} exceeded", counter.as_str(), limit),
+        retry_after_secs: ((day(now()) + 1) * DAY_SECS - now()).max(1) as u64,
+    }
+}
+
+fn quota_key(counter: Counter, public_key: &str, day: i64) -> String {
+    format!("quota:{}:{}:{}", counter.as_str(), public_key, day)
+}
+
+fn day(timestamp: i64) -> i64 {
+    timestamp.div_euclid(DAY_SECS)
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct QuotaUsage {
+    used: u64,
+    limit: Option<u64>,     // None when unlimited
+    remaining: Option<u64>, // None when unlimited
+}
+
+impl QuotaUsage {
+    fn new(used: u64, limit: u64) -> Self {
+        let limit = (limit > 0).then_some(limit);
+        Self { used, limit, remaining: limit.map(|limit| limit.saturating_sub(used)) }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct UsageResponse {
+    tier: String,
+    exempt: bool, // Admins are not counted against quotas
+    requests: QuotaUsage,
+    transactions: QuotaUsage,
+    resets_at: i64, // Start of the next UTC day
+}
+
+// Controllers
+/// The caller's quota tier and what it has used today.
+#[utoipa::path(
+    get,
+    path = "/api/v1/usage",
+    tag = "usage",
+    responses(
+        (status = 200, description = "Usage of the current UTC day", body = UsageResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/usage")]
+pub async fn get_usage(req: HttpRequest, quotas: web::Data<QuotaService>) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    Ok(HttpResponse::Ok().json(quotas.usage(&auth_token).await))
+}
@@ -0,0 +1,201 @@
+use redis::aio::ConnectionManager;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+const MAX_IN_MEMORY_BUCKETS: usize = 100_000;
+
+// Token bucket refill in Redis: stores fractional tokens and the last refill time (ms)
+// and returns whether a token was taken plus the remaining balance in milli-tokens.
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local rate = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+local tokens = tonumber(bucket[1]) or capacity
+local ts = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - ts) * rate)
+local allowed = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    allowed = 1
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', now)
+redis.call('PEXPIRE', KEYS[1], math.ceil(capacity / rate))
+return {allowed, math.floor(tokens * 1000)}
+"#;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    pub reset_secs: u64,       // until the bucket is full again
+    pub retry_after_secs: u64, // until the next token, 0 when allowed
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Clone)]
+enum Store {
+    Memory(Arc<Mutex<HashMap<String, Bucket>>>),
+    Redis(ConnectionManager),
+}
+
+/// Token bucket limiter allowing bursts of `capacity` requests, refilled at
+/// `capacity` tokens per minute. Shared through Redis when available so limits hold
+/// across replicas, otherwise kept in process memory.
+#[derive(Clone)]
+pub struct RateLimiter {
+    store: Store,
+    capacity: u32,
+    refill_per_sec: f64,
+    namespace: Option<&'static str>, // Keeps Redis buckets apart from other limiters on the same keys
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32, redis: Option<ConnectionManager>) -> Self {
+        let store = match redis {
+            Some(conn) => Store::Redis(conn),
+            None => Store::Memory(Arc::new(Mutex::new(HashMap::new()))),
+        };
+        let capacity = per_minute.max(1);
+        Self {
+            store,
+            capacity,
+            refill_per_sec: capacity as f64 / 60.0,
+            namespace: None,
+        }
+    }
+
+    /// A limiter whose buckets are separate from other limiters keyed the same way.
+    pub fn namespaced(mut self, namespace: &'static str) -> Self {
+        self.namespace = Some(namespace);
+        self
+    }
+
+    pub async fn check(&self, key: &str) -> RateLimitDecision {
+        let (allowed, tokens) = match &self.store {
+            Store::Memory(buckets) => self.take_in_memory(buckets, key),
+            Store::Redis(conn) => match self.take_in_redis(conn.clone(), key).await {
+                Ok(result) => result,
+                Err(e) => {
+                    // Fail open: a Redis outage must not take the API down with it
+                    tracing::warn!("Rate limiter unavailable for {}: {}", key, e);
+                    (true, self.capacity as f64)
+                }
+            },
+        };
+
+        let missing = self.capacity as f64 - tokens;
+        RateLimitDecision {
+            allowed,
+            limit: self.capacity,
+            remaining: tokens.floor().max(0.0) as u32,
+            reset_secs: (missing / self.refill_per_sec).ceil().max(0.0) as u64,
+            retry_after_secs: if allowed {
+                0
+            } else {
+                ((1.0 - tokens) / self.refill_per_sec).ceil().max(1.0) as u64
+            },
+        }
+    }
+
+    fn take_in_memory(&self, buckets: &Mutex<HashMap<String, Bucket>>, key: &str) -> (bool, f64) {
+        let mut buckets = buckets.lock().unwrap();
+        let now = Instant::now();
+
+        if buckets.len() >= MAX_IN_MEMORY_BUCKETS {
+            let capacity = self.capacity as f64;
+            let rate = self.refill_per_sec;
+            buckets.retain(|_, b| b.tokens + now.duration_since(b.updated).as_secs_f64() * rate < capacity);
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.capacity as f64,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity as f64);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            (true, bucket.tokens)
+        } else {
+            (false, bucket.tokens)
+        }
+    }
+
+    async fn take_in_redis(&self, mut conn: ConnectionManager, key: &str) -> redis::RedisResult<(bool, f64)> {
+        let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        let (allowed, milli_tokens): (i64, i64) = redis::Script::new(TOKEN_BUCKET_SCRIPT)
+            .key(match self.namespace {
+                Some(namespace) => format!("ratelimit:{}:{}", namespace, key),
+                None => format!("ratelimit:{}", key),
+            })
+            .arg(self.capacity)
+            .arg(self.refill_per_sec / 1000.0)
+            .arg(now_ms)
+            .invoke_async(&mut conn)
+            .await?;
+        Ok((allowed == 1, milli_tokens as f64 / 1000.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[actix_web::test]
+    async fn allows_a_burst_of_capacity() {
+        let limiter = RateLimiter::new(3, None);
+        for remaining in [2, 1, 0] {
+            let decision = limiter.check("wallet").await;
+            assert!(decision.allowed);
+            assert_eq!(decision.limit, 3);
+            assert_eq!(decision.remaining, remaining);
+            assert_eq!(decision.retry_after_secs, 0);
+        }
+        let decision = limiter.check("wallet").await;
+        assert!(!decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        // One token refills every 20 seconds, and the bucket in a minute
+        assert_eq!(decision.retry_after_secs, 20);
+        assert_eq!(decision.reset_secs, 60);
+    }
+
+    #[actix_web::test]
+    async fn keeps_keys_apart() {
+        let limiter = RateLimiter::new(1, None);
+        assert!(limiter.check("first").await.allowed);
+        assert!(!limiter.check("first").await.allowed);
+        assert!(limiter.check("second").await.allowed);
+    }
+
+    #[actix_web::test]
+    async fn refills_over_time() {
+        // Ten tokens a second
+        let limiter = RateLimiter::new(600, None);
+        for _ in 0..600 {
+            assert!(limiter.check("wallet").await.allowed);
+        }
+        assert!(!limiter.check("wallet").await.allowed);
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert!(limiter.check("wallet").await.allowed);
+    }
+
+    #[actix_web::test]
+    async fn allows_at_least_one_request() {
+        let limiter = RateLimiter::new(0, None);
+        let decision = limiter.check("wallet").await;
+        assert!(decision.allowed);
+        assert_eq!(decision.limit, 1);
+        assert!(!limiter.check("wallet").await.allowed);
+    }
+}
@@ -0,0 +1,262 @@
+use actix_web::{get, post, web, HttpMessage, HttpRequest, HttpResponse};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use solana_sdk::signature::{Keypair, Signature};
+use solana_sdk::signer::keypair::keypair_from_seed;
+use solana_sdk::signer::Signer;
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use utoipa::ToSchema;
+use validator::Validate;
+use crate::db;
+use crate::plans::PlanService;
+use crate::validation::ValidatedJson;
+use crate::wallets::WalletService;
+use crate::{unix_now, AppError, AppResult, AuthToken, Config, Role};
+
+// Bumped whenever the receipt's fields change meaning
+const RECEIPT_VERSION: u32 = 1;
+
+/// Reads `RECEIPT_SIGNING_KEY`, a base58 32-byte Ed25519 seed. Receipts are not issued without it.
+pub fn load_receipt_key() -> Option<[u8; 32]> {
+    let seed = std::env::var("RECEIPT_SIGNING_KEY").ok().filter(|v| !v.trim().is_empty())?;
+    let seed: [u8; 32] = bs58::decode(seed.trim())
+        .into_vec()
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .expect("RECEIPT_SIGNING_KEY must be a base58 32-byte seed");
+    Some(seed)
+}
+
+// Models
+/// One payment of the transaction the receipt covers.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct ReceiptPayment {
+    instruction_index: u32,
+    kind: String,         // create_subscription | renew_subscription
+    subscription: String, // PDA
+    payer: String,
+    plan_id: u64,
+    merchant: Option<String>, // None for plans not registered with this backend
+    amount_lamports: u64,
+    period_start: Option<i64>, // The block time of the payment
+    period_end: Option<i64>,   // Until which it pays for the subscription
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Receipt {
+    version: u32,
+    cluster: String,
+    signature: String, // Transaction signature
+    slot: u64,
+    block_time: Option<i64>,
+    issued_at: i64,
+    payments: Vec<ReceiptPayment>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct SignedReceipt {
+    receipt: Receipt,
+    payload: String,    // Base64url (unpadded) JSON of `receipt`: the bytes that are signed
+    signature: String,  // Base58 Ed25519 signature of `payload`'s bytes
+    public_key: String, // Base58 key that verifies it
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct VerifyReceiptRequest {
+    #[validate(length(min = 1, max = 65536, message = "must be 1 to 65536 characters"))]
+    payload: String,
+    #[validate(length(min = 1, max = 128, message = "must be 1 to 128 characters"))]
+    signature: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct ReceiptVerification {
+    valid: bool,
+    public_key: String,
+    receipt: Option<Receipt>, // The decoded payload, when the signature is valid
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct ReceiptKey {
+    public_key: String, // Base58
+    algorithm: String,  // Always "Ed25519"
+}
+
+// Receipt Service
+/// Issues receipts for indexed payments, signed with the server's Ed25519 receipt key so a
+/// merchant can check one offline against the published public key: the signature covers the
+/// exact payload bytes, so no canonical JSON form is needed to verify it.
+#[derive(Clone)]
+pub struct ReceiptService {
+    pool: PgPool,
+    plans: PlanService,
+    keypair: Arc<Keypair>,
+    cluster: String,
+}
+
+impl ReceiptService {
+    pub fn new(config: &Config, pool: PgPool, plans: PlanService) -> Option<Self> {
+        let seed = config.receipt_signing_key?;
+        let keypair = keypair_from_seed(&seed).expect("A 32-byte seed is a valid Ed25519 key");
+        Some(Self { pool, plans, keypair: Arc::new(keypair), cluster: config.cluster.as_str().to_string() })
+    }
+
+    pub fn key(&self) -> ReceiptKey {
+        ReceiptKey { public_key: self.keypair.pubkey().to_string(), algorithm: "Ed25519".to_string() }
+    }
+
+    /// A receipt for the payments of `signature` the caller made from one of `wallets` or, as
+    /// the plan's merchant or an admin, received.
+    pub async fn issue(&self, auth_token: &AuthToken, wallets: &[String], signature: &str) -> AppResult<SignedReceipt> {
+        Signature::from_str(signature).map_err(|e| AppError::BadRequest(format!("Invalid signature: {}", e)))?;
+        let rows = db::list_payments_by_signature(&self.pool, signature).await?;
+        let Some(first) = rows.first() else {
+            return Err(AppError::NotFound(format!("No indexed payment in transaction {}", signature)));
+        };
+        let (slot, block_time) = (first.slot as u64, first.block_time);
+
+        let mut durations: HashMap<String, i64> = HashMap::new();
+        let pdas: Vec<String> = rows.iter().map(|row| row.pda.clone()).collect();
+        for subscription in db::find_subscriptions(&self.pool, &pdas).await? {
+            durations.insert(subscription.pda, subscription.duration);
+        }
+        let mut payments = Vec::new();
+        for row in rows {
+            let plan_id = row.plan_id as u64;
+            let merchant = self.plans.merchant_of(plan_id).await?;
+            let receives = auth_token.has_role(Role::Admin) || merchant.as_deref() == Some(auth_token.public_key.as_str());
+            if !wallets.contains(&row.owner) && !receives {
+                continue;
+            }
+            let period_end = row.block_time.zip(durations.get(&row.pda)).map(|(start, duration)| start + duration);
+            payments.push(ReceiptPayment {
+                instruction_index: row.instruction_index as u32,
+                kind: row.kind,
+                subscription: row.pda,
+                payer: row.owner,
+                plan_id,
+                merchant,
+                amount_lamports: row.amount as u64,
+                period_start: row.block_time,
+                period_end,
+            });
+        }
+        if payments.is_empty() {
+            return Err(AppError::Forbidden(format!("Transaction {} pays for none of your subscriptions or plans", signature)));
+        }
+
+        let receipt = Receipt {
+            version: RECEIPT_VERSION,
+            cluster: self.cluster.clone(),
+            signature: signature.to_string(),
+            slot,
+            block_time,
+            issued_at: unix_now(),
+            payments,
+        };
+        let bytes = serde_json::to_vec(&receipt)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to encode receipt: {}", e)))?;
+        Ok(SignedReceipt {
+            receipt,
+            payload: URL_SAFE_NO_PAD.encode(&bytes),
+            signature: self.keypair.sign_message(&bytes).to_string(),
+            public_key: self.keypair.pubkey().to_string(),
+        })
+    }
+
+    pub fn verify(&self, req: &VerifyReceiptRequest) -> AppResult<ReceiptVerification> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(req.payload.trim())
+            .map_err(|e| AppError::BadRequest(format!("Invalid payload encoding: {}", e)))?;
+        let signature = Signature::from_str(req.signature.trim())
+            .map_err(|e| AppError::BadRequest(format!("Invalid signature: {}", e)))?;
+        let valid = signature.verify(self.keypair.pubkey().as_ref(), &bytes);
+        let receipt = if valid {
+            Some(
+                serde_json::from_slice(&bytes)
+                    .map_err(|e| AppError::BadRequest(format!("Signed payload is not a receipt: {}", e)))?,
+            )
+        } else {
+            None
+        };
+        Ok(ReceiptVerification { valid, public_key: self.keypair.pubkey().to_string(), receipt })
+    }
+}
+
+/// Public routes for checking receipts, registered outside the authenticated scopes.
+pub fn public_routes(cfg: &mut web::ServiceConfig, receipts: Option<&ReceiptService>) {
+    if let Some(receipts) = receipts {
+        cfg.app_data(web::Data::new(receipts.clone())).service(receipt_key).service(verify_receipt);
+    }
+}
+
+/// Routes for the authenticated wallet, under `/api/v1`. Registered ahead of the merchant-only
+/// `/payments` scope, which would otherwise claim the path.
+pub fn wallet_routes(cfg: &mut web::ServiceConfig, enabled: bool) {
+    if enabled {
+        cfg.service(payment_receipt);
+    }
+}
+
+// Controllers
+/// Issues a signed receipt for the indexed payments of a transaction: to the paying wallet's
+/// account for its own payments, and to the plan's merchant for payments to its plans.
+#[utoipa::path(
+    get,
+    path = "/api/v1/payments/{signature}/receipt",
+    tag = "payments",
+    params(("signature" = String, Path, description = "Transaction signature")),
+    responses(
+        (status = 200, description = "Signed receipt", body = SignedReceipt),
+        (status = 400, description = "Invalid signature", body = ErrorResponse),
+        (status = 403, description = "The transaction pays for none of the caller's subscriptions or plans", body = ErrorResponse),
+        (status = 404, description = "No indexed payment in the transaction", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/payments/{signature}/receipt")]
+pub async fn payment_receipt(
+    req: HttpRequest,
+    path: web::Path<String>,
+    receipts: web::Data<ReceiptService>,
+    wallets: web::Data<WalletService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let owned = wallets.wallets(&auth_token.public_key).await?;
+    let receipt = receipts.issue(&auth_token, &owned, &path.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(receipt))
+}
+
+/// Checks a receipt's signature against the server's receipt key. No authentication.
+#[utoipa::path(
+    post,
+    path = "/api/v1/receipts/verify",
+    tag = "receipts",
+    request_body = VerifyReceiptRequest,
+    responses(
+        (status = 200, description = "Whether the receipt was signed by this server", body = ReceiptVerification),
+        (status = 400, description = "Malformed payload or signature", body = ErrorResponse),
+    )
+)]
+#[post("/api/v1/receipts/verify")]
+pub async fn verify_receipt(
+    receipts: web::Data<ReceiptService>,
+    body: ValidatedJson<VerifyReceiptRequest>,
+) -> AppResult<HttpResponse> {
+    Ok(HttpResponse::Ok().json(receipts.verify(&body)?))
+}
+
+/// The public key receipts are signed with, for verifying them offline.
+#[utoipa::path(
+    get,
+    path = "/api/v1/receipts/key",
+    tag = "receipts",
+    responses((status = 200, description = "Receipt signing key", body = ReceiptKey))
+)]
+#[get("/api/v1/receipts/key")]
+pub async fn receipt_key(receipts: web::Data<ReceiptService>) -> HttpResponse {
+    HttpResponse::Ok().json(receipts.key())
+}
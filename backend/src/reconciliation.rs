@@ -0,0 +1,276 @@
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use serde::Serialize;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::option_serializer::OptionSerializer;
+use solana_transaction_status::UiTransactionEncoding;
+use sqlx::postgres::PgPool;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use crate::db::{self, ReconciliationRow};
+use crate::email::EmailTemplate;
+use crate::metrics;
+use crate::notifications::NotificationService;
+use crate::reporting;
+use crate::webhooks::sign_payload;
+use crate::{unix_now, AppError, AppResult, Config, SolanaService};
+
+const DAY_SECS: i64 = 86400;
+const SIGNATURE_PAGE_SIZE: usize = 1000;
+const CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+// How long after midnight UTC the previous day is reconciled, for the indexer to catch up
+const SETTLE_SECS: i64 = 900;
+const FETCH_CONCURRENCY: usize = 8;
+const ALERT_EVENT: &str = "treasury.reconciliation_failed";
+
+// Models
+#[derive(Debug, Serialize, Clone)]
+struct ReconciliationAlert {
+    event: &'static str,
+    treasury: String,
+    day_start: i64,
+    on_chain_lamports: i64,
+    on_chain_transactions: i64,
+    indexed_lamports: i64,
+    indexed_payments: i64,
+    difference_lamports: i64,
+    threshold_lamports: u64,
+}
+
+// Reconciliation Service
+/// Once a day, sums what the treasury received on chain during the previous UTC day and
+/// compares it with the payments indexed for that day. A difference beyond the threshold,
+/// from an indexing gap or a transfer that is not a subscription payment, is raised on the
+/// `treasury_reconciliation_*` metrics, to the alert webhook and to the alert email. Only
+/// the primary cluster's default tenant is indexed, so its treasury is the one reconciled.
+#[derive(Clone)]
+pub struct ReconciliationService {
+    solana: SolanaService,
+    pool: PgPool,
+    notifications: NotificationService,
+    http_client: reqwest::Client,
+    threshold_lamports: u64,
+    alert_email: Option<String>,
+    webhook_url: Option<String>,
+    webhook_secret: Option<String>,
+}
+
+impl ReconciliationService {
+    pub fn new(config: &Config, solana: SolanaService, pool: PgPool, notifications: NotificationService) -> Self {
+        let http_client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("Failed to build reconciliation HTTP client");
+
+        Self {
+            solana,
+            pool,
+            notifications,
+            http_client,
+            threshold_lamports: config.reconciliation_threshold_lamports,
+            alert_email: config.reconciliation_alert_email.clone(),
+            webhook_url: config.reconciliation_webhook_url.clone(),
+            webhook_secret: config.reconciliation_webhook_secret.clone(),
+        }
+    }
+
+    pub async fn run(self) {
+        loop {
+            let started = Instant::now();
+            match self.reconcile_due().await {
+                Ok(reconciled) => {
+                    metrics::record_job_success("reconciliation", started, reconciled as usize);
+                }
+                Err(e) => {
+                    metrics::record_job_failure("reconciliation", started);
+                    tracing::error!("Treasury reconciliation failed: {}", e);
+                    reporting::capture_job_failure("reconciliation", &e);
+                }
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    }
+
+    /// Reconciles the previous UTC day once it has settled, unless it already was. Returns
+    /// whether it did.
+    #[tracing::instrument(name = "treasury_reconciliation", skip_all)]
+    pub async fn reconcile_due(&self) -> AppResult<bool> {
+        let now = unix_now();
+        let day_start = (now - SETTLE_SECS).div_euclid(DAY_SECS) * DAY_SECS - DAY_SECS;
+        let treasury = self.solana.treasury();
+        // Claiming first keeps replicas from reconciling the same day
+        if !db::claim_reconciliation(&self.pool, day_start, &treasury.to_string()).await? {
+            return Ok(false);
+        }
+        match self.reconcile(&treasury, day_start).await {
+            Ok(row) => {
+                metrics::record_reconciliation(row.difference_lamports, row.alerted);
+                db::finish_reconciliation(&self.pool, &row).await?;
+                Ok(true)
+            }
+            Err(e) => {
+                db::release_reconciliation(&self.pool, day_start).await?;
+                Err(e)
+            }
+        }
+    }
+
+    async fn reconcile(&self, treasury: &Pubkey, day_start: i64) -> AppResult<ReconciliationRow> {
+        let day_end = day_start + DAY_SECS;
+        let (on_chain_transactions, on_chain_lamports) = self.inflows(treasury, day_start, day_end).await?;
+        let (indexed_payments, indexed_lamports) = db::indexed_inflows(&self.pool, day_start, day_end).await?;
+        let difference_lamports = on_chain_lamports - indexed_lamports;
+        let alerted = difference_lamports.unsigned_abs() > self.threshold_lamports;
+        let row = ReconciliationRow {
+            day_start,
+            treasury: treasury.to_string(),
+            on_chain_lamports,
+            on_chain_transactions,
+            indexed_lamports,
+            indexed_payments,
+            difference_lamports,
+            alerted,
+        };
+        if alerted {
+            tracing::warn!(
+                "Treasury {} received {} lamports on chain on day {} but {} were indexed",
+                treasury, on_chain_lamports, day_start, indexed_lamports
+            );
+            self.alert(&row).await;
+        } else {
+            tracing::info!("Treasury {} reconciled for day {}: {} lamports", treasury, day_start, on_chain_lamports);
+        }
+        Ok(row)
+    }
+
+    /// Count and sum of the treasury's balance increases in successful transactions with a
+    /// block time in `[from, until)`.
+    async fn inflows(&self, treasury: &Pubkey, from: i64, until: i64) -> AppResult<(i64, i64)> {
+        let mut signatures = Vec::new();
+        let mut before = None;
+        loop {
+            let page = metrics::observe_rpc(
+                "getSignaturesForAddress",
+                self.solana.rpc.client().get_signatures_for_address_with_config(
+                    treasury,
+                    GetConfirmedSignaturesForAddress2Config {
+                        before,
+                        until: None,
+                        limit: Some(SIGNATURE_PAGE_SIZE),
+                        commitment: Some(CommitmentConfig::finalized()),
+                    },
+                ),
+            )
+            .await
+                .map_err(|e| AppError::rpc("Failed to fetch treasury signatures", e))?;
+
+            let page_len = page.len();
+            before = page.last().and_then(|s| Signature::from_str(&s.signature).ok());
+            let reached_start = page.last().and_then(|s| s.block_time).is_some_and(|t| t < from);
+            signatures.extend(
+                page.into_iter()
+                    .filter(|s| s.err.is_none() && s.block_time.is_some_and(|t| t >= from && t < until))
+                    .filter_map(|s| Signature::from_str(&s.signature).ok()),
+            );
+
+            if page_len < SIGNATURE_PAGE_SIZE || before.is_none() || reached_start {
+                break;
+            }
+        }
+
+        let received: Vec<u64> = stream::iter(signatures)
+            .map(|signature| self.received(treasury, signature))
+            .buffer_unordered(FETCH_CONCURRENCY)
+            .try_collect()
+            .await?;
+        let inflows: Vec<u64> = received.into_iter().filter(|lamports| *lamports > 0).collect();
+        Ok((inflows.len() as i64, inflows.iter().sum::<u64>() as i64))
+    }
+
+    /// How much the treasury's balance rose in one transaction, 0 if it did not.
+    async fn received(&self, treasury: &Pubkey, signature: Signature) -> AppResult<u64> {
+        let tx = metrics::observe_rpc(
+            "getTransaction",
+            self.solana.rpc.client().get_transaction_with_config(
+                &signature,
+                RpcTransactionConfig {
+                    encoding: Some(UiTransactionEncoding::Base64),
+                    commitment: Some(CommitmentConfig::finalized()),
+                    max_supported_transaction_version: Some(0),
+                },
+            ),
+        )
+        .await
+            .map_err(|e| AppError::rpc(&format!("Failed to fetch transaction {}", signature), e))?;
+
+        let Some(meta) = tx.transaction.meta else { return Ok(0) };
+        let versioned = tx.transaction.transaction
+            .decode()
+            .ok_or_else(|| AppError::SolanaError(format!("Failed to decode transaction {}", signature)))?;
+        // Balances follow the static keys, then the writable and readonly lookup table keys
+        let mut keys: Vec<String> = versioned.message.static_account_keys().iter().map(Pubkey::to_string).collect();
+        if let OptionSerializer::Some(loaded) = meta.loaded_addresses {
+            keys.extend(loaded.writable);
+            keys.extend(loaded.readonly);
+        }
+        let treasury = treasury.to_string();
+        let received = keys
+            .iter()
+            .position(|key| *key == treasury)
+            .and_then(|index| Some(meta.post_balances.get(index)?.saturating_sub(*meta.pre_balances.get(index)?)))
+            .unwrap_or(0);
+        Ok(received)
+    }
+
+    async fn alert(&self, row: &ReconciliationRow) {
+        if let Some(email) = &self.alert_email {
+            let template = EmailTemplate::ReconciliationAlert {
+                treasury: row.treasury.clone(),
+                day_start: row.day_start,
+                on_chain: row.on_chain_lamports as u64,
+                indexed: row.indexed_lamports as u64,
+            };
+            if let Err(e) = self.notifications.alert(email, &template).await {
+                tracing::warn!("Failed to email reconciliation alert: {}", e);
+            }
+        }
+        if let Some(url) = &self.webhook_url {
+            let alert = ReconciliationAlert {
+                event: ALERT_EVENT,
+                treasury: row.treasury.clone(),
+                day_start: row.day_start,
+                on_chain_lamports: row.on_chain_lamports,
+                on_chain_transactions: row.on_chain_transactions,
+                indexed_lamports: row.indexed_lamports,
+                indexed_payments: row.indexed_payments,
+                difference_lamports: row.difference_lamports,
+                threshold_lamports: self.threshold_lamports,
+            };
+            if let Err(e) = self.post_alert(url, &alert).await {
+                tracing::warn!("Failed to send reconciliation alert webhook: {}", e);
+            }
+        }
+    }
+
+    /// Signed like subscription webhooks when `RECONCILIATION_WEBHOOK_SECRET` is set.
+    async fn post_alert(&self, url: &str, alert: &ReconciliationAlert) -> Result<(), String> {
+        let body = serde_json::to_string(alert).map_err(|e| format!("Failed to serialize alert: {}", e))?;
+        let mut request = self.http_client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .header("X-Webhook-Event", ALERT_EVENT);
+        if let Some(secret) = &self.webhook_secret {
+            let timestamp = unix_now();
+            request = request.header(
+                "X-Webhook-Signature",
+                format!("t={},v1={}", timestamp, sign_payload(secret, timestamp, &body)),
+            );
+        }
+        let response = request.body(body).send().await.map_err(|e| format!("Request failed: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!("Endpoint returned {}", response.status()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,375 @@
+use actix_web::{get, post, web, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer, system_instruction};
+use sqlx::postgres::PgPool;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+use crate::audit;
+use crate::db::{self, Refund};
+use crate::plans::PlanService;
+use crate::validation::{ValidatedJson, ValidatedQuery};
+use crate::webhooks::{SubscriptionEventData, WebhookEventType, WebhookService};
+use crate::{AppError, AppResult, AuthToken, Config, SolanaService};
+
+/// Transaction job instruction of refund transfers.
+pub const REFUND_INSTRUCTION: &str = "refund";
+const DEFAULT_REFUND_LIMIT: i64 = 100;
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct RefundRequest {
+    instruction_index: Option<i32>, // Which payment, when the transaction made several
+    #[validate(range(min = 1, message = "must be a positive lamport amount"))]
+    lamports: Option<u64>, // Defaults to the full payment
+    #[validate(length(max = 500, message = "must be at most 500 characters"))]
+    reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct RejectRefundRequest {
+    #[validate(length(max = 500, message = "must be at most 500 characters"))]
+    reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct RefundsQuery {
+    status: Option<String>, // requested | processing | succeeded | failed | rejected
+    #[validate(range(min = 1, max = 500, message = "must be between 1 and 500"))]
+    limit: Option<i64>,
+    #[validate(range(min = 0, message = "must not be negative"))]
+    offset: Option<i64>,
+}
+
+/// The `REFUND_PRIVATE_KEY` keypair, which must be the default tenant's treasury.
+pub fn load_treasury_keypair(config: &Config) -> Result<Option<Arc<Keypair>>, String> {
+    let Some(key) = &config.refund_private_key else {
+        return Ok(None);
+    };
+    let bytes = bs58::decode(key)
+        .into_vec()
+        .map_err(|_| "Invalid REFUND_PRIVATE_KEY format".to_string())?;
+    let keypair = Keypair::from_bytes(&bytes).map_err(|_| "Failed to parse REFUND_PRIVATE_KEY".to_string())?;
+    if keypair.pubkey() != config.treasury {
+        return Err("REFUND_PRIVATE_KEY is not the TREASURY_PUBKEY keypair".to_string());
+    }
+    Ok(Some(Arc::new(keypair)))
+}
+
+// Refund Service
+/// Refunds of indexed payments. The program has no refund instruction, so an approved refund
+/// is a plain transfer from the treasury to the wallet that paid, signed with
+/// `REFUND_PRIVATE_KEY` and sent as a transaction job like any other transaction.
+#[derive(Clone)]
+pub struct RefundService {
+    pool: PgPool,
+    webhooks: WebhookService,
+    treasury: Arc<RwLock<Option<Arc<Keypair>>>>, // Reloadable, see `reload`
+}
+
+impl RefundService {
+    pub fn new(config: &Config, pool: PgPool, webhooks: WebhookService) -> Self {
+        let treasury = load_treasury_keypair(config).unwrap_or_else(|e| panic!("{}", e));
+        Self {
+            pool,
+            webhooks,
+            treasury: Arc::new(RwLock::new(treasury)),
+        }
+    }
+
+    pub fn set_treasury_keypair(&self, keypair: Option<Arc<Keypair>>) {
+        *self.treasury.write().unwrap() = keypair;
+    }
+
+    /// The `REFUND_PRIVATE_KEY` keypair, also used for treasury withdrawals.
+    pub fn treasury_keypair(&self) -> Option<Arc<Keypair>> {
+        self.treasury.read().unwrap().clone()
+    }
+
+    /// Only the merchant of the payment's plan, or an admin, may request its refund.
+    pub async fn request(
+        &self,
+        plans: &PlanService,
+        auth_token: &AuthToken,
+        payment_signature: &str,
+        req: RefundRequest,
+    ) -> AppResult<Refund> {
+        let payments = db::list_payments_by_signature(&self.pool, payment_signature).await?;
+        let payment = match (req.instruction_index, payments.as_slice()) {
+            (_, []) => return Err(AppError::NotFound(format!("No indexed payment in {}", payment_signature))),
+            (None, [payment]) => payment,
+            (None, _) => {
+                return Err(AppError::BadRequest(
+                    "The transaction made several payments; set instruction_index".to_string(),
+                ))
+            }
+            (Some(index), payments) => payments.iter().find(|p| p.instruction_index == index).ok_or_else(|| {
+                AppError::NotFound(format!("No indexed payment at instruction {} of {}", index, payment_signature))
+            })?,
+        };
+        plans.authorize(auth_token, payment.plan_id as u64).await?;
+        let lamports = req.lamports.unwrap_or(payment.amount as u64);
+        if lamports > payment.amount as u64 {
+            return Err(AppError::BadRequest(format!("Refund exceeds the payment of {} lamports", payment.amount)));
+        }
+
+        let created_at = now();
+        let refund = Refund {
+            id: hex::encode(rand::random::<[u8; 16]>()),
+            payment_signature: payment.signature.clone(),
+            instruction_index: payment.instruction_index,
+            pda: payment.pda.clone(),
+            owner: payment.owner.clone(),
+            plan_id: payment.plan_id,
+            lamports: lamports as i64,
+            reason: req.reason,
+            status: "requested".to_string(),
+            requested_by: auth_token.public_key.clone(),
+            reviewed_by: None,
+            signature: None,
+            error: None,
+            created_at,
+            updated_at: created_at,
+        };
+        if !db::insert_refund(&self.pool, &refund).await? {
+            return Err(AppError::Conflict("This payment already has a refund requested or paid".to_string()));
+        }
+        dispatch_event(&self.webhooks, &refund).await;
+        Ok(refund)
+    }
+
+    /// Sends an approved refund and waits for confirmation. The refund comes back
+    /// `succeeded`, `failed`, or still `processing` if confirmation timed out, in which case
+    /// the job worker settles it later.
+    pub async fn approve(&self, solana_service: &SolanaService, id: &str, admin: &str) -> AppResult<Refund> {
+        let treasury = self
+            .treasury_keypair()
+            .ok_or_else(|| AppError::BadRequest("Refunds are disabled: REFUND_PRIVATE_KEY is not set".to_string()))?;
+        let refund = self.get(id).await?;
+        if refund.status != "requested" {
+            return Err(AppError::Conflict(format!("Refund {} is {}", id, refund.status)));
+        }
+        let owner = Pubkey::from_str(&refund.owner)
+            .map_err(|e| AppError::InternalServerError(format!("Invalid refund owner: {}", e)))?;
+
+        let transfer = system_instruction::transfer(&treasury.pubkey(), &owner, refund.lamports as u64);
+        let (mut tx, last_valid_block_height) = solana_service.unsigned_transaction(&treasury.pubkey(), &[transfer]).await?;
+        let recent_blockhash = tx.message.recent_blockhash;
+        tx.sign(&[treasury.as_ref()], recent_blockhash);
+        let signature = tx.signatures[0].to_string();
+        if !db::start_refund(&self.pool, id, admin, &signature).await? {
+            return Err(AppError::Conflict(format!("Refund {} was reviewed concurrently", id)));
+        }
+
+        // The job records the outcome on the refund, whether it settles now or in the job worker
+        let result = solana_service
+            .send_signed_transaction(
+                REFUND_INSTRUCTION,
+                &owner,
+                refund.plan_id as u64,
+                Vec::new(),
+                &tx,
+                last_valid_block_height,
+                None,
+            )
+            .await;
+        if let Err(e) = &result {
+            tracing::warn!("Refund {} did not confirm: {}", id, e);
+        }
+        let refund = self.get(id).await?;
+        if refund.status != "processing" {
+            dispatch_event(&self.webhooks, &refund).await;
+        }
+        Ok(refund)
+    }
+
+    pub async fn reject(&self, id: &str, admin: &str, reason: Option<&str>) -> AppResult<Refund> {
+        if !db::reject_refund(&self.pool, id, admin, reason).await? {
+            let refund = self.get(id).await?;
+            return Err(AppError::Conflict(format!("Refund {} is {}", id, refund.status)));
+        }
+        self.get(id).await
+    }
+
+    pub async fn get(&self, id: &str) -> AppResult<Refund> {
+        db::get_refund(&self.pool, id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Refund {} not found", id)))
+    }
+}
+
+/// Sends the webhook for a refund that was just requested or settled.
+pub async fn dispatch_event(webhooks: &WebhookService, refund: &Refund) {
+    let event_type = match refund.status.as_str() {
+        "requested" => WebhookEventType::RefundRequested,
+        "succeeded" => WebhookEventType::RefundSucceeded,
+        "failed" => WebhookEventType::RefundFailed,
+        _ => return,
+    };
+    webhooks
+        .dispatch(
+            event_type,
+            SubscriptionEventData {
+                subscription: refund.pda.clone(),
+                owner: refund.owner.clone(),
+                plan_id: refund.plan_id as u64,
+                signature: refund.signature.clone(),
+                milestone: None,
+                expires_at: None,
+                refund_id: Some(refund.id.clone()),
+                refund_lamports: Some(refund.lamports as u64),
+            },
+        )
+        .await;
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+// Controllers
+/// Requests a refund of an indexed payment, for an admin to approve.
+#[utoipa::path(
+    post,
+    path = "/api/v1/payments/{signature}/refund",
+    tag = "refunds",
+    params(("signature" = String, Path, description = "Transaction that made the payment")),
+    request_body = RefundRequest,
+    responses(
+        (status = 201, description = "Refund requested", body = Refund),
+        (status = 400, description = "Amount exceeds the payment, or instruction_index is needed", body = ErrorResponse),
+        (status = 403, description = "Payment is for another merchant's plan", body = ErrorResponse),
+        (status = 404, description = "No such indexed payment", body = ErrorResponse),
+        (status = 409, description = "Payment already has a refund requested or paid", body = ErrorResponse),
+        (status = 422, description = "Invalid request", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/{signature}/refund")]
+pub async fn request_refund(
+    req: HttpRequest,
+    path: web::Path<String>,
+    refunds: web::Data<RefundService>,
+    plans: web::Data<PlanService>,
+    body: ValidatedJson<RefundRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let refund = refunds.request(&plans, &auth_token, &path.into_inner(), body.into_inner()).await?;
+    Ok(HttpResponse::Created().json(refund))
+}
+
+/// Refunds of the caller's plans (every plan for admins), newest first.
+#[utoipa::path(
+    get,
+    path = "/api/v1/refunds",
+    tag = "refunds",
+    params(RefundsQuery),
+    responses(
+        (status = 200, description = "Refunds", body = [Refund]),
+        (status = 422, description = "Invalid query", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("")]
+pub async fn list_refunds(
+    req: HttpRequest,
+    query: ValidatedQuery<RefundsQuery>,
+    pool: web::Data<PgPool>,
+    plans: web::Data<PlanService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let scope = plans.scope(&auth_token).await?;
+    let refunds = db::list_refunds(
+        &pool,
+        scope.plan_ids(),
+        query.status.as_deref(),
+        query.limit.unwrap_or(DEFAULT_REFUND_LIMIT),
+        query.offset.unwrap_or(0),
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(refunds))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/refunds/{id}",
+    tag = "refunds",
+    params(("id" = String, Path, description = "Refund id")),
+    responses(
+        (status = 200, description = "Refund state", body = Refund),
+        (status = 403, description = "Refund is for another merchant's plan", body = ErrorResponse),
+        (status = 404, description = "No such refund", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/{id}")]
+pub async fn get_refund(
+    req: HttpRequest,
+    path: web::Path<String>,
+    refunds: web::Data<RefundService>,
+    plans: web::Data<PlanService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let refund = refunds.get(&path.into_inner()).await?;
+    plans.authorize(&auth_token, refund.plan_id as u64).await?;
+    Ok(HttpResponse::Ok().json(refund))
+}
+
+/// Approves a requested refund and sends it from the treasury.
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/refunds/{id}/approve",
+    tag = "admin",
+    params(("id" = String, Path, description = "Refund id")),
+    responses(
+        (status = 200, description = "Refund after sending", body = Refund),
+        (status = 400, description = "Refunds are disabled", body = ErrorResponse),
+        (status = 404, description = "No such refund", body = ErrorResponse),
+        (status = 409, description = "Refund is no longer requested", body = ErrorResponse),
+        (status = 502, description = "RPC node unavailable", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/refunds/{id}/approve")]
+pub async fn approve_refund(
+    req: HttpRequest,
+    path: web::Path<String>,
+    solana_service: web::Data<SolanaService>,
+    refunds: web::Data<RefundService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let refund = refunds.approve(&solana_service, &path.into_inner(), &auth_token.public_key).await?;
+    if let Some(signature) = &refund.signature {
+        audit::attach_signatures(&req, [signature]);
+    }
+    Ok(HttpResponse::Ok().json(refund))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/refunds/{id}/reject",
+    tag = "admin",
+    params(("id" = String, Path, description = "Refund id")),
+    request_body = RejectRefundRequest,
+    responses(
+        (status = 200, description = "Refund rejected", body = Refund),
+        (status = 404, description = "No such refund", body = ErrorResponse),
+        (status = 409, description = "Refund is no longer requested", body = ErrorResponse),
+        (status = 422, description = "Invalid request", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/refunds/{id}/reject")]
+pub async fn reject_refund(
+    req: HttpRequest,
+    path: web::Path<String>,
+    refunds: web::Data<RefundService>,
+    body: ValidatedJson<RejectRefundRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let refund = refunds.reject(&path.into_inner(), &auth_token.public_key, body.reason.as_deref()).await?;
+    Ok(HttpResponse::Ok().json(refund))
+}
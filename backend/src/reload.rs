@@ -0,0 +1,254 @@
+use actix_web::{post, web, HttpMessage, HttpRequest, HttpResponse};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+use crate::cluster::SolanaClusters;
+use crate::limits::SubscriptionLimits;
+use crate::notifications::NotificationService;
+use crate::refunds::{self, RefundService};
+use crate::reporting;
+use crate::{get_config, AppError, AppResult, AuthToken, Config};
+
+// Variables the process was started with; `.env` never overrides these, at startup or on reload
+static INHERITED: OnceCell<HashSet<String>> = OnceCell::new();
+// The `.env` file found at startup, searching up from the working directory like `dotenv()`
+static ENV_FILE: OnceCell<PathBuf> = OnceCell::new();
+
+/// Loads `.env` like `dotenv()`, first noting which variables came from the environment.
+pub fn load_env() {
+    INHERITED.get_or_init(|| std::env::vars().map(|(name, _)| name).collect());
+    if let Ok(path) = dotenvy::dotenv() {
+        ENV_FILE.get_or_init(|| path);
+    }
+}
+
+/// Re-reads `.env` into the environment. A variable removed from the file keeps its value.
+fn reread_env() -> Result<(), String> {
+    let inherited = INHERITED.get_or_init(HashSet::new);
+    let path = ENV_FILE.get_or_init(|| PathBuf::from(".env"));
+    let vars = match dotenvy::from_path_iter(path) {
+        Ok(iter) => iter
+            .collect::<Result<Vec<(String, String)>, _>>()
+            .map_err(|e| format!("Failed to read .env: {}", e))?,
+        Err(e) if e.not_found() => return Ok(()),
+        Err(e) => return Err(format!("Failed to read .env: {}", e)),
+    };
+    for (name, value) in vars {
+        if !inherited.contains(&name) {
+            std::env::set_var(name, value);
+        }
+    }
+    Ok(())
+}
+
+/// `get_config`, with its panics on invalid settings turned into errors.
+fn load_config() -> Result<Config, String> {
+    reporting::expecting_panics(|| std::panic::catch_unwind(get_config)).map_err(|panic| {
+        panic
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| panic.downcast_ref::<&str>().map(|message| message.to_string()))
+            .unwrap_or_else(|| "Invalid configuration".to_string())
+    })
+}
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, Default, ToSchema)]
+pub struct ReloadReport {
+    applied: Vec<String>, // Settings that changed and are now in effect
+    restart_required: Vec<String>, // Settings that changed but only apply on restart
+}
+
+// Config Reloader
+/// Applies changed treasuries, RPC endpoints, subscription limits, CORS origins and SMTP
+/// settings without a restart, so open connections (GraphQL subscriptions, WebSockets) are
+/// kept. Triggered by `SIGHUP` or `POST /api/admin/config/reload`. A reload either applies
+/// every change or, when any setting is invalid, none.
+#[derive(Clone)]
+pub struct ConfigReloader {
+    current: Arc<Mutex<Config>>,
+    clusters: SolanaClusters,
+    refunds: RefundService,
+    notifications: NotificationService,
+    cors_allowed_origins: Arc<RwLock<Vec<String>>>,
+}
+
+impl ConfigReloader {
+    pub fn new(
+        config: &Config,
+        clusters: SolanaClusters,
+        refunds: RefundService,
+        notifications: NotificationService,
+    ) -> Self {
+        Self {
+            current: Arc::new(Mutex::new(config.clone())),
+            clusters,
+            refunds,
+            notifications,
+            cors_allowed_origins: Arc::new(RwLock::new(config.cors_allowed_origins.clone())),
+        }
+    }
+
+    /// The allowed CORS origins, as last loaded.
+    pub fn cors_allowed_origins(&self) -> Arc<RwLock<Vec<String>>> {
+        self.cors_allowed_origins.clone()
+    }
+
+    pub async fn reload(&self) -> Result<ReloadReport, String> {
+        let mut current = self.current.lock().await;
+        reread_env()?;
+        let config = load_config()?;
+        let mut report = ReloadReport::default();
+
+        // Everything that can fail is checked before anything is applied
+        let refund_keypair = refunds::load_treasury_keypair(&config)?;
+        let mut tenants = Vec::new();
+        for service in self.clusters.all() {
+            let Some(tenant) = config.tenants.iter().find(|tenant| tenant.id == service.tenant()) else {
+                report
+                    .restart_required
+                    .push(format!("removal of tenant {} on {}", service.tenant(), service.cluster.as_str()));
+                continue;
+            };
+            let limits = SubscriptionLimits::try_new(&config, tenant)
+                .map_err(|e| format!("{} for tenant {}", e, tenant.id))?;
+            tenants.push((service, tenant, limits));
+        }
+        if config.smtp_url != current.smtp_url || config.email_from != current.email_from {
+            self.notifications.reload(&config)?;
+            report.applied.push("SMTP settings".to_string());
+        }
+
+        for (service, tenant, limits) in tenants {
+            let name = format!("tenant {} on {}", tenant.id, service.cluster.as_str());
+            if service.treasury() != tenant.treasury {
+                service.set_treasury(tenant.treasury);
+                report.applied.push(format!("treasury of {}", name));
+            }
+            if service.limits() != limits {
+                service.set_limits(limits);
+                report.applied.push(format!("subscription limits of {}", name));
+            }
+            let previous = current.tenants.iter().find(|t| t.id == tenant.id);
+            let same_key = previous.is_some_and(|previous| previous.rpc_api_key == tenant.rpc_api_key);
+            match (self.clusters.tenant_rpc(service.cluster, &tenant.id), tenant.rpc_urls(service.cluster)) {
+                (Some(rpc), Some(urls)) if same_key => {
+                    if rpc.urls() != urls {
+                        rpc.set_urls(urls);
+                        report.applied.push(format!("RPC endpoints of {}", name));
+                    }
+                }
+                (None, None) => {}
+                _ => report.restart_required.push(format!("RPC endpoints or API key of {}", name)),
+            }
+        }
+        if config.refund_private_key != current.refund_private_key {
+            self.refunds.set_treasury_keypair(refund_keypair);
+            report.applied.push("refund keypair".to_string());
+        }
+
+        for cluster in &config.clusters {
+            let name = cluster.cluster.as_str();
+            let Some(rpc) = self.clusters.rpc(cluster.cluster) else {
+                report.restart_required.push(format!("cluster {}", name));
+                continue;
+            };
+            if rpc.urls() != cluster.rpc_urls {
+                rpc.set_urls(&cluster.rpc_urls);
+                report.applied.push(format!("RPC endpoints of {}", name));
+            }
+            let previous = current.clusters.iter().find(|c| c.cluster == cluster.cluster);
+            if previous.is_some_and(|previous| {
+                previous.ws_url != cluster.ws_url
+                    || previous.program_id != cluster.program_id
+                    || previous.layout != cluster.layout
+            }) {
+                report.restart_required.push(format!("WebSocket endpoint, program ID or layout of {}", name));
+            }
+        }
+        for cluster in &current.clusters {
+            if !config.clusters.iter().any(|c| c.cluster == cluster.cluster) {
+                report.restart_required.push(format!("removal of cluster {}", cluster.cluster.as_str()));
+            }
+        }
+        for tenant in &config.tenants {
+            match current.tenants.iter().find(|t| t.id == tenant.id) {
+                None => report.restart_required.push(format!("tenant {}", tenant.id)),
+                Some(previous) if previous.sandbox != tenant.sandbox => {
+                    report.restart_required.push(format!("mode of tenant {}", tenant.id))
+                }
+                Some(previous) if previous.pii_key != tenant.pii_key => {
+                    report.restart_required.push(format!("PII encryption key of tenant {}", tenant.id))
+                }
+                Some(_) => {}
+            }
+        }
+
+        if config.cors_allowed_origins != current.cors_allowed_origins {
+            *self.cors_allowed_origins.write().unwrap() = config.cors_allowed_origins.clone();
+            report.applied.push("CORS origins".to_string());
+        }
+
+        *current = config;
+        for setting in &report.applied {
+            tracing::info!("Reloaded {}", setting);
+        }
+        for setting in &report.restart_required {
+            tracing::warn!("Changed {} only applies on restart", setting);
+        }
+        Ok(report)
+    }
+
+    /// Reloads on every `SIGHUP`.
+    pub async fn run(self) {
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGHUP, config reload is only available over HTTP: {}", e);
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            tracing::info!("SIGHUP received, reloading configuration");
+            if let Err(e) = self.reload().await {
+                tracing::error!("Configuration not reloaded: {}", e);
+            }
+        }
+    }
+}
+
+// Controllers
+/// Re-reads the configuration and applies changed treasuries, RPC endpoints, subscription
+/// limits, CORS origins and SMTP settings, as on `SIGHUP`.
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/config/reload",
+    tag = "admin",
+    responses(
+        (status = 200, description = "What changed", body = ReloadReport),
+        (status = 400, description = "Invalid configuration; nothing was changed", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/config/reload")]
+pub async fn reload_config(
+    req: HttpRequest,
+    reloader: web::Data<ConfigReloader>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req
+        .extensions()
+        .get::<AuthToken>()
+        .ok_or(AppError::Auth("No auth token found".to_string()))?
+        .clone();
+    tracing::info!("Configuration reload requested by {}", auth_token.public_key);
+    let report = reloader
+        .reload()
+        .await
+        .map_err(|e| AppError::BadRequest(format!("Configuration not reloaded: {}", e)))?;
+    Ok(HttpResponse::Ok().json(report))
+}
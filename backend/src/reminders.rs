@@ -0,0 +1,148 @@
+use sqlx::postgres::PgPool;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::db::{self, SubscriptionRow};
+use crate::email::EmailTemplate;
+use crate::metrics;
+use crate::notifications::NotificationService;
+use crate::reporting;
+use crate::webhooks::{SubscriptionEventData, WebhookEventType, WebhookService};
+use crate::{AppResult, Config};
+
+const BATCH_SIZE: i64 = 200;
+const DAY_SECS: i64 = 86400;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Milestone {
+    ExpiringIn3Days,
+    ExpiringIn1Day,
+    Expired,
+}
+
+impl Milestone {
+    const ALL: [Milestone; 3] = [Milestone::ExpiringIn3Days, Milestone::ExpiringIn1Day, Milestone::Expired];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Milestone::ExpiringIn3Days => "expiring_3d",
+            Milestone::ExpiringIn1Day => "expiring_1d",
+            Milestone::Expired => "expired",
+        }
+    }
+
+    /// Period ends in `(now + until, now + from]` are due for an expiring milestone, so a
+    /// subscription already inside the 1 day window skips the 3 day reminder.
+    fn window(&self) -> Option<(i64, i64)> {
+        match self {
+            Milestone::ExpiringIn3Days => Some((3 * DAY_SECS, DAY_SECS)),
+            Milestone::ExpiringIn1Day => Some((DAY_SECS, 0)),
+            Milestone::Expired => None,
+        }
+    }
+
+    fn event_type(&self) -> WebhookEventType {
+        match self {
+            Milestone::Expired => WebhookEventType::SubscriptionExpired,
+            _ => WebhookEventType::SubscriptionExpiring,
+        }
+    }
+}
+
+// Reminder Service
+/// Emits "expiring in 3 days", "expiring in 1 day" and "expired" notifications, each at most
+/// once per subscription and billing period. Expired reminders follow the keeper, which
+/// decides whether a lapsed subscription is renewed or expired.
+#[derive(Clone)]
+pub struct ReminderService {
+    pool: PgPool,
+    webhooks: WebhookService,
+    notifications: NotificationService,
+    interval: Duration,
+}
+
+impl ReminderService {
+    pub fn new(config: &Config, pool: PgPool, webhooks: WebhookService, notifications: NotificationService) -> Self {
+        Self {
+            pool,
+            webhooks,
+            notifications,
+            interval: Duration::from_secs(config.reminder_interval_secs),
+        }
+    }
+
+    pub async fn run(self) {
+        loop {
+            let started = Instant::now();
+            match self.send_due().await {
+                Ok(sent) => {
+                    metrics::record_job_success("reminders", started, sent);
+                    if sent > 0 {
+                        tracing::info!("Sent {} expiry reminders", sent);
+                    }
+                }
+                Err(e) => {
+                    metrics::record_job_failure("reminders", started);
+                    tracing::error!("Expiry reminders failed: {}", e);
+                    reporting::capture_job_failure("reminders", &e);
+                }
+            }
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+
+    #[tracing::instrument(name = "renewal_reminders", skip_all)]
+    pub async fn send_due(&self) -> AppResult<usize> {
+        let now = now();
+        let mut sent = 0;
+        for milestone in Milestone::ALL {
+            let due = match milestone.window() {
+                Some((from, until)) => {
+                    db::list_expiring_subscriptions(&self.pool, milestone.as_str(), now, from, until, BATCH_SIZE).await?
+                }
+                None => db::list_expired_subscriptions(&self.pool, milestone.as_str(), BATCH_SIZE).await?,
+            };
+            for subscription in due {
+                if self.send(milestone, subscription).await? {
+                    sent += 1;
+                }
+            }
+        }
+        Ok(sent)
+    }
+
+    async fn send(&self, milestone: Milestone, subscription: SubscriptionRow) -> AppResult<bool> {
+        let period_end = subscription.start_time + subscription.duration;
+        // Claiming first keeps replicas from sending the same reminder
+        if !db::claim_reminder(&self.pool, &subscription.pda, milestone.as_str(), period_end).await? {
+            return Ok(false);
+        }
+        self.notifications.notify(
+            &subscription.owner,
+            EmailTemplate::ExpiryReminder {
+                subscription: subscription.pda.clone(),
+                plan_id: subscription.plan_id as u64,
+                expires_at: period_end,
+                expired: milestone == Milestone::Expired,
+            },
+        );
+        self.webhooks
+            .dispatch(
+                milestone.event_type(),
+                SubscriptionEventData {
+                    subscription: subscription.pda,
+                    owner: subscription.owner,
+                    plan_id: subscription.plan_id as u64,
+                    signature: None,
+                    milestone: Some(milestone.as_str().to_string()),
+                    expires_at: Some(period_end),
+                    refund_id: None,
+                    refund_lamports: None,
+                },
+            )
+            .await;
+        Ok(true)
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
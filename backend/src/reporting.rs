@@ -0,0 +1,216 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use serde_json::json;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::{telemetry, Config};
+
+static REPORTER: OnceCell<ErrorReporter> = OnceCell::new();
+
+thread_local! {
+    // Set while panics are caught on purpose, e.g. when a config reload is rejected
+    static EXPECTING_PANIC: Cell<bool> = const { Cell::new(false) };
+}
+
+enum Sink {
+    /// Sentry's envelope endpoint, derived from the DSN.
+    Sentry { url: String, dsn: String, auth: String },
+    /// Any endpoint that accepts an `ErrorReport` as JSON.
+    Generic { url: String },
+}
+
+/// One captured error, as posted to `ERROR_REPORT_URL`.
+#[derive(Debug, Serialize, Clone)]
+pub struct ErrorReport {
+    id: String,
+    kind: String, // panic | http | webhook | job
+    message: String,
+    request_id: Option<String>,
+    context: BTreeMap<String, String>,
+    environment: String,
+    release: String,
+    timestamp: i64,
+}
+
+struct ErrorReporter {
+    http_client: reqwest::Client,
+    sink: Sink,
+    environment: String,
+}
+
+impl ErrorReporter {
+    async fn send(&self, report: ErrorReport) {
+        let request = match &self.sink {
+            Sink::Sentry { url, dsn, auth } => {
+                let event = json!({
+                    "event_id": report.id,
+                    "timestamp": report.timestamp,
+                    "platform": "other",
+                    "level": "error",
+                    "logger": report.kind,
+                    "message": { "formatted": report.message },
+                    "environment": report.environment,
+                    "release": report.release,
+                    "tags": { "kind": report.kind, "request_id": report.request_id },
+                    "extra": report.context,
+                });
+                let envelope = format!(
+                    "{}\n{}\n{}\n",
+                    json!({ "event_id": report.id, "dsn": dsn }),
+                    json!({ "type": "event" }),
+                    event
+                );
+                self.http_client
+                    .post(url)
+                    .header("X-Sentry-Auth", auth)
+                    .header("Content-Type", "application/x-sentry-envelope")
+                    .body(envelope)
+            }
+            Sink::Generic { url } => self.http_client.post(url).json(&report),
+        };
+        if let Err(e) = request.send().await.and_then(|response| response.error_for_status()) {
+            // Logged, never reported, so a broken sink cannot loop
+            tracing::warn!("Failed to send error report {}: {}", report.id, e);
+        }
+    }
+}
+
+/// `https://<key>@<host>/<project>` -> (envelope URL, `X-Sentry-Auth` header).
+fn parse_dsn(dsn: &str) -> Result<(String, String), String> {
+    let url = reqwest::Url::parse(dsn).map_err(|e| format!("Invalid SENTRY_DSN: {}", e))?;
+    let key = url.username();
+    let host = url.host_str().ok_or("SENTRY_DSN has no host")?;
+    let (prefix, project) = url.path().trim_end_matches('/').rsplit_once('/').ok_or("SENTRY_DSN has no project")?;
+    if key.is_empty() || project.is_empty() {
+        return Err("SENTRY_DSN must look like https://<key>@<host>/<project>".to_string());
+    }
+    let port = url.port().map(|port| format!(":{}", port)).unwrap_or_default();
+    let envelope_url = format!("{}://{}{}{}/api/{}/envelope/", url.scheme(), host, port, prefix, project);
+    let auth = format!(
+        "Sentry sentry_version=7, sentry_client=subscription-manager/{}, sentry_key={}",
+        env!("CARGO_PKG_VERSION"),
+        key
+    );
+    Ok((envelope_url, auth))
+}
+
+/// Reports errors to Sentry (`SENTRY_DSN`) or, failing that, to `ERROR_REPORT_URL`, unless
+/// `ERROR_REPORTING_ENABLED=false`. Installs a panic hook that reports panics before the
+/// default hook runs. Without a sink nothing is captured.
+pub fn init(config: &Config) {
+    if !config.error_reporting_enabled {
+        return;
+    }
+    let sink = match (&config.sentry_dsn, &config.error_report_url) {
+        (Some(dsn), _) => {
+            let (url, auth) = parse_dsn(dsn).unwrap_or_else(|e| panic!("{}", e));
+            Sink::Sentry { url, dsn: dsn.clone(), auth }
+        }
+        (None, Some(url)) => Sink::Generic { url: url.clone() },
+        (None, None) => return,
+    };
+    let http_client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .expect("Failed to build error reporting HTTP client");
+    let reporter = ErrorReporter {
+        http_client,
+        sink,
+        environment: config.error_report_environment.clone(),
+    };
+    if REPORTER.set(reporter).is_err() {
+        return;
+    }
+
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if !EXPECTING_PANIC.with(Cell::get) {
+            let message = info
+                .payload()
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| info.payload().downcast_ref::<&str>().map(|message| message.to_string()))
+                .unwrap_or_else(|| "Panic".to_string());
+            let mut context = Vec::new();
+            if let Some(location) = info.location() {
+                context.push(("location", location.to_string()));
+            }
+            if let Some(thread) = std::thread::current().name() {
+                context.push(("thread", thread.to_string()));
+            }
+            capture("panic", message, context);
+        }
+        default_hook(info);
+    }));
+    tracing::info!("Error reporting enabled");
+}
+
+/// Runs `f`, which catches its own panics, without reporting them.
+pub fn expecting_panics<T>(f: impl FnOnce() -> T) -> T {
+    EXPECTING_PANIC.with(|expecting| expecting.set(true));
+    let result = f();
+    EXPECTING_PANIC.with(|expecting| expecting.set(false));
+    result
+}
+
+/// Sends a report in the background, tagged with the current request ID if there is one.
+/// Does nothing when reporting is off or outside the Tokio runtime.
+pub fn capture(kind: &str, message: String, context: Vec<(&str, String)>) {
+    let Some(reporter) = REPORTER.get() else {
+        return;
+    };
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        return;
+    };
+    let report = ErrorReport {
+        id: hex::encode(rand::random::<[u8; 16]>()),
+        kind: kind.to_string(),
+        message,
+        request_id: telemetry::request_id().filter(|id| !id.is_empty()),
+        context: context.into_iter().map(|(key, value)| (key.to_string(), value)).collect(),
+        environment: reporter.environment.clone(),
+        release: env!("CARGO_PKG_VERSION").to_string(),
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
+    };
+    runtime.spawn(reporter.send(report));
+}
+
+/// A background job run that failed.
+pub fn capture_job_failure(job: &str, error: &impl std::fmt::Display) {
+    capture("job", format!("{} failed: {}", job, error), vec![("job", job.to_string())]);
+}
+
+/// Reports responses and errors with a 5xx status other than `503`, which is returned on purpose while
+/// the RPC circuit is open or transactions are disabled. Must be wrapped inside
+/// `telemetry::scope_request_id` so reports carry the request ID.
+pub fn capture_server_errors<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let method = req.method().to_string();
+    let path = req.path().to_string();
+    let call = srv.call(req);
+    async move {
+        let result = call.await;
+        // Middlewares such as `Timeout` answer with an error rather than a response
+        let (status, message) = match &result {
+            Ok(response) => (response.status(), response.response().error().map(|e| e.to_string())),
+            Err(e) => (e.as_response_error().status_code(), Some(e.to_string())),
+        };
+        if status.is_server_error() && status != StatusCode::SERVICE_UNAVAILABLE {
+            capture(
+                "http",
+                message.unwrap_or_else(|| status.to_string()),
+                vec![("method", method), ("path", path), ("status", status.as_u16().to_string())],
+            );
+        }
+        result
+    }
+}
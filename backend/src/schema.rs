@@ -0,0 +1,132 @@
+use sqlx::migrate::Migrator;
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use crate::{AppError, AppResult};
+
+/// The migrations in `migrations/`, compiled into the binary. Ones a newer binary applied
+/// are tolerated, so an older replica can still run during a rolling deploy.
+fn migrator() -> Migrator {
+    let mut migrator = sqlx::migrate!("./migrations");
+    migrator.set_ignore_missing(true);
+    migrator
+}
+
+/// How the database's applied migrations compare with the ones this binary carries.
+#[derive(Debug, Clone, Default)]
+pub struct SchemaStatus {
+    pub expected: i64,      // The latest migration this binary carries
+    pub applied: Vec<i64>,
+    pub pending: Vec<i64>,  // Carried by the binary, not applied yet
+    pub unknown: Vec<i64>,  // Applied by a newer binary
+    pub modified: Vec<i64>, // Applied, but the file changed since
+    pub failed: Vec<i64>,   // Started and never completed
+}
+
+impl SchemaStatus {
+    /// The latest migration the database has, 0 for an empty one.
+    pub fn version(&self) -> i64 {
+        self.applied.last().copied().unwrap_or(0)
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct AppliedMigration {
+    version: i64,
+    checksum: Vec<u8>,
+    success: bool,
+}
+
+/// Reads the migration table without changing anything; a database never migrated has
+/// every migration pending.
+pub async fn status(pool: &PgPool) -> AppResult<SchemaStatus> {
+    let exists: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+        .fetch_one(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to check migration table: {}", e)))?;
+    let rows = if exists {
+        sqlx::query_as::<_, AppliedMigration>("SELECT version, checksum, success FROM _sqlx_migrations ORDER BY version")
+            .fetch_all(pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to list applied migrations: {}", e)))?
+    } else {
+        Vec::new()
+    };
+
+    let migrator = migrator();
+    let known: HashMap<i64, &[u8]> = migrator
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .map(|m| (m.version, m.checksum.as_ref()))
+        .collect();
+    let mut status = SchemaStatus { expected: known.keys().copied().max().unwrap_or(0), ..Default::default() };
+    for row in &rows {
+        match known.get(&row.version) {
+            _ if !row.success => status.failed.push(row.version),
+            None => status.unknown.push(row.version),
+            Some(checksum) if *checksum != row.checksum.as_slice() => status.modified.push(row.version),
+            Some(_) => {}
+        }
+        if row.success {
+            status.applied.push(row.version);
+        }
+    }
+    let mut pending: Vec<i64> = known.keys().copied().filter(|v| !rows.iter().any(|row| row.version == *v)).collect();
+    pending.sort();
+    status.pending = pending;
+    Ok(status)
+}
+
+/// Applies the pending migrations, returning their versions.
+pub async fn migrate(pool: &PgPool) -> AppResult<Vec<i64>> {
+    let before = status(pool).await?;
+    ensure_consistent(&before)?;
+    if before.pending.is_empty() {
+        return Ok(Vec::new());
+    }
+    migrator()
+        .run(pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to run migrations: {}", e)))?;
+    for version in &before.pending {
+        tracing::info!("Applied migration {}", version);
+    }
+    Ok(before.pending)
+}
+
+/// The startup check when migrations are not applied automatically: refuses to start on a
+/// schema older than the binary, or one whose migrations were edited or left half done.
+pub async fn check(pool: &PgPool) -> AppResult<SchemaStatus> {
+    let status = status(pool).await?;
+    ensure_consistent(&status)?;
+    if !status.pending.is_empty() {
+        return Err(AppError::DatabaseError(format!(
+            "Database schema is at version {} but this build expects {}: run `backend migrate` first (pending: {:?})",
+            status.version(),
+            status.expected,
+            status.pending
+        )));
+    }
+    Ok(status)
+}
+
+fn ensure_consistent(status: &SchemaStatus) -> AppResult<()> {
+    if !status.unknown.is_empty() {
+        tracing::warn!(
+            "Database has migrations {:?} this build does not know; it was migrated by a newer version",
+            status.unknown
+        );
+    }
+    if !status.failed.is_empty() {
+        return Err(AppError::DatabaseError(format!(
+            "Migrations {:?} failed part way; repair the schema and their _sqlx_migrations rows",
+            status.failed
+        )));
+    }
+    if !status.modified.is_empty() {
+        return Err(AppError::DatabaseError(format!(
+            "Migrations {:?} were changed after they were applied",
+            status.modified
+        )));
+    }
+    Ok(())
+}
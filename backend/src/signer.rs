@@ -0,0 +1,500 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures_util::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::transaction::Transaction;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
+use crate::keystore::{self, SealedSigner};
+use crate::{AppError, AppResult};
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+// DER SubjectPublicKeyInfo of an Ed25519 key, before the 32 key bytes
+const ED25519_SPKI_PREFIX: [u8; 12] = [0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+const GCP_METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+/// Where the fee payer key lives, from `SIGNER_BACKEND`.
+#[derive(Clone)]
+pub enum SignerConfig {
+    /// `SIGNER_KEYPAIR_PATH`, a `solana-keygen` JSON file or one sealed by `keystore`, or else
+    /// the base58 `PHANTOM_PRIVATE_KEY`.
+    Local { keypair_path: Option<String>, private_key: Option<String> },
+    /// An `ECC_NIST_EDWARDS25519` key in AWS KMS. Credentials come from `AWS_ACCESS_KEY_ID`,
+    /// `AWS_SECRET_ACCESS_KEY` and, for temporary ones, `AWS_SESSION_TOKEN`.
+    AwsKms { key_id: String, region: String },
+    /// An `EC_SIGN_ED25519` key version in Cloud KMS. The access token is `GCP_ACCESS_TOKEN`,
+    /// or the instance service account's from the metadata server.
+    GcpKms { key_version: String },
+    /// A signing service answering `GET <url>/pubkey` and `POST <url>/sign`.
+    Remote { url: String, token: Option<String> },
+}
+
+pub fn load_signer_config() -> SignerConfig {
+    let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+    let required = |name: &str| var(name).unwrap_or_else(|| panic!("{} must be set", name));
+    match var("SIGNER_BACKEND").as_deref().unwrap_or("local") {
+        "local" => SignerConfig::Local {
+            keypair_path: var("SIGNER_KEYPAIR_PATH"),
+            private_key: var("PHANTOM_PRIVATE_KEY"),
+        },
+        "aws_kms" => SignerConfig::AwsKms {
+            key_id: required("AWS_KMS_KEY_ID"),
+            region: var("AWS_REGION").or_else(|| var("AWS_DEFAULT_REGION")).expect("AWS_REGION must be set"),
+        },
+        "gcp_kms" => SignerConfig::GcpKms { key_version: required("GCP_KMS_KEY_VERSION") },
+        "remote" => SignerConfig::Remote {
+            url: required("SIGNER_REMOTE_URL").trim_end_matches('/').to_string(),
+            token: var("SIGNER_REMOTE_TOKEN"),
+        },
+        other => panic!("Unknown SIGNER_BACKEND {}; expected local, aws_kms, gcp_kms or remote", other),
+    }
+}
+
+/// The key that pays for and signs the backend's own transactions.
+pub trait TransactionSigner: Send + Sync {
+    fn pubkey(&self) -> Pubkey;
+
+    /// Signs a serialized transaction message.
+    fn sign_message<'a>(&'a self, message: &'a [u8]) -> BoxFuture<'a, AppResult<Signature>>;
+
+    /// Whether the key must be unsealed before it can sign; see `keystore`.
+    fn is_sealed(&self) -> bool {
+        false
+    }
+
+    fn unseal(&self, _passphrase: &str) -> AppResult<()> {
+        Err(AppError::BadRequest("The transaction signer is not a sealed keypair".to_string()))
+    }
+}
+
+/// Adds `signer`'s signature to `tx`, leaving any others in place. Signatures from a backend
+/// outside the process are checked before they are used.
+pub async fn sign_transaction(signer: &dyn TransactionSigner, tx: &mut Transaction) -> AppResult<()> {
+    let pubkey = signer.pubkey();
+    let required = tx.message.header.num_required_signatures as usize;
+    let index = tx.message.account_keys[..required]
+        .iter()
+        .position(|key| *key == pubkey)
+        .ok_or_else(|| AppError::InternalServerError(format!("Transaction does not need a signature from {}", pubkey)))?;
+    tx.signatures.resize(required, Signature::default());
+    let message = tx.message_data();
+    let signature = signer.sign_message(&message).await?;
+    if !signature.verify(pubkey.as_ref(), &message) {
+        return Err(AppError::InternalServerError(format!("Signer returned an invalid signature for {}", pubkey)));
+    }
+    tx.signatures[index] = signature;
+    Ok(())
+}
+
+/// Builds the configured signer. Keys held outside the process are asked for their public key
+/// once, here.
+pub async fn connect(config: &SignerConfig) -> Result<Arc<dyn TransactionSigner>, String> {
+    let signer: Arc<dyn TransactionSigner> = match config {
+        SignerConfig::Local { keypair_path: Some(path), .. } => {
+            let contents =
+                Zeroizing::new(std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?);
+            if keystore::is_sealed(&contents) {
+                let passphrase = keystore::take_passphrase()?;
+                Arc::new(SealedSigner::load(path, &contents, passphrase.as_deref().map(String::as_str))?)
+            } else {
+                Arc::new(LocalSigner::from_file(path, &contents)?)
+            }
+        }
+        SignerConfig::Local { keypair_path: None, private_key } => Arc::new(LocalSigner::from_base58(private_key)?),
+        SignerConfig::AwsKms { key_id, region } => Arc::new(AwsKmsSigner::connect(key_id, region).await?),
+        SignerConfig::GcpKms { key_version } => Arc::new(GcpKmsSigner::connect(key_version).await?),
+        SignerConfig::Remote { url, token } => Arc::new(RemoteSigner::connect(url, token.clone()).await?),
+    };
+    tracing::info!("Transaction signer {}", signer.pubkey());
+    Ok(signer)
+}
+
+fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(HTTP_TIMEOUT)
+        .build()
+        .expect("Failed to build signer HTTP client")
+}
+
+fn signer_error(backend: &str, error: impl std::fmt::Display) -> AppError {
+    AppError::InternalServerError(format!("{} signer failed: {}", backend, error))
+}
+
+/// The key from a DER `SubjectPublicKeyInfo`, as KMS returns it.
+fn ed25519_spki(der: &[u8]) -> Result<Pubkey, String> {
+    der.strip_prefix(ED25519_SPKI_PREFIX.as_slice())
+        .and_then(|key| Pubkey::try_from(key).ok())
+        .ok_or_else(|| "public key is not an Ed25519 key".to_string())
+}
+
+fn decode_signature(bytes: &[u8]) -> Result<Signature, String> {
+    Signature::try_from(bytes).map_err(|_| format!("expected a 64 byte signature, got {} bytes", bytes.len()))
+}
+
+// Local
+struct LocalSigner(Keypair);
+
+// Key bytes are zeroed once parsed; the keypair zeroes its own when dropped
+impl LocalSigner {
+    /// A `solana-keygen` JSON byte array.
+    fn from_file(path: &str, contents: &str) -> Result<Self, String> {
+        let bytes: Zeroizing<Vec<u8>> = Zeroizing::new(
+            serde_json::from_str(contents).map_err(|_| format!("{} is not a keypair file", path))?,
+        );
+        Keypair::from_bytes(&bytes)
+            .map(Self)
+            .map_err(|_| format!("{} is not a keypair file", path))
+    }
+
+    fn from_base58(private_key: &Option<String>) -> Result<Self, String> {
+        let private_key = private_key
+            .as_ref()
+            .ok_or_else(|| "SIGNER_KEYPAIR_PATH or PHANTOM_PRIVATE_KEY must be set".to_string())?;
+        let bytes = Zeroizing::new(
+            bs58::decode(private_key)
+                .into_vec()
+                .map_err(|_| "Invalid PHANTOM_PRIVATE_KEY format".to_string())?,
+        );
+        Keypair::from_bytes(&bytes)
+            .map(Self)
+            .map_err(|_| "Failed to parse PHANTOM_PRIVATE_KEY".to_string())
+    }
+}
+
+impl TransactionSigner for LocalSigner {
+    fn pubkey(&self) -> Pubkey {
+        self.0.pubkey()
+    }
+
+    fn sign_message<'a>(&'a self, message: &'a [u8]) -> BoxFuture<'a, AppResult<Signature>> {
+        Box::pin(async move { Ok(self.0.sign_message(message)) })
+    }
+}
+
+// AWS KMS
+struct AwsCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+/// A client for the AWS KMS JSON API in one region, also used by `custody` to wrap keys.
+pub struct AwsKms {
+    http: reqwest::Client,
+    region: String,
+    credentials: AwsCredentials,
+}
+
+struct AwsKmsSigner {
+    kms: AwsKms,
+    key_id: String,
+    pubkey: Pubkey,
+}
+
+#[derive(Deserialize)]
+struct AwsPublicKey {
+    #[serde(rename = "PublicKey")]
+    public_key: String,
+}
+
+#[derive(Deserialize)]
+struct AwsSignature {
+    #[serde(rename = "Signature")]
+    signature: String,
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+impl AwsKms {
+    /// Credentials come from `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and, for temporary
+    /// ones, `AWS_SESSION_TOKEN`.
+    pub fn from_env(region: &str) -> Result<Self, String> {
+        let credentials = AwsCredentials {
+            access_key_id: std::env::var("AWS_ACCESS_KEY_ID").map_err(|_| "AWS_ACCESS_KEY_ID must be set")?,
+            secret_access_key: std::env::var("AWS_SECRET_ACCESS_KEY").map_err(|_| "AWS_SECRET_ACCESS_KEY must be set")?,
+            session_token: std::env::var("AWS_SESSION_TOKEN").ok().filter(|v| !v.is_empty()),
+        };
+        Ok(Self { http: http_client(), region: region.to_string(), credentials })
+    }
+
+    /// A KMS JSON API call, signed with Signature Version 4.
+    pub async fn call<T: serde::de::DeserializeOwned>(&self, action: &str, body: serde_json::Value) -> AppResult<T> {
+        let host = format!("kms.{}.amazonaws.com", self.region);
+        let body = body.to_string();
+        let now = chrono::Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let target = format!("TrentService.{}", action);
+
+        let mut headers = vec![
+            ("content-type", "application/x-amz-json-1.1".to_string()),
+            ("host", host.clone()),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        headers.push(("x-amz-target", target.clone()));
+        let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value)).collect();
+        let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+        let canonical_request = format!(
+            "POST\n/\n\n{}\n{}\n{}",
+            canonical_headers,
+            signed_headers,
+            hex::encode(Sha256::digest(body.as_bytes()))
+        );
+        let scope = format!("{}/{}/kms/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = ["AWS4", &self.credentials.secret_access_key].concat();
+        let key = [date.as_str(), self.region.as_str(), "kms", "aws4_request"]
+            .iter()
+            .fold(key.into_bytes(), |key, part| hmac_sha256(&key, part));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.credentials.access_key_id,
+            scope,
+            signed_headers,
+            hex::encode(hmac_sha256(&key, &string_to_sign))
+        );
+
+        let mut request = self
+            .http
+            .post(format!("https://{}/", host))
+            .header("content-type", "application/x-amz-json-1.1")
+            .header("x-amz-date", amz_date)
+            .header("x-amz-target", target)
+            .header("authorization", authorization)
+            .body(body);
+        if let Some(token) = &self.credentials.session_token {
+            request = request.header("x-amz-security-token", token);
+        }
+        let failed = |e: String| AppError::InternalServerError(format!("AWS KMS {} failed: {}", action, e));
+        let response = request.send().await.map_err(|e| failed(e.to_string()))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(failed(format!("{} {}", status, text)));
+        }
+        response.json().await.map_err(|e| failed(e.to_string()))
+    }
+}
+
+impl AwsKmsSigner {
+    async fn connect(key_id: &str, region: &str) -> Result<Self, String> {
+        let mut signer = Self { kms: AwsKms::from_env(region)?, key_id: key_id.to_string(), pubkey: Pubkey::default() };
+        let response: AwsPublicKey = signer
+            .kms
+            .call("GetPublicKey", serde_json::json!({ "KeyId": key_id }))
+            .await
+            .map_err(|e| e.to_string())?;
+        let der = BASE64.decode(&response.public_key).map_err(|e| format!("Invalid KMS public key: {}", e))?;
+        signer.pubkey = ed25519_spki(&der).map_err(|e| format!("KMS key {}: {}", key_id, e))?;
+        Ok(signer)
+    }
+}
+
+impl TransactionSigner for AwsKmsSigner {
+    fn pubkey(&self) -> Pubkey {
+        self.pubkey
+    }
+
+    fn sign_message<'a>(&'a self, message: &'a [u8]) -> BoxFuture<'a, AppResult<Signature>> {
+        Box::pin(async move {
+            let body = serde_json::json!({
+                "KeyId": self.key_id,
+                "Message": BASE64.encode(message),
+                "MessageType": "RAW",
+                "SigningAlgorithm": "ED25519_SHA_512",
+            });
+            let response: AwsSignature = self.kms.call("Sign", body).await?;
+            let bytes = BASE64.decode(&response.signature).map_err(|e| signer_error("AWS KMS", e))?;
+            decode_signature(&bytes).map_err(|e| signer_error("AWS KMS", e))
+        })
+    }
+}
+
+// GCP Cloud KMS
+struct GcpKmsSigner {
+    http: reqwest::Client,
+    key_version: String, // projects/<p>/locations/<l>/keyRings/<r>/cryptoKeys/<k>/cryptoKeyVersions/<v>
+    static_token: Option<String>,
+    token: Mutex<Option<(String, Instant)>>, // From the metadata server, with its expiry
+    pubkey: Pubkey,
+}
+
+#[derive(Deserialize)]
+struct GcpToken {
+    access_token: String,
+    expires_in: u64,
+}
+
+#[derive(Deserialize)]
+struct GcpPublicKey {
+    pem: String,
+}
+
+#[derive(Deserialize)]
+struct GcpSignature {
+    signature: String,
+}
+
+impl GcpKmsSigner {
+    async fn connect(key_version: &str) -> Result<Self, String> {
+        let mut signer = Self {
+            http: http_client(),
+            key_version: key_version.to_string(),
+            static_token: std::env::var("GCP_ACCESS_TOKEN").ok().filter(|v| !v.is_empty()),
+            token: Mutex::new(None),
+            pubkey: Pubkey::default(),
+        };
+        let token = signer.access_token().await.map_err(|e| e.to_string())?;
+        let response = signer
+            .http
+            .get(format!("https://cloudkms.googleapis.com/v1/{}/publicKey", key_version))
+            .bearer_auth(token)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("Failed to fetch Cloud KMS public key: {}", e))?;
+        let key: GcpPublicKey = response.json().await.map_err(|e| format!("Invalid Cloud KMS public key: {}", e))?;
+        let der: String = key.pem.lines().filter(|line| !line.starts_with("-----")).collect();
+        let der = BASE64.decode(der.trim()).map_err(|e| format!("Invalid Cloud KMS public key: {}", e))?;
+        signer.pubkey = ed25519_spki(&der).map_err(|e| format!("Cloud KMS key {}: {}", key_version, e))?;
+        Ok(signer)
+    }
+
+    /// `GCP_ACCESS_TOKEN`, or a metadata server token reused until a minute before it expires.
+    async fn access_token(&self) -> AppResult<String> {
+        if let Some(token) = &self.static_token {
+            return Ok(token.clone());
+        }
+        if let Some((token, expires)) = self.token.lock().unwrap().as_ref() {
+            if Instant::now() < *expires {
+                return Ok(token.clone());
+            }
+        }
+        let token: GcpToken = self
+            .http
+            .get(GCP_METADATA_TOKEN_URL)
+            .header("Metadata-Flavor", "Google")
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| signer_error("Cloud KMS", e))?
+            .json()
+            .await
+            .map_err(|e| signer_error("Cloud KMS", e))?;
+        let expires = Instant::now() + Duration::from_secs(token.expires_in.saturating_sub(60));
+        *self.token.lock().unwrap() = Some((token.access_token.clone(), expires));
+        Ok(token.access_token)
+    }
+}
+
+impl TransactionSigner for GcpKmsSigner {
+    fn pubkey(&self) -> Pubkey {
+        self.pubkey
+    }
+
+    fn sign_message<'a>(&'a self, message: &'a [u8]) -> BoxFuture<'a, AppResult<Signature>> {
+        Box::pin(async move {
+            let token = self.access_token().await?;
+            let response: GcpSignature = self
+                .http
+                .post(format!("https://cloudkms.googleapis.com/v1/{}:asymmetricSign", self.key_version))
+                .bearer_auth(token)
+                .json(&serde_json::json!({ "data": BASE64.encode(message) }))
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| signer_error("Cloud KMS", e))?
+                .json()
+                .await
+                .map_err(|e| signer_error("Cloud KMS", e))?;
+            let bytes = BASE64.decode(&response.signature).map_err(|e| signer_error("Cloud KMS", e))?;
+            decode_signature(&bytes).map_err(|e| signer_error("Cloud KMS", e))
+        })
+    }
+}
+
+// Remote
+/// A signing service of our own: `GET <url>/pubkey` returns `{"pubkey": "<base58>"}` and
+/// `POST <url>/sign` with `{"message": "<base64>"}` returns `{"signature": "<base58>"}`, both
+/// with `SIGNER_REMOTE_TOKEN` as a bearer token when set.
+struct RemoteSigner {
+    http: reqwest::Client,
+    url: String,
+    token: Option<String>,
+    pubkey: Pubkey,
+}
+
+#[derive(Deserialize)]
+struct RemotePubkey {
+    pubkey: String,
+}
+
+#[derive(Deserialize)]
+struct RemoteSignature {
+    signature: String,
+}
+
+impl RemoteSigner {
+    async fn connect(url: &str, token: Option<String>) -> Result<Self, String> {
+        let http = http_client();
+        let mut request = http.get(format!("{}/pubkey", url));
+        if let Some(token) = &token {
+            request = request.bearer_auth(token);
+        }
+        let response: RemotePubkey = request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("Failed to fetch remote signer public key: {}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("Invalid remote signer public key: {}", e))?;
+        let pubkey = Pubkey::from_str(&response.pubkey).map_err(|e| format!("Invalid remote signer public key: {}", e))?;
+        Ok(Self { http, url: url.to_string(), token, pubkey })
+    }
+}
+
+impl TransactionSigner for RemoteSigner {
+    fn pubkey(&self) -> Pubkey {
+        self.pubkey
+    }
+
+    fn sign_message<'a>(&'a self, message: &'a [u8]) -> BoxFuture<'a, AppResult<Signature>> {
+        Box::pin(async move {
+            let mut request = self
+                .http
+                .post(format!("{}/sign", self.url))
+                .json(&serde_json::json!({ "message": BASE64.encode(message) }));
+            if let Some(token) = &self.token {
+                request = request.bearer_auth(token);
+            }
+            let response: RemoteSignature = request
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| signer_error("Remote", e))?
+                .json()
+                .await
+                .map_err(|e| signer_error("Remote", e))?;
+            Signature::from_str(&response.signature).map_err(|e| signer_error("Remote", e))
+        })
+    }
+}
@@ -0,0 +1,193 @@
+use actix_web::{post, web, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use solana_sdk::instruction::InstructionError;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::transaction::TransactionError;
+use std::str::FromStr;
+use utoipa::ToSchema;
+use validator::Validate;
+use crate::cluster::SolanaClusters;
+use crate::validation::{validate, FieldError, ValidatedJson};
+use crate::{AppError, AppResult, AuthToken, SubscriptionRequest};
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SimulatedAction {
+    Create,
+    Renew,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct SimulationRequest {
+    action: SimulatedAction,
+    #[validate(range(max = 9223372036854775807, message = "must fit in a signed 64-bit integer"))]
+    plan_id: u64,
+    duration: Option<u64>, // Required for create
+    amount: Option<u64>,   // Required for create
+}
+
+/// A program or system error decoded from the simulation.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct SimulatedError {
+    code: Option<u32>, // Custom error code, when the failure was one
+    name: Option<String>, // e.g. NotYetExpired
+    message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct SimulationResponse {
+    success: bool,
+    fee_lamports: u64,
+    rent_lamports: u64, // Rent-exempt deposit for a new subscription account, 0 for renewals
+    amount_lamports: Option<u64>, // Paid to the treasury; unknown for renewals of unindexed accounts
+    compute_units: Option<u64>,
+    error: Option<SimulatedError>,
+    logs: Vec<String>,
+}
+
+/// Why the cluster rejected a transaction in preflight, returned with the error so it can be
+/// debugged without server access.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct TransactionFailure {
+    pub error: Option<SimulatedError>,
+    pub logs: Vec<String>,
+}
+
+// Lines kept from a failed transaction's logs, and characters kept of each
+const MAX_LOG_LINES: usize = 100;
+const MAX_LOG_LINE_CHARS: usize = 512;
+
+/// Errors the program can return, by custom error code. Codes 0 and 1 come from the system
+/// program transfers the program makes; 6000 onwards are the program's own errors.
+fn program_error(code: u32) -> Option<(&'static str, &'static str)> {
+    match code {
+        0 => Some(("AccountAlreadyInUse", "Subscription account already exists")),
+        1 => Some(("InsufficientFunds", "Wallet balance does not cover the payment")),
+        3012 => Some(("AccountNotInitialized", "Subscription account does not exist")),
+        6000 => Some(("InactiveSubscription", "Subscription is not active")),
+        6001 => Some(("ActiveSubscription", "Subscription is still active")),
+        6002 => Some(("Unauthorized", "Unauthorized access to subscription")),
+        6003 => Some(("NotYetExpired", "Subscription has not yet expired")),
+        6004 => Some(("FixedParameters", "Subscription parameters are fixed and cannot be updated")),
+        _ => None,
+    }
+}
+
+fn simulated_error(err: &TransactionError) -> SimulatedError {
+    match err {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => {
+            let known = program_error(*code);
+            SimulatedError {
+                code: Some(*code),
+                name: known.map(|(name, _)| name.to_string()),
+                message: known.map_or_else(|| err.to_string(), |(_, message)| message.to_string()),
+            }
+        }
+        _ => SimulatedError {
+            code: None,
+            name: None,
+            message: err.to_string(),
+        },
+    }
+}
+
+/// One-line description of a transaction error, naming known program errors.
+pub fn describe_error(err: &TransactionError) -> String {
+    let error = simulated_error(err);
+    match error.name {
+        Some(name) => format!("{}: {}", name, error.message),
+        None => error.message,
+    }
+}
+
+/// Program logs as returned to clients: control characters removed, long lines truncated,
+/// and only the last `MAX_LOG_LINES`, which hold the failure.
+pub fn sanitize_logs(logs: &[String]) -> Vec<String> {
+    logs[logs.len().saturating_sub(MAX_LOG_LINES)..]
+        .iter()
+        .map(|line| line.chars().filter(|c| !c.is_control()).take(MAX_LOG_LINE_CHARS).collect())
+        .collect()
+}
+
+impl TransactionFailure {
+    pub fn new(err: Option<&TransactionError>, logs: &[String]) -> Self {
+        Self {
+            error: err.map(simulated_error),
+            logs: sanitize_logs(logs),
+        }
+    }
+}
+
+// Controllers
+/// Simulates the create or renew transaction the backend would send, so wallets can show
+/// the cost and any program error before the user signs.
+#[utoipa::path(
+    post,
+    path = "/api/v1/subscriptions/simulate",
+    tag = "subscriptions",
+    request_body = SimulationRequest,
+    responses(
+        (status = 200, description = "Simulation result, including failed simulations", body = SimulationResponse),
+        (status = 422, description = "Invalid request", body = ErrorResponse),
+        (status = 502, description = "RPC node unavailable", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/subscriptions/simulate")]
+pub async fn simulate_subscription(
+    req: HttpRequest,
+    clusters: web::Data<SolanaClusters>,
+    body: ValidatedJson<SimulationRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let solana_service = clusters.select(&req)?;
+    let owner = Pubkey::from_str(&auth_token.public_key)
+        .map_err(|e| AppError::BadRequest(format!("Invalid public key: {}", e)))?;
+    let body = body.into_inner();
+
+    let (instruction, rent_lamports, amount_lamports) = match body.action {
+        SimulatedAction::Create => {
+            let mut missing = Vec::new();
+            if body.duration.is_none() {
+                missing.push(FieldError::new("duration", "required", "is required to simulate create"));
+            }
+            if body.amount.is_none() {
+                missing.push(FieldError::new("amount", "required", "is required to simulate create"));
+            }
+            if !missing.is_empty() {
+                return Err(AppError::Validation(missing));
+            }
+            let request = SubscriptionRequest {
+                plan_id: body.plan_id,
+                duration: body.duration.unwrap_or_default(),
+                amount: body.amount.unwrap_or_default(),
+            };
+            validate(&request)?;
+            solana_service.check_terms(&request)?;
+
+            let rent = solana_service.subscription_rent().await?;
+            (solana_service.create_instruction(&owner, &request), rent, Some(request.amount))
+        }
+        SimulatedAction::Renew => {
+            let amount = solana_service
+                .get_subscription(&auth_token.public_key, body.plan_id, solana_service.read_commitment(None))
+                .await
+                .ok()
+                .map(|sub| sub.amount);
+            (solana_service.renew_instruction(&owner, body.plan_id), 0, amount)
+        }
+    };
+
+    let (fee_lamports, result) = solana_service.simulate(&owner, &[instruction]).await?;
+    Ok(HttpResponse::Ok().json(SimulationResponse {
+        success: result.err.is_none(),
+        fee_lamports,
+        rent_lamports,
+        amount_lamports,
+        compute_units: result.units_consumed,
+        error: result.err.as_ref().map(simulated_error),
+        logs: sanitize_logs(&result.logs.unwrap_or_default()),
+    }))
+}
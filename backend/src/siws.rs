@@ -0,0 +1,265 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+const HEADER_SUFFIX: &str = " wants you to sign in with your Solana account:";
+const MAX_CLOCK_SKEW_SECS: i64 = 60;
+
+/// Sign-In-With-Solana input as accepted by wallet adapters' `signIn()`.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SiwsInput {
+    pub domain: String,
+    pub address: String,
+    pub statement: String,
+    pub version: String,
+    pub nonce: String,
+    pub issued_at: String,
+    pub expiration_time: String,
+}
+
+impl SiwsInput {
+    pub fn new(domain: &str, address: &str, statement: &str, nonce: &str, expires_at: i64) -> Self {
+        let expiration = DateTime::<Utc>::from_timestamp(expires_at, 0).unwrap_or_else(Utc::now);
+        Self {
+            domain: domain.to_string(),
+            address: address.to_string(),
+            statement: statement.to_string(),
+            version: "1".to_string(),
+            nonce: nonce.to_string(),
+            issued_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            expiration_time: expiration.to_rfc3339_opts(SecondsFormat::Secs, true),
+        }
+    }
+}
+
+/// A parsed SIWS message, following the text layout produced by the wallet standard:
+///
+/// ```text
+/// {domain} wants you to sign in with your Solana account:
+/// {address}
+///
+/// {statement}
+///
+/// URI: {uri}
+/// Version: {version}
+/// Chain ID: {chain_id}
+/// Nonce: {nonce}
+/// Issued At: {issued_at}
+/// Expiration Time: {expiration_time}
+/// Not Before: {not_before}
+/// Request ID: {request_id}
+/// Resources:
+/// - {resource}
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SiwsMessage {
+    pub domain: String,
+    pub address: String,
+    pub statement: Option<String>,
+    pub uri: Option<String>,
+    pub version: Option<String>,
+    pub chain_id: Option<String>,
+    pub nonce: Option<String>,
+    pub issued_at: Option<i64>,
+    pub expiration_time: Option<i64>,
+    pub not_before: Option<i64>,
+    pub request_id: Option<String>,
+    pub resources: Vec<String>,
+}
+
+impl SiwsMessage {
+    pub fn parse(message: &str) -> Result<Self, String> {
+        let mut lines = message.lines();
+        let domain = lines
+            .next()
+            .and_then(|line| line.strip_suffix(HEADER_SUFFIX))
+            .ok_or("missing sign-in header")?;
+        let address = lines.next().filter(|l| !l.is_empty()).ok_or("missing address")?;
+
+        let mut parsed = SiwsMessage {
+            domain: domain.to_string(),
+            address: address.to_string(),
+            ..Default::default()
+        };
+
+        let mut in_resources = false;
+        let mut seen_field = false;
+        for line in lines {
+            if line.is_empty() {
+                continue;
+            }
+            if in_resources {
+                let resource = line.strip_prefix("- ").ok_or("malformed resource entry")?;
+                parsed.resources.push(resource.to_string());
+                continue;
+            }
+
+            let field = line.split_once(": ").map(|(k, v)| (k, v.to_string()));
+            match field {
+                Some(("URI", v)) => parsed.uri = Some(v),
+                Some(("Version", v)) => parsed.version = Some(v),
+                Some(("Chain ID", v)) => parsed.chain_id = Some(v),
+                Some(("Nonce", v)) => parsed.nonce = Some(v),
+                Some(("Issued At", v)) => parsed.issued_at = Some(parse_time(&v)?),
+                Some(("Expiration Time", v)) => parsed.expiration_time = Some(parse_time(&v)?),
+                Some(("Not Before", v)) => parsed.not_before = Some(parse_time(&v)?),
+                Some(("Request ID", v)) => parsed.request_id = Some(v),
+                _ if line == "Resources:" => in_resources = true,
+                // Free text is only allowed as the statement, before any field
+                _ if !seen_field && parsed.statement.is_none() => {
+                    parsed.statement = Some(line.to_string());
+                    continue;
+                }
+                _ => return Err(format!("unexpected line: {}", line)),
+            }
+            seen_field = true;
+        }
+
+        Ok(parsed)
+    }
+
+    /// Checks the message was addressed to this service and wallet and is currently valid.
+    pub fn validate(&self, expected_domain: &str, public_key: &str, now: i64) -> Result<(), String> {
+        if self.domain != expected_domain {
+            return Err(format!("message is for domain {}, expected {}", self.domain, expected_domain));
+        }
+        if self.address != public_key {
+            return Err("message address does not match public key".to_string());
+        }
+        if self.nonce.is_none() {
+            return Err("message has no nonce".to_string());
+        }
+        if let Some(version) = &self.version {
+            if version != "1" {
+                return Err(format!("unsupported SIWS version {}", version));
+            }
+        }
+        match self.issued_at {
+            Some(issued_at) if issued_at > now + MAX_CLOCK_SKEW_SECS => {
+                return Err("message issued in the future".to_string())
+            }
+            Some(_) => {}
+            None => return Err("message has no issued-at time".to_string()),
+        }
+        if self.expiration_time.is_some_and(|exp| exp <= now) {
+            return Err("message has expired".to_string());
+        }
+        if self.not_before.is_some_and(|nbf| nbf > now + MAX_CLOCK_SKEW_SECS) {
+            return Err("message is not yet valid".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn parse_time(value: &str) -> Result<i64, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.timestamp())
+        .map_err(|e| format!("invalid timestamp {}: {}", value, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOMAIN: &str = "app.example.com";
+    const ADDRESS: &str = "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM";
+    // 2024-01-01T00:00:00Z
+    const ISSUED_AT: i64 = 1_704_067_200;
+
+    fn message(fields: &str) -> String {
+        format!("{}{}\n{}\n\nSign in to manage subscriptions\n\n{}", DOMAIN, HEADER_SUFFIX, ADDRESS, fields)
+    }
+
+    fn valid() -> SiwsMessage {
+        SiwsMessage::parse(&message(
+            "Version: 1\nNonce: abc123\nIssued At: 2024-01-01T00:00:00Z\nExpiration Time: 2024-01-01T00:05:00Z",
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_every_field() {
+        let parsed = SiwsMessage::parse(&message(
+            "URI: https://app.example.com\nVersion: 1\nChain ID: mainnet\nNonce: abc123\n\
+             Issued At: 2024-01-01T00:00:00Z\nExpiration Time: 2024-01-01T00:05:00Z\n\
+             Not Before: 2024-01-01T00:00:00+00:00\nRequest ID: req-1\nResources:\n- https://a.example\n- https://b.example",
+        ))
+        .unwrap();
+        assert_eq!(parsed.domain, DOMAIN);
+        assert_eq!(parsed.address, ADDRESS);
+        assert_eq!(parsed.statement.as_deref(), Some("Sign in to manage subscriptions"));
+        assert_eq!(parsed.uri.as_deref(), Some("https://app.example.com"));
+        assert_eq!(parsed.version.as_deref(), Some("1"));
+        assert_eq!(parsed.chain_id.as_deref(), Some("mainnet"));
+        assert_eq!(parsed.nonce.as_deref(), Some("abc123"));
+        assert_eq!(parsed.issued_at, Some(ISSUED_AT));
+        assert_eq!(parsed.expiration_time, Some(ISSUED_AT + 300));
+        assert_eq!(parsed.not_before, Some(ISSUED_AT));
+        assert_eq!(parsed.request_id.as_deref(), Some("req-1"));
+        assert_eq!(parsed.resources, vec!["https://a.example", "https://b.example"]);
+    }
+
+    #[test]
+    fn parses_without_statement() {
+        let text = format!("{}{}\n{}\n\nNonce: abc123", DOMAIN, HEADER_SUFFIX, ADDRESS);
+        let parsed = SiwsMessage::parse(&text).unwrap();
+        assert_eq!(parsed.statement, None);
+        assert_eq!(parsed.nonce.as_deref(), Some("abc123"));
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        assert!(SiwsMessage::parse("hello").is_err());
+        assert!(SiwsMessage::parse(&format!("{}{}\n", DOMAIN, HEADER_SUFFIX)).is_err());
+        assert!(SiwsMessage::parse(&message("Issued At: yesterday")).is_err());
+        assert!(SiwsMessage::parse(&message("Resources:\nhttps://a.example")).is_err());
+        // Free text after a field is not a statement
+        assert!(SiwsMessage::parse(&message("Nonce: abc123\nanything else")).is_err());
+    }
+
+    #[test]
+    fn accepts_a_current_message() {
+        assert_eq!(valid().validate(DOMAIN, ADDRESS, ISSUED_AT + 10), Ok(()));
+    }
+
+    #[test]
+    fn rejects_another_domain_or_address() {
+        assert!(valid().validate("evil.example.com", ADDRESS, ISSUED_AT).is_err());
+        assert!(valid().validate(DOMAIN, "11111111111111111111111111111111", ISSUED_AT).is_err());
+    }
+
+    #[test]
+    fn rejects_messages_outside_their_validity() {
+        assert!(valid().validate(DOMAIN, ADDRESS, ISSUED_AT + 300).is_err());
+        assert!(valid().validate(DOMAIN, ADDRESS, ISSUED_AT - MAX_CLOCK_SKEW_SECS).is_ok());
+        assert!(valid().validate(DOMAIN, ADDRESS, ISSUED_AT - MAX_CLOCK_SKEW_SECS - 1).is_err());
+
+        let mut not_yet = valid();
+        not_yet.not_before = Some(ISSUED_AT + 200);
+        assert!(not_yet.validate(DOMAIN, ADDRESS, ISSUED_AT).is_err());
+    }
+
+    #[test]
+    fn rejects_missing_or_unsupported_fields() {
+        let mut message = valid();
+        message.nonce = None;
+        assert!(message.validate(DOMAIN, ADDRESS, ISSUED_AT).is_err());
+
+        let mut message = valid();
+        message.issued_at = None;
+        assert!(message.validate(DOMAIN, ADDRESS, ISSUED_AT).is_err());
+
+        let mut message = valid();
+        message.version = Some("2".to_string());
+        assert!(message.validate(DOMAIN, ADDRESS, ISSUED_AT).is_err());
+    }
+
+    #[test]
+    fn input_expires_when_asked() {
+        let input = SiwsInput::new(DOMAIN, ADDRESS, "Sign in", "abc123", ISSUED_AT);
+        assert_eq!(input.version, "1");
+        assert_eq!(input.expiration_time, "2024-01-01T00:00:00Z");
+        assert_eq!(parse_time(&input.expiration_time), Ok(ISSUED_AT));
+    }
+}
@@ -0,0 +1,199 @@
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use solana_sdk::instruction::AccountMeta;
+use solana_sdk::pubkey::Pubkey;
+use std::str::FromStr;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+use crate::cluster::SolanaClusters;
+use crate::plans::PlanService;
+use crate::validation::{validate, validate_pubkey, ValidatedJson, ValidatedQuery};
+use crate::{AppError, AppResult, Config, SolanaService, SubscriptionRequest};
+
+const LAMPORTS_PER_SOL: f64 = 1_000_000_000.0;
+const SECS_PER_DAY: u64 = 86_400;
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PayAction {
+    Subscribe,
+    Renew,
+}
+
+/// What a wallet shows before asking for the account, per the Solana Pay transaction request
+/// spec.
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct PayLabel {
+    label: String,
+    icon: String, // SVG, PNG or WebP
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct PayRequest {
+    #[validate(custom = "validate_pubkey")]
+    account: String, // The wallet that signs and pays for the transaction
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct PayTransaction {
+    transaction: String, // Base64 bincode of the unsigned transaction, the wallet as fee payer
+    message: String,
+}
+
+#[derive(Debug, Deserialize, Clone, IntoParams, Validate)]
+pub struct PayQuery {
+    #[validate(custom = "validate_pubkey")]
+    reference: Option<String>, // Added to the instruction so the payment can be found by address
+}
+
+/// The requester shown by wallets. Solana Pay is off unless `SOLANA_PAY_ICON_URL` is set,
+/// as the spec requires an icon.
+#[derive(Debug, Clone)]
+pub struct SolanaPay {
+    label: String,
+    icon: String,
+}
+
+impl SolanaPay {
+    pub fn new(config: &Config) -> Option<Self> {
+        config.solana_pay_icon_url.as_ref().map(|icon| Self {
+            label: config.solana_pay_label.clone(),
+            icon: icon.clone(),
+        })
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub fn icon(&self) -> &str {
+        &self.icon
+    }
+}
+
+fn parse_pubkey(value: &str) -> AppResult<Pubkey> {
+    Pubkey::from_str(value).map_err(|e| AppError::BadRequest(format!("Invalid public key: {}", e)))
+}
+
+/// The subscribe or renew transaction for `account` to sign and send itself, base64 encoded,
+/// and the message for the wallet to show with it. A subscription is created at the plan's
+/// current catalog price.
+pub async fn build_transaction(
+    solana_service: &SolanaService,
+    plans: &PlanService,
+    action: PayAction,
+    plan_id: u64,
+    account: &str,
+    reference: Option<&str>,
+) -> AppResult<(String, String)> {
+    let owner = parse_pubkey(account)?;
+    let reference = reference.map(parse_pubkey).transpose()?;
+    let plan = plans.published(plan_id).await?;
+    let pda = solana_service.subscription_pda(&owner, plan_id);
+
+    let (mut instruction, message) = match action {
+        PayAction::Subscribe => {
+            if plan.archived() {
+                return Err(AppError::BadRequest(format!("Plan {} is no longer offered", plan_id)));
+            }
+            let terms = plan.terms();
+            validate(&terms)?;
+            solana_service.check_terms(&terms)?;
+            if solana_service.accounts().get(&pda, solana_service.check_commitment()).await?.is_some() {
+                return Err(AppError::BadRequest(format!("Subscription PDA {} already exists", pda)));
+            }
+            let message = format!("Subscribe to {}: {}", plan.name(), price(solana_service, &terms));
+            (solana_service.create_instruction(&owner, &terms), message)
+        }
+        PayAction::Renew => {
+            if solana_service.accounts().get(&pda, solana_service.check_commitment()).await?.is_none() {
+                return Err(AppError::BadRequest(format!("Subscription PDA {} does not exist", pda)));
+            }
+            (solana_service.renew_instruction(&owner, plan_id), format!("Renew {}", plan.name()))
+        }
+    };
+    if let Some(reference) = reference {
+        instruction.accounts.push(AccountMeta::new_readonly(reference, false));
+    }
+
+    let (tx, _) = solana_service.unsigned_transaction(&owner, &[instruction]).await?;
+    let serialized = bincode::serialize(&tx)
+        .map_err(|e| AppError::InternalServerError(format!("Failed to serialize transaction: {}", e)))?;
+    Ok((BASE64.encode(serialized), message))
+}
+
+/// What a create on `terms` charges, e.g. `1 SOL for 30 days`.
+pub fn price(solana_service: &SolanaService, terms: &SubscriptionRequest) -> String {
+    let charged = solana_service.layout.charged_amount(terms.amount);
+    format!(
+        "{} SOL for {} days",
+        charged as f64 / LAMPORTS_PER_SOL,
+        terms.duration.div_ceil(SECS_PER_DAY)
+    )
+}
+
+// Controllers
+#[utoipa::path(
+    get,
+    path = "/pay/{action}/{plan_id}",
+    tag = "solana-pay",
+    params(
+        ("action" = PayAction, Path, description = "`subscribe` or `renew`"),
+        ("plan_id" = u64, Path, description = "Plan identifier"),
+    ),
+    responses(
+        (status = 200, description = "Label and icon for the wallet to show", body = PayLabel),
+        (status = 404, description = "Plan not in the catalog", body = ErrorResponse),
+    )
+)]
+#[get("/{action}/{plan_id}")]
+pub async fn pay_label(
+    path: web::Path<(PayAction, u64)>,
+    solana_pay: web::Data<SolanaPay>,
+    plans: web::Data<PlanService>,
+) -> AppResult<HttpResponse> {
+    let (_, plan_id) = path.into_inner();
+    plans.published(plan_id).await?;
+    Ok(HttpResponse::Ok().json(PayLabel { label: solana_pay.label().to_string(), icon: solana_pay.icon().to_string() }))
+}
+
+/// Builds the subscribe or renew transaction for the wallet named in `account`, which signs
+/// and sends it itself. The listener indexes it once it lands, like any other program
+/// transaction.
+#[utoipa::path(
+    post,
+    path = "/pay/{action}/{plan_id}",
+    tag = "solana-pay",
+    params(
+        ("action" = PayAction, Path, description = "`subscribe` or `renew`"),
+        ("plan_id" = u64, Path, description = "Plan identifier"),
+        PayQuery,
+    ),
+    request_body = PayRequest,
+    responses(
+        (status = 200, description = "Transaction for the wallet to sign and send", body = PayTransaction),
+        (status = 400, description = "Subscription already exists, does not exist, or plan archived", body = ErrorResponse),
+        (status = 404, description = "Plan not in the catalog", body = ErrorResponse),
+        (status = 422, description = "Invalid account or reference", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded, retry after `Retry-After` seconds", body = ErrorResponse),
+        (status = 502, description = "RPC node unavailable", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    )
+)]
+#[post("/{action}/{plan_id}")]
+pub async fn pay_transaction(
+    req: HttpRequest,
+    path: web::Path<(PayAction, u64)>,
+    query: ValidatedQuery<PayQuery>,
+    clusters: web::Data<SolanaClusters>,
+    plans: web::Data<PlanService>,
+    body: ValidatedJson<PayRequest>,
+) -> AppResult<HttpResponse> {
+    let (action, plan_id) = path.into_inner();
+    let solana_service = clusters.select(&req)?;
+    let (transaction, message) =
+        build_transaction(solana_service, &plans, action, plan_id, &body.account, query.reference.as_deref()).await?;
+    Ok(HttpResponse::Ok().json(PayTransaction { transaction, message }))
+}
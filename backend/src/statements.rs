@@ -0,0 +1,418 @@
+use actix_web::http::header::CONTENT_DISPOSITION;
+use actix_web::{get, web, HttpMessage, HttpRequest, HttpResponse};
+use chrono::{Datelike, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+use crate::db::{self, PlanPeriodTotalsRow};
+use crate::email::EmailTemplate;
+use crate::exports::csv_field;
+use crate::metrics;
+use crate::notifications::NotificationService;
+use crate::plans::PlanService;
+use crate::reporting;
+use crate::validation::ValidatedQuery;
+use crate::{unix_now, AppError, AppResult, AuthToken, Config};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+const LAMPORTS_PER_SOL: i64 = 1_000_000_000;
+const CSV_HEADER: &str = "plan_id,plan_name,payments,gross_lamports,refunds,refunded_lamports,fee_lamports,net_lamports\n";
+// Courier 8pt on A4, with 40pt margins
+const PDF_FONT_SIZE: u32 = 8;
+const PDF_LEADING: u32 = 10;
+const PDF_LINES_PER_PAGE: usize = 75;
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StatementFormat {
+    Csv,
+    Pdf,
+    Json,
+}
+
+impl StatementFormat {
+    fn as_str(&self) -> &'static str {
+        match self {
+            StatementFormat::Csv => "csv",
+            StatementFormat::Pdf => "pdf",
+            StatementFormat::Json => "json",
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            StatementFormat::Csv => "text/csv; charset=utf-8",
+            StatementFormat::Pdf => "application/pdf",
+            StatementFormat::Json => "application/json",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct StatementQuery {
+    format: Option<StatementFormat>, // Defaults to csv
+}
+
+/// One plan's activity over the month.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, ToSchema)]
+pub struct StatementLine {
+    plan_id: Option<u64>, // Unset on the totals
+    plan_name: Option<String>,
+    payments: u64,
+    gross_lamports: u64,
+    refunds: u64,
+    refunded_lamports: u64, // Refunds paid out in the month, whichever month the payment was in
+    fee_lamports: u64,
+    net_lamports: i64, // Gross less refunds and fees; negative when refunds exceed the month's revenue
+}
+
+impl StatementLine {
+    fn add(&mut self, other: &StatementLine) {
+        self.payments += other.payments;
+        self.gross_lamports += other.gross_lamports;
+        self.refunds += other.refunds;
+        self.refunded_lamports += other.refunded_lamports;
+        self.fee_lamports += other.fee_lamports;
+        self.net_lamports += other.net_lamports;
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Statement {
+    merchant: String,
+    year: i32,
+    month: u32,
+    period_start: i64, // Unix seconds, inclusive
+    period_end: i64,   // Unix seconds, exclusive
+    fee_bps: u32,
+    plans: Vec<StatementLine>, // Plans with activity in the month
+    totals: StatementLine,
+    generated_at: i64,
+}
+
+impl Statement {
+    fn to_csv(&self) -> String {
+        let mut out = String::from(CSV_HEADER);
+        for line in self.plans.iter().chain(std::iter::once(&self.totals)) {
+            let _ = writeln!(
+                out,
+                "{},{},{},{},{},{},{},{}",
+                line.plan_id.map(|id| id.to_string()).unwrap_or_else(|| "total".to_string()),
+                csv_field(line.plan_name.as_deref().unwrap_or_default()),
+                line.payments,
+                line.gross_lamports,
+                line.refunds,
+                line.refunded_lamports,
+                line.fee_lamports,
+                line.net_lamports,
+            );
+        }
+        out
+    }
+
+    /// A plain-text table, one PDF page per `PDF_LINES_PER_PAGE` lines.
+    fn to_pdf(&self) -> Vec<u8> {
+        let row = |plan: &str, name: &str, payments: &str, gross: &str, refunds: &str, refunded: &str, fees: &str, net: &str| {
+            format!(
+                "{:<6} {:<18} {:>5} {:>15} {:>5} {:>15} {:>15} {:>15}",
+                plan, name, payments, gross, refunds, refunded, fees, net
+            )
+        };
+        let mut lines = vec![
+            format!("Statement for {}", self.merchant),
+            format!(
+                "Period: {} to {} (UTC)",
+                format_date(self.period_start),
+                format_date(self.period_end)
+            ),
+            format!("Platform fee: {} bps of gross revenue", self.fee_bps),
+            format!("Generated: {}", format_date(self.generated_at)),
+            String::new(),
+            row("Plan", "Name", "Paid", "Gross SOL", "Refs", "Refunded SOL", "Fees SOL", "Net SOL"),
+        ];
+        for line in self.plans.iter().chain(std::iter::once(&self.totals)) {
+            let mut name: String = line.plan_name.as_deref().unwrap_or_default().chars().take(18).collect();
+            if line.plan_id.is_none() {
+                name = "Total".to_string();
+            }
+            lines.push(row(
+                &line.plan_id.map(|id| id.to_string()).unwrap_or_default(),
+                &name,
+                &line.payments.to_string(),
+                &format_sol(line.gross_lamports as i64),
+                &line.refunds.to_string(),
+                &format_sol(line.refunded_lamports as i64),
+                &format_sol(line.fee_lamports as i64),
+                &format_sol(line.net_lamports),
+            ));
+        }
+        render_pdf(&lines)
+    }
+}
+
+// Statement Service
+/// Monthly statements per merchant, summing the indexed payments and paid-out refunds of the
+/// merchant's plans over a UTC calendar month, less the platform fee (`PLATFORM_FEE_BPS` of
+/// gross revenue). Merchants who opt in to `monthly_statements` get last month's statement
+/// emailed, with the CSV attached, early on the 1st.
+#[derive(Clone)]
+pub struct StatementService {
+    pool: PgPool,
+    plans: PlanService,
+    notifications: NotificationService,
+    fee_bps: u32,
+}
+
+impl StatementService {
+    pub fn new(config: &Config, pool: PgPool, plans: PlanService, notifications: NotificationService) -> Self {
+        Self { pool, plans, notifications, fee_bps: config.platform_fee_bps }
+    }
+
+    /// The statement of `merchant` for the month, over `plan_ids` (every plan when `None`).
+    pub async fn generate(&self, merchant: &str, plan_ids: Option<&[i64]>, year: i32, month: u32) -> AppResult<Statement> {
+        let (period_start, period_end) = month_range(year, month)?;
+        if period_start > unix_now() {
+            return Err(AppError::BadRequest(format!("{}-{:02} has not started yet", year, month)));
+        }
+        let rows = db::list_plan_period_totals(&self.pool, plan_ids, period_start, period_end).await?;
+        let plans: Vec<StatementLine> = rows.into_iter().map(|row| self.line(row)).collect();
+        let mut totals = StatementLine::default();
+        for line in &plans {
+            totals.add(line);
+        }
+        Ok(Statement {
+            merchant: merchant.to_string(),
+            year,
+            month,
+            period_start,
+            period_end,
+            fee_bps: self.fee_bps,
+            plans,
+            totals,
+            generated_at: unix_now(),
+        })
+    }
+
+    fn line(&self, row: PlanPeriodTotalsRow) -> StatementLine {
+        let gross = row.payment_lamports as u64;
+        let refunded = row.refunded_lamports as u64;
+        let fee = (gross as u128 * self.fee_bps as u128 / 10_000) as u64;
+        StatementLine {
+            plan_id: Some(row.plan_id as u64),
+            plan_name: row.name,
+            payments: row.payments as u64,
+            gross_lamports: gross,
+            refunds: row.refunds as u64,
+            refunded_lamports: refunded,
+            fee_lamports: fee,
+            net_lamports: gross as i64 - refunded as i64 - fee as i64,
+        }
+    }
+
+    pub async fn run(self) {
+        loop {
+            let started = Instant::now();
+            match self.email_due().await {
+                Ok(sent) => {
+                    metrics::record_job_success("statements", started, sent);
+                    if sent > 0 {
+                        tracing::info!("Emailed {} monthly statements", sent);
+                    }
+                }
+                Err(e) => {
+                    metrics::record_job_failure("statements", started);
+                    tracing::error!("Monthly statements failed: {}", e);
+                    reporting::capture_job_failure("statements", &e);
+                }
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    }
+
+    /// Emails last month's statement to each opted-in merchant that has not had it yet.
+    #[tracing::instrument(name = "monthly_statements", skip_all)]
+    pub async fn email_due(&self) -> AppResult<usize> {
+        let today = Utc::now().date_naive();
+        let (year, month) = if today.month() == 1 { (today.year() - 1, 12) } else { (today.year(), today.month() - 1) };
+        let (period_start, _) = month_range(year, month)?;
+        let mut sent = 0;
+        for merchant in db::list_statement_recipients(&self.pool).await? {
+            // Claiming first keeps replicas from sending the same statement
+            if !db::claim_statement(&self.pool, &merchant, period_start).await? {
+                continue;
+            }
+            match self.email(&merchant, year, month).await {
+                Ok(emailed) => {
+                    db::finish_statement(&self.pool, &merchant, period_start).await?;
+                    sent += emailed as usize;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to email {}'s statement for {}-{:02}: {}", merchant, year, month, e);
+                    db::release_statement(&self.pool, &merchant, period_start).await?;
+                }
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Returns false, sending nothing, for a month without activity.
+    async fn email(&self, merchant: &str, year: i32, month: u32) -> AppResult<bool> {
+        let plan_ids = self.plans.merchant_plan_ids(merchant).await?;
+        let statement = self.generate(merchant, Some(&plan_ids), year, month).await?;
+        if statement.plans.is_empty() {
+            return Ok(false);
+        }
+        let template = EmailTemplate::MonthlyStatement {
+            merchant: merchant.to_string(),
+            period_start: statement.period_start,
+            payments: statement.totals.payments,
+            gross: statement.totals.gross_lamports,
+            refunded: statement.totals.refunded_lamports,
+            fees: statement.totals.fee_lamports,
+            net: statement.totals.net_lamports,
+            csv: statement.to_csv(),
+        };
+        self.notifications.deliver(merchant, &template).await?;
+        Ok(true)
+    }
+}
+
+/// Start (inclusive) and end (exclusive) of a UTC calendar month, in Unix seconds.
+fn month_range(year: i32, month: u32) -> AppResult<(i64, i64)> {
+    if !(2000..=9999).contains(&year) {
+        return Err(AppError::BadRequest(format!("Invalid year {}", year)));
+    }
+    if !(1..=12).contains(&month) {
+        return Err(AppError::BadRequest(format!("Invalid month {}", month)));
+    }
+    let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    let start = |year: i32, month: u32| {
+        NaiveDate::from_ymd_opt(year, month, 1)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|time| time.and_utc().timestamp())
+            .ok_or_else(|| AppError::BadRequest(format!("Invalid month {}-{:02}", year, month)))
+    };
+    Ok((start(year, month)?, start(next_year, next_month)?))
+}
+
+fn format_date(timestamp: i64) -> String {
+    chrono::DateTime::<Utc>::from_timestamp(timestamp, 0)
+        .map(|t| t.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+/// Exact, unlike going through `f64`.
+fn format_sol(lamports: i64) -> String {
+    let sign = if lamports < 0 { "-" } else { "" };
+    let lamports = lamports.unsigned_abs();
+    format!("{}{}.{:09}", sign, lamports / LAMPORTS_PER_SOL as u64, lamports % LAMPORTS_PER_SOL as u64)
+}
+
+/// A minimal PDF 1.4 document of monospaced text lines, so statements need no PDF library.
+/// Only printable ASCII is kept; anything else is replaced with `?`.
+fn render_pdf(lines: &[String]) -> Vec<u8> {
+    let pages: Vec<&[String]> = if lines.is_empty() { vec![&[]] } else { lines.chunks(PDF_LINES_PER_PAGE).collect() };
+    // 1 catalog, 2 page tree, 3 font, then a page and its content stream per page
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            (0..pages.len()).map(|i| format!("{} 0 R", 4 + 2 * i)).collect::<Vec<_>>().join(" "),
+            pages.len()
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Courier >>".to_string(),
+    ];
+    for (i, page) in pages.iter().enumerate() {
+        let mut content = format!("BT /F1 {} Tf {} TL 40 802 Td\n", PDF_FONT_SIZE, PDF_LEADING);
+        for line in page.iter() {
+            let _ = writeln!(content, "({}) Tj T*", pdf_text(line));
+        }
+        content.push_str("ET");
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 595 842] /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+            5 + 2 * i
+        ));
+        objects.push(format!("<< /Length {} >>\nstream\n{}\nendstream", content.len(), content));
+    }
+
+    let mut out = String::from("%PDF-1.4\n");
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(out.len());
+        let _ = write!(out, "{} 0 obj\n{}\nendobj\n", i + 1, object);
+    }
+    let xref = out.len();
+    let _ = write!(out, "xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+        let _ = writeln!(out, "{:010} 00000 n ", offset);
+    }
+    let _ = write!(out, "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref);
+    out.into_bytes()
+}
+
+fn pdf_text(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    for c in line.chars() {
+        match c {
+            '\\' | '(' | ')' => {
+                out.push('\\');
+                out.push(c);
+            }
+            ' '..='~' => out.push(c),
+            _ => out.push('?'),
+        }
+    }
+    out
+}
+
+// Controllers
+/// The caller's statement for a UTC calendar month: every plan's payments, paid-out refunds,
+/// platform fees and net revenue. Admins get the statement across every plan. A month still
+/// in progress covers activity so far.
+#[utoipa::path(
+    get,
+    path = "/api/v1/statements/{year}/{month}",
+    tag = "statements",
+    params(
+        ("year" = i32, Path, description = "Year, e.g. 2026"),
+        ("month" = u32, Path, description = "Month, 1 to 12"),
+        StatementQuery
+    ),
+    responses(
+        (status = 200, description = "Statement as CSV, PDF or JSON", body = Statement),
+        (status = 400, description = "Invalid month, or one that has not started", body = ErrorResponse),
+        (status = 403, description = "Merchant role required", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []), ("api_key" = []))
+)]
+#[get("/{year}/{month}")]
+pub async fn get_statement(
+    req: HttpRequest,
+    path: web::Path<(i32, u32)>,
+    query: ValidatedQuery<StatementQuery>,
+    statements: web::Data<StatementService>,
+    plans: web::Data<PlanService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let (year, month) = path.into_inner();
+    let scope = plans.scope(&auth_token).await?;
+    let statement = statements.generate(&auth_token.public_key, scope.plan_ids(), year, month).await?;
+    let format = query.format.unwrap_or(StatementFormat::Csv);
+    let body = match format {
+        StatementFormat::Csv => statement.to_csv().into_bytes(),
+        StatementFormat::Pdf => statement.to_pdf(),
+        StatementFormat::Json => serde_json::to_vec(&statement)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to encode statement: {}", e)))?,
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"statement-{}-{:02}.{}\"", year, month, format.as_str()),
+        ))
+        .body(body))
+}
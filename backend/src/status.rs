@@ -0,0 +1,84 @@
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use sqlx::postgres::PgPool;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+use crate::cluster::SolanaClusters;
+use crate::db;
+use crate::validation::FieldError;
+use crate::{AppError, AppResult};
+
+// Models
+/// Whether a wallet currently holds a plan, and nothing else about the subscription.
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct MembershipStatus {
+    active: bool,
+    expires_at: Option<i64>, // Unix time the paid period ends; null when there is no subscription
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+// Controllers
+/// Public membership check for bots and gateways: no credentials, limited per client IP by
+/// `STATUS_RATE_LIMIT_PER_MINUTE`. Answered from the index on the primary cluster, falling
+/// back to a `confirmed` account read, so a wallet without the subscription is `active: false`
+/// rather than an error.
+#[utoipa::path(
+    get,
+    path = "/status/{wallet}/{plan_id}",
+    tag = "status",
+    params(
+        ("wallet" = String, Path, description = "Subscriber wallet address"),
+        ("plan_id" = u64, Path, description = "Plan identifier"),
+    ),
+    responses(
+        (status = 200, description = "Membership status", body = MembershipStatus),
+        (status = 400, description = "Invalid wallet address", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded, retry after `Retry-After` seconds", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    )
+)]
+#[get("/{wallet}/{plan_id}")]
+pub async fn membership_status(
+    req: HttpRequest,
+    path: web::Path<(String, u64)>,
+    clusters: web::Data<SolanaClusters>,
+    pool: web::Data<PgPool>,
+) -> AppResult<HttpResponse> {
+    let (wallet, plan_id) = path.into_inner();
+    let owner = Pubkey::from_str(&wallet).map_err(|_| {
+        AppError::Validation(vec![FieldError::new("wallet", "pubkey", "must be a base58 Solana public key")])
+    })?;
+    let solana_service = clusters.select(&req)?;
+    let pda = solana_service.subscription_pda(&owner, plan_id);
+
+    let indexed = if solana_service.is_primary() {
+        db::find_subscription(&pool, &pda.to_string()).await?
+    } else {
+        None
+    };
+    // (active flag, start_time, duration)
+    let state = match indexed {
+        Some(row) => Some((row.active && !row.closed, row.start_time, row.duration)),
+        None => match solana_service.accounts().get(&pda, solana_service.read_commitment(None)).await? {
+            Some(account) => {
+                let subscription = solana_service.decoder.decode(&account)?;
+                Some((subscription.active, subscription.start_time, subscription.duration as i64))
+            }
+            None => None,
+        },
+    };
+
+    let status = match state {
+        Some((active, start_time, duration)) => {
+            let expires_at = start_time.saturating_add(duration);
+            MembershipStatus { active: active && expires_at > now(), expires_at: Some(expires_at) }
+        }
+        None => MembershipStatus { active: false, expires_at: None },
+    };
+    Ok(HttpResponse::Ok().json(status))
+}
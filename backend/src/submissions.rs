@@ -0,0 +1,131 @@
+use crate::cache::CacheService;
+use crate::chain::ChainClient;
+use crate::idempotency::{self, IdempotencyService, Replay};
+use crate::indexer::IndexerService;
+use crate::notifications::NotificationService;
+use crate::webhooks::{WebhookEventType, WebhookService};
+use crate::{index_submission, subscription_event, AppError, AppResult, SignatureResponse, SubscriptionRequest};
+
+/// A transaction a wallet sends on one of its subscriptions.
+#[derive(Debug, Clone)]
+pub enum WalletAction {
+    Create(SubscriptionRequest),
+    Renew(u64),
+    Cancel(u64),
+}
+
+impl WalletAction {
+    fn name(&self) -> &'static str {
+        match self {
+            WalletAction::Create(_) => "create",
+            WalletAction::Renew(_) => "renew",
+            WalletAction::Cancel(_) => "cancel",
+        }
+    }
+
+    fn plan_id(&self) -> u64 {
+        match self {
+            WalletAction::Create(req) => req.plan_id,
+            WalletAction::Renew(plan_id) | WalletAction::Cancel(plan_id) => *plan_id,
+        }
+    }
+
+    fn event_type(&self) -> WebhookEventType {
+        match self {
+            WalletAction::Create(_) => WebhookEventType::SubscriptionCreated,
+            WalletAction::Renew(_) => WebhookEventType::SubscriptionRenewed,
+            WalletAction::Cancel(_) => WebhookEventType::SubscriptionCancelled,
+        }
+    }
+
+    // What an idempotency key is bound to, next to the cluster and tenant
+    fn fingerprint_input(&self) -> AppResult<String> {
+        match self {
+            WalletAction::Create(req) => serde_json::to_string(req)
+                .map_err(|e| AppError::InternalServerError(format!("Failed to serialize request: {}", e))),
+            WalletAction::Renew(plan_id) | WalletAction::Cancel(plan_id) => Ok(plan_id.to_string()),
+        }
+    }
+}
+
+/// The outcome of `SubmissionService::submit`.
+pub enum Submitted {
+    Sent(String),
+    /// An earlier request with the same idempotency key already finished with this response.
+    Replayed(Replay),
+}
+
+/// Sends wallet transactions the same way for the REST and gRPC APIs: one at a time per
+/// wallet, deduplicated by idempotency key, and indexed, cached and reported to webhooks
+/// and notification channels once they land on the primary cluster.
+#[derive(Clone)]
+pub struct SubmissionService {
+    idempotency: IdempotencyService,
+    webhook_service: WebhookService,
+    indexer: IndexerService,
+    cache: CacheService,
+    notifications: NotificationService,
+}
+
+impl SubmissionService {
+    pub fn new(
+        idempotency: IdempotencyService,
+        webhook_service: WebhookService,
+        indexer: IndexerService,
+        cache: CacheService,
+        notifications: NotificationService,
+    ) -> Self {
+        Self { idempotency, webhook_service, indexer, cache, notifications }
+    }
+
+    pub async fn submit(
+        &self,
+        chain: &dyn ChainClient,
+        owner: &str,
+        action: &WalletAction,
+        idempotency_key: Option<&str>,
+    ) -> AppResult<Submitted> {
+        let plan_id = action.plan_id();
+        let pda = chain.subscription_address(owner, plan_id)?;
+        // Taken before the idempotency check, so a concurrent retry waits and gets the replay
+        let guard = chain.lock_wallet(owner).await?;
+        if let Some(key) = idempotency_key {
+            let fingerprint = idempotency::fingerprint(&[
+                chain.cluster().as_str(),
+                chain.tenant(),
+                action.name(),
+                &action.fingerprint_input()?,
+            ]);
+            if let Some(replay) = self.idempotency.begin_replay(owner, key, &fingerprint).await? {
+                return Ok(Submitted::Replayed(replay));
+            }
+        }
+        let tx = match action {
+            WalletAction::Create(req) => chain.build_create_tx(owner, req).await,
+            WalletAction::Renew(plan_id) => chain.build_renew_tx(owner, *plan_id),
+            WalletAction::Cancel(plan_id) => chain.build_cancel_tx(owner, *plan_id),
+        };
+        let result = match tx {
+            Ok(tx) => chain.submit(tx).await,
+            Err(e) => Err(e),
+        }
+        .map(|signature| SignatureResponse { signature });
+        if let Some(key) = idempotency_key {
+            self.idempotency.finish(owner, key, &result).await;
+        }
+        drop(guard);
+        let SignatureResponse { signature } = result?;
+
+        if chain.is_primary() {
+            index_submission(&self.indexer, &signature).await;
+            self.cache.invalidate_subscription(&pda).await;
+            self.webhook_service
+                .dispatch(action.event_type(), subscription_event(chain, owner, plan_id, &signature))
+                .await;
+            if !matches!(action, WalletAction::Cancel(_)) {
+                self.notifications.notify_payment(&pda.to_string(), &signature);
+            }
+        }
+        Ok(Submitted::Sent(signature))
+    }
+}
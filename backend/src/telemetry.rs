@@ -0,0 +1,104 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::HttpMessage;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{self as sdktrace, Sampler, Tracer};
+use opentelemetry_sdk::{runtime, Resource};
+use std::future::Future;
+use tracing_actix_web::RequestId;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter, Registry};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Builds the OTLP span exporter when `OTEL_EXPORTER_OTLP_ENDPOINT` is set. Spans are sent
+/// over HTTP/protobuf in batches, named after `OTEL_SERVICE_NAME` (default
+/// `subscription-manager`) and sampled at `OTEL_TRACES_SAMPLER_ARG` (default `1.0`), unless
+/// the caller's `traceparent` already decided.
+fn otlp_layer() -> Option<OpenTelemetryLayer<Registry, Tracer>> {
+    let endpoint = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok().filter(|v| !v.is_empty())?;
+    let service_name = std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "subscription-manager".to_string());
+    let ratio = std::env::var("OTEL_TRACES_SAMPLER_ARG")
+        .ok()
+        .map(|v| v.parse::<f64>().expect("OTEL_TRACES_SAMPLER_ARG must be a number between 0 and 1"))
+        .unwrap_or(1.0);
+
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    let exporter = opentelemetry_otlp::new_exporter()
+        .http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')));
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(
+            sdktrace::config()
+                .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(ratio))))
+                .with_resource(Resource::new(vec![
+                    KeyValue::new("service.name", service_name),
+                    KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+                ])),
+        )
+        .install_batch(runtime::Tokio)
+        .expect("Failed to install the OTLP trace exporter");
+    Some(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// Installs the global subscriber. Filtering follows `RUST_LOG` (default `info`), and
+/// `LOG_FORMAT=json` emits one JSON object per line, with the fields of every enclosing
+/// span, for log aggregation. Records from crates using `log` are forwarded as well.
+/// With `OTEL_EXPORTER_OTLP_ENDPOINT` set, spans are also exported over OTLP. Must be
+/// called inside the Tokio runtime.
+pub fn init() {
+    let otlp = otlp_layer();
+    let enabled = otlp.is_some();
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let registry = tracing_subscriber::registry().with(otlp).with(filter);
+    if std::env::var("LOG_FORMAT").is_ok_and(|v| v.eq_ignore_ascii_case("json")) {
+        registry.with(fmt::layer().json().with_current_span(true).with_span_list(true)).init();
+    } else {
+        registry.with(fmt::layer()).init();
+    }
+    if enabled {
+        tracing::info!("Exporting traces over OTLP");
+    }
+}
+
+/// Flushes spans still waiting to be exported. Called once on shutdown.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// ID of the HTTP request being handled on this task, if any. Background work such as the
+/// indexer and keeper runs outside a request and has none.
+pub fn request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Makes the request ID assigned by `TracingLogger` available to `request_id()` while the
+/// request is handled, and echoes it back in `X-Request-Id`. Must be wrapped inside
+/// `TracingLogger`.
+pub fn scope_request_id<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let request_id = req.extensions().get::<RequestId>().map(|id| id.to_string()).unwrap_or_default();
+    let call = REQUEST_ID.sync_scope(request_id.clone(), || srv.call(req));
+    REQUEST_ID.scope(request_id.clone(), async move {
+        let mut response = call.await?;
+        if let Ok(value) = HeaderValue::from_str(&request_id) {
+            response.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+        }
+        Ok(response)
+    })
+}
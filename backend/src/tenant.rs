@@ -0,0 +1,166 @@
+use actix_web::{HttpMessage, HttpRequest};
+use reqwest::header::{HeaderName, HeaderValue};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::str::FromStr;
+use crate::cluster::Cluster;
+use crate::layout::ProgramLayout;
+use crate::pii::PiiKey;
+use crate::{AppError, AppResult, AuthToken};
+
+/// Tenant of requests that name none; backed by the unprefixed `PROGRAM_ID`/`TREASURY_PUBKEY`
+/// settings and the only tenant that is indexed.
+pub const DEFAULT_TENANT: &str = "default";
+const MAX_TENANT_LENGTH: usize = 32;
+const DEFAULT_RPC_API_KEY_HEADER: &str = "x-api-key";
+
+/// One merchant deployment: its treasury, program IDs, RPC endpoints and subscription limits.
+#[derive(Debug, Clone)]
+pub struct TenantConfig {
+    pub id: String,
+    pub sandbox: bool, // Test mode: only runs on devnet, with its own `sk_test_` API keys
+    pub treasury: Pubkey,
+    pub program_ids: HashMap<Cluster, Pubkey>, // Falls back to the cluster's program ID
+    pub program_layouts: HashMap<Cluster, ProgramLayout>, // Falls back to the cluster's layout
+    pub rpc_urls: HashMap<Cluster, Vec<String>>, // Falls back to the cluster's shared endpoints
+    pub rpc_api_key: Option<(String, String)>, // Header name and value sent to its own endpoints
+    pub min_duration_secs: Option<u64>,
+    pub max_duration_secs: Option<u64>,
+    pub min_amount_lamports: Option<u64>,
+    pub max_amount_lamports: Option<u64>,
+    pub pii_key: Option<PiiKey>, // Encrypts its merchants' customer details at rest
+}
+
+impl TenantConfig {
+    pub fn program_id(&self, cluster: Cluster) -> Option<Pubkey> {
+        self.program_ids.get(&cluster).copied()
+    }
+
+    pub fn program_layout(&self, cluster: Cluster) -> Option<ProgramLayout> {
+        self.program_layouts.get(&cluster).copied()
+    }
+
+    /// The tenant's own RPC endpoints on `cluster`, in failover order.
+    pub fn rpc_urls(&self, cluster: Cluster) -> Option<&[String]> {
+        self.rpc_urls.get(&cluster).map(Vec::as_slice)
+    }
+}
+
+pub fn is_valid_tenant_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_TENANT_LENGTH
+        && id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// The default tenant followed by every tenant listed in `TENANTS` (comma-separated ids).
+/// Each reads `TENANT_<ID>_TREASURY` (required), `TENANT_<ID>_PROGRAM_ID` or
+/// `TENANT_<ID>_PROGRAM_ID_<CLUSTER>`, `TENANT_<ID>_PROGRAM_LAYOUT` or
+/// `TENANT_<ID>_PROGRAM_LAYOUT_<CLUSTER>`, its own RPC endpoints from
+/// `TENANT_<ID>_SOLANA_RPC_URLS_<CLUSTER>` (the unsuffixed `TENANT_<ID>_SOLANA_RPC_URLS` applies
+/// to `primary`) with an optional `TENANT_<ID>_RPC_API_KEY` sent in the
+/// `TENANT_<ID>_RPC_API_KEY_HEADER` header (default `x-api-key`), and optional
+/// `TENANT_<ID>_{MIN,MAX}_{DURATION_SECS,AMOUNT_LAMPORTS}` overrides, where `<ID>` is the
+/// upper-cased id with dashes as underscores. `TENANT_<ID>_MODE=sandbox` makes it a sandbox.
+/// `TENANT_<ID>_PII_ENCRYPTION_KEY` (`PII_ENCRYPTION_KEY` for the default tenant) encrypts
+/// customer details at rest.
+pub fn load_tenants(default_treasury: Pubkey, primary: Cluster) -> Vec<TenantConfig> {
+    let env = |name: String| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+    let pubkey = |value: String, name: &str| {
+        Pubkey::from_str(value.trim()).unwrap_or_else(|_| panic!("Invalid pubkey in {}", name))
+    };
+
+    let mut tenants = vec![TenantConfig {
+        id: DEFAULT_TENANT.to_string(),
+        sandbox: false,
+        treasury: default_treasury,
+        program_ids: HashMap::new(),
+        program_layouts: HashMap::new(),
+        rpc_urls: HashMap::new(),
+        rpc_api_key: None,
+        min_duration_secs: None,
+        max_duration_secs: None,
+        min_amount_lamports: None,
+        max_amount_lamports: None,
+        pii_key: env("PII_ENCRYPTION_KEY".to_string()).map(|key| PiiKey::parse(&key, "PII_ENCRYPTION_KEY")),
+    }];
+    for id in env("TENANTS".to_string()).unwrap_or_default().split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        assert!(is_valid_tenant_id(id), "Invalid tenant id {} in TENANTS", id);
+        assert!(tenants.iter().all(|t| t.id != id), "Tenant {} is listed twice", id);
+        let prefix = format!("TENANT_{}_", id.to_uppercase().replace('-', "_"));
+        let var = |suffix: &str| env(format!("{}{}", prefix, suffix));
+        let number = |suffix: &str| {
+            var(suffix).map(|v| v.parse().unwrap_or_else(|_| panic!("Invalid {}{}", prefix, suffix)))
+        };
+
+        let sandbox = match var("MODE").as_deref().map(str::trim) {
+            None | Some("live") => false,
+            Some("sandbox") => true,
+            Some(other) => panic!("Unknown {}MODE {}; expected live or sandbox", prefix, other),
+        };
+        let treasury_var = format!("{}TREASURY", prefix);
+        let treasury = env(treasury_var.clone()).unwrap_or_else(|| panic!("{} must be set", treasury_var));
+        let mut program_ids = HashMap::new();
+        let mut program_layouts = HashMap::new();
+        let mut rpc_urls = HashMap::new();
+        for cluster in Cluster::ALL {
+            let suffix = format!("PROGRAM_ID_{}", cluster.as_str().to_uppercase());
+            if let Some(program_id) = var(&suffix).or_else(|| var("PROGRAM_ID")) {
+                program_ids.insert(cluster, pubkey(program_id, &format!("{}{}", prefix, suffix)));
+            }
+            let suffix = format!("PROGRAM_LAYOUT_{}", cluster.as_str().to_uppercase());
+            if let Some(layout) = var(&suffix).or_else(|| var("PROGRAM_LAYOUT")) {
+                let layout = layout.parse().unwrap_or_else(|e| panic!("{} in {}{}", e, prefix, suffix));
+                program_layouts.insert(cluster, layout);
+            }
+            let suffix = format!("SOLANA_RPC_URLS_{}", cluster.as_str().to_uppercase());
+            let urls = var(&suffix).or_else(|| if cluster == primary { var("SOLANA_RPC_URLS") } else { None });
+            let urls: Vec<String> = urls
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect();
+            if !urls.is_empty() {
+                rpc_urls.insert(cluster, urls);
+            }
+        }
+        let rpc_api_key = var("RPC_API_KEY").map(|key| {
+            let header = var("RPC_API_KEY_HEADER").unwrap_or_else(|| DEFAULT_RPC_API_KEY_HEADER.to_string());
+            assert!(HeaderName::from_str(header.trim()).is_ok(), "Invalid header name in {}RPC_API_KEY_HEADER", prefix);
+            assert!(HeaderValue::from_str(key.trim()).is_ok(), "Invalid {}RPC_API_KEY", prefix);
+            (header.trim().to_string(), key.trim().to_string())
+        });
+        tenants.push(TenantConfig {
+            id: id.to_string(),
+            sandbox,
+            treasury: pubkey(treasury, &treasury_var),
+            program_ids,
+            program_layouts,
+            rpc_urls,
+            rpc_api_key,
+            min_duration_secs: number("MIN_DURATION_SECS"),
+            max_duration_secs: number("MAX_DURATION_SECS"),
+            min_amount_lamports: number("MIN_AMOUNT_LAMPORTS"),
+            max_amount_lamports: number("MAX_AMOUNT_LAMPORTS"),
+            pii_key: var("PII_ENCRYPTION_KEY").map(|key| PiiKey::parse(&key, &format!("{}PII_ENCRYPTION_KEY", prefix))),
+        });
+    }
+    tenants
+}
+
+/// Tenant a request acts for: the `{tenant}` path segment of `/api/tenants/{tenant}/...`,
+/// else the tenant the access token was issued for, else the default tenant. A token issued
+/// for one tenant cannot be used on another tenant's path.
+pub fn tenant_of(req: &HttpRequest) -> AppResult<String> {
+    let from_token = req.extensions().get::<AuthToken>().and_then(|token| token.tenant.clone());
+    match (req.match_info().get("tenant"), from_token) {
+        (Some(path), Some(token)) if path != token => Err(AppError::Forbidden(format!(
+            "Token was issued for tenant {}, not {}",
+            token, path
+        ))),
+        (Some(path), _) => Ok(path.to_string()),
+        (None, Some(token)) => Ok(token),
+        (None, None) => Ok(DEFAULT_TENANT.to_string()),
+    }
+}
@@ -0,0 +1,54 @@
+use actix_web::http::Method;
+use std::time::Duration;
+use crate::quota;
+
+/// How long a handler may run before it is answered with `504 Gateway Timeout`. A zero
+/// duration means no limit.
+#[derive(Debug, Clone)]
+pub struct RequestTimeouts {
+    default: Duration,
+    transaction: Duration, // Routes that send a transaction and wait for its confirmation
+    routes: Vec<(String, Duration)>, // Route pattern suffixes, checked first
+}
+
+impl RequestTimeouts {
+    pub fn for_route(&self, method: &Method, route: &str) -> Duration {
+        if let Some((_, timeout)) = self.routes.iter().find(|(suffix, _)| route.ends_with(suffix.as_str())) {
+            return *timeout;
+        }
+        if quota::builds_transaction(method, route) {
+            self.transaction
+        } else {
+            self.default
+        }
+    }
+}
+
+/// `REQUEST_TIMEOUT_SECS` for most routes, `TRANSACTION_REQUEST_TIMEOUT_SECS` for those that
+/// send a transaction, and overrides from `ROUTE_TIMEOUTS` (comma-separated
+/// `<route>:<secs>`, the route matched on the end of its pattern, e.g.
+/// `/exports/{id}/download:300`).
+pub fn load_request_timeouts() -> RequestTimeouts {
+    let secs = |name: &str, default: u64| {
+        Duration::from_secs(std::env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default))
+    };
+    let routes = std::env::var("ROUTE_TIMEOUTS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            let (route, timeout) = s.rsplit_once(':').unwrap_or_else(|| panic!("Invalid entry {} in ROUTE_TIMEOUTS", s));
+            let timeout: u64 = timeout
+                .trim()
+                .parse()
+                .unwrap_or_else(|_| panic!("Invalid timeout for {} in ROUTE_TIMEOUTS", route));
+            (route.trim().to_string(), Duration::from_secs(timeout))
+        })
+        .collect();
+    RequestTimeouts {
+        default: secs("REQUEST_TIMEOUT_SECS", 30),
+        transaction: secs("TRANSACTION_REQUEST_TIMEOUT_SECS", 90),
+        routes,
+    }
+}
@@ -0,0 +1,95 @@
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{self, CertifiedKey};
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+// TLS
+/// Serves the certificate at `cert_path` with the key at `key_path`, and swaps in a new pair
+/// when either file changes, so renewals (e.g. by certbot) apply without a restart. A pair
+/// that fails to load is logged and the previous one kept.
+pub struct ReloadingCertResolver {
+    cert_path: String,
+    key_path: String,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadingCertResolver {
+    pub fn new(cert_path: &str, key_path: &str) -> io::Result<Arc<Self>> {
+        let current = load_certified_key(cert_path, key_path)?;
+        Ok(Arc::new(Self {
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+            current: RwLock::new(Arc::new(current)),
+        }))
+    }
+
+    pub fn server_config(self: &Arc<Self>) -> ServerConfig {
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone())
+    }
+
+    /// Polls the files' modification times every `interval` and reloads on change.
+    pub async fn run(self: Arc<Self>, interval: Duration) {
+        let mut loaded = self.modified();
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let modified = self.modified();
+            if modified == loaded {
+                continue;
+            }
+            match load_certified_key(&self.cert_path, &self.key_path) {
+                Ok(key) => {
+                    *self.current.write().unwrap() = Arc::new(key);
+                    loaded = modified;
+                    tracing::info!("Reloaded TLS certificate from {}", self.cert_path);
+                }
+                // A renewal may have written the certificate but not yet the key; retried next tick
+                Err(e) => tracing::warn!("Failed to reload TLS certificate, keeping the current one: {}", e),
+            }
+        }
+    }
+
+    fn modified(&self) -> (Option<SystemTime>, Option<SystemTime>) {
+        let mtime = |path: &str| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        (mtime(&self.cert_path), mtime(&self.key_path))
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+fn load_certified_key(cert_path: &str, key_path: &str) -> io::Result<CertifiedKey> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty() {
+        return Err(invalid(format!("No certificates found in {}", cert_path)));
+    }
+
+    let key = rustls_pemfile::read_all(&mut BufReader::new(File::open(key_path)?))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(der) | rustls_pemfile::Item::RSAKey(der) | rustls_pemfile::Item::ECKey(der) => {
+                Some(PrivateKey(der))
+            }
+            _ => None,
+        })
+        .ok_or_else(|| invalid(format!("No private key found in {}", key_path)))?;
+    let signing_key = sign::any_supported_type(&key)
+        .map_err(|e| invalid(format!("Unsupported private key in {}: {}", key_path, e)))?;
+
+    Ok(CertifiedKey::new(certs, signing_key))
+}
@@ -0,0 +1,211 @@
+use actix_web::{get, web, HttpResponse};
+use futures_util::future::try_join_all;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use solana_client::rpc_request::TokenAccountsFilter;
+use solana_sdk::pubkey::Pubkey;
+use sqlx::postgres::PgPool;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+use crate::cluster::SolanaClusters;
+use crate::db::{self, TreasuryInflowTotals};
+use crate::metrics;
+use crate::price::PriceFeed;
+use crate::validation::ValidatedQuery;
+use crate::{AppError, AppResult, SolanaService};
+
+const DEFAULT_INFLOWS_LIMIT: i64 = 100;
+const LAMPORTS_PER_SOL: f64 = 1_000_000_000.0;
+// SPL Token and Token-2022
+const TOKEN_PROGRAMS: [&str; 2] = [
+    "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+    "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb",
+];
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct TokenBalance {
+    mint: String,
+    token_program: String,
+    accounts: usize, // Token accounts of this mint the treasury owns
+    amount: String, // In base units; a string since it can exceed what JSON numbers hold exactly
+    decimals: u8,
+    ui_amount: String,
+    usd: Option<f64>, // Unset when no token price feed is configured or it has no price
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct TreasuryBalance {
+    cluster: String,
+    tenant: String,
+    treasury: String,
+    lamports: u64,
+    usd: Option<f64>, // Of `lamports`, unset without a SOL price
+    tokens: Vec<TokenBalance>, // One per mint, by mint
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct TreasurySummary {
+    balances: Vec<TreasuryBalance>,
+    inflows: TreasuryInflowTotals, // Indexed payments, default tenant on the primary cluster only
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct InflowsQuery {
+    #[validate(range(max = 9223372036854775807, message = "must fit in a signed 64-bit integer"))]
+    plan_id: Option<u64>,
+    #[validate(range(min = 1, max = 500, message = "must be between 1 and 500"))]
+    limit: Option<i64>,
+    #[validate(range(min = 0, message = "must not be negative"))]
+    offset: Option<i64>,
+}
+
+/// `amount` base units as a decimal string, e.g. 1500000 with 6 decimals is "1.5".
+fn ui_amount(amount: u128, decimals: u8) -> String {
+    let scale = 10u128.pow(decimals as u32);
+    let fraction = format!("{:0width$}", amount % scale, width = decimals as usize);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        (amount / scale).to_string()
+    } else {
+        format!("{}.{}", amount / scale, fraction)
+    }
+}
+
+/// Every token account `treasury` owns under the SPL Token and Token-2022 programs, summed
+/// per mint.
+async fn token_balances(service: &SolanaService, treasury: &Pubkey) -> AppResult<Vec<TokenBalance>> {
+    let mut by_mint: BTreeMap<String, TokenBalance> = BTreeMap::new();
+    for program in TOKEN_PROGRAMS {
+        let program_id = Pubkey::from_str(program).expect("Token program IDs are valid");
+        let accounts = metrics::observe_rpc(
+            "getTokenAccountsByOwner",
+            service.rpc.client().get_token_accounts_by_owner(treasury, TokenAccountsFilter::ProgramId(program_id)),
+        )
+        .await
+        .map_err(|e| AppError::rpc(&format!("Failed to fetch token accounts of treasury {}", treasury), e))?;
+
+        for keyed in accounts {
+            // jsonParsed data: {"program": ..., "parsed": {"info": {...}}, "space": ...}
+            let data = serde_json::to_value(&keyed.account.data).unwrap_or(Value::Null);
+            let info = &data["parsed"]["info"];
+            let (Some(mint), Some(amount), Some(decimals)) = (
+                info["mint"].as_str(),
+                info["tokenAmount"]["amount"].as_str().and_then(|amount| amount.parse::<u128>().ok()),
+                info["tokenAmount"]["decimals"].as_u64(),
+            ) else {
+                tracing::warn!("Skipping unparsable token account {}", keyed.pubkey);
+                continue;
+            };
+            let balance = by_mint.entry(mint.to_string()).or_insert_with(|| TokenBalance {
+                mint: mint.to_string(),
+                token_program: program.to_string(),
+                accounts: 0,
+                amount: "0".to_string(),
+                decimals: decimals as u8,
+                ui_amount: "0".to_string(),
+                usd: None,
+            });
+            let total = balance.amount.parse::<u128>().unwrap_or(0) + amount;
+            balance.accounts += 1;
+            balance.amount = total.to_string();
+            balance.ui_amount = ui_amount(total, balance.decimals);
+        }
+    }
+    Ok(by_mint.into_values().collect())
+}
+
+async fn balance(service: &SolanaService) -> AppResult<TreasuryBalance> {
+    let treasury = service.treasury();
+    let lamports = metrics::observe_rpc("getBalance", service.rpc.client().get_balance(&treasury))
+        .await
+        .map_err(|e| AppError::rpc(&format!("Failed to fetch balance of treasury {}", treasury), e))?;
+    Ok(TreasuryBalance {
+        cluster: service.cluster.as_str().to_string(),
+        tenant: service.tenant().to_string(),
+        treasury: treasury.to_string(),
+        lamports,
+        usd: None,
+        tokens: token_balances(service, &treasury).await?,
+    })
+}
+
+/// Fills in USD values, fetching each price once for all balances.
+async fn value(balances: &mut [TreasuryBalance], prices: &PriceFeed) {
+    let sol_usd = prices.sol_usd().await;
+    let mut mints: Vec<String> = balances
+        .iter()
+        .flat_map(|balance| balance.tokens.iter().map(|token| token.mint.clone()))
+        .collect();
+    mints.sort();
+    mints.dedup();
+    let token_usd: HashMap<String, f64> = if mints.is_empty() {
+        HashMap::new()
+    } else {
+        prices.token_usd(&mints).await
+    };
+
+    for balance in balances {
+        balance.usd = sol_usd.map(|price| balance.lamports as f64 / LAMPORTS_PER_SOL * price);
+        for token in &mut balance.tokens {
+            let amount = token.ui_amount.parse::<f64>().unwrap_or(0.0);
+            token.usd = token_usd.get(&token.mint).map(|price| amount * price);
+        }
+    }
+}
+
+// Controllers
+/// Live SOL and SPL token balances of every configured treasury, by cluster and tenant, with
+/// USD values where prices are available and inflow totals from the indexer.
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/treasury",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Treasury balances and inflow totals", body = TreasurySummary),
+        (status = 502, description = "RPC node unavailable", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/treasury")]
+pub async fn treasury_summary(
+    clusters: web::Data<SolanaClusters>,
+    prices: web::Data<PriceFeed>,
+    pool: web::Data<PgPool>,
+) -> AppResult<HttpResponse> {
+    let mut balances = try_join_all(clusters.all().into_iter().map(balance)).await?;
+    value(&mut balances, &prices).await;
+    let inflows = db::treasury_inflow_totals(&pool).await?;
+    Ok(HttpResponse::Ok().json(TreasurySummary { balances, inflows }))
+}
+
+/// Recent payments into the treasury, newest first, each matched to the subscription that
+/// paid it.
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/treasury/inflows",
+    tag = "admin",
+    params(InflowsQuery),
+    responses(
+        (status = 200, description = "Indexed treasury inflows", body = [TreasuryInflowRow]),
+        (status = 422, description = "Invalid query", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/treasury/inflows")]
+pub async fn list_treasury_inflows(
+    query: ValidatedQuery<InflowsQuery>,
+    pool: web::Data<PgPool>,
+) -> AppResult<HttpResponse> {
+    let inflows = db::list_treasury_inflows(
+        &pool,
+        query.plan_id.map(|id| id as i64),
+        query.limit.unwrap_or(DEFAULT_INFLOWS_LIMIT),
+        query.offset.unwrap_or(0),
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(inflows))
+}
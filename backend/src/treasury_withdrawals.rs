@@ -0,0 +1,342 @@
+use actix_web::{get, post, web, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{pubkey::Pubkey, signer::Signer, system_instruction};
+use sqlx::postgres::PgPool;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+use crate::audit;
+use crate::db::{self, TreasuryWithdrawal};
+use crate::refunds::RefundService;
+use crate::validation::{ValidatedJson, ValidatedQuery};
+use crate::{AppError, AppResult, AuthToken, Config, SolanaService};
+
+/// Transaction job instruction of treasury withdrawals.
+pub const TREASURY_WITHDRAWAL_INSTRUCTION: &str = "treasury_withdrawal";
+const DEFAULT_WITHDRAWAL_LIMIT: i64 = 100;
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct TreasuryWithdrawalRequest {
+    destination: String,
+    #[validate(range(min = 1, max = 9223372036854775807, message = "must be a positive lamport amount that fits in a signed 64-bit integer"))]
+    lamports: u64,
+    #[validate(length(max = 500, message = "must be at most 500 characters"))]
+    reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct RejectTreasuryWithdrawalRequest {
+    #[validate(length(max = 500, message = "must be at most 500 characters"))]
+    reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct TreasuryWithdrawalsQuery {
+    status: Option<String>, // requested | approved | processing | succeeded | failed | rejected
+    #[validate(range(min = 1, max = 500, message = "must be between 1 and 500"))]
+    limit: Option<i64>,
+    #[validate(range(min = 0, message = "must not be negative"))]
+    offset: Option<i64>,
+}
+
+// Treasury Withdrawal Service
+/// Withdrawals from the default tenant's treasury. The program has no withdrawal instruction
+/// and the treasury is a plain wallet, so the multisig and timelock are kept here: a
+/// withdrawal one admin requests needs `TREASURY_WITHDRAWAL_APPROVALS` other admins to
+/// approve it, and can only be sent `TREASURY_WITHDRAWAL_TIMELOCK_SECS` after it was
+/// requested. It is then a transfer signed with `REFUND_PRIVATE_KEY`, sent as a transaction
+/// job. Every step is an admin request, so the audit log records who took it.
+#[derive(Clone)]
+pub struct TreasuryWithdrawalService {
+    pool: PgPool,
+    refunds: RefundService, // Holds the treasury keypair, reloaded with the config
+    approvals: u32,
+    timelock_secs: u64,
+}
+
+impl TreasuryWithdrawalService {
+    pub fn new(config: &Config, pool: PgPool, refunds: RefundService) -> Self {
+        Self {
+            pool,
+            refunds,
+            approvals: config.treasury_withdrawal_approvals,
+            timelock_secs: config.treasury_withdrawal_timelock_secs,
+        }
+    }
+
+    pub async fn request(&self, admin: &str, treasury: &Pubkey, req: TreasuryWithdrawalRequest) -> AppResult<TreasuryWithdrawal> {
+        let destination = Pubkey::from_str(&req.destination)
+            .map_err(|e| AppError::BadRequest(format!("Invalid destination: {}", e)))?;
+        if destination == *treasury {
+            return Err(AppError::BadRequest("The destination is the treasury itself".to_string()));
+        }
+
+        let created_at = now();
+        let withdrawal = TreasuryWithdrawal {
+            id: hex::encode(rand::random::<[u8; 16]>()),
+            destination: destination.to_string(),
+            lamports: req.lamports as i64,
+            reason: req.reason,
+            status: if self.approvals == 0 { "approved" } else { "requested" }.to_string(),
+            requested_by: admin.to_string(),
+            approvals_required: self.approvals as i32,
+            approved_by: Vec::new(),
+            executable_at: created_at + self.timelock_secs as i64,
+            reviewed_by: None,
+            signature: None,
+            error: None,
+            created_at,
+            updated_at: created_at,
+        };
+        db::insert_treasury_withdrawal(&self.pool, &withdrawal).await?;
+        tracing::info!(
+            "Treasury withdrawal {} of {} lamports to {} requested by {}",
+            withdrawal.id,
+            withdrawal.lamports,
+            withdrawal.destination,
+            admin
+        );
+        Ok(withdrawal)
+    }
+
+    /// Each admin other than the requester approves once.
+    pub async fn approve(&self, id: &str, admin: &str) -> AppResult<TreasuryWithdrawal> {
+        if let Some(withdrawal) = db::approve_treasury_withdrawal(&self.pool, id, admin).await? {
+            return Ok(withdrawal);
+        }
+        let withdrawal = self.get(id).await?;
+        if withdrawal.requested_by == admin {
+            return Err(AppError::Forbidden("A withdrawal cannot be approved by the admin who requested it".to_string()));
+        }
+        if withdrawal.approved_by.iter().any(|approver| approver == admin) {
+            return Err(AppError::Conflict(format!("Treasury withdrawal {} is already approved by {}", id, admin)));
+        }
+        Err(AppError::Conflict(format!("Treasury withdrawal {} is {}", id, withdrawal.status)))
+    }
+
+    /// Sends an approved withdrawal once its timelock has passed, and waits for confirmation.
+    /// It comes back `succeeded`, `failed`, or still `processing` if confirmation timed out,
+    /// in which case the job worker settles it later.
+    pub async fn execute(&self, solana_service: &SolanaService, id: &str, admin: &str) -> AppResult<TreasuryWithdrawal> {
+        let treasury = self.refunds.treasury_keypair().ok_or_else(|| {
+            AppError::BadRequest("Treasury withdrawals are disabled: REFUND_PRIVATE_KEY is not set".to_string())
+        })?;
+        let withdrawal = self.get(id).await?;
+        if withdrawal.status != "approved" {
+            return Err(AppError::Conflict(format!("Treasury withdrawal {} is {}", id, withdrawal.status)));
+        }
+        if now() < withdrawal.executable_at {
+            return Err(AppError::Conflict(format!(
+                "Treasury withdrawal {} is timelocked until {}",
+                id, withdrawal.executable_at
+            )));
+        }
+        let destination = Pubkey::from_str(&withdrawal.destination)
+            .map_err(|e| AppError::InternalServerError(format!("Invalid withdrawal destination: {}", e)))?;
+
+        let transfer = system_instruction::transfer(&treasury.pubkey(), &destination, withdrawal.lamports as u64);
+        let (mut tx, last_valid_block_height) = solana_service.unsigned_transaction(&treasury.pubkey(), &[transfer]).await?;
+        let recent_blockhash = tx.message.recent_blockhash;
+        tx.sign(&[treasury.as_ref()], recent_blockhash);
+        let signature = tx.signatures[0].to_string();
+        if !db::start_treasury_withdrawal(&self.pool, id, admin, &signature).await? {
+            return Err(AppError::Conflict(format!("Treasury withdrawal {} was sent or rejected concurrently", id)));
+        }
+
+        // The job records the outcome on the withdrawal, whether it settles now or in the job worker
+        let result = solana_service
+            .send_signed_transaction(
+                TREASURY_WITHDRAWAL_INSTRUCTION,
+                &destination,
+                0,
+                Vec::new(),
+                &tx,
+                last_valid_block_height,
+                None,
+            )
+            .await;
+        if let Err(e) = &result {
+            tracing::warn!("Treasury withdrawal {} did not confirm: {}", id, e);
+        }
+        self.get(id).await
+    }
+
+    /// Either before or after it was approved, as long as it was not sent.
+    pub async fn reject(&self, id: &str, admin: &str, reason: Option<&str>) -> AppResult<TreasuryWithdrawal> {
+        if !db::reject_treasury_withdrawal(&self.pool, id, admin, reason).await? {
+            let withdrawal = self.get(id).await?;
+            return Err(AppError::Conflict(format!("Treasury withdrawal {} is {}", id, withdrawal.status)));
+        }
+        self.get(id).await
+    }
+
+    pub async fn get(&self, id: &str) -> AppResult<TreasuryWithdrawal> {
+        db::get_treasury_withdrawal(&self.pool, id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Treasury withdrawal {} not found", id)))
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+// Controllers
+/// Requests a withdrawal from the treasury, for other admins to approve.
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/treasury/withdrawals",
+    tag = "admin",
+    request_body = TreasuryWithdrawalRequest,
+    responses(
+        (status = 201, description = "Withdrawal requested", body = TreasuryWithdrawal),
+        (status = 400, description = "Invalid destination", body = ErrorResponse),
+        (status = 422, description = "Invalid request", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/treasury/withdrawals")]
+pub async fn request_treasury_withdrawal(
+    req: HttpRequest,
+    solana_service: web::Data<SolanaService>,
+    withdrawals: web::Data<TreasuryWithdrawalService>,
+    body: ValidatedJson<TreasuryWithdrawalRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let withdrawal = withdrawals
+        .request(&auth_token.public_key, &solana_service.treasury(), body.into_inner())
+        .await?;
+    Ok(HttpResponse::Created().json(withdrawal))
+}
+
+/// Treasury withdrawals, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/treasury/withdrawals",
+    tag = "admin",
+    params(TreasuryWithdrawalsQuery),
+    responses(
+        (status = 200, description = "Treasury withdrawals", body = [TreasuryWithdrawal]),
+        (status = 422, description = "Invalid query", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/treasury/withdrawals")]
+pub async fn list_treasury_withdrawals(
+    query: ValidatedQuery<TreasuryWithdrawalsQuery>,
+    pool: web::Data<PgPool>,
+) -> AppResult<HttpResponse> {
+    let withdrawals = db::list_treasury_withdrawals(
+        &pool,
+        query.status.as_deref(),
+        query.limit.unwrap_or(DEFAULT_WITHDRAWAL_LIMIT),
+        query.offset.unwrap_or(0),
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(withdrawals))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/treasury/withdrawals/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "Treasury withdrawal id")),
+    responses(
+        (status = 200, description = "Withdrawal state", body = TreasuryWithdrawal),
+        (status = 404, description = "No such withdrawal", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/treasury/withdrawals/{id}")]
+pub async fn get_treasury_withdrawal(
+    path: web::Path<String>,
+    withdrawals: web::Data<TreasuryWithdrawalService>,
+) -> AppResult<HttpResponse> {
+    let withdrawal = withdrawals.get(&path.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(withdrawal))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/treasury/withdrawals/{id}/approve",
+    tag = "admin",
+    params(("id" = String, Path, description = "Treasury withdrawal id")),
+    responses(
+        (status = 200, description = "Withdrawal with the approval added", body = TreasuryWithdrawal),
+        (status = 403, description = "Caller requested the withdrawal", body = ErrorResponse),
+        (status = 404, description = "No such withdrawal", body = ErrorResponse),
+        (status = 409, description = "Already approved by the caller, or no longer requested", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/treasury/withdrawals/{id}/approve")]
+pub async fn approve_treasury_withdrawal(
+    req: HttpRequest,
+    path: web::Path<String>,
+    withdrawals: web::Data<TreasuryWithdrawalService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let withdrawal = withdrawals.approve(&path.into_inner(), &auth_token.public_key).await?;
+    Ok(HttpResponse::Ok().json(withdrawal))
+}
+
+/// Sends an approved withdrawal from the treasury once its timelock has passed.
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/treasury/withdrawals/{id}/execute",
+    tag = "admin",
+    params(("id" = String, Path, description = "Treasury withdrawal id")),
+    responses(
+        (status = 200, description = "Withdrawal after sending", body = TreasuryWithdrawal),
+        (status = 400, description = "Treasury withdrawals are disabled", body = ErrorResponse),
+        (status = 404, description = "No such withdrawal", body = ErrorResponse),
+        (status = 409, description = "Not approved, or still timelocked", body = ErrorResponse),
+        (status = 502, description = "RPC node unavailable", body = ErrorResponse),
+        (status = 503, description = "RPC circuit open, retry after `Retry-After` seconds", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/treasury/withdrawals/{id}/execute")]
+pub async fn execute_treasury_withdrawal(
+    req: HttpRequest,
+    path: web::Path<String>,
+    solana_service: web::Data<SolanaService>,
+    withdrawals: web::Data<TreasuryWithdrawalService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let withdrawal = withdrawals.execute(&solana_service, &path.into_inner(), &auth_token.public_key).await?;
+    if let Some(signature) = &withdrawal.signature {
+        audit::attach_signatures(&req, [signature]);
+    }
+    Ok(HttpResponse::Ok().json(withdrawal))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/treasury/withdrawals/{id}/reject",
+    tag = "admin",
+    params(("id" = String, Path, description = "Treasury withdrawal id")),
+    request_body = RejectTreasuryWithdrawalRequest,
+    responses(
+        (status = 200, description = "Withdrawal rejected", body = TreasuryWithdrawal),
+        (status = 404, description = "No such withdrawal", body = ErrorResponse),
+        (status = 409, description = "Withdrawal was already sent or rejected", body = ErrorResponse),
+        (status = 422, description = "Invalid request", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/treasury/withdrawals/{id}/reject")]
+pub async fn reject_treasury_withdrawal(
+    req: HttpRequest,
+    path: web::Path<String>,
+    withdrawals: web::Data<TreasuryWithdrawalService>,
+    body: ValidatedJson<RejectTreasuryWithdrawalRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let withdrawal = withdrawals
+        .reject(&path.into_inner(), &auth_token.public_key, body.reason.as_deref())
+        .await?;
+    Ok(HttpResponse::Ok().json(withdrawal))
+}
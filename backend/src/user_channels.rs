@@ -0,0 +1,392 @@
+use actix_web::{delete, get, post, web, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPool;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+use validator::Validate;
+use crate::channels::headline;
+use crate::validation::{FieldError, ValidatedJson};
+use crate::webhooks::{sign_payload, SubscriptionEventData, WebhookEvent, WebhookEventType};
+use crate::{AppError, AppResult, AuthToken, Config};
+
+const MAX_CHANNELS_PER_OWNER: i64 = 10;
+const PUSH_TOKEN_PREFIXES: [&str; 2] = ["ExponentPushToken[", "ExpoPushToken["];
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum UserChannelKind {
+    Webhook,
+    Push,
+}
+
+impl UserChannelKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            UserChannelKind::Webhook => "webhook",
+            UserChannelKind::Push => "push",
+        }
+    }
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+struct UserChannelRow {
+    id: String,
+    owner: String,
+    kind: String,
+    target: String,
+    secret: Option<String>,
+    events: Vec<String>,
+    plan_ids: Option<Vec<i64>>,
+    created_at: i64,
+}
+
+impl UserChannelRow {
+    fn matches(&self, event_type: WebhookEventType, plan_id: u64) -> bool {
+        self.events.iter().any(|e| e == event_type.as_str())
+            && self.plan_ids.as_ref().is_none_or(|ids| ids.contains(&(plan_id as i64)))
+    }
+}
+
+/// A subscriber's channel. The webhook secret is only returned when the channel is created.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct UserChannel {
+    id: String,
+    kind: UserChannelKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>, // Webhook only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    push_token: Option<String>, // Push only
+    events: Vec<WebhookEventType>,
+    plan_ids: Option<Vec<u64>>,
+    created_at: i64,
+}
+
+impl From<UserChannelRow> for UserChannel {
+    fn from(row: UserChannelRow) -> Self {
+        let kind = if row.kind == "push" { UserChannelKind::Push } else { UserChannelKind::Webhook };
+        let (url, push_token) = match kind {
+            UserChannelKind::Webhook => (Some(row.target), None),
+            UserChannelKind::Push => (None, Some(row.target)),
+        };
+        UserChannel {
+            id: row.id,
+            kind,
+            url,
+            push_token,
+            events: row.events.iter().filter_map(|e| WebhookEventType::from_name(e)).collect(),
+            plan_ids: row.plan_ids.map(|ids| ids.into_iter().map(|id| id as u64).collect()),
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct UserChannelRequest {
+    kind: UserChannelKind,
+    #[validate(url(message = "must be an absolute https URL"))]
+    url: Option<String>, // Webhook
+    #[validate(length(min = 1, max = 256, message = "must be 1 to 256 characters"))]
+    push_token: Option<String>, // Push: an Expo push token
+    #[validate(length(min = 1, message = "must list at least one event type"))]
+    events: Vec<WebhookEventType>,
+    plan_ids: Option<Vec<u64>>, // Every plan when unset
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct UserChannelCreated {
+    #[serde(flatten)]
+    channel: UserChannel,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>, // Webhook only, and only returned here
+}
+
+// User Channel Service
+/// Webhooks and push tokens a wallet registers for events on its own subscriptions, such as
+/// renewals, failed auto-renewals and expiry reminders. Events are sent once, without retries;
+/// failures are only logged. Push notifications go through the Expo push service, which
+/// reports tokens of uninstalled apps, and those channels are removed.
+#[derive(Clone)]
+pub struct UserChannelService {
+    pool: PgPool,
+    http_client: reqwest::Client,
+    push_api_url: String,
+    push_access_token: Option<String>,
+}
+
+impl UserChannelService {
+    pub fn new(config: &Config, pool: PgPool) -> Self {
+        let http_client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("Failed to build user channel HTTP client");
+        Self {
+            pool,
+            http_client,
+            push_api_url: config.push_api_url.clone(),
+            push_access_token: config.push_access_token.clone(),
+        }
+    }
+
+    pub async fn create(&self, owner: &str, req: UserChannelRequest) -> AppResult<UserChannelCreated> {
+        let (target, secret) = match req.kind {
+            UserChannelKind::Webhook => {
+                let url = req
+                    .url
+                    .as_deref()
+                    .and_then(|url| reqwest::Url::parse(url).ok())
+                    .filter(|url| url.scheme() == "https")
+                    .ok_or_else(|| invalid("url", "must be an absolute https URL"))?;
+                (url.to_string(), Some(format!("whsec_{}", random_hex(32))))
+            }
+            UserChannelKind::Push => {
+                let token = req
+                    .push_token
+                    .as_deref()
+                    .map(str::trim)
+                    .filter(|token| PUSH_TOKEN_PREFIXES.iter().any(|prefix| token.starts_with(prefix)) && token.ends_with(']'))
+                    .ok_or_else(|| invalid("push_token", "must be an Expo push token"))?;
+                (token.to_string(), None)
+            }
+        };
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM user_notification_channels WHERE owner = $1")
+            .bind(owner)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to count notification channels: {}", e)))?;
+        if count >= MAX_CHANNELS_PER_OWNER {
+            return Err(AppError::Conflict(format!(
+                "At most {} notification channels can be registered per wallet",
+                MAX_CHANNELS_PER_OWNER
+            )));
+        }
+
+        let events: Vec<String> = req.events.iter().map(|e| e.as_str().to_string()).collect();
+        let plan_ids: Option<Vec<i64>> = req.plan_ids.map(|ids| ids.into_iter().map(|id| id as i64).collect());
+        let row = sqlx::query_as::<_, UserChannelRow>(
+            "INSERT INTO user_notification_channels (id, owner, kind, target, secret, events, plan_ids, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             ON CONFLICT (owner, kind, target) DO NOTHING
+             RETURNING *",
+        )
+        .bind(random_hex(16))
+        .bind(owner)
+        .bind(req.kind.as_str())
+        .bind(&target)
+        .bind(&secret)
+        .bind(&events)
+        .bind(&plan_ids)
+        .bind(now())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to create notification channel: {}", e)))?
+        .ok_or_else(|| AppError::Conflict(format!("This {} is already registered", req.kind.as_str())))?;
+
+        tracing::info!("Registered {} notification channel {} for {}", row.kind, row.id, row.owner);
+        Ok(UserChannelCreated { channel: row.into(), secret })
+    }
+
+    pub async fn list(&self, owner: &str) -> AppResult<Vec<UserChannel>> {
+        let rows = self.rows(owner).await?;
+        Ok(rows.into_iter().map(UserChannel::from).collect())
+    }
+
+    pub async fn remove(&self, owner: &str, id: &str) -> AppResult<()> {
+        let result = sqlx::query("DELETE FROM user_notification_channels WHERE id = $1 AND owner = $2")
+            .bind(id)
+            .bind(owner)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to delete notification channel: {}", e)))?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("Notification channel {} not found", id)));
+        }
+        Ok(())
+    }
+
+    async fn rows(&self, owner: &str) -> AppResult<Vec<UserChannelRow>> {
+        sqlx::query_as::<_, UserChannelRow>(
+            "SELECT * FROM user_notification_channels WHERE owner = $1 ORDER BY created_at DESC",
+        )
+        .bind(owner)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to list notification channels: {}", e)))
+    }
+
+    /// Sends the event to the subscription owner's matching channels in the background.
+    pub fn dispatch(&self, event: &WebhookEvent<SubscriptionEventData>) {
+        let service = self.clone();
+        let event = event.clone();
+        tokio::spawn(async move {
+            let rows = match service.rows(&event.data.owner).await {
+                Ok(rows) => rows,
+                Err(e) => {
+                    tracing::error!("Failed to load notification channels of {}: {}", event.data.owner, e);
+                    return;
+                }
+            };
+            for row in rows.iter().filter(|row| row.matches(event.event_type, event.data.plan_id)) {
+                let result = match row.kind.as_str() {
+                    "webhook" => service.post_webhook(row, &event).await,
+                    "push" => service.push(row, &event).await,
+                    _ => continue,
+                };
+                if let Err(e) = result {
+                    tracing::warn!(
+                        "Failed to send {} to {} channel {}: {}",
+                        event.event_type.as_str(),
+                        row.kind,
+                        row.id,
+                        e
+                    );
+                }
+            }
+        });
+    }
+
+    /// Signed like merchant webhooks, with the channel's secret.
+    async fn post_webhook(&self, row: &UserChannelRow, event: &WebhookEvent<SubscriptionEventData>) -> Result<(), String> {
+        let body = serde_json::to_string(event).map_err(|e| format!("Failed to serialize event: {}", e))?;
+        let mut request = self.http_client
+            .post(&row.target)
+            .header("Content-Type", "application/json")
+            .header("X-Webhook-Id", &row.id)
+            .header("X-Webhook-Event", event.event_type.as_str());
+        if let Some(secret) = &row.secret {
+            let timestamp = now();
+            request = request.header(
+                "X-Webhook-Signature",
+                format!("t={},v1={}", timestamp, sign_payload(secret, timestamp, &body)),
+            );
+        }
+        let response = request.body(body).send().await.map_err(|e| format!("Request failed: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!("Responded with status {}", response.status()));
+        }
+        Ok(())
+    }
+
+    async fn push(&self, row: &UserChannelRow, event: &WebhookEvent<SubscriptionEventData>) -> Result<(), String> {
+        let message = serde_json::json!({
+            "to": row.target,
+            "title": headline(event.event_type),
+            "body": push_body(event.event_type, &event.data),
+            "data": {
+                "event": event.event_type.as_str(),
+                "subscription": event.data.subscription,
+                "plan_id": event.data.plan_id,
+            },
+        });
+        let mut request = self.http_client.post(&self.push_api_url).json(&message);
+        if let Some(token) = &self.push_access_token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await.map_err(|e| format!("Request failed: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!("Responded with status {}", response.status()));
+        }
+        // Delivery errors come back in a 200 response as a ticket
+        let ticket: serde_json::Value = response.json().await.map_err(|e| format!("Invalid response: {}", e))?;
+        if ticket["data"]["status"] != "error" {
+            return Ok(());
+        }
+        if ticket["data"]["details"]["error"] == "DeviceNotRegistered" {
+            tracing::info!("Removing notification channel {}: the app was uninstalled", row.id);
+            if let Err(e) = self.remove(&row.owner, &row.id).await {
+                tracing::warn!("Failed to remove notification channel {}: {}", row.id, e);
+            }
+        }
+        Err(format!("Push rejected: {}", ticket["data"]["message"]))
+    }
+}
+
+fn push_body(event_type: WebhookEventType, data: &SubscriptionEventData) -> String {
+    match event_type {
+        WebhookEventType::SubscriptionRenewed => format!("Your plan {} subscription was renewed.", data.plan_id),
+        WebhookEventType::SubscriptionRenewalFailed => {
+            format!("We could not renew your plan {} subscription. Renew it to keep access.", data.plan_id)
+        }
+        WebhookEventType::SubscriptionExpiring => format!("Your plan {} subscription expires soon.", data.plan_id),
+        WebhookEventType::SubscriptionExpired => format!("Your plan {} subscription has expired.", data.plan_id),
+        _ => format!("{} for plan {}.", headline(event_type), data.plan_id),
+    }
+}
+
+fn invalid(field: &str, message: &str) -> AppError {
+    AppError::Validation(vec![FieldError::new(field, "invalid", message)])
+}
+
+fn random_hex(len: usize) -> String {
+    let bytes: Vec<u8> = (0..len).map(|_| rand::random::<u8>()).collect();
+    hex::encode(bytes)
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+// Controllers
+/// Registers a webhook or push token of the authenticated wallet for events on its own
+/// subscriptions.
+#[utoipa::path(
+    post,
+    path = "/api/v1/notifications/channels",
+    tag = "notifications",
+    request_body = UserChannelRequest,
+    responses(
+        (status = 201, description = "Channel created; a webhook's secret is only returned here", body = UserChannelCreated),
+        (status = 409, description = "Already registered, or the wallet has too many channels", body = ErrorResponse),
+        (status = 422, description = "Invalid URL or push token", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/notifications/channels")]
+pub async fn create_user_channel(
+    req: HttpRequest,
+    user_channels: web::Data<UserChannelService>,
+    body: ValidatedJson<UserChannelRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let channel = user_channels.create(&auth_token.public_key, body.into_inner()).await?;
+    Ok(HttpResponse::Created().json(channel))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/notifications/channels",
+    tag = "notifications",
+    responses((status = 200, description = "The authenticated wallet's channels, newest first", body = [UserChannel])),
+    security(("bearer_auth" = []))
+)]
+#[get("/notifications/channels")]
+pub async fn list_user_channels(
+    req: HttpRequest,
+    user_channels: web::Data<UserChannelService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    Ok(HttpResponse::Ok().json(user_channels.list(&auth_token.public_key).await?))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/notifications/channels/{id}",
+    tag = "notifications",
+    params(("id" = String, Path, description = "Channel id")),
+    responses(
+        (status = 204, description = "Channel deleted"),
+        (status = 404, description = "Channel not found", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[delete("/notifications/channels/{id}")]
+pub async fn delete_user_channel(
+    req: HttpRequest,
+    path: web::Path<String>,
+    user_channels: web::Data<UserChannelService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    user_channels.remove(&auth_token.public_key, &path.into_inner()).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,147 @@
+use actix_web::{
+    dev::Payload,
+    error::{JsonPayloadError, PathError, QueryPayloadError},
+    web, FromRequest, HttpRequest,
+};
+use futures_util::future::LocalBoxFuture;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::ops::Deref;
+use std::str::FromStr;
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError, ValidationErrors};
+use crate::AppError;
+
+/// One rejected field, reported in the `errors` array of a 422 response.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct FieldError {
+    field: String,
+    code: String,
+    message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, code: &str, message: impl Into<String>) -> Self {
+        Self {
+            field: field.to_string(),
+            code: code.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+fn field_errors(errors: &ValidationErrors) -> Vec<FieldError> {
+    let mut fields: Vec<FieldError> = errors
+        .field_errors()
+        .into_iter()
+        .flat_map(|(field, errors)| {
+            errors.iter().map(move |e| {
+                let message = e.message.as_ref().map_or_else(|| e.code.to_string(), |m| m.to_string());
+                FieldError::new(field, &e.code, message)
+            })
+        })
+        .collect();
+    fields.sort_by(|a, b| a.field.cmp(&b.field));
+    fields
+}
+
+pub fn validate<T: Validate>(value: &T) -> Result<(), AppError> {
+    value.validate().map_err(|e| AppError::Validation(field_errors(&e)))
+}
+
+pub fn validate_pubkey(value: &str) -> Result<(), ValidationError> {
+    Pubkey::from_str(value).map(|_| ()).map_err(|_| {
+        let mut error = ValidationError::new("pubkey");
+        error.message = Some("must be a base58 Solana public key".into());
+        error
+    })
+}
+
+/// `web::Json` that also runs the body's `Validate` rules before the handler sees it.
+pub struct ValidatedJson<T>(pub T);
+
+impl<T> ValidatedJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for ValidatedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> FromRequest for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate + 'static,
+{
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = web::Json::<T>::from_request(req, payload);
+        Box::pin(async move {
+            let value = json.await?.into_inner();
+            validate(&value)?;
+            Ok(ValidatedJson(value))
+        })
+    }
+}
+
+/// `web::Query` counterpart of [`ValidatedJson`].
+pub struct ValidatedQuery<T>(pub T);
+
+impl<T> Deref for ValidatedQuery<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> FromRequest for ValidatedQuery<T>
+where
+    T: DeserializeOwned + Validate + 'static,
+{
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let query = web::Query::<T>::from_request(req, payload);
+        Box::pin(async move {
+            let value = query.await?.into_inner();
+            validate(&value)?;
+            Ok(ValidatedQuery(value))
+        })
+    }
+}
+
+// Extractor error handlers, so type mismatches come back in the same shape as rule violations
+pub fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    match err {
+        JsonPayloadError::Deserialize(e) if e.is_data() => {
+            AppError::Validation(vec![FieldError::new("body", "invalid_type", e.to_string())]).into()
+        }
+        JsonPayloadError::Overflow { limit } | JsonPayloadError::OverflowKnownLength { limit, .. } => {
+            AppError::PayloadTooLarge(format!("JSON body exceeds {} bytes", limit)).into()
+        }
+        e => AppError::BadRequest(format!("Invalid JSON body: {}", e)).into(),
+    }
+}
+
+pub fn query_error_handler(err: QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    AppError::Validation(vec![FieldError::new("query", "invalid_type", err.to_string())]).into()
+}
+
+pub fn path_error_handler(err: PathError, _req: &HttpRequest) -> actix_web::Error {
+    AppError::Validation(vec![FieldError::new("path", "invalid_type", err.to_string())]).into()
+}
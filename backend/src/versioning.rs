@@ -0,0 +1,129 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue, ACCEPT, VARY};
+use actix_web::http::Uri;
+use futures_util::future::LocalBoxFuture;
+use std::future::ready;
+use crate::AppError;
+
+pub const API_VERSION_HEADER: &str = "api-version";
+// Versions with a scope mounted at `/api/v<N>`
+const SUPPORTED_VERSIONS: [u32; 1] = [1];
+// Served to clients that do not ask for a version, so existing integrations keep working
+const DEFAULT_VERSION: u32 = 1;
+const VENDOR_MEDIA_TYPE: &str = "application/vnd.subscription-manager";
+// `/api` routes registered outside the versioned scopes
+const UNVERSIONED_PATHS: [&str; 1] = ["/api/openapi.json"];
+
+/// The version in `/api/v<N>/...`, or `None` when the path has none.
+fn path_version(path: &str) -> Option<u32> {
+    let segment = path.strip_prefix("/api/")?.split('/').next()?;
+    let digits = segment.strip_prefix('v')?;
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(digits.parse().unwrap_or(u32::MAX))
+}
+
+/// The versions asked for in `Accept`, as `application/vnd.subscription-manager.v2+json` or as a
+/// `version=2` parameter on any media type. Empty when the client asks for none.
+fn accepted_versions(accept: &str) -> Vec<u32> {
+    accept
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';').map(str::trim);
+            let media_type = parts.next()?.to_ascii_lowercase();
+            let from_type = media_type
+                .strip_prefix(VENDOR_MEDIA_TYPE)
+                .and_then(|rest| rest.strip_prefix(".v"))
+                .and_then(|rest| rest.strip_suffix("+json"))
+                .map(str::to_string);
+            let from_param = parts.find_map(|param| {
+                let (name, value) = param.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("version")
+                    .then(|| value.trim().trim_matches('"').to_string())
+            });
+            from_type.or(from_param).map(|version| version.parse().unwrap_or(u32::MAX))
+        })
+        .collect()
+}
+
+/// The newest supported version the client accepts, or `DEFAULT_VERSION` if it asks for none.
+fn negotiate(req: &ServiceRequest) -> Result<u32, AppError> {
+    let accept = req
+        .headers()
+        .get_all(ACCEPT)
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+    let requested = accepted_versions(&accept);
+    if requested.is_empty() {
+        return Ok(DEFAULT_VERSION);
+    }
+    requested
+        .into_iter()
+        .filter(|version| SUPPORTED_VERSIONS.contains(version))
+        .max()
+        .ok_or_else(|| {
+            let supported: Vec<String> = SUPPORTED_VERSIONS.iter().map(ToString::to_string).collect();
+            AppError::NotAcceptable(format!("Supported API versions: {}", supported.join(", ")))
+        })
+}
+
+/// `/api/...` -> `/api/v<version>/...`, query kept, before routing sees it.
+fn rewrite(req: &mut ServiceRequest, version: u32) {
+    let uri = req.uri().clone();
+    let mut path = format!("/api/v{}{}", version, &uri.path()["/api".len()..]);
+    if let Some(query) = uri.query() {
+        path = format!("{}?{}", path, query);
+    }
+    let mut parts = uri.into_parts();
+    parts.path_and_query = Some(path.parse().expect("Rewritten API path is valid"));
+    let uri = Uri::from_parts(parts).expect("Rewritten API URI is valid");
+    req.match_info_mut().get_mut().update(&uri);
+    req.head_mut().uri = uri;
+}
+
+/// Sends `/api` requests to a versioned scope. `/api/v<N>/...` is served as version N, or
+/// `404` when there is none. An unversioned `/api/...` is served as the version negotiated
+/// from `Accept`, version 1 when none is asked for, and gets `406 Not Acceptable` when no
+/// version asked for is supported. Responses carry the version served in `API-Version`.
+///
+/// Must be wrapped inside the logging, metrics and rate limit middlewares so they see the path
+/// the client sent.
+pub fn route_version<S, B>(
+    mut req: ServiceRequest,
+    srv: &S,
+) -> LocalBoxFuture<'static, Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    let path = req.path().to_string();
+    if !path.starts_with("/api/") || UNVERSIONED_PATHS.contains(&path.as_str()) {
+        return Box::pin(srv.call(req));
+    }
+    let (version, negotiated) = match path_version(&path) {
+        Some(version) if SUPPORTED_VERSIONS.contains(&version) => (version, false),
+        Some(_) => return Box::pin(ready(Err(AppError::NotFound("Unknown API version".to_string()).into()))),
+        None => match negotiate(&req) {
+            Ok(version) => {
+                rewrite(&mut req, version);
+                (version, true)
+            }
+            Err(e) => return Box::pin(ready(Err(e.into()))),
+        },
+    };
+
+    let call = srv.call(req);
+    Box::pin(async move {
+        let mut res = call.await?;
+        res.headers_mut()
+            .insert(HeaderName::from_static(API_VERSION_HEADER), HeaderValue::from(version));
+        if negotiated {
+            res.headers_mut().append(VARY, HeaderValue::from_static("Accept"));
+        }
+        Ok(res)
+    })
+}
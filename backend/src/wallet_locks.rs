@@ -0,0 +1,60 @@
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+use crate::{AppError, AppResult};
+
+// Dead entries are swept once the map grows past this
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// Holds a wallet's lock until dropped.
+pub struct WalletGuard {
+    _guard: OwnedMutexGuard<()>,
+}
+
+/// One async mutex per wallet, so a wallet's transactions are built and submitted one at a
+/// time: two renew clicks cannot both charge or race for the same blockhash or nonce, while
+/// other wallets go ahead in parallel. The map only holds weak references, so a wallet's
+/// mutex lives as long as someone holds or waits for it. The locks are per process; replicas
+/// still rely on idempotency keys and the program's own checks.
+#[derive(Clone)]
+pub struct WalletLocks {
+    locks: Arc<Mutex<HashMap<Pubkey, Weak<AsyncMutex<()>>>>>,
+    timeout: Duration,
+}
+
+impl WalletLocks {
+    pub fn new(timeout: Duration) -> Self {
+        Self { locks: Arc::new(Mutex::new(HashMap::new())), timeout }
+    }
+
+    /// Waits for `wallet`'s lock, failing with a conflict if it is still held after the
+    /// timeout. Not reentrant: a holder must not lock the same wallet again.
+    pub async fn lock(&self, wallet: &Pubkey) -> AppResult<WalletGuard> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+            match locks.get(wallet).and_then(Weak::upgrade) {
+                Some(lock) => lock,
+                None => {
+                    if locks.len() >= PRUNE_THRESHOLD {
+                        locks.retain(|_, lock| lock.strong_count() > 0);
+                    }
+                    let lock = Arc::new(AsyncMutex::new(()));
+                    locks.insert(*wallet, Arc::downgrade(&lock));
+                    lock
+                }
+            }
+        };
+        match tokio::time::timeout(self.timeout, lock.lock_owned()).await {
+            Ok(guard) => Ok(WalletGuard { _guard: guard }),
+            Err(_) => {
+                tracing::warn!("Timed out waiting for the transaction lock of {}", wallet);
+                Err(AppError::Conflict(format!(
+                    "Another transaction for wallet {} is still in progress; retry shortly",
+                    wallet
+                )))
+            }
+        }
+    }
+}
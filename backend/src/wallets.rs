@@ -0,0 +1,226 @@
+use actix_web::{delete, get, post, web, HttpMessage, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use sqlx::postgres::PgPool;
+use std::str::FromStr;
+use utoipa::ToSchema;
+use validator::Validate;
+use crate::db;
+use crate::validation::{validate_pubkey, ValidatedJson};
+use crate::{random_token, unix_now, AppError, AppResult, AuthToken, Config};
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct LinkChallengeRequest {
+    #[validate(custom = "validate_pubkey")]
+    public_key: String, // The wallet to link
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct LinkChallenge {
+    nonce: String,
+    message: String, // What the wallet to link signs
+    expires_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct LinkWalletRequest {
+    #[validate(custom = "validate_pubkey")]
+    public_key: String,
+    #[validate(length(min = 1, max = 128, message = "must be 1 to 128 characters"))]
+    nonce: String,
+    #[validate(length(min = 1, max = 128, message = "must be 1 to 128 characters"))]
+    signature: String, // Base58 signature of the challenge message by `public_key`
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct LinkedWallet {
+    wallet: String,
+    linked_at: Option<i64>, // Unset for the account's own wallet
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct LinkedWallets {
+    account: String,
+    wallets: Vec<LinkedWallet>, // The account's wallet first
+}
+
+// Wallet Service
+/// Links wallets into one account. The account is the wallet that links the others and any of
+/// them signs in to it: its subscriptions are listed across every wallet. Linking takes a
+/// signature from the new wallet over a single-use challenge naming the account, so neither a
+/// sign-in signature nor one made for another account can be replayed to link it.
+#[derive(Clone)]
+pub struct WalletService {
+    pool: PgPool,
+    challenge_ttl_secs: i64,
+    replay_ttl_secs: i64,
+}
+
+impl WalletService {
+    pub fn new(config: &Config, pool: PgPool) -> Self {
+        Self {
+            pool,
+            challenge_ttl_secs: config.auth_challenge_ttl_secs as i64,
+            replay_ttl_secs: config.auth_replay_ttl_secs.max(config.auth_challenge_ttl_secs) as i64,
+        }
+    }
+
+    /// The account `wallet` belongs to, itself if it is not linked to another.
+    pub async fn account(&self, wallet: &str) -> AppResult<String> {
+        Ok(db::find_wallet_account(&self.pool, wallet).await?.unwrap_or_else(|| wallet.to_string()))
+    }
+
+    /// Every wallet of the account `wallet` belongs to, the account's own first.
+    pub async fn wallets(&self, wallet: &str) -> AppResult<Vec<String>> {
+        Ok(self.list(wallet).await?.wallets.into_iter().map(|linked| linked.wallet).collect())
+    }
+
+    pub async fn list(&self, wallet: &str) -> AppResult<LinkedWallets> {
+        let account = self.account(wallet).await?;
+        let links = db::list_wallet_links(&self.pool, &account).await?;
+        let wallets = std::iter::once(LinkedWallet { wallet: account.clone(), linked_at: None })
+            .chain(links.into_iter().map(|link| LinkedWallet { wallet: link.wallet, linked_at: Some(link.linked_at) }))
+            .collect();
+        Ok(LinkedWallets { account, wallets })
+    }
+
+    /// Issues a single-use nonce for `public_key` to prove it may be linked to `owner`'s account.
+    pub async fn challenge(&self, owner: &str, public_key: &str) -> AppResult<LinkChallenge> {
+        let account = self.account(owner).await?;
+        let nonce = random_token(32);
+        let expires_at = unix_now() + self.challenge_ttl_secs;
+        db::insert_auth_challenge(&self.pool, &nonce, public_key, expires_at).await?;
+        Ok(LinkChallenge { message: link_message(&account, &nonce), nonce, expires_at })
+    }
+
+    pub async fn link(&self, owner: &str, req: LinkWalletRequest) -> AppResult<LinkedWallets> {
+        let account = self.account(owner).await?;
+        if req.public_key == account {
+            return Err(AppError::Conflict("Wallet is the account's own wallet".to_string()));
+        }
+        let signature_bytes = bs58::decode(&req.signature)
+            .into_vec()
+            .map_err(|e| AppError::BadRequest(format!("Invalid signature format: {}", e)))?;
+        let signature = Signature::try_from(signature_bytes.as_slice())
+            .map_err(|e| AppError::BadRequest(format!("Invalid signature: {}", e)))?;
+        let pubkey = Pubkey::from_str(&req.public_key)
+            .map_err(|e| AppError::BadRequest(format!("Invalid public key: {}", e)))?;
+        if !signature.verify(pubkey.as_ref(), link_message(&account, &req.nonce).as_bytes()) {
+            return Err(AppError::Auth("Invalid signature".to_string()));
+        }
+
+        let remember_until = unix_now() + self.replay_ttl_secs;
+        if !db::consume_auth_signature(&self.pool, &signature.to_string(), &req.public_key, &req.nonce, remember_until).await? {
+            return Err(AppError::Auth("Signature has already been used".to_string()));
+        }
+        if !db::consume_auth_challenge(&self.pool, &req.nonce, &req.public_key).await? {
+            return Err(AppError::Auth("Unknown, expired or already used challenge".to_string()));
+        }
+
+        if !db::insert_wallet_link(&self.pool, &req.public_key, &account, unix_now()).await? {
+            return Err(AppError::Conflict(format!(
+                "Wallet {} is already linked to an account or has wallets linked to it",
+                req.public_key
+            )));
+        }
+        tracing::info!("Linked wallet {} to account {}", req.public_key, account);
+        self.list(&account).await
+    }
+
+    pub async fn unlink(&self, owner: &str, wallet: &str) -> AppResult<()> {
+        let account = self.account(owner).await?;
+        if !db::delete_wallet_link(&self.pool, wallet, &account).await? {
+            return Err(AppError::NotFound(format!("Wallet {} is not linked to this account", wallet)));
+        }
+        tracing::info!("Unlinked wallet {} from account {}", wallet, account);
+        Ok(())
+    }
+}
+
+fn link_message(account: &str, nonce: &str) -> String {
+    format!("Link this wallet to Subscription Manager account {}: {}", account, nonce)
+}
+
+// Controllers
+/// Issues the challenge the wallet to link signs for `POST /wallets`.
+#[utoipa::path(
+    post,
+    path = "/api/v1/wallets/challenge",
+    tag = "wallets",
+    request_body = LinkChallengeRequest,
+    responses(
+        (status = 200, description = "Single-use link challenge", body = LinkChallenge),
+        (status = 422, description = "Invalid public key", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/wallets/challenge")]
+pub async fn link_challenge(
+    req: HttpRequest,
+    wallets: web::Data<WalletService>,
+    body: ValidatedJson<LinkChallengeRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let challenge = wallets.challenge(&auth_token.public_key, &body.public_key).await?;
+    Ok(HttpResponse::Ok().json(challenge))
+}
+
+/// Links the signing wallet to the authenticated wallet's account.
+#[utoipa::path(
+    post,
+    path = "/api/v1/wallets",
+    tag = "wallets",
+    request_body = LinkWalletRequest,
+    responses(
+        (status = 201, description = "Wallets of the account", body = LinkedWallets),
+        (status = 401, description = "Invalid signature or challenge", body = ErrorResponse),
+        (status = 409, description = "Wallet already belongs to an account", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/wallets")]
+pub async fn link_wallet(
+    req: HttpRequest,
+    wallets: web::Data<WalletService>,
+    body: ValidatedJson<LinkWalletRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let linked = wallets.link(&auth_token.public_key, body.into_inner()).await?;
+    Ok(HttpResponse::Created().json(linked))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/wallets",
+    tag = "wallets",
+    responses((status = 200, description = "Wallets of the authenticated wallet's account", body = LinkedWallets)),
+    security(("bearer_auth" = []))
+)]
+#[get("/wallets")]
+pub async fn list_wallets(req: HttpRequest, wallets: web::Data<WalletService>) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    Ok(HttpResponse::Ok().json(wallets.list(&auth_token.public_key).await?))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/wallets/{wallet}",
+    tag = "wallets",
+    params(("wallet" = String, Path, description = "Linked wallet")),
+    responses(
+        (status = 204, description = "Wallet unlinked"),
+        (status = 404, description = "Wallet not linked to this account", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[delete("/wallets/{wallet}")]
+pub async fn unlink_wallet(
+    req: HttpRequest,
+    path: web::Path<String>,
+    wallets: web::Data<WalletService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    wallets.unlink(&auth_token.public_key, &path.into_inner()).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
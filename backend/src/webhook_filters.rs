@@ -0,0 +1,513 @@
+use crate::db::SubscriptionRow;
+use crate::webhooks::{SubscriptionEventData, WebhookEventType};
+
+const MAX_FILTER_LEN: usize = 1024;
+// Nested parentheses and negations, bounding the parser's recursion
+const MAX_DEPTH: usize = 32;
+
+/// What a filter can test. Most come from the event itself; `amount` and `duration` are read
+/// from the indexed subscription.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Type,
+    PlanId,
+    Owner,
+    Subscription,
+    Signature,
+    Milestone,
+    ExpiresAt,
+    RefundId,
+    RefundLamports,
+    Amount,
+    Duration,
+}
+
+impl Field {
+    const ALL: [Field; 11] = [
+        Field::Type,
+        Field::PlanId,
+        Field::Owner,
+        Field::Subscription,
+        Field::Signature,
+        Field::Milestone,
+        Field::ExpiresAt,
+        Field::RefundId,
+        Field::RefundLamports,
+        Field::Amount,
+        Field::Duration,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Field::Type => "type",
+            Field::PlanId => "plan_id",
+            Field::Owner => "owner",
+            Field::Subscription => "subscription",
+            Field::Signature => "signature",
+            Field::Milestone => "milestone",
+            Field::ExpiresAt => "expires_at",
+            Field::RefundId => "refund_id",
+            Field::RefundLamports => "refund_lamports",
+            Field::Amount => "amount",
+            Field::Duration => "duration",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|field| field.name() == name)
+    }
+
+    fn numeric(&self) -> bool {
+        matches!(
+            self,
+            Field::PlanId | Field::ExpiresAt | Field::RefundLamports | Field::Amount | Field::Duration
+        )
+    }
+
+    fn is_subscription_field(&self) -> bool {
+        matches!(self, Field::Amount | Field::Duration)
+    }
+
+    /// The field's value in `input`, `None` when the event does not carry it.
+    fn value<'a>(&self, input: &FilterInput<'a>) -> Option<Operand<'a>> {
+        let data = input.data;
+        match self {
+            Field::Type => Some(Operand::Text(input.event_type.as_str())),
+            Field::PlanId => Some(Operand::Number(data.plan_id as i128)),
+            Field::Owner => Some(Operand::Text(&data.owner)),
+            Field::Subscription => Some(Operand::Text(&data.subscription)),
+            Field::Signature => data.signature.as_deref().map(Operand::Text),
+            Field::Milestone => data.milestone.as_deref().map(Operand::Text),
+            Field::ExpiresAt => data.expires_at.map(|v| Operand::Number(v as i128)),
+            Field::RefundId => data.refund_id.as_deref().map(Operand::Text),
+            Field::RefundLamports => data.refund_lamports.map(|v| Operand::Number(v as i128)),
+            Field::Amount => input.subscription.map(|s| Operand::Number(s.amount as i128)),
+            Field::Duration => input.subscription.map(|s| Operand::Number(s.duration as i128)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Literal {
+    Number(i128),
+    Text(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand<'a> {
+    Number(i128),
+    Text(&'a str),
+}
+
+impl Operand<'_> {
+    fn equals(&self, literal: &Literal) -> bool {
+        match (self, literal) {
+            (Operand::Number(a), Literal::Number(b)) => a == b,
+            (Operand::Text(a), Literal::Text(b)) => *a == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Compare(Field, Op, Literal),
+    In(Field, Vec<Literal>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, input: &FilterInput) -> bool {
+        match self {
+            Expr::Compare(field, op, literal) => {
+                let Some(value) = field.value(input) else {
+                    return false;
+                };
+                match (op, value, literal) {
+                    (Op::Eq, value, literal) => value.equals(literal),
+                    (Op::Ne, value, literal) => !value.equals(literal),
+                    (op, Operand::Number(a), Literal::Number(b)) => match op {
+                        Op::Gt => a > *b,
+                        Op::Ge => a >= *b,
+                        Op::Lt => a < *b,
+                        _ => a <= *b,
+                    },
+                    _ => false,
+                }
+            }
+            Expr::In(field, literals) => {
+                field.value(input).is_some_and(|value| literals.iter().any(|literal| value.equals(literal)))
+            }
+            Expr::Not(expr) => !expr.eval(input),
+            Expr::And(left, right) => left.eval(input) && right.eval(input),
+            Expr::Or(left, right) => left.eval(input) || right.eval(input),
+        }
+    }
+
+    fn uses_subscription(&self) -> bool {
+        match self {
+            Expr::Compare(field, _, _) | Expr::In(field, _) => field.is_subscription_field(),
+            Expr::Not(expr) => expr.uses_subscription(),
+            Expr::And(left, right) | Expr::Or(left, right) => left.uses_subscription() || right.uses_subscription(),
+        }
+    }
+}
+
+/// What a filter is evaluated against.
+pub struct FilterInput<'a> {
+    pub event_type: WebhookEventType,
+    pub data: &'a SubscriptionEventData,
+    pub subscription: Option<&'a SubscriptionRow>, // Loaded only for filters that need it
+}
+
+/// A parsed webhook filter, e.g. `plan_id in [1, 2] && amount > 1_000_000`.
+///
+/// Comparisons are `==`, `!=`, `>`, `>=`, `<`, `<=` and `in [..]` between a field and a
+/// literal: a number, or a string in single or double quotes. They combine with `&&`, `||`,
+/// `!` and parentheses, `!` binding tightest and `||` loosest. Comparing a field the event
+/// does not carry, such as `refund_lamports` on a renewal, is false.
+#[derive(Debug, Clone)]
+pub struct WebhookFilter {
+    expr: Expr,
+}
+
+impl WebhookFilter {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        if source.len() > MAX_FILTER_LEN {
+            return Err(format!("Filter must be at most {} characters", MAX_FILTER_LEN));
+        }
+        let mut parser = Parser { tokens: tokenize(source)?, pos: 0, depth: 0 };
+        let expr = parser.or()?;
+        if let Some((token, at)) = parser.tokens.get(parser.pos) {
+            return Err(format!("Unexpected {} at position {}", token.describe(), at));
+        }
+        Ok(Self { expr })
+    }
+
+    /// Whether `amount` or `duration` appear, so the indexed subscription must be loaded.
+    pub fn uses_subscription(&self) -> bool {
+        self.expr.uses_subscription()
+    }
+
+    pub fn matches(&self, input: &FilterInput) -> bool {
+        self.expr.eval(input)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Number(i128),
+    Text(String),
+    Symbol(&'static str),
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Ident(name) => format!("`{}`", name),
+            Token::Number(n) => format!("`{}`", n),
+            Token::Text(s) => format!("\"{}\"", s),
+            Token::Symbol(s) => format!("`{}`", s),
+        }
+    }
+}
+
+const SYMBOLS: [&str; 14] = ["==", "!=", ">=", "<=", "&&", "||", ">", "<", "!", "(", ")", "[", "]", ","];
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<(usize, char)> = source.char_indices().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let (at, c) = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '"' || c == '\'' {
+            let end = chars[i + 1..]
+                .iter()
+                .position(|(_, ch)| *ch == c)
+                .ok_or_else(|| format!("Unterminated string at position {}", at))?;
+            let text: String = chars[i + 1..i + 1 + end].iter().map(|(_, ch)| ch).collect();
+            tokens.push((Token::Text(text), at));
+            i += end + 2;
+        } else if c.is_ascii_digit() {
+            let mut digits = String::new();
+            while let Some((_, ch)) = chars.get(i).filter(|(_, ch)| ch.is_ascii_digit() || *ch == '_') {
+                if *ch != '_' {
+                    digits.push(*ch);
+                }
+                i += 1;
+            }
+            let number: u64 = digits.parse().map_err(|_| format!("Number at position {} is too large", at))?;
+            tokens.push((Token::Number(number as i128), at));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some((_, ch)) = chars.get(i).filter(|(_, ch)| ch.is_ascii_alphanumeric() || *ch == '_') {
+                name.push(*ch);
+                i += 1;
+            }
+            tokens.push((Token::Ident(name), at));
+        } else {
+            let rest = &source[at..];
+            let symbol = SYMBOLS
+                .into_iter()
+                .find(|symbol| rest.starts_with(symbol))
+                .ok_or_else(|| format!("Unexpected `{}` at position {}", c, at))?;
+            tokens.push((Token::Symbol(symbol), at));
+            i += symbol.len();
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn next(&mut self, expected: &str) -> Result<(Token, usize), String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| format!("Expected {} at the end of the filter", expected))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, symbol: &'static str) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), String> {
+        match self.next(&format!("`{}`", symbol))? {
+            (Token::Symbol(s), _) if s == symbol => Ok(()),
+            (token, at) => Err(format!("Expected `{}` at position {}, found {}", symbol, at, token.describe())),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if matches!(self.peek(), Some(Token::Symbol("!" | "("))) {
+            self.depth += 1;
+            if self.depth > MAX_DEPTH {
+                return Err(format!("Filter nests deeper than {} levels", MAX_DEPTH));
+            }
+            let expr = if self.eat("!") {
+                Expr::Not(Box::new(self.unary()?))
+            } else {
+                self.expect("(")?;
+                let expr = self.or()?;
+                self.expect(")")?;
+                expr
+            };
+            self.depth -= 1;
+            return Ok(expr);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let field = match self.next("a field")? {
+            (Token::Ident(name), at) => Field::from_name(&name).ok_or_else(|| {
+                let fields: Vec<&str> = Field::ALL.iter().map(Field::name).collect();
+                format!("Unknown field `{}` at position {}, expected one of {}", name, at, fields.join(", "))
+            })?,
+            (token, at) => return Err(format!("Expected a field at position {}, found {}", at, token.describe())),
+        };
+        let (op, at) = match self.next("an operator")? {
+            (Token::Ident(name), _) if name == "in" => {
+                self.expect("[")?;
+                let mut literals = vec![self.literal(field)?];
+                while self.eat(",") {
+                    literals.push(self.literal(field)?);
+                }
+                self.expect("]")?;
+                return Ok(Expr::In(field, literals));
+            }
+            (Token::Symbol("=="), at) => (Op::Eq, at),
+            (Token::Symbol("!="), at) => (Op::Ne, at),
+            (Token::Symbol(">"), at) => (Op::Gt, at),
+            (Token::Symbol(">="), at) => (Op::Ge, at),
+            (Token::Symbol("<"), at) => (Op::Lt, at),
+            (Token::Symbol("<="), at) => (Op::Le, at),
+            (token, at) => return Err(format!("Expected an operator at position {}, found {}", at, token.describe())),
+        };
+        if !matches!(op, Op::Eq | Op::Ne) && !field.numeric() {
+            return Err(format!("`{}` is not a number and cannot be ordered (position {})", field.name(), at));
+        }
+        Ok(Expr::Compare(field, op, self.literal(field)?))
+    }
+
+    /// A literal of the field's type; event types must exist.
+    fn literal(&mut self, field: Field) -> Result<Literal, String> {
+        match self.next("a value")? {
+            (Token::Number(n), _) if field.numeric() => Ok(Literal::Number(n)),
+            (Token::Text(s), at) if field == Field::Type && WebhookEventType::from_name(&s).is_none() => {
+                Err(format!("Unknown event type \"{}\" at position {}", s, at))
+            }
+            (Token::Text(s), _) if !field.numeric() => Ok(Literal::Text(s)),
+            (token, at) => {
+                let expected = if field.numeric() { "a number" } else { "a quoted string" };
+                Err(format!("`{}` takes {}, found {} at position {}", field.name(), expected, token.describe(), at))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn renewal() -> SubscriptionEventData {
+        SubscriptionEventData {
+            subscription: "Sub1".to_string(),
+            owner: "Owner1".to_string(),
+            plan_id: 2,
+            signature: Some("Sig1".to_string()),
+            milestone: None,
+            expires_at: None,
+            refund_id: None,
+            refund_lamports: None,
+        }
+    }
+
+    fn subscription() -> SubscriptionRow {
+        SubscriptionRow {
+            pda: "Sub1".to_string(),
+            owner: "Owner1".to_string(),
+            plan_id: 2,
+            start_time: 0,
+            duration: 2_592_000,
+            amount: 5_000_000,
+            active: true,
+            closed: false,
+            history: vec![0],
+            updated_slot: 1,
+        }
+    }
+
+    fn matches(filter: &str, data: &SubscriptionEventData, subscription: Option<&SubscriptionRow>) -> bool {
+        let filter = WebhookFilter::parse(filter).unwrap();
+        filter.matches(&FilterInput { event_type: WebhookEventType::SubscriptionRenewed, data, subscription })
+    }
+
+    fn parse_error(filter: &str) -> String {
+        WebhookFilter::parse(filter).unwrap_err()
+    }
+
+    #[test]
+    fn compares_event_fields() {
+        let data = renewal();
+        assert!(matches("plan_id == 2", &data, None));
+        assert!(matches("plan_id >= 2 && plan_id < 3", &data, None));
+        assert!(!matches("plan_id > 2", &data, None));
+        assert!(matches("owner == 'Owner1'", &data, None));
+        assert!(matches("owner != \"Owner2\"", &data, None));
+        assert!(matches("type == \"subscription.renewed\"", &data, None));
+        assert!(matches("plan_id in [1, 2, 3]", &data, None));
+        assert!(!matches("owner in ['Owner2']", &data, None));
+    }
+
+    #[test]
+    fn combines_with_precedence() {
+        let data = renewal();
+        // `&&` binds tighter than `||`, and `!` tighter than both
+        assert!(matches("plan_id == 9 && owner == 'x' || plan_id == 2", &data, None));
+        assert!(!matches("plan_id == 9 && (owner == 'x' || plan_id == 2)", &data, None));
+        assert!(matches("!plan_id == 9 && !!(plan_id == 2)", &data, None));
+    }
+
+    #[test]
+    fn missing_fields_never_match() {
+        let data = renewal();
+        assert!(!matches("refund_lamports > 0", &data, None));
+        assert!(!matches("refund_lamports == 0", &data, None));
+        assert!(!matches("milestone in ['expired']", &data, None));
+        assert!(matches("!(refund_lamports > 0)", &data, None));
+    }
+
+    #[test]
+    fn reads_amount_and_duration_from_the_subscription() {
+        let data = renewal();
+        let filter = WebhookFilter::parse("amount > 1_000_000").unwrap();
+        assert!(filter.uses_subscription());
+        assert!(!WebhookFilter::parse("plan_id == 2").unwrap().uses_subscription());
+        assert!(matches("amount > 1_000_000 && duration == 2_592_000", &data, Some(&subscription())));
+        assert!(!matches("amount > 1_000_000", &data, None));
+    }
+
+    #[test]
+    fn rejects_unknown_names() {
+        assert!(parse_error("price > 1").contains("Unknown field `price`"));
+        assert!(parse_error("type == 'subscription.deleted'").contains("Unknown event type"));
+        assert!(parse_error("plan_id like 1").contains("Expected an operator"));
+    }
+
+    #[test]
+    fn rejects_mistyped_values() {
+        assert!(parse_error("plan_id == '2'").contains("takes a number"));
+        assert!(parse_error("owner == 2").contains("takes a quoted string"));
+        assert!(parse_error("owner > 'a'").contains("cannot be ordered"));
+        assert!(parse_error("plan_id == 99999999999999999999").contains("too large"));
+    }
+
+    #[test]
+    fn rejects_malformed_syntax() {
+        assert!(parse_error("").contains("at the end of the filter"));
+        assert!(parse_error("owner == 'Owner1").contains("Unterminated string at position 9"));
+        assert!(parse_error("(plan_id == 2").contains("Expected `)`"));
+        assert!(parse_error("plan_id == 2 plan_id").contains("Unexpected `plan_id` at position 13"));
+        assert!(parse_error("plan_id in [1, 2").contains("Expected `]`"));
+        assert!(parse_error("plan_id == 2 & owner == 'a'").contains("Unexpected `&`"));
+    }
+
+    #[test]
+    fn bounds_length_and_nesting() {
+        let long = format!("owner == '{}'", "a".repeat(MAX_FILTER_LEN));
+        assert!(parse_error(&long).contains("at most"));
+        let deep = format!("{}plan_id == 2", "!".repeat(MAX_DEPTH + 1));
+        assert!(parse_error(&deep).contains("nests deeper"));
+        let nested = format!("{}plan_id == 2{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert!(WebhookFilter::parse(&nested).is_ok());
+    }
+}
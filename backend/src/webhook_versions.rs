@@ -0,0 +1,280 @@
+use actix_web::{get, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+use crate::webhooks::{SubscriptionEventData, WebhookEvent, WebhookEventType};
+use crate::{AppError, AppResult};
+
+// Models
+/// A webhook payload format. Webhooks are pinned to one, so a later version never changes
+/// what an existing integration receives; changes within a version are additive only.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookVersion {
+    /// The original flat payload. Webhooks registered without a version get it.
+    #[default]
+    V1,
+    /// Groups the data into `subscription`, `transaction`, `period` and `refund` objects.
+    V2,
+}
+
+impl WebhookVersion {
+    pub const ALL: [WebhookVersion; 2] = [WebhookVersion::V1, WebhookVersion::V2];
+    pub const LATEST: WebhookVersion = WebhookVersion::V2;
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookVersion::V1 => "v1",
+            WebhookVersion::V2 => "v2",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|version| version.as_str() == name)
+    }
+
+    fn changes(&self) -> &'static str {
+        match self {
+            WebhookVersion::V1 => "The original format: subscription fields directly under `data`.",
+            WebhookVersion::V2 => {
+                "`data` groups the subscription (`address`, `owner`, `plan_id`), its `transaction` (always \
+                 present, `null` when none), the billing `period` of reminder and renewal events (`expires_at` \
+                 becomes `period.ends_at`) and the `refund` of refund events."
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct EventV1<'a> {
+    id: &'a str,
+    #[serde(rename = "type")]
+    event_type: WebhookEventType,
+    version: WebhookVersion,
+    created_at: i64,
+    data: &'a SubscriptionEventData,
+}
+
+#[derive(Serialize)]
+struct EventV2<'a> {
+    id: &'a str,
+    #[serde(rename = "type")]
+    event_type: WebhookEventType,
+    version: WebhookVersion,
+    created_at: i64,
+    data: DataV2<'a>,
+}
+
+#[derive(Serialize)]
+struct DataV2<'a> {
+    subscription: SubscriptionV2<'a>,
+    transaction: Option<TransactionV2<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    period: Option<PeriodV2<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refund: Option<RefundV2<'a>>,
+}
+
+#[derive(Serialize)]
+struct SubscriptionV2<'a> {
+    address: &'a str,
+    owner: &'a str,
+    plan_id: u64,
+}
+
+#[derive(Serialize)]
+struct TransactionV2<'a> {
+    signature: &'a str,
+}
+
+#[derive(Serialize)]
+struct PeriodV2<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ends_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    milestone: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct RefundV2<'a> {
+    id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lamports: Option<u64>,
+}
+
+/// `event` as webhooks pinned to `version` receive it.
+pub fn render(event: &WebhookEvent<SubscriptionEventData>, version: WebhookVersion) -> serde_json::Result<String> {
+    let data = &event.data;
+    match version {
+        WebhookVersion::V1 => serde_json::to_string(&EventV1 {
+            id: &event.id,
+            event_type: event.event_type,
+            version,
+            created_at: event.created_at,
+            data,
+        }),
+        WebhookVersion::V2 => serde_json::to_string(&EventV2 {
+            id: &event.id,
+            event_type: event.event_type,
+            version,
+            created_at: event.created_at,
+            data: DataV2 {
+                subscription: SubscriptionV2 { address: &data.subscription, owner: &data.owner, plan_id: data.plan_id },
+                transaction: data.signature.as_deref().map(|signature| TransactionV2 { signature }),
+                period: (data.expires_at.is_some() || data.milestone.is_some()).then_some(PeriodV2 {
+                    ends_at: data.expires_at,
+                    milestone: data.milestone.as_deref(),
+                }),
+                refund: data.refund_id.as_deref().map(|id| RefundV2 { id, lamports: data.refund_lamports }),
+            },
+        }),
+    }
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct WebhookSchema {
+    version: WebhookVersion,
+    latest: bool,
+    default: bool, // Used by webhooks registered without a version
+    changes: String,
+    #[schema(value_type = Object)]
+    schema: Value, // JSON Schema of the payload
+    #[schema(value_type = Object)]
+    example: Value,
+}
+
+/// Every payload version, oldest first, with its JSON Schema and an example.
+pub fn schemas() -> Vec<WebhookSchema> {
+    WebhookVersion::ALL.into_iter().map(schema).collect()
+}
+
+fn schema(version: WebhookVersion) -> WebhookSchema {
+    let event_types: Vec<&str> = WebhookEventType::ALL.iter().map(WebhookEventType::as_str).collect();
+    let milestone = json!({ "type": "string", "enum": ["expiring_3d", "expiring_1d", "expired"] });
+    let data = match version {
+        WebhookVersion::V1 => json!({
+            "type": "object",
+            "required": ["subscription", "owner", "plan_id", "signature"],
+            "properties": {
+                "subscription": { "type": "string", "description": "Subscription account address" },
+                "owner": { "type": "string" },
+                "plan_id": { "type": "integer", "minimum": 0 },
+                "signature": { "type": ["string", "null"], "description": "Transaction signature, if any" },
+                "milestone": milestone,
+                "expires_at": { "type": "integer", "description": "End of the billing period, Unix seconds" },
+                "refund_id": { "type": "string" },
+                "refund_lamports": { "type": "integer", "minimum": 0 }
+            }
+        }),
+        WebhookVersion::V2 => json!({
+            "type": "object",
+            "required": ["subscription", "transaction"],
+            "properties": {
+                "subscription": {
+                    "type": "object",
+                    "required": ["address", "owner", "plan_id"],
+                    "properties": {
+                        "address": { "type": "string" },
+                        "owner": { "type": "string" },
+                        "plan_id": { "type": "integer", "minimum": 0 }
+                    }
+                },
+                "transaction": {
+                    "type": ["object", "null"],
+                    "required": ["signature"],
+                    "properties": { "signature": { "type": "string" } }
+                },
+                "period": {
+                    "type": "object",
+                    "properties": {
+                        "ends_at": { "type": "integer", "description": "Unix seconds" },
+                        "milestone": milestone
+                    }
+                },
+                "refund": {
+                    "type": "object",
+                    "required": ["id"],
+                    "properties": {
+                        "id": { "type": "string" },
+                        "lamports": { "type": "integer", "minimum": 0 }
+                    }
+                }
+            }
+        }),
+    };
+    let example = WebhookEvent {
+        id: "3f9a1c0e5b7d2a4c6e8f0a1b2c3d4e5f".to_string(),
+        event_type: WebhookEventType::SubscriptionExpiring,
+        created_at: 1735689600,
+        data: SubscriptionEventData {
+            subscription: "7Xq3ZbUu4xvV1bJ3X3S2aH9kZp1xW1o8yTqVQjvL2dQm".to_string(),
+            owner: "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM".to_string(),
+            plan_id: 1,
+            signature: None,
+            milestone: Some("expiring_3d".to_string()),
+            expires_at: Some(1735948800),
+            refund_id: None,
+            refund_lamports: None,
+        },
+    };
+    let example = render(&example, version)
+        .ok()
+        .and_then(|body| serde_json::from_str(&body).ok())
+        .unwrap_or(Value::Null);
+
+    WebhookSchema {
+        version,
+        latest: version == WebhookVersion::LATEST,
+        default: version == WebhookVersion::default(),
+        changes: version.changes().to_string(),
+        schema: json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": format!("Webhook event {}", version.as_str()),
+            "type": "object",
+            "required": ["id", "type", "version", "created_at", "data"],
+            "properties": {
+                "id": { "type": "string", "description": "Event id, the same across retries and replays" },
+                "type": { "type": "string", "enum": event_types },
+                "version": { "const": version.as_str() },
+                "created_at": { "type": "integer", "description": "Unix seconds" },
+                "data": data
+            }
+        }),
+        example,
+    }
+}
+
+// Controllers
+#[utoipa::path(
+    get,
+    path = "/api/v1/webhooks/schemas",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "Every webhook payload version, oldest first", body = [WebhookSchema]),
+        (status = 403, description = "Merchant role required", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []), ("api_key" = []))
+)]
+#[get("/schemas")]
+pub async fn list_webhook_schemas() -> AppResult<HttpResponse> {
+    Ok(HttpResponse::Ok().json(schemas()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/webhooks/schemas/{version}",
+    tag = "webhooks",
+    params(("version" = String, Path, description = "Payload version, e.g. v2")),
+    responses(
+        (status = 200, description = "The version's JSON Schema and an example", body = WebhookSchema),
+        (status = 404, description = "Unknown version", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []), ("api_key" = []))
+)]
+#[get("/schemas/{version}")]
+pub async fn get_webhook_schema(path: web::Path<String>) -> AppResult<HttpResponse> {
+    let name = path.into_inner();
+    let version = WebhookVersion::from_name(&name)
+        .ok_or_else(|| AppError::NotFound(format!("Webhook payload version {} not found", name)))?;
+    Ok(HttpResponse::Ok().json(schema(version)))
+}
@@ -0,0 +1,1303 @@
+use actix_web::{delete, get, post, put, web, HttpMessage, HttpResponse};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::postgres::PgPool;
+use futures_util::future::join_all;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use tracing::Instrument;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+use crate::channels::ChannelService;
+use crate::db::{self, DeadLetterRow, WebhookDeliveryRow};
+use crate::metrics;
+use crate::plans::PlanService;
+use crate::reporting;
+use crate::telemetry;
+use crate::user_channels::UserChannelService;
+use crate::validation::{FieldError, ValidatedJson, ValidatedQuery};
+use crate::webhook_filters::{FilterInput, WebhookFilter};
+use crate::webhook_versions::{self, WebhookVersion};
+use crate::{AppError, AppResult, AuthToken, Config, Role};
+
+const MAX_BACKOFF_SECS: u64 = 3600;
+const DEFAULT_OVERLAP_SECS: u64 = 86400;
+const DEFAULT_DELIVERIES_LIMIT: usize = 100;
+const DELIVERY_SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+const DELIVERY_BATCH_SIZE: i64 = 100;
+// How long a replica has to send a claimed delivery before another may, well past the send timeout
+const DELIVERY_CLAIM_SECS: i64 = 60;
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+const DEFAULT_DEAD_LETTERS_LIMIT: i64 = 100;
+// Events buffered per live subscriber before the slowest starts missing them
+const EVENT_STREAM_CAPACITY: usize = 1024;
+
+// Models
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, ToSchema)]
+pub enum WebhookEventType {
+    #[serde(rename = "subscription.created")]
+    SubscriptionCreated,
+    #[serde(rename = "subscription.renewed")]
+    SubscriptionRenewed,
+    #[serde(rename = "subscription.renewal_failed")]
+    SubscriptionRenewalFailed,
+    #[serde(rename = "subscription.cancelled")]
+    SubscriptionCancelled,
+    #[serde(rename = "subscription.expiring")]
+    SubscriptionExpiring,
+    #[serde(rename = "subscription.expired")]
+    SubscriptionExpired,
+    #[serde(rename = "subscription.closed")]
+    SubscriptionClosed,
+    #[serde(rename = "refund.requested")]
+    RefundRequested,
+    #[serde(rename = "refund.succeeded")]
+    RefundSucceeded,
+    #[serde(rename = "refund.failed")]
+    RefundFailed,
+}
+
+impl WebhookEventType {
+    pub const ALL: [WebhookEventType; 10] = [
+        WebhookEventType::SubscriptionCreated,
+        WebhookEventType::SubscriptionRenewed,
+        WebhookEventType::SubscriptionRenewalFailed,
+        WebhookEventType::SubscriptionCancelled,
+        WebhookEventType::SubscriptionExpiring,
+        WebhookEventType::SubscriptionExpired,
+        WebhookEventType::SubscriptionClosed,
+        WebhookEventType::RefundRequested,
+        WebhookEventType::RefundSucceeded,
+        WebhookEventType::RefundFailed,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEventType::SubscriptionCreated => "subscription.created",
+            WebhookEventType::SubscriptionRenewed => "subscription.renewed",
+            WebhookEventType::SubscriptionRenewalFailed => "subscription.renewal_failed",
+            WebhookEventType::SubscriptionCancelled => "subscription.cancelled",
+            WebhookEventType::SubscriptionExpiring => "subscription.expiring",
+            WebhookEventType::SubscriptionExpired => "subscription.expired",
+            WebhookEventType::SubscriptionClosed => "subscription.closed",
+            WebhookEventType::RefundRequested => "refund.requested",
+            WebhookEventType::RefundSucceeded => "refund.succeeded",
+            WebhookEventType::RefundFailed => "refund.failed",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|event_type| event_type.as_str() == name)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct Webhook {
+    id: String,
+    owner: String,
+    url: String,
+    #[serde(skip_serializing)]
+    secret: String,
+    #[serde(skip_serializing)]
+    previous_secret: Option<String>, // Set by a rotation, also signs until it expires
+    previous_secret_expires_at: Option<i64>,
+    events: Vec<WebhookEventType>,
+    plan_ids: Option<Vec<u64>>, // None matches every plan in scope
+    all_plans: bool, // Registered by an admin: every plan is in scope, not just the owner's
+    version: WebhookVersion, // Payload version the webhook is pinned to
+    filter: Option<String>, // Expression events must also match, see `WebhookFilter`
+    #[serde(skip)]
+    parsed_filter: Option<WebhookFilter>,
+    created_at: i64,
+}
+
+impl Webhook {
+    fn passes_filter(&self, input: &FilterInput) -> bool {
+        self.parsed_filter.as_ref().is_none_or(|filter| filter.matches(input))
+    }
+
+    /// The current secret, then the previous one while its overlap window lasts.
+    fn signing_secrets(&self) -> Vec<&str> {
+        let mut secrets = vec![self.secret.as_str()];
+        if let (Some(previous), Some(expires_at)) = (&self.previous_secret, self.previous_secret_expires_at) {
+            if now() < expires_at {
+                secrets.push(previous);
+            }
+        }
+        secrets
+    }
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+struct WebhookRow {
+    id: String,
+    owner: String,
+    url: String,
+    secret: String,
+    previous_secret: Option<String>,
+    previous_secret_expires_at: Option<i64>,
+    events: Vec<String>,
+    plan_ids: Option<Vec<i64>>,
+    all_plans: bool,
+    version: String,
+    filter: Option<String>,
+    created_at: i64,
+}
+
+impl TryFrom<WebhookRow> for Webhook {
+    type Error = AppError;
+
+    fn try_from(row: WebhookRow) -> AppResult<Self> {
+        let version = WebhookVersion::from_name(&row.version).ok_or_else(|| {
+            AppError::InternalServerError(format!("Webhook {} has unknown payload version {}", row.id, row.version))
+        })?;
+        let parsed_filter = row
+            .filter
+            .as_deref()
+            .map(WebhookFilter::parse)
+            .transpose()
+            .map_err(|e| AppError::InternalServerError(format!("Webhook {} has an invalid filter: {}", row.id, e)))?;
+        Ok(Webhook {
+            id: row.id,
+            owner: row.owner,
+            url: row.url,
+            secret: row.secret,
+            previous_secret: row.previous_secret,
+            previous_secret_expires_at: row.previous_secret_expires_at,
+            events: row.events.iter().filter_map(|e| WebhookEventType::from_name(e)).collect(),
+            plan_ids: row.plan_ids.map(|ids| ids.into_iter().map(|id| id as u64).collect()),
+            all_plans: row.all_plans,
+            version,
+            filter: row.filter,
+            parsed_filter,
+            created_at: row.created_at,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct WebhookRequest {
+    #[validate(url(message = "must be an absolute http(s) URL"))]
+    url: String,
+    #[validate(length(min = 1, message = "must list at least one event type"))]
+    events: Vec<WebhookEventType>,
+    plan_ids: Option<Vec<u64>>,
+    version: Option<WebhookVersion>, // Default v1
+    #[validate(length(max = 1024, message = "must be at most 1024 characters"))]
+    filter: Option<String>, // e.g. `plan_id in [1, 2] && amount > 1000000`; default every matching event
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct PinVersionRequest {
+    version: WebhookVersion,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct SetFilterRequest {
+    #[validate(length(max = 1024, message = "must be at most 1024 characters"))]
+    filter: Option<String>, // None or empty removes the filter
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct WebhookCreatedResponse {
+    #[serde(flatten)]
+    webhook: Webhook,
+    secret: String, // Only returned once, at registration or rotation
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+pub struct RotateSecretRequest {
+    #[validate(range(max = 604800, message = "must be at most 7 days"))]
+    overlap_secs: Option<u64>, // How long the old secret keeps signing; default one day
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Succeeded => "succeeded",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [DeliveryStatus::Pending, DeliveryStatus::Succeeded, DeliveryStatus::Failed]
+            .into_iter()
+            .find(|status| status.as_str() == name)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct WebhookDelivery {
+    id: String,
+    webhook_id: String,
+    event_id: String,
+    event_type: WebhookEventType,
+    version: WebhookVersion,
+    status: DeliveryStatus,
+    attempts: u32,
+    last_status_code: Option<u16>,
+    last_error: Option<String>,
+    next_attempt_at: Option<i64>,
+    replay_of: Option<String>, // The delivery this one replays
+    updated_at: i64,
+}
+
+impl TryFrom<WebhookDeliveryRow> for WebhookDelivery {
+    type Error = AppError;
+
+    fn try_from(row: WebhookDeliveryRow) -> AppResult<Self> {
+        let corrupt = |field: &str, value: &str| {
+            AppError::InternalServerError(format!("Webhook delivery {} has unknown {} {}", row.id, field, value))
+        };
+        Ok(WebhookDelivery {
+            event_type: WebhookEventType::from_name(&row.event_type).ok_or_else(|| corrupt("event type", &row.event_type))?,
+            version: WebhookVersion::from_name(&row.version).ok_or_else(|| corrupt("payload version", &row.version))?,
+            status: DeliveryStatus::from_name(&row.status).ok_or_else(|| corrupt("status", &row.status))?,
+            id: row.id,
+            webhook_id: row.webhook_id,
+            event_id: row.event_id,
+            attempts: row.attempts as u32,
+            last_status_code: row.last_status_code.map(|code| code as u16),
+            last_error: row.last_error,
+            next_attempt_at: row.next_attempt_at,
+            replay_of: row.replay_of,
+            updated_at: row.updated_at,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct DeliveriesQuery {
+    webhook_id: Option<String>,
+    status: Option<DeliveryStatus>,
+    event_type: Option<WebhookEventType>,
+    #[validate(range(min = 1, max = 500, message = "must be between 1 and 500"))]
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, IntoParams, Validate)]
+pub struct DeadLettersQuery {
+    webhook_id: Option<String>,
+    include_requeued: Option<bool>, // Default false: only deliveries still in the queue
+    #[validate(range(min = 1, max = 500, message = "must be between 1 and 500"))]
+    limit: Option<i64>,
+    #[validate(range(min = 0, message = "must not be negative"))]
+    offset: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookEvent<T> {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub event_type: WebhookEventType,
+    pub created_at: i64,
+    pub data: T,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SubscriptionEventData {
+    pub subscription: String,
+    pub owner: String,
+    pub plan_id: u64,
+    pub signature: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub milestone: Option<String>, // Reminder events only: expiring_3d | expiring_1d | expired
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>, // Reminder events only: end of the billing period
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refund_id: Option<String>, // Refund events only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refund_lamports: Option<u64>, // Refund events only
+}
+
+// Webhook Service
+/// Delivers events to registered webhooks, retrying with backoff. Webhooks and deliveries are
+/// stored in the `webhooks` and `webhook_deliveries` tables, so every replica sees them and
+/// pending retries survive a restart. Each delivery is first sent by the replica that
+/// dispatched it; retries are sent by the delivery worker of whichever replica claims them.
+/// Deliveries that fail every attempt are moved to the `webhook_dead_letters` table, from
+/// where an admin can requeue them, and finished ones are removed after `retention_secs`.
+#[derive(Clone)]
+pub struct WebhookService {
+    http_client: reqwest::Client,
+    max_attempts: u32,
+    backoff_base_secs: u64,
+    dead_letter_alert_threshold: i64, // Dead letters queued before an alert is raised, 0 to never alert
+    retention_secs: i64,
+    pool: PgPool,
+    in_flight: Arc<AtomicUsize>, // Requests currently being sent, awaited on shutdown
+    channels: ChannelService,
+    user_channels: UserChannelService,
+    events: broadcast::Sender<WebhookEvent<SubscriptionEventData>>, // Live subscribers such as gRPC streams
+}
+
+impl WebhookService {
+    pub fn new(config: &Config, channels: ChannelService, user_channels: UserChannelService, pool: PgPool) -> Self {
+        let http_client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("Failed to build webhook HTTP client");
+
+        Self {
+            http_client,
+            max_attempts: config.webhook_max_attempts,
+            backoff_base_secs: config.webhook_backoff_base_secs,
+            dead_letter_alert_threshold: config.webhook_dead_letter_alert_threshold,
+            retention_secs: config.webhook_delivery_retention_secs as i64,
+            pool,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            channels,
+            user_channels,
+            events: broadcast::channel(EVENT_STREAM_CAPACITY).0,
+        }
+    }
+
+    /// Every event dispatched from now on, regardless of registered webhooks.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<WebhookEvent<SubscriptionEventData>> {
+        self.events.subscribe()
+    }
+
+    /// Registers a webhook for the caller. A merchant's webhook only receives events of its own
+    /// plans, so `plan_ids` must all be in its catalog; an admin's receives those of every plan.
+    pub async fn register(&self, auth_token: &AuthToken, plans: &PlanService, req: WebhookRequest) -> AppResult<WebhookCreatedResponse> {
+        let url = reqwest::Url::parse(&req.url)
+            .map_err(|e| AppError::BadRequest(format!("Invalid webhook URL: {}", e)))?;
+        if url.scheme() != "https" && url.scheme() != "http" {
+            return Err(AppError::BadRequest("Webhook URL must use http or https".to_string()));
+        }
+        if req.events.is_empty() {
+            return Err(AppError::BadRequest("At least one event type is required".to_string()));
+        }
+
+        for plan_id in req.plan_ids.iter().flatten() {
+            plans.authorize(auth_token, *plan_id).await?;
+        }
+        let (filter, parsed_filter) = parse_filter(req.filter)?;
+
+        let secret = format!("whsec_{}", random_hex(32));
+        let webhook = Webhook {
+            id: random_hex(16),
+            owner: auth_token.public_key.clone(),
+            url: url.to_string(),
+            secret: secret.clone(),
+            previous_secret: None,
+            previous_secret_expires_at: None,
+            events: req.events,
+            plan_ids: req.plan_ids,
+            all_plans: auth_token.has_role(Role::Admin),
+            version: req.version.unwrap_or_default(),
+            filter,
+            parsed_filter,
+            created_at: now(),
+        };
+
+        let events: Vec<&str> = webhook.events.iter().map(WebhookEventType::as_str).collect();
+        let plan_ids: Option<Vec<i64>> = webhook.plan_ids.as_ref().map(|ids| ids.iter().map(|id| *id as i64).collect());
+        sqlx::query(
+            "INSERT INTO webhooks (id, owner, url, secret, events, plan_ids, all_plans, version, filter, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+        )
+        .bind(&webhook.id)
+        .bind(&webhook.owner)
+        .bind(&webhook.url)
+        .bind(&webhook.secret)
+        .bind(&events)
+        .bind(&plan_ids)
+        .bind(webhook.all_plans)
+        .bind(webhook.version.as_str())
+        .bind(&webhook.filter)
+        .bind(webhook.created_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to register webhook: {}", e)))?;
+        tracing::info!("Registered webhook {} -> {}", webhook.id, webhook.url);
+
+        Ok(WebhookCreatedResponse { webhook, secret })
+    }
+
+    pub async fn list(&self, owner: &str) -> AppResult<Vec<Webhook>> {
+        sqlx::query_as::<_, WebhookRow>("SELECT * FROM webhooks WHERE owner = $1 ORDER BY created_at DESC")
+            .bind(owner)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to list webhooks: {}", e)))?
+            .into_iter()
+            .map(Webhook::try_from)
+            .collect()
+    }
+
+    /// Removes the webhook and its deliveries, pending retries included; its dead letters stay.
+    pub async fn remove(&self, owner: &str, id: &str) -> AppResult<()> {
+        let result = sqlx::query("DELETE FROM webhooks WHERE id = $1 AND owner = $2")
+            .bind(id)
+            .bind(owner)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to delete webhook: {}", e)))?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("Webhook {} not found", id)));
+        }
+        sqlx::query("DELETE FROM webhook_deliveries WHERE webhook_id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to delete webhook deliveries: {}", e)))?;
+        Ok(())
+    }
+
+    async fn find(&self, id: &str) -> AppResult<Option<Webhook>> {
+        sqlx::query_as::<_, WebhookRow>("SELECT * FROM webhooks WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| AppError::DatabaseError(format!("Failed to fetch webhook: {}", e)))?
+            .map(Webhook::try_from)
+            .transpose()
+    }
+
+    async fn owned(&self, owner: &str, id: &str) -> AppResult<Webhook> {
+        match self.find(id).await? {
+            Some(webhook) if webhook.owner == owner => Ok(webhook),
+            _ => Err(AppError::NotFound(format!("Webhook {} not found", id))),
+        }
+    }
+
+    /// Issues a new signing secret. Deliveries are signed with both secrets for
+    /// `overlap_secs`, so the receiver can switch over without rejecting any.
+    pub async fn rotate_secret(&self, owner: &str, id: &str, req: RotateSecretRequest) -> AppResult<WebhookCreatedResponse> {
+        let secret = format!("whsec_{}", random_hex(32));
+        let row = sqlx::query_as::<_, WebhookRow>(
+            "UPDATE webhooks SET previous_secret = secret, secret = $3, previous_secret_expires_at = $4
+             WHERE id = $1 AND owner = $2
+             RETURNING *",
+        )
+        .bind(id)
+        .bind(owner)
+        .bind(&secret)
+        .bind(now() + req.overlap_secs.unwrap_or(DEFAULT_OVERLAP_SECS) as i64)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to rotate webhook secret: {}", e)))?
+        .ok_or_else(|| AppError::NotFound(format!("Webhook {} not found", id)))?;
+        tracing::info!("Rotated the secret of webhook {}", id);
+
+        Ok(WebhookCreatedResponse { webhook: row.try_into()?, secret })
+    }
+
+    /// Pins the webhook to another payload version. Deliveries already started, their retries
+    /// and replays keep the version they were rendered in.
+    pub async fn pin_version(&self, owner: &str, id: &str, version: WebhookVersion) -> AppResult<Webhook> {
+        let row = sqlx::query_as::<_, WebhookRow>(
+            "UPDATE webhooks SET version = $3 WHERE id = $1 AND owner = $2 RETURNING *",
+        )
+        .bind(id)
+        .bind(owner)
+        .bind(version.as_str())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to pin webhook version: {}", e)))?
+        .ok_or_else(|| AppError::NotFound(format!("Webhook {} not found", id)))?;
+        tracing::info!("Pinned webhook {} to payload {}", id, version.as_str());
+        row.try_into()
+    }
+
+    /// Replaces the webhook's filter, or removes it. Events already dispatched are unaffected.
+    pub async fn set_filter(&self, owner: &str, id: &str, filter: Option<String>) -> AppResult<Webhook> {
+        let (filter, _) = parse_filter(filter)?;
+        let row = sqlx::query_as::<_, WebhookRow>(
+            "UPDATE webhooks SET filter = $3 WHERE id = $1 AND owner = $2 RETURNING *",
+        )
+        .bind(id)
+        .bind(owner)
+        .bind(&filter)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to set webhook filter: {}", e)))?
+        .ok_or_else(|| AppError::NotFound(format!("Webhook {} not found", id)))?;
+        tracing::info!("Set the filter of webhook {} to {:?}", id, filter);
+        row.try_into()
+    }
+
+    pub async fn deliveries(&self, owner: &str, webhook_id: &str) -> AppResult<Vec<WebhookDelivery>> {
+        self.owned(owner, webhook_id).await?;
+        db::list_webhook_deliveries(&self.pool, webhook_id, DEFAULT_DELIVERIES_LIMIT as i64)
+            .await?
+            .into_iter()
+            .map(WebhookDelivery::try_from)
+            .collect()
+    }
+
+    /// Deliveries to any of the owner's webhooks, newest first.
+    pub async fn list_deliveries(&self, owner: &str, query: &DeliveriesQuery) -> AppResult<Vec<WebhookDelivery>> {
+        db::list_owner_webhook_deliveries(
+            &self.pool,
+            owner,
+            query.webhook_id.as_deref(),
+            query.status.as_ref().map(DeliveryStatus::as_str),
+            query.event_type.as_ref().map(WebhookEventType::as_str),
+            query.limit.unwrap_or(DEFAULT_DELIVERIES_LIMIT) as i64,
+        )
+        .await?
+        .into_iter()
+        .map(WebhookDelivery::try_from)
+        .collect()
+    }
+
+    /// Sends a past delivery's event again, unchanged, as a new delivery with its own retries.
+    /// Receivers can tell it is the same event by its `id`.
+    pub async fn replay(&self, owner: &str, delivery_id: &str) -> AppResult<WebhookDelivery> {
+        let not_found = || AppError::NotFound(format!("Delivery {} not found", delivery_id));
+        let original = db::find_webhook_delivery(&self.pool, delivery_id)
+            .await?
+            .filter(|delivery| delivery.owner == owner)
+            .ok_or_else(not_found)?;
+        let webhook = self.owned(owner, &original.webhook_id).await.map_err(|_| not_found())?;
+        let original_delivery = WebhookDelivery::try_from(original.clone())?;
+        if original_delivery.status == DeliveryStatus::Pending {
+            return Err(AppError::Conflict(format!("Delivery {} is still being retried", delivery_id)));
+        }
+
+        let delivery = self
+            .start_delivery(
+                random_hex(16),
+                &webhook,
+                &original.event_id,
+                original_delivery.event_type,
+                original_delivery.version,
+                original.body,
+                telemetry::request_id(),
+                Some(original.id),
+            )
+            .await?;
+        tracing::info!("Replaying delivery {} as {}", delivery_id, delivery.id);
+        Ok(delivery)
+    }
+
+    /// Fans an event out to every matching webhook, merchant chat channel and channel of the
+    /// subscriber's own. Deliveries run in the background, carrying the ID of the request that
+    /// caused the event, if any.
+    pub async fn dispatch(&self, event_type: WebhookEventType, data: SubscriptionEventData) {
+        self.channels.dispatch(event_type, &data);
+        let request_id = telemetry::request_id();
+        let plan_id = data.plan_id;
+        let event = WebhookEvent {
+            id: random_hex(16),
+            event_type,
+            created_at: now(),
+            data,
+        };
+        self.user_channels.dispatch(&event);
+        // Only fails when nobody is listening
+        let _ = self.events.send(event.clone());
+
+        let candidates = match self.matching(event_type, plan_id).await {
+            Ok(candidates) => candidates,
+            Err(e) => {
+                tracing::error!("Failed to load webhooks for event {}: {}", event.id, e);
+                return;
+            }
+        };
+
+        // Filters on `amount` or `duration` read the indexed subscription, loaded once per event
+        let needs_subscription = candidates
+            .iter()
+            .any(|w| w.parsed_filter.as_ref().is_some_and(WebhookFilter::uses_subscription));
+        let subscription = if needs_subscription {
+            db::find_subscription(&self.pool, &event.data.subscription).await.unwrap_or_else(|e| {
+                tracing::warn!("Failed to load subscription {} for webhook filters: {}", event.data.subscription, e);
+                None
+            })
+        } else {
+            None
+        };
+        let input = FilterInput { event_type, data: &event.data, subscription: subscription.as_ref() };
+        let (targets, filtered): (Vec<Webhook>, Vec<Webhook>) =
+            candidates.into_iter().partition(|w| w.passes_filter(&input));
+        if !filtered.is_empty() {
+            tracing::debug!("Event {} filtered out for {} webhooks", event.id, filtered.len());
+            metrics::record_webhooks_filtered(filtered.len() as u64);
+        }
+
+        // Rendered once per payload version in use
+        let mut bodies: HashMap<WebhookVersion, String> = HashMap::new();
+        for webhook in targets {
+            let version = webhook.version;
+            let body = match bodies.get(&version) {
+                Some(body) => body.clone(),
+                None => match webhook_versions::render(&event, version) {
+                    Ok(body) => bodies.entry(version).or_insert(body).clone(),
+                    Err(e) => {
+                        tracing::error!("Failed to serialize webhook event {} as {}: {}", event.id, version.as_str(), e);
+                        continue;
+                    }
+                },
+            };
+            let started = self
+                .start_delivery(random_hex(16), &webhook, &event.id, event_type, version, body, request_id.clone(), None)
+                .await;
+            if let Err(e) = started {
+                tracing::error!("Failed to start delivery of event {} to webhook {}: {}", event.id, webhook.id, e);
+            }
+        }
+    }
+
+    /// Webhooks subscribed to `event_type` on `plan_id`: those of the plan's merchant, and
+    /// admins'. One whose stored filter no longer parses is skipped rather than sent every event.
+    async fn matching(&self, event_type: WebhookEventType, plan_id: u64) -> AppResult<Vec<Webhook>> {
+        let rows = sqlx::query_as::<_, WebhookRow>(
+            "SELECT * FROM webhooks
+             WHERE $1 = ANY(events) AND (plan_ids IS NULL OR $2 = ANY(plan_ids))
+               AND (all_plans OR owner = (SELECT merchant FROM plans WHERE plan_id = $2))",
+        )
+        .bind(event_type.as_str())
+        .bind(plan_id as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| AppError::DatabaseError(format!("Failed to list webhooks: {}", e)))?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                Webhook::try_from(row)
+                    .inspect_err(|e| tracing::error!("Skipping webhook: {}", e))
+                    .ok()
+            })
+            .collect())
+    }
+
+    /// Records a delivery and sends its first attempt in the background. It is claimed for
+    /// this replica from the start, so the delivery worker only picks it up for a retry, or
+    /// if the process dies before the attempt is recorded.
+    #[allow(clippy::too_many_arguments)]
+    async fn start_delivery(
+        &self,
+        id: String,
+        webhook: &Webhook,
+        event_id: &str,
+        event_type: WebhookEventType,
+        version: WebhookVersion,
+        body: String,
+        request_id: Option<String>,
+        replay_of: Option<String>,
+    ) -> AppResult<WebhookDelivery> {
+        let row = WebhookDeliveryRow {
+            id,
+            webhook_id: webhook.id.clone(),
+            owner: webhook.owner.clone(),
+            event_id: event_id.to_string(),
+            event_type: event_type.as_str().to_string(),
+            version: version.as_str().to_string(),
+            body,
+            status: DeliveryStatus::Pending.as_str().to_string(),
+            attempts: 0,
+            last_status_code: None,
+            last_error: None,
+            next_attempt_at: Some(now() + DELIVERY_CLAIM_SECS),
+            replay_of,
+            request_id,
+            created_at: now(),
+            updated_at: now(),
+        };
+        db::insert_webhook_delivery(&self.pool, &row).await?;
+        let delivery = WebhookDelivery::try_from(row.clone())?;
+        let service = self.clone();
+        tokio::spawn(async move { service.attempt(row).await });
+        Ok(delivery)
+    }
+
+    /// Sends pending deliveries whose retry is due, on every replica; each is claimed by one.
+    pub async fn run_deliveries(self) {
+        loop {
+            let started = Instant::now();
+            match db::claim_due_webhook_deliveries(&self.pool, now() + DELIVERY_CLAIM_SECS, DELIVERY_BATCH_SIZE).await {
+                Ok(due) => {
+                    let sent = due.len();
+                    join_all(due.into_iter().map(|row| self.attempt(row))).await;
+                    metrics::record_job_success("webhook_deliveries", started, sent);
+                }
+                Err(e) => {
+                    metrics::record_job_failure("webhook_deliveries", started);
+                    tracing::error!("Webhook delivery sweep failed: {}", e);
+                    reporting::capture_job_failure("webhook_deliveries", &e);
+                }
+            }
+            tokio::time::sleep(DELIVERY_SWEEP_INTERVAL).await;
+        }
+    }
+
+    /// Drops deliveries that finished more than `retention_secs` ago.
+    pub async fn run_cleanup(self) {
+        loop {
+            match db::prune_webhook_deliveries(&self.pool, now() - self.retention_secs).await {
+                Ok(0) => {}
+                Ok(removed) => tracing::info!("Removed {} expired webhook deliveries", removed),
+                Err(e) => tracing::error!("Failed to remove expired webhook deliveries: {}", e),
+            }
+            tokio::time::sleep(CLEANUP_INTERVAL).await;
+        }
+    }
+
+    /// Sends one attempt of a claimed delivery and records the outcome: succeeded, due again
+    /// after a backoff, or failed and dead-lettered once out of attempts.
+    async fn attempt(&self, row: WebhookDeliveryRow) {
+        let span = tracing::info_span!(
+            "webhook_delivery",
+            delivery_id = %row.id,
+            webhook_id = %row.webhook_id,
+            request_id = row.request_id.as_deref(),
+        );
+        async {
+            if let Err(e) = self.try_attempt(&row).await {
+                // Left claimed, so it is tried again once the claim runs out
+                tracing::warn!("Webhook delivery {} attempt not recorded: {}", row.id, e);
+            }
+        }
+        .instrument(span)
+        .await
+    }
+
+    async fn try_attempt(&self, row: &WebhookDeliveryRow) -> AppResult<()> {
+        let delivery = WebhookDelivery::try_from(row.clone())?;
+        let attempt = row.attempts + 1;
+        // Loaded on every attempt so retries are signed with the current secrets
+        let Some(webhook) = self.find(&row.webhook_id).await? else {
+            return db::record_webhook_attempt(
+                &self.pool,
+                &row.id,
+                DeliveryStatus::Failed.as_str(),
+                row.attempts,
+                None,
+                Some("Webhook was removed"),
+                None,
+            )
+            .await;
+        };
+
+        let started = Instant::now();
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let result = self
+            .send(&webhook, delivery.event_type, delivery.version, &row.body, row.request_id.as_deref())
+            .await;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        let outcome = match &result {
+            Ok(status) if (200..300).contains(status) => "success",
+            Ok(_) => "http_error",
+            Err(_) => "network_error",
+        };
+        metrics::record_webhook_attempt(outcome, started.elapsed().as_secs_f64());
+
+        let (last_status_code, last_error) = match result {
+            Ok(status) if (200..300).contains(&status) => {
+                metrics::record_webhook_delivery(true);
+                return db::record_webhook_attempt(
+                    &self.pool,
+                    &row.id,
+                    DeliveryStatus::Succeeded.as_str(),
+                    attempt,
+                    Some(i32::from(status)),
+                    None,
+                    None,
+                )
+                .await;
+            }
+            Ok(status) => (Some(i32::from(status)), format!("Receiver responded with status {}", status)),
+            Err(e) => (None, e),
+        };
+
+        if attempt as u32 >= self.max_attempts {
+            db::record_webhook_attempt(
+                &self.pool,
+                &row.id,
+                DeliveryStatus::Failed.as_str(),
+                attempt,
+                last_status_code,
+                Some(&last_error),
+                None,
+            )
+            .await?;
+            metrics::record_webhook_delivery(false);
+            tracing::warn!("Webhook delivery {} failed after {} attempts", row.id, attempt);
+            let mut context = vec![
+                ("delivery_id", row.id.clone()),
+                ("webhook_id", webhook.id.clone()),
+                ("event_type", row.event_type.clone()),
+            ];
+            if let Some(request_id) = &row.request_id {
+                context.push(("request_id", request_id.clone()));
+            }
+            reporting::capture(
+                "webhook",
+                format!("Webhook delivery failed after {} attempts: {}", attempt, last_error),
+                context,
+            );
+            self.dead_letter(DeadLetterRow {
+                id: row.id.clone(),
+                webhook_id: webhook.id.clone(),
+                owner: webhook.owner.clone(),
+                url: webhook.url.clone(),
+                event_id: row.event_id.clone(),
+                event_type: row.event_type.clone(),
+                version: row.version.clone(),
+                body: row.body.clone(),
+                attempts: attempt,
+                last_status_code,
+                last_error: Some(last_error),
+                failed_at: now(),
+                requeued_at: None,
+                requeued_as: None,
+            })
+            .await;
+            return Ok(());
+        }
+
+        let backoff = self.backoff(attempt as u32);
+        tracing::debug!("Retrying webhook delivery {} in {:?}", row.id, backoff);
+        db::record_webhook_attempt(
+            &self.pool,
+            &row.id,
+            DeliveryStatus::Pending.as_str(),
+            attempt,
+            last_status_code,
+            Some(&last_error),
+            Some(now() + backoff.as_secs() as i64),
+        )
+        .await
+    }
+
+    /// Moves a delivery that failed every attempt to the dead-letter queue, alerting each time
+    /// the queue grows by another `WEBHOOK_DEAD_LETTER_ALERT_THRESHOLD` deliveries.
+    async fn dead_letter(&self, dead_letter: DeadLetterRow) {
+        if let Err(e) = db::insert_dead_letter(&self.pool, &dead_letter).await {
+            tracing::error!("Failed to dead-letter webhook delivery {}: {}", dead_letter.id, e);
+            return;
+        }
+        let queued = match db::count_dead_letters(&self.pool).await {
+            Ok(queued) => queued,
+            Err(e) => {
+                tracing::warn!("Failed to count webhook dead letters: {}", e);
+                return;
+            }
+        };
+        metrics::set_webhook_dead_letters(queued);
+        let threshold = self.dead_letter_alert_threshold;
+        if threshold > 0 && queued >= threshold && queued % threshold == 0 {
+            tracing::error!("{} webhook deliveries are in the dead-letter queue", queued);
+            reporting::capture(
+                "webhook_dead_letters",
+                format!("{} webhook deliveries are in the dead-letter queue", queued),
+                vec![("queued", queued.to_string()), ("delivery_id", dead_letter.id)],
+            );
+        }
+    }
+
+    /// Sets the dead-letter metric from the table, which other replicas also write to.
+    pub async fn refresh_dead_letters(&self) -> AppResult<()> {
+        metrics::set_webhook_dead_letters(db::count_dead_letters(&self.pool).await?);
+        Ok(())
+    }
+
+    pub async fn dead_letters(&self, query: &DeadLettersQuery) -> AppResult<Vec<DeadLetterRow>> {
+        db::list_dead_letters(
+            &self.pool,
+            query.webhook_id.as_deref(),
+            query.include_requeued.unwrap_or(false),
+            query.limit.unwrap_or(DEFAULT_DEAD_LETTERS_LIMIT),
+            query.offset.unwrap_or(0),
+        )
+        .await
+    }
+
+    /// Sends a dead letter's event again as a new delivery with its own retries, and takes it
+    /// off the queue. Its webhook must still be registered.
+    pub async fn requeue(&self, id: &str) -> AppResult<WebhookDelivery> {
+        let dead_letter = db::find_dead_letter(&self.pool, id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("Dead letter {} not found", id)))?;
+        let event_type = WebhookEventType::from_name(&dead_letter.event_type)
+            .ok_or_else(|| AppError::InternalServerError(format!("Unknown event type {}", dead_letter.event_type)))?;
+        let version = WebhookVersion::from_name(&dead_letter.version)
+            .ok_or_else(|| AppError::InternalServerError(format!("Unknown payload version {}", dead_letter.version)))?;
+        let webhook = self
+            .find(&dead_letter.webhook_id)
+            .await?
+            .ok_or_else(|| AppError::Conflict(format!("Webhook {} is no longer registered", dead_letter.webhook_id)))?;
+
+        // Claiming first keeps concurrent requeues from sending the event twice
+        let delivery_id = random_hex(16);
+        if !db::mark_dead_letter_requeued(&self.pool, id, &delivery_id).await? {
+            return Err(AppError::Conflict(format!("Dead letter {} was already requeued", id)));
+        }
+        let delivery = self
+            .start_delivery(
+                delivery_id,
+                &webhook,
+                &dead_letter.event_id,
+                event_type,
+                version,
+                dead_letter.body,
+                telemetry::request_id(),
+                Some(dead_letter.id),
+            )
+            .await?;
+        self.refresh_dead_letters().await?;
+        tracing::info!("Requeued dead letter {} as delivery {}", id, delivery.id);
+        Ok(delivery)
+    }
+
+    /// Waits for in-flight sends to finish, up to `timeout`. Deliveries awaiting a retry stay in
+    /// the table for the delivery worker; one cut off mid-send is sent again once its claim
+    /// runs out.
+    pub async fn drain(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        while self.in_flight.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        let in_flight = self.in_flight.load(Ordering::SeqCst);
+        if in_flight > 0 {
+            tracing::warn!("Shutdown timeout reached with {} webhook requests in flight", in_flight);
+        }
+    }
+
+    async fn send(
+        &self,
+        webhook: &Webhook,
+        event_type: WebhookEventType,
+        version: WebhookVersion,
+        body: &str,
+        request_id: Option<&str>,
+    ) -> Result<u16, String> {
+        let timestamp = now();
+        let signatures: Vec<String> = webhook
+            .signing_secrets()
+            .into_iter()
+            .map(|secret| format!("v1={}", sign_payload(secret, timestamp, body)))
+            .collect();
+        let mut request = self.http_client
+            .post(&webhook.url)
+            .header("Content-Type", "application/json")
+            .header("X-Webhook-Id", &webhook.id)
+            .header("X-Webhook-Event", event_type.as_str())
+            .header("X-Webhook-Version", version.as_str())
+            .header(
+                "X-Webhook-Signature",
+                format!("t={},version={},{}", timestamp, version.as_str(), signatures.join(",")),
+            );
+        if let Some(request_id) = request_id {
+            request = request.header(telemetry::REQUEST_ID_HEADER, request_id);
+        }
+        let response = request
+            .body(body.to_string())
+            .send()
+            .await
+            .map_err(|e| format!("Request failed: {}", e))?;
+
+        Ok(response.status().as_u16())
+    }
+
+    // Exponential backoff: base, 2*base, 4*base, ... capped at one hour
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+        Duration::from_secs(self.backoff_base_secs.saturating_mul(factor).min(MAX_BACKOFF_SECS))
+    }
+}
+
+/// HMAC-SHA256 over "{timestamp}.{body}", hex encoded.
+pub fn sign_payload(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn random_hex(len: usize) -> String {
+    let bytes: Vec<u8> = (0..len).map(|_| rand::random::<u8>()).collect();
+    hex::encode(bytes)
+}
+
+/// The filter as stored and parsed; blank means none. A filter that does not parse is
+/// rejected with the parser's message.
+fn parse_filter(filter: Option<String>) -> AppResult<(Option<String>, Option<WebhookFilter>)> {
+    let Some(filter) = filter.map(|f| f.trim().to_string()).filter(|f| !f.is_empty()) else {
+        return Ok((None, None));
+    };
+    let parsed = WebhookFilter::parse(&filter)
+        .map_err(|message| AppError::Validation(vec![FieldError::new("filter", "invalid", message)]))?;
+    Ok((Some(filter), Some(parsed)))
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+// Controllers
+// Also mounted under /merchant/webhooks for API key callers
+#[utoipa::path(
+    post,
+    path = "/api/v1/webhooks",
+    tag = "webhooks",
+    request_body = WebhookRequest,
+    responses(
+        (status = 201, description = "Webhook registered; the signing secret is only returned here", body = WebhookCreatedResponse),
+        (status = 403, description = "Merchant role required, or a plan belongs to another merchant", body = ErrorResponse),
+        (status = 422, description = "Invalid request", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []), ("api_key" = []))
+)]
+#[post("")]
+pub async fn register_webhook(
+    req: actix_web::HttpRequest,
+    webhook_service: web::Data<WebhookService>,
+    plans: web::Data<PlanService>,
+    webhook_req: ValidatedJson<WebhookRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let webhook = webhook_service.register(&auth_token, &plans, webhook_req.into_inner()).await?;
+    Ok(HttpResponse::Created().json(webhook))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/webhooks",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "Registered webhooks", body = [Webhook]),
+        (status = 403, description = "Merchant role required", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []), ("api_key" = []))
+)]
+#[get("")]
+pub async fn list_webhooks(
+    req: actix_web::HttpRequest,
+    webhook_service: web::Data<WebhookService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let webhooks = webhook_service.list(&auth_token.public_key).await?;
+    Ok(HttpResponse::Ok().json(webhooks))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook id")),
+    responses(
+        (status = 204, description = "Webhook removed"),
+        (status = 404, description = "Webhook not found", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []), ("api_key" = []))
+)]
+#[delete("/{id}")]
+pub async fn delete_webhook(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    webhook_service: web::Data<WebhookService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    webhook_service.remove(&auth_token.public_key, &path.into_inner()).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/webhooks/{id}/deliveries",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook id")),
+    responses(
+        (status = 200, description = "Delivery attempts, newest first", body = [WebhookDelivery]),
+        (status = 404, description = "Webhook not found", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []), ("api_key" = []))
+)]
+#[get("/{id}/deliveries")]
+pub async fn list_webhook_deliveries(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    webhook_service: web::Data<WebhookService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let deliveries = webhook_service.deliveries(&auth_token.public_key, &path.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(deliveries))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/webhooks/{id}/rotate",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook id")),
+    request_body = RotateSecretRequest,
+    responses(
+        (status = 200, description = "New signing secret, only returned here", body = WebhookCreatedResponse),
+        (status = 404, description = "Webhook not found", body = ErrorResponse),
+        (status = 422, description = "Invalid request", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []), ("api_key" = []))
+)]
+#[post("/{id}/rotate")]
+pub async fn rotate_webhook_secret(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    webhook_service: web::Data<WebhookService>,
+    rotate_req: ValidatedJson<RotateSecretRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let webhook = webhook_service
+        .rotate_secret(&auth_token.public_key, &path.into_inner(), rotate_req.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(webhook))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/webhooks/{id}/version",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook id")),
+    request_body = PinVersionRequest,
+    responses(
+        (status = 200, description = "Webhook pinned to the payload version", body = Webhook),
+        (status = 404, description = "Webhook not found", body = ErrorResponse),
+        (status = 422, description = "Unknown version", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []), ("api_key" = []))
+)]
+#[put("/{id}/version")]
+pub async fn pin_webhook_version(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    webhook_service: web::Data<WebhookService>,
+    pin_req: ValidatedJson<PinVersionRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let webhook = webhook_service
+        .pin_version(&auth_token.public_key, &path.into_inner(), pin_req.into_inner().version)
+        .await?;
+    Ok(HttpResponse::Ok().json(webhook))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/webhooks/{id}/filter",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook id")),
+    request_body = SetFilterRequest,
+    responses(
+        (status = 200, description = "Filter replaced, or removed when empty", body = Webhook),
+        (status = 404, description = "Webhook not found", body = ErrorResponse),
+        (status = 422, description = "Filter does not parse", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []), ("api_key" = []))
+)]
+#[put("/{id}/filter")]
+pub async fn set_webhook_filter(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    webhook_service: web::Data<WebhookService>,
+    filter_req: ValidatedJson<SetFilterRequest>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let webhook = webhook_service
+        .set_filter(&auth_token.public_key, &path.into_inner(), filter_req.into_inner().filter)
+        .await?;
+    Ok(HttpResponse::Ok().json(webhook))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/webhooks/deliveries",
+    tag = "webhooks",
+    params(DeliveriesQuery),
+    responses(
+        (status = 200, description = "Deliveries to the merchant's webhooks, newest first", body = [WebhookDelivery]),
+        (status = 422, description = "Invalid query", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []), ("api_key" = []))
+)]
+#[get("/deliveries")]
+pub async fn list_deliveries(
+    req: actix_web::HttpRequest,
+    query: ValidatedQuery<DeliveriesQuery>,
+    webhook_service: web::Data<WebhookService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let deliveries = webhook_service.list_deliveries(&auth_token.public_key, &query).await?;
+    Ok(HttpResponse::Ok().json(deliveries))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/webhooks/deliveries/{id}/replay",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Delivery id")),
+    responses(
+        (status = 202, description = "Replay started", body = WebhookDelivery),
+        (status = 404, description = "Delivery not found", body = ErrorResponse),
+        (status = 409, description = "The delivery is still being retried", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []), ("api_key" = []))
+)]
+#[post("/deliveries/{id}/replay")]
+pub async fn replay_delivery(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    webhook_service: web::Data<WebhookService>,
+) -> AppResult<HttpResponse> {
+    let auth_token = req.extensions().get::<AuthToken>().ok_or(AppError::Auth("No auth token found".to_string()))?.clone();
+    let delivery = webhook_service.replay(&auth_token.public_key, &path.into_inner()).await?;
+    Ok(HttpResponse::Accepted().json(delivery))
+}
+
+/// Deliveries that failed every attempt, newest first, across every merchant's webhooks.
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/webhooks/dead-letters",
+    tag = "admin",
+    params(DeadLettersQuery),
+    responses(
+        (status = 200, description = "Dead-lettered deliveries, newest first", body = [DeadLetterRow]),
+        (status = 422, description = "Invalid query", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[get("/webhooks/dead-letters")]
+pub async fn list_dead_letters(
+    query: ValidatedQuery<DeadLettersQuery>,
+    webhook_service: web::Data<WebhookService>,
+) -> AppResult<HttpResponse> {
+    let dead_letters = webhook_service.dead_letters(&query).await?;
+    Ok(HttpResponse::Ok().json(dead_letters))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/webhooks/dead-letters/{id}/requeue",
+    tag = "admin",
+    params(("id" = String, Path, description = "Dead letter id, the failed delivery's id")),
+    responses(
+        (status = 202, description = "Delivery started", body = WebhookDelivery),
+        (status = 404, description = "Dead letter not found", body = ErrorResponse),
+        (status = 409, description = "Already requeued, or the webhook is no longer registered", body = ErrorResponse),
+    ),
+    security(("bearer_auth" = []))
+)]
+#[post("/webhooks/dead-letters/{id}/requeue")]
+pub async fn requeue_dead_letter(
+    path: web::Path<String>,
+    webhook_service: web::Data<WebhookService>,
+) -> AppResult<HttpResponse> {
+    let delivery = webhook_service.requeue(&path.into_inner()).await?;
+    Ok(HttpResponse::Accepted().json(delivery))
+}
@@ -14,18 +14,18 @@ fn main() {
         .expect("Invalid private key format");
     let keypair = Keypair::from_bytes(&private_key_bytes).expect("Failed to parse keypair");
 
-    let timestamp = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as i64;
-    let message = format!("Sign in to Subscription Manager: {}", timestamp);
+    // Nonce issued by the backend's GET /auth/challenge
+    let nonce = env::args()
+        .nth(1)
+        .expect("Usage: sign_message <nonce>");
+    let message = format!("Sign in to Subscription Manager: {}", nonce);
 
     let signature = keypair.sign_message(message.as_bytes());
     let signature_bs58 = bs58::encode(signature).into_string();
 
     println!("Public Key: {}", keypair.pubkey());
     println!("Signature: {}", signature_bs58);
-    println!("Timestamp: {}", timestamp);
+    println!("Nonce: {}", nonce);
 }
 
 #[cfg(not(feature = "offchain"))]